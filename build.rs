@@ -8,12 +8,20 @@
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
 //!
-//! The build script also sets the linker flags to tell it which link script to use.
+//! The build script also sets the linker flags to tell it which link script to use,
+//! and passes the git hash and the build date on for `crate::identity`. It
+//! passes the USART1 framing of the environment on for `crate::uart`.
+//! Last it generates the keymaps and chords from `keymap.toml`, see `build/keymap.rs`.
 
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[path = "build/keymap.rs"]
+mod keymap;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -40,4 +48,88 @@ fn main() {
 
     // Set the linker script to the one provided by cortex-m-rt.
     println!("cargo:rustc-link-arg=-Tlink.x");
+
+    // The defmt log frames need their own sections for the interned strings.
+    if env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        println!("cargo:rustc-link-arg=-Tdefmt.x");
+    } else {
+        // Folds the functions that compile to the same code, RTIC generates plenty
+        // of them, 140 B of the default build and the same with rtt. Not with
+        // defmt, its interned strings are identical sections that have to stay apart.
+        println!("cargo:rustc-link-arg=--icf=all");
+    }
+
+    // Outside a git checkout, or without git, the hash is "unknown".
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=FIRMWARE_GIT_HASH={}", hash);
+    println!("cargo:rustc-env=FIRMWARE_BUILD_DATE={}", build_date());
+    // A new commit moves the branch the HEAD file points to, or HEAD itself when
+    // it is detached. A path that doesn't exist would rerun the script every time.
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    // The framing of USART1 with `uart-config`, `UART_BAUD=57600 UART_FORMAT=8E1
+    // cargo build`. `crate::uart` parses it, a bad one fails the build there.
+    for (name, default) in [("UART_BAUD", "115200"), ("UART_FORMAT", "8N1")] {
+        let value = env::var(name).unwrap_or_else(|_| default.to_owned());
+        println!("cargo:rustc-env={}={}", name, value.to_ascii_uppercase());
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
+
+    // A broken keymap fails the build with the entry at fault.
+    let source = std::fs::read_to_string("keymap.toml").unwrap();
+    let second_pad = env::var_os("CARGO_FEATURE_SECOND_PAD").is_some();
+    match keymap::generate(&source, second_pad) {
+        Ok(generated) => std::fs::write(out.join("keymap_generated.rs"), generated).unwrap(),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+    println!("cargo:rerun-if-changed=keymap.toml");
+    println!("cargo:rerun-if-changed=build/keymap.rs");
+}
+
+/// Today as `YYYY-MM-DD` in UTC, or the day of `SOURCE_DATE_EPOCH` for
+/// reproducible builds.
+fn build_date() -> String {
+    let seconds = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since| since.as_secs())
+        });
+    let Some(seconds) = seconds else {
+        return "unknown".to_owned();
+    };
+    // days since 1970-01-01 to a civil date, Howard Hinnant's civil_from_days
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
 }
@@ -0,0 +1,562 @@
+//! Turns `keymap.toml` into the `LAYOUT`, `LAYER_COUNT`, `LAYERS`, `CHORDS`, `LOCKS`
+//! and `SEQUENCES` constants of `src/keymap.rs`, see the comments in the file for
+//! what it holds.
+//!
+//! Only the part of TOML the file needs is understood: comments, `key = value`
+//! pairs, `[[table]]` arrays, strings, integers and arrays of them.
+
+use std::fmt::Write;
+
+const ROWS: usize = 4;
+// of each pad, with `second-pad` the second one's follow in every row
+const COLUMNS: usize = 4;
+// `keymap::FN_KEY`, it can't lock or chord
+const FN_KEY: (usize, usize) = (3, 3);
+// keys of a sequence, `keypad_core::sequence::MAX_KEYS` at most
+const SEQUENCE_KEYS: std::ops::RangeInclusive<usize> = 2..=16;
+
+/// The Rust source for `keymap.toml`, or what is wrong with it. With `second_pad`
+/// every layer has the keys of the second pad too.
+pub fn generate(source: &str, second_pad: bool) -> Result<String, String> {
+    let tables = Parser::new(source).document()?;
+    let mut layout = None;
+    let mut layers: Vec<(String, Vec<Vec<char>>)> = Vec::new();
+    let mut chords: Vec<(u8, [char; 2])> = Vec::new();
+    let mut locks: Vec<(u8, char)> = Vec::new();
+    let mut sequences: Vec<(u8, Vec<char>, u32)> = Vec::new();
+    for table in &tables {
+        match table.name.as_str() {
+            "" => {
+                table.only(&["layout"])?;
+                layout = Some(table.string("layout")?);
+            }
+            "layer" => {
+                table.only(&["name", "rows", "second_pad"])?;
+                let name = table.string("name")?;
+                let entry = format!("layer {} ({})", layers.len(), name);
+                let mut rows = table.rows("rows", &entry)?;
+                if second_pad {
+                    let second = table.rows("second_pad", &entry)?;
+                    for (keys, more) in rows.iter_mut().zip(second) {
+                        keys.extend(more);
+                    }
+                }
+                layers.push((name, rows));
+            }
+            "chord" => {
+                table.only(&["id", "keys"])?;
+                let (line, id) = table.get("id")?;
+                let id = match id {
+                    Value::Integer(id) => u8::try_from(*id).ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| at(line, "`id` of the chord has to be a number from 0 to 255"))?;
+                let entry = format!("chord {}", id);
+                if chords.iter().any(|&(other, ..)| other == id) {
+                    return Err(at(table.line, &format!("{} is defined twice", entry)));
+                }
+                let (line, keys) = table.get("keys")?;
+                let keys = match keys {
+                    Value::Array(keys) if keys.len() == 2 => keys
+                        .iter()
+                        .map(|key| character(line, &entry, key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    _ => return Err(at(line, &format!("{}: `keys` has to name two keys", entry))),
+                };
+                chords.push((id, [keys[0], keys[1]]));
+            }
+            "lock" => {
+                table.only(&["id", "key"])?;
+                let (line, id) = table.get("id")?;
+                let id = match id {
+                    Value::Integer(id @ 1..=8) => Some(*id as u8),
+                    _ => None,
+                }
+                .ok_or_else(|| at(line, "`id` of the lock has to be a number from 1 to 8"))?;
+                let entry = format!("lock {}", id);
+                if locks.iter().any(|&(other, _)| other == id) {
+                    return Err(at(table.line, &format!("{} is defined twice", entry)));
+                }
+                let (line, key) = table.get("key")?;
+                locks.push((id, character(line, &entry, key)?));
+            }
+            "sequence" => {
+                table.only(&["id", "keys", "within_ms"])?;
+                let (line, id) = table.get("id")?;
+                let id = match id {
+                    Value::Integer(id) => u8::try_from(*id).ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    at(
+                        line,
+                        "`id` of the sequence has to be a number from 0 to 255",
+                    )
+                })?;
+                let entry = format!("sequence {}", id);
+                if sequences.iter().any(|&(other, ..)| other == id) {
+                    return Err(at(table.line, &format!("{} is defined twice", entry)));
+                }
+                let (line, keys) = table.get("keys")?;
+                let keys = match keys {
+                    Value::Array(keys) if SEQUENCE_KEYS.contains(&keys.len()) => keys
+                        .iter()
+                        .map(|key| character(line, &entry, key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    _ => {
+                        return Err(at(
+                            line,
+                            &format!(
+                                "{}: `keys` has to name {} to {} keys",
+                                entry,
+                                SEQUENCE_KEYS.start(),
+                                SEQUENCE_KEYS.end()
+                            ),
+                        ))
+                    }
+                };
+                let (line, within_ms) = table.get("within_ms")?;
+                let within_ms = match within_ms {
+                    Value::Integer(ms @ 1..=60_000) => *ms as u32,
+                    _ => {
+                        return Err(at(
+                            line,
+                            &format!("{}: `within_ms` has to be 1 to 60000", entry),
+                        ))
+                    }
+                };
+                sequences.push((id, keys, within_ms));
+            }
+            name => return Err(at(table.line, &format!("unknown table `[[{}]]`", name))),
+        }
+    }
+    let layout = layout.ok_or_else(|| "keymap.toml: `layout` is missing".to_owned())?;
+    if layers.len() < 2 {
+        return Err("keymap.toml: needs a base and a fn layer at least".to_owned());
+    }
+
+    // the chord keys are looked up on the base layer
+    let base = &layers[0].1;
+    let position = |key: char| {
+        let mut found = base.iter().enumerate().flat_map(|(row, keys)| {
+            keys.iter()
+                .enumerate()
+                .filter(move |&(_, &other)| other == key)
+                .map(move |(col, _)| (row, col))
+        });
+        match (found.next(), found.next()) {
+            (Some(position), None) => Ok(position),
+            (None, _) => Err(format!("`{}` isn't on the base layer", key)),
+            (Some(_), Some(_)) => Err(format!("`{}` is on the base layer twice", key)),
+        }
+    };
+    let mut positions = Vec::new();
+    for (id, keys) in &chords {
+        if keys[0] == keys[1] {
+            return Err(format!(
+                "keymap.toml: chord {}: needs two different keys",
+                id
+            ));
+        }
+        let chord =
+            |key| position(key).map_err(|error| format!("keymap.toml: chord {}: {}", id, error));
+        let chord_positions = [chord(keys[0])?, chord(keys[1])?];
+        // the fn key only switches the layer, it never reaches the chords
+        if let Some(index) = chord_positions
+            .iter()
+            .position(|&position| position == FN_KEY)
+        {
+            return Err(format!(
+                "keymap.toml: chord {}: `{}` is the fn key",
+                id, keys[index]
+            ));
+        }
+        positions.push(chord_positions);
+    }
+    // a lock key produces no events, so it can't be the fn key or part of a chord
+    let mut lock_positions = Vec::new();
+    for (id, key) in &locks {
+        let error = |error: String| format!("keymap.toml: lock {}: {}", id, error);
+        let position = position(*key).map_err(error)?;
+        if position == FN_KEY {
+            return Err(error(format!("`{}` is the fn key", key)));
+        }
+        if chords.iter().any(|(_, keys)| keys.contains(key)) {
+            return Err(error(format!("`{}` is a chord key", key)));
+        }
+        if lock_positions.contains(&position) {
+            return Err(error(format!("`{}` locks twice", key)));
+        }
+        lock_positions.push(position);
+    }
+    // a sequence key has to be on a key that produces presses, on any layer
+    for (id, keys, _) in &sequences {
+        for key in keys {
+            let pressable = layers.iter().any(|(_, rows)| {
+                rows.iter().enumerate().any(|(row, keys)| {
+                    keys.iter().enumerate().any(|(col, other)| {
+                        other == key
+                            && (row, col) != FN_KEY
+                            && !lock_positions.contains(&(row, col))
+                    })
+                })
+            });
+            if !pressable {
+                return Err(format!(
+                    "keymap.toml: sequence {}: `{}` is on no key that can be pressed",
+                    id, key
+                ));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// generated by build.rs from keymap.toml, edit that instead"
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(out, "/// Name of the layout in `keymap.toml`.");
+    let _ = writeln!(out, "pub const LAYOUT: &str = {:?};", layout);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "pub const LAYER_COUNT: usize = {};", layers.len());
+    let _ = writeln!(
+        out,
+        "pub const LAYERS: [Keymap<COLUMNS, ROWS>; LAYER_COUNT] = ["
+    );
+    for (name, rows) in &layers {
+        let _ = writeln!(out, "    // {}", name);
+        let _ = writeln!(out, "    Keymap([");
+        for keys in rows {
+            let keys: Vec<_> = keys.iter().map(|key| format!("{:?}", key)).collect();
+            let _ = writeln!(out, "        [{}],", keys.join(", "));
+        }
+        let _ = writeln!(out, "    ]),");
+    }
+    let _ = writeln!(out, "];");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "/// Key pairs reported as a single `Chord(id)` event.");
+    let _ = writeln!(
+        out,
+        "pub const CHORDS: [(KeyState, u8); {}] = [",
+        chords.len()
+    );
+    for ((id, [key_a, key_b]), [(row_a, col_a), (row_b, col_b)]) in chords.iter().zip(&positions) {
+        let _ = writeln!(out, "    // {:?} + {:?}", key_a, key_b);
+        let _ = writeln!(
+            out,
+            "    (KeyState(key_bit({}, {}) | key_bit({}, {})), {}),",
+            row_a, col_a, row_b, col_b, id
+        );
+    }
+    let _ = writeln!(out, "];");
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "/// Keys of the base layer toggling a lock of `Modifiers`, with its bit."
+    );
+    let _ = writeln!(
+        out,
+        "pub const LOCKS: [(KeyState, u8); {}] = [",
+        locks.len()
+    );
+    for ((id, key), (row, col)) in locks.iter().zip(&lock_positions) {
+        let _ = writeln!(out, "    // {:?}, lock {}", key, id);
+        let _ = writeln!(
+            out,
+            "    (KeyState(key_bit({}, {})), 1 << {}),",
+            row,
+            col,
+            id - 1
+        );
+    }
+    let _ = writeln!(out, "];");
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "/// Key sequences reported as a single `SequenceMatched(id)` event."
+    );
+    let _ = writeln!(
+        out,
+        "pub const SEQUENCES: [Sequence; {}] = [",
+        sequences.len()
+    );
+    for (id, keys, within_ms) in &sequences {
+        let keys: Vec<_> = keys.iter().map(|key| format!("{:?}", key)).collect();
+        let _ = writeln!(out, "    Sequence {{");
+        let _ = writeln!(out, "        id: {},", id);
+        let _ = writeln!(out, "        keys: &[{}],", keys.join(", "));
+        let _ = writeln!(out, "        within_ms: {},", within_ms);
+        let _ = writeln!(out, "    }},");
+    }
+    let _ = writeln!(out, "];");
+    Ok(out)
+}
+
+fn at(line: usize, message: &str) -> String {
+    format!("keymap.toml:{}: {}", line, message)
+}
+
+fn character(line: usize, entry: &str, value: &Value) -> Result<char, String> {
+    let Value::String(key) = value else {
+        return Err(at(line, &format!("{}: keys have to be strings", entry)));
+    };
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(key), None) if key.is_ascii_graphic() => Ok(key),
+        (Some(key), None) => Err(at(
+            line,
+            &format!(
+                "{}: {:?} isn't printable ASCII, the settings keep a byte per key",
+                entry, key
+            ),
+        )),
+        _ => Err(at(
+            line,
+            &format!("{}: {:?} isn't a single character", entry, key),
+        )),
+    }
+}
+
+#[derive(Debug)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Array(Vec<Value>),
+}
+
+struct Table {
+    // empty for the keys before the first table
+    name: String,
+    line: usize,
+    entries: Vec<(String, usize, Value)>,
+}
+
+impl Table {
+    fn get(&self, key: &str) -> Result<(usize, &Value), String> {
+        self.entries
+            .iter()
+            .find(|(name, ..)| name == key)
+            .map(|(_, line, value)| (*line, value))
+            .ok_or_else(|| at(self.line, &format!("`{}` is missing", key)))
+    }
+
+    fn string(&self, key: &str) -> Result<String, String> {
+        match self.get(key)? {
+            (_, Value::String(value)) => Ok(value.clone()),
+            (line, _) => Err(at(line, &format!("`{}` has to be a string", key))),
+        }
+    }
+
+    // the keys of a pad under `name`
+    fn rows(&self, name: &str, entry: &str) -> Result<Vec<Vec<char>>, String> {
+        let (line, rows) = self.get(name)?;
+        let rows = match rows {
+            Value::Array(rows) if rows.len() == ROWS => rows,
+            _ => {
+                return Err(at(
+                    line,
+                    &format!("{}: `{}` needs {} rows", entry, name, ROWS),
+                ))
+            }
+        };
+        rows.iter()
+            .enumerate()
+            .map(|(row, keys)| match keys {
+                Value::Array(keys) if keys.len() == COLUMNS => keys
+                    .iter()
+                    .map(|key| character(line, &format!("{} {} row {}", entry, name, row), key))
+                    .collect(),
+                _ => Err(at(
+                    line,
+                    &format!("{}: `{}` row {} needs {} keys", entry, name, row, COLUMNS),
+                )),
+            })
+            .collect()
+    }
+
+    // rejects misspelled keys
+    fn only(&self, known: &[&str]) -> Result<(), String> {
+        match self
+            .entries
+            .iter()
+            .find(|(name, ..)| !known.contains(&name.as_str()))
+        {
+            Some((name, line, _)) => Err(at(*line, &format!("unknown key `{}`", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            rest: source,
+            line: 1,
+        }
+    }
+
+    fn document(mut self) -> Result<Vec<Table>, String> {
+        let mut tables = vec![Table {
+            name: String::new(),
+            line: 1,
+            entries: Vec::new(),
+        }];
+        loop {
+            self.skip(true);
+            if self.rest.is_empty() {
+                return Ok(tables);
+            }
+            if let Some(rest) = self.rest.strip_prefix("[[") {
+                let end = rest
+                    .find("]]")
+                    .filter(|&end| !rest[..end].contains('\n'))
+                    .ok_or_else(|| at(self.line, "unterminated table header"))?;
+                tables.push(Table {
+                    name: rest[..end].trim().to_owned(),
+                    line: self.line,
+                    entries: Vec::new(),
+                });
+                self.rest = &rest[end + 2..];
+            } else {
+                let line = self.line;
+                let key = self.key()?;
+                self.skip(false);
+                self.expect('=')?;
+                self.skip(false);
+                let value = self.value()?;
+                let table = tables.last_mut().unwrap();
+                if table.entries.iter().any(|(name, ..)| *name == key) {
+                    return Err(at(line, &format!("`{}` is set twice", key)));
+                }
+                table.entries.push((key, line, value));
+            }
+            self.skip(false);
+            if !self.rest.is_empty() && !self.rest.starts_with('\n') {
+                return Err(at(self.line, "expected the end of the line"));
+            }
+        }
+    }
+
+    // spaces and comments, and line ends with `lines`
+    fn skip(&mut self, lines: bool) {
+        loop {
+            let trimmed = self.rest.trim_start_matches([' ', '\t', '\r']);
+            self.rest = trimmed;
+            if trimmed.starts_with('#') {
+                let end = trimmed.find('\n').unwrap_or(trimmed.len());
+                self.rest = &trimmed[end..];
+            } else if lines && trimmed.starts_with('\n') {
+                self.rest = &trimmed[1..];
+                self.line += 1;
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.rest.strip_prefix(expected) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err(at(self.line, &format!("expected `{}`", expected))),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(at(self.line, "expected a key"));
+        }
+        let (key, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(key.to_owned())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if let Some(rest) = self.rest.strip_prefix('[') {
+            self.rest = rest;
+            let mut values = Vec::new();
+            loop {
+                self.skip(true);
+                if self.expect(']').is_ok() {
+                    return Ok(Value::Array(values));
+                }
+                values.push(self.value()?);
+                self.skip(true);
+                if self.expect(',').is_err() {
+                    self.expect(']')?;
+                    return Ok(Value::Array(values));
+                }
+            }
+        }
+        if let Some(rest) = self.rest.strip_prefix('\'') {
+            let end = rest
+                .find(['\'', '\n'])
+                .filter(|&end| rest[end..].starts_with('\''))
+                .ok_or_else(|| at(self.line, "unterminated string"))?;
+            self.rest = &rest[end + 1..];
+            return Ok(Value::String(rest[..end].to_owned()));
+        }
+        if let Some(rest) = self.rest.strip_prefix('"') {
+            return self.basic_string(rest);
+        }
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '-' || c == '+' || c == '_'))
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..end].replace('_', "");
+        match number.parse() {
+            Ok(number) => {
+                self.rest = &self.rest[end..];
+                Ok(Value::Integer(number))
+            }
+            Err(_) => Err(at(self.line, "expected a string, a number or an array")),
+        }
+    }
+
+    // `rest` follows the opening quote
+    fn basic_string(&mut self, rest: &'a str) -> Result<Value, String> {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &rest[index + 1..];
+                    return Ok(Value::String(value));
+                }
+                '\n' => break,
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some(u @ ('u' | 'U')) => {
+                            let digits = if u == 'u' { 4 } else { 8 };
+                            let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| at(self.line, "invalid unicode escape"))?
+                        }
+                        _ => return Err(at(self.line, "unknown escape")),
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+        Err(at(self.line, "unterminated string"))
+    }
+}
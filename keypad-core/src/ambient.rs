@@ -0,0 +1,100 @@
+//! Led brightness from the ambient light. [`Curve`] maps a raw light reading to a
+//! brightness through a few points with straight lines between them, [`Slew`]
+//! walks the brightness over to a new one a step per PWM period instead of at
+//! once.
+
+/// The points of a [`Curve`].
+pub const POINTS: usize = 4;
+
+/// Brightness of 0 to 255 over the raw 12-bit light reading, `(raw, brightness)`
+/// points in rising `raw`. Readings below the first point take its brightness,
+/// the ones past the last point the last's.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Curve(pub [(u16, u8); POINTS]);
+
+impl Curve {
+    pub const DEFAULT: Curve = Curve([(100, 8), (800, 32), (2000, 96), (3500, 255)]);
+
+    pub fn is_valid(&self) -> bool {
+        self.0.windows(2).all(|pair| pair[0].0 < pair[1].0)
+    }
+
+    /// Moves point `index`, `false` and no change if the raw readings wouldn't
+    /// rise from point to point anymore.
+    pub fn set(&mut self, index: usize, raw: u16, brightness: u8) -> bool {
+        let mut moved = *self;
+        match moved.0.get_mut(index) {
+            Some(point) => *point = (raw, brightness),
+            None => return false,
+        }
+        let valid = moved.is_valid();
+        if valid {
+            *self = moved;
+        }
+        valid
+    }
+
+    pub fn brightness(&self, raw: u16) -> u8 {
+        let points = &self.0;
+        if raw <= points[0].0 {
+            return points[0].1;
+        }
+        for pair in points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            if raw <= x1 {
+                let span = i32::from(x1 - x0);
+                let rise = i32::from(y1) - i32::from(y0);
+                return (i32::from(y0) + rise * i32::from(raw - x0) / span) as u8;
+            }
+        }
+        points[POINTS - 1].1
+    }
+}
+
+/// A brightness on its way to a target, in equal steps over a number of periods.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Slew {
+    current: u8,
+    target: u8,
+    step: u8,
+}
+
+impl Slew {
+    pub const fn new(brightness: u8) -> Self {
+        Self {
+            current: brightness,
+            target: brightness,
+            step: 1,
+        }
+    }
+
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    /// Heads for `target`, there after `periods` calls of [`Slew::advance`] or a
+    /// few less where the distance doesn't divide.
+    pub fn slew_to(&mut self, target: u8, periods: u8) {
+        let distance = self.current.abs_diff(target);
+        self.target = target;
+        self.step = distance.div_ceil(periods.max(1)).max(1);
+    }
+
+    /// At `brightness` right away.
+    pub fn jump(&mut self, brightness: u8) {
+        *self = Self::new(brightness);
+    }
+
+    /// A period's step, the new brightness if it moved.
+    pub fn advance(&mut self) -> Option<u8> {
+        if self.current == self.target {
+            return None;
+        }
+        self.current = if self.current < self.target {
+            self.current.saturating_add(self.step).min(self.target)
+        } else {
+            self.current.saturating_sub(self.step).max(self.target)
+        };
+        Some(self.current)
+    }
+}
@@ -0,0 +1,97 @@
+//! What a key does at the output, the bindings of the firmware's `bind` feature:
+//! its keymap character as usual, or a code of its own on one backend. Written
+//! `KEY`, `NONE`, `HID:0X1E`, `MIDI:60` or `MACRO:2`, in upper case as the
+//! commands come in, the codes in decimal or hex.
+
+use core::fmt;
+
+/// The highest MIDI note.
+pub const MAX_NOTE: u8 = 127;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    /// The keymap character through the output mode.
+    Key,
+    /// A keyboard page usage on the USB keyboard.
+    Hid(u8),
+    /// A note on the MIDI out.
+    Midi(u8),
+    /// The macro of a slot.
+    Macro(u8),
+    /// No backend at all.
+    None,
+}
+
+impl Binding {
+    pub fn parse(word: &str) -> Option<Binding> {
+        let binding = if word == "KEY" {
+            Binding::Key
+        } else if word == "NONE" {
+            Binding::None
+        } else if let Some(usage) = word.strip_prefix("HID:") {
+            // usage 0 is no key
+            Binding::Hid(number(usage).filter(|&usage| usage != 0)?)
+        } else if let Some(note) = word.strip_prefix("MIDI:") {
+            Binding::Midi(number(note).filter(|&note| note <= MAX_NOTE)?)
+        } else {
+            Binding::Macro(number(word.strip_prefix("MACRO:")?)?)
+        };
+        Some(binding)
+    }
+
+    /// The kind and the code, all zero for `Key`.
+    pub fn to_bytes(self) -> [u8; 2] {
+        match self {
+            Binding::Key => [0, 0],
+            Binding::Hid(usage) => [1, usage],
+            Binding::Midi(note) => [2, note],
+            Binding::Macro(slot) => [3, slot],
+            Binding::None => [4, 0],
+        }
+    }
+
+    /// The binding of [`Binding::to_bytes`], `None` for one out of range.
+    pub fn from_bytes(bytes: [u8; 2]) -> Option<Binding> {
+        let binding = match bytes {
+            [0, 0] => Binding::Key,
+            [1, usage] if usage != 0 => Binding::Hid(usage),
+            [2, note] if note <= MAX_NOTE => Binding::Midi(note),
+            [3, slot] => Binding::Macro(slot),
+            [4, 0] => Binding::None,
+            _ => return None,
+        };
+        Some(binding)
+    }
+}
+
+/// In lower case, `hid:0x1e` and the like.
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Binding::Key => f.write_str("key"),
+            Binding::Hid(usage) => write!(f, "hid:{:#x}", u32::from(usage)),
+            Binding::Midi(note) => write!(f, "midi:{}", note),
+            Binding::Macro(slot) => write!(f, "macro:{}", slot),
+            Binding::None => f.write_str("none"),
+        }
+    }
+}
+
+// decimal, or hex after `0X`
+fn number(code: &str) -> Option<u8> {
+    let (digits, radix) = match code.strip_prefix("0X") {
+        Some(hex) => (hex, 16),
+        None => (code, 10),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    digits.bytes().try_fold(0u8, |value, digit| {
+        let digit = match digit {
+            b'0'..=b'9' => digit - b'0',
+            b'A'..=b'F' if radix == 16 => digit - b'A' + 10,
+            _ => return None,
+        };
+        value.checked_mul(radix)?.checked_add(digit)
+    })
+}
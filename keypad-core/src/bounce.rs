@@ -0,0 +1,75 @@
+//! The raw edges of one actuation of a key, for characterizing its bounce. The
+//! row interrupt records every edge with the cycle counter, the scanner sees the
+//! key settled once no edge came for a while and turns the stamps into the time
+//! from every edge to the next.
+
+/// Edges kept of one actuation, any later ones are only counted.
+pub const MAX_EDGES: usize = 64;
+
+pub struct EdgeLog {
+    stamps: [u32; MAX_EDGES],
+    len: usize,
+    dropped: u32,
+    // of the latest edge, kept or not
+    last: u32,
+}
+
+impl EdgeLog {
+    pub const fn new() -> Self {
+        Self {
+            stamps: [0; MAX_EDGES],
+            len: 0,
+            dropped: 0,
+            last: 0,
+        }
+    }
+
+    /// Records an edge at the cycle count `cycles`.
+    pub fn record(&mut self, cycles: u32) {
+        if self.len < MAX_EDGES {
+            self.stamps[self.len] = cycles;
+            self.len += 1;
+        } else {
+            self.dropped += 1;
+        }
+        self.last = cycles;
+    }
+
+    /// The edges kept.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The edges past [`MAX_EDGES`].
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Whether the key had edges and none of them for the last `quiet` cycles as of
+    /// `now`. The counter wraps, `quiet` has to be much shorter than its period.
+    pub fn settled(&self, now: u32, quiet: u32) -> bool {
+        !self.is_empty() && now.wrapping_sub(self.last) >= quiet
+    }
+
+    /// Time from the first edge to the last one, dropped ones included, in cycles.
+    pub fn span(&self) -> u32 {
+        self.last.wrapping_sub(self.stamps[0])
+    }
+
+    /// Time from every kept edge to the next in microseconds, at `cycles_per_us`.
+    pub fn deltas_us(&self, cycles_per_us: u32) -> impl Iterator<Item = u32> + '_ {
+        self.stamps[..self.len]
+            .windows(2)
+            .map(move |pair| pair[1].wrapping_sub(pair[0]) / cycles_per_us)
+    }
+
+    /// Forgets the edges, for the next actuation.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.dropped = 0;
+    }
+}
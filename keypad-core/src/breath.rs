@@ -0,0 +1,57 @@
+//! The breathing of a led: a brightness that swells and fades over [`CYCLE_MS`],
+//! [`STEP_HZ`] steps through [`TABLE`]. The table is worked out at compile time
+//! in fixed point, a smoothstep up and back down as the sine-ish ramp and squared
+//! for the gamma of the eye, the led looks linear that way.
+
+/// One breath, in and out.
+pub const CYCLE_MS: u32 = 3000;
+/// Steps a second.
+pub const STEP_HZ: u32 = 50;
+pub const STEPS: usize = (CYCLE_MS * STEP_HZ / 1000) as usize;
+
+/// The share of the brightness of every step, of 255.
+pub const TABLE: [u8; STEPS] = table();
+
+// 1.0 in the fixed point of `table`
+const ONE: u64 = 1 << 16;
+
+const fn table() -> [u8; STEPS] {
+    let half = (STEPS / 2) as u64;
+    let mut table = [0; STEPS];
+    let mut step = 0;
+    while step < STEPS {
+        // up for the first half, back down for the second
+        let t = match step < STEPS / 2 {
+            true => step as u64,
+            false => (STEPS - step) as u64,
+        };
+        let x = t * ONE / half;
+        let x2 = x * x / ONE;
+        let smooth = 3 * x2 - 2 * x2 * x / ONE;
+        let gamma = smooth * smooth / ONE;
+        table[step] = (gamma * 255 / ONE) as u8;
+        step += 1;
+    }
+    table
+}
+
+/// Where a breath is, it stays there while it isn't stepped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Breath {
+    step: u8,
+}
+
+impl Breath {
+    pub const fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    /// The share of the brightness, of 255.
+    pub fn level(&self) -> u8 {
+        TABLE[usize::from(self.step)]
+    }
+
+    pub fn advance(&mut self) {
+        self.step = (usize::from(self.step) + 1) as u8 % STEPS as u8;
+    }
+}
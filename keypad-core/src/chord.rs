@@ -0,0 +1,107 @@
+//! Chords, two keys pressed together reported as one event.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::{key_bit, KeyState};
+
+// the second key of a chord has to follow the first one within this window
+pub const CHORD_WINDOW_MS: u32 = 50;
+// both keys of a chord held this long report `ChordHeld`
+pub const CHORD_HOLD_MS: u32 = 3000;
+
+fn bit(event: &KeyEvent) -> KeyState {
+    KeyState(key_bit(event.row.into(), event.col.into()))
+}
+
+/// Turns the keys of a chord pressed within [`CHORD_WINDOW_MS`] into one `Chord`
+/// event, and into a `ChordHeld` one after [`CHORD_HOLD_MS`] if neither was
+/// released. A press of a chord key is held back until the window expires, so
+/// single chord keys keep working with a slightly delayed press.
+pub struct ChordDetector {
+    // the keys of every chord and its id
+    chords: &'static [(KeyState, u8)],
+    pending: Option<(KeyEvent, u32)>,
+    // keys of a recognized chord, their events are swallowed until released
+    consumed: KeyState,
+    // the `Chord` event of the chord still held, and when
+    held: Option<(KeyEvent, u32)>,
+}
+
+impl ChordDetector {
+    pub const fn new(chords: &'static [(KeyState, u8)]) -> Self {
+        Self {
+            chords,
+            pending: None,
+            consumed: KeyState(0),
+            held: None,
+        }
+    }
+
+    pub fn filter(&mut self, event: KeyEvent, now: u32, mut emit: impl FnMut(KeyEvent)) {
+        let mask = bit(&event);
+        if !(self.consumed & mask).is_empty() {
+            if matches!(event.kind, EventKind::Released { .. }) {
+                self.consumed &= !mask;
+                self.held = None;
+            }
+            return;
+        }
+
+        if event.kind == EventKind::Pressed {
+            if let Some((first, _)) = self.pending.take() {
+                let pair = bit(&first) | mask;
+                if let Some(&(_, id)) = self.chords.iter().find(|&&(chord, _)| chord == pair) {
+                    self.consumed |= pair;
+                    let chord = KeyEvent {
+                        kind: EventKind::Chord(id),
+                        ..event
+                    };
+                    self.held = Some((chord, now));
+                    emit(chord);
+                    return;
+                }
+                emit(first);
+            }
+            if self.is_chord_key(mask) {
+                self.pending = Some((event, now));
+                return;
+            }
+        } else if let Some((first, _)) = self.pending {
+            if bit(&first) == mask {
+                // tapped faster than the chord window
+                self.pending = None;
+                emit(first);
+            }
+        }
+        emit(event);
+    }
+
+    fn is_chord_key(&self, mask: KeyState) -> bool {
+        self.chords
+            .iter()
+            .any(|&(chord, _)| !(chord & mask).is_empty())
+    }
+
+    /// Releases a held back press once the chord window expired, and reports a
+    /// chord held long enough.
+    pub fn poll(&mut self, now: u32, mut emit: impl FnMut(KeyEvent)) {
+        if let Some((first, since)) = self.pending {
+            if now.wrapping_sub(since) >= CHORD_WINDOW_MS {
+                self.pending = None;
+                emit(first);
+            }
+        }
+        if let Some((chord, since)) = self.held {
+            if now.wrapping_sub(since) >= CHORD_HOLD_MS {
+                self.held = None;
+                if let EventKind::Chord(id) = chord.kind {
+                    // stamped by the scan that queues it
+                    emit(KeyEvent {
+                        kind: EventKind::ChordHeld(id),
+                        at: 0,
+                        ..chord
+                    });
+                }
+            }
+        }
+    }
+}
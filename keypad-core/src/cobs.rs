@@ -0,0 +1,140 @@
+//! Frames of a serial link: a payload followed by its CRC-32, COBS encoded and
+//! terminated by a zero byte. Zero never appears inside a frame, so a receiver
+//! attaching mid-stream resynchronizes on the next delimiter.
+//!
+//! The CRC is the caller's, the firmware's runs on its CRC unit over the words of
+//! [`crate::crc`]. It goes after the payload as big-endian bytes.
+
+use heapless::Vec;
+
+/// Bytes needed to encode a payload of `payload` bytes, delimiter included.
+pub const fn max_frame_len(payload: usize) -> usize {
+    let data = payload + 4;
+    data + data / 254 + 2
+}
+
+/// COBS encodes `data` into `out` without the delimiter and returns the length.
+pub fn cobs_encode(data: impl IntoIterator<Item = u8>, out: &mut [u8]) -> usize {
+    let mut code_index = 0;
+    let mut code = 1_u8;
+    let mut write = 1;
+    for byte in data {
+        if byte != 0 {
+            out[write] = byte;
+            write += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            out[code_index] = code;
+            code_index = write;
+            write += 1;
+            code = 1;
+        }
+    }
+    out[code_index] = code;
+    write
+}
+
+/// Decodes a COBS block in place, returns the decoded length or `None` if the
+/// block is malformed.
+pub fn cobs_decode(block: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < block.len() {
+        let code = block[read] as usize;
+        if code == 0 || read + code > block.len() {
+            return None;
+        }
+        read += 1;
+        for _ in 1..code {
+            block[write] = block[read];
+            write += 1;
+            read += 1;
+        }
+        if code != 0xff && read < block.len() {
+            block[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+/// Frames `payload` with its `checksum` into `out`, which has to hold
+/// [`max_frame_len`] bytes. Returns the frame length including the zero delimiter.
+pub fn encode_frame(payload: &[u8], checksum: fn(&[u8]) -> u32, out: &mut [u8]) -> usize {
+    let crc = checksum(payload).to_be_bytes();
+    let len = cobs_encode(payload.iter().copied().chain(crc), out);
+    out[len] = 0;
+    len + 1
+}
+
+/// A frame was too long, malformed or failed its CRC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CorruptedFrame;
+
+/// Collects inbound bytes into frames of up to `N` bytes and checks them with its
+/// `checksum`. Frames that are too long, malformed or fail the CRC are dropped and
+/// counted.
+pub struct FrameDecoder<const N: usize> {
+    block: Vec<u8, N>,
+    checksum: fn(&[u8]) -> u32,
+    // `block` holds the payload returned by the last `feed`
+    decoded: bool,
+    overflow: bool,
+    errors: u32,
+}
+
+impl<const N: usize> FrameDecoder<N> {
+    pub const fn new(checksum: fn(&[u8]) -> u32) -> Self {
+        Self {
+            block: Vec::new(),
+            checksum,
+            decoded: false,
+            overflow: false,
+            errors: 0,
+        }
+    }
+
+    /// Feeds one byte, returns the payload once a frame is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<&[u8], CorruptedFrame>> {
+        if self.decoded {
+            self.block.clear();
+            self.decoded = false;
+        }
+        if byte != 0 {
+            self.overflow |= self.block.push(byte).is_err();
+            return None;
+        }
+
+        let overflow = core::mem::replace(&mut self.overflow, false);
+        // back to back delimiters are just an idle line
+        if self.block.is_empty() && !overflow {
+            return None;
+        }
+        let payload = match overflow {
+            true => None,
+            false => cobs_decode(&mut self.block).filter(|&len| len >= 4),
+        };
+        let valid = payload.filter(|&len| {
+            let (data, crc) = self.block[..len].split_at(len - 4);
+            (self.checksum)(data).to_be_bytes() == crc
+        });
+        match valid {
+            Some(len) => {
+                self.block.truncate(len - 4);
+                self.decoded = true;
+                Some(Ok(&self.block))
+            }
+            None => {
+                self.block.clear();
+                self.errors += 1;
+                Some(Err(CorruptedFrame))
+            }
+        }
+    }
+
+    /// Number of frames dropped so far.
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+}
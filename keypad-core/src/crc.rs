@@ -0,0 +1,22 @@
+//! How the firmware puts bytes into words for the CRC unit: CRC-32/MPEG-2,
+//! polynomial 0x04c11db7, initial value 0xffffffff, each 32 bit word fed most
+//! significant bit first, no reflection and no final XOR.
+//!
+//! The bytes go in as little-endian words, the last one filled up with zeros,
+//! followed by the byte count as one more word. A host computes the same with
+//! CRC-32/MPEG-2 over every group of four bytes reversed, the padding and the
+//! count included.
+
+/// The words of `bytes` for the unit, the count last.
+pub fn words(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    // little-endian, a short chunk leaves the high bytes zero
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .rev()
+                .fold(0, |word, &byte| word << 8 | u32::from(byte))
+        })
+        .chain([bytes.len() as u32])
+}
@@ -0,0 +1,324 @@
+//! From raw scans to the debounced key state.
+
+use crate::keys::{ghost_mask, KeyState};
+
+// number of consecutive scans a key has to read high before it counts as pressed,
+// unless the settings say otherwise
+pub const DEBOUNCE_THRESHOLD: u8 = 3;
+
+/// The scans a key has to read high to debounce pressed and low to debounce
+/// released. A clean edge one way can take a lower threshold than the bouncing one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Thresholds {
+    pub press: u8,
+    pub release: u8,
+}
+
+impl Thresholds {
+    pub const DEFAULT: Thresholds = Thresholds::symmetric(DEBOUNCE_THRESHOLD);
+
+    pub const fn symmetric(threshold: u8) -> Self {
+        Self {
+            press: threshold,
+            release: threshold,
+        }
+    }
+
+    /// The threshold of the edge that takes longer.
+    pub fn longest(&self) -> u8 {
+        self.press.max(self.release)
+    }
+
+    /// The thresholds raised to the longest of the keys' own, what a check of the
+    /// latency goes by.
+    pub fn with_keys(self, per_key: &[u8]) -> Self {
+        let longest = per_key.iter().copied().max().unwrap_or(0);
+        Self {
+            press: self.press.max(longest),
+            release: self.release.max(longest),
+        }
+    }
+
+    // of a key with a threshold of its own for both edges, 0 for none
+    fn of_key(self, own: u8) -> Self {
+        match own {
+            0 => self,
+            own => Self::symmetric(own),
+        }
+    }
+}
+
+/// Integrating debouncer with one counter for each of the `KEYS` keys. The
+/// counter moves up while the key reads high and towards zero while it reads low.
+/// A released key turns pressed once its counter hits the press threshold, which
+/// puts the counter at the release threshold, and a pressed key turns released
+/// once its counter is back at zero.
+///
+/// A key can have a threshold of its own for both edges in place of the shared
+/// ones, a slower switch in the matrix for one.
+pub struct Debouncer<const KEYS: usize> {
+    counters: [u8; KEYS],
+    state: u32,
+    thresholds: Thresholds,
+    // the keys' own thresholds, 0 for the shared ones
+    per_key: [u8; KEYS],
+}
+
+impl<const KEYS: usize> Debouncer<KEYS> {
+    pub const fn new(thresholds: Thresholds) -> Self {
+        Self {
+            counters: [0; KEYS],
+            state: 0,
+            thresholds,
+            per_key: [0; KEYS],
+        }
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        self.thresholds
+    }
+
+    pub fn key_thresholds(&self) -> &[u8; KEYS] {
+        &self.per_key
+    }
+
+    /// Changes the thresholds, the held keys stay held.
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+        self.thresholds = thresholds;
+        self.clamp();
+    }
+
+    /// Changes the keys' own thresholds, 0 for the shared ones. The held keys stay
+    /// held.
+    pub fn set_key_thresholds(&mut self, per_key: &[u8; KEYS]) {
+        self.per_key = *per_key;
+        self.clamp();
+    }
+
+    fn clamp(&mut self) {
+        let keys = self.counters.iter_mut().zip(&self.per_key);
+        for (key, (counter, &own)) in keys.enumerate() {
+            let thresholds = self.thresholds.of_key(own);
+            let top = match self.state & 1 << key != 0 {
+                true => thresholds.release,
+                false => thresholds.press,
+            };
+            *counter = (*counter).min(top);
+        }
+    }
+
+    /// Feeds one raw scan into the debouncer and returns the debounced key state.
+    pub fn update(&mut self, raw: u32) -> u32 {
+        let keys = self.counters.iter_mut().zip(&self.per_key);
+        for (key, (counter, &own)) in keys.enumerate() {
+            let mask = 1 << key;
+            let pressed = self.state & mask != 0;
+            match step(
+                counter,
+                pressed,
+                raw & mask != 0,
+                self.thresholds.of_key(own),
+            ) {
+                true => self.state |= mask,
+                false => self.state &= !mask,
+            }
+        }
+        self.state
+    }
+
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    /// The counter of every key, as of the latest scan.
+    pub fn counters(&self) -> &[u8; KEYS] {
+        &self.counters
+    }
+}
+
+// one sample of a single key's counter, returns whether it's pressed now
+fn step(counter: &mut u8, pressed: bool, raw: bool, thresholds: Thresholds) -> bool {
+    let Thresholds { press, release } = thresholds;
+    if raw {
+        let top = if pressed { release } else { press };
+        if *counter < top {
+            *counter += 1;
+        }
+        if !pressed && *counter == press {
+            *counter = release;
+            return true;
+        }
+        pressed
+    } else {
+        *counter = counter.saturating_sub(1);
+        pressed && *counter != 0
+    }
+}
+
+/// A debounced change of a single pin.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Pressed,
+    Released,
+}
+
+/// The [`Debouncer`] of a single pin, a button outside the matrix. It counts the
+/// same way whoever samples it, a scan or a recheck after an edge interrupt.
+pub struct PinDebouncer {
+    counter: u8,
+    pressed: bool,
+    thresholds: Thresholds,
+}
+
+impl PinDebouncer {
+    pub const fn new(thresholds: Thresholds) -> Self {
+        Self {
+            counter: 0,
+            pressed: false,
+            thresholds,
+        }
+    }
+
+    /// Feeds one sample of the pin, high for pressed, and returns the edge it
+    /// debounced if any.
+    pub fn update(&mut self, raw: bool) -> Option<Edge> {
+        let pressed = step(&mut self.counter, self.pressed, raw, self.thresholds);
+        if pressed == self.pressed {
+            return None;
+        }
+        self.pressed = pressed;
+        Some(if pressed {
+            Edge::Pressed
+        } else {
+            Edge::Released
+        })
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Whether the counter sits at rest, the last samples agree with the
+    /// debounced state.
+    pub fn is_settled(&self) -> bool {
+        let top = match self.pressed {
+            true => self.thresholds.release,
+            false => 0,
+        };
+        self.counter == top
+    }
+}
+
+/// Hold time after which a key is taken to be stuck.
+pub const STUCK_KEY_MS: u32 = 30_000;
+
+/// Releases keys that stay pressed for too long, a held key is more likely a
+/// fault or debris under the keycap than someone holding it. A stuck key reads
+/// released until it debounces released for real.
+pub struct StuckKeys<const KEYS: usize> {
+    limit_ms: u32,
+    // debounced state of the last update
+    held: u32,
+    // press time of every held key, in ms since boot
+    since: [u32; KEYS],
+    stuck: u32,
+}
+
+impl<const KEYS: usize> StuckKeys<KEYS> {
+    pub const fn new(limit_ms: u32) -> Self {
+        Self {
+            limit_ms,
+            held: 0,
+            since: [0; KEYS],
+            stuck: 0,
+        }
+    }
+
+    /// Takes the debounced key state and returns it without the stuck keys.
+    pub fn update(&mut self, state: u32, now: u32) -> u32 {
+        for (key, since) in self.since.iter_mut().enumerate() {
+            let mask = 1 << key;
+            if state & mask == 0 {
+                continue;
+            }
+            if self.held & mask == 0 {
+                *since = now;
+            } else if now.wrapping_sub(*since) >= self.limit_ms {
+                self.stuck |= mask;
+            }
+        }
+        self.held = state;
+        self.stuck &= state;
+        state & !self.stuck
+    }
+
+    /// Keys currently flagged as stuck.
+    pub fn stuck(&self) -> u32 {
+        self.stuck
+    }
+}
+
+/// A raw scan to the key state the events come from: ambiguous keys keep their
+/// debounced state until the ghosting clears, the other keys are debounced and a
+/// stuck key reads released until it recovers.
+pub struct KeyFilter<const COLS: usize, const ROWS: usize, const KEYS: usize> {
+    debouncer: Debouncer<KEYS>,
+    stuck: StuckKeys<KEYS>,
+    // ambiguous keys of the last scan
+    ghosts: u32,
+}
+
+/// What [`KeyFilter::update`] made of a scan.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Filtered {
+    pub state: KeyState,
+    pub ghosts: u32,
+    /// The ghosting began with this scan.
+    pub ghosting_started: bool,
+    /// The stuck keys before and after the scan.
+    pub was_stuck: KeyState,
+    pub stuck: KeyState,
+}
+
+impl<const COLS: usize, const ROWS: usize, const KEYS: usize> KeyFilter<COLS, ROWS, KEYS> {
+    pub const fn new(thresholds: Thresholds, stuck_limit_ms: u32) -> Self {
+        Self {
+            debouncer: Debouncer::new(thresholds),
+            stuck: StuckKeys::new(stuck_limit_ms),
+            ghosts: 0,
+        }
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+        if thresholds != self.debouncer.thresholds() {
+            self.debouncer.set_thresholds(thresholds);
+        }
+    }
+
+    pub fn set_key_thresholds(&mut self, per_key: &[u8; KEYS]) {
+        if per_key != self.debouncer.key_thresholds() {
+            self.debouncer.set_key_thresholds(per_key);
+        }
+    }
+
+    pub fn counters(&self) -> &[u8; KEYS] {
+        self.debouncer.counters()
+    }
+
+    pub fn update(&mut self, raw: u32, now: u32) -> Filtered {
+        let previous = self.debouncer.state();
+        let ghosts = ghost_mask::<COLS, ROWS>(raw);
+        let ghosting_started = ghosts != 0 && self.ghosts == 0;
+        self.ghosts = ghosts;
+        let debounced = self.debouncer.update((raw & !ghosts) | (previous & ghosts));
+        let was_stuck = KeyState(self.stuck.stuck());
+        let state = KeyState(self.stuck.update(debounced, now));
+        Filtered {
+            state,
+            ghosts,
+            ghosting_started,
+            was_stuck,
+            stuck: KeyState(self.stuck.stuck()),
+        }
+    }
+}
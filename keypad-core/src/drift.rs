@@ -0,0 +1,82 @@
+//! The drift of the RTC between two syncs with a host, the firmware's `time-sync`
+//! feature. A sync sets the clock to the host's time and keeps that point, so the
+//! clock's reading at the next sync less the host's time is what it drifted
+//! since. The point is kept in 15 bits of a backup register, in units of
+//! 2^[`POINT_SHIFT`] s, which puts the syncs up to ~388 days apart. The bits
+//! above are taken from the clock.
+//!
+//! The RTC trims a fast clock by skipping up to [`MAX_CALIBRATION`] pulses every
+//! 2^20, ~0.95 ppm a step. It can't speed up a slow one.
+
+pub const POINT_SHIFT: u32 = 10;
+/// Syncs closer than this tell the drift alone, the point is only known to
+/// 2^[`POINT_SHIFT`] s.
+pub const MIN_ELAPSED_S: u32 = 1 << (POINT_SHIFT + 4);
+pub const MAX_CALIBRATION: u8 = 127;
+
+// in the register of a point, it reads zero after a loss of the backup domain
+const VALID: u16 = 0x8000;
+const POINT_MASK: u32 = 0x7fff;
+
+/// The register value of a sync at `seconds`.
+pub fn point(seconds: u32) -> u16 {
+    VALID | (seconds >> POINT_SHIFT & POINT_MASK) as u16
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Drift {
+    pub elapsed_s: u32,
+    // positive for a fast clock
+    pub drift_s: i32,
+}
+
+// in 32 bits, the elapsed time has bits to spare below what it's known to, and
+// drifts past a day or so say nothing about a rate anyway
+impl Drift {
+    pub fn ppm(&self) -> i32 {
+        // 10^6 / 2^6
+        let drift = self.drift_s.clamp(-(1 << 17), 1 << 17);
+        drift * 15_625 / (self.elapsed_s >> 6) as i32
+    }
+
+    /// The calibration that would have kept the clock on time, from the one it
+    /// ran with.
+    pub fn calibration(&self, current: u8) -> u8 {
+        // the rate in steps of 2^-20, over the elapsed time in its 2^10 s units
+        let drift = self.drift_s.clamp(-(1 << 20), 1 << 20);
+        let steps = drift * 1024 / (self.elapsed_s >> POINT_SHIFT) as i32;
+        (i32::from(current) + steps).clamp(0, i32::from(MAX_CALIBRATION)) as u8
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Measurement {
+    /// No point to measure from, the first sync since the backup domain was lost.
+    First,
+    /// The host's time is before the previous sync.
+    Backwards,
+    /// Too close to the previous sync for a rate, the clock is `drift_s` ahead.
+    Drift {
+        drift_s: i32,
+    },
+    Measured(Drift),
+}
+
+/// Measures against the `register` of the previous sync, with the clock reading
+/// `clock` at the `host`'s time.
+pub fn measure(register: u16, host: u32, clock: u32) -> Measurement {
+    if register & VALID == 0 {
+        return Measurement::First;
+    }
+    // the clock ran on from the point, so it's the one to count back from
+    let units = (clock >> POINT_SHIFT).wrapping_sub(u32::from(register) & POINT_MASK) & POINT_MASK;
+    let point = (clock >> POINT_SHIFT).wrapping_sub(units) << POINT_SHIFT;
+    let Some(elapsed_s) = host.checked_sub(point) else {
+        return Measurement::Backwards;
+    };
+    let drift_s = clock.wrapping_sub(host) as i32;
+    match elapsed_s < MIN_ELAPSED_S {
+        true => Measurement::Drift { drift_s },
+        false => Measurement::Measured(Drift { elapsed_s, drift_s }),
+    }
+}
@@ -0,0 +1,56 @@
+//! The matrix as the firmware's `DUMP` prints it, the `dump` feature. A row of the
+//! pad is a line of the debounced keys, the raw ones of the same scan and the
+//! debounce counters, in fixed columns so that two captures diff line by line:
+//!
+//! ```text
+//! . X . . | . X . X |  0  3  0  2
+//! ```
+//!
+//! `X` for a key pressed and `.` otherwise, the counters right-aligned in three
+//! characters each.
+
+use crate::keys::{KeyState, COLUMNS, KEYS};
+use core::fmt::{self, Write as _};
+
+/// A scan and the debouncer after it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub debounced: KeyState,
+    pub raw: KeyState,
+    pub counters: [u8; KEYS],
+}
+
+impl Snapshot {
+    /// The line of `row`.
+    pub fn row(&self, row: usize) -> Row<'_> {
+        Row {
+            snapshot: self,
+            row,
+        }
+    }
+}
+
+pub struct Row<'a> {
+    snapshot: &'a Snapshot,
+    row: usize,
+}
+
+impl fmt::Display for Row<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Row { snapshot, row } = *self;
+        let grid = |f: &mut fmt::Formatter, keys: KeyState| {
+            (0..COLUMNS).try_for_each(|col| {
+                f.write_char(if keys.is_pressed(row, col) { 'X' } else { '.' })?;
+                f.write_char(' ')
+            })
+        };
+        grid(f, snapshot.debounced)?;
+        f.write_str("| ")?;
+        grid(f, snapshot.raw)?;
+        f.write_char('|')?;
+        for counter in &snapshot.counters[row * COLUMNS..][..COLUMNS] {
+            write!(f, "{:3}", counter)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! The pipeline of the firmware's scanner in one piece, for a firmware of another
+//! board: the scans of any [`MatrixScanner`] to the debounced frames, their edges,
+//! the chords, long presses, double taps and the typematic repeat. The caller
+//! gets the events through its [`Hooks`], and adds the locks, the layers or a
+//! limit of keys there. The timing of the scans is left to it.
+//!
+//! A scanner of fewer columns or rows than the pad gets its keys moved to the
+//! bits of [`crate::keys`], the events then tell its own rows and columns.
+
+use crate::chord::ChordDetector;
+use crate::debounce::{Filtered, KeyFilter, Thresholds};
+use crate::event::{EventKind, KeyEvent, KeyFrames};
+use crate::gesture::{DoubleTap, LongPress, Repeat};
+use crate::keys::{key_bit, KeyState, COLUMNS, KEYS, ROWS};
+use crate::matrix::MatrixScanner;
+
+/// Where the events of [`EventEngine::update`] go, and what the caller adds to the
+/// pipeline. Any `FnMut(KeyEvent)` takes the events and adds nothing.
+pub trait Hooks {
+    /// Takes an event, stamped with the tick of its scan.
+    fn emit(&mut self, event: KeyEvent);
+
+    /// The keys pressed and released in the frame of the debounced `state`, before
+    /// they make edges. Returns the keys left for the edges, where the lock keys or
+    /// the presses past a limit are taken out. Events of its own go to `emit`, ahead
+    /// of the edges.
+    fn frame(
+        &mut self,
+        state: KeyState,
+        changes: (KeyState, KeyState),
+        now: u32,
+    ) -> (KeyState, KeyState) {
+        let _ = (state, now);
+        changes
+    }
+
+    /// The bits of a scan of [`EventEngine::scan`], before the debounce. Returns
+    /// the bits to take, where keys pressed by other means than the matrix are
+    /// added.
+    fn scanned(&mut self, raw: u32, now: u32) -> u32 {
+        let _ = now;
+        raw
+    }
+
+    /// An edge on its way to the chords, where a layer maps its key. `None` drops
+    /// it.
+    fn edge(&mut self, event: KeyEvent) -> Option<KeyEvent> {
+        Some(event)
+    }
+}
+
+impl<F: FnMut(KeyEvent)> Hooks for F {
+    fn emit(&mut self, event: KeyEvent) {
+        self(event);
+    }
+}
+
+/// A matrix of more columns or rows than the pad, refused by [`EventEngine::new`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TooLarge {
+    pub columns: u8,
+    pub rows: u8,
+}
+
+/// The scanner `S` and the state of the events coming from it.
+pub struct EventEngine<S> {
+    scanner: S,
+    filter: KeyFilter<COLUMNS, ROWS, KEYS>,
+    frames: KeyFrames,
+    chords: ChordDetector,
+    long_press: LongPress,
+    double_tap: DoubleTap,
+    repeat: Option<Repeat>,
+}
+
+impl<S: MatrixScanner> EventEngine<S> {
+    /// Fails if the matrix of `scanner` is larger than the pad.
+    pub fn new(
+        scanner: S,
+        thresholds: Thresholds,
+        stuck_limit_ms: u32,
+        chords: &'static [(KeyState, u8)],
+    ) -> Result<Self, TooLarge> {
+        let (columns, rows) = scanner.dims();
+        if usize::from(columns) > COLUMNS || usize::from(rows) > ROWS {
+            return Err(TooLarge { columns, rows });
+        }
+        Ok(Self {
+            scanner,
+            filter: KeyFilter::new(thresholds, stuck_limit_ms),
+            frames: KeyFrames::new(),
+            chords: ChordDetector::new(chords),
+            long_press: LongPress::new(),
+            double_tap: DoubleTap::new(),
+            repeat: None,
+        })
+    }
+
+    /// Repeats the key pressed last while it is held, see [`Repeat`].
+    pub fn with_repeat(self, delay_ms: u32, interval_ms: u32) -> Self {
+        Self {
+            repeat: Some(Repeat::new(delay_ms, interval_ms)),
+            ..self
+        }
+    }
+
+    /// For what the scanner does besides the scans.
+    pub fn scanner_mut(&mut self) -> &mut S {
+        &mut self.scanner
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+        self.filter.set_thresholds(thresholds);
+    }
+
+    pub fn set_key_thresholds(&mut self, per_key: &[u8; KEYS]) {
+        self.filter.set_key_thresholds(per_key);
+    }
+
+    /// The debounce counters of every key, see [`KeyFilter::counters`].
+    pub fn counters(&self) -> &[u8; KEYS] {
+        self.filter.counters()
+    }
+
+    /// Scans the matrix and takes the scan with what [`Hooks::scanned`] adds, see
+    /// [`EventEngine::update`]. A failed scan leaves every key as it was.
+    pub fn scan(
+        &mut self,
+        now: u32,
+        at: u64,
+        hooks: &mut impl Hooks,
+    ) -> Result<Filtered, S::Error> {
+        let raw = self.scanner.scan_raw()?;
+        let (columns, rows) = self.scanner.dims();
+        let raw = match usize::from(columns) {
+            COLUMNS => raw,
+            columns => (0..columns * usize::from(rows))
+                .filter(|key| raw & (1 << key) != 0)
+                .fold(0, |keys, key| keys | key_bit(key / columns, key % columns)),
+        };
+        let raw = hooks.scanned(raw, now);
+        Ok(self.update(raw, now, at, hooks))
+    }
+
+    /// Takes a scan of the pad's bits at `now` milliseconds and `at` monotonic
+    /// ticks, emits its events and those that came due, all stamped with `at`.
+    pub fn update(&mut self, raw: u32, now: u32, at: u64, hooks: &mut impl Hooks) -> Filtered {
+        let filtered = self.filter.update(raw, now);
+        let Self {
+            frames,
+            chords,
+            long_press,
+            double_tap,
+            repeat,
+            ..
+        } = self;
+        let mut out = Out {
+            hooks,
+            repeat,
+            now,
+            at,
+        };
+        if filtered.ghosting_started {
+            out.emit(KeyEvent::new(
+                KeyState(filtered.ghosts).first(),
+                EventKind::GhostingDetected,
+            ));
+        }
+        let changes = frames.push(filtered.state);
+        let changes = out.hooks.frame(filtered.state, changes, now);
+        let mut gestures = |out: &mut Out<_>, event| {
+            let event = long_press.track(event, now);
+            double_tap.track(event, now, |event| out.emit(event));
+        };
+        for event in frames.edges(changes, at) {
+            if let Some(event) = out.hooks.edge(event) {
+                chords.filter(event, now, |event| gestures(&mut out, event));
+            }
+        }
+        chords.poll(now, |event| gestures(&mut out, event));
+        long_press.poll(now, |event| out.emit(event));
+        if let Some(event) = out.repeat.as_mut().and_then(|repeat| repeat.poll(now)) {
+            out.emit(event);
+        }
+        filtered
+    }
+}
+
+// the way out of a scan, the repeat follows what goes out
+struct Out<'a, H> {
+    hooks: &'a mut H,
+    repeat: &'a mut Option<Repeat>,
+    now: u32,
+    at: u64,
+}
+
+impl<H: Hooks> Out<'_, H> {
+    fn emit(&mut self, mut event: KeyEvent) {
+        event.stamp(self.at);
+        if let Some(repeat) = self.repeat {
+            repeat.track(&event, self.now);
+        }
+        self.hooks.emit(event);
+    }
+}
@@ -0,0 +1,215 @@
+//! Key events, what the pipeline makes of the changes between the debounced frames.
+
+use crate::keys::{self, KeyState, COLUMNS, KEYS};
+use crate::modifiers::Modifiers;
+use crate::time;
+use core::fmt::{self, Write};
+use serde::Serialize;
+
+// reported for positions outside of the keymap, and for events not resolved yet
+pub const UNKNOWN_KEY: char = '?';
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EventKind {
+    Pressed,
+    // ms the key was held, zero if its press was never seen
+    Released { held_ms: u32 },
+    // the key has been held for `gesture::LONG_PRESS_MS`
+    LongPressed,
+    // replaces `Released` for a key that produced `LongPressed`
+    ReleasedAfterLong,
+    // second press of the same key within `gesture::DOUBLE_TAP_MS`
+    DoubleTap,
+    // typematic repeat of a held key
+    Repeat,
+    // both keys of the chord with this id went down together, reported on the second key
+    Chord(u8),
+    // keys are masked because of ghosting, reported once on the first masked key
+    GhostingDetected,
+    // the keys of the sequence with this id were typed, reported on the last key
+    SequenceMatched(u8),
+    // the keys of the chord with this id stayed down for `chord::CHORD_HOLD_MS`
+    ChordHeld(u8),
+    // the periodic self-test of the matrix failed, reported on the first failed key
+    KeypadFault,
+    // more keys are held than `limit::MAX_SIMULTANEOUS`, the presses stop until
+    // `KeysWithinLimit`. Reported on the first key whose press was dropped.
+    TooManyKeys,
+    // reported on the key whose release brought the held keys back within the limit
+    KeysWithinLimit,
+}
+
+impl EventKind {
+    /// Number of the kind in the binary encodings, 0 stands for no event there.
+    pub fn code(self) -> u8 {
+        match self {
+            EventKind::Pressed => 1,
+            EventKind::Released { .. } => 2,
+            EventKind::LongPressed => 3,
+            EventKind::ReleasedAfterLong => 4,
+            EventKind::DoubleTap => 5,
+            EventKind::Repeat => 6,
+            EventKind::Chord(_) => 7,
+            EventKind::GhostingDetected => 8,
+            EventKind::SequenceMatched(_) => 9,
+            EventKind::ChordHeld(_) => 10,
+            EventKind::KeypadFault => 11,
+            EventKind::TooManyKeys => 12,
+            EventKind::KeysWithinLimit => 13,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Pressed => "PRESSED",
+            EventKind::Released { .. } => "RELEASED",
+            EventKind::LongPressed => "LONG",
+            EventKind::ReleasedAfterLong => "RELEASED_LONG",
+            EventKind::DoubleTap => "DOUBLE",
+            EventKind::Repeat => "REPEAT",
+            EventKind::Chord(_) => "CHORD",
+            EventKind::GhostingDetected => "GHOSTING",
+            EventKind::SequenceMatched(_) => "SEQUENCE",
+            EventKind::ChordHeld(_) => "CHORD_HELD",
+            EventKind::KeypadFault => "KEYPAD_FAULT",
+            EventKind::TooManyKeys => "TOO_MANY_KEYS",
+            EventKind::KeysWithinLimit => "WITHIN_LIMIT",
+        }
+    }
+}
+
+/// A key transition produced by the scanner, `key` stays [`UNKNOWN_KEY`] until the
+/// event is resolved by a keymap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyEvent {
+    pub row: u8,
+    pub col: u8,
+    pub key: char,
+    pub kind: EventKind,
+    // monotonic ticks (us since boot) of the scan that saw the edge, or of the one
+    // that made up a gesture's event, zero until then
+    pub at: u64,
+    // wall clock seconds at the same time, zero without a clock
+    pub time: u32,
+    // the locks that were on at the same time
+    pub modifiers: Modifiers,
+    // strike velocity of a press from the sensor under the key, 1 to 127, zero
+    // for the other events and a key without one, see `crate::velocity`
+    #[cfg(feature = "velocity")]
+    pub velocity: u8,
+}
+
+impl KeyEvent {
+    /// Unresolved event for the key with index `row * COLUMNS + col`.
+    pub const fn new(index: usize, kind: EventKind) -> Self {
+        Self {
+            row: (index / COLUMNS) as u8,
+            col: (index % COLUMNS) as u8,
+            key: UNKNOWN_KEY,
+            kind,
+            at: 0,
+            time: 0,
+            modifiers: Modifiers(0),
+            #[cfg(feature = "velocity")]
+            velocity: 0,
+        }
+    }
+
+    /// The pad of the key, its column tells, see [`crate::keys::pad`].
+    pub const fn pad(&self) -> usize {
+        keys::pad(self.col as usize)
+    }
+
+    /// Stamps an event that wasn't yet with the scan at `at` that queues it. An
+    /// edge keeps the scan that saw it, a press the chord window held back included.
+    pub fn stamp(&mut self, at: u64) {
+        if self.at == 0 {
+            self.at = at;
+        }
+    }
+}
+
+/// `<ms> <row> <col> <key> <kind>`, followed by the hold time of a release, the
+/// id of a chord or a sequence or the velocity of a press, like
+/// `1520 0 3 A RELEASED 120`.
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // not `{}` for the key and the kind, the padding of a `char` and a `str`
+        // is more code than the firmware has room for
+        write!(
+            f,
+            "{} {} {} ",
+            time::to_ms(self.at) as u32,
+            self.row,
+            self.col
+        )?;
+        f.write_char(self.key)?;
+        f.write_char(' ')?;
+        f.write_str(self.kind.name())?;
+        match self.kind {
+            EventKind::Released { held_ms } => write!(f, " {}", held_ms),
+            EventKind::Chord(id) | EventKind::SequenceMatched(id) | EventKind::ChordHeld(id) => {
+                write!(f, " {}", id)
+            }
+            #[cfg(feature = "velocity")]
+            EventKind::Pressed if self.velocity != 0 => write!(f, " {}", self.velocity),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The debounced keys of the current and the previous frame, the only key history
+/// events come from. The chords and the gestures work on the events of the change
+/// between the two, so every actuation is one press and one release whatever
+/// feature is in between.
+pub struct KeyFrames {
+    current: KeyState,
+    previous: KeyState,
+    // monotonic ticks of the press of every held key
+    pressed_at: [Option<u64>; KEYS],
+}
+
+impl KeyFrames {
+    pub const fn new() -> Self {
+        Self {
+            current: KeyState(0),
+            previous: KeyState(0),
+            pressed_at: [None; KEYS],
+        }
+    }
+
+    /// Takes `state` as the current frame, returns the keys pressed and the keys
+    /// released since the previous one.
+    pub fn push(&mut self, state: KeyState) -> (KeyState, KeyState) {
+        self.previous = core::mem::replace(&mut self.current, state);
+        state.diff(self.previous)
+    }
+
+    /// Press and release events of a change returned by [`Self::push`] at `at`
+    /// monotonic ticks, in the order of the keys and stamped with `at`.
+    pub fn edges(
+        &mut self,
+        (pressed, released): (KeyState, KeyState),
+        at: u64,
+    ) -> impl Iterator<Item = KeyEvent> + '_ {
+        (pressed | released).iter_pressed().map(move |(row, col)| {
+            let index = usize::from(row) * COLUMNS + usize::from(col);
+            let since = &mut self.pressed_at[index];
+            let kind = if pressed.is_pressed(row.into(), col.into()) {
+                *since = Some(at);
+                EventKind::Pressed
+            } else {
+                let held = since.take().map_or(0, |since| at.saturating_sub(since));
+                EventKind::Released {
+                    held_ms: u32::try_from(time::to_ms(held)).unwrap_or(u32::MAX),
+                }
+            };
+            KeyEvent {
+                at,
+                ..KeyEvent::new(index, kind)
+            }
+        })
+    }
+}
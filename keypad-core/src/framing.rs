@@ -0,0 +1,114 @@
+//! The framing of a serial port: the baud rate, the parity and the stop bits, 8
+//! data bits always, written `8E1` style. [`Framing::parse`] is a `const fn`, the
+//! firmware parses the framing of its build environment with it at compile time
+//! and the one of a command at run time.
+
+/// The lowest and the highest baud rate accepted.
+pub const MIN_BAUD: u32 = 1200;
+pub const MAX_BAUD: u32 = 460_800;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Framing {
+    pub baud: u32,
+    pub parity: Parity,
+    pub two_stop_bits: bool,
+}
+
+impl Framing {
+    /// `115200 8N1`.
+    pub const DEFAULT: Framing = Framing {
+        baud: 115_200,
+        parity: Parity::None,
+        two_stop_bits: false,
+    };
+
+    /// `baud` in decimal and `format` like `8N1` or `8E2`, in upper case.
+    pub const fn parse(baud: &str, format: &str) -> Option<Framing> {
+        let digits = baud.as_bytes();
+        // past 7 digits it's out of range anyway, and it can't overflow
+        if digits.is_empty() || digits.len() > 7 {
+            return None;
+        }
+        let mut value = 0;
+        let mut index = 0;
+        while index < digits.len() {
+            if !digits[index].is_ascii_digit() {
+                return None;
+            }
+            value = value * 10 + (digits[index] - b'0') as u32;
+            index += 1;
+        }
+        Framing::new(value, format)
+    }
+
+    /// [`Framing::parse`] with the baud rate parsed already.
+    pub const fn new(baud: u32, format: &str) -> Option<Framing> {
+        if baud < MIN_BAUD || baud > MAX_BAUD {
+            return None;
+        }
+        let (parity, stop) = match format.as_bytes() {
+            [b'8', parity, stop] => (*parity, *stop),
+            _ => return None,
+        };
+        let parity = match parity {
+            b'N' => Parity::None,
+            b'E' => Parity::Even,
+            b'O' => Parity::Odd,
+            _ => return None,
+        };
+        let two_stop_bits = match stop {
+            b'1' => false,
+            b'2' => true,
+            _ => return None,
+        };
+        Some(Framing {
+            baud,
+            parity,
+            two_stop_bits,
+        })
+    }
+
+    /// `8N1` and the like.
+    pub fn format(&self) -> &'static str {
+        match (self.parity, self.two_stop_bits) {
+            (Parity::None, false) => "8N1",
+            (Parity::None, true) => "8N2",
+            (Parity::Even, false) => "8E1",
+            (Parity::Even, true) => "8E2",
+            (Parity::Odd, false) => "8O1",
+            (Parity::Odd, true) => "8O2",
+        }
+    }
+
+    /// The baud rate in little endian, then the parity and the stop bits.
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let mut bytes = [0; 5];
+        bytes[..4].copy_from_slice(&self.baud.to_le_bytes());
+        bytes[4] = self.parity as u8 | u8::from(self.two_stop_bits) << 2;
+        bytes
+    }
+
+    /// The framing of [`Framing::to_bytes`], `None` for one out of range.
+    pub fn from_bytes(bytes: &[u8; 5]) -> Option<Framing> {
+        let baud = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let parity = match bytes[4] & 0b11 {
+            0 => Parity::None,
+            1 => Parity::Even,
+            2 => Parity::Odd,
+            _ => return None,
+        };
+        let valid = (MIN_BAUD..=MAX_BAUD).contains(&baud) && bytes[4] >> 3 == 0;
+        valid.then_some(Framing {
+            baud,
+            parity,
+            two_stop_bits: bytes[4] & 0b100 != 0,
+        })
+    }
+}
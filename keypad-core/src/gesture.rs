@@ -0,0 +1,177 @@
+//! Long presses, double taps and the typematic repeat, from the key events and the
+//! milliseconds they were seen at.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::{COLUMNS, KEYS};
+
+// a key held at least this long reports `LongPressed`
+pub const LONG_PRESS_MS: u32 = 1000;
+
+// maximum time between two presses of the same key to count as a double tap
+pub const DOUBLE_TAP_MS: u32 = 300;
+// single taps are never delayed: the first tap is reported as usual and the second
+// press emits `DoubleTap`, either in addition to its `Pressed` (true) or instead of it
+pub const DOUBLE_TAP_KEEPS_PRESS: bool = true;
+
+// a held key starts repeating after `REPEAT_DELAY_MS`, then repeats at `REPEAT_RATE_HZ`
+pub const REPEAT_DELAY_MS: u32 = 500;
+pub const REPEAT_RATE_HZ: u32 = 15;
+pub const REPEAT_INTERVAL_MS: u32 = 1000 / REPEAT_RATE_HZ;
+
+#[derive(Copy, Clone)]
+struct Held {
+    event: KeyEvent,
+    since: u32,
+    long: bool,
+}
+
+/// Detects keys held past [`LONG_PRESS_MS`], timestamps are monotonic milliseconds.
+pub struct LongPress {
+    held: [Option<Held>; KEYS],
+}
+
+impl LongPress {
+    pub const fn new() -> Self {
+        Self { held: [None; KEYS] }
+    }
+
+    /// Records the press time of `event`, the release of a long pressed key is
+    /// turned into `ReleasedAfterLong`.
+    pub fn track(&mut self, mut event: KeyEvent, now: u32) -> KeyEvent {
+        let held = &mut self.held[event.row as usize * COLUMNS + event.col as usize];
+        match event.kind {
+            EventKind::Pressed => {
+                *held = Some(Held {
+                    event,
+                    since: now,
+                    long: false,
+                })
+            }
+            EventKind::Released { .. } if held.take().is_some_and(|held| held.long) => {
+                event.kind = EventKind::ReleasedAfterLong;
+            }
+            _ => {}
+        }
+        event
+    }
+
+    /// Emits `LongPressed` once for every key that crossed the threshold.
+    pub fn poll(&mut self, now: u32, mut emit: impl FnMut(KeyEvent)) {
+        for held in self.held.iter_mut().flatten() {
+            if !held.long && now.wrapping_sub(held.since) >= LONG_PRESS_MS {
+                held.long = true;
+                // stamped by the scan that queues it
+                emit(KeyEvent {
+                    kind: EventKind::LongPressed,
+                    at: 0,
+                    ..held.event
+                });
+            }
+        }
+    }
+}
+
+/// Detects two presses of the same key within [`DOUBLE_TAP_MS`].
+pub struct DoubleTap {
+    // key and time of the last press that may start a double tap
+    last: Option<(u8, u8, u32)>,
+}
+
+impl DoubleTap {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    pub fn track(&mut self, event: KeyEvent, now: u32, mut emit: impl FnMut(KeyEvent)) {
+        if event.kind != EventKind::Pressed {
+            emit(event);
+            return;
+        }
+
+        match self.last.take() {
+            Some((row, col, since))
+                if (row, col) == (event.row, event.col)
+                    && now.wrapping_sub(since) <= DOUBLE_TAP_MS =>
+            {
+                if DOUBLE_TAP_KEEPS_PRESS {
+                    emit(event);
+                }
+                emit(KeyEvent {
+                    kind: EventKind::DoubleTap,
+                    ..event
+                });
+            }
+            _ => {
+                self.last = Some((event.row, event.col, now));
+                emit(event);
+            }
+        }
+    }
+}
+
+/// The typematic repeat: only the most recently pressed key repeats, once
+/// `delay_ms` after its press and every `interval_ms` from then on.
+pub struct Repeat {
+    held: Option<KeyEvent>,
+    // when the next repeat is due
+    due: u32,
+    delay_ms: u32,
+    interval_ms: u32,
+}
+
+impl Repeat {
+    pub const fn new(delay_ms: u32, interval_ms: u32) -> Self {
+        Self {
+            held: None,
+            due: 0,
+            delay_ms,
+            interval_ms,
+        }
+    }
+
+    /// Follows presses and releases, a press at `now` starts its repeat over.
+    pub fn track(&mut self, event: &KeyEvent, now: u32) {
+        match event.kind {
+            EventKind::Pressed => {
+                self.held = Some(*event);
+                self.due = now.wrapping_add(self.delay_ms);
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong
+                if self
+                    .held
+                    .is_some_and(|held| (held.row, held.col) == (event.row, event.col)) =>
+            {
+                self.held = None;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn held(&self) -> Option<KeyEvent> {
+        self.held
+    }
+
+    /// The repeat of the held key if one is due by `now`. The next one is due an
+    /// interval after this one was, so a late poll doesn't make the rate drift.
+    /// After a stall it doesn't try to catch up.
+    pub fn poll(&mut self, now: u32) -> Option<KeyEvent> {
+        let held = self.held?;
+        if !reached(now, self.due) {
+            return None;
+        }
+        self.due = self.due.wrapping_add(self.interval_ms);
+        if reached(now, self.due) {
+            self.due = now.wrapping_add(self.interval_ms);
+        }
+        // stamped by the scan that queues it
+        Some(KeyEvent {
+            kind: EventKind::Repeat,
+            at: 0,
+            ..held
+        })
+    }
+}
+
+fn reached(now: u32, due: u32) -> bool {
+    now.wrapping_sub(due) as i32 >= 0
+}
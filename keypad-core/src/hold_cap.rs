@@ -0,0 +1,94 @@
+//! A cap on how long a backend holds a key down, whatever the key does. Past
+//! [`HOLD_CAP_MS`] [`HoldCap::expire`] makes up the release of a key, the key
+//! stays down for the pipeline ahead of it and its real release later on is
+//! swallowed, as is anything else of the key until then. It only sees the events
+//! handed to [`HoldCap::pass`], the ones not for a capped backend go around it.
+//!
+//! The stuck keys of `debounce::StuckKeys` come before it and are released for
+//! real, a key capped before it was stuck has that release swallowed.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::{COLUMNS, KEYS};
+
+/// The longest a backend holds a key down, unless the settings say otherwise.
+pub const HOLD_CAP_MS: u32 = 10_000;
+
+pub struct HoldCap {
+    cap_ms: u32,
+    // keys down as the events passed tell it
+    held: u32,
+    // held keys the cap released
+    capped: u32,
+    // the press time and the character of every held key
+    since: [u32; KEYS],
+    keys: [char; KEYS],
+}
+
+impl HoldCap {
+    pub const fn new(cap_ms: u32) -> Self {
+        Self {
+            cap_ms,
+            held: 0,
+            capped: 0,
+            since: [0; KEYS],
+            keys: ['\0'; KEYS],
+        }
+    }
+
+    pub fn cap_ms(&self) -> u32 {
+        self.cap_ms
+    }
+
+    /// Changes the cap, the held keys keep their press times.
+    pub fn set_cap(&mut self, cap_ms: u32) {
+        self.cap_ms = cap_ms;
+    }
+
+    /// Tracks an event on its way to the backend, false if it is of a key the cap
+    /// released and has to be swallowed.
+    pub fn pass(&mut self, event: &KeyEvent, now_ms: u32) -> bool {
+        let index = usize::from(event.row) * COLUMNS + usize::from(event.col);
+        if index >= KEYS {
+            return true;
+        }
+        let mask = 1 << index;
+        let capped = self.capped & mask != 0;
+        match event.kind {
+            EventKind::Pressed => {
+                self.held |= mask;
+                self.capped &= !mask;
+                self.since[index] = now_ms;
+                self.keys[index] = event.key;
+                return true;
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => {
+                self.held &= !mask;
+                self.capped &= !mask;
+            }
+            _ => {}
+        }
+        !capped
+    }
+
+    /// Hands `release` the made up release of every key held past the cap since
+    /// the last call.
+    pub fn expire(&mut self, now_ms: u32, mut release: impl FnMut(KeyEvent)) {
+        for index in 0..KEYS {
+            let mask = 1 << index;
+            let held_ms = now_ms.wrapping_sub(self.since[index]);
+            if self.held & !self.capped & mask == 0 || held_ms < self.cap_ms {
+                continue;
+            }
+            self.capped |= mask;
+            release(KeyEvent {
+                key: self.keys[index],
+                ..KeyEvent::new(index, EventKind::Released { held_ms })
+            });
+        }
+    }
+
+    /// Keys released by the cap that are still down.
+    pub fn capped(&self) -> u32 {
+        self.capped
+    }
+}
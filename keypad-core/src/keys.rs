@@ -0,0 +1,139 @@
+//! The keys of the pad as a bitmask, bit `row * COLUMNS + col` for a key.
+
+use core::fmt::{self, Write as _};
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
+use serde::Serialize;
+
+// the pad of the firmware, the filter and the drivers take any size with up to 8 rows
+// and 32 keys. With `second-pad` a second 4x4 pad shares the rows, its columns
+// follow the ones of the first pad.
+#[cfg(not(feature = "second-pad"))]
+pub const COLUMNS: usize = 4;
+#[cfg(feature = "second-pad")]
+pub const COLUMNS: usize = 2 * PAD_COLUMNS;
+pub const ROWS: usize = 4;
+pub const KEYS: usize = ROWS * COLUMNS;
+/// Columns of each pad.
+pub const PAD_COLUMNS: usize = 4;
+
+/// The pad of the key in column `col`, 0 for the first one.
+pub const fn pad(col: usize) -> usize {
+    col / PAD_COLUMNS
+}
+
+/// Bit of the key at `row`/`col` in a key state bitmask of the pad.
+pub const fn key_bit(row: usize, col: usize) -> u32 {
+    1 << (row * COLUMNS + col)
+}
+
+/// Keys of the pad, a bit per key as the scanner sets them. Shows as the
+/// rows of the grid, `X` for a set key and `.` otherwise, like `X... .... ..X. ....`.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyState(pub u32);
+
+impl KeyState {
+    pub fn is_pressed(self, row: usize, col: usize) -> bool {
+        row < ROWS && col < COLUMNS && self.0 & key_bit(row, col) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn pressed_count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// Index `row * COLUMNS + col` of the first set key, 0 without one.
+    pub fn first(self) -> usize {
+        match self.0 {
+            0 => 0,
+            keys => keys.trailing_zeros() as usize,
+        }
+    }
+
+    /// Row and column of every set key, in the order of the bits.
+    pub fn iter_pressed(self) -> impl Iterator<Item = (u8, u8)> {
+        (0..KEYS)
+            .filter(move |key| self.0 & (1 << key) != 0)
+            .map(|key| ((key / COLUMNS) as u8, (key % COLUMNS) as u8))
+    }
+
+    /// The keys pressed and the keys released since `previous`.
+    pub fn diff(self, previous: KeyState) -> (KeyState, KeyState) {
+        (self & !previous, previous & !self)
+    }
+}
+
+impl BitAnd for KeyState {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for KeyState {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitAndAssign for KeyState {
+    fn bitand_assign(&mut self, other: Self) {
+        self.0 &= other.0;
+    }
+}
+
+impl BitOrAssign for KeyState {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Not for KeyState {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+impl fmt::Display for KeyState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in 0..ROWS {
+            if row > 0 {
+                f.write_char(' ')?;
+            }
+            for col in 0..COLUMNS {
+                f.write_char(if self.is_pressed(row, col) { 'X' } else { '.' })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for KeyState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "KeyState({})", self)
+    }
+}
+
+/// Keys that can't be told apart from ghosts: without diodes, three pressed corners of
+/// a rectangle make the fourth one read pressed, so all corners become ambiguous.
+pub fn ghost_mask<const COLS: usize, const ROWS: usize>(raw: u32) -> u32 {
+    let row_bits = |row: usize| (raw >> (row * COLS)) & ((1 << COLS) - 1);
+    let mut mask = 0;
+    for first in 0..ROWS {
+        for second in first + 1..ROWS {
+            let shared = row_bits(first) & row_bits(second);
+            if shared.count_ones() >= 2 {
+                mask |= shared << (first * COLS) | shared << (second * COLS);
+            }
+        }
+    }
+    mask
+}
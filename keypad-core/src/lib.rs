@@ -0,0 +1,40 @@
+//! The key pipeline of the firmware without the hardware: raw scans as bitmasks in,
+//! debounced states, edges, chords and gestures out, timed by the ticks and
+//! milliseconds the caller passes in. The firmware drives it from its scanner and
+//! its tasks, the tests in `tests/` with made up scans on the host. The matrix
+//! scan itself works on any `embedded-hal` pins, the tests' are mocks. Another
+//! firmware gets the whole pipeline from `engine` for a scanner of its own.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+// the firmware builds its state in statics and `init`, with the `const fn new`s
+#![allow(clippy::new_without_default)]
+
+pub mod ambient;
+pub mod binding;
+pub mod bounce;
+pub mod breath;
+pub mod chord;
+pub mod cobs;
+pub mod crc;
+pub mod debounce;
+pub mod drift;
+pub mod dump;
+pub mod engine;
+pub mod event;
+pub mod framing;
+pub mod gesture;
+pub mod hold_cap;
+pub mod keys;
+pub mod limit;
+pub mod matrix;
+pub mod midi;
+pub mod modifiers;
+pub mod pattern;
+pub mod pull_check;
+pub mod queue;
+pub mod report;
+pub mod route;
+pub mod sequence;
+pub mod time;
+pub mod velocity;
+pub mod wake;
@@ -0,0 +1,54 @@
+//! The most keys the pad reports at once. Without diodes a matrix can't tell more
+//! than two held keys apart reliably, so past [`MAX_SIMULTANEOUS`] the presses
+//! stop: [`PressLimit`] reports `TooManyKeys` once and drops the presses from then
+//! on, the keys already down still get their releases. `KeysWithinLimit` tells
+//! once the held keys are back within the limit, a key whose press was dropped
+//! stays unreported until it is released.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::KeyState;
+
+/// Held keys the pad reports at once, `KEYS` for no limit.
+pub const MAX_SIMULTANEOUS: u32 = 2;
+
+pub struct PressLimit {
+    max: u32,
+    over: bool,
+    // held keys whose press was dropped, their release is dropped too
+    dropped: KeyState,
+}
+
+impl PressLimit {
+    pub const fn new(max: u32) -> Self {
+        Self {
+            max,
+            over: false,
+            dropped: KeyState(0),
+        }
+    }
+
+    /// Takes the change from `KeyFrames::push` to the debounced `state` and returns
+    /// what is left of it to report, with the event of the limit if it was crossed
+    /// either way.
+    pub fn filter(
+        &mut self,
+        state: KeyState,
+        (mut pressed, mut released): (KeyState, KeyState),
+    ) -> ((KeyState, KeyState), Option<KeyEvent>) {
+        let over = state.pressed_count() > self.max;
+        let event = match (self.over, over) {
+            (false, true) => Some(KeyEvent::new(pressed.first(), EventKind::TooManyKeys)),
+            (true, false) => Some(KeyEvent::new(released.first(), EventKind::KeysWithinLimit)),
+            _ => None,
+        };
+        self.over = over;
+        let unreported = released & self.dropped;
+        self.dropped &= !released;
+        released &= !unreported;
+        if over {
+            self.dropped |= pressed;
+            pressed = KeyState(0);
+        }
+        ((pressed, released), event)
+    }
+}
@@ -0,0 +1,384 @@
+//! Scanning a key matrix through the `embedded-hal` pin traits: the columns are
+//! driven active one at a time and the rows read back. Any HAL's pins do, the
+//! firmware plugs in its faster GPIO and shift register variants.
+
+use core::marker::PhantomData;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// A key matrix backend of `COLS` columns and `ROWS` rows, `scan` returns a
+/// bitmask of the pressed keys where bit `row * COLS + col` is set when the key
+/// is pressed.
+pub trait Matrix<const COLS: usize, const ROWS: usize> {
+    type Error;
+
+    fn scan(&mut self) -> Result<u32, Self::Error>;
+
+    /// Stops driving the matrix so nothing wired to it sees a drive voltage.
+    fn park(&mut self) -> Result<(), Self::Error>;
+
+    /// Undoes [`Matrix::park`], the lines should settle for a scan period before
+    /// the next scan.
+    fn unpark(&mut self) -> Result<(), Self::Error>;
+
+    /// Reads the rows with no column driven, then scans as usual. Meant for boot,
+    /// before anyone can be expected to press a key.
+    fn self_test(&mut self) -> Result<SelfTest<COLS, ROWS>, Self::Error>;
+}
+
+/// The least a matrix needs for [`crate::engine::EventEngine`]: a scan in the
+/// bitmask of [`Matrix::scan`] for its own number of columns, and the size. The
+/// port of a firmware that can't park or self-test its matrix implements this.
+pub trait MatrixScanner {
+    type Error;
+
+    fn scan_raw(&mut self) -> Result<u32, Self::Error>;
+
+    /// The columns and the rows.
+    fn dims(&self) -> (u8, u8);
+}
+
+/// Findings of [`Matrix::self_test`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelfTest<const COLS: usize, const ROWS: usize> {
+    /// Rows that read pressed with no column driven, a short or a wiring fault.
+    pub shorted_rows: u8,
+    /// Keys that read closed with their column driven alone.
+    pub closed: u32,
+}
+
+impl<const COLS: usize, const ROWS: usize> SelfTest<COLS, ROWS> {
+    /// Positions that failed: every key of a shorted row and the closed keys.
+    pub fn failed(&self) -> u32 {
+        (0..ROWS)
+            .filter(|row| self.shorted_rows & (1 << row) != 0)
+            .fold(self.closed, |failed, row| failed | row_mask::<COLS>(row))
+    }
+
+    /// A shorted row, or a row that reads closed whichever column is driven. A
+    /// single closed key may just be held.
+    pub fn hard_fault(&self) -> bool {
+        let closed = |row| self.closed & row_mask::<COLS>(row) == row_mask::<COLS>(row);
+        self.shorted_rows != 0 || (0..ROWS).any(closed)
+    }
+}
+
+const fn row_mask<const COLS: usize>(row: usize) -> u32 {
+    ((1 << COLS) - 1) << (row * COLS)
+}
+
+/// How the matrix is wired: the level a selected column is driven to, which a
+/// pressed key passes on to its row, and the pull of the rows towards the other.
+pub trait Polarity {
+    /// Whether the active level is high.
+    const ACTIVE_HIGH: bool;
+}
+
+/// Rows pulled down, the selected column driven high.
+pub struct ActiveHigh;
+
+/// Rows pulled up, the selected column driven low.
+pub struct ActiveLow;
+
+impl Polarity for ActiveHigh {
+    const ACTIVE_HIGH: bool = true;
+}
+
+impl Polarity for ActiveLow {
+    const ACTIVE_HIGH: bool = false;
+}
+
+/// Drives the matrix columns, at most one of them is active at a time, see
+/// [`Polarity`].
+pub trait ColumnDriver {
+    type Error;
+
+    /// Drives `col` active and every other column inactive.
+    fn select(&mut self, col: usize) -> Result<(), Self::Error>;
+    /// Drives every column inactive.
+    fn release(&mut self) -> Result<(), Self::Error>;
+    /// Drives every column active, any pressed key then makes its row active.
+    fn select_all(&mut self) -> Result<(), Self::Error>;
+    /// Stops driving the columns, as far as the hardware allows.
+    fn park(&mut self) -> Result<(), Self::Error>;
+    /// Drives the columns again, all of them inactive.
+    fn unpark(&mut self) -> Result<(), Self::Error>;
+}
+
+/// The row inputs of a matrix of `ROWS` rows.
+pub trait Rows<const ROWS: usize> {
+    type Error;
+
+    /// A bit per row that reads high.
+    fn read(&self) -> Result<u32, Self::Error>;
+}
+
+impl<R: InputPin, const ROWS: usize> Rows<ROWS> for [R; ROWS] {
+    type Error = R::Error;
+
+    fn read(&self) -> Result<u32, R::Error> {
+        let mut levels = 0;
+        for (row, pin) in self.iter().enumerate() {
+            if pin.is_high()? {
+                levels |= 1 << row;
+            }
+        }
+        Ok(levels)
+    }
+}
+
+/// `COLS` columns on plain output pins. They can't float, so parking only drives
+/// them inactive.
+pub struct PinColumns<C, const COLS: usize, P> {
+    pins: [C; COLS],
+    polarity: PhantomData<P>,
+}
+
+impl<C: OutputPin, const COLS: usize, P: Polarity> PinColumns<C, COLS, P> {
+    pub fn new(pins: [C; COLS]) -> Result<Self, C::Error> {
+        let mut columns = Self {
+            pins,
+            polarity: PhantomData,
+        };
+        columns.release()?;
+        Ok(columns)
+    }
+
+    fn output(&mut self, active: impl Fn(usize) -> bool) -> Result<(), C::Error> {
+        for (index, pin) in self.pins.iter_mut().enumerate() {
+            if active(index) == P::ACTIVE_HIGH {
+                pin.set_high()?;
+            } else {
+                pin.set_low()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: OutputPin, const COLS: usize, P: Polarity> ColumnDriver for PinColumns<C, COLS, P> {
+    type Error = C::Error;
+
+    fn select(&mut self, col: usize) -> Result<(), C::Error> {
+        self.output(|index| index == col)
+    }
+
+    fn release(&mut self) -> Result<(), C::Error> {
+        self.output(|_| false)
+    }
+
+    fn select_all(&mut self) -> Result<(), C::Error> {
+        self.output(|_| true)
+    }
+
+    fn park(&mut self) -> Result<(), C::Error> {
+        self.release()
+    }
+
+    fn unpark(&mut self) -> Result<(), C::Error> {
+        self.release()
+    }
+}
+
+/// A failed column or row access of a [`Keypad`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<C, R> {
+    Columns(C),
+    Rows(R),
+}
+
+/// Time the rows get to follow a newly selected column before they are read, for
+/// the capacitance of longer cables.
+pub const SETTLE_US: u32 = 2;
+
+// for the rows after releasing the columns
+const SELF_TEST_SETTLE_US: u32 = 10;
+
+/// `COLS` x `ROWS` key matrix: columns are driven active one at a time by `C` and
+/// the rows `R` are read back, both with the levels of `P`. `D` waits for the
+/// rows to settle.
+pub struct Keypad<C, R, D, const COLS: usize, const ROWS: usize, P> {
+    columns: C,
+    rows: R,
+    delay: D,
+    polarity: PhantomData<P>,
+}
+
+impl<C, R, D, const COLS: usize, const ROWS: usize, P> Keypad<C, R, D, COLS, ROWS, P>
+where
+    C: ColumnDriver,
+    R: Rows<ROWS>,
+    D: DelayUs<u32>,
+    P: Polarity,
+{
+    // a bit per key in the scan, a bit per row in `SelfTest`
+    const FITS: () = assert!(COLS * ROWS <= 32 && ROWS <= 8);
+
+    pub fn new(columns: C, rows: R, delay: D) -> Self {
+        let () = Self::FITS;
+        Self {
+            columns,
+            rows,
+            delay,
+            polarity: PhantomData,
+        }
+    }
+
+    /// Drives every column active for a wake up on the rows, returns `true` if a
+    /// key is already pressed. The next scan takes the columns back.
+    pub fn select_all(&mut self) -> Result<bool, Error<C::Error, R::Error>> {
+        self.columns.select_all().map_err(Error::Columns)?;
+        Ok(self.read_rows()? != 0)
+    }
+
+    /// Selects `col` alone, for [`Keypad::read_column`] once the rows settled.
+    pub fn select_column(&mut self, col: usize) -> Result<(), Error<C::Error, R::Error>> {
+        self.columns.select(col).map_err(Error::Columns)
+    }
+
+    /// The keys of the selected `col` in a state bitmask, see [`Matrix::scan`].
+    pub fn read_column(&self, col: usize) -> Result<u32, Error<C::Error, R::Error>> {
+        let rows = self.read_rows()?;
+        Ok((0..ROWS)
+            .filter(|row| rows & (1 << row) != 0)
+            .fold(0, |state, row| state | 1 << (row * COLS + col)))
+    }
+
+    // a bit per active row
+    fn read_rows(&self) -> Result<u32, Error<C::Error, R::Error>> {
+        let levels = self.rows.read().map_err(Error::Rows)?;
+        let mask = (1 << ROWS) - 1;
+        Ok(if P::ACTIVE_HIGH {
+            levels & mask
+        } else {
+            !levels & mask
+        })
+    }
+}
+
+impl<C, R, D, const COLS: usize, const ROWS: usize, P> Matrix<COLS, ROWS>
+    for Keypad<C, R, D, COLS, ROWS, P>
+where
+    C: ColumnDriver,
+    R: Rows<ROWS>,
+    D: DelayUs<u32>,
+    P: Polarity,
+{
+    type Error = Error<C::Error, R::Error>;
+
+    fn scan(&mut self) -> Result<u32, Self::Error> {
+        let mut state = 0;
+        for col in 0..COLS {
+            self.select_column(col)?;
+            self.delay.delay_us(SETTLE_US);
+            state |= self.read_column(col)?;
+        }
+        self.columns.release().map_err(Error::Columns)?;
+        Ok(state)
+    }
+
+    fn park(&mut self) -> Result<(), Self::Error> {
+        self.columns.park().map_err(Error::Columns)
+    }
+
+    fn unpark(&mut self) -> Result<(), Self::Error> {
+        self.columns.unpark().map_err(Error::Columns)
+    }
+
+    fn self_test(&mut self) -> Result<SelfTest<COLS, ROWS>, Self::Error> {
+        self.columns.release().map_err(Error::Columns)?;
+        self.delay.delay_us(SELF_TEST_SETTLE_US);
+        Ok(SelfTest {
+            shorted_rows: self.read_rows()? as u8,
+            closed: self.scan()?,
+        })
+    }
+}
+
+impl<C, R, D, const COLS: usize, const ROWS: usize, P> MatrixScanner
+    for Keypad<C, R, D, COLS, ROWS, P>
+where
+    C: ColumnDriver,
+    R: Rows<ROWS>,
+    D: DelayUs<u32>,
+    P: Polarity,
+{
+    type Error = Error<C::Error, R::Error>;
+
+    fn scan_raw(&mut self) -> Result<u32, Self::Error> {
+        Matrix::scan(self)
+    }
+
+    fn dims(&self) -> (u8, u8) {
+        (COLS as u8, ROWS as u8)
+    }
+}
+
+/// A scan spread over `COLS` runs of the scanner, one column each: a run reads
+/// the column the one before selected and selects the next, so the rows settle
+/// for a whole step in between.
+pub struct SlicedScan<const COLS: usize> {
+    selected: Option<usize>,
+    frame: u32,
+}
+
+impl<const COLS: usize> SlicedScan<COLS> {
+    pub const fn new() -> Self {
+        Self {
+            selected: None,
+            frame: 0,
+        }
+    }
+
+    /// Reads a column and selects the next one, returns the whole scan once the
+    /// last column is in. A run after [`SlicedScan::restart`] only selects, as
+    /// does the run after a failed one.
+    pub fn step<C, R, D, const ROWS: usize, P>(
+        &mut self,
+        keypad: &mut Keypad<C, R, D, COLS, ROWS, P>,
+    ) -> Result<Option<u32>, Error<C::Error, R::Error>>
+    where
+        C: ColumnDriver,
+        R: Rows<ROWS>,
+        D: DelayUs<u32>,
+        P: Polarity,
+    {
+        let stepped = self.advance(keypad);
+        if stepped.is_err() {
+            self.restart();
+        }
+        stepped
+    }
+
+    fn advance<C, R, D, const ROWS: usize, P>(
+        &mut self,
+        keypad: &mut Keypad<C, R, D, COLS, ROWS, P>,
+    ) -> Result<Option<u32>, Error<C::Error, R::Error>>
+    where
+        C: ColumnDriver,
+        R: Rows<ROWS>,
+        D: DelayUs<u32>,
+        P: Polarity,
+    {
+        let read = self.selected;
+        if let Some(col) = read {
+            self.frame |= keypad.read_column(col)?;
+        }
+        let next = read.map_or(0, |col| (col + 1) % COLS);
+        keypad.select_column(next)?;
+        self.selected = Some(next);
+        Ok(match read {
+            Some(col) if col + 1 == COLS => Some(core::mem::take(&mut self.frame)),
+            _ => None,
+        })
+    }
+
+    /// Starts over from the first column, after something else drove the
+    /// columns.
+    pub fn restart(&mut self) {
+        self.selected = None;
+        self.frame = 0;
+    }
+}
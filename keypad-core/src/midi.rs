@@ -0,0 +1,123 @@
+//! The keys as MIDI notes: a press sends Note On, a release Note Off, both on
+//! channel 1. With the `velocity` feature a key with a sensor strikes its note as
+//! hard as it was hit.
+//!
+//! Holding the octave key shifts the notes pressed meanwhile up an octave, a note
+//! still gets its Note Off at the pitch it started with. The hold is read from the
+//! debounced state of the keys rather than from events, so the octave key can be
+//! one that never produces any, like the firmware's fn key. Running status leaves
+//! out a status byte that repeats the one before.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::{KeyState, COLUMNS, KEYS};
+use heapless::Vec;
+
+// status bytes of channel 1
+const NOTE_ON: u8 = 0x90;
+const NOTE_OFF: u8 = 0x80;
+const VELOCITY: u8 = 0x64;
+
+pub const OCTAVE: u8 = 12;
+
+/// Two octaves of C major from middle C, by key position, row by row.
+#[cfg(not(feature = "second-pad"))]
+pub const NOTES: [u8; KEYS] = [
+    60, 62, 64, 65, 67, 69, 71, 72, 74, 76, 77, 79, 81, 83, 84, 86,
+];
+/// With `second-pad` its keys go on with the next two octaves in every row.
+#[cfg(feature = "second-pad")]
+pub const NOTES: [u8; KEYS] = [
+    60, 62, 64, 65, 88, 89, 91, 93, // C4-F4, E6-A6
+    67, 69, 71, 72, 95, 96, 98, 100, // G4-C5, B6-E7
+    74, 76, 77, 79, 101, 103, 105, 107, // D5-G5, F7-B7
+    81, 83, 84, 86, 108, 110, 112, 113, // A5-D6, C8-F8
+];
+
+/// A status byte and the two data bytes at most.
+pub type MidiMessage = Vec<u8, 3>;
+
+pub struct Midi {
+    // the bit of the octave key
+    octave_key: KeyState,
+    // the note each key sounds, to end it at the same pitch
+    sounding: [Option<u8>; KEYS],
+    running_status: Option<u8>,
+}
+
+impl Midi {
+    pub const fn new(octave_key: KeyState) -> Self {
+        Self {
+            octave_key,
+            sounding: [None; KEYS],
+            running_status: None,
+        }
+    }
+
+    /// The message for a press or release with the keys in `held`, `None` for the
+    /// other events and the octave key.
+    pub fn handle(&mut self, event: &KeyEvent, held: KeyState) -> Option<MidiMessage> {
+        let key = usize::from(event.row) * COLUMNS + usize::from(event.col);
+        if self.octave_key.0 & (1 << key) != 0 {
+            return None;
+        }
+        let shift = if (held & self.octave_key).is_empty() {
+            0
+        } else {
+            OCTAVE
+        };
+        self.play(event, NOTES.get(key)? + shift)
+    }
+
+    /// The message of `event` for `note`, unshifted, `None` for the events other
+    /// than a press or release. A release ends the note the press started.
+    pub fn play(&mut self, event: &KeyEvent, note: u8) -> Option<MidiMessage> {
+        let pressed = match event.kind {
+            EventKind::Pressed => true,
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => false,
+            _ => return None,
+        };
+        let key = usize::from(event.row) * COLUMNS + usize::from(event.col);
+        let sounding = self.sounding.get_mut(key)?;
+        let (status, note) = if pressed {
+            *sounding = Some(note);
+            (NOTE_ON, note)
+        } else {
+            (NOTE_OFF, sounding.take()?)
+        };
+        // the sensor's velocity of a press, if the key has one
+        #[cfg(feature = "velocity")]
+        let velocity = match event.velocity {
+            0 => VELOCITY,
+            velocity => velocity,
+        };
+        #[cfg(not(feature = "velocity"))]
+        let velocity = VELOCITY;
+        Some(message(&mut self.running_status, status, note, velocity))
+    }
+
+    /// Ends every note still sounding, one message each to `send`. The first one
+    /// spells out its status byte again, the stop doesn't rely on what the
+    /// receiver got before. The releases of the keys find nothing left to end.
+    pub fn release_all(&mut self, mut send: impl FnMut(MidiMessage)) {
+        self.running_status = None;
+        for note in self.sounding.iter_mut().filter_map(Option::take) {
+            send(message(&mut self.running_status, NOTE_OFF, note, VELOCITY));
+        }
+    }
+
+    /// Forgets the status sent last, for a receiver that may have missed it.
+    pub fn reset_running_status(&mut self) {
+        self.running_status = None;
+    }
+}
+
+// without the status byte if it repeats the one sent last
+fn message(running_status: &mut Option<u8>, status: u8, note: u8, velocity: u8) -> MidiMessage {
+    let mut message = MidiMessage::new();
+    if running_status.replace(status) != Some(status) {
+        let _ = message.push(status);
+    }
+    let _ = message.push(note);
+    let _ = message.push(velocity);
+    message
+}
@@ -0,0 +1,35 @@
+//! Lock keys, like a Caps Lock. A press of one toggles its lock instead of
+//! producing events, the release is swallowed as well. Every key event carries
+//! the locks as they were when it was queued, see [`crate::event::KeyEvent`].
+
+use crate::keys::KeyState;
+use serde::Serialize;
+
+/// The locks that are on, a bit per lock.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Modifiers(pub u8);
+
+impl Modifiers {
+    pub fn is_locked(self, lock: u8) -> bool {
+        self.0 & lock != 0
+    }
+
+    /// Toggles the locks of the lock keys pressed in `changes`, the pressed and
+    /// released keys of a frame, and returns the changes of the other keys.
+    /// `locks` pairs every lock key with the bit of its lock.
+    pub fn apply(
+        &mut self,
+        (pressed, released): (KeyState, KeyState),
+        locks: &[(KeyState, u8)],
+    ) -> (KeyState, KeyState) {
+        let mut lock_keys = KeyState(0);
+        for &(key, lock) in locks.iter() {
+            if !(pressed & key).is_empty() {
+                self.0 ^= lock;
+            }
+            lock_keys |= key;
+        }
+        (pressed & !lock_keys, released & !lock_keys)
+    }
+}
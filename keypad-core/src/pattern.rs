@@ -0,0 +1,168 @@
+//! Declarative patterns of the status leds, played by the firmware's
+//! `led_controller`. A pattern is a fixed list of steps, the leds lit and for how
+//! long, played a number of times or until it is stopped. Each plays at its own
+//! priority and the highest one playing is shown; one it takes over from either
+//! waits to go on where it was or is dropped, by its [`Policy`].
+//!
+//! The player has no clock of its own. It takes the time in ms of any source that
+//! counts up, and [`Player::update`] tells when the step shown ends.
+
+/// The leds lit, a bit each.
+pub type LedMask = u8;
+
+/// What a pattern does while one of a higher priority plays.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Waits, then goes on with what was left of its step.
+    Resume,
+    /// Is dropped.
+    Cancel,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    /// The leds and ms of every step, at least 1 ms each.
+    pub steps: &'static [(LedMask, u16)],
+    /// Plays of all the steps, `None` until it is stopped.
+    pub repeat: Option<u8>,
+    pub policy: Policy,
+}
+
+impl Pattern {
+    /// Played until it is stopped, resumed after an interruption.
+    pub const fn new(steps: &'static [(LedMask, u16)]) -> Self {
+        Self {
+            steps,
+            repeat: None,
+            policy: Policy::Resume,
+        }
+    }
+
+    pub const fn times(self, repeat: u8) -> Self {
+        Self {
+            repeat: Some(repeat),
+            ..self
+        }
+    }
+
+    pub const fn cancelled(self) -> Self {
+        Self {
+            policy: Policy::Cancel,
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Playing {
+    pattern: Pattern,
+    step: u8,
+    round: u8,
+    // the end of the step while it is shown, what is left of it while not
+    until_ms: u32,
+}
+
+/// The patterns of `N` priorities, 0 the lowest. One at a time each.
+pub struct Player<const N: usize> {
+    playing: [Option<Playing>; N],
+    shown: Option<usize>,
+}
+
+impl<const N: usize> Player<N> {
+    pub const fn new() -> Self {
+        Self {
+            playing: [None; N],
+            shown: None,
+        }
+    }
+
+    /// Starts `pattern` at `priority` from its first step, in place of the one
+    /// there. If it plays the same steps there it goes on.
+    pub fn play(&mut self, priority: usize, pattern: Pattern, now_ms: u32) {
+        if let Some(playing) = self.playing[priority] {
+            if core::ptr::eq(playing.pattern.steps, pattern.steps) {
+                return;
+            }
+        }
+        if self.shown == Some(priority) {
+            self.shown = None;
+        }
+        self.playing[priority] = Some(Playing {
+            pattern,
+            step: 0,
+            round: 0,
+            until_ms: u32::from(pattern.steps[0].1),
+        });
+        self.show(now_ms);
+    }
+
+    pub fn stop(&mut self, priority: usize, now_ms: u32) {
+        self.playing[priority] = None;
+        self.show(now_ms);
+    }
+
+    /// The priority of the pattern shown.
+    pub fn shown(&self) -> Option<usize> {
+        self.shown
+    }
+
+    pub fn is_playing(&self, priority: usize) -> bool {
+        self.playing[priority].is_some()
+    }
+
+    /// The leds of the step shown.
+    pub fn lights(&self) -> LedMask {
+        match self.shown.and_then(|priority| self.playing[priority]) {
+            Some(playing) => playing.pattern.steps[usize::from(playing.step)].0,
+            None => 0,
+        }
+    }
+
+    /// Moves the pattern shown to its next step if the one shown is over by
+    /// `now_ms`, a step at a time. Returns when the step shown then ends.
+    pub fn update(&mut self, now_ms: u32) -> Option<u32> {
+        let priority = self.shown?;
+        let playing = self.playing[priority].as_mut()?;
+        if (now_ms.wrapping_sub(playing.until_ms) as i32) < 0 {
+            return Some(playing.until_ms);
+        }
+        playing.step += 1;
+        if usize::from(playing.step) == playing.pattern.steps.len() {
+            playing.step = 0;
+            playing.round = playing.round.wrapping_add(1);
+            if playing.pattern.repeat == Some(playing.round) {
+                self.stop(priority, now_ms);
+                return self.until();
+            }
+        }
+        let (_, ms) = playing.pattern.steps[usize::from(playing.step)];
+        playing.until_ms = now_ms.wrapping_add(u32::from(ms));
+        Some(playing.until_ms)
+    }
+
+    /// When the step shown ends.
+    pub fn until(&self) -> Option<u32> {
+        Some(self.playing[self.shown?]?.until_ms)
+    }
+
+    // shows the highest pattern, the one it takes over from keeps what was left of
+    // its step
+    fn show(&mut self, now_ms: u32) {
+        let top = self.playing.iter().rposition(Option::is_some);
+        for playing in self.playing[..top.unwrap_or(0)].iter_mut() {
+            if playing.is_some_and(|playing| playing.pattern.policy == Policy::Cancel) {
+                *playing = None;
+            }
+        }
+        if top == self.shown {
+            return;
+        }
+        if let Some(playing) = self.shown.and_then(|shown| self.playing[shown].as_mut()) {
+            playing.until_ms = playing.until_ms.wrapping_sub(now_ms);
+        }
+        if let Some(playing) = top.and_then(|top| self.playing[top].as_mut()) {
+            playing.until_ms = playing.until_ms.wrapping_add(now_ms);
+        }
+        self.shown = top;
+    }
+}
@@ -0,0 +1,52 @@
+//! The check of the external row pulls at boot: the rows float for a moment with
+//! the columns inactive and get sampled [`SAMPLES`] times, then once more back on
+//! their internal pulls. A row with a pull of its own reads inactive throughout.
+//! One that flickers or sits at the active level while floating has none, only the
+//! weak internal pull holds it, which masks it partially: the row still works but
+//! is slow to settle and picks up noise.
+
+/// Reads of the floating rows.
+pub const SAMPLES: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Inactive floating and on the internal pull.
+    Pulled,
+    /// Changed between the floating reads.
+    Unstable,
+    /// Active in every floating read, inactive on the internal pull.
+    Stuck,
+    /// Active even on the internal pull, shorted to the active level.
+    Shorted,
+}
+
+impl Verdict {
+    pub fn failed(self) -> bool {
+        self != Verdict::Pulled
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Verdict::Pulled => "ok",
+            Verdict::Unstable => "unstable",
+            Verdict::Stuck => "stuck active",
+            Verdict::Shorted => "shorted",
+        }
+    }
+}
+
+/// The verdict on `row` from the active rows of the floating `samples` and of the
+/// read on the internal pulls, `pulled`, bit `row` for the row.
+pub fn verdict(samples: &[u32], pulled: u32, row: usize) -> Verdict {
+    let bit = 1 << row;
+    let active = samples.iter().filter(|&&sample| sample & bit != 0).count();
+    if pulled & bit != 0 {
+        Verdict::Shorted
+    } else if active == 0 {
+        Verdict::Pulled
+    } else if active == samples.len() {
+        Verdict::Stuck
+    } else {
+        Verdict::Unstable
+    }
+}
@@ -0,0 +1,72 @@
+//! The write end of the event queue, with the drops of a full queue made visible.
+//! The queue itself is the sender's, the firmware's is the message queue of its
+//! router task.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// The event that stands for a run of dropped events.
+pub trait Overflow {
+    const OVERFLOW: Self;
+}
+
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Events dropped because the queue was full, since boot.
+pub fn dropped() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Write end of an event queue of `N` slots. A full queue drops the newest
+/// event, and once there is room again an [`Overflow::OVERFLOW`] goes in ahead of
+/// the next event, one for every run of drops.
+///
+/// It counts the events sent and not yet taken, so the consumer calls
+/// [`EventProducer::taken`] for every event it takes off the queue.
+pub struct EventProducer<const N: usize> {
+    queued: usize,
+    overflowed: bool,
+}
+
+impl<const N: usize> EventProducer<N> {
+    pub const fn new() -> Self {
+        Self {
+            queued: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Sends `event` through `send`, which is given a slot of the queue for every
+    /// event. Returns `true` if the event was sent.
+    pub fn push<T: Overflow>(&mut self, event: T, mut send: impl FnMut(T) -> bool) -> bool {
+        // the marker only goes in together with an event, so the consumer never
+        // sees it with the gap still growing
+        let needed = if self.overflowed { 2 } else { 1 };
+        if N - self.queued >= needed {
+            if core::mem::take(&mut self.overflowed) {
+                self.send(T::OVERFLOW, &mut send);
+            }
+            if self.send(event, &mut send) {
+                return true;
+            }
+        }
+        self.overflowed = true;
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    fn send<T>(&mut self, event: T, send: &mut impl FnMut(T) -> bool) -> bool {
+        let sent = send(event);
+        self.queued += usize::from(sent);
+        sent
+    }
+
+    /// The consumer took an event off the queue.
+    pub fn taken(&mut self) {
+        self.queued = self.queued.saturating_sub(1);
+    }
+
+    /// Whether the consumer has events left to take.
+    pub fn pending(&self) -> bool {
+        self.queued > 0
+    }
+}
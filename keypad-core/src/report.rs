@@ -0,0 +1,107 @@
+//! The keyboard reports of the pad built straight from its [`KeyState`]: the
+//! N-key rollover bitmap of the report protocol, a bit per key, and the 6KRO boot
+//! report for a host that asked for the boot protocol. Both take the keys in the
+//! order of [`USAGES`], and so does [`NKRO_DESCRIPTOR`].
+
+use crate::keys::{KeyState, KEYS};
+
+/// Keyboard page usage of every key in `row * COLUMNS + col` order, the
+/// characters of the base layer of the telephone pad: digits, letters, keypad `*`
+/// and keypad `#`.
+#[cfg(not(feature = "second-pad"))]
+pub const USAGES: [u8; KEYS] = [
+    0x1e, 0x1f, 0x20, 0x04, // 1 2 3 A
+    0x21, 0x22, 0x23, 0x05, // 4 5 6 B
+    0x24, 0x25, 0x26, 0x06, // 7 8 9 C
+    0x55, 0x27, 0xcc, 0x07, // * 0 # D
+];
+/// With `second-pad` the letters `E` to `T` of the second pad follow in every row.
+#[cfg(feature = "second-pad")]
+pub const USAGES: [u8; KEYS] = [
+    0x1e, 0x1f, 0x20, 0x04, 0x08, 0x09, 0x0a, 0x0b, // 1 2 3 A E F G H
+    0x21, 0x22, 0x23, 0x05, 0x0c, 0x0d, 0x0e, 0x0f, // 4 5 6 B I J K L
+    0x24, 0x25, 0x26, 0x06, 0x10, 0x11, 0x12, 0x13, // 7 8 9 C M N O P
+    0x55, 0x27, 0xcc, 0x07, 0x14, 0x15, 0x16, 0x17, // * 0 # D Q R S T
+];
+
+pub const NKRO_REPORT_SIZE: usize = KEYS / 8;
+pub const BOOT_REPORT_SIZE: usize = 8;
+
+const BOOT_SLOTS: usize = 6;
+// reported in every slot when more keys are held than the boot report can carry
+const ROLLOVER: u8 = 0x01;
+
+/// Report descriptor of the bitmap: a one bit variable per key, a usage each.
+pub const NKRO_DESCRIPTOR: [u8; 19 + 2 * KEYS] = nkro_descriptor();
+
+const fn nkro_descriptor() -> [u8; 19 + 2 * KEYS] {
+    let mut descriptor = [0; 19 + 2 * KEYS];
+    let head = [
+        0x05, 0x01, // usage page (generic desktop)
+        0x09, 0x06, // usage (keyboard)
+        0xa1, 0x01, // collection (application)
+        0x05, 0x07, // usage page (keyboard)
+    ];
+    let tail = [
+        0x15, 0x00, // logical minimum (0)
+        0x25, 0x01, // logical maximum (1)
+        0x75, 0x01, // report size (1)
+        0x95, KEYS as u8, // report count
+        0x81, 0x02, // input (data, variable, absolute)
+        0xc0, // end collection
+    ];
+    let mut i = 0;
+    while i < head.len() {
+        descriptor[i] = head[i];
+        i += 1;
+    }
+    let mut key = 0;
+    while key < KEYS {
+        descriptor[head.len() + 2 * key] = 0x09;
+        descriptor[head.len() + 2 * key + 1] = USAGES[key];
+        key += 1;
+    }
+    let mut i = 0;
+    while i < tail.len() {
+        descriptor[head.len() + 2 * KEYS + i] = tail[i];
+        i += 1;
+    }
+    descriptor
+}
+
+/// The keys as the bitmap has them, bit `row * COLUMNS + col` for a key, which is
+/// the order of [`USAGES`]. The I2C key register is the same bitmap.
+pub fn bitmap(keys: KeyState) -> u16 {
+    keys.0 as u16
+}
+
+/// The report of the report protocol, the bitmap little endian.
+#[cfg(not(feature = "second-pad"))]
+pub fn nkro_report(keys: KeyState) -> [u8; NKRO_REPORT_SIZE] {
+    bitmap(keys).to_le_bytes()
+}
+
+/// The report of the report protocol, all 32 keys little endian.
+#[cfg(feature = "second-pad")]
+pub fn nkro_report(keys: KeyState) -> [u8; NKRO_REPORT_SIZE] {
+    keys.0.to_le_bytes()
+}
+
+/// The boot keyboard report: no modifiers, and the usages of up to six keys in
+/// the order of [`USAGES`], or the rollover error in every slot for more.
+pub fn boot_report(keys: KeyState) -> [u8; BOOT_REPORT_SIZE] {
+    let mut report = [0; BOOT_REPORT_SIZE];
+    let slots = &mut report[2..];
+    if keys.pressed_count() as usize > BOOT_SLOTS {
+        slots.fill(ROLLOVER);
+        return report;
+    }
+    let held = USAGES
+        .iter()
+        .enumerate()
+        .filter(|&(key, _)| keys.0 & 1 << key != 0);
+    for (slot, (_, &usage)) in slots.iter_mut().zip(held) {
+        *slot = usage;
+    }
+    report
+}
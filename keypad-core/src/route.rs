@@ -0,0 +1,136 @@
+//! Which consumers of the key events get which, the firmware's `subscribe`
+//! feature. Each consumer subscribes to some keys and some kinds of events, and
+//! the router hands an event to the ones whose subscription matches it. A
+//! subscription can take its keys for itself as well, the entry mode's digits
+//! while it is on: the others don't see those events for as long as it holds
+//! them.
+//!
+//! Chords, sequences and the faults are reported on some key, but they aren't
+//! about that one. They only go by [`Kinds::OTHER`].
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::{key_bit, KeyState};
+
+/// Kinds of events, a bit each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Kinds(pub u8);
+
+impl Kinds {
+    pub const NONE: Kinds = Kinds(0);
+    pub const PRESS: Kinds = Kinds(1);
+    /// Both releases, after a long press or not.
+    pub const RELEASE: Kinds = Kinds(1 << 1);
+    pub const REPEAT: Kinds = Kinds(1 << 2);
+    pub const LONG: Kinds = Kinds(1 << 3);
+    pub const DOUBLE_TAP: Kinds = Kinds(1 << 4);
+    pub const OTHER: Kinds = Kinds(1 << 5);
+    /// The kinds of a single key.
+    pub const KEY: Kinds = Kinds(Kinds::OTHER.0 - 1);
+    pub const ALL: Kinds = Kinds(Kinds::KEY.0 | Kinds::OTHER.0);
+
+    pub fn of(kind: EventKind) -> Kinds {
+        match kind {
+            EventKind::Pressed => Kinds::PRESS,
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => Kinds::RELEASE,
+            EventKind::Repeat => Kinds::REPEAT,
+            EventKind::LongPressed => Kinds::LONG,
+            EventKind::DoubleTap => Kinds::DOUBLE_TAP,
+            _ => Kinds::OTHER,
+        }
+    }
+
+    pub const fn with(self, other: Kinds) -> Kinds {
+        Kinds(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: Kinds) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Subscription {
+    pub keys: KeyState,
+    pub kinds: Kinds,
+    // keeps the events it matches from everyone without the flag
+    pub exclusive: bool,
+}
+
+impl Subscription {
+    pub const ALL: Subscription = Subscription::new(KeyState(u32::MAX), Kinds::ALL);
+    pub const NONE: Subscription = Subscription::new(KeyState(0), Kinds::NONE);
+
+    pub const fn new(keys: KeyState, kinds: Kinds) -> Self {
+        Self {
+            keys,
+            kinds,
+            exclusive: false,
+        }
+    }
+
+    /// Every kind of event of `keys`, for this subscription alone.
+    pub const fn grab(keys: KeyState) -> Self {
+        Self {
+            keys,
+            kinds: Kinds::KEY,
+            exclusive: true,
+        }
+    }
+
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let kind = Kinds::of(event.kind);
+        let key = key_bit(usize::from(event.row), usize::from(event.col));
+        self.kinds.contains(kind) && (kind == Kinds::OTHER || self.keys.0 & key != 0)
+    }
+}
+
+/// The consumers an event goes to, bit `n` for consumer `n`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Receivers(pub u32);
+
+impl Receivers {
+    pub const ALL: Receivers = Receivers(u32::MAX);
+
+    pub fn has(self, consumer: usize) -> bool {
+        self.0 & 1 << consumer != 0
+    }
+}
+
+/// The subscriptions of `N` consumers, numbered from 0. Each one starts with
+/// [`Subscription::ALL`].
+pub struct Router<const N: usize> {
+    subscriptions: [Subscription; N],
+}
+
+impl<const N: usize> Router<N> {
+    pub const fn new() -> Self {
+        Self {
+            subscriptions: [Subscription::ALL; N],
+        }
+    }
+
+    /// Replaces the subscription of `consumer`, from the next event on.
+    pub fn subscribe(&mut self, consumer: usize, subscription: Subscription) {
+        self.subscriptions[consumer] = subscription;
+    }
+
+    pub fn subscription(&self, consumer: usize) -> Subscription {
+        self.subscriptions[consumer]
+    }
+
+    /// The consumers `event` goes to. Only the exclusive ones if any of them
+    /// matches, all of those then.
+    pub fn route(&self, event: &KeyEvent) -> Receivers {
+        let mut matched = 0;
+        let mut exclusive = 0;
+        for (consumer, subscription) in self.subscriptions.iter().enumerate() {
+            if subscription.matches(event) {
+                matched |= 1 << consumer;
+                if subscription.exclusive {
+                    exclusive |= 1 << consumer;
+                }
+            }
+        }
+        Receivers(if exclusive != 0 { exclusive } else { matched })
+    }
+}
@@ -0,0 +1,76 @@
+//! Secret key sequences, like `C 1 C 1 #` typed within 3 s, each reported as one
+//! `SequenceMatched(id)` event on its last key. The firmware's sequences are the
+//! `[[sequence]]` entries of its `keymap.toml`.
+//!
+//! Every sequence is tracked on its own, so sequences sharing a prefix all move
+//! on with it. A press that doesn't continue a sequence falls back to the longest
+//! start of it the last presses still spell, as a KMP matcher does: `C 1 C 1 C 1 #`
+//! completes `C 1 C 1 #`, and a stray key in between starts it over. Only presses
+//! count, by the character they resolved to.
+
+use crate::event::{EventKind, KeyEvent};
+
+/// The most keys a sequence can have, the presses the detector remembers.
+pub const MAX_KEYS: usize = 16;
+
+/// The `keys` pressed in order, the last one within `within_ms` of the first one.
+pub struct Sequence {
+    pub id: u8,
+    pub keys: &'static [char],
+    pub within_ms: u32,
+}
+
+pub struct SequenceDetector<const N: usize> {
+    sequences: &'static [Sequence; N],
+    // keys of each sequence the last presses spell
+    matched: [u8; N],
+    // ms of the last presses, the newest at `last`
+    presses: [u32; MAX_KEYS],
+    last: usize,
+}
+
+impl<const N: usize> SequenceDetector<N> {
+    /// Tracks `sequences`, none of them longer than [`MAX_KEYS`].
+    pub const fn new(sequences: &'static [Sequence; N]) -> Self {
+        Self {
+            sequences,
+            matched: [0; N],
+            presses: [0; MAX_KEYS],
+            last: 0,
+        }
+    }
+
+    /// The id of the sequence `event` completes, of the first one in the table if
+    /// it completes several.
+    pub fn track(&mut self, event: &KeyEvent, now: u32) -> Option<u8> {
+        if event.kind != EventKind::Pressed {
+            return None;
+        }
+        self.last = (self.last + 1) % MAX_KEYS;
+        self.presses[self.last] = now;
+        let mut completed = None;
+        for (sequence, matched) in self.sequences.iter().zip(&mut self.matched) {
+            let keys = sequence.keys;
+            let before = usize::from(*matched);
+            // the presses end with `keys[..before]` and this one, so a longer
+            // start of the sequence ending here can only be one of those
+            let now_matched = (1..=before + 1)
+                .rev()
+                .find(|&len| {
+                    let first = self.presses[(self.last + MAX_KEYS + 1 - len) % MAX_KEYS];
+                    keys[len - 1] == event.key
+                        && keys[..len - 1] == keys[before + 1 - len..before]
+                        && now.wrapping_sub(first) <= sequence.within_ms
+                })
+                .unwrap_or(0);
+            // a completed sequence starts over, its keys don't begin the next one
+            if now_matched == keys.len() {
+                *matched = 0;
+                completed = completed.or(Some(sequence.id));
+            } else {
+                *matched = now_matched as u8;
+            }
+        }
+        completed
+    }
+}
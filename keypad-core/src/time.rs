@@ -0,0 +1,62 @@
+//! The time base of the events, ticks of the firmware's 1 MHz monotonic, and the
+//! extension of its 16 bit timer to 64 bits.
+
+pub const TICK_HZ: u32 = 1_000_000;
+
+/// Milliseconds in `ticks`.
+pub fn to_ms(ticks: u64) -> u64 {
+    div(ticks, TICK_HZ / 1000)
+}
+
+/// `value / divisor` for a divisor below 2^16, on the 32 bit divider of the core.
+/// The u64 division of the compiler builtins takes close to 1K of flash.
+pub fn div(value: u64, divisor: u32) -> u64 {
+    let mut quotient = 0;
+    let mut remainder = 0;
+    // 16 bits at a time, the remainder times 2^16 stays within 32 bits
+    for shift in [48, 32, 16, 0] {
+        let part = remainder << 16 | (value >> shift) as u32 & 0xffff;
+        quotient = quotient << 16 | u64::from(part / divisor);
+        remainder = part % divisor;
+    }
+    quotient
+}
+
+const OVERFLOW_TICKS: u64 = 1 << 16;
+
+/// The counter of a 16 bit timer and its overflow flag.
+pub trait Counter16 {
+    fn count(&self) -> u16;
+    /// Whether the counter overflowed since the last [`WideCounter::overflowed`].
+    fn overflow_pending(&self) -> bool;
+}
+
+/// A 16 bit counter extended to 64 bits by its overflows, which the update
+/// interrupt hands to [`WideCounter::overflowed`], so the time never wraps.
+pub struct WideCounter {
+    // ticks of the overflows handled so far
+    overflows: u64,
+}
+
+impl WideCounter {
+    pub const fn new() -> Self {
+        Self { overflows: 0 }
+    }
+
+    /// Takes the overflow of an update interrupt.
+    pub fn overflowed(&mut self) {
+        self.overflows += OVERFLOW_TICKS;
+    }
+
+    /// The ticks of `counter`, with interrupts disabled. An overflow that is still
+    /// pending hasn't been added yet, the count is read again in case it
+    /// overflowed in between.
+    pub fn now(&self, counter: &impl Counter16) -> u64 {
+        let count = counter.count();
+        if counter.overflow_pending() {
+            self.overflows + OVERFLOW_TICKS + u64::from(counter.count())
+        } else {
+            self.overflows + u64::from(count)
+        }
+    }
+}
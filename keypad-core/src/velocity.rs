@@ -0,0 +1,96 @@
+//! Strike velocity of a key from the force sensing resistor under it. The reading
+//! of the sensor rises as the key goes down, and the faster it rises the harder
+//! the strike: [`Strike`] follows the readings of one sensor, measures the slope of
+//! the rising edge from the last reading below its onset to its peak and scales it
+//! to a velocity of 1 to [`MAX_VELOCITY`], the range of a MIDI Note On.
+
+use crate::time;
+
+pub const MAX_VELOCITY: u8 = 127;
+
+const TICKS_PER_MS: u32 = time::TICK_HZ / 1000;
+
+/// Where a strike starts and ends and how fast a full one rises, the readings in
+/// raw ADC counts.
+pub struct StrikeConfig {
+    // a reading from here up starts a strike
+    pub onset: u16,
+    // and one below this ends it, below `onset` for the noise
+    pub release: u16,
+    // counts per millisecond of a strike at full velocity
+    pub full_slope: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    // the reading and the tick of the latest one, where a rising edge would start
+    Idle {
+        last: u16,
+        at: u64,
+    },
+    Rising {
+        from: u16,
+        since: u64,
+        peak: u16,
+        velocity: u8,
+    },
+    Held {
+        velocity: u8,
+    },
+}
+
+/// The strikes of one sensor.
+pub struct Strike {
+    state: State,
+}
+
+impl Strike {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle { last: 0, at: 0 },
+        }
+    }
+
+    /// Feeds the reading `value` at the monotonic tick `at`. The edge rises while
+    /// every reading is above the peak so far, the first one that isn't ends it.
+    pub fn update(&mut self, config: &StrikeConfig, value: u16, at: u64) {
+        self.state = match self.state {
+            State::Idle { last, at: since } if value >= config.onset => State::Rising {
+                from: last,
+                since,
+                peak: value,
+                velocity: scale(config, value.saturating_sub(last), at.wrapping_sub(since)),
+            },
+            State::Idle { .. } => State::Idle { last: value, at },
+            _ if value < config.release => State::Idle { last: value, at },
+            State::Rising {
+                from, since, peak, ..
+            } if value > peak => State::Rising {
+                from,
+                since,
+                peak: value,
+                velocity: scale(config, value - from, at.wrapping_sub(since)),
+            },
+            State::Rising { velocity, .. } => State::Held { velocity },
+            held => held,
+        };
+    }
+
+    /// Velocity of the strike under way, the slope so far while the edge still
+    /// rises, `None` between strikes.
+    pub fn velocity(&self) -> Option<u8> {
+        match self.state {
+            State::Idle { .. } => None,
+            State::Rising { velocity, .. } | State::Held { velocity } => Some(velocity),
+        }
+    }
+}
+
+// a rise of `rise` counts over `ticks`, anything faster than a millisecond counts
+// as one. A strike is short, the ticks fit into 32 bits and so does the math.
+fn scale(config: &StrikeConfig, rise: u16, ticks: u64) -> u8 {
+    let ticks = u32::try_from(ticks).unwrap_or(u32::MAX).max(TICKS_PER_MS);
+    let full_slope = config.full_slope.max(1);
+    let slope = (u32::from(rise) * TICKS_PER_MS / ticks).min(full_slope);
+    (slope * u32::from(MAX_VELOCITY) / full_slope).max(1) as u8
+}
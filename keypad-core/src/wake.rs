@@ -0,0 +1,72 @@
+//! The press that wakes the waiting scanner. With the columns all driven the rows
+//! interrupt on their press edge, and the first of them posts its row and its tick
+//! to a [`WakeMailbox`] before the lines are masked again. The debounced frame
+//! that has the press only comes a few scans later, [`WakeMailbox::merge`] gives
+//! it back the key that woke the scanner and the tick of its edge. The bounces of
+//! the edge, or a second key pressed during the handover, don't take the slot.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keys::{KeyState, COLUMNS};
+
+/// The key that woke the scanner, by its index `row * COLUMNS + col`, and the
+/// tick of the wake.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WokenKey {
+    pub index: usize,
+    pub at: u64,
+}
+
+impl WokenKey {
+    /// Backdates the press of the key to the wake, any other event stays as it is.
+    pub fn backdate(self, event: &mut KeyEvent) {
+        let index = usize::from(event.row) * COLUMNS + usize::from(event.col);
+        if index == self.index && event.kind == EventKind::Pressed {
+            event.at = self.at;
+        }
+    }
+}
+
+/// One slot for the wake, filled by the row interrupt and emptied by the scanner.
+pub struct WakeMailbox {
+    row: Option<u8>,
+    at: u64,
+}
+
+impl WakeMailbox {
+    pub const fn new() -> Self {
+        Self { row: None, at: 0 }
+    }
+
+    /// Keeps the first wake until the scanner takes it, returns whether this one
+    /// was kept.
+    pub fn post(&mut self, row: u8, at: u64) -> bool {
+        if self.row.is_some() {
+            return false;
+        }
+        self.row = Some(row);
+        self.at = at;
+        true
+    }
+
+    /// Drops a wake no press came of, before the scanner waits again.
+    pub fn clear(&mut self) {
+        self.row = None;
+    }
+
+    /// Merges the wake into the first frame with presses, the `pressed` of
+    /// `KeyFrames::push`: the first key pressed in the row of the wake is the one
+    /// that woke the scanner. Without a press in that row the wake was a bounce, or
+    /// another key beat it, and all the keys of the frame keep the tick of their
+    /// scan. A frame without presses leaves the wake in the slot.
+    pub fn merge(&mut self, pressed: KeyState) -> Option<WokenKey> {
+        if pressed.is_empty() {
+            return None;
+        }
+        let row = self.row.take()?;
+        let keys = pressed & KeyState(((1 << COLUMNS) - 1) << (usize::from(row) * COLUMNS));
+        (!keys.is_empty()).then(|| WokenKey {
+            index: keys.first(),
+            at: self.at,
+        })
+    }
+}
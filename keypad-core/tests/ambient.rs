@@ -0,0 +1,61 @@
+//! The brightness the calibration curve gives an ambient light reading, and the
+//! slew over to a new one.
+
+use keypad_core::ambient::{Curve, Slew};
+
+const CURVE: Curve = Curve([(100, 10), (1100, 110), (2100, 60), (3100, 255)]);
+
+#[test]
+fn straight_lines_between_the_points() {
+    assert_eq!(CURVE.brightness(100), 10);
+    assert_eq!(CURVE.brightness(600), 60);
+    assert_eq!(CURVE.brightness(1100), 110);
+    // down as well as up
+    assert_eq!(CURVE.brightness(1600), 85);
+    assert_eq!(CURVE.brightness(3100), 255);
+}
+
+#[test]
+fn flat_past_the_ends() {
+    assert_eq!(CURVE.brightness(0), 10);
+    assert_eq!(CURVE.brightness(4095), 255);
+}
+
+#[test]
+fn points_stay_in_rising_order() {
+    assert!(Curve::DEFAULT.is_valid());
+    let mut curve = CURVE;
+    assert!(!curve.set(1, 2100, 0));
+    assert!(!curve.set(4, 4000, 0));
+    assert_eq!(curve, CURVE);
+    assert!(curve.set(1, 2000, 200));
+    assert_eq!(curve.brightness(2000), 200);
+}
+
+#[test]
+fn slews_in_equal_steps() {
+    let mut slew = Slew::new(0);
+    slew.slew_to(250, 125);
+    let steps: Vec<u8> = std::iter::from_fn(|| slew.advance()).collect();
+    assert_eq!(steps.len(), 125);
+    assert_eq!(steps[0], 2);
+    assert_eq!(slew.current(), 250);
+
+    // a short way takes single steps, and a step never overshoots
+    slew.slew_to(247, 125);
+    assert_eq!(std::iter::from_fn(|| slew.advance()).count(), 3);
+    slew.slew_to(0, 2);
+    assert_eq!(slew.advance(), Some(123));
+    assert_eq!(slew.advance(), Some(0));
+    assert_eq!(slew.advance(), None);
+}
+
+#[test]
+fn a_jump_ends_the_slew() {
+    let mut slew = Slew::new(10);
+    slew.slew_to(200, 125);
+    slew.advance();
+    slew.jump(50);
+    assert_eq!(slew.current(), 50);
+    assert_eq!(slew.advance(), None);
+}
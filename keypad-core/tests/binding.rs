@@ -0,0 +1,64 @@
+//! The key bindings: what `BIND` accepts, how a binding reads back and the bytes
+//! it is saved as.
+
+use keypad_core::binding::{Binding, MAX_NOTE};
+
+#[test]
+fn parses_every_backend() {
+    assert_eq!(Binding::parse("KEY"), Some(Binding::Key));
+    assert_eq!(Binding::parse("NONE"), Some(Binding::None));
+    assert_eq!(Binding::parse("HID:0X1E"), Some(Binding::Hid(0x1e)));
+    assert_eq!(Binding::parse("HID:30"), Some(Binding::Hid(0x1e)));
+    assert_eq!(Binding::parse("MIDI:60"), Some(Binding::Midi(60)));
+    assert_eq!(Binding::parse("MACRO:2"), Some(Binding::Macro(2)));
+}
+
+#[test]
+fn rejects_the_rest() {
+    for word in [
+        "",
+        "KEY:1",
+        "NONE:0",
+        "HID",
+        "HID:",
+        "HID:0",
+        "HID:0X",
+        "HID:0X100",
+        "HID:1E",
+        "MIDI:128",
+        "MIDI:-1",
+        "MACRO:256",
+        "hid:0x1e",
+        "SERVO:1",
+    ] {
+        assert_eq!(Binding::parse(word), None, "{}", word);
+    }
+    assert!(Binding::parse(&format!("MIDI:{}", MAX_NOTE)).is_some());
+}
+
+#[test]
+fn shows_in_lower_case() {
+    assert_eq!(Binding::Hid(0x1e).to_string(), "hid:0x1e");
+    assert_eq!(Binding::Midi(60).to_string(), "midi:60");
+    assert_eq!(Binding::Macro(2).to_string(), "macro:2");
+    assert_eq!(Binding::None.to_string(), "none");
+}
+
+#[test]
+fn bytes_round_trip() {
+    let bindings = [
+        Binding::Key,
+        Binding::Hid(0xe1),
+        Binding::Midi(MAX_NOTE),
+        Binding::Macro(0),
+        Binding::None,
+    ];
+    for binding in bindings {
+        assert_eq!(Binding::from_bytes(binding.to_bytes()), Some(binding));
+    }
+    // an erased page, and the bytes only `Key` may have
+    assert_eq!(Binding::to_bytes(Binding::Key), [0, 0]);
+    assert_eq!(Binding::from_bytes([0xff, 0xff]), None);
+    assert_eq!(Binding::from_bytes([0, 1]), None);
+    assert_eq!(Binding::from_bytes([1, 0]), None);
+}
@@ -0,0 +1,64 @@
+//! The edges of a bouncing key as the row interrupt records them, and the deltas
+//! the scanner dumps once the key settled.
+
+use keypad_core::bounce::{EdgeLog, MAX_EDGES};
+
+const CYCLES_PER_US: u32 = 72;
+const QUIET: u32 = 20_000 * CYCLES_PER_US;
+
+fn at_us(stamps_us: &[u32]) -> EdgeLog {
+    let mut edges = EdgeLog::new();
+    for &us in stamps_us {
+        edges.record(us * CYCLES_PER_US);
+    }
+    edges
+}
+
+#[test]
+fn deltas_between_the_edges() {
+    let edges = at_us(&[1000, 1012, 1030, 1031, 1250]);
+    assert_eq!(edges.len(), 5);
+    assert_eq!(
+        edges.deltas_us(CYCLES_PER_US).collect::<Vec<_>>(),
+        [12, 18, 1, 219]
+    );
+    assert_eq!(edges.span(), 250 * CYCLES_PER_US);
+}
+
+#[test]
+fn settles_once_quiet() {
+    let edges = at_us(&[1000, 1100]);
+    assert!(!edges.settled(1100 * CYCLES_PER_US + QUIET - 1, QUIET));
+    assert!(edges.settled(1100 * CYCLES_PER_US + QUIET, QUIET));
+    // nothing to settle without an edge
+    assert!(!EdgeLog::new().settled(u32::MAX, QUIET));
+}
+
+#[test]
+fn edges_across_the_counter_wrap() {
+    let mut edges = EdgeLog::new();
+    edges.record(u32::MAX - 71);
+    edges.record(72);
+    assert_eq!(edges.deltas_us(CYCLES_PER_US).collect::<Vec<_>>(), [2]);
+    assert!(!edges.settled(1000, QUIET));
+    assert!(edges.settled(72 + QUIET, QUIET));
+}
+
+#[test]
+fn edges_past_the_log_are_counted() {
+    let stamps: Vec<u32> = (0..MAX_EDGES as u32 + 3).map(|us| us * 10).collect();
+    let mut edges = at_us(&stamps);
+    assert_eq!(edges.len(), MAX_EDGES);
+    assert_eq!(edges.dropped(), 3);
+    assert_eq!(edges.deltas_us(CYCLES_PER_US).count(), MAX_EDGES - 1);
+    // the last edge counts for settling and the span even if it wasn't kept
+    let last = *stamps.last().unwrap() * CYCLES_PER_US;
+    assert_eq!(edges.span(), last);
+    assert!(!edges.settled(last + QUIET - 1, QUIET));
+
+    edges.clear();
+    assert!(edges.is_empty());
+    assert_eq!(edges.dropped(), 0);
+    edges.record(5);
+    assert_eq!(edges.span(), 0);
+}
@@ -0,0 +1,41 @@
+//! The breathing table: dark at both ends, full in the middle, rising and falling
+//! smoothly, and a breath that wraps around after a cycle.
+
+use keypad_core::breath::{Breath, STEPS, TABLE};
+
+#[test]
+fn swells_to_full_and_fades_back() {
+    assert_eq!(STEPS, 150);
+    assert_eq!(TABLE[0], 0);
+    assert_eq!(TABLE[STEPS / 2], 255);
+    assert!(TABLE[..=STEPS / 2]
+        .windows(2)
+        .all(|pair| pair[0] <= pair[1]));
+    assert!(TABLE[STEPS / 2..].windows(2).all(|pair| pair[0] >= pair[1]));
+    // the fade is the swell backwards
+    for step in 1..STEPS / 2 {
+        assert_eq!(TABLE[step], TABLE[STEPS - step]);
+    }
+}
+
+#[test]
+fn gamma_keeps_the_dark_half_long() {
+    // a quarter in, the led is well below a quarter lit
+    assert!(TABLE[STEPS / 8] < 64);
+    // and no step jumps by much
+    assert!(TABLE.windows(2).all(|pair| pair[0].abs_diff(pair[1]) <= 16));
+}
+
+#[test]
+fn a_breath_wraps_after_a_cycle() {
+    let mut breath = Breath::new();
+    assert_eq!(breath.level(), 0);
+    for _ in 0..STEPS / 2 {
+        breath.advance();
+    }
+    assert_eq!(breath.level(), 255);
+    for _ in 0..STEPS / 2 {
+        breath.advance();
+    }
+    assert_eq!(breath, Breath::new());
+}
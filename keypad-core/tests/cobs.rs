@@ -0,0 +1,127 @@
+//! The frames of the serial link through the decoder: payloads with and without
+//! zeros, a run of non-zero bytes as long as a COBS block holds, frames failing
+//! their CRC or too long, and a receiver attaching in the middle of the stream.
+
+use keypad_core::cobs::{
+    cobs_decode, cobs_encode, encode_frame, max_frame_len, CorruptedFrame, FrameDecoder,
+};
+use keypad_core::crc::words;
+
+const POLY: u32 = 0x04c1_1db7;
+
+const MAX_FRAME: usize = max_frame_len(300);
+
+// the CRC unit of the firmware, a word at a time most significant bit first
+fn checksum(bytes: &[u8]) -> u32 {
+    words(bytes).fold(0xffff_ffff, |crc, word| {
+        (0..32).fold(crc ^ word, |crc, _| match crc >> 31 {
+            1 => crc << 1 ^ POLY,
+            _ => crc << 1,
+        })
+    })
+}
+
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0; max_frame_len(payload.len())];
+    let len = encode_frame(payload, checksum, &mut out);
+    out.truncate(len);
+    out
+}
+
+// what the decoder made of `bytes`, a frame at a time
+fn feed(
+    decoder: &mut FrameDecoder<MAX_FRAME>,
+    bytes: &[u8],
+) -> Vec<Result<Vec<u8>, CorruptedFrame>> {
+    bytes
+        .iter()
+        .filter_map(|&byte| Some(decoder.feed(byte)?.map(<[u8]>::to_vec)))
+        .collect()
+}
+
+#[test]
+fn a_frame_comes_back_as_its_payload() {
+    let mut decoder = FrameDecoder::new(checksum);
+    let payloads: [&[u8]; 5] = [&[], &[0], &[1, 2, 3], &[0, 0, 5, 0], b"KEY 1 5\n"];
+    for payload in payloads {
+        let frame = frame(payload);
+        assert_eq!(
+            frame.iter().position(|&byte| byte == 0),
+            Some(frame.len() - 1)
+        );
+        assert!(frame.len() <= max_frame_len(payload.len()));
+        assert_eq!(feed(&mut decoder, &frame), [Ok(payload.to_vec())]);
+    }
+    assert_eq!(decoder.errors(), 0);
+}
+
+#[test]
+fn a_run_of_254_non_zero_bytes_fills_one_block() {
+    let run: Vec<u8> = (0..254).map(|byte| byte as u8 + 1).collect();
+    let mut out = [0; 258];
+    let len = cobs_encode(run.iter().copied(), &mut out);
+    // the full block's code, the run and the code of an empty last block
+    assert_eq!(len, 256);
+    assert_eq!((out[0], out[255]), (0xff, 1));
+    assert_eq!(&out[1..255], run.as_slice());
+    assert_eq!(cobs_decode(&mut out[..len]), Some(254));
+    assert_eq!(&out[..254], run.as_slice());
+
+    // and framed, the CRC starting the next block
+    let mut decoder = FrameDecoder::new(checksum);
+    for payload in [&run[..253], &run, &[run.as_slice(), &[7]].concat()] {
+        assert_eq!(feed(&mut decoder, &frame(payload)), [Ok(payload.to_vec())]);
+    }
+}
+
+#[test]
+fn a_corrupted_frame_is_counted() {
+    let mut decoder = FrameDecoder::new(checksum);
+    let mut corrupted = frame(b"STATUS");
+    corrupted[3] ^= 0x20;
+    assert_eq!(feed(&mut decoder, &corrupted), [Err(CorruptedFrame)]);
+    assert_eq!(decoder.errors(), 1);
+    // too short for a CRC, and a code past the end of the block
+    assert_eq!(feed(&mut decoder, &[2, 1, 0]), [Err(CorruptedFrame)]);
+    assert_eq!(feed(&mut decoder, &[9, 1, 2, 0]), [Err(CorruptedFrame)]);
+    assert_eq!(decoder.errors(), 3);
+    // the next frame is fine
+    assert_eq!(
+        feed(&mut decoder, &frame(b"STATUS")),
+        [Ok(b"STATUS".to_vec())]
+    );
+    assert_eq!(decoder.errors(), 3);
+}
+
+#[test]
+fn a_frame_too_long_is_dropped_whole() {
+    let mut decoder = FrameDecoder::<MAX_FRAME>::new(checksum);
+    let long = frame(&[1; 400]);
+    assert_eq!(feed(&mut decoder, &long), [Err(CorruptedFrame)]);
+    assert_eq!(feed(&mut decoder, &frame(&[1; 300])), [Ok(vec![1; 300])]);
+    assert_eq!(decoder.errors(), 1);
+}
+
+#[test]
+fn a_receiver_attaching_mid_stream_resyncs_on_the_next_delimiter() {
+    let mut stream = Vec::new();
+    for payload in [&b"PING"[..], b"KEY 1 5", b"STATUS"] {
+        stream.extend(frame(payload));
+    }
+    // idle delimiters in between are nothing
+    stream.extend([0, 0]);
+    stream.extend(frame(b"OK"));
+    let mut decoder = FrameDecoder::new(checksum);
+    // from the middle of the first frame
+    let frames = feed(&mut decoder, &stream[3..]);
+    assert_eq!(
+        frames,
+        [
+            Err(CorruptedFrame),
+            Ok(b"KEY 1 5".to_vec()),
+            Ok(b"STATUS".to_vec()),
+            Ok(b"OK".to_vec()),
+        ]
+    );
+    assert_eq!(decoder.errors(), 1);
+}
@@ -0,0 +1,71 @@
+//! The words of the CRC unit against a software CRC-32/MPEG-2 over the bytes the
+//! way a host tool computes it, and a model of the unit fed those words.
+
+use keypad_core::crc::words;
+
+const POLY: u32 = 0x04c1_1db7;
+
+// CRC-32/MPEG-2, a byte at a time
+fn mpeg2(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    bytes.into_iter().fold(0xffff_ffff, |crc, byte| {
+        (0..8).fold(crc ^ u32::from(byte) << 24, |crc, _| match crc >> 31 {
+            1 => crc << 1 ^ POLY,
+            _ => crc << 1,
+        })
+    })
+}
+
+// the unit, a word at a time most significant bit first
+fn unit(words: impl IntoIterator<Item = u32>) -> u32 {
+    words.into_iter().fold(0xffff_ffff, |crc, word| {
+        (0..32).fold(crc ^ word, |crc, _| match crc >> 31 {
+            1 => crc << 1 ^ POLY,
+            _ => crc << 1,
+        })
+    })
+}
+
+// what the host does: pads to whole words, appends the count and reverses every
+// group of four bytes
+fn host(bytes: &[u8]) -> u32 {
+    let mut padded = bytes.to_vec();
+    padded.resize(bytes.len().next_multiple_of(4), 0);
+    padded.extend((bytes.len() as u32).to_le_bytes());
+    mpeg2(
+        padded
+            .chunks(4)
+            .flat_map(|group| group.iter().rev().copied()),
+    )
+}
+
+#[test]
+fn the_reference_is_mpeg2() {
+    assert_eq!(mpeg2(*b"123456789"), 0x0376_e6e7);
+}
+
+#[test]
+fn bytes_go_in_as_little_endian_words() {
+    assert_eq!(
+        words(b"abcdef").collect::<Vec<_>>(),
+        [0x6463_6261, 0x6665, 6]
+    );
+    assert_eq!(words(&[]).collect::<Vec<_>>(), [0]);
+}
+
+#[test]
+fn the_unit_agrees_with_the_host_for_every_length() {
+    let bytes: Vec<u8> = (0..=40u8)
+        .map(|byte| byte.wrapping_mul(37).wrapping_add(11))
+        .collect();
+    for len in 0..bytes.len() {
+        let bytes = &bytes[..len];
+        assert_eq!(unit(words(bytes)), host(bytes), "{len} bytes");
+    }
+}
+
+#[test]
+fn the_count_tells_trailing_zeros_apart() {
+    let short = unit(words(&[1, 2]));
+    assert_ne!(short, unit(words(&[1, 2, 0])));
+    assert_ne!(short, unit(words(&[1, 2, 0, 0])));
+}
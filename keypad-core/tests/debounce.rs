@@ -0,0 +1,217 @@
+//! Separate press and release thresholds, with switches that only bounce one way,
+//! and keys with thresholds of their own next to the others in the same frames.
+//! The single pin debouncer of a button outside the matrix counts the same way.
+
+use keypad_core::debounce::{Debouncer, Edge, KeyFilter, PinDebouncer, Thresholds};
+use keypad_core::event::{EventKind, KeyFrames};
+use keypad_core::keys::{key_bit, COLUMNS, KEYS, ROWS};
+
+const KEY: u32 = key_bit(1, 2);
+// the arcade button among the keys, with a threshold of its own
+const SLOW: u32 = key_bit(0, 0);
+const SLOW_THRESHOLD: u8 = 12;
+
+fn slow_key() -> [u8; KEYS] {
+    let mut per_key = [0; KEYS];
+    per_key[SLOW.trailing_zeros() as usize] = SLOW_THRESHOLD;
+    per_key
+}
+
+// a release that bounces for a while before it settles
+const BOUNCING_RELEASE: [u32; 5] = [0, KEY, 0, KEY, 0];
+
+fn run(debouncer: &mut Debouncer<KEYS>, raws: &[u32]) -> Vec<u32> {
+    raws.iter().map(|&raw| debouncer.update(raw)).collect()
+}
+
+#[test]
+fn clean_press_bouncing_release() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds {
+        press: 1,
+        release: 5,
+    });
+    // a single scan presses it
+    assert_eq!(debouncer.update(KEY), KEY);
+    // the bounces of the release don't let go of it
+    assert_eq!(run(&mut debouncer, &BOUNCING_RELEASE), [KEY; 5]);
+    // the release debounces once it settled
+    assert_eq!(run(&mut debouncer, &[0; 4]), [KEY, KEY, KEY, 0]);
+}
+
+#[test]
+fn symmetric_threshold_chatters_on_the_same_release() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds::symmetric(1));
+    assert_eq!(debouncer.update(KEY), KEY);
+    assert_eq!(run(&mut debouncer, &BOUNCING_RELEASE), [0, KEY, 0, KEY, 0]);
+}
+
+#[test]
+fn bouncing_press_clean_release() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds {
+        press: 5,
+        release: 1,
+    });
+    // the bounces of the press take it up to the threshold slowly
+    let bouncing_press = [KEY, KEY, 0, KEY, KEY, 0, KEY, KEY];
+    assert_eq!(run(&mut debouncer, &bouncing_press), [0; 8]);
+    assert_eq!(debouncer.update(KEY), KEY);
+    // a single scan releases it, however long it was held
+    assert_eq!(run(&mut debouncer, &[KEY; 10]), [KEY; 10]);
+    assert_eq!(debouncer.update(0), 0);
+}
+
+#[test]
+fn lower_release_threshold_keeps_held_keys() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds::symmetric(5));
+    run(&mut debouncer, &[KEY; 5]);
+    debouncer.set_thresholds(Thresholds {
+        press: 5,
+        release: 2,
+    });
+    assert_eq!(debouncer.state(), KEY);
+    assert_eq!(run(&mut debouncer, &[0, 0]), [KEY, 0]);
+}
+
+#[test]
+fn one_press_and_one_release_through_the_filter() {
+    let mut filter = KeyFilter::<COLUMNS, ROWS, KEYS>::new(
+        Thresholds {
+            press: 1,
+            release: 5,
+        },
+        30_000,
+    );
+    let mut frames = KeyFrames::new();
+    let raws = [KEY, KEY].into_iter().chain(BOUNCING_RELEASE).chain([0; 5]);
+    let mut kinds = Vec::new();
+    for (now, raw) in (1..).zip(raws) {
+        let changes = frames.push(filter.update(raw, now).state);
+        kinds.extend(
+            frames
+                .edges(changes, u64::from(now))
+                .map(|event| event.kind),
+        );
+    }
+    assert_eq!(kinds.len(), 2);
+    assert_eq!(kinds[0], EventKind::Pressed);
+    assert!(matches!(kinds[1], EventKind::Released { .. }));
+}
+
+#[test]
+fn mixed_thresholds_in_one_frame() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds::symmetric(3));
+    debouncer.set_key_thresholds(&slow_key());
+    let both = run(&mut debouncer, &[KEY | SLOW; 12]);
+    assert_eq!(both[..2], [0; 2]);
+    assert_eq!(both[2..11], [KEY; 9]);
+    assert_eq!(both[11], KEY | SLOW);
+
+    let released = run(&mut debouncer, &[0; 12]);
+    assert_eq!(released[..2], [KEY | SLOW; 2]);
+    assert_eq!(released[2..11], [SLOW; 9]);
+    assert_eq!(released[11], 0);
+}
+
+#[test]
+fn the_slow_key_rides_out_bounces_the_others_take() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds::symmetric(1));
+    debouncer.set_key_thresholds(&slow_key());
+    run(&mut debouncer, &[KEY | SLOW; 12]);
+    // both release with the same bounces, only the other key follows them
+    let bounces = [0, KEY | SLOW, 0, KEY | SLOW, 0];
+    assert_eq!(
+        run(&mut debouncer, &bounces),
+        [SLOW, KEY | SLOW, SLOW, KEY | SLOW, SLOW]
+    );
+}
+
+#[test]
+fn keys_without_their_own_follow_the_shared_thresholds() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds::symmetric(3));
+    debouncer.set_key_thresholds(&slow_key());
+    debouncer.set_thresholds(Thresholds::symmetric(1));
+    assert_eq!(debouncer.update(KEY | SLOW), KEY);
+    // and clearing the key's own puts it on them as well
+    run(&mut debouncer, &[KEY | SLOW; 4]);
+    debouncer.set_key_thresholds(&[0; KEYS]);
+    assert_eq!(debouncer.update(KEY | SLOW), KEY | SLOW);
+    assert_eq!(debouncer.update(0), 0);
+}
+
+#[test]
+fn the_latency_goes_by_the_longest_threshold() {
+    let shared = Thresholds {
+        press: 2,
+        release: 6,
+    };
+    assert_eq!(shared.with_keys(&[0; KEYS]), shared);
+    assert_eq!(
+        shared.with_keys(&[0, 4, 0, 0]),
+        Thresholds {
+            press: 4,
+            release: 6
+        }
+    );
+    assert_eq!(
+        shared.with_keys(&slow_key()),
+        Thresholds::symmetric(SLOW_THRESHOLD)
+    );
+}
+
+fn samples(debouncer: &mut PinDebouncer, raws: &[bool]) -> Vec<Option<Edge>> {
+    raws.iter().map(|&raw| debouncer.update(raw)).collect()
+}
+
+#[test]
+fn pin_with_asymmetric_thresholds() {
+    let mut pin = PinDebouncer::new(Thresholds {
+        press: 2,
+        release: 4,
+    });
+    assert_eq!(
+        samples(&mut pin, &[true, true]),
+        [None, Some(Edge::Pressed)]
+    );
+    assert!(pin.is_pressed() && pin.is_settled());
+    assert_eq!(
+        samples(&mut pin, &[false; 4]),
+        [None, None, None, Some(Edge::Released)]
+    );
+    assert!(!pin.is_pressed() && pin.is_settled());
+}
+
+#[test]
+fn pin_glitch_shorter_than_the_threshold() {
+    let mut pin = PinDebouncer::new(Thresholds::symmetric(3));
+    assert_eq!(samples(&mut pin, &[true, true, false, false]), [None; 4]);
+    assert!(!pin.is_pressed() && pin.is_settled());
+    // a release glitch of a held button is ridden out as well
+    samples(&mut pin, &[true; 3]);
+    assert_eq!(samples(&mut pin, &[false, true, false, true]), [None; 4]);
+    assert!(pin.is_pressed());
+}
+
+#[test]
+fn pin_held_for_long_presses_once() {
+    let mut pin = PinDebouncer::new(Thresholds::symmetric(3));
+    let edges = samples(&mut pin, &[true; 1000]);
+    assert_eq!(edges.iter().flatten().count(), 1);
+    assert_eq!(edges[2], Some(Edge::Pressed));
+    // and the release takes the threshold no matter how long it was held
+    assert_eq!(samples(&mut pin, &[false; 3])[2], Some(Edge::Released));
+}
+
+#[test]
+fn pin_counts_like_a_matrix_key() {
+    let thresholds = Thresholds {
+        press: 2,
+        release: 5,
+    };
+    let mut matrix = Debouncer::<KEYS>::new(thresholds);
+    let mut pin = PinDebouncer::new(thresholds);
+    let raws = [KEY, KEY, 0, KEY, 0, 0, KEY, 0, 0, 0, 0, 0, KEY];
+    for raw in raws {
+        pin.update(raw != 0);
+        assert_eq!(matrix.update(raw) != 0, pin.is_pressed());
+    }
+}
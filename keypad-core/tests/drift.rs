@@ -0,0 +1,94 @@
+//! The drift between two syncs with a host, from the point the first one left in
+//! its register, and the calibration that trims it.
+
+use keypad_core::drift::{self, Drift, Measurement, MAX_CALIBRATION, MIN_ELAPSED_S};
+
+const SYNCED: u32 = 1_760_000_000;
+const DAY: u32 = 86_400;
+
+#[test]
+fn first_sync_has_nothing_to_measure() {
+    // a lost backup domain reads zero
+    assert_eq!(drift::measure(0, SYNCED, 12), Measurement::First);
+    assert_ne!(drift::point(0), 0);
+}
+
+#[test]
+fn a_day_later() {
+    let point = drift::point(SYNCED);
+    let host = SYNCED + DAY;
+    let Measurement::Measured(drift) = drift::measure(point, host, host + 3) else {
+        panic!("no rate measured");
+    };
+    // the point is only known to 2^10 s
+    assert!((DAY..DAY + 1024).contains(&drift.elapsed_s));
+    assert_eq!(drift.drift_s, 3);
+    assert_eq!(drift.ppm(), 34);
+    // a slow clock reads behind
+    let Measurement::Measured(slow) = drift::measure(point, host, host - 3) else {
+        panic!("no rate measured");
+    };
+    assert_eq!(slow.drift_s, -3);
+    assert_eq!(slow.ppm(), -34);
+}
+
+#[test]
+fn the_point_follows_the_clock_past_its_bits() {
+    // 300 days, past the 2^15 units of the register
+    let host = SYNCED + 300 * DAY;
+    let measured = drift::measure(drift::point(SYNCED), host, host);
+    let Measurement::Measured(drift) = measured else {
+        panic!("no rate measured");
+    };
+    assert!((300 * DAY..300 * DAY + 1024).contains(&drift.elapsed_s));
+}
+
+#[test]
+fn set_back_past_the_previous_sync() {
+    let point = drift::point(SYNCED);
+    let host = SYNCED - 2 * DAY;
+    assert_eq!(
+        drift::measure(point, host, SYNCED + 60),
+        Measurement::Backwards
+    );
+}
+
+#[test]
+fn close_syncs_tell_the_drift_alone() {
+    let point = drift::point(SYNCED);
+    let host = SYNCED + 600;
+    assert_eq!(
+        drift::measure(point, host, host + 1),
+        Measurement::Drift { drift_s: 1 }
+    );
+    let host = SYNCED + MIN_ELAPSED_S + 1024;
+    assert!(matches!(
+        drift::measure(point, host, host),
+        Measurement::Measured(_)
+    ));
+}
+
+#[test]
+fn calibration_slows_a_fast_clock_only() {
+    let fast = Drift {
+        elapsed_s: 1 << 20,
+        drift_s: 20,
+    };
+    assert_eq!(fast.calibration(0), 20);
+    assert_eq!(fast.calibration(10), 30);
+    assert_eq!(fast.calibration(120), MAX_CALIBRATION);
+    // a slow clock takes back what it was trimmed by, down to none
+    let slow = Drift {
+        elapsed_s: 1 << 20,
+        drift_s: -8,
+    };
+    assert_eq!(slow.calibration(10), 2);
+    assert_eq!(slow.calibration(5), 0);
+    // far off, the LSI
+    let lsi = Drift {
+        elapsed_s: DAY,
+        drift_s: DAY as i32 / 10,
+    };
+    assert_eq!(lsi.calibration(0), MAX_CALIBRATION);
+    assert_eq!(lsi.ppm(), 100_000);
+}
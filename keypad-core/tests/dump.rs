@@ -0,0 +1,91 @@
+//! The lines of `DUMP` for snapshots taken off a debouncer mid-bounce, and their
+//! fixed columns.
+
+use keypad_core::debounce::{Debouncer, Thresholds};
+use keypad_core::dump::Snapshot;
+use keypad_core::keys::{key_bit, KeyState, COLUMNS, KEYS, ROWS};
+
+const THRESHOLDS: Thresholds = Thresholds {
+    press: 3,
+    release: 3,
+};
+
+fn snapshot(debouncer: &Debouncer<KEYS>, raw: u32) -> Snapshot {
+    Snapshot {
+        debounced: KeyState(debouncer.state()),
+        raw: KeyState(raw),
+        counters: *debouncer.counters(),
+    }
+}
+
+fn lines(snapshot: &Snapshot) -> Vec<String> {
+    (0..ROWS).map(|row| snapshot.row(row).to_string()).collect()
+}
+
+// the line of a row with the keys in the columns of `debounced` and `raw`, and the
+// counters of the columns of `counted`
+fn line(debounced: &[usize], raw: &[usize], counted: &[(usize, u8)]) -> String {
+    let grid = |keys: &[usize]| -> String {
+        (0..COLUMNS)
+            .map(|col| if keys.contains(&col) { "X " } else { ". " })
+            .collect()
+    };
+    let counters: String = (0..COLUMNS)
+        .map(|col| {
+            let counted = counted.iter().find(|(counted, _)| *counted == col);
+            format!("{:3}", counted.map_or(0, |&(_, counter)| counter))
+        })
+        .collect();
+    format!("{}| {}|{}", grid(debounced), grid(raw), counters)
+}
+
+#[test]
+fn an_idle_pad() {
+    let debouncer = Debouncer::<KEYS>::new(THRESHOLDS);
+    let lines = lines(&snapshot(&debouncer, 0));
+    assert_eq!(lines, vec![line(&[], &[], &[]); ROWS]);
+}
+
+#[test]
+fn a_press_still_debouncing_shows_raw_only() {
+    let mut debouncer = Debouncer::<KEYS>::new(THRESHOLDS);
+    let held = key_bit(0, 1);
+    let pressing = key_bit(0, 3);
+    for _ in 0..3 {
+        debouncer.update(held);
+    }
+    debouncer.update(held | pressing);
+    debouncer.update(held | pressing);
+    let lines = lines(&snapshot(&debouncer, held | pressing));
+    assert_eq!(lines[0], line(&[1], &[1, 3], &[(1, 3), (3, 2)]));
+    assert_eq!(lines[1], line(&[], &[], &[]));
+}
+
+#[test]
+fn a_release_bouncing_keeps_the_key_debounced() {
+    let mut debouncer = Debouncer::<KEYS>::new(THRESHOLDS);
+    let key = key_bit(2, 0);
+    for _ in 0..3 {
+        debouncer.update(key);
+    }
+    debouncer.update(0);
+    let lines = lines(&snapshot(&debouncer, 0));
+    assert_eq!(lines[2], line(&[0], &[], &[(0, 2)]));
+}
+
+#[test]
+fn counters_of_three_digits_keep_the_columns() {
+    let mut counters = [0; KEYS];
+    counters[0] = 200;
+    counters[1] = 15;
+    let snapshot = Snapshot {
+        debounced: KeyState(1),
+        raw: KeyState(1),
+        counters,
+    };
+    let line = snapshot.row(0).to_string();
+    let idle = snapshot.row(1).to_string();
+    assert_eq!(line.len(), idle.len());
+    assert!(line.starts_with("X "));
+    assert!(line.ends_with(&format!("|200 15{}", "  0".repeat(COLUMNS - 2))));
+}
@@ -0,0 +1,319 @@
+//! The engine on scanners of made up scans, a scan per millisecond: the same
+//! events as the pieces of the pipeline, for a matrix of the pad's size and for a
+//! smaller one, with the hooks of a firmware and the typematic repeat.
+
+use keypad_core::chord::CHORD_WINDOW_MS;
+use keypad_core::debounce::Thresholds;
+use keypad_core::engine::{EventEngine, Hooks, TooLarge};
+use keypad_core::event::{EventKind, KeyEvent};
+use keypad_core::gesture::LONG_PRESS_MS;
+use keypad_core::keys::{key_bit, KeyState, COLUMNS, ROWS};
+use keypad_core::limit::PressLimit;
+use keypad_core::matrix::MatrixScanner;
+use keypad_core::modifiers::Modifiers;
+
+const THRESHOLDS: Thresholds = Thresholds::symmetric(2);
+const STUCK_MS: u32 = 30_000;
+const TICKS_PER_MS: u64 = 1000;
+
+static CHORDS: [(KeyState, u8); 1] = [(KeyState(key_bit(0, 0) | key_bit(0, 1)), 7)];
+static LOCKS: [(KeyState, u8); 1] = [(KeyState(key_bit(3, 3)), 1)];
+
+// keys closed in its own layout of `columns`, `None` for a scan that fails
+struct Scanner {
+    columns: u8,
+    rows: u8,
+    closed: Option<u32>,
+}
+
+impl Scanner {
+    fn of(columns: usize, rows: usize) -> Self {
+        Self {
+            columns: columns as u8,
+            rows: rows as u8,
+            closed: Some(0),
+        }
+    }
+}
+
+impl MatrixScanner for Scanner {
+    type Error = ();
+
+    fn scan_raw(&mut self) -> Result<u32, ()> {
+        self.closed.ok_or(())
+    }
+
+    fn dims(&self) -> (u8, u8) {
+        (self.columns, self.rows)
+    }
+}
+
+struct Run {
+    engine: EventEngine<Scanner>,
+    now_ms: u32,
+}
+
+impl Run {
+    fn new(scanner: Scanner) -> Self {
+        Self {
+            engine: EventEngine::new(scanner, THRESHOLDS, STUCK_MS, &CHORDS).unwrap(),
+            now_ms: 0,
+        }
+    }
+
+    fn repeating(scanner: Scanner, delay_ms: u32, interval_ms: u32) -> Self {
+        Self {
+            engine: EventEngine::new(scanner, THRESHOLDS, STUCK_MS, &CHORDS)
+                .unwrap()
+                .with_repeat(delay_ms, interval_ms),
+            now_ms: 0,
+        }
+    }
+
+    // the events of `scans` scans of `closed`
+    fn hold(&mut self, closed: u32, scans: u32) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        self.hold_with(closed, scans, &mut |event| events.push(event));
+        events
+    }
+
+    fn hold_with(&mut self, closed: u32, scans: u32, hooks: &mut impl Hooks) {
+        self.engine.scanner_mut().closed = Some(closed);
+        for _ in 0..scans {
+            self.now_ms += 1;
+            let at = u64::from(self.now_ms) * TICKS_PER_MS;
+            assert!(self.engine.scan(self.now_ms, at, hooks).is_ok());
+        }
+    }
+}
+
+// the hooks of a firmware: a lock key, a layer of letters without the last
+// column and a limit of two keys
+struct Pad {
+    events: Vec<KeyEvent>,
+    modifiers: Modifiers,
+    limit: PressLimit,
+}
+
+impl Hooks for Pad {
+    fn emit(&mut self, mut event: KeyEvent) {
+        event.modifiers = self.modifiers;
+        self.events.push(event);
+    }
+
+    fn frame(
+        &mut self,
+        state: KeyState,
+        changes: (KeyState, KeyState),
+        _now: u32,
+    ) -> (KeyState, KeyState) {
+        let (changes, limit) = self.limit.filter(state, changes);
+        if let Some(event) = limit {
+            self.emit(event);
+        }
+        self.modifiers.apply(changes, &LOCKS)
+    }
+
+    fn edge(&mut self, mut event: KeyEvent) -> Option<KeyEvent> {
+        if usize::from(event.col) == COLUMNS - 1 {
+            return None;
+        }
+        event.key = char::from(b'a' + event.row * COLUMNS as u8 + event.col);
+        Some(event)
+    }
+}
+
+fn kinds(events: &[KeyEvent]) -> Vec<(u8, u8, EventKind)> {
+    events.iter().map(|e| (e.row, e.col, e.kind)).collect()
+}
+
+#[test]
+fn a_press_and_its_release() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    let key = key_bit(2, 3);
+    let pressed = run.hold(key, 2);
+    assert_eq!(kinds(&pressed), [(2, 3, EventKind::Pressed)]);
+    assert_eq!(pressed[0].at, 2 * TICKS_PER_MS);
+    run.hold(key, 98);
+    let released = run.hold(0, 2);
+    assert_eq!(
+        kinds(&released),
+        [(2, 3, EventKind::Released { held_ms: 100 })]
+    );
+}
+
+#[test]
+fn a_smaller_matrix_keeps_its_rows_and_columns() {
+    let mut run = Run::new(Scanner::of(3, 2));
+    // the last key of its second row
+    let pressed = run.hold(1 << 5, 2);
+    assert_eq!(kinds(&pressed), [(1, 2, EventKind::Pressed)]);
+}
+
+#[test]
+fn a_larger_matrix_is_refused() {
+    let engine = EventEngine::new(
+        Scanner::of(COLUMNS, ROWS + 1),
+        THRESHOLDS,
+        STUCK_MS,
+        &CHORDS,
+    );
+    assert_eq!(
+        engine.err(),
+        Some(TooLarge {
+            columns: COLUMNS as u8,
+            rows: ROWS as u8 + 1
+        })
+    );
+}
+
+#[test]
+fn a_failed_scan_keeps_the_keys() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    run.hold(key_bit(0, 2), 2);
+    run.engine.scanner_mut().closed = None;
+    assert_eq!(
+        run.engine.scan(3, 3 * TICKS_PER_MS, &mut |_| panic!()),
+        Err(())
+    );
+    let released = run.hold(0, 2);
+    assert_eq!(kinds(&released)[0].2, EventKind::Released { held_ms: 2 });
+}
+
+#[test]
+fn a_chord_within_the_window() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    assert!(run.hold(key_bit(0, 0), 2).is_empty());
+    let chord = run.hold(key_bit(0, 0) | key_bit(0, 1), 2);
+    assert_eq!(kinds(&chord), [(0, 1, EventKind::Chord(7))]);
+}
+
+#[test]
+fn a_single_chord_key_presses_after_the_window() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    assert!(run.hold(key_bit(0, 0), 2).is_empty());
+    let pressed = run.hold(key_bit(0, 0), CHORD_WINDOW_MS);
+    assert_eq!(kinds(&pressed), [(0, 0, EventKind::Pressed)]);
+    // the edge's scan, not the one that let it go
+    assert_eq!(pressed[0].at, 2 * TICKS_PER_MS);
+}
+
+#[test]
+fn a_long_press_gets_the_tick_of_its_scan() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    let key = key_bit(3, 3);
+    run.hold(key, 2);
+    let long = run.hold(key, LONG_PRESS_MS);
+    assert_eq!(kinds(&long), [(3, 3, EventKind::LongPressed)]);
+    assert_eq!(long[0].at, u64::from(run.now_ms) * TICKS_PER_MS);
+    let released = run.hold(0, 2);
+    assert_eq!(kinds(&released), [(3, 3, EventKind::ReleasedAfterLong)]);
+}
+
+#[test]
+fn a_second_tap_is_a_double_tap() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    let key = key_bit(1, 1);
+    run.hold(key, 2);
+    run.hold(0, 2);
+    let second = run.hold(key, 2);
+    assert_eq!(
+        kinds(&second),
+        [(1, 1, EventKind::Pressed), (1, 1, EventKind::DoubleTap)]
+    );
+}
+
+#[test]
+fn ghosting_is_reported_once() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    let rectangle = key_bit(0, 0) | key_bit(0, 2) | key_bit(2, 0) | key_bit(2, 2);
+    let events = run.hold(rectangle, 5);
+    let ghosting = events
+        .iter()
+        .filter(|event| event.kind == EventKind::GhostingDetected)
+        .count();
+    assert_eq!(ghosting, 1);
+}
+
+#[test]
+fn the_hooks_add_locks_layers_and_a_limit() {
+    let mut run = Run::new(Scanner::of(COLUMNS, ROWS));
+    let mut pad = Pad {
+        events: Vec::new(),
+        modifiers: Modifiers::default(),
+        limit: PressLimit::new(2),
+    };
+    let lock = key_bit(3, 3);
+    run.hold_with(lock, 2, &mut pad);
+    run.hold_with(0, 2, &mut pad);
+    assert!(pad.events.is_empty());
+    assert!(pad.modifiers.is_locked(1));
+
+    let key = key_bit(1, 1);
+    run.hold_with(key, 2, &mut pad);
+    // a key the layer leaves out
+    let unmapped = key_bit(0, COLUMNS - 1);
+    run.hold_with(key | unmapped, 2, &mut pad);
+    run.hold_with(key | unmapped | key_bit(2, 2), 2, &mut pad);
+    let pressed = pad.events[0];
+    assert_eq!(
+        (pressed.key, pressed.kind),
+        (char::from(b'a' + COLUMNS as u8 + 1), EventKind::Pressed)
+    );
+    assert!(pressed.modifiers.is_locked(1));
+    assert_eq!(kinds(&pad.events[1..]), [(2, 2, EventKind::TooManyKeys)]);
+}
+
+// the hooks of a firmware that presses keys of its own, besides the matrix
+struct Injecting {
+    keys: u32,
+    events: Vec<KeyEvent>,
+}
+
+impl Hooks for Injecting {
+    fn emit(&mut self, event: KeyEvent) {
+        self.events.push(event);
+    }
+
+    fn scanned(&mut self, raw: u32, _now: u32) -> u32 {
+        raw | self.keys
+    }
+}
+
+#[test]
+fn the_hooks_add_keys_to_a_scan() {
+    let mut run = Run::new(Scanner::of(3, 2));
+    let mut injecting = Injecting {
+        keys: key_bit(3, 3),
+        events: Vec::new(),
+    };
+    // the last key of the scanner's second row, and one of the pad past it
+    run.hold_with(1 << 5, 2, &mut injecting);
+    assert_eq!(
+        kinds(&injecting.events),
+        [(1, 2, EventKind::Pressed), (3, 3, EventKind::Pressed)]
+    );
+}
+
+#[test]
+fn a_held_key_repeats() {
+    let mut run = Run::repeating(Scanner::of(COLUMNS, ROWS), 10, 5);
+    let key = key_bit(2, 1);
+    assert_eq!(kinds(&run.hold(key, 2)), [(2, 1, EventKind::Pressed)]);
+    // due 10 ms after the press, then every 5 ms
+    let repeats = run.hold(key, 20);
+    let at: Vec<_> = repeats
+        .iter()
+        .map(|event| event.at / TICKS_PER_MS)
+        .collect();
+    assert_eq!(at, [12, 17, 22]);
+    assert!(repeats.iter().all(|event| event.kind == EventKind::Repeat));
+    // the key pressed last takes over, the other one's release doesn't stop it
+    let other = key_bit(0, 3);
+    run.hold(key | other, 2);
+    run.hold(other, 2);
+    let repeats = run.hold(other, 10);
+    assert_eq!(kinds(&repeats), [(0, 3, EventKind::Repeat)]);
+    run.hold(0, 2);
+    assert!(run.hold(0, 20).is_empty());
+}
@@ -0,0 +1,50 @@
+//! The serial framing: what `UART SET` and the build environment accept, and the
+//! bytes it is saved as.
+
+use keypad_core::framing::{Framing, Parity, MAX_BAUD, MIN_BAUD};
+
+// parsed at compile time like the firmware's build framing
+const BUILD: Option<Framing> = Framing::parse("57600", "8E1");
+
+#[test]
+fn parses_baud_and_format() {
+    assert_eq!(
+        BUILD,
+        Some(Framing {
+            baud: 57_600,
+            parity: Parity::Even,
+            two_stop_bits: false,
+        })
+    );
+    assert_eq!(Framing::parse("115200", "8N1"), Some(Framing::DEFAULT));
+    let odd = Framing::parse("9600", "8O2").unwrap();
+    assert_eq!((odd.parity, odd.two_stop_bits), (Parity::Odd, true));
+    assert_eq!(odd.format(), "8O2");
+    assert_eq!(Framing::DEFAULT.format(), "8N1");
+    assert_eq!(Framing::new(57_600, "8E1"), BUILD);
+}
+
+#[test]
+fn rejects_the_rest() {
+    assert_eq!(Framing::parse("", "8N1"), None);
+    assert_eq!(Framing::parse("57k6", "8N1"), None);
+    assert_eq!(Framing::parse("99999999999", "8N1"), None);
+    assert_eq!(Framing::parse("600", "8N1"), None);
+    assert_eq!(Framing::parse("921600", "8N1"), None);
+    assert!(Framing::parse(&MIN_BAUD.to_string(), "8N1").is_some());
+    assert!(Framing::parse(&MAX_BAUD.to_string(), "8N1").is_some());
+    for format in ["7E1", "8X1", "8N3", "8N", "8N11", "8n1"] {
+        assert_eq!(Framing::parse("9600", format), None, "{}", format);
+    }
+}
+
+#[test]
+fn bytes_round_trip() {
+    for format in ["8N1", "8N2", "8E1", "8E2", "8O1", "8O2"] {
+        let framing = Framing::parse("57600", format).unwrap();
+        assert_eq!(Framing::from_bytes(&framing.to_bytes()), Some(framing));
+    }
+    // an erased page and a zero baud rate
+    assert_eq!(Framing::from_bytes(&[0xff; 5]), None);
+    assert_eq!(Framing::from_bytes(&[0; 5]), None);
+}
@@ -0,0 +1,133 @@
+//! The cap on how long a backend holds a key: one made up release, quiet until the
+//! real one, which is swallowed, and the stuck keys of the debouncer next to it.
+
+use keypad_core::debounce::{KeyFilter, Thresholds};
+use keypad_core::event::{EventKind, KeyEvent, KeyFrames};
+use keypad_core::hold_cap::{HoldCap, HOLD_CAP_MS};
+use keypad_core::keys::{key_bit, COLUMNS, KEYS, ROWS};
+use keypad_core::time;
+
+const TICKS_PER_MS: u64 = (time::TICK_HZ / 1000) as u64;
+
+// '7', row 1 column 2
+const KEY: usize = COLUMNS + 2;
+
+fn event(index: usize, key: char, kind: EventKind) -> KeyEvent {
+    KeyEvent {
+        key,
+        ..KeyEvent::new(index, kind)
+    }
+}
+
+fn expired(cap: &mut HoldCap, now_ms: u32) -> Vec<(u8, u8, char, EventKind)> {
+    let mut released = Vec::new();
+    cap.expire(now_ms, |e| released.push((e.row, e.col, e.key, e.kind)));
+    released
+}
+
+#[test]
+fn releases_once_and_swallows_the_real_release() {
+    let mut cap = HoldCap::new(HOLD_CAP_MS);
+    assert!(cap.pass(&event(KEY, '7', EventKind::Pressed), 1000));
+    assert!(expired(&mut cap, 1000 + HOLD_CAP_MS - 1).is_empty());
+    let held_ms = HOLD_CAP_MS;
+    assert_eq!(
+        expired(&mut cap, 1000 + HOLD_CAP_MS),
+        [(1, 2, '7', EventKind::Released { held_ms })]
+    );
+    assert!(expired(&mut cap, 1000 + 2 * HOLD_CAP_MS).is_empty());
+    assert_eq!(cap.capped(), 1 << KEY);
+
+    // the key is still down, anything of it is swallowed up to its release
+    assert!(!cap.pass(&event(KEY, '7', EventKind::Repeat), 15_000));
+    let held_ms = 20_000;
+    assert!(!cap.pass(&event(KEY, '7', EventKind::Released { held_ms }), 21_000));
+    assert_eq!(cap.capped(), 0);
+
+    // and the next press is a press again
+    assert!(cap.pass(&event(KEY, '7', EventKind::Pressed), 22_000));
+    assert!(cap.pass(
+        &event(KEY, '7', EventKind::Released { held_ms: 100 }),
+        22_100
+    ));
+    assert!(expired(&mut cap, 40_000).is_empty());
+}
+
+#[test]
+fn keys_within_the_cap_pass() {
+    let mut cap = HoldCap::new(1000);
+    assert!(cap.pass(&event(KEY, '7', EventKind::Pressed), 0));
+    assert!(cap.pass(&event(KEY, '7', EventKind::LongPressed), 500));
+    assert!(cap.pass(&event(KEY, '7', EventKind::ReleasedAfterLong), 999));
+    assert!(expired(&mut cap, 5000).is_empty());
+}
+
+#[test]
+fn every_key_is_capped_on_its_own_time() {
+    let mut cap = HoldCap::new(1000);
+    cap.pass(&event(KEY, '7', EventKind::Pressed), 0);
+    cap.pass(&event(0, '1', EventKind::Pressed), 600);
+    assert_eq!(expired(&mut cap, 1000).len(), 1);
+    // the other key isn't swallowed for the first one's cap
+    assert!(cap.pass(&event(0, '1', EventKind::Repeat), 1200));
+    assert_eq!(
+        expired(&mut cap, 1600),
+        [(0, 0, '1', EventKind::Released { held_ms: 1000 })]
+    );
+}
+
+#[test]
+fn a_raised_cap_holds_the_keys_longer() {
+    let mut cap = HoldCap::new(1000);
+    cap.pass(&event(KEY, '7', EventKind::Pressed), 0);
+    cap.set_cap(3000);
+    assert_eq!(cap.cap_ms(), 3000);
+    assert!(expired(&mut cap, 2000).is_empty());
+    assert_eq!(expired(&mut cap, 3000).len(), 1);
+}
+
+#[test]
+fn next_to_the_stuck_keys() {
+    const SCAN_MS: u32 = 10;
+    const STUCK_MS: u32 = 30_000;
+    let mut filter = KeyFilter::<COLUMNS, ROWS, KEYS>::new(Thresholds::DEFAULT, STUCK_MS);
+    let mut frames = KeyFrames::new();
+    let mut cap = HoldCap::new(HOLD_CAP_MS);
+    let raw = key_bit(1, 2);
+
+    // what the debounced edges and the cap make of the key held for 35 s and
+    // pressed once more, the physical events and what the backend sees
+    let mut physical = Vec::new();
+    let mut backend = Vec::new();
+    let scans = (0..3500).map(|_| raw).chain([0; 10]).chain([raw; 10]);
+    for (now, raw) in (SCAN_MS..).step_by(SCAN_MS as usize).zip(scans) {
+        let changes = frames.push(filter.update(raw, now).state);
+        for event in frames.edges(changes, u64::from(now) * TICKS_PER_MS) {
+            physical.push((now, event.kind));
+            if cap.pass(&event, now) {
+                backend.push((now, event.kind));
+            }
+        }
+        cap.expire(now, |release| backend.push((now, release.kind)));
+    }
+
+    let stuck_at = 30 + STUCK_MS;
+    let held_ms = STUCK_MS;
+    assert_eq!(
+        physical,
+        [
+            (30, EventKind::Pressed),
+            (stuck_at, EventKind::Released { held_ms }),
+            (35_130, EventKind::Pressed),
+        ]
+    );
+    let held_ms = HOLD_CAP_MS;
+    assert_eq!(
+        backend,
+        [
+            (30, EventKind::Pressed),
+            (30 + HOLD_CAP_MS, EventKind::Released { held_ms }),
+            (35_130, EventKind::Pressed),
+        ]
+    );
+}
@@ -0,0 +1,89 @@
+//! The presses past the limit of simultaneous keys: dropped after a single
+//! `TooManyKeys`, and the events of the keys it let through as usual.
+
+use keypad_core::event::{EventKind, KeyEvent, KeyFrames};
+use keypad_core::keys::{key_bit, KeyState};
+use keypad_core::limit::{PressLimit, MAX_SIMULTANEOUS};
+
+const A: u32 = key_bit(0, 0);
+const B: u32 = key_bit(1, 1);
+const C: u32 = key_bit(2, 2);
+const D: u32 = key_bit(3, 3);
+
+/// The frames and the limit as the scanner runs them, on debounced states.
+struct Pad {
+    frames: KeyFrames,
+    limit: PressLimit,
+    at: u64,
+}
+
+impl Pad {
+    fn new() -> Self {
+        Self {
+            frames: KeyFrames::new(),
+            limit: PressLimit::new(MAX_SIMULTANEOUS),
+            at: 0,
+        }
+    }
+
+    fn frame(&mut self, state: u32) -> Vec<(u8, u8, EventKind)> {
+        self.at += 10_000;
+        let state = KeyState(state);
+        let changes = self.frames.push(state);
+        let (changes, limit) = self.limit.filter(state, changes);
+        let mut events: Vec<KeyEvent> = limit.into_iter().collect();
+        events.extend(self.frames.edges(changes, self.at));
+        events.iter().map(|e| (e.row, e.col, e.kind)).collect()
+    }
+}
+
+fn released(held_ms: u32) -> EventKind {
+    EventKind::Released { held_ms }
+}
+
+#[test]
+fn within_the_limit_nothing_changes() {
+    let mut pad = Pad::new();
+    assert_eq!(pad.frame(A), [(0, 0, EventKind::Pressed)]);
+    assert_eq!(pad.frame(A | B), [(1, 1, EventKind::Pressed)]);
+    assert_eq!(pad.frame(0), [(0, 0, released(20)), (1, 1, released(10))]);
+}
+
+#[test]
+fn press_three_release_one_press_another() {
+    let mut pad = Pad::new();
+    pad.frame(A);
+    pad.frame(A | B);
+    // the third key is reported once and not pressed
+    assert_eq!(pad.frame(A | B | C), [(2, 2, EventKind::TooManyKeys)]);
+    assert!(pad.frame(A | B | C).is_empty());
+
+    // back at the limit with the release of a reported key
+    assert_eq!(
+        pad.frame(B | C),
+        [(0, 0, EventKind::KeysWithinLimit), (0, 0, released(40))]
+    );
+
+    // another press while at the limit is over it again, the held third key
+    // still counts
+    assert_eq!(pad.frame(B | C | D), [(3, 3, EventKind::TooManyKeys)]);
+    assert_eq!(
+        pad.frame(C | D),
+        [(1, 1, EventKind::KeysWithinLimit), (1, 1, released(50))]
+    );
+
+    // the keys whose press was dropped go without a release
+    assert!(pad.frame(D).is_empty());
+    assert!(pad.frame(0).is_empty());
+    // and the pad is back to normal
+    assert_eq!(pad.frame(A), [(0, 0, EventKind::Pressed)]);
+}
+
+#[test]
+fn several_keys_at_once_past_the_limit() {
+    let mut pad = Pad::new();
+    assert_eq!(pad.frame(A | B | C), [(0, 0, EventKind::TooManyKeys)]);
+    // a dropped key's release brings it back, but nothing was reported
+    assert_eq!(pad.frame(A | B), [(2, 2, EventKind::KeysWithinLimit)]);
+    assert!(pad.frame(0).is_empty());
+}
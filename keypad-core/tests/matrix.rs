@@ -0,0 +1,298 @@
+//! The matrix scan on mock pins: the columns record the level they are driven to,
+//! the rows read what the pressed keys of a simulated pad pass on to them. The
+//! pad of the firmware, and a few of other sizes.
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use keypad_core::debounce::{KeyFilter, Thresholds};
+use keypad_core::keys::{ghost_mask, key_bit, COLUMNS, KEYS, ROWS};
+use keypad_core::matrix::{
+    ActiveHigh, ActiveLow, Error, Keypad, Matrix, MatrixScanner, PinColumns, Polarity, SlicedScan,
+    SETTLE_US,
+};
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+struct Pad {
+    pressed: u32,
+    // false for a pad without diodes, where held keys connect their lines
+    diodes: bool,
+    // the level each column is driven to, and how many rows there are
+    columns: Vec<bool>,
+    rows: usize,
+    // rows that fail to read
+    broken_rows: u8,
+}
+
+type Shared = Rc<RefCell<Pad>>;
+
+fn pad(pressed: u32, diodes: bool) -> Shared {
+    sized_pad(COLUMNS, ROWS, pressed, diodes)
+}
+
+// `pressed` with the bits of a pad `cols` wide
+fn sized_pad(cols: usize, rows: usize, pressed: u32, diodes: bool) -> Shared {
+    Rc::new(RefCell::new(Pad {
+        pressed,
+        diodes,
+        columns: vec![false; cols],
+        rows,
+        broken_rows: 0,
+    }))
+}
+
+impl Pad {
+    fn closed(&self, row: usize, col: usize) -> bool {
+        self.pressed & 1 << (row * self.columns.len() + col) != 0
+    }
+
+    // the rows connected to a column driven active, through the keys held
+    fn active_rows<P: Polarity>(&self) -> u32 {
+        let (columns, rows_of) = (self.columns.len(), self.rows);
+        let mut cols: u32 = (0..columns)
+            .filter(|&col| self.columns[col] == P::ACTIVE_HIGH)
+            .fold(0, |cols, col| cols | 1 << col);
+        let mut rows = 0;
+        loop {
+            let reached = (0..rows_of)
+                .filter(|&row| {
+                    (0..columns).any(|col| cols & 1 << col != 0 && self.closed(row, col))
+                })
+                .fold(0, |rows, row| rows | 1 << row);
+            let spread = match self.diodes {
+                true => cols,
+                false => (0..columns)
+                    .filter(|&col| {
+                        (0..rows_of).any(|row| reached & 1 << row != 0 && self.closed(row, col))
+                    })
+                    .fold(cols, |cols, col| cols | 1 << col),
+            };
+            if (reached, spread) == (rows, cols) {
+                return rows;
+            }
+            (rows, cols) = (reached, spread);
+        }
+    }
+}
+
+struct Column(Shared, usize);
+
+impl OutputPin for Column {
+    type Error = ();
+
+    fn set_high(&mut self) -> Result<(), ()> {
+        self.0.borrow_mut().columns[self.1] = true;
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), ()> {
+        self.0.borrow_mut().columns[self.1] = false;
+        Ok(())
+    }
+}
+
+struct Row<P>(Shared, usize, PhantomData<P>);
+
+impl<P: Polarity> InputPin for Row<P> {
+    type Error = &'static str;
+
+    fn is_high(&self) -> Result<bool, &'static str> {
+        let pad = self.0.borrow();
+        if pad.broken_rows & 1 << self.1 != 0 {
+            return Err("row");
+        }
+        let active = pad.active_rows::<P>() & 1 << self.1 != 0;
+        Ok(active == P::ACTIVE_HIGH)
+    }
+
+    fn is_low(&self) -> Result<bool, &'static str> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+// adds up the waits
+struct Delay(Rc<Cell<u32>>);
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        self.0.set(self.0.get() + us);
+    }
+}
+
+type SizedKeypad<P, const COLS: usize, const ROWS: usize> =
+    Keypad<PinColumns<Column, COLS, P>, [Row<P>; ROWS], Delay, COLS, ROWS, P>;
+type MockKeypad<P> = SizedKeypad<P, COLUMNS, ROWS>;
+
+fn keypad<P: Polarity>(pad: &Shared) -> MockKeypad<P> {
+    keypad_waiting(pad, Rc::default())
+}
+
+fn keypad_waiting<P: Polarity, const COLS: usize, const ROWS: usize>(
+    pad: &Shared,
+    waited: Rc<Cell<u32>>,
+) -> SizedKeypad<P, COLS, ROWS> {
+    let columns = PinColumns::new(core::array::from_fn(|col| Column(pad.clone(), col))).unwrap();
+    let rows = core::array::from_fn(|row| Row(pad.clone(), row, PhantomData));
+    Keypad::new(columns, rows, Delay(waited))
+}
+
+fn idle<P: Polarity>(pad: &Shared) -> bool {
+    pad.borrow()
+        .columns
+        .iter()
+        .all(|&level| level != P::ACTIVE_HIGH)
+}
+
+#[test]
+fn scans_the_pressed_keys() {
+    let pressed = key_bit(0, 0) | key_bit(1, 3) | key_bit(3, 2);
+    let pad = pad(pressed, true);
+    let mut high = keypad::<ActiveHigh>(&pad);
+    assert_eq!(high.scan(), Ok(pressed));
+    assert!(idle::<ActiveHigh>(&pad));
+    let mut low = keypad::<ActiveLow>(&pad);
+    assert_eq!(low.scan(), Ok(pressed));
+    assert!(idle::<ActiveLow>(&pad));
+}
+
+#[test]
+fn every_single_key() {
+    for key in 0..KEYS {
+        let pad = pad(1 << key, true);
+        assert_eq!(keypad::<ActiveLow>(&pad).scan(), Ok(1 << key));
+    }
+}
+
+#[test]
+fn rows_settle_after_every_column() {
+    let pad = pad(0, true);
+    let waited = Rc::new(Cell::new(0));
+    let mut keypad = keypad_waiting::<ActiveHigh, COLUMNS, ROWS>(&pad, waited.clone());
+    keypad.scan().unwrap();
+    assert_eq!(waited.get(), COLUMNS as u32 * SETTLE_US);
+}
+
+#[test]
+fn three_corners_without_diodes_show_the_fourth() {
+    let corners = key_bit(0, 0) | key_bit(0, 1) | key_bit(1, 0);
+    let ghost = key_bit(1, 1);
+    let pad = pad(corners, false);
+    let mut keypad = keypad::<ActiveLow>(&pad);
+    let raw = keypad.scan().unwrap();
+    assert_eq!(raw, corners | ghost);
+    assert_eq!(ghost_mask::<COLUMNS, ROWS>(raw), corners | ghost);
+    // the filter keeps the corners where they were, none of them pressed
+    let mut filter = KeyFilter::<COLUMNS, ROWS, KEYS>::new(Thresholds::symmetric(1), 30_000);
+    let filtered = filter.update(raw, 0);
+    assert!(filtered.state.is_empty());
+    assert!(filtered.ghosting_started);
+    // with diodes the matrix reads what is held
+    pad.borrow_mut().diodes = true;
+    assert_eq!(keypad.scan(), Ok(corners));
+}
+
+#[test]
+fn self_test_finds_a_shorted_row() {
+    let pad = pad(0, true);
+    let mut keypad = keypad::<ActiveHigh>(&pad);
+    let test = keypad.self_test().unwrap();
+    assert_eq!((test.shorted_rows, test.closed), (0, 0));
+    assert!(!test.hard_fault());
+    // a row reading closed whatever column is driven
+    let row = (0..COLUMNS).fold(0, |keys, col| keys | key_bit(2, col));
+    pad.borrow_mut().pressed = row;
+    let test = keypad.self_test().unwrap();
+    assert!(test.hard_fault());
+    assert_eq!(test.failed(), row);
+}
+
+#[test]
+fn select_all_sees_any_key() {
+    let pad = pad(0, true);
+    let mut keypad = keypad::<ActiveLow>(&pad);
+    assert_eq!(keypad.select_all(), Ok(false));
+    pad.borrow_mut().pressed = key_bit(3, 1);
+    assert_eq!(keypad.select_all(), Ok(true));
+    assert!(!idle::<ActiveLow>(&pad));
+    keypad.park().unwrap();
+    assert!(idle::<ActiveLow>(&pad));
+}
+
+#[test]
+fn a_failing_row_fails_the_scan() {
+    let pad = pad(key_bit(0, 0), true);
+    let mut keypad = keypad::<ActiveHigh>(&pad);
+    pad.borrow_mut().broken_rows = 1 << 2;
+    assert_eq!(keypad.scan(), Err(Error::Rows("row")));
+    assert!(keypad.self_test().is_err());
+    pad.borrow_mut().broken_rows = 0;
+    assert_eq!(keypad.scan(), Ok(key_bit(0, 0)));
+}
+
+#[test]
+fn sliced_scan_takes_a_run_per_column() {
+    let pressed = key_bit(0, 3) | key_bit(2, 0);
+    let pad = pad(pressed, true);
+    let mut keypad = keypad::<ActiveLow>(&pad);
+    let mut sliced = SlicedScan::<COLUMNS>::new();
+    // the first run only selects
+    let frames: Vec<_> = (0..=COLUMNS).map(|_| sliced.step(&mut keypad)).collect();
+    assert!(frames[..COLUMNS].iter().all(|frame| *frame == Ok(None)));
+    assert_eq!(frames[COLUMNS], Ok(Some(pressed)));
+    // and every `COLUMNS` runs after that another frame
+    let next: Vec<_> = (0..COLUMNS).map(|_| sliced.step(&mut keypad)).collect();
+    assert_eq!(next[COLUMNS - 1], Ok(Some(pressed)));
+}
+
+#[test]
+fn sliced_scan_starts_over_after_a_failure() {
+    let pad = pad(key_bit(1, 1), true);
+    let mut keypad = keypad::<ActiveHigh>(&pad);
+    let mut sliced = SlicedScan::<COLUMNS>::new();
+    sliced.step(&mut keypad).unwrap();
+    sliced.step(&mut keypad).unwrap();
+    pad.borrow_mut().broken_rows = 1;
+    assert!(sliced.step(&mut keypad).is_err());
+    pad.borrow_mut().broken_rows = 0;
+    let frames: Vec<_> = (0..=COLUMNS).map(|_| sliced.step(&mut keypad)).collect();
+    assert_eq!(frames[COLUMNS], Ok(Some(key_bit(1, 1))));
+    assert!(frames[..COLUMNS].iter().all(|frame| *frame == Ok(None)));
+}
+
+// every key alone, a key of every row at once, the self-test and a sliced scan of
+// a pad `COLS` wide and `ROWS` high
+fn scans_a_pad_of<const COLS: usize, const ROWS: usize>() {
+    for key in 0..COLS * ROWS {
+        let pad = sized_pad(COLS, ROWS, 1 << key, true);
+        let mut keypad = keypad_waiting::<ActiveLow, COLS, ROWS>(&pad, Rc::default());
+        assert_eq!(keypad.scan(), Ok(1 << key));
+    }
+    // the last key of every row and the first one of the last row
+    let pressed = (0..ROWS).fold(1 << ((ROWS - 1) * COLS), |keys, row| {
+        keys | 1 << (row * COLS + COLS - 1)
+    });
+    let pad = sized_pad(COLS, ROWS, pressed, true);
+    let mut keypad = keypad_waiting::<ActiveHigh, COLS, ROWS>(&pad, Rc::default());
+    assert_eq!(keypad.dims(), (COLS as u8, ROWS as u8));
+    assert_eq!(keypad.scan(), Ok(pressed));
+    assert!(idle::<ActiveHigh>(&pad));
+    let test = keypad.self_test().unwrap();
+    assert_eq!((test.shorted_rows, test.closed), (0, pressed));
+    let mut sliced = SlicedScan::<COLS>::new();
+    let frames: Vec<_> = (0..=COLS).map(|_| sliced.step(&mut keypad)).collect();
+    assert!(frames[..COLS].iter().all(|frame| *frame == Ok(None)));
+    assert_eq!(frames[COLS], Ok(Some(pressed)));
+}
+
+#[test]
+fn a_4x3_pad() {
+    // 4 columns of 3 rows, and the phone pad of 3 columns and 4 rows
+    scans_a_pad_of::<4, 3>();
+    scans_a_pad_of::<3, 4>();
+}
+
+#[test]
+fn a_5x4_pad() {
+    scans_a_pad_of::<5, 4>();
+}
@@ -0,0 +1,151 @@
+//! The MIDI notes of the keys, driven through the engine with hooks like the
+//! firmware's scanner: the fn key is published in the debounced state of the frame
+//! and dropped at its edge, the way the layers take it, so the octave shift only
+//! ever sees it held.
+
+use keypad_core::debounce::Thresholds;
+use keypad_core::engine::{EventEngine, Hooks};
+use keypad_core::event::{EventKind, KeyEvent};
+use keypad_core::keys::{key_bit, KeyState, COLUMNS, ROWS};
+use keypad_core::matrix::MatrixScanner;
+use keypad_core::midi::{Midi, NOTES, OCTAVE};
+
+const THRESHOLDS: Thresholds = Thresholds::symmetric(2);
+const STUCK_MS: u32 = 30_000;
+const TICKS_PER_MS: u64 = 1000;
+
+const FN_KEY: (usize, usize) = (3, 3);
+const FN: u32 = key_bit(FN_KEY.0, FN_KEY.1);
+const KEY: u32 = key_bit(0, 1);
+
+struct Scanner(u32);
+
+impl MatrixScanner for Scanner {
+    type Error = ();
+
+    fn scan_raw(&mut self) -> Result<u32, ()> {
+        Ok(self.0)
+    }
+
+    fn dims(&self) -> (u8, u8) {
+        (COLUMNS as u8, ROWS as u8)
+    }
+}
+
+// the scanner's hooks with the router's MIDI backend behind them
+struct Pad {
+    midi: Midi,
+    // the published state of the latest frame
+    state: KeyState,
+    edges: Vec<KeyEvent>,
+    messages: Vec<Vec<u8>>,
+}
+
+impl Hooks for Pad {
+    fn emit(&mut self, event: KeyEvent) {
+        if let Some(message) = self.midi.handle(&event, self.state) {
+            self.messages.push(message.to_vec());
+        }
+    }
+
+    fn frame(
+        &mut self,
+        state: KeyState,
+        changes: (KeyState, KeyState),
+        _now: u32,
+    ) -> (KeyState, KeyState) {
+        self.state = state;
+        changes
+    }
+
+    fn edge(&mut self, event: KeyEvent) -> Option<KeyEvent> {
+        self.edges.push(event);
+        if (usize::from(event.row), usize::from(event.col)) == FN_KEY {
+            return None;
+        }
+        Some(event)
+    }
+}
+
+struct Run {
+    engine: EventEngine<Scanner>,
+    pad: Pad,
+    now_ms: u32,
+}
+
+impl Run {
+    fn new() -> Self {
+        Self {
+            engine: EventEngine::new(Scanner(0), THRESHOLDS, STUCK_MS, &[]).unwrap(),
+            pad: Pad {
+                midi: Midi::new(KeyState(FN)),
+                state: KeyState(0),
+                edges: Vec::new(),
+                messages: Vec::new(),
+            },
+            now_ms: 0,
+        }
+    }
+
+    // the messages of 10 scans of `closed`
+    fn hold(&mut self, closed: u32) -> Vec<Vec<u8>> {
+        self.engine.scanner_mut().0 = closed;
+        for _ in 0..10 {
+            self.now_ms += 1;
+            let at = u64::from(self.now_ms) * TICKS_PER_MS;
+            assert!(self.engine.scan(self.now_ms, at, &mut self.pad).is_ok());
+        }
+        std::mem::take(&mut self.pad.messages)
+    }
+}
+
+#[test]
+fn a_key_plays_its_note() {
+    let mut run = Run::new();
+    let note = NOTES[1];
+    assert_eq!(run.hold(KEY), [vec![0x90, note, 0x64]]);
+    assert_eq!(run.hold(0), [vec![0x80, note, 0x64]]);
+}
+
+#[test]
+fn the_held_fn_key_shifts_an_octave_up() {
+    let mut run = Run::new();
+    let note = NOTES[1] + OCTAVE;
+    assert!(run.hold(FN).is_empty());
+    // it reached the hooks, the layers dropped it there
+    assert!(run
+        .pad
+        .edges
+        .iter()
+        .any(|event| event.kind == EventKind::Pressed && (event.row, event.col) == (3, 3)));
+    assert_eq!(run.hold(FN | KEY), [vec![0x90, note, 0x64]]);
+    assert_eq!(run.hold(FN), [vec![0x80, note, 0x64]]);
+    assert!(run.hold(0).is_empty());
+}
+
+#[test]
+fn a_note_ends_at_the_pitch_it_started_with() {
+    let mut run = Run::new();
+    let shifted = NOTES[1] + OCTAVE;
+    run.hold(FN);
+    assert_eq!(run.hold(FN | KEY), [vec![0x90, shifted, 0x64]]);
+    // the fn key going up first leaves the sounding note alone
+    assert!(run.hold(KEY).is_empty());
+    assert_eq!(run.hold(0), [vec![0x80, shifted, 0x64]]);
+    assert_eq!(run.hold(KEY), [vec![0x90, NOTES[1], 0x64]]);
+}
+
+#[test]
+fn releasing_all_ends_every_sounding_note_once() {
+    let mut run = Run::new();
+    let other = key_bit(0, 2);
+    run.hold(KEY);
+    run.hold(KEY | other);
+    let mut messages = Vec::new();
+    run.pad
+        .midi
+        .release_all(|message| messages.push(message.to_vec()));
+    assert_eq!(messages, [vec![0x80, NOTES[1], 0x64], vec![NOTES[2], 0x64]]);
+    // the keys going up afterwards find nothing left to end
+    assert!(run.hold(0).is_empty());
+}
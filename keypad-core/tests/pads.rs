@@ -0,0 +1,40 @@
+//! Two pads on shared rows, the layout of `second-pad`: one matrix of twice the
+//! columns, where a chord spans both pads and a ghost can too.
+
+use keypad_core::debounce::{KeyFilter, Thresholds};
+use keypad_core::keys::{ghost_mask, pad, PAD_COLUMNS};
+
+const COLUMNS: usize = 2 * PAD_COLUMNS;
+const ROWS: usize = 4;
+const KEYS: usize = ROWS * COLUMNS;
+
+const fn bit(row: usize, col: usize) -> u32 {
+    1 << (row * COLUMNS + col)
+}
+
+#[test]
+fn columns_tell_the_pad() {
+    assert_eq!(
+        (0..COLUMNS).map(pad).collect::<Vec<_>>(),
+        [0, 0, 0, 0, 1, 1, 1, 1]
+    );
+}
+
+#[test]
+fn a_chord_spans_both_pads() {
+    let mut filter = KeyFilter::<COLUMNS, ROWS, KEYS>::new(Thresholds::symmetric(1), 10_000);
+    // '1' of the first pad, the last key of the second on its bottom row
+    let chord = bit(0, 0) | bit(3, 7);
+    let filtered = filter.update(chord, 0);
+    assert_eq!((filtered.state.0, filtered.ghosts), (chord, 0));
+    assert_eq!(filter.update(0, 1).state.0, 0);
+}
+
+#[test]
+fn a_ghost_spans_both_pads() {
+    // a rectangle with a column on either pad, any three of its keys read as four
+    let raw = bit(0, 1) | bit(0, 5) | bit(2, 1) | bit(2, 5);
+    assert_eq!(ghost_mask::<COLUMNS, ROWS>(raw), raw);
+    // two opposite corners alone are no ghost
+    assert_eq!(ghost_mask::<COLUMNS, ROWS>(bit(0, 1) | bit(2, 5)), 0);
+}
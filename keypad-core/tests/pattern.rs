@@ -0,0 +1,186 @@
+//! The player on a mock clock that jumps to every deadline it returns, as the
+//! `led_controller` does with `spawn_after`: the steps of a pattern, its repeats,
+//! and one of a higher priority taking over.
+
+use keypad_core::pattern::{Pattern, Player};
+
+const RED: u8 = 1;
+const BLUE: u8 = 2;
+const GREEN: u8 = 4;
+
+static HEARTBEAT: Pattern = Pattern::new(&[(BLUE, 50), (0, 100), (BLUE, 50), (0, 1800)]);
+static FLASH: Pattern = Pattern::new(&[(RED | BLUE | GREEN, 200), (0, 200)]).times(3);
+static ERROR: Pattern = Pattern::new(&[(RED, 200), (0, 200), (RED, 200), (0, 1500)]);
+static IDENTIFY: Pattern = Pattern::new(&[(RED | GREEN, 50), (0, 50)]).cancelled();
+
+const NORMAL: usize = 0;
+const IDENT: usize = 1;
+const TEST: usize = 2;
+const FAULT: usize = 3;
+
+// the ms since the start, moved on only by `run`
+struct Clock {
+    now_ms: u32,
+}
+
+impl Clock {
+    fn at(now_ms: u32) -> Self {
+        Self { now_ms }
+    }
+
+    // the lights and when they went on, step by step until `end_ms`
+    fn run(&mut self, player: &mut Player<4>, end_ms: u32) -> Vec<(u32, u8)> {
+        let mut shown = Vec::new();
+        while self.now_ms < end_ms {
+            let Some(until_ms) = player.update(self.now_ms) else {
+                break;
+            };
+            shown.push((self.now_ms, player.lights()));
+            self.now_ms = until_ms;
+        }
+        shown
+    }
+}
+
+#[test]
+fn steps_follow_their_durations() {
+    let mut clock = Clock::at(1000);
+    let mut player = Player::new();
+    player.play(NORMAL, HEARTBEAT, clock.now_ms);
+    // the first update is early and shows the first step again
+    assert_eq!(
+        clock.run(&mut player, 5000),
+        [
+            (1000, BLUE),
+            (1050, 0),
+            (1150, BLUE),
+            (1200, 0),
+            (3000, BLUE),
+            (3050, 0),
+            (3150, BLUE),
+            (3200, 0),
+        ]
+    );
+}
+
+#[test]
+fn an_update_before_the_step_is_over_keeps_it() {
+    let mut player = Player::<4>::new();
+    player.play(NORMAL, HEARTBEAT, 0);
+    assert_eq!(player.update(20), Some(50));
+    assert_eq!(player.lights(), BLUE);
+    assert_eq!(player.update(50), Some(150));
+    assert_eq!(player.lights(), 0);
+}
+
+#[test]
+fn a_repeated_pattern_stops_after_its_last_round() {
+    let mut clock = Clock::at(0);
+    let mut player = Player::new();
+    player.play(TEST, FLASH, 0);
+    let shown = clock.run(&mut player, 10_000);
+    assert_eq!(shown.len(), 6);
+    assert_eq!(shown[5], (1000, 0));
+    assert!(!player.is_playing(TEST));
+    assert_eq!(player.shown(), None);
+    assert_eq!(player.update(clock.now_ms), None);
+}
+
+#[test]
+fn the_one_below_goes_on_when_a_repeated_one_ends() {
+    let mut clock = Clock::at(0);
+    let mut player = Player::new();
+    player.play(NORMAL, HEARTBEAT, 0);
+    player.play(TEST, FLASH, 0);
+    assert_eq!(player.shown(), Some(TEST));
+    let shown = clock.run(&mut player, 1300);
+    assert_eq!(shown[6], (1200, BLUE));
+    assert_eq!(shown[7], (1250, 0));
+    assert_eq!(player.shown(), Some(NORMAL));
+}
+
+#[test]
+fn an_interrupted_pattern_resumes_with_what_was_left_of_its_step() {
+    let mut clock = Clock::at(0);
+    let mut player = Player::new();
+    player.play(NORMAL, HEARTBEAT, 0);
+    clock.run(&mut player, 1200);
+    // 200 ms before the end of the pause
+    player.play(FAULT, ERROR, 1800);
+    assert_eq!(player.lights(), RED);
+    assert_eq!(player.update(1800), Some(2000));
+    player.stop(FAULT, 1900);
+    assert_eq!(player.shown(), Some(NORMAL));
+    assert_eq!(player.lights(), 0);
+    assert_eq!(player.update(1900), Some(2100));
+    assert_eq!(player.update(2100), Some(2150));
+    assert_eq!(player.lights(), BLUE);
+}
+
+#[test]
+fn an_interrupted_pattern_with_the_cancel_policy_is_dropped() {
+    let mut player = Player::<4>::new();
+    player.play(NORMAL, HEARTBEAT, 0);
+    player.play(IDENT, IDENTIFY, 0);
+    assert_eq!(player.lights(), RED | GREEN);
+    player.play(FAULT, ERROR, 30);
+    assert!(!player.is_playing(IDENT));
+    player.stop(FAULT, 100);
+    assert_eq!(player.shown(), Some(NORMAL));
+}
+
+#[test]
+fn a_pattern_below_the_one_shown_waits_from_its_first_step() {
+    let mut clock = Clock::at(0);
+    let mut player = Player::new();
+    player.play(FAULT, ERROR, 0);
+    player.play(NORMAL, HEARTBEAT, 100);
+    assert_eq!(player.shown(), Some(FAULT));
+    assert_eq!(player.lights(), RED);
+    clock.now_ms = 500;
+    player.stop(FAULT, 500);
+    assert_eq!(
+        clock.run(&mut player, 700),
+        [(500, BLUE), (550, 0), (650, BLUE)]
+    );
+}
+
+#[test]
+fn playing_the_same_again_goes_on_and_another_one_starts_over() {
+    let mut player = Player::<4>::new();
+    player.play(FAULT, ERROR, 0);
+    player.update(200);
+    player.update(400);
+    assert_eq!(player.update(500), Some(600));
+    player.play(FAULT, ERROR, 500);
+    assert_eq!(player.update(500), Some(600));
+    player.play(FAULT, IDENTIFY, 500);
+    assert_eq!(player.lights(), RED | GREEN);
+    assert_eq!(player.update(500), Some(550));
+}
+
+#[test]
+fn nothing_is_shown_once_all_are_stopped() {
+    let mut player = Player::<4>::new();
+    player.play(NORMAL, HEARTBEAT, 0);
+    player.play(FAULT, ERROR, 10);
+    player.stop(NORMAL, 20);
+    assert_eq!(player.shown(), Some(FAULT));
+    player.stop(FAULT, 30);
+    assert_eq!(player.shown(), None);
+    assert_eq!(player.lights(), 0);
+    assert_eq!(player.update(40), None);
+}
+
+#[test]
+fn the_clock_may_wrap() {
+    let start = u32::MAX - 60;
+    let mut player = Player::<4>::new();
+    player.play(NORMAL, HEARTBEAT, start);
+    assert_eq!(player.update(start + 40), Some(start.wrapping_add(50)));
+    assert_eq!(
+        player.update(start.wrapping_add(50)),
+        Some(start.wrapping_add(150))
+    );
+    assert_eq!(player.lights(), 0);
+}
@@ -0,0 +1,518 @@
+//! The pipeline driven by made up scans: raw bitmasks as the scanner would read
+//! them, a scan per millisecond unless a test says otherwise. The scans go through
+//! the engine as the firmware's scanner runs it, the pieces of its own are tested
+//! alone.
+
+use keypad_core::chord::{ChordDetector, CHORD_HOLD_MS, CHORD_WINDOW_MS};
+use keypad_core::debounce::{Debouncer, KeyFilter, Thresholds, DEBOUNCE_THRESHOLD};
+use keypad_core::engine::EventEngine;
+use keypad_core::event::{EventKind, KeyEvent};
+use keypad_core::gesture::{DoubleTap, LongPress, Repeat, DOUBLE_TAP_MS, LONG_PRESS_MS};
+use keypad_core::keys::{ghost_mask, key_bit, KeyState, COLUMNS, KEYS, ROWS};
+use keypad_core::matrix::MatrixScanner;
+use keypad_core::modifiers::Modifiers;
+use keypad_core::queue::{self, EventProducer, Overflow};
+use keypad_core::time;
+use std::collections::VecDeque;
+
+const STUCK_MS: u32 = 30_000;
+const TICKS_PER_MS: u64 = (time::TICK_HZ / 1000) as u64;
+
+static CHORDS: [(KeyState, u8); 1] = [(KeyState(key_bit(0, 0) | key_bit(0, 1)), 7)];
+static LOCKS: [(KeyState, u8); 1] = [(KeyState(key_bit(3, 3)), 1)];
+
+// no matrix of its own, the tests feed `EventEngine::update` the raw scans
+struct NoScanner;
+
+impl MatrixScanner for NoScanner {
+    type Error = ();
+
+    fn scan_raw(&mut self) -> Result<u32, ()> {
+        Err(())
+    }
+
+    fn dims(&self) -> (u8, u8) {
+        (COLUMNS as u8, ROWS as u8)
+    }
+}
+
+/// The engine as the scanner runs it, without chords, collecting the events.
+struct Pipeline {
+    engine: EventEngine<NoScanner>,
+    now_ms: u32,
+    ghosting: u32,
+}
+
+impl Pipeline {
+    fn new() -> Self {
+        Self {
+            engine: EventEngine::new(NoScanner, Thresholds::DEFAULT, STUCK_MS, &[]).unwrap(),
+            now_ms: 0,
+            ghosting: 0,
+        }
+    }
+
+    fn scan(&mut self, raw: u32) -> Vec<KeyEvent> {
+        self.now_ms += 1;
+        let at = u64::from(self.now_ms) * TICKS_PER_MS;
+        let mut events = Vec::new();
+        self.engine
+            .update(raw, self.now_ms, at, &mut |event| events.push(event));
+        let ghosting = |event: &KeyEvent| event.kind == EventKind::GhostingDetected;
+        self.ghosting += events.iter().filter(|event| ghosting(event)).count() as u32;
+        events.retain(|event| !ghosting(event));
+        events
+    }
+
+    fn scans(&mut self, raws: &[u32]) -> Vec<KeyEvent> {
+        raws.iter().flat_map(|&raw| self.scan(raw)).collect()
+    }
+
+    fn hold(&mut self, raw: u32, scans: usize) -> Vec<KeyEvent> {
+        self.scans(&vec![raw; scans])
+    }
+}
+
+fn kinds(events: &[KeyEvent]) -> Vec<(u8, u8, EventKind)> {
+    events.iter().map(|e| (e.row, e.col, e.kind)).collect()
+}
+
+fn event(row: usize, col: usize, kind: EventKind) -> KeyEvent {
+    KeyEvent::new(row * COLUMNS + col, kind)
+}
+
+#[test]
+fn clean_press_and_release() {
+    let key = key_bit(1, 2);
+    let mut pipeline = Pipeline::new();
+    let pressed = pipeline.hold(key, DEBOUNCE_THRESHOLD.into());
+    assert_eq!(kinds(&pressed), [(1, 2, EventKind::Pressed)]);
+    assert!(pipeline.hold(key, 97).is_empty());
+    let released = pipeline.hold(0, DEBOUNCE_THRESHOLD.into());
+    assert_eq!(
+        kinds(&released),
+        [(1, 2, EventKind::Released { held_ms: 100 })]
+    );
+}
+
+#[test]
+fn bounces_make_one_press_and_one_release() {
+    let key = key_bit(0, 3);
+    let mut pipeline = Pipeline::new();
+    let mut events = pipeline.scans(&[key, 0, key, 0, key, key, 0, key, key, key]);
+    events.extend(pipeline.hold(key, 20));
+    events.extend(pipeline.scans(&[0, key, 0, 0, key, 0, 0, 0]));
+    events.extend(pipeline.hold(0, 10));
+    let kinds: Vec<_> = events.iter().map(|e| e.kind).collect();
+    assert_eq!(kinds.len(), 2);
+    assert_eq!(kinds[0], EventKind::Pressed);
+    assert!(matches!(kinds[1], EventKind::Released { .. }));
+}
+
+#[test]
+fn events_keep_the_tick_their_edge_was_seen_at() {
+    let key = key_bit(0, 0);
+    let mut pipeline = Pipeline::new();
+    let mut pressed = pipeline.scans(&[key, 0, key, 0, key]);
+    while pressed.is_empty() {
+        pressed = pipeline.scan(key);
+    }
+    let seen_at = u64::from(pipeline.now_ms) * TICKS_PER_MS;
+    assert_eq!(pressed[0].at, seen_at);
+
+    // a chord key's press comes out of the detector only once the window expired,
+    // and the consumer takes it off the queue later still
+    let mut queue = VecDeque::new();
+    let mut producer = EventProducer::<3>::new();
+    let mut detector = ChordDetector::new(&CHORDS);
+    let mut out = Vec::new();
+    detector.filter(pressed[0], pipeline.now_ms, |event| out.push(event));
+    assert!(out.is_empty());
+    pipeline.hold(key, CHORD_WINDOW_MS as usize);
+    detector.poll(pipeline.now_ms, |event| out.push(event));
+    for mut event in out {
+        // as the scanner queues them
+        event.stamp(u64::from(pipeline.now_ms) * TICKS_PER_MS);
+        producer.push(Queued::Key(event), send(&mut queue));
+    }
+    pipeline.hold(key, 20);
+    assert_eq!(queue.pop_front(), Some(Queued::Key(pressed[0])));
+
+    // what the gestures make up is stamped when they do
+    let mut long = LongPress::new();
+    long.track(pressed[0], 0);
+    let mut long_pressed = None;
+    long.poll(LONG_PRESS_MS, |event| long_pressed = Some(event));
+    let mut long_pressed = long_pressed.unwrap();
+    assert_eq!(long_pressed.at, 0);
+    long_pressed.stamp(seen_at + 1000);
+    assert_eq!(long_pressed.at, seen_at + 1000);
+}
+
+#[test]
+fn short_glitch_never_presses() {
+    let mut debouncer = Debouncer::<KEYS>::new(Thresholds::DEFAULT);
+    let key = key_bit(2, 2);
+    for raw in [key, key, 0, 0, key, key, 0, 0] {
+        assert_eq!(debouncer.update(raw), 0);
+    }
+}
+
+#[test]
+fn lower_threshold_keeps_held_keys() {
+    let key = key_bit(3, 0);
+    let mut pipeline = Pipeline::new();
+    let pressed = pipeline.hold(key, 10);
+    assert_eq!(pressed.len(), 1);
+    pipeline.engine.set_thresholds(Thresholds::symmetric(1));
+    assert!(pipeline.hold(key, 3).is_empty());
+    let released = pipeline.scan(0);
+    assert!(matches!(released[0].kind, EventKind::Released { .. }));
+}
+
+#[test]
+fn ghosting_rectangle_keeps_the_corners() {
+    // three corners of the rectangle of rows 0 and 1, columns 0 and 1, and the
+    // fourth one the matrix puts in
+    let corners = key_bit(0, 0) | key_bit(0, 1) | key_bit(1, 0);
+    let ghost = key_bit(1, 1);
+    assert_eq!(
+        ghost_mask::<COLUMNS, ROWS>(corners | ghost),
+        corners | ghost
+    );
+    assert_eq!(ghost_mask::<COLUMNS, ROWS>(corners), 0);
+
+    let mut pipeline = Pipeline::new();
+    let pressed = pipeline.hold(key_bit(0, 0) | key_bit(1, 0), 5);
+    assert_eq!(pressed.len(), 2);
+    // the third corner and its ghost arrive together, every corner keeps the state
+    // it had before
+    assert!(pipeline.hold(corners | ghost, 20).is_empty());
+    assert_eq!(pipeline.ghosting, 1);
+    // once a corner lets go the others are read again
+    let events = pipeline.hold(key_bit(0, 0) | key_bit(0, 1), 5);
+    assert_eq!(
+        kinds(&events),
+        [
+            (0, 1, EventKind::Pressed),
+            (1, 0, EventKind::Released { held_ms: 25 }),
+        ]
+    );
+}
+
+#[test]
+fn ghosting_is_reported_once_per_run() {
+    let rectangle = key_bit(1, 1) | key_bit(1, 3) | key_bit(2, 1) | key_bit(2, 3);
+    let mut pipeline = Pipeline::new();
+    pipeline.hold(rectangle, 10);
+    pipeline.hold(0, 5);
+    pipeline.hold(rectangle, 10);
+    assert_eq!(pipeline.ghosting, 2);
+}
+
+#[test]
+fn stuck_key_reads_released_until_it_recovers() {
+    let key = key_bit(2, 0);
+    let mut filter = KeyFilter::<COLUMNS, ROWS, KEYS>::new(Thresholds::symmetric(1), 1000);
+    assert_eq!(filter.update(key, 0).state, KeyState(key));
+    let stuck = filter.update(key, 1000);
+    assert_eq!(stuck.state, KeyState(0));
+    assert_eq!(stuck.stuck, KeyState(key));
+    assert_eq!(stuck.was_stuck, KeyState(0));
+    let released = filter.update(0, 1001);
+    assert_eq!(released.was_stuck, KeyState(key));
+    assert_eq!(released.stuck, KeyState(0));
+    assert_eq!(filter.update(key, 1002).state, KeyState(key));
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Queued {
+    Key(KeyEvent),
+    Overflow,
+}
+
+impl Overflow for Queued {
+    const OVERFLOW: Self = Queued::Overflow;
+}
+
+// the queue of the consumer, `EventProducer` keeps it within its slots
+fn send(queue: &mut VecDeque<Queued>) -> impl FnMut(Queued) -> bool + '_ {
+    |event| {
+        queue.push_back(event);
+        true
+    }
+}
+
+fn take(producer: &mut EventProducer<3>, queue: &mut VecDeque<Queued>) -> Option<Queued> {
+    producer.taken();
+    queue.pop_front()
+}
+
+#[test]
+fn full_queue_drops_and_marks_the_gap() {
+    let mut queue = VecDeque::new();
+    let mut producer = EventProducer::<3>::new();
+    let dropped = queue::dropped();
+    let events: Vec<_> = (0..6)
+        .map(|index| event(index / COLUMNS, index % COLUMNS, EventKind::Pressed))
+        .collect();
+    // three slots, the other three presses are dropped
+    let pushed: Vec<_> = events
+        .iter()
+        .map(|&e| producer.push(Queued::Key(e), send(&mut queue)))
+        .collect();
+    assert_eq!(pushed, [true, true, true, false, false, false]);
+    assert_eq!(queue::dropped() - dropped, 3);
+    // one free slot isn't enough for the marker and an event
+    take(&mut producer, &mut queue);
+    assert!(!producer.push(Queued::Key(events[0]), send(&mut queue)));
+    take(&mut producer, &mut queue);
+    assert!(producer.push(Queued::Key(events[5]), send(&mut queue)));
+    assert_eq!(
+        take(&mut producer, &mut queue),
+        Some(Queued::Key(events[2]))
+    );
+    assert_eq!(take(&mut producer, &mut queue), Some(Queued::Overflow));
+    assert_eq!(
+        take(&mut producer, &mut queue),
+        Some(Queued::Key(events[5]))
+    );
+    assert!(!producer.pending());
+    // no marker without another drop
+    assert!(producer.push(Queued::Key(events[1]), send(&mut queue)));
+    assert_eq!(
+        take(&mut producer, &mut queue),
+        Some(Queued::Key(events[1]))
+    );
+    // a send that fails is a drop as well
+    assert!(!producer.push(Queued::Key(events[2]), |_| false));
+    assert_eq!(queue::dropped() - dropped, 5);
+    assert!(!producer.pending());
+    assert!(producer.push(Queued::Key(events[3]), send(&mut queue)));
+    assert_eq!(queue, [Queued::Overflow, Queued::Key(events[3])]);
+}
+
+fn chord(events: &[(KeyEvent, u32)], poll_at: &[u32]) -> Vec<(u8, u8, EventKind)> {
+    let mut detector = ChordDetector::new(&CHORDS);
+    let mut out = Vec::new();
+    let mut polls = poll_at.iter().peekable();
+    for &(event, now) in events {
+        while let Some(&&at) = polls.peek().filter(|&&&at| at <= now) {
+            detector.poll(at, |e| out.push(e));
+            polls.next();
+        }
+        detector.filter(event, now, |e| out.push(e));
+    }
+    for &at in polls {
+        detector.poll(at, |e| out.push(e));
+    }
+    kinds(&out)
+}
+
+#[test]
+fn chord_within_the_window() {
+    let released = EventKind::Released { held_ms: 100 };
+    let out = chord(
+        &[
+            (event(0, 0, EventKind::Pressed), 0),
+            (event(0, 1, EventKind::Pressed), CHORD_WINDOW_MS - 1),
+            (event(0, 0, released), 100),
+            (event(0, 1, released), 110),
+        ],
+        &[200],
+    );
+    assert_eq!(out, [(0, 1, EventKind::Chord(7))]);
+}
+
+#[test]
+fn chord_held_is_reported_once() {
+    let out = chord(
+        &[
+            (event(0, 0, EventKind::Pressed), 0),
+            (event(0, 1, EventKind::Pressed), 10),
+        ],
+        &[CHORD_HOLD_MS, CHORD_HOLD_MS + 10, CHORD_HOLD_MS * 2],
+    );
+    assert_eq!(
+        out,
+        [(0, 1, EventKind::Chord(7)), (0, 1, EventKind::ChordHeld(7))]
+    );
+}
+
+#[test]
+fn single_chord_key_is_delayed_not_lost() {
+    let out = chord(
+        &[
+            (event(0, 0, EventKind::Pressed), 0),
+            (event(0, 1, EventKind::Pressed), CHORD_WINDOW_MS + 5),
+        ],
+        &[CHORD_WINDOW_MS, CHORD_WINDOW_MS * 3],
+    );
+    assert_eq!(
+        out,
+        [(0, 0, EventKind::Pressed), (0, 1, EventKind::Pressed)]
+    );
+    // a quick tap comes out as it happened
+    let tap = chord(
+        &[
+            (event(0, 0, EventKind::Pressed), 0),
+            (event(0, 0, EventKind::Released { held_ms: 20 }), 20),
+        ],
+        &[],
+    );
+    assert_eq!(
+        tap,
+        [
+            (0, 0, EventKind::Pressed),
+            (0, 0, EventKind::Released { held_ms: 20 })
+        ]
+    );
+}
+
+#[test]
+fn other_keys_pass_the_chord_detector() {
+    let out = chord(&[(event(2, 2, EventKind::Pressed), 0)], &[]);
+    assert_eq!(out, [(2, 2, EventKind::Pressed)]);
+}
+
+#[test]
+fn long_press_then_release() {
+    let mut long = LongPress::new();
+    let mut out = Vec::new();
+    long.track(event(1, 1, EventKind::Pressed), 0);
+    long.poll(LONG_PRESS_MS - 1, |e| out.push(e));
+    assert!(out.is_empty());
+    long.poll(LONG_PRESS_MS, |e| out.push(e));
+    long.poll(LONG_PRESS_MS + 500, |e| out.push(e));
+    assert_eq!(kinds(&out), [(1, 1, EventKind::LongPressed)]);
+    let released = long.track(event(1, 1, EventKind::Released { held_ms: 1600 }), 1600);
+    assert_eq!(released.kind, EventKind::ReleasedAfterLong);
+    // a short press after it releases as usual
+    long.track(event(1, 1, EventKind::Pressed), 2000);
+    let released = long.track(event(1, 1, EventKind::Released { held_ms: 10 }), 2010);
+    assert_eq!(released.kind, EventKind::Released { held_ms: 10 });
+}
+
+#[test]
+fn double_tap_within_the_window() {
+    let mut taps = DoubleTap::new();
+    let mut out = Vec::new();
+    taps.track(event(0, 2, EventKind::Pressed), 0, |e| out.push(e));
+    taps.track(event(0, 2, EventKind::Pressed), DOUBLE_TAP_MS, |e| {
+        out.push(e)
+    });
+    // a third press starts over
+    taps.track(event(0, 2, EventKind::Pressed), DOUBLE_TAP_MS + 10, |e| {
+        out.push(e)
+    });
+    let kinds: Vec<_> = out.iter().map(|e| e.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            EventKind::Pressed,
+            EventKind::Pressed,
+            EventKind::DoubleTap,
+            EventKind::Pressed
+        ]
+    );
+    let mut late = Vec::new();
+    taps.track(
+        event(0, 2, EventKind::Pressed),
+        2 * DOUBLE_TAP_MS + 11,
+        |e| late.push(e),
+    );
+    assert_eq!(late.len(), 1);
+}
+
+#[test]
+fn repeat_follows_the_last_pressed_key() {
+    let mut repeat = Repeat::new(500, 100);
+    repeat.track(&event(0, 0, EventKind::Pressed), 0);
+    assert_eq!(repeat.poll(499), None);
+    assert_eq!(repeat.poll(500).map(|e| e.kind), Some(EventKind::Repeat));
+    assert_eq!(repeat.poll(550), None);
+    // a late poll keeps the rate, a stall doesn't catch up
+    assert!(repeat.poll(620).is_some());
+    assert_eq!(repeat.poll(700).map(|e| (e.row, e.col)), Some((0, 0)));
+    assert!(repeat.poll(1150).is_some());
+    assert_eq!(repeat.poll(1200), None);
+    assert!(repeat.poll(1250).is_some());
+    repeat.track(&event(1, 1, EventKind::Pressed), 1300);
+    assert_eq!(repeat.poll(1799), None);
+    // releasing the key that no longer repeats changes nothing
+    repeat.track(&event(0, 0, EventKind::Released { held_ms: 5 }), 1800);
+    assert_eq!(repeat.held().map(|e| (e.row, e.col)), Some((1, 1)));
+    assert_eq!(repeat.poll(1800).map(|e| (e.row, e.col)), Some((1, 1)));
+    repeat.track(&event(1, 1, EventKind::ReleasedAfterLong), 1850);
+    assert_eq!(repeat.held(), None);
+    assert_eq!(repeat.poll(2000), None);
+}
+
+#[test]
+fn lock_keys_toggle_and_are_swallowed() {
+    let mut modifiers = Modifiers::default();
+    let lock = KeyState(key_bit(3, 3));
+    let other = KeyState(key_bit(0, 0));
+    let changes = modifiers.apply((lock | other, KeyState(0)), &LOCKS);
+    assert_eq!(changes, (other, KeyState(0)));
+    assert!(modifiers.is_locked(1));
+    let changes = modifiers.apply((KeyState(0), lock), &LOCKS);
+    assert_eq!(changes, (KeyState(0), KeyState(0)));
+    modifiers.apply((lock, KeyState(0)), &LOCKS);
+    assert!(!modifiers.is_locked(1));
+}
+
+#[test]
+fn key_state_shows_the_grid() {
+    let state = KeyState(key_bit(0, 0) | key_bit(2, 2));
+    let row = |pressed: Option<usize>| {
+        (0..COLUMNS)
+            .map(|col| if Some(col) == pressed { 'X' } else { '.' })
+            .collect::<String>()
+    };
+    let grid = [row(Some(0)), row(None), row(Some(2)), row(None)].join(" ");
+    assert_eq!(state.to_string(), grid);
+    assert_eq!(state.iter_pressed().collect::<Vec<_>>(), [(0, 0), (2, 2)]);
+    let (pressed, released) = KeyState(key_bit(1, 1)).diff(state);
+    assert_eq!(pressed, KeyState(key_bit(1, 1)));
+    assert_eq!(released, state);
+}
+
+#[test]
+fn key_state_of_no_key_and_of_every_key() {
+    let none = KeyState(0);
+    let all = KeyState((0..KEYS).fold(0, |keys, key| keys | 1 << key));
+    assert_eq!(none.pressed_count(), 0);
+    assert_eq!(none.iter_pressed().count(), 0);
+    assert_eq!(all.pressed_count(), KEYS as u32);
+    let every: Vec<_> = (0..ROWS as u8)
+        .flat_map(|row| (0..COLUMNS as u8).map(move |col| (row, col)))
+        .collect();
+    assert_eq!(all.iter_pressed().collect::<Vec<_>>(), every);
+    assert_eq!(all.diff(none), (all, none));
+    assert_eq!(none.diff(all), (none, all));
+    assert_eq!(all.diff(all), (none, none));
+    assert_eq!(none.diff(none), (none, none));
+    assert!(!all.to_string().contains('.'));
+}
+
+#[test]
+fn time_division_matches_u64() {
+    for value in [0, 999, 1000, 123_456_789, u64::MAX / 3, u64::MAX] {
+        assert_eq!(time::to_ms(value), value / 1000);
+        assert_eq!(time::div(value, 72), value / 72);
+    }
+}
+
+#[test]
+fn event_line_format() {
+    let mut released = event(0, 3, EventKind::Released { held_ms: 120 });
+    released.key = 'A';
+    released.at = 1_520_000;
+    assert_eq!(released.to_string(), "1520 0 3 A RELEASED 120");
+    let chord = KeyEvent {
+        at: 2_000,
+        ..event(1, 0, EventKind::Chord(2))
+    };
+    assert_eq!(chord.to_string(), "2 1 0 ? CHORD 2");
+}
@@ -0,0 +1,36 @@
+//! The verdicts on the row pulls: quiet rows pass, flickering and stuck floating
+//! rows fail, and so does a row active on its internal pull.
+
+use keypad_core::pull_check::{verdict, Verdict, SAMPLES};
+
+#[test]
+fn quiet_rows_are_pulled() {
+    let samples = [0; SAMPLES];
+    for row in 0..4 {
+        assert_eq!(verdict(&samples, 0, row), Verdict::Pulled);
+        assert!(!verdict(&samples, 0, row).failed());
+    }
+}
+
+#[test]
+fn floating_rows_fail() {
+    // row 1 flickers, row 2 sits active, both read inactive on the internal pull
+    let samples = [
+        0b0110, 0b0100, 0b0110, 0b0100, 0b0100, 0b0110, 0b0100, 0b0100,
+    ];
+    assert_eq!(verdict(&samples, 0, 0), Verdict::Pulled);
+    assert_eq!(verdict(&samples, 0, 1), Verdict::Unstable);
+    assert_eq!(verdict(&samples, 0, 2), Verdict::Stuck);
+    assert_eq!(verdict(&samples, 0, 3), Verdict::Pulled);
+    assert!(verdict(&samples, 0, 1).failed());
+    assert!(verdict(&samples, 0, 2).failed());
+}
+
+#[test]
+fn a_row_active_on_its_pull_is_shorted() {
+    let samples = [0b1000; SAMPLES];
+    assert_eq!(verdict(&samples, 0b1000, 3), Verdict::Shorted);
+    assert!(verdict(&samples, 0b1000, 3).failed());
+    // other rows are unaffected
+    assert_eq!(verdict(&samples, 0b1000, 0), Verdict::Pulled);
+}
@@ -0,0 +1,94 @@
+//! The NKRO bitmap and the 6KRO boot report of the same key states, and the
+//! report descriptor the host reads the bitmap with.
+
+use keypad_core::keys::{key_bit, KeyState, KEYS};
+use keypad_core::report::{
+    bitmap, boot_report, nkro_report, BOOT_REPORT_SIZE, NKRO_DESCRIPTOR, NKRO_REPORT_SIZE, USAGES,
+};
+
+// the usages the descriptor assigns to the bits, in the order of the bits
+fn descriptor_usages() -> Vec<u8> {
+    let start = NKRO_DESCRIPTOR
+        .windows(2)
+        .position(|item| item == [0x05, 0x07])
+        .unwrap()
+        + 2;
+    NKRO_DESCRIPTOR[start..]
+        .chunks(2)
+        .take_while(|item| item[0] == 0x09)
+        .map(|item| item[1])
+        .collect()
+}
+
+// the usages of the boot report's slots that are in use
+fn boot_usages(keys: KeyState) -> Vec<u8> {
+    boot_report(keys)[2..]
+        .iter()
+        .copied()
+        .filter(|&usage| usage != 0)
+        .collect()
+}
+
+// the usages of the bits set in the bitmap, in the order of the bits
+fn nkro_usages(keys: KeyState) -> Vec<u8> {
+    let report = nkro_report(keys);
+    (0..KEYS)
+        .filter(|&bit| report[bit / 8] & 1 << (bit % 8) != 0)
+        .map(|bit| descriptor_usages()[bit])
+        .collect()
+}
+
+#[test]
+fn a_bit_per_key() {
+    for key in 0..KEYS {
+        let mut report = [0; NKRO_REPORT_SIZE];
+        report[key / 8] = 1 << (key % 8);
+        assert_eq!(nkro_report(KeyState(1 << key)), report);
+    }
+    // the I2C key register, which only takes a single pad
+    for key in 0..KEYS.min(16) {
+        assert_eq!(bitmap(KeyState(1 << key)), 1 << key);
+    }
+    let all = KeyState((0..KEYS).fold(0, |keys, key| keys | 1 << key));
+    assert_eq!(nkro_report(KeyState(0)), [0; NKRO_REPORT_SIZE]);
+    assert_eq!(nkro_report(all), [0xff; NKRO_REPORT_SIZE]);
+}
+
+#[test]
+fn descriptor_lists_the_usages_in_bit_order() {
+    assert_eq!(descriptor_usages(), USAGES);
+    // a report count of one bit per key
+    assert!(NKRO_DESCRIPTOR
+        .windows(2)
+        .any(|item| item == [0x95, KEYS as u8]));
+    assert_eq!(NKRO_DESCRIPTOR.last(), Some(&0xc0));
+}
+
+#[test]
+fn boot_report_of_a_single_key() {
+    let report = boot_report(KeyState(key_bit(3, 2)));
+    assert_eq!(report, [0, 0, 0xcc, 0, 0, 0, 0, 0]);
+    assert_eq!(boot_report(KeyState(0)), [0; BOOT_REPORT_SIZE]);
+}
+
+#[test]
+fn both_reports_agree_on_the_order() {
+    let keys = KeyState(key_bit(3, 3) | key_bit(0, 1) | key_bit(2, 0) | key_bit(1, 2));
+    assert_eq!(boot_usages(keys), nkro_usages(keys));
+    assert_eq!(boot_usages(keys), [0x1f, 0x23, 0x24, 0x07]);
+}
+
+#[test]
+fn boot_report_carries_six_keys() {
+    let six = KeyState(0b11_1111 << 4);
+    assert_eq!(boot_usages(six), nkro_usages(six));
+    assert_eq!(boot_usages(six).len(), 6);
+}
+
+#[test]
+fn boot_report_rolls_over_past_six_keys() {
+    let seven = KeyState(0b111_1111);
+    assert_eq!(boot_report(seven), [0, 0, 1, 1, 1, 1, 1, 1]);
+    // the bitmap keeps every one of them
+    assert_eq!(nkro_usages(seven).len(), 7);
+}
@@ -0,0 +1,127 @@
+//! The router with subscribers whose keys and kinds overlap, and with grabs that
+//! take keys from the others.
+
+use keypad_core::event::{EventKind, KeyEvent};
+use keypad_core::keys::{key_bit, KeyState, COLUMNS};
+use keypad_core::route::{Kinds, Receivers, Router, Subscription};
+
+// the entry mode, a backend, the stream and the lcd
+const ENTRY: usize = 0;
+const BACKEND: usize = 1;
+const STREAM: usize = 2;
+const LCD: usize = 3;
+
+const ROW_0: KeyState = KeyState(key_bit(0, 0) | key_bit(0, 1) | key_bit(0, 2) | key_bit(0, 3));
+const DIGITS: KeyState = KeyState(key_bit(0, 0) | key_bit(0, 1) | key_bit(1, 0));
+
+fn event(row: usize, col: usize, kind: EventKind) -> KeyEvent {
+    KeyEvent::new(row * COLUMNS + col, kind)
+}
+
+fn receivers(router: &Router<4>, event: &KeyEvent) -> Vec<usize> {
+    let receivers = router.route(event);
+    (0..4).filter(|&consumer| receivers.has(consumer)).collect()
+}
+
+fn overlapping() -> Router<4> {
+    let mut router = Router::new();
+    router.subscribe(ENTRY, Subscription::NONE);
+    router.subscribe(BACKEND, Subscription::new(ROW_0, Kinds::KEY));
+    let edges = Kinds::PRESS.with(Kinds::RELEASE);
+    router.subscribe(STREAM, Subscription::new(KeyState(u32::MAX), edges));
+    router.subscribe(LCD, Subscription::new(DIGITS, Kinds::PRESS));
+    router
+}
+
+#[test]
+fn everyone_starts_with_everything() {
+    let router = Router::<4>::new();
+    for kind in [EventKind::Pressed, EventKind::Repeat, EventKind::Chord(1)] {
+        assert_eq!(receivers(&router, &event(3, 3, kind)), [0, 1, 2, 3]);
+    }
+    assert!(Receivers::ALL.has(31));
+}
+
+#[test]
+fn keys_and_kinds_overlap() {
+    let router = overlapping();
+    assert_eq!(
+        receivers(&router, &event(0, 0, EventKind::Pressed)),
+        [BACKEND, STREAM, LCD]
+    );
+    let released = EventKind::Released { held_ms: 80 };
+    assert_eq!(
+        receivers(&router, &event(0, 0, released)),
+        [BACKEND, STREAM]
+    );
+    assert_eq!(
+        receivers(&router, &event(0, 3, EventKind::Repeat)),
+        [BACKEND]
+    );
+    assert_eq!(
+        receivers(&router, &event(1, 0, EventKind::Pressed)),
+        [STREAM, LCD]
+    );
+    assert_eq!(
+        receivers(&router, &event(2, 2, EventKind::ReleasedAfterLong)),
+        [STREAM]
+    );
+    assert!(receivers(&router, &event(2, 2, EventKind::LongPressed)).is_empty());
+}
+
+#[test]
+fn a_grab_takes_its_keys_from_the_others() {
+    let mut router = overlapping();
+    router.subscribe(ENTRY, Subscription::grab(DIGITS));
+    for kind in [
+        EventKind::Pressed,
+        EventKind::LongPressed,
+        EventKind::Repeat,
+    ] {
+        assert_eq!(receivers(&router, &event(0, 1, kind)), [ENTRY]);
+    }
+    // the other keys go on as before
+    assert_eq!(
+        receivers(&router, &event(0, 2, EventKind::Pressed)),
+        [BACKEND, STREAM]
+    );
+    // and a chord on a grabbed key isn't the grab's
+    let mut everyone = Router::<4>::new();
+    everyone.subscribe(ENTRY, Subscription::grab(DIGITS));
+    assert_eq!(
+        receivers(&everyone, &event(0, 0, EventKind::Chord(1))),
+        [BACKEND, STREAM, LCD]
+    );
+
+    // letting go hands them back
+    router.subscribe(ENTRY, Subscription::NONE);
+    assert_eq!(
+        receivers(&router, &event(0, 1, EventKind::Pressed)),
+        [BACKEND, STREAM, LCD]
+    );
+}
+
+#[test]
+fn overlapping_grabs_share_their_keys() {
+    let mut router = overlapping();
+    router.subscribe(ENTRY, Subscription::grab(DIGITS));
+    router.subscribe(LCD, Subscription::grab(ROW_0));
+    assert_eq!(
+        receivers(&router, &event(0, 0, EventKind::Pressed)),
+        [ENTRY, LCD]
+    );
+    assert_eq!(receivers(&router, &event(0, 3, EventKind::Pressed)), [LCD]);
+    assert_eq!(
+        receivers(&router, &event(1, 0, EventKind::Pressed)),
+        [ENTRY]
+    );
+}
+
+#[test]
+fn kinds_of_the_events() {
+    assert_eq!(Kinds::of(EventKind::ReleasedAfterLong), Kinds::RELEASE);
+    assert_eq!(Kinds::of(EventKind::DoubleTap), Kinds::DOUBLE_TAP);
+    assert_eq!(Kinds::of(EventKind::TooManyKeys), Kinds::OTHER);
+    assert!(Kinds::ALL.contains(Kinds::OTHER) && !Kinds::KEY.contains(Kinds::OTHER));
+    assert!(Kinds::KEY.contains(Kinds::PRESS.with(Kinds::LONG)));
+}
@@ -0,0 +1,96 @@
+//! The sequence detector fed presses by their keys, ms apart: noise in between,
+//! sequences sharing a start, and sequences that repeat their own start.
+
+use keypad_core::event::{EventKind, KeyEvent};
+use keypad_core::sequence::{Sequence, SequenceDetector};
+
+static SEQUENCES: [Sequence; 3] = [
+    Sequence {
+        id: 0,
+        keys: &['C', '1', 'C', '1', '#'],
+        within_ms: 3000,
+    },
+    Sequence {
+        id: 1,
+        keys: &['C', '1', 'C', '2'],
+        within_ms: 3000,
+    },
+    Sequence {
+        id: 2,
+        keys: &['A', 'B', 'A', 'B', '#'],
+        within_ms: 3000,
+    },
+];
+
+fn press(key: char) -> KeyEvent {
+    KeyEvent {
+        key,
+        ..KeyEvent::new(0, EventKind::Pressed)
+    }
+}
+
+// the ids completed by `keys` pressed `gap_ms` apart, with the key that did it
+fn typed(detector: &mut SequenceDetector<3>, keys: &str, gap_ms: u32) -> Vec<(usize, u8)> {
+    keys.chars()
+        .enumerate()
+        .filter_map(|(i, key)| {
+            let id = detector.track(&press(key), i as u32 * gap_ms)?;
+            Some((i, id))
+        })
+        .collect()
+}
+
+#[test]
+fn a_sequence_completes_on_its_last_key() {
+    let mut detector = SequenceDetector::new(&SEQUENCES);
+    assert_eq!(typed(&mut detector, "C1C1#", 100), [(4, 0)]);
+    assert_eq!(typed(&mut detector, "C1C2", 100), [(3, 1)]);
+}
+
+#[test]
+fn only_presses_count() {
+    let mut detector = SequenceDetector::new(&SEQUENCES);
+    for key in ['C', '1', 'C', '1'] {
+        assert_eq!(detector.track(&press(key), 0), None);
+        let released = KeyEvent {
+            key,
+            ..KeyEvent::new(0, EventKind::Released { held_ms: 50 })
+        };
+        assert_eq!(detector.track(&released, 0), None);
+    }
+    assert_eq!(detector.track(&press('#'), 0), Some(0));
+}
+
+#[test]
+fn a_noise_key_starts_it_over() {
+    let mut detector = SequenceDetector::new(&SEQUENCES);
+    assert_eq!(typed(&mut detector, "C15C1#", 100), []);
+    // before it, and after it
+    assert_eq!(typed(&mut detector, "77C1C1#", 100), [(6, 0)]);
+    assert_eq!(typed(&mut detector, "C1C3C1C1#", 100), [(8, 0)]);
+}
+
+#[test]
+fn sequences_sharing_a_start_both_move_on() {
+    let mut detector = SequenceDetector::new(&SEQUENCES);
+    assert_eq!(typed(&mut detector, "C1C2C1C1#", 100), [(3, 1), (8, 0)]);
+}
+
+#[test]
+fn a_repeated_start_falls_back_to_it() {
+    let mut detector = SequenceDetector::new(&SEQUENCES);
+    assert_eq!(typed(&mut detector, "C1C1C1#", 100), [(6, 0)]);
+    assert_eq!(typed(&mut detector, "ABABAB#", 100), [(6, 2)]);
+    assert_eq!(typed(&mut detector, "AABAB#", 100), [(5, 2)]);
+    // the second sequence goes on from the last `C 1 C` of the first
+    assert_eq!(typed(&mut detector, "C1C1C2", 100), [(5, 1)]);
+}
+
+#[test]
+fn a_late_key_keeps_only_what_is_in_time() {
+    let mut detector = SequenceDetector::new(&SEQUENCES);
+    // 4 s for the five keys
+    assert_eq!(typed(&mut detector, "C1C1#", 1000), []);
+    // the first `C 1` is too old by the `#`, the start it falls back to isn't
+    assert_eq!(typed(&mut detector, "C1C1C1#", 700), [(6, 0)]);
+}
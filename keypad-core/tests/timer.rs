@@ -0,0 +1,104 @@
+//! The 16 bit timer extended to 64 bits, on a mock timer that moves on by a few
+//! ticks with every read of its counter, so a read can straddle an overflow.
+
+use keypad_core::time::{Counter16, WideCounter};
+use std::cell::Cell;
+
+struct Timer {
+    // ticks since the start, the counter shows the low 16 bits of them
+    ticks: Cell<u64>,
+    // how far a read of the counter moves the time on
+    step: u64,
+    // overflows the update interrupt took
+    handled: Cell<u64>,
+}
+
+impl Timer {
+    fn at(ticks: u64, step: u64) -> Self {
+        Self {
+            ticks: Cell::new(ticks),
+            step,
+            handled: Cell::new(ticks >> 16),
+        }
+    }
+
+    // the update interrupt
+    fn interrupt(&self, wide: &mut WideCounter) {
+        if self.overflow_pending() {
+            self.handled.set(self.handled.get() + 1);
+            wide.overflowed();
+        }
+    }
+}
+
+impl Counter16 for Timer {
+    fn count(&self) -> u16 {
+        let ticks = self.ticks.get();
+        self.ticks.set(ticks + self.step);
+        ticks as u16
+    }
+
+    fn overflow_pending(&self) -> bool {
+        self.ticks.get() >> 16 > self.handled.get()
+    }
+}
+
+// a counter that has the overflows of `timer` handled
+fn counter_of(timer: &Timer) -> WideCounter {
+    let mut wide = WideCounter::new();
+    for _ in 0..timer.handled.get() {
+        wide.overflowed();
+    }
+    wide
+}
+
+#[test]
+fn the_count_within_the_first_period() {
+    let timer = Timer::at(1234, 0);
+    assert_eq!(WideCounter::new().now(&timer), 1234);
+}
+
+#[test]
+fn every_overflow_adds_a_period() {
+    let timer = Timer::at(3 * 65_536 + 5, 0);
+    assert_eq!(counter_of(&timer).now(&timer), 3 * 65_536 + 5);
+}
+
+#[test]
+fn a_pending_overflow_counts_before_its_interrupt() {
+    let timer = Timer::at(65_530, 10);
+    let mut wide = counter_of(&timer);
+    // the first read takes the counter past the wrap
+    timer.count();
+    assert!(timer.overflow_pending());
+    // the count of the second read, the first one was at 65_540
+    assert_eq!(wide.now(&timer), 65_550);
+    timer.interrupt(&mut wide);
+    assert_eq!(wide.now(&timer), 65_560);
+}
+
+#[test]
+fn a_wrap_between_the_count_and_the_flag_reads_again() {
+    // the first read sees 0xffff, the flag is set by then
+    let timer = Timer::at(0xffff, 1);
+    let wide = counter_of(&timer);
+    assert_eq!(wide.now(&timer), 65_536);
+}
+
+#[test]
+fn reads_never_go_back_across_many_wraps() {
+    let timer = Timer::at(0, 7);
+    let mut wide = WideCounter::new();
+    let mut last = 0;
+    for read in 0..100_000 {
+        let now = wide.now(&timer);
+        assert!(now >= last, "read {read}: {now} after {last}");
+        assert!(now < timer.ticks.get());
+        last = now;
+        // the interrupt runs late, between every few reads
+        if read % 5 == 0 {
+            timer.interrupt(&mut wide);
+        }
+    }
+    assert!(last > 10 * 65_536);
+}
@@ -0,0 +1,95 @@
+//! The strikes of a force sensing resistor read every millisecond, soft and hard,
+//! and the velocity of each.
+
+use keypad_core::time;
+use keypad_core::velocity::{Strike, StrikeConfig, MAX_VELOCITY};
+
+const TICKS_PER_MS: u64 = (time::TICK_HZ / 1000) as u64;
+// 127 at 400 counts per millisecond
+const FULL_SLOPE: u32 = 400;
+const CONFIG: StrikeConfig = StrikeConfig {
+    onset: 300,
+    release: 200,
+    full_slope: FULL_SLOPE,
+};
+
+// one reading per millisecond from `start_ms`
+fn feed(strike: &mut Strike, start_ms: u64, readings: &[u16]) -> Vec<Option<u8>> {
+    readings
+        .iter()
+        .enumerate()
+        .map(|(ms, &value)| {
+            strike.update(&CONFIG, value, (start_ms + ms as u64) * TICKS_PER_MS);
+            strike.velocity()
+        })
+        .collect()
+}
+
+#[test]
+fn nothing_below_the_onset() {
+    let mut strike = Strike::new();
+    let velocities = feed(&mut strike, 100, &[0, 50, 299, 120, 0]);
+    assert!(velocities.iter().all(Option::is_none));
+}
+
+#[test]
+fn slope_from_the_last_idle_reading_to_the_peak() {
+    let mut strike = Strike::new();
+    // 200 counts a millisecond from 100 over 5 ms, held at the peak
+    let velocities = feed(
+        &mut strike,
+        100,
+        &[100, 300, 500, 700, 900, 1100, 1100, 1050],
+    );
+    let expected = (200 * u32::from(MAX_VELOCITY) / FULL_SLOPE) as u8;
+    assert_eq!(velocities.last(), Some(&Some(expected)));
+    // kept while the key is held
+    feed(&mut strike, 108, &[900, 400, 250]);
+    assert_eq!(strike.velocity(), Some(expected));
+}
+
+#[test]
+fn harder_strikes_are_faster() {
+    let velocity = |readings: &[u16]| {
+        let mut strike = Strike::new();
+        feed(&mut strike, 0, readings);
+        strike.velocity().unwrap()
+    };
+    let soft = velocity(&[0, 310, 400, 500, 600, 700, 700]);
+    let hard = velocity(&[0, 900, 1800, 2400, 2400]);
+    assert!(soft < hard, "{soft} {hard}");
+    assert_eq!(hard, MAX_VELOCITY);
+    // the slowest one still counts as a strike
+    assert_eq!(velocity(&[290, 300, 301, 302, 302]), 1);
+}
+
+#[test]
+fn slope_so_far_while_rising() {
+    let mut strike = Strike::new();
+    let velocities = feed(&mut strike, 0, &[0, 400, 800]);
+    assert_eq!(velocities[0], None);
+    assert_eq!(velocities[1], Some(MAX_VELOCITY));
+    assert_eq!(velocities[2], velocities[1]);
+}
+
+#[test]
+fn release_ends_the_strike() {
+    let mut strike = Strike::new();
+    feed(&mut strike, 0, &[0, 800, 1600, 1600]);
+    assert_eq!(strike.velocity(), Some(MAX_VELOCITY));
+    feed(&mut strike, 4, &[199]);
+    assert_eq!(strike.velocity(), None);
+    // the next strike starts from the reading the release left
+    let velocities = feed(&mut strike, 5, &[150, 350, 550, 550]);
+    assert_eq!(
+        velocities[3],
+        Some((200 * u32::from(MAX_VELOCITY) / FULL_SLOPE) as u8)
+    );
+}
+
+#[test]
+fn a_bounce_below_the_release_aborts_the_edge() {
+    let mut strike = Strike::new();
+    feed(&mut strike, 0, &[0, 350, 100]);
+    assert_eq!(strike.velocity(), None);
+}
@@ -0,0 +1,195 @@
+//! The row interrupt's wake merged into the scanner's first debounced frame, with
+//! the bounces of the waking edge and a second key pressed during the handover.
+
+use keypad_core::debounce::{KeyFilter, Thresholds, DEBOUNCE_THRESHOLD};
+use keypad_core::event::{EventKind, KeyEvent, KeyFrames};
+use keypad_core::keys::{key_bit, KeyState, COLUMNS, KEYS, ROWS};
+use keypad_core::time;
+use keypad_core::wake::{WakeMailbox, WokenKey};
+
+const TICKS_PER_MS: u64 = (time::TICK_HZ / 1000) as u64;
+
+/// The scanner resumed by a wake, a scan per millisecond from `now_ms`.
+struct Resumed {
+    filter: KeyFilter<COLUMNS, ROWS, KEYS>,
+    frames: KeyFrames,
+    now_ms: u32,
+}
+
+impl Resumed {
+    fn new(now_ms: u32) -> Self {
+        Self {
+            filter: KeyFilter::new(Thresholds::DEFAULT, 30_000),
+            frames: KeyFrames::new(),
+            now_ms,
+        }
+    }
+
+    // as the scanner merges the wake into its frames
+    fn scan(&mut self, raw: u32, mailbox: &mut WakeMailbox) -> Vec<KeyEvent> {
+        self.now_ms += 1;
+        let state = self.filter.update(raw, self.now_ms).state;
+        let changes = self.frames.push(state);
+        let woken = mailbox.merge(changes.0);
+        let at = ticks(self.now_ms);
+        self.frames
+            .edges(changes, at)
+            .map(|mut event| {
+                if let Some(woken) = woken {
+                    woken.backdate(&mut event);
+                }
+                event
+            })
+            .collect()
+    }
+
+    fn scans(&mut self, raws: &[u32], mailbox: &mut WakeMailbox) -> Vec<KeyEvent> {
+        raws.iter()
+            .flat_map(|&raw| self.scan(raw, mailbox))
+            .collect()
+    }
+}
+
+fn ticks(ms: u32) -> u64 {
+    u64::from(ms) * TICKS_PER_MS
+}
+
+fn presses(events: &[KeyEvent]) -> Vec<(u8, u8, u64)> {
+    events
+        .iter()
+        .filter(|event| event.kind == EventKind::Pressed)
+        .map(|event| (event.row, event.col, event.at))
+        .collect()
+}
+
+#[test]
+fn only_the_first_edge_is_kept() {
+    let mut mailbox = WakeMailbox::new();
+    assert!(mailbox.post(1, 100));
+    // its bounces, and another row
+    assert!(!mailbox.post(1, 120));
+    assert!(!mailbox.post(2, 130));
+    let woken = mailbox.merge(KeyState(key_bit(1, 3)));
+    assert_eq!(
+        woken,
+        Some(WokenKey {
+            index: COLUMNS + 3,
+            at: 100
+        })
+    );
+    // taken, the next wake goes in
+    assert_eq!(mailbox.merge(KeyState(key_bit(1, 2))), None);
+    assert!(mailbox.post(3, 200));
+}
+
+#[test]
+fn frames_without_presses_leave_the_wake() {
+    let mut mailbox = WakeMailbox::new();
+    mailbox.post(0, 100);
+    assert_eq!(mailbox.merge(KeyState(0)), None);
+    assert_eq!(
+        mailbox.merge(KeyState(key_bit(0, 1))),
+        Some(WokenKey { index: 1, at: 100 })
+    );
+}
+
+#[test]
+fn a_bounce_without_a_press_in_its_row_is_dropped() {
+    let mut mailbox = WakeMailbox::new();
+    mailbox.post(2, 100);
+    assert_eq!(mailbox.merge(KeyState(key_bit(0, 0))), None);
+    // a later press in the row keeps its scan
+    assert_eq!(mailbox.merge(KeyState(key_bit(2, 0))), None);
+
+    mailbox.post(2, 300);
+    mailbox.clear();
+    assert_eq!(mailbox.merge(KeyState(key_bit(2, 0))), None);
+}
+
+#[test]
+fn waking_press_gets_the_tick_of_its_edge() {
+    let key = key_bit(1, 2);
+    let mut mailbox = WakeMailbox::new();
+    mailbox.post(1, ticks(1000));
+    let mut scanner = Resumed::new(1000);
+    let mut events = scanner.scans(&[key, 0, key, key], &mut mailbox);
+    while events.is_empty() {
+        events = scanner.scan(key, &mut mailbox);
+    }
+    assert_eq!(presses(&events), [(1, 2, ticks(1000))]);
+    assert!(scanner.now_ms > 1000 + u32::from(DEBOUNCE_THRESHOLD));
+
+    // its release still comes from the scans
+    scanner.scans(&[key; 50], &mut mailbox);
+    let mut released = Vec::new();
+    while released.is_empty() {
+        released = scanner.scan(0, &mut mailbox);
+    }
+    assert!(matches!(released[0].kind, EventKind::Released { .. }));
+    assert_eq!(released[0].at, ticks(scanner.now_ms));
+}
+
+#[test]
+fn second_key_in_another_row_during_the_handover() {
+    let waking = key_bit(1, 2);
+    let second = key_bit(3, 0);
+    let mut mailbox = WakeMailbox::new();
+    mailbox.post(1, ticks(1000));
+    // the second key's edge comes with the lines masked already
+    assert!(!mailbox.post(3, ticks(1001)));
+    let mut scanner = Resumed::new(1000);
+    let mut events = Vec::new();
+    for _ in 0..DEBOUNCE_THRESHOLD + 2 {
+        events.extend(scanner.scan(waking | second, &mut mailbox));
+    }
+    let seen_at = events[0].at.max(events[1].at);
+    assert_eq!(presses(&events), [(1, 2, ticks(1000)), (3, 0, seen_at)]);
+    assert_eq!(seen_at, ticks(1000 + u32::from(DEBOUNCE_THRESHOLD)));
+}
+
+#[test]
+fn second_key_in_the_same_row_after_the_waking_one() {
+    let waking = key_bit(2, 1);
+    let second = key_bit(2, 3);
+    let mut mailbox = WakeMailbox::new();
+    mailbox.post(2, ticks(1000));
+    let mut scanner = Resumed::new(1000);
+    let mut events = scanner.scans(&[waking, waking], &mut mailbox);
+    for _ in 0..DEBOUNCE_THRESHOLD + 2 {
+        events.extend(scanner.scan(waking | second, &mut mailbox));
+    }
+    let pressed = presses(&events);
+    assert_eq!(pressed.len(), 2);
+    assert_eq!(pressed[0], (2, 1, ticks(1000)));
+    // debounced in a later frame, from its own scans
+    assert_eq!(pressed[1].1, 3);
+    assert!(pressed[1].2 > ticks(1000 + u32::from(DEBOUNCE_THRESHOLD)));
+}
+
+#[test]
+fn second_key_debounced_first_drops_the_wake() {
+    // the waking key bounces on, the second key is steady and debounces first
+    let waking = key_bit(0, 0);
+    let second = key_bit(3, 3);
+    let mut mailbox = WakeMailbox::new();
+    mailbox.post(0, ticks(1000));
+    let mut scanner = Resumed::new(1000);
+    let mut events = Vec::new();
+    for step in 0..DEBOUNCE_THRESHOLD + 2 {
+        let bouncing = if step % 2 == 0 { waking } else { 0 };
+        events.extend(scanner.scan(bouncing | second, &mut mailbox));
+    }
+    let mut pressed = presses(&events);
+    assert_eq!(pressed.len(), 1);
+    assert_eq!(pressed[0].0, 3);
+    assert_ne!(pressed[0].2, ticks(1000));
+    // the waking key settles, no wake is left to claim it
+    let mut more = Vec::new();
+    for _ in 0..DEBOUNCE_THRESHOLD + 1 {
+        more.extend(scanner.scan(waking | second, &mut mailbox));
+    }
+    pressed = presses(&more);
+    assert_eq!(pressed.len(), 1);
+    assert_eq!(pressed[0].0, 0);
+    assert!(pressed[0].2 > events[0].at);
+}
@@ -0,0 +1,85 @@
+//! Auto brightness of the status leds from an LDR, the `ambient` feature. The
+//! LDR sits between 3.3 V and PB1 (ADC1 channel 9) with a resistor from PB1 to
+//! ground, so more light reads higher. `event_stats` samples it once a second and
+//! slews the leds to the brightness the `keypad_core::ambient::Curve` of the
+//! settings gives the reading, over [`crate::leds::SLEW_MS`].
+//!
+//! `BRIGHTNESS 128` or the config mode sets a brightness by hand and turns the
+//! auto brightness off, `BRIGHTNESS AUTO` on again. `AMBIENT 1 2000 96` moves
+//! point 1 of the curve to a reading of 2000 at a brightness of 96, the raw
+//! reading for it is in `STATUS`. `SAVE` keeps both.
+
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal::adc::OneShot;
+use keypad_core::ambient::{Curve, POINTS};
+use stm32f1xx_hal::adc::Adc;
+use stm32f1xx_hal::gpio::{Analog, PB1};
+use stm32f1xx_hal::pac::ADC1;
+
+struct Ambient {
+    // taken by `start`
+    pin: Option<PB1<Analog>>,
+    auto: bool,
+    // the latest reading
+    raw: u16,
+    curve: Curve,
+}
+
+// all zero until `start`, so it takes no flash
+static AMBIENT: Mutex<RefCell<Ambient>> = Mutex::new(RefCell::new(Ambient {
+    pin: None,
+    auto: false,
+    raw: 0,
+    curve: Curve([(0, 0); POINTS]),
+}));
+
+/// Starts with the saved settings, the first sample is a second away.
+pub fn start(pin: PB1<Analog>, auto: bool, curve: Curve) {
+    interrupt::free(|cs| {
+        *AMBIENT.borrow(cs).borrow_mut() = Ambient {
+            pin: Some(pin),
+            auto,
+            raw: 0,
+            curve,
+        }
+    });
+}
+
+/// Reads the LDR, returns the brightness for the leds if it is up to the auto
+/// brightness.
+pub fn sample(adc: &mut Adc<ADC1>) -> Option<u8> {
+    interrupt::free(|cs| {
+        let ambient = &mut *AMBIENT.borrow(cs).borrow_mut();
+        let pin = ambient.pin.as_mut()?;
+        ambient.raw = adc.read(pin).unwrap_or(0);
+        ambient.auto.then(|| ambient.curve.brightness(ambient.raw))
+    })
+}
+
+pub fn raw() -> u16 {
+    interrupt::free(|cs| AMBIENT.borrow(cs).borrow().raw)
+}
+
+pub fn set_auto(auto: bool) {
+    interrupt::free(|cs| AMBIENT.borrow(cs).borrow_mut().auto = auto);
+}
+
+/// Moves a point of the curve, see `Curve::set`.
+pub fn set_point(index: usize, raw: u16, brightness: u8) -> bool {
+    interrupt::free(|cs| {
+        AMBIENT
+            .borrow(cs)
+            .borrow_mut()
+            .curve
+            .set(index, raw, brightness)
+    })
+}
+
+/// Whether the auto brightness is on and its curve, for `SAVE`.
+pub fn settings() -> (bool, Curve) {
+    interrupt::free(|cs| {
+        let ambient = AMBIENT.borrow(cs).borrow();
+        (ambient.auto, ambient.curve)
+    })
+}
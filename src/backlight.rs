@@ -0,0 +1,175 @@
+//! Per-key WS2812 backlight: a key lights up while it is held and fades out over
+//! [`FADE_MS`] after it is released.
+//!
+//! The LEDs are chained on SPI2 MOSI (PB15), SPI2 runs at 2.25 MHz and every
+//! WS2812 bit takes three SPI bits, `110` for one and `100` for zero. DMA1
+//! channel 5 feeds the whole frame, so no task can stretch the bit timing.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{COLUMNS, KEYS};
+#[cfg(feature = "backlight")]
+use stm32f1xx_hal::{
+    dma::{Transfer, WriteDma, R},
+    gpio::{Alternate, PushPull, PB15},
+    spi::{Master, NoMiso, NoSck, Spi2NoRemap, Spi2TxDma},
+};
+
+/// One LED per key, in `row * 4 + col` order along the chain.
+pub const LEDS: usize = KEYS;
+
+pub const FADE_MS: u32 = 300;
+
+/// Colour of a held key.
+pub const PRESSED: Rgb = Rgb { r: 0, g: 48, b: 96 };
+
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const OFF: Self = Self { r: 0, g: 0, b: 0 };
+
+    // `level` out of `FADE_MS`
+    fn scaled(self, level: u32) -> Self {
+        let scale = |channel: u8| (channel as u32 * level / FADE_MS) as u8;
+        Self {
+            r: scale(self.r),
+            g: scale(self.g),
+            b: scale(self.b),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Pixel {
+    colour: Rgb,
+    // `None` while the key is held or the pixel is off
+    released_at: Option<u32>,
+}
+
+/// Framebuffer of the backlight, the key consumer sets the colours and the frame
+/// task renders the fades.
+pub struct Backlight {
+    pixels: [Pixel; LEDS],
+}
+
+impl Backlight {
+    pub const fn new() -> Self {
+        Self {
+            pixels: [Pixel {
+                colour: Rgb::OFF,
+                released_at: None,
+            }; LEDS],
+        }
+    }
+
+    pub fn record(&mut self, event: &KeyEvent, now: u32) {
+        let pixel = &mut self.pixels[event.row as usize * COLUMNS + event.col as usize];
+        match event.kind {
+            EventKind::Pressed => {
+                pixel.colour = PRESSED;
+                pixel.released_at = None;
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => {
+                pixel.released_at = Some(now)
+            }
+            _ => {}
+        }
+    }
+
+    /// The colours to show at `now`, pixels that finished fading are turned off.
+    pub fn frame(&mut self, now: u32) -> [Rgb; LEDS] {
+        let mut frame = [Rgb::OFF; LEDS];
+        for (pixel, out) in self.pixels.iter_mut().zip(frame.iter_mut()) {
+            *out = match pixel.released_at {
+                None => pixel.colour,
+                Some(since) => {
+                    let elapsed = now.wrapping_sub(since);
+                    if elapsed >= FADE_MS {
+                        pixel.colour = Rgb::OFF;
+                        pixel.released_at = None;
+                        Rgb::OFF
+                    } else {
+                        pixel.colour.scaled(FADE_MS - elapsed)
+                    }
+                }
+            };
+        }
+        frame
+    }
+}
+
+// three SPI bytes per colour byte, three colour bytes per LED
+#[cfg(feature = "backlight")]
+pub const ENCODED_SIZE: usize = LEDS * 3 * 3;
+
+#[cfg(feature = "backlight")]
+pub type Encoded = [u8; ENCODED_SIZE];
+
+#[cfg(feature = "backlight")]
+pub type SpiDma = Spi2TxDma<Spi2NoRemap, (NoSck, NoMiso, PB15<Alternate<PushPull>>), Master>;
+
+#[cfg(feature = "backlight")]
+enum State {
+    Idle(SpiDma, &'static mut Encoded),
+    Busy(Transfer<R, &'static mut Encoded, SpiDma>),
+}
+
+/// The WS2812 chain. MOSI idles low between frames, which doubles as the reset.
+#[cfg(feature = "backlight")]
+pub struct Strip {
+    state: Option<State>,
+}
+
+#[cfg(feature = "backlight")]
+impl Strip {
+    pub fn new(spi: SpiDma, buffer: &'static mut Encoded) -> Self {
+        Self {
+            state: Some(State::Idle(spi, buffer)),
+        }
+    }
+
+    /// Starts sending `frame`, skipped if the previous frame is still going out.
+    pub fn write(&mut self, frame: &[Rgb; LEDS]) {
+        let (spi, buffer) = match self.state.take() {
+            Some(State::Busy(transfer)) if !transfer.is_done() => {
+                self.state = Some(State::Busy(transfer));
+                return;
+            }
+            Some(State::Busy(transfer)) => {
+                let (buffer, spi) = transfer.wait();
+                (spi, buffer)
+            }
+            Some(State::Idle(spi, buffer)) => (spi, buffer),
+            None => return,
+        };
+        encode(frame, buffer);
+        self.state = Some(State::Busy(spi.write(buffer)));
+    }
+}
+
+// WS2812 expects green, red, blue with the most significant bit first
+#[cfg(feature = "backlight")]
+fn encode(frame: &[Rgb; LEDS], buffer: &mut Encoded) {
+    let bytes = frame.iter().flat_map(|rgb| [rgb.g, rgb.r, rgb.b]);
+    for (byte, out) in bytes.zip(buffer.chunks_exact_mut(3)) {
+        let mut bits = 0u32;
+        for bit in (0..8).rev() {
+            let pattern = if byte & (1 << bit) != 0 { 0b110 } else { 0b100 };
+            bits = bits << 3 | pattern;
+        }
+        out.copy_from_slice(&bits.to_be_bytes()[1..]);
+    }
+}
+
+/// Stand-in without the `backlight` feature, frames go nowhere.
+#[cfg(not(feature = "backlight"))]
+pub struct Strip;
+
+#[cfg(not(feature = "backlight"))]
+impl Strip {
+    pub fn write(&mut self, _frame: &[Rgb; LEDS]) {}
+}
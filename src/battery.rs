@@ -0,0 +1,116 @@
+//! LiPo voltage through a resistor divider on PB1 (ADC1 channel 9).
+//!
+//! Below [`WARNING_MV`] the red led blinks slowly, it only stops once the
+//! battery recovers past the threshold plus [`HYSTERESIS_MV`]. Below
+//! [`CRITICAL_MV`] the firmware logs a last line and goes into standby, which
+//! only a reset leaves.
+
+#[cfg(feature = "battery")]
+use embedded_hal::adc::OneShot;
+use stm32f1xx_hal::adc::Adc;
+#[cfg(feature = "battery")]
+use stm32f1xx_hal::gpio::{Analog, PB1};
+use stm32f1xx_hal::pac::ADC1;
+#[cfg(feature = "battery")]
+use stm32f1xx_hal::pac::{PWR, RCC};
+
+// divider between the battery and PB1
+#[cfg(feature = "battery")]
+pub const R_TOP_OHMS: u32 = 100_000;
+#[cfg(feature = "battery")]
+pub const R_BOTTOM_OHMS: u32 = 100_000;
+
+pub const WARNING_MV: u16 = 3500;
+pub const CRITICAL_MV: u16 = 3300;
+pub const HYSTERESIS_MV: u16 = 100;
+
+// used until the first VREFINT reading is in
+#[cfg(feature = "battery")]
+const NOMINAL_VDDA_MV: u32 = 3300;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    Ok,
+    Low,
+    Critical,
+}
+
+/// Latest battery reading.
+#[derive(Copy, Clone)]
+pub struct Battery {
+    pub mv: u16,
+    pub level: Level,
+}
+
+impl Battery {
+    pub const fn new() -> Self {
+        Self {
+            mv: 0,
+            level: Level::Ok,
+        }
+    }
+
+    /// Records a reading and returns the new level if it changed.
+    pub fn update(&mut self, mv: u16) -> Option<Level> {
+        self.mv = mv;
+        let level = match self.level {
+            // there is no way back once the halt started
+            Level::Critical => Level::Critical,
+            _ if mv < CRITICAL_MV => Level::Critical,
+            Level::Low if mv > WARNING_MV + HYSTERESIS_MV => Level::Ok,
+            Level::Ok if mv < WARNING_MV => Level::Low,
+            level => level,
+        };
+        let changed = level != self.level;
+        self.level = level;
+        changed.then_some(level)
+    }
+}
+
+#[cfg(feature = "battery")]
+pub struct BatteryPin(pub PB1<Analog>);
+
+#[cfg(feature = "battery")]
+impl BatteryPin {
+    /// Battery voltage in millivolts, `vdda_mv` is 0 when it isn't known yet.
+    pub fn sample(&mut self, adc: &mut Adc<ADC1>, vdda_mv: u16) -> u16 {
+        let raw: u16 = adc.read(&mut self.0).unwrap_or(0);
+        let vdda_mv = match vdda_mv {
+            0 => NOMINAL_VDDA_MV,
+            mv => mv as u32,
+        };
+        let pin_mv = raw as u32 * vdda_mv / 4095;
+        (pin_mv * (R_TOP_OHMS + R_BOTTOM_OHMS) / R_BOTTOM_OHMS) as u16
+    }
+}
+
+/// Stand-in without the `battery` feature, the monitor is never started.
+#[cfg(not(feature = "battery"))]
+pub struct BatteryPin;
+
+#[cfg(not(feature = "battery"))]
+impl BatteryPin {
+    pub fn sample(&mut self, _adc: &mut Adc<ADC1>, _vdda_mv: u16) -> u16 {
+        0
+    }
+}
+
+/// Enters standby with everything off, only a reset wakes the chip again. The
+/// IWDG can't be stopped and keeps running in standby, so its reset ends the
+/// standby after `watchdog::TIMEOUT_MS` and the next battery check halts again.
+#[cfg(feature = "battery")]
+pub fn standby() -> ! {
+    cortex_m::interrupt::disable();
+    unsafe {
+        (*RCC::ptr()).apb1enr.modify(|_, w| w.pwren().set_bit());
+        (*PWR::ptr())
+            .cr
+            .modify(|_, w| w.pdds().set_bit().cwuf().set_bit());
+        (*cortex_m::peripheral::SCB::PTR)
+            .scr
+            .modify(|scr| scr | 1 << 2);
+    }
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,65 @@
+//! Bindings of single keys at the output, the `bind` feature. `BIND 1 2 HID:0X1E`
+//! sends the key on row 1, column 2 to the USB keyboard as that usage whatever
+//! the output mode, `MIDI:60` plays that note on the MIDI out, `MACRO:2` plays the
+//! macro of slot 2, `NONE` keeps it from every backend and `KEY` puts it back on
+//! its keymap character. `BIND SHOW` lists the keys bound, `SAVE` keeps them.
+//!
+//! The log, the stats and the leds see a bound key as usual, a key bound to a
+//! macro produces no events of its own like the macro keys.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{COLUMNS, KEYS};
+#[cfg(feature = "macros")]
+use crate::macros;
+use core::cell::Cell;
+use cortex_m::interrupt::{self, Mutex};
+use keypad_core::binding::Binding;
+
+// `Key` throughout, so it takes no flash
+static TABLE: Mutex<Cell<[Binding; KEYS]>> = Mutex::new(Cell::new([Binding::Key; KEYS]));
+
+/// Starts with the saved table.
+pub fn start(table: [Binding; KEYS]) {
+    interrupt::free(|cs| TABLE.borrow(cs).set(table));
+}
+
+pub fn table() -> [Binding; KEYS] {
+    interrupt::free(|cs| TABLE.borrow(cs).get())
+}
+
+pub fn set(index: usize, binding: Binding) {
+    interrupt::free(|cs| {
+        let table = TABLE.borrow(cs);
+        let mut bindings = table.get();
+        bindings[index] = binding;
+        table.set(bindings);
+    });
+}
+
+/// Whether the backend of `binding` is built in, the parser takes them all.
+pub fn is_built_in(binding: Binding) -> bool {
+    match binding {
+        Binding::Midi(_) => cfg!(feature = "midi"),
+        #[cfg(feature = "macros")]
+        Binding::Macro(slot) => usize::from(slot) < macros::SLOTS,
+        #[cfg(not(feature = "macros"))]
+        Binding::Macro(_) => false,
+        Binding::Key | Binding::Hid(_) | Binding::None => true,
+    }
+}
+
+/// The binding of the key of `event`, `Key` for the events of no single key.
+pub fn of(event: &KeyEvent) -> Binding {
+    match event.kind {
+        EventKind::Pressed
+        | EventKind::Released { .. }
+        | EventKind::LongPressed
+        | EventKind::ReleasedAfterLong
+        | EventKind::DoubleTap
+        | EventKind::Repeat => {
+            let index = usize::from(event.row) * COLUMNS + usize::from(event.col);
+            interrupt::free(|cs| TABLE.borrow(cs).get()[index])
+        }
+        _ => Binding::Key,
+    }
+}
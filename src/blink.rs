@@ -0,0 +1,81 @@
+//! The body of `blinker`, the blink chain of the red led. It alternates two
+//! phases a [`timing::BLINK_PHASE`] apart: one counts the blink, the other logs
+//! the count with the diagnostics. Every run toggles the led through
+//! `led_controller` and sets its bit in the alive mask of `crate::watchdog`.
+//!
+//! The emergency stop cancels the pending run, see `crate::emergency`.
+
+use crate::app::{blinker, monotonics, send_led_message};
+use crate::led_mode::LedMessage;
+use crate::monotonic;
+use crate::sensors::Celsius;
+use crate::spawn::Counted;
+use crate::timing;
+use crate::watchdog;
+use rtic::Mutex;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlinkPhase {
+    // counts the blink
+    Count,
+    // logs the count and the diagnostics
+    Report,
+}
+
+impl BlinkPhase {
+    fn next(self) -> Self {
+        match self {
+            BlinkPhase::Count => BlinkPhase::Report,
+            BlinkPhase::Report => BlinkPhase::Count,
+        }
+    }
+}
+
+/// Runs `phase` and schedules the next one from `deadline`, so the period doesn't
+/// drift with their latency.
+pub fn blinker(mut ctx: blinker::Context, phase: BlinkPhase, deadline: monotonic::Instant) {
+    ctx.shared.blink_handle.lock(|handle| *handle = None);
+    ctx.shared.alive.lock(|alive| *alive |= watchdog::BLINK);
+    // already dispatched when the emergency stopped the blink, `emergency_release`
+    // starts it again
+    if ctx.shared.emergency.lock(|emergency| *emergency) {
+        return;
+    }
+    match phase {
+        BlinkPhase::Count => {
+            log!("red led blink");
+            ctx.shared.counter.lock(|counter| *counter += 1);
+        }
+        BlinkPhase::Report => {
+            let counter = ctx.shared.counter.lock(|counter| *counter);
+            let diagnostics = ctx.shared.diagnostics.lock(|diagnostics| *diagnostics);
+            log!(
+                "red led blinks: {}, mcu {} C, vdda {} mV",
+                counter,
+                Celsius(diagnostics.temperature_tenths),
+                u32::from(diagnostics.vdda_mv)
+            );
+        }
+    }
+    send_led_message(LedMessage::Blink);
+
+    // a run late by more than a phase starts over from now instead of catching up
+    let next = (deadline + timing::BLINK_PHASE).max(monotonics::now());
+    // only fails with a run already pending, which carries on the blink
+    let handle = blinker::spawn_at(next, phase.next(), next).ok();
+    ctx.shared.blink_handle.lock(|pending| *pending = handle);
+}
+
+/// A run that is already dispatched sees the emergency latch and stops as well.
+pub fn stop(handle: &mut Option<blinker::SpawnHandle>) {
+    if let Some(handle) = handle.take() {
+        let _ = handle.cancel();
+    }
+}
+
+/// Over from the first phase.
+pub fn start(handle: &mut Option<blinker::SpawnHandle>) {
+    stop(handle);
+    // a run that is still pending carries on the blink just as well
+    blinker::spawn(BlinkPhase::Count, monotonics::now()).or_count();
+}
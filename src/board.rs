@@ -0,0 +1,252 @@
+//! The bluepill and what is wired to it: the clock tree, and every pin in the mode
+//! its job needs. The plain GPIO jobs come out as erased pins with their
+//! interrupts set up, the pins of the peripherals typed as their drivers take
+//! them. `init` builds the drivers and the resources from a [`Board`], the
+//! hardware tests the parts they check.
+//!
+//! Where the matrix, the leds and the buttons sit depends on the PCB revision,
+//! the `board-rev-a` or `board-rev-b` feature picks its pin mapping. Both build
+//! the same [`Board`], anything past `Board::new` doesn't know which one it got,
+//! but for the EXTI lines the tasks in `main` are bound to.
+
+#[cfg(not(any(feature = "board-rev-a", feature = "board-rev-b")))]
+compile_error!("pick the pin mapping of the PCB with `board-rev-a` or `board-rev-b`");
+#[cfg(all(feature = "board-rev-a", feature = "board-rev-b"))]
+compile_error!("the `board-rev-a` and `board-rev-b` features are two pin mappings, enable one");
+
+#[cfg(feature = "board-rev-a")]
+mod rev_a;
+#[cfg(all(feature = "board-rev-b", not(feature = "board-rev-a")))]
+mod rev_b;
+
+#[cfg(all(feature = "board-rev-a", feature = "relays"))]
+pub use rev_a::RELAY_PINS;
+#[cfg(feature = "board-rev-a")]
+pub use rev_a::{panic_led, WAKE_LINES};
+#[cfg(all(
+    feature = "board-rev-b",
+    not(feature = "board-rev-a"),
+    feature = "relays"
+))]
+pub use rev_b::RELAY_PINS;
+#[cfg(all(feature = "board-rev-b", not(feature = "board-rev-a")))]
+pub use rev_b::{panic_led, WAKE_LINES};
+
+use crate::delay::Delay;
+#[cfg(any(not(feature = "mcp23017"), feature = "lock-pins"))]
+use crate::keypad;
+#[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+use crate::keypad::COLUMNS;
+#[cfg(not(feature = "mcp23017"))]
+use crate::keypad::{RowInput, ROWS};
+use core::sync::atomic::{AtomicBool, Ordering};
+use stm32f1xx_hal::flash::ACR;
+use stm32f1xx_hal::gpio::*;
+use stm32f1xx_hal::prelude::*;
+use stm32f1xx_hal::rcc::{Clocks, CFGR};
+
+#[cfg(not(any(feature = "active-low", feature = "mcp23017")))]
+pub type Wiring = keypad::ActiveHigh;
+#[cfg(all(feature = "active-low", not(feature = "mcp23017")))]
+pub type Wiring = keypad::ActiveLow;
+#[cfg(not(any(
+    feature = "open-drain",
+    feature = "shift-register",
+    feature = "mcp23017"
+)))]
+pub type ColumnOutput = PushPull;
+#[cfg(feature = "open-drain")]
+pub type ColumnOutput = OpenDrain;
+
+pub const FULL_SYSCLK_HZ: u32 = 72_000_000;
+pub const LOW_SYSCLK_HZ: u32 = 8_000_000;
+// the WWDG timings are derived from it
+pub const PCLK1_HZ: u32 = 36_000_000;
+
+// set while running from HSI
+static LOW: AtomicBool = AtomicBool::new(false);
+
+/// Full speed on the PLL, or low speed on the HSI alone, see `clock_manager`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Speed {
+    Full,
+    Low,
+}
+
+impl Speed {
+    pub fn sysclk_hz(self) -> u32 {
+        match self {
+            Speed::Full => FULL_SYSCLK_HZ,
+            Speed::Low => LOW_SYSCLK_HZ,
+        }
+    }
+}
+
+pub fn speed() -> Speed {
+    if LOW.load(Ordering::Relaxed) {
+        Speed::Low
+    } else {
+        Speed::Full
+    }
+}
+
+/// Notes a switch of the system clock, for whatever counts cycles.
+pub fn set_speed(speed: Speed) {
+    LOW.store(speed == Speed::Low, Ordering::Relaxed);
+}
+
+/// 72 MHz from the 8 MHz crystal. `freeze` also sets the two flash wait states
+/// above 48 MHz.
+pub fn clocks(cfgr: CFGR, acr: &mut ACR) -> Clocks {
+    cfgr.use_hse(8.MHz())
+        .sysclk(FULL_SYSCLK_HZ.Hz())
+        .pclk1(PCLK1_HZ.Hz())
+        .pclk2(FULL_SYSCLK_HZ.Hz())
+        .freeze(acr)
+}
+
+/// The USB peripheral needs its 48 MHz clock derived from the PLL, the WWDG
+/// timings and the clock manager are derived from the other two.
+pub fn clocks_valid(clocks: &Clocks) -> bool {
+    clocks.usbclk_valid()
+        && clocks.pclk1().raw() == PCLK1_HZ
+        && clocks.sysclk().raw() == FULL_SYSCLK_HZ
+}
+
+/// Locks the CNF/MODE bits of the `pins` of a port, by its [`PinExt::port_id`],
+/// until the next reset: the LCKK sequence of the reference manual, set, clear,
+/// set and two reads. Returns whether the lock took.
+#[cfg(feature = "lock-pins")]
+pub fn lock_pins(port_id: u8, pins: u16) -> bool {
+    // SAFETY: LCKR locks the configuration alone, the levels stay free
+    let gpio = unsafe { &*keypad::port(port_id) };
+    let key = 1 << 16 | u32::from(pins);
+    gpio.lckr.write(|w| unsafe { w.bits(key) });
+    gpio.lckr.write(|w| unsafe { w.bits(u32::from(pins)) });
+    gpio.lckr.write(|w| unsafe { w.bits(key) });
+    let _ = gpio.lckr.read();
+    gpio.lckr.read().lckk().bit_is_set()
+}
+
+/// Every pin in use, set up by `Board::new` of the pin mapping.
+pub struct Board {
+    pub led_red: ErasedPin<Output>,
+    pub led_blue: ErasedPin<Output>,
+    // starts out dark
+    pub led_green: ErasedPin<Output>,
+    // its EXTI line is left to the emergency stop
+    pub emergency_button: ErasedPin<Input<PullUp>>,
+    // both phases interrupt on every edge
+    pub encoder_a: ErasedPin<Input<PullUp>>,
+    pub encoder_b: ErasedPin<Input<PullUp>>,
+    // scanned, its falling edge wakes the waiting scanner like the rows
+    pub encoder_button: ErasedPin<Input<PullUp>>,
+    #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+    pub columns: [ErasedPin<Output<ColumnOutput>>; COLUMNS],
+    // SPI1 remapped, PB3 is a JTAG pin, SWD keeps working without it
+    #[cfg(feature = "shift-register")]
+    pub shift_register: (PB3<Alternate<PushPull>>, PB5<Alternate<PushPull>>),
+    #[cfg(feature = "shift-register")]
+    pub latch: ErasedPin<Output>,
+    // the lines stay masked until the scanner goes to sleep
+    #[cfg(not(feature = "mcp23017"))]
+    pub rows: [ErasedPin<Input<<Wiring as RowInput>::Pull>>; ROWS],
+    #[cfg(any(feature = "mcp23017", feature = "i2c-slave"))]
+    pub i2c1: (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>),
+    // signals pending events to the I2C master
+    #[cfg(feature = "i2c-slave")]
+    pub data_ready: ErasedPin<Output>,
+    #[cfg(feature = "joystick")]
+    pub joystick: (PA0<Analog>, PA1<Analog>),
+    #[cfg(feature = "velocity")]
+    pub velocity: (PA0<Analog>, PA1<Analog>, PA2<Analog>, PA3<Analog>),
+    #[cfg(feature = "battery")]
+    pub battery: PB1<Analog>,
+    #[cfg(feature = "ambient")]
+    pub ambient: PB1<Analog>,
+    #[cfg(feature = "buzzer")]
+    pub buzzer: PB1<Alternate<PushPull>>,
+    #[cfg(feature = "backlight")]
+    pub backlight: PB15<Alternate<PushPull>>,
+    #[cfg(feature = "display")]
+    pub i2c2: (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>),
+    // RS, E and D4-D7, PB3 and PB4 are JTAG pins
+    #[cfg(feature = "lcd")]
+    pub lcd: (ErasedPin<Output>, ErasedPin<Output>, [ErasedPin<Output>; 4]),
+    // clock and data, open-drain on the host's pull-ups
+    #[cfg(feature = "ps2")]
+    pub ps2: (ErasedPin<Output<OpenDrain>>, ErasedPin<Output<OpenDrain>>),
+    // channel 1 and 2 of TIM3
+    #[cfg(feature = "servo")]
+    pub servo: [ErasedPin<Alternate<PushPull>>; 2],
+    // relay 1 to 4, see `crate::relays`
+    #[cfg(feature = "relays")]
+    pub relays: [ErasedPin<Output>; 4],
+    pub usart1: (PA9<Alternate<PushPull>>, PA10),
+    pub usb: (PA11, PA12),
+}
+
+impl Board {
+    /// The plain GPIO pins of ports A to C for [`lock_pins`]: the columns, the
+    /// rows, the leds and the buttons.
+    #[cfg(feature = "lock-pins")]
+    pub fn gpio_pins(&self) -> [u16; 3] {
+        let mut ports = [0; 3];
+        let mut add = |port: u8, pin: u8| ports[usize::from(port)] |= 1 << pin;
+        for pin in [&self.led_red, &self.led_blue, &self.led_green] {
+            add(pin.port_id(), pin.pin_id());
+        }
+        let buttons = [
+            &self.emergency_button,
+            &self.encoder_a,
+            &self.encoder_b,
+            &self.encoder_button,
+        ];
+        for pin in buttons {
+            add(pin.port_id(), pin.pin_id());
+        }
+        #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+        for pin in &self.columns {
+            add(pin.port_id(), pin.pin_id());
+        }
+        #[cfg(not(feature = "mcp23017"))]
+        for pin in &self.rows {
+            add(pin.port_id(), pin.pin_id());
+        }
+        ports
+    }
+}
+
+/// Configures `pin` as a column of the second pad, in the mode of the others. Both
+/// revisions have them on GPIOB.
+#[cfg(all(feature = "second-pad", not(feature = "shift-register")))]
+fn column<const N: u8>(
+    pin: Pin<'B', N>,
+    cr: &mut <Pin<'B', N> as HL>::Cr,
+) -> ErasedPin<Output<ColumnOutput>>
+where
+    Pin<'B', N>: HL,
+{
+    #[cfg(not(feature = "open-drain"))]
+    let pin = pin.into_push_pull_output(cr);
+    #[cfg(feature = "open-drain")]
+    let pin = pin.into_open_drain_output(cr);
+    pin.erase()
+}
+
+// the columns of the first pad, then the ones of the second
+#[cfg(all(feature = "second-pad", not(feature = "shift-register")))]
+fn join<T>(first: [T; 4], second: [T; 4]) -> [T; COLUMNS] {
+    let [a, b, c, d] = first;
+    let [e, f, g, h] = second;
+    [a, b, c, d, e, f, g, h]
+}
+
+// the bluepill has a fixed pull-up on D+, so pull the line low for a moment to
+// make the host notice a reset
+fn usb(pa11: PA11, pa12: PA12, crh: &mut Cr<'A', true>, delay: &mut Delay) -> (PA11, PA12) {
+    let mut dp = pa12.into_push_pull_output(crh);
+    dp.set_low();
+    delay.delay_ms(10u32);
+    (pa11, dp.into_floating_input(crh))
+}
@@ -0,0 +1,276 @@
+//! Rev A: the matrix on PA0-PA7, columns first, the leds on PB12-PB14, the
+//! emergency button on PB0 and the encoder on PC14/PC15 with its switch on PA8.
+//! The rows raise EXTI4 and EXTI9_5, the encoder EXTI15_10 and the emergency
+//! button EXTI0.
+//!
+//! The servos of `servo` take PB4 and PB5, what `shift-register` and `lcd` use.
+//! The columns of `second-pad` go on PB8-PB11, and so do the outputs of
+//! `relays`.
+
+#[cfg(all(feature = "servo", any(feature = "shift-register", feature = "lcd")))]
+compile_error!("the `servo` feature needs PB4 and PB5, which `shift-register` and `lcd` use");
+
+#[cfg(all(
+    feature = "velocity",
+    not(any(feature = "shift-register", feature = "mcp23017"))
+))]
+compile_error!(
+    "the `velocity` feature needs PA0-PA3, move the columns with `shift-register` or `mcp23017`"
+);
+
+#[cfg(all(
+    feature = "joystick",
+    not(any(feature = "shift-register", feature = "mcp23017"))
+))]
+compile_error!(
+    "the `joystick` feature needs PA0 and PA1, move the columns with `shift-register` or `mcp23017`"
+);
+
+#[cfg(all(
+    feature = "second-pad",
+    not(feature = "shift-register"),
+    any(feature = "display", feature = "lcd", feature = "ps2")
+))]
+compile_error!(
+    "the `second-pad` feature needs PB8-PB11, which `display`, `lcd` and `ps2` use, or `shift-register`"
+);
+
+#[cfg(all(
+    feature = "relays",
+    any(
+        feature = "second-pad",
+        feature = "shift-register",
+        feature = "display",
+        feature = "lcd",
+        feature = "ps2"
+    )
+))]
+compile_error!(
+    "the `relays` feature needs PB8-PB11, which `second-pad`, `shift-register`, `display`, `lcd` and `ps2` use"
+);
+
+use super::Board;
+#[cfg(not(feature = "mcp23017"))]
+use super::Wiring;
+use crate::delay::Delay;
+#[cfg(not(feature = "mcp23017"))]
+use crate::keypad::RowInput;
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::*;
+use stm32f1xx_hal::pac::{EXTI, GPIOA, GPIOB, GPIOC, RCC};
+
+/// EXTI lines of the rows on PA4-PA7 and of the encoder switch on PA8.
+pub const WAKE_LINES: u32 = 0b11111 << 4;
+
+/// The port B pins of relay 1 to 4.
+#[cfg(feature = "relays")]
+pub const RELAY_PINS: [u8; 4] = [8, 9, 10, 11];
+
+/// Sets up the red led on PB12 from the registers alone and returns its switch,
+/// for blinking after a panic.
+///
+/// # Safety
+///
+/// Takes the pin from whatever owns it, only for when nothing else runs.
+pub unsafe fn panic_led() -> impl Fn(bool) {
+    (*RCC::ptr()).apb2enr.modify(|_, w| w.iopben().set_bit());
+    let gpio = &*GPIOB::ptr();
+    gpio.crh
+        .modify(|_, w| w.mode12().output2().cnf12().push_pull());
+    move |on| {
+        gpio.bsrr.write(|w| {
+            if on {
+                w.bs12().set_bit()
+            } else {
+                w.br12().set_bit()
+            }
+        })
+    }
+}
+
+impl Board {
+    /// Sets up the pins, the EXTI lines of the rows and of the encoder are
+    /// configured but for the rows not unmasked. `delay` times the USB reset.
+    pub fn new(
+        gpioa: GPIOA,
+        gpiob: GPIOB,
+        gpioc: GPIOC,
+        afio: &mut afio::Parts,
+        exti: &mut EXTI,
+        delay: &mut Delay,
+    ) -> Self {
+        let mut gpio_a = gpioa.split();
+        let mut gpio_b = gpiob.split();
+        let mut gpio_c = gpioc.split();
+
+        let led_red = gpio_b.pb12.into_push_pull_output(&mut gpio_b.crh);
+        let led_blue = gpio_b.pb14.into_push_pull_output(&mut gpio_b.crh);
+        let led_green = gpio_b
+            .pb13
+            .into_push_pull_output_with_state(&mut gpio_b.crh, PinState::Low);
+        let mut emergency_button = gpio_b.pb0.into_pull_up_input(&mut gpio_b.crl);
+        emergency_button.make_interrupt_source(afio);
+
+        #[cfg(not(any(
+            feature = "open-drain",
+            feature = "shift-register",
+            feature = "mcp23017"
+        )))]
+        let columns = [
+            gpio_a.pa0.into_push_pull_output(&mut gpio_a.crl).erase(),
+            gpio_a.pa1.into_push_pull_output(&mut gpio_a.crl).erase(),
+            gpio_a.pa2.into_push_pull_output(&mut gpio_a.crl).erase(),
+            gpio_a.pa3.into_push_pull_output(&mut gpio_a.crl).erase(),
+        ];
+        #[cfg(feature = "open-drain")]
+        let columns = [
+            gpio_a.pa0.into_open_drain_output(&mut gpio_a.crl).erase(),
+            gpio_a.pa1.into_open_drain_output(&mut gpio_a.crl).erase(),
+            gpio_a.pa2.into_open_drain_output(&mut gpio_a.crl).erase(),
+            gpio_a.pa3.into_open_drain_output(&mut gpio_a.crl).erase(),
+        ];
+        #[cfg(all(feature = "second-pad", not(feature = "shift-register")))]
+        let columns = super::join(
+            columns,
+            [
+                super::column(gpio_b.pb8, &mut gpio_b.crh),
+                super::column(gpio_b.pb9, &mut gpio_b.crh),
+                super::column(gpio_b.pb10, &mut gpio_b.crh),
+                super::column(gpio_b.pb11, &mut gpio_b.crh),
+            ],
+        );
+        #[cfg(feature = "shift-register")]
+        let (shift_register, latch) = {
+            let (_pa15, pb3, _pb4) = afio.mapr.disable_jtag(gpio_a.pa15, gpio_b.pb3, gpio_b.pb4);
+            (
+                (
+                    pb3.into_alternate_push_pull(&mut gpio_b.crl),
+                    gpio_b.pb5.into_alternate_push_pull(&mut gpio_b.crl),
+                ),
+                gpio_b.pb8.into_push_pull_output(&mut gpio_b.crh).erase(),
+            )
+        };
+
+        #[cfg(not(feature = "mcp23017"))]
+        let rows = {
+            let mut rows = [
+                Wiring::row(gpio_a.pa4, &mut gpio_a.crl).erase(),
+                Wiring::row(gpio_a.pa5, &mut gpio_a.crl).erase(),
+                Wiring::row(gpio_a.pa6, &mut gpio_a.crl).erase(),
+                Wiring::row(gpio_a.pa7, &mut gpio_a.crl).erase(),
+            ];
+            for row in rows.iter_mut() {
+                row.make_interrupt_source(afio);
+                row.trigger_on_edge(exti, <Wiring as RowInput>::PRESS_EDGE);
+            }
+            rows
+        };
+
+        let mut encoder_a = gpio_c.pc14.into_pull_up_input(&mut gpio_c.crh);
+        let mut encoder_b = gpio_c.pc15.into_pull_up_input(&mut gpio_c.crh);
+        encoder_a.make_interrupt_source(afio);
+        encoder_a.trigger_on_edge(exti, Edge::RisingFalling);
+        encoder_a.enable_interrupt(exti);
+        encoder_b.make_interrupt_source(afio);
+        encoder_b.trigger_on_edge(exti, Edge::RisingFalling);
+        encoder_b.enable_interrupt(exti);
+        let mut encoder_button = gpio_a.pa8.into_pull_up_input(&mut gpio_a.crh);
+        encoder_button.make_interrupt_source(afio);
+        encoder_button.trigger_on_edge(exti, Edge::Falling);
+
+        let usb = super::usb(gpio_a.pa11, gpio_a.pa12, &mut gpio_a.crh, delay);
+
+        Self {
+            led_red: led_red.erase(),
+            led_blue: led_blue.erase(),
+            led_green: led_green.erase(),
+            emergency_button: emergency_button.erase(),
+            encoder_a: encoder_a.erase(),
+            encoder_b: encoder_b.erase(),
+            encoder_button: encoder_button.erase(),
+            #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+            columns,
+            #[cfg(feature = "shift-register")]
+            shift_register,
+            #[cfg(feature = "shift-register")]
+            latch,
+            #[cfg(not(feature = "mcp23017"))]
+            rows,
+            #[cfg(any(feature = "mcp23017", feature = "i2c-slave"))]
+            i2c1: (
+                gpio_b.pb6.into_alternate_open_drain(&mut gpio_b.crl),
+                gpio_b.pb7.into_alternate_open_drain(&mut gpio_b.crl),
+            ),
+            #[cfg(feature = "i2c-slave")]
+            data_ready: gpio_b.pb1.into_push_pull_output(&mut gpio_b.crl).erase(),
+            #[cfg(feature = "joystick")]
+            joystick: (
+                gpio_a.pa0.into_analog(&mut gpio_a.crl),
+                gpio_a.pa1.into_analog(&mut gpio_a.crl),
+            ),
+            #[cfg(feature = "velocity")]
+            velocity: (
+                gpio_a.pa0.into_analog(&mut gpio_a.crl),
+                gpio_a.pa1.into_analog(&mut gpio_a.crl),
+                gpio_a.pa2.into_analog(&mut gpio_a.crl),
+                gpio_a.pa3.into_analog(&mut gpio_a.crl),
+            ),
+            #[cfg(feature = "battery")]
+            battery: gpio_b.pb1.into_analog(&mut gpio_b.crl),
+            #[cfg(feature = "ambient")]
+            ambient: gpio_b.pb1.into_analog(&mut gpio_b.crl),
+            #[cfg(feature = "buzzer")]
+            buzzer: gpio_b.pb1.into_alternate_push_pull(&mut gpio_b.crl),
+            #[cfg(feature = "backlight")]
+            backlight: gpio_b.pb15.into_alternate_push_pull(&mut gpio_b.crh),
+            #[cfg(feature = "display")]
+            i2c2: (
+                gpio_b.pb10.into_alternate_open_drain(&mut gpio_b.crh),
+                gpio_b.pb11.into_alternate_open_drain(&mut gpio_b.crh),
+            ),
+            #[cfg(feature = "lcd")]
+            lcd: {
+                let (_pa15, pb3, pb4) = afio.mapr.disable_jtag(gpio_a.pa15, gpio_b.pb3, gpio_b.pb4);
+                (
+                    gpio_b.pb15.into_push_pull_output(&mut gpio_b.crh).erase(),
+                    gpio_b.pb9.into_push_pull_output(&mut gpio_b.crh).erase(),
+                    [
+                        gpio_b.pb8.into_push_pull_output(&mut gpio_b.crh).erase(),
+                        gpio_b.pb5.into_push_pull_output(&mut gpio_b.crl).erase(),
+                        pb4.into_push_pull_output(&mut gpio_b.crl).erase(),
+                        pb3.into_push_pull_output(&mut gpio_b.crl).erase(),
+                    ],
+                )
+            },
+            #[cfg(feature = "servo")]
+            servo: {
+                let (_pa15, _pb3, pb4) =
+                    afio.mapr.disable_jtag(gpio_a.pa15, gpio_b.pb3, gpio_b.pb4);
+                // SAFETY: 0b10 is the partial remap, channel 1 and 2 on PB4 and PB5
+                afio.mapr
+                    .modify_mapr(|_, w| unsafe { w.tim3_remap().bits(0b10) });
+                [
+                    pb4.into_alternate_push_pull(&mut gpio_b.crl).erase(),
+                    gpio_b.pb5.into_alternate_push_pull(&mut gpio_b.crl).erase(),
+                ]
+            },
+            #[cfg(feature = "ps2")]
+            ps2: (
+                gpio_b.pb8.into_open_drain_output(&mut gpio_b.crh).erase(),
+                gpio_b.pb9.into_open_drain_output(&mut gpio_b.crh).erase(),
+            ),
+            #[cfg(feature = "relays")]
+            relays: [
+                gpio_b.pb8.into_push_pull_output(&mut gpio_b.crh).erase(),
+                gpio_b.pb9.into_push_pull_output(&mut gpio_b.crh).erase(),
+                gpio_b.pb10.into_push_pull_output(&mut gpio_b.crh).erase(),
+                gpio_b.pb11.into_push_pull_output(&mut gpio_b.crh).erase(),
+            ],
+            usart1: (
+                gpio_a.pa9.into_alternate_push_pull(&mut gpio_a.crh),
+                gpio_a.pa10,
+            ),
+            usb,
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Rev B: the matrix on PB3-PB10, the rows on PB4-PB7 and the columns on PB3 and
+//! PB8-PB10, the leds on PC13-PC15, the emergency button on PA15 and the encoder
+//! on PB12/PB13 with its switch on PA8. PB3, PB4 and PA15 are JTAG pins, SWD
+//! keeps working without them. The columns of `second-pad` go on PB0, PB1, PB11
+//! and PB14, and so do the outputs of `relays`.
+//!
+//! The rows and the switch raise EXTI4 and EXTI9_5 as on rev A, the encoder
+//! EXTI15_10. The emergency button shares that one on PA15, EXTI0 stays unused.
+
+#[cfg(any(feature = "shift-register", feature = "mcp23017"))]
+compile_error!("rev B wires the matrix to PB3-PB10, the `shift-register` and `mcp23017` pins");
+#[cfg(feature = "i2c-slave")]
+compile_error!("the `i2c-slave` feature needs PB6 and PB7, which rev B wires to the matrix");
+#[cfg(feature = "display")]
+compile_error!("the `display` feature needs PB10, which rev B wires to the matrix");
+#[cfg(feature = "lcd")]
+compile_error!("the `lcd` feature needs PB3-PB5, PB8 and PB9, which rev B wires to the matrix");
+#[cfg(feature = "itm")]
+compile_error!("the `itm` feature needs PB3 for SWO, which rev B wires to the matrix");
+#[cfg(all(
+    feature = "second-pad",
+    any(
+        feature = "battery",
+        feature = "ambient",
+        feature = "buzzer",
+        feature = "ps2"
+    )
+))]
+compile_error!(
+    "the `second-pad` feature needs PB0, PB1, PB11 and PB14, which `battery`, `ambient`, `buzzer` and `ps2` use"
+);
+#[cfg(all(
+    feature = "relays",
+    any(
+        feature = "second-pad",
+        feature = "battery",
+        feature = "ambient",
+        feature = "buzzer",
+        feature = "ps2"
+    )
+))]
+compile_error!(
+    "the `relays` feature needs PB0, PB1, PB11 and PB14, which `second-pad`, `battery`, `ambient`, `buzzer` and `ps2` use"
+);
+
+use super::{Board, Wiring};
+use crate::delay::Delay;
+use crate::keypad::RowInput;
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::*;
+use stm32f1xx_hal::pac::{EXTI, GPIOA, GPIOB, GPIOC, RCC};
+
+/// EXTI lines of the rows on PB4-PB7 and of the encoder switch on PA8.
+pub const WAKE_LINES: u32 = 0b11111 << 4;
+
+/// The port B pins of relay 1 to 4.
+#[cfg(feature = "relays")]
+pub const RELAY_PINS: [u8; 4] = [0, 1, 11, 14];
+
+/// Sets up the red led on PC13 from the registers alone and returns its switch,
+/// for blinking after a panic.
+///
+/// # Safety
+///
+/// Takes the pin from whatever owns it, only for when nothing else runs.
+pub unsafe fn panic_led() -> impl Fn(bool) {
+    (*RCC::ptr()).apb2enr.modify(|_, w| w.iopcen().set_bit());
+    let gpio = &*GPIOC::ptr();
+    gpio.crh
+        .modify(|_, w| w.mode13().output2().cnf13().push_pull());
+    move |on| {
+        gpio.bsrr.write(|w| {
+            if on {
+                w.bs13().set_bit()
+            } else {
+                w.br13().set_bit()
+            }
+        })
+    }
+}
+
+impl Board {
+    /// Sets up the pins, the EXTI lines of the rows and of the encoder are
+    /// configured but for the rows not unmasked. `delay` times the USB reset.
+    pub fn new(
+        gpioa: GPIOA,
+        gpiob: GPIOB,
+        gpioc: GPIOC,
+        afio: &mut afio::Parts,
+        exti: &mut EXTI,
+        delay: &mut Delay,
+    ) -> Self {
+        let mut gpio_a = gpioa.split();
+        let mut gpio_b = gpiob.split();
+        let mut gpio_c = gpioc.split();
+        let (pa15, pb3, pb4) = afio.mapr.disable_jtag(gpio_a.pa15, gpio_b.pb3, gpio_b.pb4);
+
+        let led_red = gpio_c.pc13.into_push_pull_output(&mut gpio_c.crh);
+        let led_blue = gpio_c.pc15.into_push_pull_output(&mut gpio_c.crh);
+        let led_green = gpio_c
+            .pc14
+            .into_push_pull_output_with_state(&mut gpio_c.crh, PinState::Low);
+        let mut emergency_button = pa15.into_pull_up_input(&mut gpio_a.crh);
+        emergency_button.make_interrupt_source(afio);
+
+        #[cfg(not(feature = "open-drain"))]
+        let columns = [
+            pb3.into_push_pull_output(&mut gpio_b.crl).erase(),
+            gpio_b.pb8.into_push_pull_output(&mut gpio_b.crh).erase(),
+            gpio_b.pb9.into_push_pull_output(&mut gpio_b.crh).erase(),
+            gpio_b.pb10.into_push_pull_output(&mut gpio_b.crh).erase(),
+        ];
+        #[cfg(feature = "open-drain")]
+        let columns = [
+            pb3.into_open_drain_output(&mut gpio_b.crl).erase(),
+            gpio_b.pb8.into_open_drain_output(&mut gpio_b.crh).erase(),
+            gpio_b.pb9.into_open_drain_output(&mut gpio_b.crh).erase(),
+            gpio_b.pb10.into_open_drain_output(&mut gpio_b.crh).erase(),
+        ];
+
+        // all on port B with the first pad's, the columns still switch at once
+        #[cfg(feature = "second-pad")]
+        let columns = super::join(
+            columns,
+            [
+                super::column(gpio_b.pb0, &mut gpio_b.crl),
+                super::column(gpio_b.pb1, &mut gpio_b.crl),
+                super::column(gpio_b.pb11, &mut gpio_b.crh),
+                super::column(gpio_b.pb14, &mut gpio_b.crh),
+            ],
+        );
+
+        let mut rows = [
+            Wiring::row(pb4, &mut gpio_b.crl).erase(),
+            Wiring::row(gpio_b.pb5, &mut gpio_b.crl).erase(),
+            Wiring::row(gpio_b.pb6, &mut gpio_b.crl).erase(),
+            Wiring::row(gpio_b.pb7, &mut gpio_b.crl).erase(),
+        ];
+        for row in rows.iter_mut() {
+            row.make_interrupt_source(afio);
+            row.trigger_on_edge(exti, <Wiring as RowInput>::PRESS_EDGE);
+        }
+
+        let mut encoder_a = gpio_b.pb12.into_pull_up_input(&mut gpio_b.crh);
+        let mut encoder_b = gpio_b.pb13.into_pull_up_input(&mut gpio_b.crh);
+        encoder_a.make_interrupt_source(afio);
+        encoder_a.trigger_on_edge(exti, Edge::RisingFalling);
+        encoder_a.enable_interrupt(exti);
+        encoder_b.make_interrupt_source(afio);
+        encoder_b.trigger_on_edge(exti, Edge::RisingFalling);
+        encoder_b.enable_interrupt(exti);
+        let mut encoder_button = gpio_a.pa8.into_pull_up_input(&mut gpio_a.crh);
+        encoder_button.make_interrupt_source(afio);
+        encoder_button.trigger_on_edge(exti, Edge::Falling);
+
+        let usb = super::usb(gpio_a.pa11, gpio_a.pa12, &mut gpio_a.crh, delay);
+
+        Self {
+            led_red: led_red.erase(),
+            led_blue: led_blue.erase(),
+            led_green: led_green.erase(),
+            emergency_button: emergency_button.erase(),
+            encoder_a: encoder_a.erase(),
+            encoder_b: encoder_b.erase(),
+            encoder_button: encoder_button.erase(),
+            columns,
+            rows,
+            #[cfg(feature = "joystick")]
+            joystick: (
+                gpio_a.pa0.into_analog(&mut gpio_a.crl),
+                gpio_a.pa1.into_analog(&mut gpio_a.crl),
+            ),
+            #[cfg(feature = "velocity")]
+            velocity: (
+                gpio_a.pa0.into_analog(&mut gpio_a.crl),
+                gpio_a.pa1.into_analog(&mut gpio_a.crl),
+                gpio_a.pa2.into_analog(&mut gpio_a.crl),
+                gpio_a.pa3.into_analog(&mut gpio_a.crl),
+            ),
+            #[cfg(feature = "battery")]
+            battery: gpio_b.pb1.into_analog(&mut gpio_b.crl),
+            #[cfg(feature = "ambient")]
+            ambient: gpio_b.pb1.into_analog(&mut gpio_b.crl),
+            #[cfg(feature = "buzzer")]
+            buzzer: gpio_b.pb1.into_alternate_push_pull(&mut gpio_b.crl),
+            #[cfg(feature = "backlight")]
+            backlight: gpio_b.pb15.into_alternate_push_pull(&mut gpio_b.crh),
+            #[cfg(feature = "servo")]
+            servo: [
+                gpio_a.pa6.into_alternate_push_pull(&mut gpio_a.crl).erase(),
+                gpio_a.pa7.into_alternate_push_pull(&mut gpio_a.crl).erase(),
+            ],
+            #[cfg(feature = "ps2")]
+            ps2: (
+                gpio_b.pb14.into_open_drain_output(&mut gpio_b.crh).erase(),
+                gpio_b.pb11.into_open_drain_output(&mut gpio_b.crh).erase(),
+            ),
+            #[cfg(feature = "relays")]
+            relays: [
+                gpio_b.pb0.into_push_pull_output(&mut gpio_b.crl).erase(),
+                gpio_b.pb1.into_push_pull_output(&mut gpio_b.crl).erase(),
+                gpio_b.pb11.into_push_pull_output(&mut gpio_b.crh).erase(),
+                gpio_b.pb14.into_push_pull_output(&mut gpio_b.crh).erase(),
+            ],
+            usart1: (
+                gpio_a.pa9.into_alternate_push_pull(&mut gpio_a.crh),
+                gpio_a.pa10,
+            ),
+            usb,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Stages of the startup. `init` reports the ones it runs itself, the first run
+//! of `event_stats` the ones that need the monotonic, then the summary is logged
+//! and the led controller finishes the boot animation, see `LedMode::Boot`.
+//!
+//! A failed critical stage halts the startup, the keypad is never scanned and
+//! the red led stays lit. Any other failure only flashes the red led.
+
+use crate::app::send_led_message;
+use crate::led_mode::LedMessage;
+use crate::logging::Text;
+use core::fmt;
+
+// stage bits
+pub const CLOCKS: u8 = 1 << 0;
+pub const SETTINGS: u8 = 1 << 1;
+pub const KEYPAD: u8 = 1 << 2;
+pub const DISPLAY: u8 = 1 << 3;
+pub const SCANNER: u8 = 1 << 4;
+/// The stages the firmware can't run without.
+pub const CRITICAL: u8 = CLOCKS;
+
+const NAMES: [(u8, &str); 5] = [
+    (CLOCKS, "clocks"),
+    (SETTINGS, "settings"),
+    (KEYPAD, "keypad"),
+    (DISPLAY, "display"),
+    (SCANNER, "scanner"),
+];
+
+/// The stages that passed and failed so far, one that didn't run is neither.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BootStages {
+    passed: u8,
+    failed: u8,
+}
+
+impl BootStages {
+    pub const fn new() -> Self {
+        Self {
+            passed: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn report(&mut self, stage: u8, passed: bool) {
+        if passed {
+            self.passed |= stage;
+        } else {
+            self.failed |= stage;
+        }
+    }
+
+    pub fn passed(&self, stage: u8) -> bool {
+        self.passed & stage != 0
+    }
+
+    pub fn any_failed(&self) -> bool {
+        self.failed != 0
+    }
+
+    pub fn halted(&self) -> bool {
+        self.failed & CRITICAL != 0
+    }
+}
+
+/// `boot clocks=ok settings=FAIL ...`, the stages that ran.
+impl fmt::Display for BootStages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("boot")?;
+        for (stage, name) in NAMES {
+            let verdict = if self.passed(stage) {
+                "=ok"
+            } else if self.failed & stage != 0 {
+                "=FAIL"
+            } else {
+                continue;
+            };
+            write!(f, " {}{}", Text(name), Text(verdict))?;
+        }
+        Ok(())
+    }
+}
+
+/// The stages of the startup that need the monotonic, then the summary. Run by
+/// the first `event_stats`, right after `init`, with the `scans` so far.
+pub fn check(mut stages: BootStages, scans: u32) {
+    if !stages.halted() {
+        stages.report(SCANNER, scans > 0);
+    }
+    log!("{}", stages);
+    send_led_message(LedMessage::Booted(stages));
+}
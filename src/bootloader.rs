@@ -0,0 +1,88 @@
+//! The system bootloader in ROM, for reflashing over USART1 with the board in its
+//! enclosure. `BOOTLOADER`, or [`POWER_ON_KEYS`] held at boot, marks the request
+//! in the backup register of `crate::reset` and resets; the next boot takes the
+//! mark at the start of `init` and jumps, before the HAL set up anything.
+//!
+//! RTIC has enabled the task interrupts by then and the ROM expects the chip as
+//! it comes out of reset, so [`jump`] masks and clears every interrupt, stops
+//! SysTick, resets the peripherals and goes back to the HSI. It starts the IWDG
+//! with its longest timeout first, the ROM keeps feeding it once it runs. A jump
+//! that never gets there ends in a watchdog reset, and with the mark gone that
+//! boot is a normal one.
+
+use crate::keypad::{key_bit, KeyState};
+use core::ptr;
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+use stm32f1xx_hal::pac::{FLASH, IWDG, RCC};
+
+/// '1' and '*' of the base layer.
+pub const POWER_ON_KEYS: KeyState = KeyState(key_bit(0, 0) | key_bit(3, 0));
+
+// the vector table of the system memory, its stack pointer and reset vector
+const SYSTEM_MEMORY: u32 = 0x1fff_f000;
+
+// 40 kHz LSI / 256 * 4095, about 26 s
+const FALLBACK_RELOAD: u16 = 0xfff;
+
+/// Jumps to the ROM bootloader. Returns only if the system memory holds no
+/// vector table, on a clone without the ROM loader.
+///
+/// # Safety
+///
+/// Only for the start of `init`, the firmware doesn't get back from it.
+pub unsafe fn jump() {
+    let table = SYSTEM_MEMORY as *const u32;
+    // the initial stack pointer points into the SRAM
+    if ptr::read_volatile(table) & 0xfff0_0000 != 0x2000_0000 {
+        return;
+    }
+    cortex_m::interrupt::disable();
+    let syst = &*SYST::PTR;
+    syst.csr.write(0);
+    syst.rvr.write(0);
+    syst.cvr.write(0);
+    let nvic = &*NVIC::PTR;
+    for (icer, icpr) in nvic.icer.iter().zip(&nvic.icpr) {
+        icer.write(!0);
+        icpr.write(!0);
+    }
+    reset_clocks();
+    let iwdg = &*IWDG::ptr();
+    iwdg.kr.write(|w| w.key().start());
+    iwdg.kr.write(|w| w.key().enable());
+    iwdg.pr.write(|w| w.pr().divide_by256());
+    iwdg.rlr.write(|w| w.rl().bits(FALLBACK_RELOAD));
+    iwdg.kr.write(|w| w.key().reset());
+    (*SCB::PTR).vtor.write(SYSTEM_MEMORY);
+    // nothing is left to interrupt, the ROM runs with interrupts on
+    cortex_m::interrupt::enable();
+    cortex_m::asm::bootload(table)
+}
+
+// the peripherals through their reset bits, the clock tree back to the HSI alone
+unsafe fn reset_clocks() {
+    let rcc = &*RCC::ptr();
+    rcc.apb1rstr.write(|w| w.bits(!0));
+    rcc.apb1rstr.reset();
+    rcc.apb2rstr.write(|w| w.bits(!0));
+    rcc.apb2rstr.reset();
+    rcc.apb1enr.reset();
+    rcc.apb2enr.reset();
+    rcc.ahbenr.reset();
+    rcc.cr.modify(|_, w| w.hsion().set_bit());
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+    rcc.cfgr.reset();
+    while rcc.cfgr.read().sws().bits() != 0 {}
+    rcc.cr.modify(|_, w| {
+        w.pllon()
+            .clear_bit()
+            .csson()
+            .clear_bit()
+            .hseon()
+            .clear_bit()
+    });
+    rcc.cr.modify(|_, w| w.hsebyp().clear_bit());
+    // the interrupts off and their flags cleared
+    rcc.cir.write(|w| w.bits(0x009f_0000));
+    (*FLASH::ptr()).acr.reset();
+}
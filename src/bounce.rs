@@ -0,0 +1,145 @@
+//! `BOUNCE row col` of the `debug-bounce` feature, the bounce of one key edge by
+//! edge. The key's column is driven alone and its row's EXTI line triggers on both
+//! edges, the row interrupt stamps every raw edge with the DWT cycle counter into
+//! a `keypad_core::bounce::EdgeLog`. Once the key saw no edge for [`SETTLE_MS`]
+//! the scanner logs the edges as microsecond deltas, which with `rtt` go out over
+//! RTT, and the next actuation starts over. `BOUNCE OFF` ends it.
+//!
+//! The column has to stay driven for the row to see the edges, so the scanner
+//! doesn't scan the matrix at all while it captures and the other keys keep their
+//! state, as when parked. Not with `mcp23017`, whose rows can't interrupt.
+
+use crate::board::{Wiring, WAKE_LINES};
+use crate::clock_manager::FULL_SYSCLK_HZ;
+use crate::keypad::RowInput;
+use crate::logging::{Line, Text};
+use core::cell::RefCell;
+use core::fmt::Write;
+use cortex_m::interrupt::{self, Mutex};
+use cortex_m::peripheral::DWT;
+use keypad_core::bounce::EdgeLog;
+use stm32f1xx_hal::gpio::Edge;
+use stm32f1xx_hal::pac::EXTI;
+
+/// Quiet time after the last edge that ends an actuation.
+pub const SETTLE_MS: u32 = 20;
+
+// the scanner holds full speed while it polls
+const CYCLES_PER_US: u32 = FULL_SYSCLK_HZ / 1_000_000;
+const FIRST_ROW_LINE: u32 = WAKE_LINES.trailing_zeros();
+
+struct Capture {
+    // the key of the latest `BOUNCE`, and the one the lines are set up for
+    wanted: Option<(u8, u8)>,
+    active: Option<(u8, u8)>,
+    edges: EdgeLog,
+}
+
+// the row interrupts record, the scanner takes the edges
+static CAPTURE: Mutex<RefCell<Capture>> = Mutex::new(RefCell::new(Capture {
+    wanted: None,
+    active: None,
+    edges: EdgeLog::new(),
+}));
+
+/// Captures the key at `row`, `col` from the next run of the scanner on, or
+/// nothing for `None`.
+pub fn capture(key: Option<(u8, u8)>) {
+    interrupt::free(|cs| CAPTURE.borrow(cs).borrow_mut().wanted = key);
+}
+
+/// Records the edge of the captured row if its line is pending and returns
+/// whether it was, for the row interrupts.
+pub fn stamp() -> bool {
+    let cycles = DWT::cycle_count();
+    interrupt::free(|cs| {
+        let mut capture = CAPTURE.borrow(cs).borrow_mut();
+        let Some((row, _)) = capture.active else {
+            return false;
+        };
+        let line = 1 << (FIRST_ROW_LINE + u32::from(row));
+        // SAFETY: a read of the pending register and a write clearing that line
+        let exti = unsafe { &*EXTI::ptr() };
+        if exti.pr.read().bits() & line == 0 {
+            return false;
+        }
+        exti.pr.write(|w| unsafe { w.bits(line) });
+        capture.edges.record(cycles);
+        true
+    })
+}
+
+/// Sets the lines up for the key the latest `BOUNCE` asked for, `select` driving
+/// its column alone, and logs an actuation that settled. Returns whether a key is
+/// being captured, the scanner leaves the matrix alone then.
+pub fn poll(select: impl FnOnce(usize)) -> bool {
+    let (key, settled) = interrupt::free(|cs| {
+        let mut capture = CAPTURE.borrow(cs).borrow_mut();
+        if capture.active != capture.wanted {
+            if let Some((row, _)) = capture.active {
+                listen(row, false);
+            }
+            capture.active = capture.wanted;
+            capture.edges.clear();
+            if let Some((row, col)) = capture.active {
+                select(usize::from(col));
+                listen(row, true);
+            }
+        }
+        let quiet = SETTLE_MS * 1000 * CYCLES_PER_US;
+        let settled = capture.edges.settled(DWT::cycle_count(), quiet);
+        let edges = settled.then(|| core::mem::replace(&mut capture.edges, EdgeLog::new()));
+        (capture.active, edges)
+    });
+    if let (Some((row, col)), Some(edges)) = (key, settled) {
+        dump(row, col, &edges);
+    }
+    key.is_some()
+}
+
+// both edges of the row's line while capturing, its press edge alone again after
+fn listen(row: u8, on: bool) {
+    let line = 1 << (FIRST_ROW_LINE + u32::from(row));
+    // SAFETY: only the line of the row changes, and the caller holds the critical
+    // section the other EXTI users take as well
+    unsafe {
+        let exti = &*EXTI::ptr();
+        if on {
+            exti.rtsr.modify(|r, w| w.bits(r.bits() | line));
+            exti.ftsr.modify(|r, w| w.bits(r.bits() | line));
+            exti.pr.write(|w| w.bits(line));
+            exti.imr.modify(|r, w| w.bits(r.bits() | line));
+        } else {
+            exti.imr.modify(|r, w| w.bits(r.bits() & !line));
+            match <Wiring as RowInput>::PRESS_EDGE {
+                Edge::Rising => exti.ftsr.modify(|r, w| w.bits(r.bits() & !line)),
+                _ => exti.rtsr.modify(|r, w| w.bits(r.bits() & !line)),
+            }
+            exti.pr.write(|w| w.bits(line));
+        }
+    }
+}
+
+// a header, then the deltas as many to a line as fit
+fn dump(row: u8, col: u8, edges: &EdgeLog) {
+    log!(
+        "bounce {} {}: {} edges in {} us, {} dropped",
+        row,
+        col,
+        edges.len() as u32 + edges.dropped(),
+        edges.span() / CYCLES_PER_US,
+        edges.dropped()
+    );
+    let mut line = Line::new();
+    for delta in edges.deltas_us(CYCLES_PER_US) {
+        // a delta takes at most 11 characters with its space
+        if line.len() + 11 > line.capacity() {
+            log!("{}", Text(&line));
+            line.clear();
+        }
+        let _ = write!(line, " {}", delta);
+    }
+    if !line.is_empty() {
+        log!("{}", Text(&line));
+    }
+}
@@ -0,0 +1,49 @@
+/// Byte ring for outgoing text that only accepts whole lines, a line that doesn't
+/// fit is dropped instead of being truncated.
+pub struct LineBuffer<const N: usize> {
+    bytes: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends `line` and a newline, returns `false` if there is no room for it.
+    pub fn push_line(&mut self, line: &str) -> bool {
+        if self.len + line.len() + 1 > N {
+            return false;
+        }
+        for &byte in line.as_bytes().iter().chain(b"\n") {
+            self.bytes[(self.head + self.len) % N] = byte;
+            self.len += 1;
+        }
+        true
+    }
+
+    /// The buffered bytes up to the end of the backing array.
+    pub fn pending(&self) -> &[u8] {
+        let end = (self.head + self.len).min(N);
+        &self.bytes[self.head..end]
+    }
+
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.head = (self.head + count) % N;
+        self.len -= count;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
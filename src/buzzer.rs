@@ -0,0 +1,122 @@
+//! Passive piezo on TIM3 channel 4 (PB1), driven with a 50% duty square wave.
+//!
+//! Tones don't queue: a new request replaces whatever is playing and restarts
+//! the duration, so the latest request always wins. The `beeper` task runs
+//! above the key consumer and handles every request before the next one is made.
+
+use crate::app::beeper;
+#[cfg(feature = "buzzer")]
+use crate::monotonic;
+#[cfg(feature = "buzzer")]
+use rtic::mutex::prelude::*;
+#[cfg(feature = "buzzer")]
+use stm32f1xx_hal::{
+    gpio::{Alternate, PushPull, PB1},
+    pac::TIM3,
+    prelude::*,
+    timer::{Ch, Channel, PwmHz, Tim3NoRemap, C4},
+};
+
+#[cfg(feature = "buzzer")]
+/// `(freq_hz, duration_ms)` of the click on a key press.
+pub const PRESS_CLICK: (u32, u32) = (4_000, 20);
+#[cfg(feature = "buzzer")]
+/// `(freq_hz, duration_ms)` of the blip on a key release.
+pub const RELEASE_BLIP: (u32, u32) = (2_000, 15);
+#[cfg(feature = "buzzer")]
+/// `(freq_hz, duration_ms)` of the emergency stop tone.
+pub const EMERGENCY_TONE: (u32, u32) = (1_000, 2_000);
+
+#[cfg(feature = "buzzer")]
+pub type Pwm = PwmHz<TIM3, Tim3NoRemap, Ch<C4>, PB1<Alternate<PushPull>>>;
+
+#[cfg(feature = "buzzer")]
+pub struct Buzzer {
+    pwm: Pwm,
+    // bumped by every tone, a stop for an older tone is ignored
+    generation: u32,
+}
+
+#[cfg(feature = "buzzer")]
+impl Buzzer {
+    pub fn new(pwm: Pwm) -> Self {
+        Self { pwm, generation: 0 }
+    }
+
+    /// Starts a tone or changes the frequency of the one playing, the returned
+    /// generation has to be passed to [`Buzzer::stop`].
+    pub fn start(&mut self, freq_hz: u32) -> u32 {
+        self.generation = self.generation.wrapping_add(1);
+        // prescaler, reload and compare values are all preloaded, with the update
+        // event held off they switch together at the end of the current period
+        let tim = unsafe { &*TIM3::ptr() };
+        tim.cr1.modify(|_, w| w.udis().set_bit());
+        self.pwm.set_period(freq_hz.Hz());
+        let duty = self.pwm.get_max_duty() / 2;
+        self.pwm.set_duty(Channel::C4, duty);
+        tim.cr1.modify(|_, w| w.udis().clear_bit());
+        self.pwm.enable(Channel::C4);
+        self.generation
+    }
+
+    /// Silences the tone started as `generation`, unless another one replaced it.
+    pub fn stop(&mut self, generation: u32) {
+        if generation == self.generation {
+            self.silence();
+        }
+    }
+
+    pub fn silence(&mut self) {
+        self.pwm.disable(Channel::C4);
+    }
+}
+
+/// Stand-in without the `buzzer` feature, tones are never requested.
+#[cfg(not(feature = "buzzer"))]
+pub struct Buzzer;
+
+#[cfg(not(feature = "buzzer"))]
+impl Buzzer {
+    pub fn start(&mut self, _freq_hz: u32) -> u32 {
+        0
+    }
+
+    pub fn stop(&mut self, _generation: u32) {}
+
+    pub fn silence(&mut self) {}
+}
+
+/// A run of `beeper`, without the `buzzer` feature there is none.
+#[derive(Copy, Clone, Debug)]
+pub enum Tone {
+    // `(freq_hz, duration_ms)`
+    #[cfg(feature = "buzzer")]
+    Start(u32, u32),
+    // the end of the tone of a generation, see `Buzzer::stop`
+    #[cfg(feature = "buzzer")]
+    Stop(u32),
+}
+
+/// Starts or stops a tone, the body of `beeper`.
+pub fn beeper(ctx: beeper::Context, tone: Tone) {
+    match (tone, ctx) {
+        #[cfg(feature = "buzzer")]
+        (Tone::Start(freq_hz, duration_ms), ctx) => {
+            let mut shared = (ctx.shared.buzzer, ctx.shared.beep_handle);
+            shared.lock(|buzzer, handle| {
+                if let Some(handle) = handle.take() {
+                    // fails if the timer already expired, the generation check
+                    // catches that stop
+                    let _ = handle.cancel();
+                }
+                let generation = buzzer.start(freq_hz);
+                let duration = monotonic::millis(duration_ms);
+                *handle = beeper::spawn_after(duration, Tone::Stop(generation)).ok();
+            });
+        }
+        #[cfg(feature = "buzzer")]
+        (Tone::Stop(generation), mut ctx) => {
+            ctx.shared.buzzer.lock(|buzzer| buzzer.stop(generation));
+        }
+    }
+}
@@ -0,0 +1,13 @@
+/// Chord toggling piano mode, see [`crate::piano`] and `keymap.toml`.
+pub const PIANO_CHORD: u8 = 1;
+
+/// Chord starting a macro recording, see `crate::macros`.
+#[cfg(feature = "macros")]
+pub const MACRO_CHORD: u8 = 0;
+
+/// Chord restarting the firmware once held for [`CHORD_HOLD_MS`], see
+/// [`crate::reset`]. A chord of its own, a tap of the `MACRO_CHORD` would arm
+/// a recording on the way.
+///
+/// [`CHORD_HOLD_MS`]: keypad_core::chord::CHORD_HOLD_MS
+pub const RESET_CHORD: u8 = 3;
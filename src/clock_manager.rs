@@ -0,0 +1,118 @@
+//! Switches between full speed (HSE and the PLL, 72 MHz) and low speed (HSI, 8 MHz).
+//!
+//! Everything that needs full speed holds it with [`ClockManager::request_high`]:
+//! the polling scanner, USB unless it is suspended, and for good the features whose
+//! peripherals are only set up for it (buzzer, backlight, I2C slave). Once the last
+//! holder calls [`ClockManager::release_high`] the core drops to HSI.
+//!
+//! A switch retunes the prescalers of the TIM4 monotonic and the TIM2 status led
+//! PWM, the USART1 baud rate and with `itm` the SWO one. The I2C masters and SPI1
+//! keep their dividers and simply run 9x slower, the WWDG counts 9x slower too,
+//! which its refresh polling copes with.
+//! The flash keeps the two wait states full speed needs.
+//!
+//! The switch runs with interrupts masked: dropping to HSI takes a few cycles,
+//! coming back waits for HSE (~1-2 ms) and the PLL lock (~200 us), which delays
+//! any deadline due meanwhile by as much. The monotonic keeps counting through
+//! it and loses less than a tick to the prescaler change.
+
+use crate::board;
+use crate::monotonic;
+use crate::status_leds;
+use crate::uart;
+use stm32f1xx_hal::pac::{RCC, TIM2};
+
+pub use crate::board::{speed, Speed, FULL_SYSCLK_HZ};
+
+/// Counts the holders of full speed, init leaves the core at full speed with none.
+pub struct ClockManager {
+    holders: u32,
+}
+
+impl ClockManager {
+    pub const fn new() -> Self {
+        Self { holders: 0 }
+    }
+
+    pub fn request_high(&mut self) {
+        self.holders += 1;
+        if self.holders == 1 {
+            switch(Speed::Full);
+        }
+    }
+
+    /// Every call has to match an earlier [`ClockManager::request_high`].
+    pub fn release_high(&mut self) {
+        self.holders -= 1;
+        if self.holders == 0 {
+            switch(Speed::Low);
+        }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.holders > 0
+    }
+}
+
+fn switch(speed: Speed) {
+    if self::speed() == speed {
+        return;
+    }
+    cortex_m::interrupt::free(|_| {
+        match speed {
+            Speed::Full => enable_pll(),
+            Speed::Low => disable_pll(),
+        }
+        board::set_speed(speed);
+        retune(speed);
+    });
+    log!("clock {} MHz", speed.sysclk_hz() / 1_000_000);
+}
+
+/// Brings the PLL back after STOP if full speed was running before, STOP always
+/// wakes up on HSI. Needs interrupts disabled.
+pub fn resume_from_stop() {
+    if speed() == Speed::Full {
+        enable_pll();
+    }
+}
+
+// the PLL settings and the bus prescalers from init stay in place: AHB /1, APB1 /2
+// and APB2 /1 at either speed
+fn enable_pll() {
+    // SAFETY: nothing else touches the clock setup after init, interrupts are masked
+    let rcc = unsafe { &*RCC::ptr() };
+    if rcc.cfgr.read().sws().is_pll() {
+        return;
+    }
+    rcc.cr.modify(|_, w| w.hseon().set_bit());
+    while rcc.cr.read().hserdy().bit_is_clear() {}
+    rcc.cr.modify(|_, w| w.pllon().set_bit());
+    while rcc.cr.read().pllrdy().bit_is_clear() {}
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
+
+fn disable_pll() {
+    let rcc = unsafe { &*RCC::ptr() };
+    rcc.cfgr.modify(|_, w| w.sw().hsi());
+    while !rcc.cfgr.read().sws().is_hsi() {}
+    rcc.cr
+        .modify(|_, w| w.pllon().clear_bit().hseon().clear_bit());
+}
+
+fn retune(speed: Speed) {
+    let sysclk = speed.sysclk_hz();
+    // APB2 runs at sysclk, APB1 at half of it with the timers at twice that
+    monotonic::set_clock(sysclk);
+    uart::set_clock(sysclk);
+    #[cfg(feature = "itm")]
+    crate::itm::set_clock(sysclk);
+    // SAFETY: `StatusLeds` only writes the prescaler in its constructor, it is
+    // preloaded and takes over on the next PWM period
+    unsafe {
+        (*TIM2::ptr())
+            .psc
+            .write(|w| w.psc().bits(status_leds::prescaler(sysclk)))
+    };
+}
@@ -0,0 +1,601 @@
+#[cfg(feature = "bind")]
+use crate::bind;
+use crate::emergency;
+use crate::entry::EntryMode;
+#[cfg(feature = "hold-cap")]
+use crate::hold_cap;
+#[cfg(feature = "debug-inject")]
+use crate::inject;
+use crate::keymap::{self, LAYER_COUNT};
+use crate::keypad::{KeyState, Thresholds, COLUMNS, ROWS};
+use crate::logging;
+use crate::output::OutputMode;
+use crate::pin::Pin;
+#[cfg(feature = "relays")]
+use crate::relays;
+use crate::watchdog::ResetCauses;
+use core::ops::RangeInclusive;
+#[cfg(feature = "bind")]
+use keypad_core::binding::Binding;
+#[cfg(feature = "uart-config")]
+use keypad_core::framing::Framing;
+use serde::Serialize;
+
+// longest command line accepted over the UART, without the newline
+pub const MAX_LINE: usize = 32;
+
+// scan periods `SCANRATE` accepts, in milliseconds
+pub const SCAN_PERIODS_MS: RangeInclusive<u32> = 1..=50;
+
+// thresholds `DEBOUNCE` accepts, in scans
+pub const DEBOUNCE_THRESHOLDS: RangeInclusive<u8> = 1..=20;
+
+// longest a press or a release may take to debounce, the threshold times the scan
+// period
+pub const MAX_DEBOUNCE_LATENCY_MS: u32 = 150;
+
+/// Whether both edges debounce within [`MAX_DEBOUNCE_LATENCY_MS`] at that scan
+/// period and thresholds. `SCANRATE`, `DEBOUNCE` and the config mode keep to it.
+pub fn debounce_fits(scan_period_ms: u32, thresholds: Thresholds) -> bool {
+    scan_period_ms * u32::from(thresholds.longest()) <= MAX_DEBOUNCE_LATENCY_MS
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Led {
+    Red,
+    Blue,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    // `LED RED ON`, `LED BLUE OFF`
+    Led(Led, bool),
+    // `LED RED TOGGLE`
+    LedToggle(Led),
+    // `SCANRATE 5` or `SCAN RATE 5`, scan period in milliseconds, see
+    // `debounce_fits`
+    ScanRate(u32),
+    // `STATUS` or `STATS`
+    Status,
+    // `DUMP KEYS`, the debounced key state
+    Keys,
+    // `DUMP`, the matrix with a raw scan and the debounce counters, see `crate::dump`
+    #[cfg(feature = "dump")]
+    Dump,
+    // `PIANO ON`, `PIANO OFF`
+    Piano(bool),
+    // `ENTRY ON`, `ENTRY PIN`, `ENTRY OFF`, see `crate::entry`
+    Entry(EntryMode),
+    // `OUTPUT TEXT|BINARY|HID|SILENT|MIDI|MEDIA`, where key events go, see `crate::output`
+    Output(OutputMode),
+    // `MACRO RECORD C` records a macro for `C`, the character keeps its case, see
+    // `crate::macros`
+    #[cfg(feature = "macros")]
+    MacroRecord(char),
+    // `PIN SET 4321`, 4 to 8 digits, only after the right PIN was entered. `SAVE`
+    // keeps it.
+    PinSet(Pin),
+    // `JOYSTICK`, raw readings of both axes for calibration
+    Joystick,
+    // `JITTER`, lateness of the scanner since the last `JITTER`
+    Jitter,
+    // `STACK`, the high-water mark of the stack, see `crate::stack`
+    #[cfg(feature = "stack-watch")]
+    Stack,
+    // `STATS KEYS`, the presses of every key as a grid and the totals, see
+    // `crate::stats`
+    Stats,
+    // `STATS RESET`
+    StatsReset,
+    // `HISTORY`, logs the last `event::HISTORY_LEN` key events, oldest first
+    History,
+    // `TIME SET 1760000000`, the wall clock in seconds since the Unix epoch
+    TimeSet(u32),
+    // `TIME SYNC 1760000000`, sets it as well and reports the drift since the
+    // previous sync, `TIME SYNC 1760000000 TRIM` also trims the RTC by it, see
+    // `crate::time_sync`
+    #[cfg(feature = "time-sync")]
+    TimeSync {
+        seconds: u32,
+        trim: bool,
+    },
+    // `ID`, the unique device ID and the firmware version, the leds flash for a
+    // few seconds to find the board
+    Id,
+    // `KEYMAP 1`, the layer used while the fn key is up
+    Keymap(u8),
+    // `DEBOUNCE 5`, scans a key has to be stable for, or `DEBOUNCE 2 8` with the
+    // press and the release threshold apart
+    Debounce(Thresholds),
+    // `DEBOUNCE 1 2 120`, the key on row 1, column 2 debounced for 120 ms of its
+    // own, see `crate::key_debounce`
+    #[cfg(feature = "key-debounce")]
+    KeyDebounce {
+        row: usize,
+        col: usize,
+        ms: u32,
+    },
+    // `BRIGHTNESS 128` of both status leds, 0 to 255
+    Brightness(u8),
+    // `BRIGHTNESS AUTO`, from the ambient light again until the next
+    // `BRIGHTNESS 128`, see `crate::ambient`
+    #[cfg(feature = "ambient")]
+    BrightnessAuto,
+    // `BREATHE ON|OFF`, the blue led breathing in normal operation, see
+    // `crate::leds`
+    #[cfg(feature = "breathe")]
+    Breathe(bool),
+    // `AMBIENT 1 2000 96`, point 1 of the auto brightness curve at a raw reading of
+    // 2000 and a brightness of 96
+    #[cfg(feature = "ambient")]
+    AmbientPoint {
+        index: usize,
+        raw: u16,
+        brightness: u8,
+    },
+    // `HOLDCAP 5000`, the longest the backends hold a key down, see
+    // `crate::hold_cap`
+    #[cfg(feature = "hold-cap")]
+    HoldCap(u32),
+    // `HOLDCAP HID OFF` takes a backend out of the cap, `ON` back in
+    #[cfg(feature = "hold-cap")]
+    HoldCapOutput(OutputMode, bool),
+    // `MAP 1 2 x` puts `x` on row 1, column 2 of the base layer, the character
+    // keeps its case
+    Map {
+        row: usize,
+        col: usize,
+        key: char,
+    },
+    // `MAP SHOW`, the base layer as a table
+    MapShow,
+    // `MAP RESET`, back to the compiled-in keymaps
+    MapReset,
+    // `BIND 1 2 HID:0X1E`, what the key on row 1, column 2 does at the output,
+    // see `crate::bind`
+    #[cfg(feature = "bind")]
+    Bind {
+        index: usize,
+        binding: Binding,
+    },
+    // `BIND SHOW`, the keys bound
+    #[cfg(feature = "bind")]
+    BindShow,
+    // `OUT 1 ON`, `OUT 1 OFF`, relay 1 to 4, see `crate::relays`
+    #[cfg(feature = "relays")]
+    Relay {
+        index: usize,
+        on: bool,
+    },
+    // `UART SET 57600 8E1`, the framing of USART1 at once, and for good after a
+    // `UART CONFIRM` over the UART itself, see `crate::uart`
+    #[cfg(feature = "uart-config")]
+    UartSet(Framing),
+    #[cfg(feature = "uart-config")]
+    UartConfirm,
+    // `BOOTLOADER`, resets into the system bootloader for reflashing over USART1,
+    // see `crate::bootloader`
+    Bootloader,
+    // `SAVE`, writes the settings above, the keymaps and the scan period to the
+    // flash, see `crate::config`
+    Save,
+    // `PRESS 1 2` or `PRESS 1 2 500`, holds the key at row 1, column 2 for the
+    // given or the default time, see `crate::inject`
+    #[cfg(feature = "debug-inject")]
+    Press {
+        index: usize,
+        hold_ms: u32,
+    },
+    // `BOUNCE 1 2`, logs the raw edges of every actuation of the key at row 1,
+    // column 2 until `BOUNCE OFF`, see `crate::bounce`
+    #[cfg(feature = "debug-bounce")]
+    Bounce(Option<(u8, u8)>),
+    // `WDG TEST`, stops the scanner for good so the watchdog resets the chip
+    #[cfg(feature = "debug-inject")]
+    WatchdogTest,
+}
+
+/// Where the reply to a command goes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplyTo {
+    Uart,
+    // the text up-channel, for commands from the RTT console
+    #[cfg(feature = "rtt")]
+    Rtt,
+}
+
+/// Answer to `STATUS`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StatusReport {
+    pub uptime_ms: u32,
+    // counted blinks over every run, see `crate::lifetime`
+    pub blinks: u32,
+    pub scans: u32,
+    pub dropped_events: u32,
+    // smoothed internal sensor readings, see `crate::sensors`
+    pub temperature_tenths: i16,
+    pub vdda_mv: u16,
+    // the latest emergency stop since boot
+    pub last_emergency: Option<emergency::Snapshot>,
+    // over the last second, see `crate::profile`
+    pub cpu_load_percent: u8,
+    // spawns that found the task's message slots taken, see `crate::spawn`
+    pub spawn_failures: u32,
+    // the period the scanner runs at, see `SCANRATE`
+    pub scan_period_ms: u32,
+    // bitmask of the keys taken as stuck, see `keypad_core::debounce::StuckKeys`
+    pub stuck_keys: KeyState,
+    pub reset_causes: ResetCauses,
+    // wall clock seconds, see `crate::rtc`
+    pub time: u32,
+    // degrees, see `crate::servo`
+    #[cfg(feature = "servo")]
+    pub servos: [u8; 2],
+    // the latest raw LDR reading, see `crate::ambient`
+    #[cfg(feature = "ambient")]
+    pub ambient: u16,
+    // bit `n` for relay `n + 1`, see `crate::relays`
+    #[cfg(feature = "relays")]
+    pub relays: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown,
+    TooLong,
+    // a frame failed its CRC or wasn't valid COBS, not in `text` mode
+    Corrupted,
+    // an `OUTPUT` mode or a `BIND` backend this build left out
+    NotBuiltIn,
+    // a `SCANRATE` or `DEBOUNCE` past `MAX_DEBOUNCE_LATENCY_MS` with the other one
+    TooSlow,
+}
+
+impl CommandError {
+    /// Reply sent back over the UART.
+    pub fn reply(self) -> &'static str {
+        match self {
+            CommandError::Unknown => "ERR unknown",
+            CommandError::TooLong => "ERR too long",
+            CommandError::Corrupted => "ERR corrupted",
+            CommandError::NotBuiltIn => "ERR not built in",
+            CommandError::TooSlow => "ERR too slow",
+        }
+    }
+}
+
+// a `u8` through the `u32` parser, which is already there for the other numbers
+fn byte(word: &str) -> Option<u8> {
+    word.parse::<u32>()
+        .ok()
+        .and_then(|value| u8::try_from(value).ok())
+}
+
+/// Parses one command line, case and extra whitespace don't matter but for the
+/// character of a `MAP` or a `MACRO RECORD`.
+pub fn parse(line: &str) -> Result<Command, CommandError> {
+    if line.len() > MAX_LINE {
+        return Err(CommandError::TooLong);
+    }
+    // a log line's type, every `String` size takes its own flash
+    let mut upper = logging::Line::new();
+    let _ = upper.push_str(line);
+    upper.make_ascii_uppercase();
+    let scan_rate = |period: &str| match period.parse() {
+        Ok(period) if SCAN_PERIODS_MS.contains(&period) => Ok(Command::ScanRate(period)),
+        _ => Err(CommandError::Unknown),
+    };
+    let mut words = upper.split_ascii_whitespace();
+    let command = match (words.next(), words.next(), words.next()) {
+        (Some("LED"), Some(led), Some(state)) => {
+            let led = match led {
+                "RED" => Led::Red,
+                "BLUE" => Led::Blue,
+                _ => return Err(CommandError::Unknown),
+            };
+            match state {
+                "ON" => Command::Led(led, true),
+                "OFF" => Command::Led(led, false),
+                "TOGGLE" => Command::LedToggle(led),
+                _ => return Err(CommandError::Unknown),
+            }
+        }
+        (Some("SCANRATE"), Some(period), None) => scan_rate(period)?,
+        (Some("SCAN"), Some("RATE"), Some(period)) => scan_rate(period)?,
+        (Some("STATUS" | "STATS"), None, None) => Command::Status,
+        (Some("DUMP"), Some("KEYS"), None) => Command::Keys,
+        #[cfg(feature = "dump")]
+        (Some("DUMP"), None, None) => Command::Dump,
+        (Some("STATS"), Some("KEYS"), None) => Command::Stats,
+        (Some("STATS"), Some("RESET"), None) => Command::StatsReset,
+        (Some("JOYSTICK"), None, None) => Command::Joystick,
+        (Some("JITTER"), None, None) => Command::Jitter,
+        #[cfg(feature = "stack-watch")]
+        (Some("STACK"), None, None) => Command::Stack,
+        (Some("HISTORY"), None, None) => Command::History,
+        (Some("ID"), None, None) => Command::Id,
+        (Some("TIME"), Some("SET"), Some(seconds)) => match seconds.parse() {
+            Ok(seconds) => Command::TimeSet(seconds),
+            _ => return Err(CommandError::Unknown),
+        },
+        #[cfg(feature = "time-sync")]
+        (Some("TIME"), Some("SYNC"), Some(seconds)) => match (seconds.parse(), words.next()) {
+            (Ok(seconds), trim @ (None | Some("TRIM"))) => Command::TimeSync {
+                seconds,
+                trim: trim.is_some(),
+            },
+            _ => return Err(CommandError::Unknown),
+        },
+        (Some("KEYMAP"), Some(layer), None) => match byte(layer) {
+            Some(layer) if usize::from(layer) < LAYER_COUNT => Command::Keymap(layer),
+            _ => return Err(CommandError::Unknown),
+        },
+        (Some("DEBOUNCE"), Some(first), second) => match (second, words.next()) {
+            #[cfg(feature = "key-debounce")]
+            (Some(col), Some(ms)) => match (byte(first), byte(col), ms.parse()) {
+                (Some(row), Some(col), Ok(ms))
+                    if usize::from(row) < ROWS && usize::from(col) < COLUMNS =>
+                {
+                    Command::KeyDebounce {
+                        row: usize::from(row),
+                        col: usize::from(col),
+                        ms,
+                    }
+                }
+                _ => return Err(CommandError::Unknown),
+            },
+            (release, None) => {
+                let threshold = |word| byte(word).filter(|t| DEBOUNCE_THRESHOLDS.contains(t));
+                match (threshold(first), threshold(release.unwrap_or(first))) {
+                    (Some(press), Some(release)) => {
+                        Command::Debounce(Thresholds { press, release })
+                    }
+                    _ => return Err(CommandError::Unknown),
+                }
+            }
+            _ => return Err(CommandError::Unknown),
+        },
+        #[cfg(feature = "ambient")]
+        (Some("BRIGHTNESS"), Some("AUTO"), None) => Command::BrightnessAuto,
+        #[cfg(feature = "breathe")]
+        (Some("BREATHE"), Some("ON"), None) => Command::Breathe(true),
+        #[cfg(feature = "breathe")]
+        (Some("BREATHE"), Some("OFF"), None) => Command::Breathe(false),
+        #[cfg(feature = "ambient")]
+        (Some("AMBIENT"), Some(index), Some(raw)) => {
+            let brightness = words.next().and_then(byte);
+            match (index.parse::<usize>(), raw.parse::<u32>(), brightness) {
+                (Ok(index), Ok(raw), Some(brightness))
+                    if index < keypad_core::ambient::POINTS && raw <= 4095 =>
+                {
+                    Command::AmbientPoint {
+                        index,
+                        raw: raw as u16,
+                        brightness,
+                    }
+                }
+                _ => return Err(CommandError::Unknown),
+            }
+        }
+        (Some("BRIGHTNESS"), Some(brightness), None) => match byte(brightness) {
+            Some(brightness) => Command::Brightness(brightness),
+            _ => return Err(CommandError::Unknown),
+        },
+        #[cfg(feature = "hold-cap")]
+        (Some("HOLDCAP"), Some(cap), None) => match cap.parse() {
+            Ok(cap) if hold_cap::HOLD_CAPS_MS.contains(&cap) => Command::HoldCap(cap),
+            _ => return Err(CommandError::Unknown),
+        },
+        #[cfg(feature = "hold-cap")]
+        (Some("HOLDCAP"), Some(output), Some(capped)) => {
+            let output = match output {
+                "HID" => OutputMode::Hid,
+                #[cfg(feature = "midi")]
+                "MIDI" => OutputMode::Midi,
+                #[cfg(feature = "media")]
+                "MEDIA" => OutputMode::Media,
+                _ => return Err(CommandError::Unknown),
+            };
+            match capped {
+                "ON" => Command::HoldCapOutput(output, true),
+                "OFF" => Command::HoldCapOutput(output, false),
+                _ => return Err(CommandError::Unknown),
+            }
+        }
+        #[cfg(feature = "uart-config")]
+        (Some("UART"), Some("CONFIRM"), None) => Command::UartConfirm,
+        #[cfg(feature = "uart-config")]
+        (Some("UART"), Some("SET"), Some(baud)) => {
+            // the `u32` parser is there already, `Framing::parse` is for the build
+            let format = words.next().unwrap_or("");
+            match baud
+                .parse()
+                .ok()
+                .and_then(|baud| Framing::new(baud, format))
+            {
+                Some(framing) => Command::UartSet(framing),
+                None => return Err(CommandError::Unknown),
+            }
+        }
+        (Some("SAVE"), None, None) => Command::Save,
+        (Some("BOOTLOADER"), None, None) => Command::Bootloader,
+        #[cfg(feature = "bind")]
+        (Some("BIND"), Some("SHOW"), None) => Command::BindShow,
+        #[cfg(feature = "bind")]
+        (Some("BIND"), Some(row), Some(col)) => {
+            let binding = words.next().and_then(Binding::parse);
+            let (Ok(row), Ok(col), Some(binding)) =
+                (row.parse::<usize>(), col.parse::<usize>(), binding)
+            else {
+                return Err(CommandError::Unknown);
+            };
+            if row >= ROWS || col >= COLUMNS {
+                return Err(CommandError::Unknown);
+            }
+            if !bind::is_built_in(binding) {
+                return Err(CommandError::NotBuiltIn);
+            }
+            Command::Bind {
+                index: row * COLUMNS + col,
+                binding,
+            }
+        }
+        #[cfg(feature = "relays")]
+        (Some("OUT"), Some(relay), Some(state)) => {
+            let on = match state {
+                "ON" => true,
+                "OFF" => false,
+                _ => return Err(CommandError::Unknown),
+            };
+            match byte(relay) {
+                Some(relay @ 1..) if usize::from(relay) <= relays::RELAYS => Command::Relay {
+                    index: usize::from(relay - 1),
+                    on,
+                },
+                _ => return Err(CommandError::Unknown),
+            }
+        }
+        (Some("MAP"), Some("SHOW"), None) => Command::MapShow,
+        (Some("MAP"), Some("RESET"), None) => Command::MapReset,
+        (Some("MAP"), Some(row), Some(col)) => {
+            // taken from the line as it came in
+            words.next();
+            let key = line.split_ascii_whitespace().nth(3).map(str::as_bytes);
+            let (Ok(row), Ok(col), Some(&[key])) =
+                (row.parse::<usize>(), col.parse::<usize>(), key)
+            else {
+                return Err(CommandError::Unknown);
+            };
+            let key = char::from(key);
+            if row >= ROWS || col >= COLUMNS || !keymap::is_mappable(key) {
+                return Err(CommandError::Unknown);
+            }
+            Command::Map { row, col, key }
+        }
+        #[cfg(feature = "debug-bounce")]
+        (Some("BOUNCE"), Some("OFF"), None) => Command::Bounce(None),
+        #[cfg(feature = "debug-bounce")]
+        (Some("BOUNCE"), Some(row), Some(col)) => match (byte(row), byte(col)) {
+            (Some(row), Some(col)) if usize::from(row) < ROWS && usize::from(col) < COLUMNS => {
+                Command::Bounce(Some((row, col)))
+            }
+            _ => return Err(CommandError::Unknown),
+        },
+        #[cfg(feature = "debug-inject")]
+        (Some("WDG"), Some("TEST"), None) => Command::WatchdogTest,
+        #[cfg(feature = "debug-inject")]
+        (Some("PRESS"), Some(row), Some(col)) => {
+            let (Ok(row), Ok(col)) = (row.parse::<usize>(), col.parse::<usize>()) else {
+                return Err(CommandError::Unknown);
+            };
+            let hold_ms = match words.next().map(str::parse) {
+                None => inject::DEFAULT_HOLD_MS,
+                Some(Ok(hold_ms)) if hold_ms <= inject::MAX_HOLD_MS => hold_ms,
+                Some(_) => return Err(CommandError::Unknown),
+            };
+            if row >= ROWS || col >= COLUMNS {
+                return Err(CommandError::Unknown);
+            }
+            Command::Press {
+                index: row * COLUMNS + col,
+                hold_ms,
+            }
+        }
+        (Some("PIANO"), Some(state), None) => match state {
+            "ON" => Command::Piano(true),
+            "OFF" => Command::Piano(false),
+            _ => return Err(CommandError::Unknown),
+        },
+        (Some("ENTRY"), Some(state), None) => match state {
+            "ON" => Command::Entry(EntryMode::Number),
+            "PIN" => Command::Entry(EntryMode::Pin),
+            "OFF" => Command::Entry(EntryMode::Off),
+            _ => return Err(CommandError::Unknown),
+        },
+        (Some("OUTPUT"), Some(mode), None) => {
+            let mode = match mode {
+                "TEXT" => OutputMode::Text,
+                "BINARY" => OutputMode::Binary,
+                "HID" => OutputMode::Hid,
+                "SILENT" => OutputMode::Silent,
+                #[cfg(feature = "midi")]
+                "MIDI" => OutputMode::Midi,
+                #[cfg(feature = "media")]
+                "MEDIA" => OutputMode::Media,
+                _ => return Err(CommandError::Unknown),
+            };
+            if !mode.is_built_in() {
+                return Err(CommandError::NotBuiltIn);
+            }
+            Command::Output(mode)
+        }
+        #[cfg(feature = "macros")]
+        (Some("MACRO"), Some("RECORD"), Some(_)) => {
+            let key = line.split_ascii_whitespace().nth(2).map(str::as_bytes);
+            match key {
+                Some(&[key]) if keymap::is_mappable(char::from(key)) => {
+                    Command::MacroRecord(char::from(key))
+                }
+                _ => return Err(CommandError::Unknown),
+            }
+        }
+        (Some("PIN"), Some("SET"), Some(digits)) => match Pin::new(digits) {
+            Some(pin) if pin.is_valid() => Command::PinSet(pin),
+            _ => return Err(CommandError::Unknown),
+        },
+        _ => return Err(CommandError::Unknown),
+    };
+    match words.next() {
+        Some(_) => Err(CommandError::Unknown),
+        None => Ok(command),
+    }
+}
+
+/// Turns the inbound byte stream into commands: [`crate::wire`] frames, or text
+/// lines with the `text` feature.
+#[cfg(feature = "text")]
+pub type CommandReader = LineReader;
+#[cfg(not(feature = "text"))]
+pub type CommandReader = crate::wire::CommandFrames;
+
+/// Collects received bytes into lines. A line longer than [`MAX_LINE`] is thrown
+/// away up to its newline instead of being cut into pieces.
+#[cfg(any(feature = "text", feature = "rtt"))]
+pub struct LineReader {
+    line: heapless::String<MAX_LINE>,
+    overflow: bool,
+}
+
+#[cfg(any(feature = "text", feature = "rtt"))]
+impl LineReader {
+    pub const fn new() -> Self {
+        Self {
+            line: heapless::String::new(),
+            overflow: false,
+        }
+    }
+
+    /// Feeds one byte, returns the parsed command once a line is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Command, CommandError>> {
+        match byte {
+            b'\n' | b'\r' => {
+                let result = if self.overflow {
+                    Some(Err(CommandError::TooLong))
+                } else if self.line.trim_ascii().is_empty() {
+                    None
+                } else {
+                    Some(parse(&self.line))
+                };
+                self.line.clear();
+                self.overflow = false;
+                result
+            }
+            _ => {
+                if !self.overflow && self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! Settings that survive a power cycle, in the last 1K page of the flash which
+//! `memory.x` keeps out of the firmware. The page holds a single record: a
+//! magic number, a layout version, the settings and their CRC-32. Anything else
+//! in the page, an erased one included, loads as the compiled-in defaults.
+//!
+//! Erasing the page stalls every fetch from the flash for up to 40 ms and with
+//! it every interrupt, programming the record takes under a millisecond more.
+//! `save` has to start right after a refresh of the window watchdog, which leaves
+//! it ~58 ms, and nothing should preempt it in between. Bytes arriving on USART1
+//! meanwhile are lost.
+
+#[cfg(feature = "ambient")]
+use crate::ambient;
+use crate::app::{clear_error, raise_error, save_settings, send_led_message, send_reply};
+#[cfg(feature = "bind")]
+use crate::bind;
+use crate::command::{self, ReplyTo, DEBOUNCE_THRESHOLDS, SCAN_PERIODS_MS};
+use crate::crc;
+#[cfg(feature = "hold-cap")]
+use crate::hold_cap;
+#[cfg(feature = "key-debounce")]
+use crate::key_debounce;
+use crate::keymap::{self, Keymap, Layers, LAYERS, LAYER_COUNT};
+use crate::keypad::{Thresholds, COLUMNS, KEYS, ROWS};
+use crate::led_mode::{ErrorCode, Flash, LedMessage};
+use crate::modifiers::Modifiers;
+use crate::output::OutputMode;
+use crate::pin::{self, Pin};
+#[cfg(feature = "relays")]
+use crate::relays;
+use crate::status_leds::DEFAULT_BRIGHTNESS;
+use crate::timing;
+#[cfg(feature = "uart-config")]
+use crate::uart;
+#[cfg(feature = "ambient")]
+use keypad_core::ambient::Curve;
+#[cfg(feature = "bind")]
+use keypad_core::binding::Binding;
+#[cfg(feature = "uart-config")]
+use keypad_core::framing::Framing;
+#[cfg(feature = "hold-cap")]
+use keypad_core::hold_cap::HOLD_CAP_MS;
+use rtic::mutex::prelude::*;
+use stm32f1xx_hal::flash::{self, FLASH_START};
+use stm32f1xx_hal::pac::{flash::RegisterBlock, FLASH};
+
+// offset of the settings page from the start of the flash
+const PAGE_OFFSET: u32 = 63 * 1024;
+// unlock the FPEC, RM0008 3.3.3
+const UNLOCK_KEYS: [u32; 2] = [0x4567_0123, 0xcdef_89ab];
+
+const MAGIC: [u8; 4] = *b"KBCF";
+// bumped whenever the record layout changes, older records load as the defaults
+const VERSION: u8 = 13;
+
+// magic, version, the settings, a byte per key of every layer, with
+// `key-debounce` the keys' own thresholds, with `ambient` the auto brightness and
+// its curve, with `hold-cap` the cap and its backends, with `breathe` whether the
+// blue led breathes, with `uart-config` the framing, with `bind` the kind and the
+// code of every key's binding, with `relays` their states, and the CRC of
+// everything before it. The CRC lands elsewhere with and without any of them, a
+// record of another build loads as the defaults.
+const PIN_AT: usize = 15;
+const KEYS_AT: usize = PIN_AT + pin::STORED_LEN;
+const KEY_DEBOUNCE_AT: usize = KEYS_AT + LAYER_COUNT * KEYS;
+const KEY_DEBOUNCE_LEN: usize = match cfg!(feature = "key-debounce") {
+    true => KEYS,
+    false => 0,
+};
+const AMBIENT_AT: usize = KEY_DEBOUNCE_AT + KEY_DEBOUNCE_LEN;
+const AMBIENT_LEN: usize = match cfg!(feature = "ambient") {
+    true => 1 + 3 * keypad_core::ambient::POINTS,
+    false => 0,
+};
+const HOLD_CAP_AT: usize = AMBIENT_AT + AMBIENT_LEN;
+const HOLD_CAP_LEN: usize = match cfg!(feature = "hold-cap") {
+    true => 5,
+    false => 0,
+};
+const BREATHE_AT: usize = HOLD_CAP_AT + HOLD_CAP_LEN;
+const BREATHE_LEN: usize = match cfg!(feature = "breathe") {
+    true => 1,
+    false => 0,
+};
+const UART_AT: usize = BREATHE_AT + BREATHE_LEN;
+const UART_LEN: usize = match cfg!(feature = "uart-config") {
+    true => 5,
+    false => 0,
+};
+const BIND_AT: usize = UART_AT + UART_LEN;
+const BIND_LEN: usize = match cfg!(feature = "bind") {
+    true => 2 * KEYS,
+    false => 0,
+};
+const RELAYS_AT: usize = BIND_AT + BIND_LEN;
+const RELAYS_LEN: usize = match cfg!(feature = "relays") {
+    true => 1,
+    false => 0,
+};
+// the flash takes half-words, a zero pads the record before the CRC if it has to
+const RECORD_LEN: usize = (RELAYS_AT + RELAYS_LEN + 4 + 1) & !1;
+// `save` writes half-words, an odd record would lose its last byte after the erase
+const _: () = assert!(RECORD_LEN.is_multiple_of(2));
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    // active layer while the fn key is up, an index into `keymap::LAYERS`
+    pub keymap: u8,
+    pub scan_period_ms: u32,
+    pub debounce: Thresholds,
+    // of both status leds
+    pub brightness: u8,
+    // the locks on when the settings were saved
+    pub modifiers: Modifiers,
+    // of the `ENTRY PIN` mode
+    pub pin: Pin,
+    pub output: OutputMode,
+    pub keymaps: [Keymap<COLUMNS, ROWS>; LAYER_COUNT],
+    // see `crate::key_debounce`
+    #[cfg(feature = "key-debounce")]
+    pub key_debounce: [u8; KEYS],
+    // see `crate::ambient`
+    #[cfg(feature = "ambient")]
+    pub auto_brightness: bool,
+    #[cfg(feature = "ambient")]
+    pub curve: Curve,
+    // see `crate::hold_cap`, the backends a bit each
+    #[cfg(feature = "hold-cap")]
+    pub hold_cap_ms: u32,
+    #[cfg(feature = "hold-cap")]
+    pub hold_cap_outputs: u8,
+    // the blue led breathes in `Normal`, see `crate::leds`
+    #[cfg(feature = "breathe")]
+    pub breathing: bool,
+    // a confirmed `UART SET`, `None` for the framing of the build, see `crate::uart`
+    #[cfg(feature = "uart-config")]
+    pub uart: Option<Framing>,
+    // see `crate::bind`
+    #[cfg(feature = "bind")]
+    pub bindings: [Binding; KEYS],
+    // see `crate::relays`, a bit each
+    #[cfg(feature = "relays")]
+    pub relays: u8,
+}
+
+impl Settings {
+    pub const DEFAULT: Settings = Settings {
+        keymap: 0,
+        // a key has to be stable for the `debounce` periods of the edge before its
+        // state changes
+        scan_period_ms: timing::ms(timing::SCAN_PERIOD),
+        debounce: Thresholds::DEFAULT,
+        brightness: DEFAULT_BRIGHTNESS,
+        modifiers: Modifiers(0),
+        pin: Pin::DEFAULT,
+        output: OutputMode::DEFAULT,
+        keymaps: LAYERS,
+        #[cfg(feature = "key-debounce")]
+        key_debounce: [0; KEYS],
+        #[cfg(feature = "ambient")]
+        auto_brightness: true,
+        #[cfg(feature = "ambient")]
+        curve: Curve::DEFAULT,
+        #[cfg(feature = "hold-cap")]
+        hold_cap_ms: HOLD_CAP_MS,
+        #[cfg(feature = "hold-cap")]
+        hold_cap_outputs: hold_cap::DEFAULT_OUTPUTS,
+        #[cfg(feature = "breathe")]
+        breathing: true,
+        #[cfg(feature = "uart-config")]
+        uart: None,
+        #[cfg(feature = "bind")]
+        bindings: [Binding::Key; KEYS],
+        #[cfg(feature = "relays")]
+        relays: 0,
+    };
+
+    fn is_valid(&self) -> bool {
+        usize::from(self.keymap) < LAYER_COUNT
+            && SCAN_PERIODS_MS.contains(&self.scan_period_ms)
+            && DEBOUNCE_THRESHOLDS.contains(&self.debounce.press)
+            && DEBOUNCE_THRESHOLDS.contains(&self.debounce.release)
+            && command::debounce_fits(self.scan_period_ms, self.debounce)
+            && self.pin.is_valid()
+            && self.keys().all(|key| keymap::is_mappable(*key))
+            && self.key_debounce_is_valid()
+            && self.curve_is_valid()
+            && self.hold_cap_is_valid()
+    }
+
+    #[cfg(feature = "key-debounce")]
+    fn key_debounce_is_valid(&self) -> bool {
+        let thresholds = self.debounce.with_keys(&self.key_debounce);
+        key_debounce::is_valid(&self.key_debounce)
+            && command::debounce_fits(self.scan_period_ms, thresholds)
+    }
+
+    #[cfg(not(feature = "key-debounce"))]
+    fn key_debounce_is_valid(&self) -> bool {
+        true
+    }
+
+    #[cfg(feature = "ambient")]
+    fn curve_is_valid(&self) -> bool {
+        self.curve.is_valid()
+    }
+
+    #[cfg(not(feature = "ambient"))]
+    fn curve_is_valid(&self) -> bool {
+        true
+    }
+
+    #[cfg(feature = "hold-cap")]
+    fn hold_cap_is_valid(&self) -> bool {
+        hold_cap::HOLD_CAPS_MS.contains(&self.hold_cap_ms)
+    }
+
+    #[cfg(not(feature = "hold-cap"))]
+    fn hold_cap_is_valid(&self) -> bool {
+        true
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &char> {
+        self.keymaps
+            .iter()
+            .flat_map(|keymap| keymap.0.iter().flatten())
+    }
+
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[..4].copy_from_slice(&MAGIC);
+        record[4] = VERSION;
+        record[5] = self.keymap;
+        record[6] = self.debounce.press;
+        record[7] = self.brightness;
+        record[8..12].copy_from_slice(&self.scan_period_ms.to_le_bytes());
+        record[12] = self.modifiers.0;
+        record[13] = self.output.to_byte();
+        record[14] = self.debounce.release;
+        record[PIN_AT..KEYS_AT].copy_from_slice(&self.pin.stored());
+        // only ASCII is mappable
+        for (byte, key) in record[KEYS_AT..].iter_mut().zip(self.keys()) {
+            *byte = *key as u8;
+        }
+        #[cfg(feature = "key-debounce")]
+        record[KEY_DEBOUNCE_AT..AMBIENT_AT].copy_from_slice(&self.key_debounce);
+        #[cfg(feature = "ambient")]
+        {
+            record[AMBIENT_AT] = u8::from(self.auto_brightness);
+            let points = record[AMBIENT_AT + 1..HOLD_CAP_AT].chunks_exact_mut(3);
+            for (bytes, &(raw, brightness)) in points.zip(&self.curve.0) {
+                bytes[..2].copy_from_slice(&raw.to_le_bytes());
+                bytes[2] = brightness;
+            }
+        }
+        #[cfg(feature = "hold-cap")]
+        {
+            record[HOLD_CAP_AT..HOLD_CAP_AT + 4].copy_from_slice(&self.hold_cap_ms.to_le_bytes());
+            record[HOLD_CAP_AT + 4] = self.hold_cap_outputs;
+        }
+        #[cfg(feature = "breathe")]
+        {
+            record[BREATHE_AT] = u8::from(self.breathing);
+        }
+        // a zero baud rate for the build's
+        #[cfg(feature = "uart-config")]
+        if let Some(framing) = self.uart {
+            record[UART_AT..UART_AT + UART_LEN].copy_from_slice(&framing.to_bytes());
+        }
+        #[cfg(feature = "bind")]
+        for (bytes, binding) in record[BIND_AT..].chunks_exact_mut(2).zip(self.bindings) {
+            bytes.copy_from_slice(&binding.to_bytes());
+        }
+        #[cfg(feature = "relays")]
+        {
+            record[RELAYS_AT] = self.relays;
+        }
+        let crc = crc::checksum(&record[..RECORD_LEN - 4]);
+        record[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8; RECORD_LEN]) -> Option<Settings> {
+        let (data, crc) = record.split_at(RECORD_LEN - 4);
+        if data[..4] != MAGIC || data[4] != VERSION || crc::checksum(data).to_le_bytes() != crc {
+            return None;
+        }
+        let mut keymaps = [Keymap([[' '; COLUMNS]; ROWS]); LAYER_COUNT];
+        for (index, &byte) in data[KEYS_AT..KEY_DEBOUNCE_AT].iter().enumerate() {
+            let key = index % KEYS;
+            keymaps[index / KEYS].0[key / COLUMNS][key % COLUMNS] = char::from(byte);
+        }
+        let settings = Settings {
+            keymap: data[5],
+            debounce: Thresholds {
+                press: data[6],
+                release: data[14],
+            },
+            brightness: data[7],
+            scan_period_ms: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+            modifiers: Modifiers(data[12]),
+            pin: Pin::from_stored(&data[PIN_AT..KEYS_AT]),
+            output: OutputMode::from_byte(data[13])?,
+            keymaps,
+            #[cfg(feature = "key-debounce")]
+            key_debounce: data[KEY_DEBOUNCE_AT..AMBIENT_AT].try_into().ok()?,
+            #[cfg(feature = "ambient")]
+            auto_brightness: data[AMBIENT_AT] != 0,
+            #[cfg(feature = "ambient")]
+            curve: {
+                let mut curve = Curve::DEFAULT;
+                let points = data[AMBIENT_AT + 1..HOLD_CAP_AT].chunks_exact(3);
+                for (point, bytes) in curve.0.iter_mut().zip(points) {
+                    *point = (u16::from_le_bytes([bytes[0], bytes[1]]), bytes[2]);
+                }
+                curve
+            },
+            #[cfg(feature = "hold-cap")]
+            hold_cap_ms: u32::from_le_bytes(data[HOLD_CAP_AT..HOLD_CAP_AT + 4].try_into().ok()?),
+            #[cfg(feature = "hold-cap")]
+            hold_cap_outputs: data[HOLD_CAP_AT + 4],
+            #[cfg(feature = "breathe")]
+            breathing: data[BREATHE_AT] != 0,
+            #[cfg(feature = "uart-config")]
+            uart: match data[UART_AT..UART_AT + 4] {
+                [0, 0, 0, 0] => None,
+                _ => Some(Framing::from_bytes(data[UART_AT..].try_into().ok()?)?),
+            },
+            #[cfg(feature = "bind")]
+            bindings: {
+                let mut bindings = [Binding::Key; KEYS];
+                for (binding, bytes) in bindings.iter_mut().zip(data[BIND_AT..].chunks_exact(2)) {
+                    *binding = Binding::from_bytes([bytes[0], bytes[1]])?;
+                }
+                bindings
+            },
+            #[cfg(feature = "relays")]
+            relays: data[RELAYS_AT] & ((1 << relays::RELAYS) - 1),
+        };
+        // a mode only another build has loads as the default of this one
+        let output = match settings.output.is_built_in() {
+            true => settings.output,
+            false => OutputMode::DEFAULT,
+        };
+        settings
+            .is_valid()
+            .then_some(Settings { output, ..settings })
+    }
+}
+
+/// The saved settings, `None` if the page holds no valid record.
+pub fn load() -> Option<Settings> {
+    Settings::decode(page())
+}
+
+fn page() -> &'static [u8; RECORD_LEN] {
+    // SAFETY: the page is mapped, never part of the firmware and only written by
+    // `save`, which can't run before init is through
+    unsafe { &*((FLASH_START + PAGE_OFFSET) as *const [u8; RECORD_LEN]) }
+}
+
+/// Erases the settings page and writes `settings` to it, see the module docs for
+/// how long that blocks, and returns whether it reads back as written. The FPEC
+/// is programmed directly, the HAL's writer checks every half-word against the
+/// whole flash and takes more room than is left. Like the HAL, it stops at the
+/// first operation the FPEC flags.
+pub fn save(_flash: &mut flash::Parts, settings: &Settings) -> bool {
+    let record = settings.encode();
+    // SAFETY: the borrow of the flash parts keeps everything else off the FPEC
+    let fpec = unsafe { &*FLASH::ptr() };
+    while fpec.sr.read().bsy().bit_is_set() {}
+    for key in UNLOCK_KEYS {
+        fpec.keyr.write(|w| unsafe { w.key().bits(key) });
+    }
+    // a wrong sequence locks the FPEC until the next reset
+    if fpec.cr.read().lock().bit_is_set() {
+        return false;
+    }
+    fpec.cr.modify(|_, w| w.per().set_bit());
+    fpec.ar
+        .write(|w| unsafe { w.far().bits(FLASH_START + PAGE_OFFSET) });
+    fpec.cr.modify(|_, w| w.strt().set_bit());
+    let erased = finish(fpec);
+    fpec.cr.modify(|_, w| w.per().clear_bit().pg().set_bit());
+    let start = (FLASH_START + PAGE_OFFSET) as *mut u16;
+    let written = erased
+        && record.chunks_exact(2).enumerate().all(|(index, half)| {
+            // SAFETY: inside the page, which is erased and in programming mode
+            unsafe {
+                start
+                    .add(index)
+                    .write_volatile(u16::from_le_bytes([half[0], half[1]]))
+            };
+            finish(fpec)
+        });
+    fpec.cr.modify(|_, w| w.pg().clear_bit().lock().set_bit());
+    // a half-word the FPEC took without a flag may still read back different
+    written && *page() == record
+}
+
+// waits for the erase or the half-word, clears its flags and returns whether it
+// ended without a programming or a write protection error
+fn finish(fpec: &RegisterBlock) -> bool {
+    // BSY only rises the cycle after STRT, see the errata of the F105/F107
+    cortex_m::asm::nop();
+    while fpec.sr.read().bsy().bit_is_set() {}
+    let sr = fpec.sr.read();
+    // the flags are cleared by writing ones
+    fpec.sr
+        .write(|w| w.eop().set_bit().pgerr().set_bit().wrprterr().set_bit());
+    sr.eop().bit_is_set() && sr.pgerr().bit_is_clear() && sr.wrprterr().bit_is_clear()
+}
+
+/// Saves the settings as they are now, the body of `save_settings`. Without
+/// `reply_to`, for the config mode, the leds flash green or red instead.
+pub fn save_settings(mut ctx: save_settings::Context, reply_to: Option<ReplyTo>) {
+    let mut shared = (
+        ctx.shared.scan_period_ms,
+        ctx.shared.debounce,
+        ctx.shared.layers,
+        ctx.shared.status_leds,
+    );
+    let modifiers = ctx.shared.modifiers.lock(|modifiers| *modifiers);
+    let pin = ctx.shared.pin_lock.lock(|lock| lock.pin());
+    let output = ctx.shared.output.lock(|output| *output);
+    #[cfg(feature = "ambient")]
+    let (auto_brightness, curve) = ambient::settings();
+    #[cfg(feature = "hold-cap")]
+    let (hold_cap_ms, hold_cap_outputs) = hold_cap::settings();
+    let settings = shared.lock(|scan_period, debounce, layers, leds| Settings {
+        keymap: layers.base() as u8,
+        keymaps: *layers.keymaps(),
+        scan_period_ms: *scan_period,
+        debounce: *debounce,
+        #[cfg(feature = "key-debounce")]
+        key_debounce: key_debounce::table(),
+        brightness: leds.brightness(),
+        modifiers,
+        pin,
+        output,
+        #[cfg(feature = "ambient")]
+        auto_brightness,
+        #[cfg(feature = "ambient")]
+        curve,
+        #[cfg(feature = "hold-cap")]
+        hold_cap_ms,
+        #[cfg(feature = "hold-cap")]
+        hold_cap_outputs,
+        #[cfg(feature = "breathe")]
+        breathing: leds.breathing(),
+        #[cfg(feature = "uart-config")]
+        uart: uart::saved(),
+        #[cfg(feature = "bind")]
+        bindings: bind::table(),
+        #[cfg(feature = "relays")]
+        relays: relays::states(),
+    });
+    let flash = ctx.local.flash;
+    let mut wwdg = ctx.shared.wwdg;
+    let saved = loop {
+        let saved = wwdg.lock(|wwdg| wwdg.refresh().then(|| save(flash, &settings)));
+        if let Some(saved) = saved {
+            break saved;
+        }
+    };
+    match saved {
+        true => {
+            if core::mem::take(ctx.local.save_failed) {
+                clear_error(ErrorCode::FlashWrite);
+            }
+            // nothing else runs commands meanwhile, the keymaps are as saved
+            shared.2.lock(Layers::mark_saved);
+            match reply_to {
+                Some(reply_to) => send_reply(reply_to, "OK"),
+                None => send_led_message(LedMessage::Flash(Flash::Granted)),
+            }
+        }
+        false => {
+            if !core::mem::replace(ctx.local.save_failed, true) {
+                raise_error(ErrorCode::FlashWrite);
+            }
+            match reply_to {
+                Some(reply_to) => send_reply(reply_to, "ERR flash"),
+                None => send_led_message(LedMessage::Flash(Flash::Reject)),
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! The config mode, the keypad as the UI for a few settings. Holding 'A' and 'D'
+//! for [`HOLD_MS`] enters it, as does the sequence [`CONFIG_SEQUENCE`]. '1' to '4'
+//! pick a [`Setting`], '*' and '#' step it down and up, 'D' saves the settings and
+//! leaves, 'B' leaves without saving, like a change over the UART without `SAVE`.
+//! The blue led blinks the number of the setting picked.
+//!
+//! The keys are the characters of the base layer at their positions, whatever
+//! lock or layer they stand for otherwise. Their presses and releases end in
+//! `key_listener` ahead of the locks and layers and go out as nothing else.
+//!
+//! Only with the `config-mode` feature, the keys and the sequence are ordinary
+//! keys otherwise.
+
+#[cfg(all(feature = "config-mode", feature = "ambient"))]
+use crate::ambient;
+#[cfg(feature = "config-mode")]
+use crate::app::{configure, save_settings, send_led_message, switch_output};
+#[cfg(feature = "config-mode")]
+use crate::command::{self, DEBOUNCE_THRESHOLDS, SCAN_PERIODS_MS};
+#[cfg(all(feature = "config-mode", feature = "key-debounce"))]
+use crate::key_debounce;
+#[cfg(feature = "config-mode")]
+use crate::keypad::{key_bit, KeyState, Thresholds};
+#[cfg(feature = "config-mode")]
+use crate::led_mode::{LedMessage, LedMode};
+#[cfg(feature = "config-mode")]
+use crate::modifiers::Modifiers;
+#[cfg(feature = "config-mode")]
+use core::ops::RangeInclusive;
+#[cfg(feature = "config-mode")]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "config-mode")]
+use rtic::mutex::prelude::*;
+
+#[cfg(feature = "config-mode")]
+pub const HOLD_MS: u32 = 2000;
+#[cfg(feature = "config-mode")]
+// 'A' and 'D' of the base layer
+const HOLD_KEYS: KeyState = KeyState(key_bit(0, 3) | key_bit(3, 3));
+
+#[cfg(feature = "config-mode")]
+/// The `[[sequence]]` of `keymap.toml` entering the config mode.
+pub const CONFIG_SEQUENCE: u8 = 0;
+
+#[cfg(feature = "config-mode")]
+// a step of the led brightness, 17 of them from off to full
+const BRIGHTNESS_STEP: u32 = 15;
+
+#[cfg(feature = "config-mode")]
+// set by `key_listener`, cleared by the `configure` task
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "config-mode")]
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "config-mode")]
+pub fn enter() {
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+#[cfg(feature = "config-mode")]
+pub fn leave() {
+    ACTIVE.store(false, Ordering::Relaxed);
+}
+
+/// What `key_listener` sends the config mode through the event queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(not(feature = "config-mode"), allow(dead_code))]
+pub enum ConfigInput {
+    Entered,
+    // a press, by its character on the base layer
+    Key(char),
+}
+
+#[cfg(feature = "config-mode")]
+/// The keys of the config mode in `key_listener`.
+pub struct ConfigKeys {
+    // when 'A' and 'D' were both down
+    held_since: Option<u32>,
+    // the locks from before the first of them went down, 'A' toggled one
+    locks: Option<Modifiers>,
+    // pressed while the mode was on, their releases end here as well
+    diverted: KeyState,
+}
+
+#[cfg(feature = "config-mode")]
+impl ConfigKeys {
+    pub const fn new() -> Self {
+        Self {
+            held_since: None,
+            locks: None,
+            diverted: KeyState(0),
+        }
+    }
+
+    /// Takes the presses out of a frame's `changes` while the mode is on, and the
+    /// releases of the keys pressed meanwhile. Returns the rest and the presses.
+    pub fn divert(
+        &mut self,
+        (pressed, released): (KeyState, KeyState),
+    ) -> ((KeyState, KeyState), KeyState) {
+        let taken = if is_active() { pressed } else { KeyState(0) };
+        let ended = released & self.diverted;
+        self.diverted = (self.diverted | taken) & !ended;
+        ((pressed & !taken, released & !ended), taken)
+    }
+
+    /// Enters the mode once 'A' and 'D' of the debounced `state` were held for
+    /// [`HOLD_MS`], and returns the locks to go back to. `before` are the locks
+    /// ahead of the frame.
+    pub fn hold(&mut self, state: KeyState, before: Modifiers, now: u32) -> Option<Modifiers> {
+        let held = state & HOLD_KEYS;
+        if held.is_empty() {
+            self.locks = None;
+        } else if self.locks.is_none() {
+            self.locks = Some(before);
+        }
+        if held != HOLD_KEYS || is_active() {
+            self.held_since = None;
+            return None;
+        }
+        let since = *self.held_since.get_or_insert(now);
+        if now.wrapping_sub(since) < HOLD_MS {
+            return None;
+        }
+        enter();
+        self.locks
+    }
+}
+
+#[cfg(feature = "config-mode")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Setting {
+    ScanPeriod,
+    Debounce,
+    Brightness,
+    Output,
+}
+
+#[cfg(feature = "config-mode")]
+impl Setting {
+    const ALL: [Setting; 4] = [
+        Setting::ScanPeriod,
+        Setting::Debounce,
+        Setting::Brightness,
+        Setting::Output,
+    ];
+
+    /// Its key, and the blinks of the blue led.
+    pub fn number(self) -> u8 {
+        self as u8 + 1
+    }
+}
+
+#[cfg(feature = "config-mode")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigAction {
+    Pick(Setting),
+    // down, or up for `true`
+    Step(Setting, bool),
+    Save,
+    Leave,
+}
+
+#[cfg(feature = "config-mode")]
+/// The setting picked, in the `configure` task.
+pub struct ConfigMode {
+    setting: Setting,
+}
+
+#[cfg(feature = "config-mode")]
+impl ConfigMode {
+    pub const fn new() -> Self {
+        Self {
+            setting: Setting::ScanPeriod,
+        }
+    }
+
+    /// Starts over from the first setting.
+    pub fn enter(&mut self) -> Setting {
+        self.setting = Setting::ScanPeriod;
+        self.setting
+    }
+
+    pub fn handle(&mut self, key: char) -> Option<ConfigAction> {
+        let action = match key {
+            '1'..='4' => {
+                self.setting = Setting::ALL[usize::from(key as u8 - b'1')];
+                ConfigAction::Pick(self.setting)
+            }
+            '*' => ConfigAction::Step(self.setting, false),
+            '#' => ConfigAction::Step(self.setting, true),
+            'D' => ConfigAction::Save,
+            'B' => ConfigAction::Leave,
+            _ => return None,
+        };
+        if let ConfigAction::Save | ConfigAction::Leave = action {
+            leave();
+        }
+        Some(action)
+    }
+}
+
+#[cfg(feature = "config-mode")]
+fn step(value: u32, up: bool, by: u32, range: RangeInclusive<u32>) -> u32 {
+    let value = match up {
+        true => value.saturating_add(by),
+        false => value.saturating_sub(by),
+    };
+    value.clamp(*range.start(), *range.end())
+}
+
+#[cfg(feature = "config-mode")]
+// a step past `command::debounce_fits` stays where it was, with `key-debounce` the
+// thresholds take the keys' own into account
+pub fn step_scan_period(period_ms: u32, thresholds: Thresholds, up: bool) -> u32 {
+    let stepped = step(period_ms, up, 1, SCAN_PERIODS_MS);
+    match command::debounce_fits(stepped, thresholds) {
+        true => stepped,
+        false => period_ms,
+    }
+}
+
+#[cfg(feature = "config-mode")]
+// both thresholds step together, the one at the end of the range stays there
+pub fn step_debounce(thresholds: Thresholds, period_ms: u32, up: bool) -> Thresholds {
+    let range = u32::from(*DEBOUNCE_THRESHOLDS.start())..=u32::from(*DEBOUNCE_THRESHOLDS.end());
+    let step = |threshold| step(u32::from(threshold), up, 1, range.clone()) as u8;
+    let stepped = Thresholds {
+        press: step(thresholds.press),
+        release: step(thresholds.release),
+    };
+    match command::debounce_fits(period_ms, stepped) {
+        true => stepped,
+        false => thresholds,
+    }
+}
+
+#[cfg(feature = "config-mode")]
+pub fn step_brightness(brightness: u8, up: bool) -> u8 {
+    step(u32::from(brightness), up, BRIGHTNESS_STEP, 0..=255) as u8
+}
+
+#[cfg(feature = "config-mode")]
+/// A key of the config mode, the body of `configure`. A change takes effect right
+/// away, 'D' saves it like `SAVE`.
+pub fn configure(mut ctx: configure::Context, input: ConfigInput) {
+    let action = match input {
+        ConfigInput::Entered => {
+            log!("config mode");
+            ConfigAction::Pick(ctx.local.config_mode.enter())
+        }
+        ConfigInput::Key(key) => match ctx.local.config_mode.handle(key) {
+            Some(action) => action,
+            None => return,
+        },
+    };
+    match action {
+        ConfigAction::Pick(setting) => {
+            send_led_message(LedMessage::Enter(LedMode::Config(setting.number())));
+        }
+        ConfigAction::Step(Setting::ScanPeriod, up) => {
+            let mut shared = (&mut ctx.shared.scan_period_ms, &mut ctx.shared.debounce);
+            let period = shared.lock(|period, debounce| {
+                #[cfg(feature = "key-debounce")]
+                let debounce = &debounce.with_keys(&key_debounce::table());
+                *period = step_scan_period(*period, *debounce, up);
+                *period
+            });
+            log!("scan period {} ms", period);
+        }
+        ConfigAction::Step(Setting::Debounce, up) => {
+            let mut shared = (&mut ctx.shared.scan_period_ms, &mut ctx.shared.debounce);
+            let debounce = shared.lock(|period, debounce| {
+                *debounce = step_debounce(*debounce, *period, up);
+                *debounce
+            });
+            log!("debounce {}/{} scans", debounce.press, debounce.release);
+        }
+        ConfigAction::Step(Setting::Brightness, up) => {
+            let brightness = ctx.shared.status_leds.lock(|leds| {
+                let brightness = step_brightness(leds.brightness(), up);
+                leds.set_brightness(brightness);
+                brightness
+            });
+            #[cfg(feature = "ambient")]
+            ambient::set_auto(false);
+            log!("brightness {}", brightness);
+        }
+        ConfigAction::Step(Setting::Output, up) => {
+            let mut shared = (ctx.shared.output, ctx.shared.usb);
+            shared.lock(|output, usb| switch_output(output, usb, output.step(up)));
+        }
+        ConfigAction::Save | ConfigAction::Leave => {
+            log!("config mode over");
+            send_led_message(LedMessage::Leave(LedMode::Config(0)));
+            if action == ConfigAction::Save && save_settings::spawn(None).is_err() {
+                log!("settings not saved, still busy");
+            }
+        }
+    }
+}
+
+/// Stand-ins for the locals of `key_listener` and `configure`.
+#[cfg(not(feature = "config-mode"))]
+pub struct ConfigKeys;
+
+#[cfg(not(feature = "config-mode"))]
+impl ConfigKeys {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(not(feature = "config-mode"))]
+pub struct ConfigMode;
+
+#[cfg(not(feature = "config-mode"))]
+impl ConfigMode {
+    pub const fn new() -> Self {
+        Self
+    }
+}
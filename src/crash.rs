@@ -0,0 +1,324 @@
+//! What the firmware leaves behind when it dies. The panic handler notes the
+//! panic in a backup register for the next boot, the calls that led to it in RAM
+//! that isn't initialized at boot, reports it over RTT and blinks SOS on the red
+//! led until the watchdog resets the chip. Nothing of RTIC or the HAL is trusted
+//! by then, everything is done on the registers.
+//!
+//! Where it panicked comes from the frame pointers: the return addresses of the
+//! calls down to the panic handler, for `addr2line` against the ELF. Reading the
+//! location of the panic keeps the locations of every panic site, which takes
+//! `panic-location`: the line goes into the backup register and the file, line,
+//! column and message to RTT. That is 6 KB of flash in a release build and 26 KB
+//! in a debug one, which doesn't fit. The message also comes with the defmt
+//! build, whose panic-probe prints it with the location.
+//!
+//! A HardFault reports the stacked registers and the fault status over RTT,
+//! keeps them in RAM as well and resets the chip.
+
+#[cfg(not(feature = "defmt"))]
+use crate::{board, clock_manager, watchdog};
+use core::mem::MaybeUninit;
+#[cfg(not(feature = "defmt"))]
+use core::panic::PanicInfo;
+use core::ptr;
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+use stm32f1xx_hal::backup_domain::BackupDomain;
+#[cfg(not(feature = "defmt"))]
+use stm32f1xx_hal::pac::{BKP, PWR, RCC};
+
+// backup register DR1, it survives any reset but a power loss. A panic takes
+// it over from a reset marker of `crate::reset`.
+const MAGIC_REGISTER: usize = 0;
+// the top three bits of the magic register after a panic, the markers of
+// `crate::reset` never set them all. The other bits hold the line.
+const PANICKED: u16 = 0xe000;
+const LINE: u16 = !PANICKED;
+
+/// Return addresses the panic handler keeps.
+pub const BACKTRACE_LEN: usize = 8;
+
+/// How the previous run ended in a panic, see [`last_panic`].
+pub struct PanicRecord {
+    /// The line, 0 where the build doesn't record it.
+    pub line: u32,
+    /// Return addresses of the calls that led to the panic handler, the innermost
+    /// first and 0 past the last one. All 0 after a power loss took the RAM.
+    pub backtrace: [u32; BACKTRACE_LEN],
+}
+
+#[repr(C)]
+struct Backtrace {
+    magic: u32,
+    frames: [u32; BACKTRACE_LEN],
+}
+
+// in `Backtrace::magic` after a panic, anything else is what the RAM powered up
+// with
+const TRACED: u32 = 0xba0c_7ace;
+
+// survives the reset, cortex-m-rt leaves `.uninit` alone at boot
+#[link_section = ".uninit.BACKTRACE"]
+static mut BACKTRACE: MaybeUninit<Backtrace> = MaybeUninit::uninit();
+
+/// The panic the previous run ended in, if it did. The record stays until
+/// [`clear_panic`], a reset before it was reported keeps it for the next boot.
+pub fn last_panic(backup_domain: &BackupDomain) -> Option<PanicRecord> {
+    let magic = backup_domain.read_data_register_low(MAGIC_REGISTER);
+    if magic & PANICKED != PANICKED {
+        return None;
+    }
+    // SAFETY: only read in init, written by the panic handler alone; any bit
+    // pattern is a valid record
+    let trace = unsafe { ptr::read_volatile(ptr::addr_of!(BACKTRACE).cast::<Backtrace>()) };
+    Some(PanicRecord {
+        line: u32::from(magic & LINE),
+        backtrace: match trace.magic {
+            TRACED => trace.frames,
+            _ => [0; BACKTRACE_LEN],
+        },
+    })
+}
+
+/// Drops the record of the last panic once it was reported.
+pub fn clear_panic(backup_domain: &BackupDomain) {
+    backup_domain.write_data_register_low(MAGIC_REGISTER, 0);
+    // SAFETY: as in `last_panic`
+    unsafe {
+        let trace = ptr::addr_of_mut!(BACKTRACE).cast::<Backtrace>();
+        ptr::write_volatile(ptr::addr_of_mut!((*trace).magic), 0);
+    }
+}
+
+// the defmt build reports panics with panic-probe
+#[cfg(not(feature = "defmt"))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    // SAFETY: interrupts are off for good, nothing else touches the record or
+    // these registers again
+    unsafe { record_backtrace() };
+    #[cfg(feature = "rtt")]
+    report(info);
+    unsafe { record_panic(line(info)) };
+    unsafe { sos() }
+}
+
+// clamped to the bits the magic register leaves for it
+#[cfg(all(feature = "panic-location", not(feature = "defmt")))]
+fn line(info: &PanicInfo) -> u16 {
+    info.location()
+        .map_or(0, |location| location.line().min(u32::from(LINE)) as u16)
+}
+
+// the location isn't read and the linker drops those of the panic sites
+#[cfg(not(any(feature = "panic-location", feature = "defmt")))]
+fn line(_: &PanicInfo) -> u16 {
+    0
+}
+
+// the print channel may be in blocking mode or not set up at all
+#[cfg(all(feature = "rtt", not(feature = "defmt")))]
+fn report(info: &PanicInfo) {
+    // SAFETY: nothing else writes to the channel once interrupts are off
+    let Some(mut channel) = (unsafe { rtt_target::UpChannel::conjure(0) }) else {
+        return;
+    };
+    channel.set_mode(rtt_target::ChannelMode::NoBlockTrim);
+    #[cfg(feature = "panic-location")]
+    if let Some(location) = info.location() {
+        use core::fmt::Write;
+        channel.write(b"panicked at ");
+        channel.write(location.file().as_bytes());
+        // in the string type of the status line like the fault report
+        let mut line = heapless::String::<160>::new();
+        let _ = writeln!(
+            line,
+            ":{}:{}: {}",
+            location.line(),
+            location.column(),
+            info.message()
+        );
+        channel.write(line.as_bytes());
+        return;
+    }
+    let _ = info;
+    channel.write(b"panicked, `panic-location` tells where\n");
+}
+
+// walks the frame records the calls pushed, the caller's r7 and the return
+// address each. A frame pointer outside the stack or not above the last one ends
+// the walk, the top frames of `main` have none.
+#[cfg(not(feature = "defmt"))]
+#[inline(always)]
+unsafe fn record_backtrace() {
+    extern "C" {
+        static _stack_start: u32;
+    }
+    let top = ptr::addr_of!(_stack_start) as u32;
+    let mut frame: u32;
+    core::arch::asm!("mov {}, r7", out(reg) frame, options(nomem, nostack, preserves_flags));
+    let mut frames = [0; BACKTRACE_LEN];
+    let mut below = cortex_m::register::msp::read();
+    for address in &mut frames {
+        if frame < below || frame > top - 8 || frame & 3 != 0 {
+            break;
+        }
+        *address = ptr::read_volatile((frame + 4) as *const u32);
+        below = frame + 8;
+        frame = ptr::read_volatile(frame as *const u32);
+    }
+    let trace = Backtrace {
+        magic: TRACED,
+        frames,
+    };
+    ptr::write_volatile(ptr::addr_of_mut!(BACKTRACE).cast(), trace);
+}
+
+#[cfg(not(feature = "defmt"))]
+unsafe fn record_panic(line: u16) {
+    let rcc = &*RCC::ptr();
+    rcc.apb1enr
+        .modify(|_, w| w.pwren().set_bit().bkpen().set_bit());
+    (*PWR::ptr()).cr.modify(|_, w| w.dbp().set_bit());
+    let bkp = &*BKP::ptr();
+    bkp.dr[MAGIC_REGISTER].write(|w| w.d().bits(PANICKED | line));
+}
+
+// dot length of the SOS, a dash and the gap between letters are three dots
+#[cfg(not(feature = "defmt"))]
+const DOT_MS: u32 = 200;
+
+// SOS rounds blinked with the watchdogs fed, most of a minute
+#[cfg(not(feature = "defmt"))]
+const SOS_ROUNDS: u32 = 8;
+
+/// Blinks `... --- ...` on the red led. The watchdogs are fed for
+/// [`SOS_ROUNDS`] rounds, then left to reset the chip so the next boot reports
+/// the panic; the blinking goes on until they do.
+#[cfg(not(feature = "defmt"))]
+unsafe fn sos() -> ! {
+    let led = board::panic_led();
+    let round = |feed| {
+        for letter in [[1; 3], [3; 3], [1; 3]] {
+            for dots in letter {
+                led(true);
+                wait(dots * DOT_MS, feed);
+                led(false);
+                wait(DOT_MS, feed);
+            }
+            wait(2 * DOT_MS, feed);
+        }
+        // seven dots between the words
+        wait(4 * DOT_MS, feed);
+    };
+    for _ in 0..SOS_ROUNDS {
+        round(true);
+    }
+    loop {
+        round(false);
+    }
+}
+
+// in steps of a millisecond, well inside the window of the WWDG
+#[cfg(not(feature = "defmt"))]
+fn wait(ms: u32, feed: bool) {
+    let cycles_per_ms = clock_manager::speed().sysclk_hz() / 1000;
+    for _ in 0..ms {
+        cortex_m::asm::delay(cycles_per_ms);
+        if feed {
+            watchdog::feed_unowned();
+        }
+    }
+}
+
+/// The state of the core at the HardFault that ended the previous run.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct FaultRecord {
+    magic: u32,
+    pub pc: u32,
+    pub lr: u32,
+    pub xpsr: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub bfar: u32,
+    pub mmfar: u32,
+}
+
+// in `FaultRecord::magic` after a HardFault, anything else is what the RAM
+// powered up with
+const FAULTED: u32 = 0xfa17_ed00;
+
+// survives the reset, cortex-m-rt leaves `.uninit` alone at boot
+#[link_section = ".uninit.FAULT"]
+static mut FAULT: MaybeUninit<FaultRecord> = MaybeUninit::uninit();
+
+/// The HardFault that ended the previous run, if it ended in one. Clears the
+/// record.
+pub fn take_fault() -> Option<FaultRecord> {
+    // SAFETY: only called in init and in the HardFault handler, which resets
+    // right after; any bit pattern is a valid record
+    unsafe {
+        let fault = ptr::addr_of_mut!(FAULT).cast::<FaultRecord>();
+        let record = ptr::read_volatile(fault);
+        ptr::write_volatile(ptr::addr_of_mut!((*fault).magic), 0);
+        (record.magic == FAULTED).then_some(record)
+    }
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    let scb = &*SCB::PTR;
+    let record = FaultRecord {
+        magic: FAULTED,
+        pc: frame.pc(),
+        lr: frame.lr(),
+        xpsr: frame.xpsr(),
+        cfsr: scb.cfsr.read(),
+        hfsr: scb.hfsr.read(),
+        bfar: scb.bfar.read(),
+        mmfar: scb.mmfar.read(),
+    };
+    ptr::write_volatile(ptr::addr_of_mut!(FAULT).cast(), record);
+    report_fault(&record);
+    SCB::sys_reset()
+}
+
+fn report_fault(record: &FaultRecord) {
+    #[cfg(feature = "defmt")]
+    defmt::error!(
+        "hard fault at pc {=u32:08x} lr {=u32:08x} xpsr {=u32:08x} cfsr {=u32:08x} hfsr {=u32:08x} bfar {=u32:08x} mmfar {=u32:08x}",
+        record.pc,
+        record.lr,
+        record.xpsr,
+        record.cfsr,
+        record.hfsr,
+        record.bfar,
+        record.mmfar
+    );
+    // the print channel may be in blocking mode or not set up at all
+    #[cfg(all(feature = "rtt", not(feature = "defmt")))]
+    // SAFETY: nothing else runs at this priority
+    if let Some(mut channel) = unsafe { rtt_target::UpChannel::conjure(0) } {
+        use core::fmt::Write;
+        channel.set_mode(rtt_target::ChannelMode::NoBlockTrim);
+        // formatted into the string type of the status line, the channel's own
+        // `Write` would take flash of its own
+        let mut line = heapless::String::<160>::new();
+        let _ = writeln!(
+            line,
+            "hard fault at pc {:08x} lr {:08x} xpsr {:08x} cfsr {:08x} hfsr {:08x} bfar {:08x} mmfar {:08x}",
+            record.pc,
+            record.lr,
+            record.xpsr,
+            record.cfsr,
+            record.hfsr,
+            record.bfar,
+            record.mmfar
+        );
+        channel.write(line.as_bytes());
+    }
+    #[cfg(not(any(feature = "rtt", feature = "defmt")))]
+    let _ = record;
+}
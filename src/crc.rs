@@ -0,0 +1,36 @@
+//! CRC-32 on the CRC unit, CRC-32/MPEG-2 over the big-endian bytes of the words.
+//! [`checksum`] puts bytes into words as `keypad_core::crc` has it.
+//!
+//! The unit has a single accumulator, so every calculation runs in a critical
+//! section.
+
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use stm32f1xx_hal::crc::{Crc, CrcExt};
+use stm32f1xx_hal::pac::CRC;
+
+static UNIT: Mutex<RefCell<Option<Crc>>> = Mutex::new(RefCell::new(None));
+
+/// Turns the unit on, before anything checks a frame or the settings.
+pub fn init(crc: CRC) {
+    interrupt::free(|cs| *UNIT.borrow(cs).borrow_mut() = Some(crc.new()));
+}
+
+/// CRC of `words`, 0 before [`init`].
+pub fn calculate(words: impl IntoIterator<Item = u32>) -> u32 {
+    interrupt::free(|cs| match UNIT.borrow(cs).borrow_mut().as_mut() {
+        Some(unit) => {
+            unit.reset();
+            for word in words {
+                unit.write(word);
+            }
+            unit.read()
+        }
+        None => 0,
+    })
+}
+
+/// CRC of `bytes`, the words of `keypad_core::crc::words`.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    calculate(keypad_core::crc::words(bytes))
+}
@@ -0,0 +1,55 @@
+//! Blocking delays for the bring-up of the drivers: the USB reset, the HD44780
+//! timings and the settling of the matrix lines. [`Delay`] spins the core for as
+//! many cycles as the sysclk of the moment takes, full speed from the clocks `init`
+//! froze or low speed on the HSI, see `board::speed`.
+//!
+//! A clock switch can come in the middle of a delay, the speed is read again for
+//! every [`CHUNK_US`], so a switch only gets the chunk it preempted wrong. Going
+//! up to full speed that chunk is cut short, a driver that can't take that holds
+//! full speed meanwhile.
+
+use crate::board::{self, Speed, LOW_SYSCLK_HZ};
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use stm32f1xx_hal::rcc::Clocks;
+
+/// The longest stretch spun at one speed.
+pub const CHUNK_US: u32 = 1000;
+
+#[derive(Copy, Clone)]
+pub struct Delay {
+    // at full speed, from the frozen clocks
+    cycles_per_us: u32,
+}
+
+impl Delay {
+    pub fn new(clocks: &Clocks) -> Self {
+        Self {
+            cycles_per_us: clocks.sysclk().raw() / 1_000_000,
+        }
+    }
+
+    fn cycles_per_us(&self) -> u32 {
+        match board::speed() {
+            Speed::Full => self.cycles_per_us,
+            Speed::Low => LOW_SYSCLK_HZ / 1_000_000,
+        }
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, mut us: u32) {
+        while us > 0 {
+            let chunk = us.min(CHUNK_US);
+            cortex_m::asm::delay(chunk * self.cycles_per_us());
+            us -= chunk;
+        }
+    }
+}
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.delay_us(1000);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! The status display, a 128x64 SSD1306 on I2C. The frame is drawn into a copy of
+//! the display RAM, a byte for a column of 8 pixels of a page, and written out
+//! whole; the text is in the public domain 5x8 font of X11's misc-fixed.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{key_bit, KeyState};
+#[cfg(feature = "display")]
+use crate::keypad::{COLUMNS, ROWS};
+#[cfg(feature = "display")]
+use embedded_hal::blocking::i2c::Write;
+
+/// What the status display shows, kept up to date by the key consumer.
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+pub struct DisplayModel {
+    last_key: Option<char>,
+    held: KeyState,
+    cpu_load_percent: u8,
+}
+
+impl DisplayModel {
+    pub const fn new() -> Self {
+        Self {
+            last_key: None,
+            held: KeyState(0),
+            cpu_load_percent: 0,
+        }
+    }
+
+    pub fn set_cpu_load(&mut self, percent: u8) {
+        self.cpu_load_percent = percent;
+    }
+
+    pub fn record(&mut self, event: &KeyEvent) {
+        let bit = KeyState(key_bit(event.row as usize, event.col as usize));
+        match event.kind {
+            EventKind::Pressed => {
+                self.last_key = Some(event.key);
+                self.held |= bit;
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => self.held &= !bit,
+            _ => {}
+        }
+    }
+}
+
+// side of one key of the held keys grid in pixels
+#[cfg(feature = "display")]
+const CELL: usize = 12;
+
+#[cfg(feature = "display")]
+const ADDRESS: u8 = 0x3c;
+
+#[cfg(feature = "display")]
+const WIDTH: usize = 128;
+
+// the settings of the panel after a reset, behind the command control byte:
+// display off, clock, 64 lines, no offset, start line 0, charge pump on,
+// horizontal addressing, alternative COM pins, the panel not mirrored,
+// precharge, contrast, VCOMH, follow the RAM, not inverted, no scrolling,
+// display on
+#[cfg(feature = "display")]
+const INIT: [u8; 27] = [
+    0x00, 0xae, 0xd5, 0x80, 0xa8, 0x3f, 0xd3, 0x00, 0x40, 0x8d, 0x14, 0x20, 0x00, 0xda, 0x12, 0xa1,
+    0xc8, 0xd9, 0x21, 0x81, 0x5f, 0xdb, 0x40, 0xa4, 0xa6, 0x2e, 0xaf,
+];
+
+// the whole RAM from its first column and page on
+#[cfg(feature = "display")]
+const WINDOW: [u8; 7] = [0x00, 0x21, 0x00, 0x7f, 0x22, 0x00, 0x07];
+
+// the first printable character of the font, ' '
+#[cfg(feature = "display")]
+const FIRST: u8 = 0x20;
+
+/// 128x64 SSD1306 showing the last pressed key, the uptime and the held keys.
+#[cfg(feature = "display")]
+pub struct StatusDisplay<I2C> {
+    i2c: I2C,
+    // the data control byte and then the pages of the frame
+    frame: [u8; 1 + WIDTH * 8],
+}
+
+#[cfg(feature = "display")]
+impl<I2C: Write> StatusDisplay<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        let mut frame = [0; 1 + WIDTH * 8];
+        frame[0] = 0x40;
+        Self { i2c, frame }
+    }
+
+    pub fn init(&mut self) -> Result<(), I2C::Error> {
+        self.i2c.write(ADDRESS, &INIT)
+    }
+
+    pub fn render(&mut self, model: &DisplayModel, uptime_ms: u32) -> Result<(), I2C::Error> {
+        self.frame[1..].fill(0);
+
+        self.text(0, b"key ");
+        self.text_at(0, 24, &[model.last_key.map_or(b'-', ascii)]);
+
+        let seconds = uptime_ms / 1000;
+        let mut clock = *b"00:00:00";
+        for (digits, value) in
+            clock
+                .chunks_mut(3)
+                .zip([seconds / 3600 % 100, seconds / 60 % 60, seconds % 60])
+        {
+            digits[0] = b'0' + (value / 10) as u8;
+            digits[1] = b'0' + (value % 10) as u8;
+        }
+        self.text(2, &clock);
+
+        let load = model.cpu_load_percent.min(100);
+        let mut line = *b"cpu 000%";
+        for (at, digit) in [(4, load / 100), (5, load / 10 % 10), (6, load % 10)] {
+            line[at] = b'0' + digit;
+        }
+        // right aligned, without the leading zeros
+        for digit in line[4..6].iter_mut().take_while(|digit| **digit == b'0') {
+            *digit = b' ';
+        }
+        self.text(4, &line);
+
+        // grid in the right half, filled squares are held keys
+        for row in 0..ROWS {
+            for col in 0..COLUMNS {
+                let filled = model.held.is_pressed(row, col);
+                let (left, top) = (64 + col * CELL, row * CELL);
+                for x in left..left + CELL - 2 {
+                    for y in top..top + CELL - 2 {
+                        let edge =
+                            x == left || x == left + CELL - 3 || y == top || y == top + CELL - 3;
+                        if filled || edge {
+                            self.pixel(x, y);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.i2c.write(ADDRESS, &WINDOW)?;
+        self.i2c.write(ADDRESS, &self.frame)
+    }
+
+    // the text in `page`, a glyph after another from the left edge
+    fn text(&mut self, page: usize, text: &[u8]) {
+        self.text_at(page, 0, text);
+    }
+
+    fn text_at(&mut self, page: usize, column: usize, text: &[u8]) {
+        for (n, &character) in text.iter().enumerate() {
+            let at = 1 + page * WIDTH + column + n * 6;
+            let glyph = &FONT[usize::from(character.saturating_sub(FIRST)).min(FONT.len() - 1)];
+            self.frame[at..at + 5].copy_from_slice(glyph);
+        }
+    }
+
+    fn pixel(&mut self, x: usize, y: usize) {
+        if x < WIDTH && y < 64 {
+            self.frame[1 + y / 8 * WIDTH + x] |= 1 << (y % 8);
+        }
+    }
+}
+
+// the key characters outside the font show as '~', its last glyph
+#[cfg(feature = "display")]
+fn ascii(key: char) -> u8 {
+    u8::try_from(key).unwrap_or(0x7e)
+}
+
+// the columns of a glyph, the top pixel in bit 0
+#[cfg(feature = "display")]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5e, 0x00, 0x00], // '!'
+    [0x00, 0x0e, 0x00, 0x0e, 0x00], // '"'
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // '#'
+    [0x04, 0x2a, 0x7f, 0x2a, 0x10], // '$'
+    [0x00, 0x16, 0x08, 0x34, 0x00], // '%'
+    [0x36, 0x49, 0x36, 0x40, 0x00], // '&'
+    [0x00, 0x00, 0x0e, 0x00, 0x00], // '\''
+    [0x00, 0x3c, 0x42, 0x00, 0x00], // '('
+    [0x00, 0x42, 0x3c, 0x00, 0x00], // ')'
+    [0x54, 0x38, 0x38, 0x54, 0x00], // '*'
+    [0x10, 0x10, 0x7c, 0x10, 0x10], // '+'
+    [0x00, 0x80, 0x60, 0x20, 0x00], // ','
+    [0x10, 0x10, 0x10, 0x10, 0x00], // '-'
+    [0x00, 0x40, 0xe0, 0x40, 0x00], // '.'
+    [0x60, 0x10, 0x08, 0x06, 0x00], // '/'
+    [0x00, 0x3c, 0x42, 0x3c, 0x00], // '0'
+    [0x00, 0x44, 0x7e, 0x40, 0x00], // '1'
+    [0x64, 0x52, 0x52, 0x4c, 0x00], // '2'
+    [0x22, 0x4a, 0x4e, 0x32, 0x00], // '3'
+    [0x18, 0x14, 0x7e, 0x10, 0x00], // '4'
+    [0x2e, 0x4a, 0x4a, 0x32, 0x00], // '5'
+    [0x3c, 0x4a, 0x4a, 0x30, 0x00], // '6'
+    [0x02, 0x62, 0x1a, 0x06, 0x00], // '7'
+    [0x34, 0x4a, 0x4a, 0x34, 0x00], // '8'
+    [0x0c, 0x52, 0x52, 0x3c, 0x00], // '9'
+    [0x00, 0x6c, 0x6c, 0x00, 0x00], // ':'
+    [0x00, 0x80, 0x6c, 0x2c, 0x00], // ';'
+    [0x00, 0x18, 0x24, 0x42, 0x00], // '<'
+    [0x28, 0x28, 0x28, 0x28, 0x00], // '='
+    [0x00, 0x42, 0x24, 0x18, 0x00], // '>'
+    [0x00, 0x04, 0x52, 0x0c, 0x00], // '?'
+    [0x3c, 0x42, 0x99, 0xa5, 0x1e], // '@'
+    [0x7c, 0x12, 0x12, 0x7c, 0x00], // 'A'
+    [0x7e, 0x4a, 0x4a, 0x34, 0x00], // 'B'
+    [0x3c, 0x42, 0x42, 0x24, 0x00], // 'C'
+    [0x7e, 0x42, 0x42, 0x3c, 0x00], // 'D'
+    [0x7e, 0x4a, 0x4a, 0x42, 0x00], // 'E'
+    [0x7e, 0x0a, 0x0a, 0x02, 0x00], // 'F'
+    [0x3c, 0x42, 0x52, 0x34, 0x00], // 'G'
+    [0x7e, 0x08, 0x08, 0x7e, 0x00], // 'H'
+    [0x00, 0x42, 0x7e, 0x42, 0x00], // 'I'
+    [0x20, 0x42, 0x3e, 0x02, 0x00], // 'J'
+    [0x7e, 0x08, 0x34, 0x42, 0x00], // 'K'
+    [0x7e, 0x40, 0x40, 0x40, 0x00], // 'L'
+    [0x7e, 0x0c, 0x0c, 0x7e, 0x00], // 'M'
+    [0x7e, 0x0c, 0x38, 0x7e, 0x00], // 'N'
+    [0x3c, 0x42, 0x42, 0x3c, 0x00], // 'O'
+    [0x7e, 0x12, 0x12, 0x0c, 0x00], // 'P'
+    [0x3c, 0x52, 0x62, 0xbc, 0x00], // 'Q'
+    [0x7e, 0x12, 0x12, 0x6c, 0x00], // 'R'
+    [0x24, 0x4a, 0x52, 0x24, 0x00], // 'S'
+    [0x00, 0x02, 0x7e, 0x02, 0x00], // 'T'
+    [0x3e, 0x40, 0x40, 0x3e, 0x00], // 'U'
+    [0x1e, 0x60, 0x60, 0x1e, 0x00], // 'V'
+    [0x7e, 0x30, 0x30, 0x7e, 0x00], // 'W'
+    [0x66, 0x18, 0x18, 0x66, 0x00], // 'X'
+    [0x06, 0x08, 0x70, 0x08, 0x06], // 'Y'
+    [0x62, 0x52, 0x4a, 0x46, 0x00], // 'Z'
+    [0x00, 0x7e, 0x42, 0x42, 0x00], // '['
+    [0x06, 0x08, 0x10, 0x60, 0x00], // '\\'
+    [0x00, 0x42, 0x42, 0x7e, 0x00], // ']'
+    [0x00, 0x04, 0x02, 0x04, 0x00], // '^'
+    [0x80, 0x80, 0x80, 0x80, 0x00], // '_'
+    [0x00, 0x02, 0x04, 0x00, 0x00], // '`'
+    [0x30, 0x48, 0x48, 0x78, 0x00], // 'a'
+    [0x7e, 0x48, 0x48, 0x30, 0x00], // 'b'
+    [0x00, 0x30, 0x48, 0x48, 0x00], // 'c'
+    [0x30, 0x48, 0x48, 0x7e, 0x00], // 'd'
+    [0x30, 0x68, 0x58, 0x10, 0x00], // 'e'
+    [0x10, 0x7c, 0x12, 0x04, 0x00], // 'f'
+    [0x10, 0xa8, 0xa8, 0x70, 0x00], // 'g'
+    [0x7e, 0x08, 0x08, 0x70, 0x00], // 'h'
+    [0x00, 0x48, 0x7a, 0x40, 0x00], // 'i'
+    [0x00, 0x40, 0x80, 0x7a, 0x00], // 'j'
+    [0x7e, 0x10, 0x10, 0x68, 0x00], // 'k'
+    [0x00, 0x42, 0x7e, 0x40, 0x00], // 'l'
+    [0x78, 0x08, 0x70, 0x08, 0x70], // 'm'
+    [0x78, 0x08, 0x08, 0x70, 0x00], // 'n'
+    [0x30, 0x48, 0x48, 0x30, 0x00], // 'o'
+    [0xf8, 0x28, 0x28, 0x10, 0x00], // 'p'
+    [0x10, 0x28, 0x28, 0xf8, 0x00], // 'q'
+    [0x78, 0x10, 0x08, 0x10, 0x00], // 'r'
+    [0x00, 0x50, 0x58, 0x28, 0x00], // 's'
+    [0x08, 0x3e, 0x48, 0x20, 0x00], // 't'
+    [0x38, 0x40, 0x40, 0x78, 0x00], // 'u'
+    [0x00, 0x38, 0x40, 0x38, 0x00], // 'v'
+    [0x38, 0x40, 0x30, 0x40, 0x38], // 'w'
+    [0x48, 0x30, 0x30, 0x48, 0x00], // 'x'
+    [0x58, 0xa0, 0xa0, 0x78, 0x00], // 'y'
+    [0x48, 0x68, 0x58, 0x48, 0x00], // 'z'
+    [0x08, 0x2a, 0x55, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7e, 0x00, 0x00], // '|'
+    [0x41, 0x55, 0x2a, 0x08, 0x00], // '}'
+    [0x04, 0x02, 0x04, 0x02, 0x00], // '~'
+];
+
+/// Stand-in without the `display` feature: there never is a display, so the
+/// update task is never started.
+#[cfg(not(feature = "display"))]
+pub struct StatusDisplay;
+
+#[cfg(not(feature = "display"))]
+#[derive(Debug)]
+pub struct NoDisplay;
+
+#[cfg(not(feature = "display"))]
+impl StatusDisplay {
+    pub fn render(&mut self, _model: &DisplayModel, _uptime_ms: u32) -> Result<(), NoDisplay> {
+        Err(NoDisplay)
+    }
+}
@@ -0,0 +1,32 @@
+//! `DUMP` of the `dump` feature, the matrix as a grid in the format of
+//! `keypad_core::dump`: the debounced keys, the raw ones of a scan and the debounce
+//! counters, a line per row after a `DUMP 4x4` header.
+//!
+//! The frame and the counters belong to the scanner, so `run_command` asks it for
+//! them and wakes it. Right after the next frame it debounces, the scanner scans
+//! the matrix once more for the raw keys, which leaves out the keys held by
+//! `PRESS`, and hands the snapshot to `feature_job` to write the lines. A parked
+//! matrix isn't scanned, the reply is `ERR parked` then.
+
+use crate::command::ReplyTo;
+use core::cell::Cell;
+use cortex_m::interrupt::{self, Mutex};
+pub use keypad_core::dump::Snapshot;
+
+// where the requested one goes
+static REQUESTED: Mutex<Cell<Option<ReplyTo>>> = Mutex::new(Cell::new(None));
+
+/// The snapshot for a `DUMP`, or the error reply there is instead.
+pub type Dumped = Result<Snapshot, &'static str>;
+
+/// The scanner takes the next frame for `reply_to`, a later request replaces an
+/// earlier one.
+pub fn request(reply_to: ReplyTo) {
+    interrupt::free(|cs| REQUESTED.borrow(cs).set(Some(reply_to)));
+}
+
+/// Where the snapshot of this frame goes if one was requested, once. For the
+/// scanner.
+pub fn take_request() -> Option<ReplyTo> {
+    interrupt::free(|cs| REQUESTED.borrow(cs).take())
+}
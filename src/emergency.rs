@@ -0,0 +1,249 @@
+//! Debounced emergency button, active low, on an EXTI line of its own.
+//!
+//! An edge only disarms the interrupt, the caller samples the level after
+//! [`SETTLE_MS`] into a [`PinDebouncer`]. Until the samples settle the interrupt
+//! fires again at once for another one, after that it's re-armed for the opposite
+//! edge of the debounced state.
+
+#[cfg(feature = "buzzer")]
+use crate::app::beep;
+use crate::app::{
+    button_check, emergency_latch, emergency_release, log_history, now_ms, send_led_message,
+    wake_scanner,
+};
+use crate::blink;
+#[cfg(feature = "buzzer")]
+use crate::buzzer;
+#[cfg(feature = "i2c-slave")]
+use crate::i2c_slave;
+use crate::keypad::{self, KeyState, PinDebouncer, Thresholds};
+use crate::led_mode::{LedMessage, LedMode};
+use crate::lifetime;
+use crate::logging;
+#[cfg(feature = "midi")]
+use crate::midi;
+#[cfg(feature = "relays")]
+use crate::relays;
+use crate::spawn::Counted;
+use crate::timing;
+#[cfg(feature = "midi")]
+use crate::uart;
+use crate::usb::Usb;
+use rtic::mutex::prelude::*;
+use serde::Serialize;
+use stm32f1xx_hal::gpio::{Edge, ErasedPin, ExtiPin, Input, PinExt, PullUp};
+use stm32f1xx_hal::pac::EXTI;
+
+pub use keypad_core::debounce::Edge as ButtonEdge;
+
+/// Time between the samples of the level after an edge.
+pub const SETTLE_MS: u32 = 10;
+
+// samples of a steady level an edge takes, 30 ms either way
+const THRESHOLDS: Thresholds = Thresholds::symmetric(3);
+
+pub struct EmergencyButton {
+    pin: ErasedPin<Input<PullUp>>,
+    exti: EXTI,
+    debouncer: PinDebouncer,
+    // confirmed presses since boot
+    presses: u32,
+}
+
+impl EmergencyButton {
+    /// Takes the pin already set up as an EXTI source and arms it for a press.
+    pub fn new(pin: ErasedPin<Input<PullUp>>, exti: EXTI) -> Self {
+        let mut button = Self {
+            pin,
+            exti,
+            debouncer: PinDebouncer::new(THRESHOLDS),
+            presses: 0,
+        };
+        button.arm(Edge::Falling);
+        button
+    }
+
+    fn arm(&mut self, edge: Edge) {
+        self.pin.trigger_on_edge(&mut self.exti, edge);
+        self.pin.enable_interrupt(&mut self.exti);
+        // the level may have changed before the edge got armed, the software
+        // trigger makes sure that change is checked as well, and so does a
+        // debouncer still counting
+        if self.pin.is_low() != self.debouncer.is_pressed() || !self.debouncer.is_settled() {
+            let line = 1 << self.pin.pin_id();
+            // SAFETY: sets the bit of the button's line alone
+            self.exti
+                .swier
+                .modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        }
+    }
+
+    /// Whether an edge waits for [`EmergencyButton::on_edge`], for an EXTI
+    /// interrupt shared with other lines.
+    pub fn pending(&self) -> bool {
+        self.pin.check_interrupt()
+    }
+
+    /// Called from the EXTI interrupt, ignores further edges until [`EmergencyButton::check`].
+    pub fn on_edge(&mut self) {
+        self.pin.disable_interrupt(&mut self.exti);
+        self.pin.clear_interrupt_pending_bit();
+    }
+
+    /// Samples the level and re-arms the interrupt, returns the debounced edge.
+    pub fn check(&mut self) -> Option<ButtonEdge> {
+        let edge = self.debouncer.update(self.pin.is_low());
+        if edge == Some(ButtonEdge::Pressed) {
+            self.presses = self.presses.wrapping_add(1);
+        }
+        let pressed = self.debouncer.is_pressed();
+        self.arm(if pressed { Edge::Rising } else { Edge::Falling });
+        edge
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.debouncer.is_pressed()
+    }
+
+    pub fn presses(&self) -> u32 {
+        self.presses
+    }
+}
+
+/// Keys held when the emergency stop latched, reported by `STATUS`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    pub at_ms: u32,
+    // debounced key state
+    pub keys: KeyState,
+}
+
+/// An edge of the button from its EXTI interrupt, only starts the debounce.
+pub fn edge(button: &mut impl Mutex<T = EmergencyButton>) {
+    button.lock(|button| button.on_edge());
+    // can't be pending, the interrupt stays off until the check ran
+    let _ = button_check::spawn_after(timing::BUTTON_SETTLE);
+}
+
+/// A settled edge of the button, the body of `button_check`. A press shorter than
+/// `timing::EMERGENCY_LATCH` pauses or resumes the scanning, `emergency_latch`
+/// takes a longer one. While latched, holding the button again for
+/// `timing::EMERGENCY_HOLD` clears the stop.
+pub fn button_check(mut ctx: button_check::Context) {
+    let (edge, presses) = ctx
+        .shared
+        .emergency_button
+        .lock(|button| (button.check(), button.presses()));
+    let latched = ctx.shared.emergency.lock(|emergency| *emergency);
+    match edge {
+        Some(ButtonEdge::Pressed) if latched => {
+            let hold = timing::EMERGENCY_HOLD;
+            // a pending check for an earlier press fails on the press count anyway
+            let _ = emergency_release::spawn_after(hold, presses);
+        }
+        Some(ButtonEdge::Pressed) => {
+            *ctx.local.pressed_at = now_ms();
+            let hold = timing::EMERGENCY_LATCH;
+            *ctx.local.latch_handle = emergency_latch::spawn_after(hold).ok();
+        }
+        // a long press latched the stop, or does so right now
+        Some(ButtonEdge::Released) if latched => {}
+        Some(ButtonEdge::Released) => {
+            let held_ms = now_ms().wrapping_sub(*ctx.local.pressed_at);
+            // a failed cancel means the latch ran since the check above
+            let pending = ctx
+                .local
+                .latch_handle
+                .take()
+                .is_some_and(|handle| handle.cancel().is_ok());
+            if !pending {
+                return;
+            }
+            // released right at the threshold, ahead of the timeout
+            if held_ms >= const { timing::ms(timing::EMERGENCY_LATCH) } {
+                emergency_latch::spawn().or_count();
+                return;
+            }
+            let paused = ctx.shared.scan_paused.lock(|paused| {
+                *paused = !*paused;
+                *paused
+            });
+            // a waiting scanner has to park or unpark the columns
+            let mut scanner = (
+                ctx.shared.scan_mode,
+                ctx.shared.stop_handle,
+                ctx.shared.clock,
+            );
+            scanner.lock(wake_scanner);
+            if paused {
+                log!("scanning paused, button pressed for {} ms", held_ms);
+            } else {
+                log!("scanning resumed");
+            }
+        }
+        None => {}
+    }
+}
+
+/// Latches the stop, the body of `emergency_latch`. It doesn't wait for the
+/// release.
+pub fn emergency_latch(mut ctx: emergency_latch::Context) {
+    ctx.shared.emergency.lock(|emergency| *emergency = true);
+    #[cfg(feature = "relays")]
+    relays::stop(now_ms());
+    // the host would keep the keys and notes held through the latch
+    ctx.shared.usb.lock(Usb::release_all);
+    #[cfg(feature = "midi")]
+    midi::release_all(|message| uart::write_bytes(&message));
+    // the stop takes over a pause, the scanning resumes once it is cleared
+    ctx.shared.scan_paused.lock(|paused| *paused = false);
+    ctx.shared.blink_handle.lock(blink::stop);
+    // a waiting scanner has every column driven active, it has to park them
+    let mut scanner = (
+        ctx.shared.scan_mode,
+        ctx.shared.stop_handle,
+        ctx.shared.clock,
+    );
+    scanner.lock(wake_scanner);
+    send_led_message(LedMessage::Enter(LedMode::Emergency));
+    log!("Emergency STOP!");
+    let snapshot = Snapshot {
+        at_ms: now_ms(),
+        keys: keypad::read_key_state(),
+    };
+    ctx.shared
+        .last_emergency
+        .lock(|last| *last = Some(snapshot));
+    log!("keys {}", snapshot.keys);
+    // what led up to the stop, the lock holds off nothing this task doesn't
+    ctx.shared.key_history.lock(|history| log_history(history));
+    let mut lifetime = (ctx.shared.backup_domain, ctx.shared.counter);
+    lifetime.lock(|backup_domain, blinks| lifetime::save(backup_domain, *blinks));
+    // the latch only keeps the scanner parked and the blink stopped until
+    // `emergency_release`, the lower priorities run on. The flush gets these lines
+    // out before them, the board may well be switched off right after the stop
+    logging::flush();
+    #[cfg(feature = "i2c-slave")]
+    i2c_slave::latch_emergency_stop();
+    #[cfg(feature = "buzzer")]
+    beep(buzzer::EMERGENCY_TONE);
+}
+
+/// Clears the latch if the press that scheduled it is still held, the body of
+/// `emergency_release`.
+pub fn emergency_release(mut ctx: emergency_release::Context, presses: u32) {
+    let held = ctx
+        .shared
+        .emergency_button
+        .lock(|button| button.is_pressed() && button.presses() == presses);
+    if !held {
+        return;
+    }
+    ctx.shared.emergency.lock(|emergency| *emergency = false);
+    #[cfg(feature = "relays")]
+    relays::release();
+    send_led_message(LedMessage::Leave(LedMode::Emergency));
+    log!("emergency stop cleared, resuming");
+    // a second release can't be scheduled, it needs a new press while latched
+    ctx.shared.blink_handle.lock(blink::start);
+}
@@ -0,0 +1,70 @@
+//! Rotary encoder with a push switch next to the matrix, all active low with
+//! pull-ups, see `board` for the pins.
+//!
+//! A and B raise EXTI15_10 on every edge and run through a full-step
+//! quadrature state machine, which only reports a step once the whole gray code
+//! sequence up to the next detent was seen. Contact bounce moves it back and
+//! forth inside the sequence, so it neither loses nor doubles steps. The switch
+//! is one of the buttons of `crate::input`; its line only wakes the scanner, see
+//! `crate::sleep`.
+
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncoderEvent {
+    Clockwise,
+    CounterClockwise,
+    Pressed,
+}
+
+const START: u8 = 0;
+const CW_FINAL: u8 = 1;
+const CW_BEGIN: u8 = 2;
+const CW_NEXT: u8 = 3;
+const CCW_BEGIN: u8 = 4;
+const CCW_FINAL: u8 = 5;
+const CCW_NEXT: u8 = 6;
+// step reported by a transition, or'ed into the next state
+const CW: u8 = 0x10;
+const CCW: u8 = 0x20;
+
+// next state indexed by the current state and `b << 1 | a`, the detent reads 0b11
+const TRANSITIONS: [[u8; 4]; 7] = [
+    // START
+    [START, CW_BEGIN, CCW_BEGIN, START],
+    // CW_FINAL
+    [CW_NEXT, START, CW_FINAL, START | CW],
+    // CW_BEGIN
+    [CW_NEXT, CW_BEGIN, START, START],
+    // CW_NEXT
+    [CW_NEXT, CW_BEGIN, CW_FINAL, START],
+    // CCW_BEGIN
+    [CCW_NEXT, START, CCW_BEGIN, START],
+    // CCW_FINAL
+    [CCW_NEXT, CCW_FINAL, START, START | CCW],
+    // CCW_NEXT
+    [CCW_NEXT, CCW_FINAL, CCW_BEGIN, START],
+];
+
+pub struct Quadrature {
+    state: u8,
+}
+
+impl Quadrature {
+    pub const fn new() -> Self {
+        Self { state: START }
+    }
+
+    /// Feeds the current pin levels, `true` is high, and returns a completed step.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<EncoderEvent> {
+        let input = (b as usize) << 1 | a as usize;
+        let next = TRANSITIONS[(self.state & 0x0f) as usize][input];
+        self.state = next & 0x0f;
+        match next & 0x30 {
+            CW => Some(EncoderEvent::Clockwise),
+            CCW => Some(EncoderEvent::CounterClockwise),
+            _ => None,
+        }
+    }
+}
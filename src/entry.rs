@@ -0,0 +1,123 @@
+//! Entry mode, the keypad as a number pad: digits are appended, '*' deletes the
+//! last one and held for a second clears them all, '#' confirms. 'A' to 'D' are
+//! ignored. Turned on with `ENTRY ON`, or `ENTRY PIN` to check the digits against
+//! the PIN of `crate::pin` instead, and off with `ENTRY OFF`. While it is on, the
+//! serial link carries what was entered instead of the key events.
+
+use crate::app::{entry_mode, now_ms, send_led_message};
+use crate::event::{EventKind, InputEvent, KeyEvent};
+use crate::gesture::LONG_PRESS_MS;
+use crate::led_mode::{Flash, LedMessage, LedMode};
+use crate::pin::{self, Pin, Verdict};
+use rtic::Mutex;
+
+pub const MAX_DIGITS: usize = 12;
+
+// holding '*' this long clears the entry, it arrives as the long press
+const _: () = assert!(LONG_PRESS_MS == 1000);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntryMode {
+    Off,
+    Number,
+    Pin,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Entered {
+    Complete(u32),
+    Pin(Pin),
+    // a digit past `MAX_DIGITS`, or a number past `u32::MAX` confirmed
+    Rejected,
+}
+
+pub struct Entry {
+    mode: EntryMode,
+    digits: heapless::String<MAX_DIGITS>,
+}
+
+impl Entry {
+    pub const fn new() -> Self {
+        Self {
+            mode: EntryMode::Off,
+            digits: heapless::String::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode != EntryMode::Off
+    }
+
+    /// A change of the mode starts over with no digits.
+    pub fn set_mode(&mut self, mode: EntryMode) {
+        self.mode = mode;
+        self.digits.clear();
+    }
+
+    pub fn handle(&mut self, event: &KeyEvent) -> Option<Entered> {
+        match (event.kind, event.key) {
+            (EventKind::Pressed, '0'..='9') => {
+                self.digits.push(event.key).err().map(|_| Entered::Rejected)
+            }
+            (EventKind::Pressed, '*') => {
+                // a byte each, `pop` would decode the last character as UTF-8
+                let len = self.digits.len().saturating_sub(1);
+                self.digits.truncate(len);
+                None
+            }
+            (EventKind::LongPressed, '*') => {
+                self.digits.clear();
+                None
+            }
+            (EventKind::Pressed, '#') if !self.digits.is_empty() => {
+                let entered = match self.mode {
+                    EntryMode::Pin => Pin::new(&self.digits).map(Entered::Pin),
+                    _ => self.digits.parse().ok().map(Entered::Complete),
+                };
+                self.digits.clear();
+                Some(entered.unwrap_or(Entered::Rejected))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The key events while the entry mode is on, the body of `entry_mode`. A
+/// confirmed number or the right PIN goes back into the event queue.
+pub fn entry_mode(mut ctx: entry_mode::Context, event: KeyEvent) {
+    let entered = match ctx.shared.entry.lock(|entry| entry.handle(&event)) {
+        Some(Entered::Complete(value)) => Some(InputEvent::EntryComplete(value)),
+        Some(Entered::Pin(entered)) => {
+            match ctx
+                .shared
+                .pin_lock
+                .lock(|lock| lock.check(&entered, now_ms()))
+            {
+                Verdict::Granted => {
+                    send_led_message(LedMessage::Flash(Flash::Granted));
+                    Some(InputEvent::AccessGranted)
+                }
+                Verdict::Denied(failures) => {
+                    log!("wrong PIN, {} of {}", failures, pin::MAX_FAILURES);
+                    send_led_message(LedMessage::Flash(Flash::Reject));
+                    None
+                }
+                Verdict::LockedOut => {
+                    log!("wrong PIN, locked out for {} s", pin::LOCKOUT_MS / 1000);
+                    send_led_message(LedMessage::Enter(LedMode::Lockout));
+                    None
+                }
+            }
+        }
+        Some(Entered::Rejected) => {
+            send_led_message(LedMessage::Flash(Flash::Reject));
+            None
+        }
+        None => None,
+    };
+    if let Some(entered) = entered {
+        ctx.shared
+            .event_producer
+            .lock(|producer| producer.push(entered));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::config_mode::ConfigInput;
+use crate::encoder::EncoderEvent;
+use crate::input::ButtonEvent;
+use crate::joystick::JoystickEvent;
+use keypad_core::queue::{self, Overflow};
+
+pub use keypad_core::event::{EventKind, KeyEvent};
+pub use keypad_core::queue::dropped;
+
+// the capacity of `event_router`
+pub const EVENT_CAPACITY: usize = 15;
+
+// key events kept for `HISTORY`, a power of two
+pub const HISTORY_LEN: usize = 32;
+
+/// Everything that goes through the event queue to `event_router`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InputEvent {
+    Key(KeyEvent),
+    // a key event played back by a macro, see `crate::macros`
+    Playback(KeyEvent),
+    Encoder(EncoderEvent),
+    Joystick(JoystickEvent),
+    // one of the discrete buttons of `crate::input`
+    Button(ButtonEvent),
+    // a number confirmed in `crate::entry`
+    EntryComplete(u32),
+    // the right PIN was entered, see `crate::pin`
+    AccessGranted,
+    // for the config mode, see `crate::config_mode`
+    #[cfg_attr(not(feature = "config-mode"), allow(dead_code))]
+    Config(ConfigInput),
+    // events were dropped right before this one, see `EventProducer`
+    QueueOverflow,
+    // the release of a key held past the cap, for the backend alone, see
+    // `crate::hold_cap`
+    #[cfg(feature = "hold-cap")]
+    HoldCapped(KeyEvent),
+}
+
+impl From<KeyEvent> for InputEvent {
+    fn from(event: KeyEvent) -> Self {
+        InputEvent::Key(event)
+    }
+}
+
+impl From<EncoderEvent> for InputEvent {
+    fn from(event: EncoderEvent) -> Self {
+        InputEvent::Encoder(event)
+    }
+}
+
+impl From<ButtonEvent> for InputEvent {
+    fn from(event: ButtonEvent) -> Self {
+        InputEvent::Button(event)
+    }
+}
+
+impl From<JoystickEvent> for InputEvent {
+    fn from(event: JoystickEvent) -> Self {
+        InputEvent::Joystick(event)
+    }
+}
+
+/// The latest [`HISTORY_LEN`] key events off the queue, whether a host listened or
+/// not. A plain copy, so it can be taken out of a lock in one go.
+#[derive(Copy, Clone)]
+pub struct History {
+    events: [KeyEvent; HISTORY_LEN],
+    // slot of the next event
+    head: usize,
+    len: usize,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        Self {
+            events: [KeyEvent::new(0, EventKind::Pressed); HISTORY_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Keeps `event`, dropping the oldest one once full.
+    pub fn write(&mut self, event: KeyEvent) {
+        self.events[self.head] = event;
+        self.head = (self.head + 1) % HISTORY_LEN;
+        self.len = (self.len + 1).min(HISTORY_LEN);
+    }
+
+    /// The events kept, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &KeyEvent> {
+        let oldest = self.head + HISTORY_LEN - self.len;
+        (oldest..oldest + self.len).map(|slot| &self.events[slot % HISTORY_LEN])
+    }
+}
+
+impl Overflow for InputEvent {
+    const OVERFLOW: Self = InputEvent::QueueOverflow;
+}
+
+/// Write end of the event queue, the messages of `event_router`, see
+/// [`keypad_core::queue::EventProducer`].
+pub struct EventProducer(queue::EventProducer<EVENT_CAPACITY>);
+
+impl EventProducer {
+    pub const fn new() -> Self {
+        Self(queue::EventProducer::new())
+    }
+
+    /// Returns `true` if the event was spawned.
+    pub fn push(&mut self, event: impl Into<InputEvent>) -> bool {
+        self.push_event(event.into())
+    }
+
+    // a copy for every type pushed doesn't fit into the flash
+    #[inline(never)]
+    fn push_event(&mut self, event: InputEvent) -> bool {
+        self.0.push(event, |event| {
+            crate::app::event_router::spawn(event).is_ok()
+        })
+    }
+
+    /// For `event_router`, once for every event.
+    pub fn taken(&mut self) {
+        self.0.taken();
+    }
+
+    /// Whether `event_router` has events left to take.
+    pub fn pending(&self) -> bool {
+        self.0.pending()
+    }
+}
@@ -0,0 +1,516 @@
+//! What the commands of `crate::command` do, the body of `run_command`. Most of
+//! them reply with a line of their own, the others with `OK` or the error.
+
+#[cfg(feature = "ambient")]
+use crate::ambient;
+#[cfg(feature = "time-sync")]
+use crate::app::now_u64;
+#[cfg(any(feature = "debug-inject", feature = "debug-bounce", feature = "dump"))]
+use crate::app::wake_scanner;
+use crate::app::{
+    log_history, now_ms, play, run_command, save_settings, send_led_message, send_reply,
+    switch_output, user_reset, Reply,
+};
+#[cfg(feature = "bind")]
+use crate::bind;
+#[cfg(feature = "debug-bounce")]
+use crate::bounce;
+use crate::command::{self, Command, CommandError, ReplyTo, StatusReport};
+#[cfg(feature = "dump")]
+use crate::dump;
+#[cfg(feature = "subscribe")]
+use crate::entry::EntryMode;
+use crate::event;
+#[cfg(feature = "hold-cap")]
+use crate::hold_cap;
+use crate::identity;
+#[cfg(feature = "debug-inject")]
+use crate::inject;
+#[cfg(feature = "key-debounce")]
+use crate::key_debounce;
+use crate::keymap::LAYERS;
+use crate::keypad::{self, COLUMNS};
+use crate::led_mode::{LedMessage, LedMode};
+use crate::lifetime;
+use crate::logging::{Char, Text};
+use crate::pin::PinLock;
+#[cfg(feature = "relays")]
+use crate::relays;
+use crate::reset::Request;
+#[cfg(feature = "subscribe")]
+use crate::route;
+use crate::rtc;
+#[cfg(any(feature = "text", feature = "rtt"))]
+use crate::sensors::Celsius;
+#[cfg(feature = "servo")]
+use crate::servo;
+use crate::spawn;
+#[cfg(feature = "stack-watch")]
+use crate::stack;
+use crate::stats::KeyStats;
+#[cfg(feature = "time-sync")]
+use crate::time_sync;
+#[cfg(feature = "uart-config")]
+use crate::timing;
+#[cfg(feature = "uart-config")]
+use crate::uart;
+#[cfg(feature = "debug-inject")]
+use crate::watchdog;
+#[cfg(not(feature = "text"))]
+use crate::wire::{self, Message};
+use core::fmt::Write;
+#[cfg(feature = "bind")]
+use keypad_core::binding::Binding;
+#[cfg(feature = "time-sync")]
+use keypad_core::drift::Measurement;
+use rtic::mutex::prelude::*;
+
+/// Runs `command` and replies to `reply_to`. `SAVE` and `DUMP` leave the reply to
+/// the task that finishes them.
+pub fn run_command(
+    mut ctx: run_command::Context,
+    command: Result<Command, CommandError>,
+    reply_to: ReplyTo,
+) {
+    let reply = match command {
+        Ok(Command::Led(led, on)) => {
+            ctx.shared.status_leds.lock(|leds| leds.set(led, on));
+            "OK"
+        }
+        Ok(Command::LedToggle(led)) => {
+            ctx.shared.status_leds.lock(|leds| leds.toggle(led));
+            "OK"
+        }
+        // the scanner takes the new period with its next deadline
+        Ok(Command::ScanRate(period)) => {
+            let thresholds = ctx.shared.debounce.lock(|debounce| *debounce);
+            #[cfg(feature = "key-debounce")]
+            let thresholds = thresholds.with_keys(&key_debounce::table());
+            if command::debounce_fits(period, thresholds) {
+                ctx.shared
+                    .scan_period_ms
+                    .lock(|scan_period| *scan_period = period);
+                "OK"
+            } else {
+                CommandError::TooSlow.reply()
+            }
+        }
+        Ok(Command::Keymap(layer)) => {
+            ctx.shared
+                .layers
+                .lock(|layers| layers.set_base(usize::from(layer)));
+            "OK"
+        }
+        Ok(Command::Map { row, col, key }) => {
+            ctx.shared.layers.lock(|layers| layers.map(row, col, key));
+            "OK"
+        }
+        Ok(Command::MapReset) => {
+            ctx.shared.layers.lock(|layers| layers.reset(LAYERS));
+            "OK"
+        }
+        Ok(Command::MapShow) => {
+            let (base, keymap, edited) = ctx.shared.layers.lock(|layers| {
+                let base = layers.base();
+                (base, layers.keymaps()[base], layers.is_edited())
+            });
+            let mut line = Reply::new();
+            let saved = if edited { " unsaved" } else { "" };
+            let _ = write!(line, "MAP layer {}{}", base, Text(saved));
+            send_reply(reply_to, &line);
+            for keys in keymap.0 {
+                line.clear();
+                for (col, key) in keys.iter().enumerate() {
+                    let gap = if col == 0 { "" } else { " " };
+                    let _ = write!(line, "{}{}", Text(gap), Char(*key));
+                }
+                send_reply(reply_to, &line);
+            }
+            return;
+        }
+        #[cfg(feature = "bind")]
+        Ok(Command::Bind { index, binding }) => {
+            bind::set(index, binding);
+            "OK"
+        }
+        #[cfg(feature = "bind")]
+        Ok(Command::BindShow) => {
+            let bindings = bind::table();
+            let bound = bindings.iter().filter(|&&binding| binding != Binding::Key);
+            let mut line = Reply::new();
+            let _ = write!(line, "BIND {} keys", bound.count());
+            send_reply(reply_to, &line);
+            for (index, binding) in bindings.iter().enumerate() {
+                if *binding != Binding::Key {
+                    line.clear();
+                    let _ = write!(line, "{} {} {}", index / COLUMNS, index % COLUMNS, binding);
+                    send_reply(reply_to, &line);
+                }
+            }
+            return;
+        }
+        #[cfg(feature = "relays")]
+        Ok(Command::Relay { index, on }) => match relays::set(index, on, now_ms()) {
+            true => "OK",
+            false => "ERR emergency stop",
+        },
+        Ok(Command::Debounce(thresholds)) => {
+            let period = ctx.shared.scan_period_ms.lock(|period| *period);
+            if command::debounce_fits(period, thresholds) {
+                ctx.shared.debounce.lock(|debounce| *debounce = thresholds);
+                "OK"
+            } else {
+                CommandError::TooSlow.reply()
+            }
+        }
+        #[cfg(feature = "key-debounce")]
+        Ok(Command::KeyDebounce { row, col, ms }) => {
+            let period = ctx.shared.scan_period_ms.lock(|period| *period);
+            match key_debounce::set(row, col, ms, period) {
+                true => "OK",
+                false => CommandError::TooSlow.reply(),
+            }
+        }
+        Ok(Command::Brightness(brightness)) => {
+            ctx.shared
+                .status_leds
+                .lock(|leds| leds.set_brightness(brightness));
+            #[cfg(feature = "ambient")]
+            ambient::set_auto(false);
+            "OK"
+        }
+        // the next sample slews the leds over
+        #[cfg(feature = "ambient")]
+        Ok(Command::BrightnessAuto) => {
+            ambient::set_auto(true);
+            "OK"
+        }
+        #[cfg(feature = "ambient")]
+        Ok(Command::AmbientPoint {
+            index,
+            raw,
+            brightness,
+        }) => match ambient::set_point(index, raw, brightness) {
+            true => "OK",
+            false => CommandError::Unknown.reply(),
+        },
+        #[cfg(feature = "breathe")]
+        Ok(Command::Breathe(breathing)) => {
+            ctx.shared
+                .status_leds
+                .lock(|leds| leds.set_breathing(breathing));
+            "OK"
+        }
+        #[cfg(feature = "hold-cap")]
+        Ok(Command::HoldCap(cap_ms)) => {
+            hold_cap::set_cap(cap_ms);
+            "OK"
+        }
+        #[cfg(feature = "hold-cap")]
+        Ok(Command::HoldCapOutput(output, capped)) => {
+            hold_cap::set_output(output, capped);
+            "OK"
+        }
+        Ok(Command::Bootloader) => match user_reset::spawn(Request::Bootloader, false) {
+            Ok(()) => "OK",
+            Err(_) => "ERR busy",
+        },
+        // replies itself once the flash is written
+        Ok(Command::Save) => match save_settings::spawn(Some(reply_to)) {
+            Ok(()) => return,
+            Err(_) => "ERR busy",
+        },
+        Ok(Command::Piano(enabled)) => {
+            if let Some(note) = ctx.shared.piano.lock(|piano| piano.set_enabled(enabled)) {
+                ctx.shared.buzzer.lock(|buzzer| play(buzzer, note));
+            }
+            "OK"
+        }
+        Ok(Command::Entry(mode)) => {
+            ctx.shared.entry.lock(|entry| entry.set_mode(mode));
+            #[cfg(feature = "subscribe")]
+            ctx.shared
+                .layers
+                .lock(|layers| route::grab_entry(layers, mode != EntryMode::Off));
+            ctx.shared.pin_lock.lock(PinLock::relock);
+            "OK"
+        }
+        Ok(Command::Output(mode)) => {
+            let mut shared = (&mut ctx.shared.output, &mut ctx.shared.usb);
+            shared.lock(|output, usb| switch_output(output, usb, mode));
+            "OK"
+        }
+        #[cfg(feature = "macros")]
+        Ok(Command::MacroRecord(key)) => {
+            match ctx
+                .shared
+                .macros
+                .lock(|macros| macros.record(key, now_ms()))
+            {
+                Ok(()) => "OK",
+                Err(error) => error.reply(),
+            }
+        }
+        Ok(Command::PinSet(pin)) => {
+            if ctx.shared.pin_lock.lock(|lock| lock.set_pin(pin)) {
+                "OK"
+            } else {
+                "ERR locked"
+            }
+        }
+        #[cfg(feature = "debug-inject")]
+        Ok(Command::Press { index, hold_ms }) => {
+            let scan_period = ctx.shared.scan_period_ms.lock(|period| *period);
+            let thresholds = ctx.shared.debounce.lock(|debounce| *debounce);
+            let hold_ms = hold_ms.max(inject::min_hold_ms(scan_period, thresholds.press));
+            let now = now_ms();
+            ctx.shared
+                .injected
+                .lock(|injected| injected.press(index, now, hold_ms));
+            let mut scanner = (
+                ctx.shared.scan_mode,
+                ctx.shared.stop_handle,
+                ctx.shared.clock,
+            );
+            scanner.lock(wake_scanner);
+            "OK"
+        }
+        #[cfg(feature = "debug-bounce")]
+        Ok(Command::Bounce(key)) => {
+            bounce::capture(key);
+            // a waiting scanner sets the capture up right away
+            let mut scanner = (
+                ctx.shared.scan_mode,
+                ctx.shared.stop_handle,
+                ctx.shared.clock,
+            );
+            scanner.lock(wake_scanner);
+            "OK"
+        }
+        #[cfg(feature = "debug-inject")]
+        Ok(Command::WatchdogTest) => {
+            log!("watchdog test, stalling the scanner");
+            watchdog::stall_scanner();
+            "OK"
+        }
+        Ok(Command::Joystick) => {
+            let [x, y] = ctx.shared.joystick.lock(|joystick| joystick.raw());
+            let mut line = Reply::new();
+            let _ = write!(line, "JOY {} {}", u32::from(x), u32::from(y));
+            send_reply(reply_to, &line);
+            return;
+        }
+        Ok(Command::TimeSet(seconds)) => {
+            #[cfg(feature = "time-sync")]
+            let set = ctx
+                .shared
+                .rtc
+                .lock(|rtc| time_sync::set(rtc, seconds, now_u64()));
+            #[cfg(not(feature = "time-sync"))]
+            let set = ctx.shared.rtc.lock(|rtc| rtc.set_time(seconds));
+            if set {
+                "OK"
+            } else {
+                // the LSE is still starting
+                "ERR busy"
+            }
+        }
+        #[cfg(feature = "time-sync")]
+        Ok(Command::TimeSync { seconds, trim }) => {
+            let at = now_u64();
+            let Some(synced) = ctx
+                .shared
+                .rtc
+                .lock(|rtc| time_sync::sync(rtc, seconds, trim, at))
+            else {
+                send_reply(reply_to, "ERR busy");
+                return;
+            };
+            if synced.measurement == Measurement::Backwards {
+                log!("time set back past the previous sync, no drift measured");
+            }
+            let mut line = Reply::new();
+            let _ = write!(line, "{}", synced);
+            send_reply(reply_to, &line);
+            return;
+        }
+        Ok(Command::Id) => {
+            send_led_message(LedMessage::Enter(LedMode::Identify));
+            let mut line = Reply::new();
+            let _ = write!(line, "ID {} {}", identity::uid(), Text(identity::FIRMWARE));
+            send_reply(reply_to, &line);
+            return;
+        }
+        Ok(Command::Keys) => {
+            let keys = keypad::read_key_state();
+            let mut line = Reply::new();
+            let _ = write!(line, "KEYS {:04x}", keys.0);
+            send_reply(reply_to, &line);
+            return;
+        }
+        // the reply comes with the next frame, a sleeping scanner is woken for it
+        #[cfg(feature = "dump")]
+        Ok(Command::Dump) => {
+            dump::request(reply_to);
+            let mut scanner = (
+                ctx.shared.scan_mode,
+                ctx.shared.stop_handle,
+                ctx.shared.clock,
+            );
+            scanner.lock(wake_scanner);
+            return;
+        }
+        Ok(Command::Stats) => {
+            let stats = ctx.shared.key_stats.lock(|stats| *stats);
+            let mut line = Reply::new();
+            let _ = write!(
+                line,
+                "STATS presses={} releases={} max_held={} lifetime={}",
+                stats.total_presses(),
+                stats.releases(),
+                stats.max_held(),
+                lifetime::keypresses()
+            );
+            send_reply(reply_to, &line);
+            for keys in stats.presses().chunks(COLUMNS) {
+                line.clear();
+                for (col, presses) in keys.iter().enumerate() {
+                    let gap = if col == 0 { "" } else { " " };
+                    let _ = write!(line, "{}{}", Text(gap), presses);
+                }
+                send_reply(reply_to, &line);
+            }
+            return;
+        }
+        Ok(Command::StatsReset) => {
+            ctx.shared.key_stats.lock(KeyStats::reset);
+            "OK"
+        }
+        Ok(Command::History) => {
+            // copied, the lock would hold off the emergency stop
+            log_history(&ctx.shared.key_history.lock(|history| *history));
+            "OK"
+        }
+        Ok(Command::Jitter) => {
+            let lateness = ctx.shared.scan_lateness.lock(core::mem::take);
+            let [ms1, ms2, ms5, rest] = lateness.buckets;
+            let mut line = Reply::new();
+            let _ = write!(
+                line,
+                "JITTER max={}us <1ms={} <2ms={} <5ms={} >=5ms={}",
+                lateness.max_us, ms1, ms2, ms5, rest
+            );
+            send_reply(reply_to, &line);
+            return;
+        }
+        #[cfg(feature = "stack-watch")]
+        Ok(Command::Stack) => {
+            let stack = stack::usage();
+            let mut line = Reply::new();
+            let _ = write!(line, "STACK used={} free={}", stack.used, stack.free);
+            send_reply(reply_to, &line);
+            return;
+        }
+        // a transfer cut off by the switch would go out garbled, the reply goes
+        // out over the new framing
+        #[cfg(feature = "uart-config")]
+        Ok(Command::UartSet(framing)) => {
+            match uart::set(framing, now_ms() + timing::ms(timing::UART_CONFIRM)) {
+                Ok(()) => "OK, UART CONFIRM to keep it",
+                Err(uart::Busy) => "ERR busy",
+            }
+        }
+        // only the UART itself proves the new framing gets through
+        #[cfg(feature = "uart-config")]
+        Ok(Command::UartConfirm) if reply_to != ReplyTo::Uart => "ERR confirm over the UART",
+        #[cfg(feature = "uart-config")]
+        Ok(Command::UartConfirm) => match uart::confirm() {
+            true => "OK",
+            false => "ERR nothing to confirm",
+        },
+        Ok(Command::Status) => {
+            let diagnostics = ctx.shared.diagnostics.lock(|diagnostics| *diagnostics);
+            let report = StatusReport {
+                uptime_ms: now_ms(),
+                blinks: ctx.shared.counter.lock(|counter| *counter),
+                scans: ctx.shared.scans.lock(|scans| *scans),
+                dropped_events: event::dropped(),
+                temperature_tenths: diagnostics.temperature_tenths,
+                vdda_mv: diagnostics.vdda_mv,
+                last_emergency: ctx.shared.last_emergency.lock(|last| *last),
+                cpu_load_percent: ctx.shared.cpu_load_percent.lock(|load| *load),
+                spawn_failures: spawn::failures(),
+                scan_period_ms: ctx.shared.scan_period_ms.lock(|period| *period),
+                stuck_keys: ctx.shared.stuck_keys.lock(|keys| *keys),
+                reset_causes: ctx.shared.reset_causes.lock(|causes| *causes),
+                time: rtc::seconds(),
+                #[cfg(feature = "servo")]
+                servos: servo::positions(),
+                #[cfg(feature = "ambient")]
+                ambient: ambient::raw(),
+                #[cfg(feature = "relays")]
+                relays: relays::states(),
+            };
+            send_status(reply_to, &report);
+            return;
+        }
+        Err(error) => error.reply(),
+    };
+    send_reply(reply_to, reply);
+}
+
+// the binary link gets the report as it is, the text ones a line of it
+fn send_status(reply_to: ReplyTo, report: &StatusReport) {
+    match reply_to {
+        #[cfg(not(feature = "text"))]
+        ReplyTo::Uart => wire::send(&Message::Status(*report)),
+        #[cfg(any(feature = "text", feature = "rtt"))]
+        _ => send_reply(reply_to, &status_line(report)),
+    }
+}
+
+#[cfg(any(feature = "text", feature = "rtt"))]
+fn status_line(report: &StatusReport) -> heapless::String<160> {
+    let mut line = heapless::String::<160>::new();
+    let _ = write!(
+        line,
+        "STATUS uptime={} time={} blinks={} scans={} scan_period={}ms dropped={} temp={} vdda={} cpu={} spawn_failures={} stuck={:04x} reset={}",
+        report.uptime_ms,
+        report.time,
+        report.blinks,
+        report.scans,
+        report.scan_period_ms,
+        report.dropped_events,
+        Celsius(report.temperature_tenths),
+        u32::from(report.vdda_mv),
+        report.cpu_load_percent,
+        report.spawn_failures,
+        report.stuck_keys.0,
+        Text(&report.reset_causes.text())
+    );
+    let _ = match report.last_emergency {
+        Some(snapshot) => write!(
+            line,
+            " estop={} keys={:04x}",
+            snapshot.at_ms, snapshot.keys.0
+        ),
+        None => write!(line, " estop=none"),
+    };
+    #[cfg(feature = "servo")]
+    let _ = write!(line, " servo={},{}", report.servos[0], report.servos[1]);
+    #[cfg(feature = "ambient")]
+    let _ = write!(line, " ambient={}", u32::from(report.ambient));
+    // relay 1 first
+    #[cfg(feature = "relays")]
+    {
+        let _ = line.push_str(" relays=");
+        for index in 0..relays::RELAYS {
+            let _ = line.push(if report.relays & 1 << index != 0 {
+                '1'
+            } else {
+                '0'
+            });
+        }
+    }
+    line
+}
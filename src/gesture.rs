@@ -0,0 +1 @@
+pub use keypad_core::gesture::{DOUBLE_TAP_MS, LONG_PRESS_MS, REPEAT_DELAY_MS, REPEAT_INTERVAL_MS};
@@ -0,0 +1,115 @@
+//! The body of `event_stats`, the health report once a [`timing::STATS_PERIOD`]:
+//! the CPU load, the internal sensors, the drops of the event queue, the UART and
+//! the RTT channel, and every `timing::LIFETIME_SAVE_STATS` runs the lifetime
+//! counters saved and a `HEARTBEAT` line logged. It also carries the slow chores
+//! of the same priority that would each need a task of their own: the boot
+//! summary on its first run and the countdown of a PIN lockout.
+
+#[cfg(feature = "ambient")]
+use crate::ambient;
+#[cfg(feature = "uart-config")]
+use crate::app::now_ms;
+use crate::app::{clear_error, event_stats, now_u64, raise_error};
+use crate::boot;
+use crate::event;
+use crate::led_mode::ErrorCode;
+use crate::lifetime;
+use crate::monotonic;
+use crate::pin;
+use crate::profile;
+use crate::rtc::Rtc;
+use crate::spawn::{self, Counted};
+#[cfg(feature = "stack-watch")]
+use crate::stack;
+use crate::timing;
+use crate::uart;
+use rtic::mutex::prelude::*;
+
+/// Samples and reports, then schedules the next run, the body of `event_stats`.
+pub fn event_stats(mut ctx: event_stats::Context) {
+    if let Some(stages) = ctx.local.boot_stages.take() {
+        boot::check(stages, ctx.shared.scans.lock(|scans| *scans));
+    }
+    let now = now_u64();
+    let elapsed = now.wrapping_sub(*ctx.local.load_sampled_at) as u32;
+    *ctx.local.load_sampled_at = now;
+    let load = profile::load_percent(ctx.shared.idle_us.lock(core::mem::take), elapsed);
+    ctx.shared.cpu_load_percent.lock(|shared| *shared = load);
+    ctx.shared
+        .display_model
+        .lock(|model| model.set_cpu_load(load));
+    log!("cpu {}%", load);
+
+    let sensors = ctx.local.sensors;
+    ctx.shared.adc.lock(|adc| sensors.sample(adc));
+    if let Some(diagnostics) = sensors.diagnostics() {
+        ctx.shared.diagnostics.lock(|shared| *shared = diagnostics);
+    }
+    #[cfg(feature = "ambient")]
+    if let Some(brightness) = ctx.shared.adc.lock(ambient::sample) {
+        ctx.shared
+            .status_leds
+            .lock(|leds| leds.slew_brightness(brightness));
+    }
+
+    // the error stays up until a second passed without a drop
+    let dropped = event::dropped();
+    let overflowing = dropped != *ctx.local.reported_drops;
+    if overflowing {
+        log!("dropped events: {}", dropped);
+        *ctx.local.reported_drops = dropped;
+    }
+    if overflowing != core::mem::replace(ctx.local.overflowing, overflowing) {
+        if overflowing {
+            raise_error(ErrorCode::QueueOverflow);
+        } else {
+            clear_error(ErrorCode::QueueOverflow);
+        }
+    }
+    let uart_dropped = uart::dropped();
+    if uart_dropped != *ctx.local.reported_uart_drops {
+        log!("dropped uart lines: {}", uart_dropped);
+        *ctx.local.reported_uart_drops = uart_dropped;
+    }
+    // the mark never comes back down, the error stays up
+    #[cfg(feature = "stack-watch")]
+    let stack = stack::usage();
+    #[cfg(feature = "stack-watch")]
+    if stack.free < stack::LOW_FREE && !core::mem::replace(ctx.local.stack_low, true) {
+        log!("stack used {} B, {} B free", stack.used, stack.free);
+        raise_error(ErrorCode::StackLow);
+    }
+    #[cfg(feature = "uart-config")]
+    if uart::expire(now_ms()) {
+        log!("uart not confirmed, the framing before is back");
+    }
+    let rtt_dropped = ctx.shared.rtt_events.lock(|channel| channel.dropped());
+    if rtt_dropped != *ctx.local.reported_rtt_drops {
+        log!("dropped rtt events: {}", rtt_dropped);
+        *ctx.local.reported_rtt_drops = rtt_dropped;
+    }
+    *ctx.local.since_save += 1;
+    if *ctx.local.since_save == timing::LIFETIME_SAVE_STATS {
+        *ctx.local.since_save = 0;
+        let mut shared = (&mut ctx.shared.backup_domain, &mut ctx.shared.counter);
+        shared.lock(|backup_domain, blinks| lifetime::save(backup_domain, *blinks));
+        // also keeps the RTC counter away from its wrap, see `Rtc::carry`
+        ctx.shared.rtc.lock(Rtc::carry);
+        // greppable in long captures, fields are only ever added at the end
+        let seconds = monotonic::div(monotonic::to_ms(now), 1000) as u32;
+        log!(
+            "HEARTBEAT uptime={}:{:02}:{:02} blinks={} presses={} dropped={} spawn_failures={}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            ctx.shared.counter.lock(|counter| *counter),
+            lifetime::keypresses(),
+            dropped,
+            spawn::failures()
+        );
+    }
+
+    pin::lockout_countdown(&mut ctx.shared.pin_lock);
+
+    event_stats::spawn_after(timing::STATS_PERIOD).or_count();
+}
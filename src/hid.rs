@@ -0,0 +1,249 @@
+#[cfg(feature = "bind")]
+use crate::bind;
+#[cfg(any(feature = "media", not(feature = "nkro")))]
+use crate::event::{EventKind, KeyEvent};
+#[cfg(feature = "nkro")]
+use crate::keypad::KeyState;
+#[cfg(feature = "media")]
+use crate::keypad::COLUMNS;
+#[cfg(any(feature = "media", not(feature = "nkro")))]
+use crate::keypad::KEYS;
+#[cfg(not(feature = "nkro"))]
+use heapless::Vec;
+#[cfg(feature = "bind")]
+use keypad_core::binding::Binding;
+#[cfg(feature = "nkro")]
+use keypad_core::report::{self, BOOT_REPORT_SIZE};
+#[cfg(not(feature = "nkro"))]
+use usbd_hid::descriptor::KeyboardReport;
+#[cfg(feature = "media")]
+use usbd_hid::descriptor::{MediaKey, MediaKeyboardReport};
+
+// reported in every slot when more keys are held than the report can carry
+#[cfg(not(feature = "nkro"))]
+const ROLLOVER: u8 = 0x01;
+#[cfg(not(feature = "nkro"))]
+const REPORT_SLOTS: usize = 6;
+
+/// HID usage id of the keyboard page sent for a keymap character.
+#[cfg(not(feature = "nkro"))]
+pub fn usage(key: char) -> Option<u8> {
+    match key {
+        '1'..='9' => Some(0x1e + (key as u8 - b'1')),
+        '0' => Some(0x27),
+        'A'..='D' => Some(0x04 + (key as u8 - b'A')),
+        'a'..='d' => Some(0x04 + (key as u8 - b'a')),
+        '*' => Some(0x55), // keypad *
+        '#' => Some(0xcc), // keypad #
+        _ => None,
+    }
+}
+
+/// Keys held from the host's point of view, turned into 6KRO boot keyboard reports.
+#[cfg(not(feature = "nkro"))]
+pub struct Keyboard {
+    held: Vec<u8, KEYS>,
+    // the current report still has to reach the host
+    dirty: bool,
+}
+
+#[cfg(not(feature = "nkro"))]
+impl Keyboard {
+    pub const fn new() -> Self {
+        Self {
+            held: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn handle(&mut self, event: &KeyEvent) {
+        // a key bound to a usage of its own, see `crate::bind`
+        #[cfg(feature = "bind")]
+        let usage = match bind::of(event) {
+            Binding::Hid(usage) => Some(usage),
+            _ => usage(event.key),
+        };
+        #[cfg(not(feature = "bind"))]
+        let usage = usage(event.key);
+        let Some(usage) = usage else {
+            return;
+        };
+        match event.kind {
+            EventKind::Pressed if !self.held.contains(&usage) => {
+                self.dirty |= self.held.push(usage).is_ok();
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => {
+                // the order of the keys in a report means nothing to the host
+                if let Some(index) = self.held.iter().position(|&held| held == usage) {
+                    self.held.swap_remove(index);
+                    self.dirty = true;
+                }
+            }
+            // the host generates its own repeats
+            _ => {}
+        }
+    }
+
+    /// Lets go of every key held.
+    pub fn release_all(&mut self) {
+        self.dirty |= !self.held.is_empty();
+        self.held.clear();
+    }
+
+    /// The report to send if the held keys changed since the last sent report.
+    pub fn pending(&self) -> Option<KeyboardReport> {
+        if !self.dirty {
+            return None;
+        }
+
+        let mut keycodes = [0; REPORT_SLOTS];
+        if self.held.len() > REPORT_SLOTS {
+            keycodes = [ROLLOVER; REPORT_SLOTS];
+        } else {
+            keycodes[..self.held.len()].copy_from_slice(&self.held);
+        }
+        Some(KeyboardReport {
+            modifier: 0,
+            reserved: 0,
+            leds: 0,
+            keycodes,
+        })
+    }
+
+    pub fn sent(&mut self) {
+        self.dirty = false;
+    }
+}
+
+/// The keys held as the NKRO keyboard sends them, the debounced key state itself
+/// rather than the keymap characters of the events: see `keypad_core::report`.
+#[cfg(feature = "nkro")]
+pub struct NkroKeyboard {
+    keys: KeyState,
+    dirty: bool,
+}
+
+#[cfg(feature = "nkro")]
+impl NkroKeyboard {
+    pub const fn new() -> Self {
+        Self {
+            keys: KeyState(0),
+            dirty: false,
+        }
+    }
+
+    pub fn set_keys(&mut self, keys: KeyState) {
+        self.dirty |= keys != self.keys;
+        self.keys = keys;
+    }
+
+    pub fn release_all(&mut self) {
+        self.set_keys(KeyState(0));
+    }
+
+    /// The report to send if the keys changed since the last sent report, the
+    /// boot report in `boot` protocol and the bitmap otherwise. Returns the
+    /// report and its length.
+    pub fn pending(&self, boot: bool) -> Option<([u8; BOOT_REPORT_SIZE], usize)> {
+        if !self.dirty {
+            return None;
+        }
+        if boot {
+            return Some((report::boot_report(self.keys), BOOT_REPORT_SIZE));
+        }
+        let mut bytes = [0; BOOT_REPORT_SIZE];
+        let bitmap = report::nkro_report(self.keys);
+        bytes[..bitmap.len()].copy_from_slice(&bitmap);
+        Some((bytes, bitmap.len()))
+    }
+
+    pub fn sent(&mut self) {
+        self.dirty = false;
+    }
+}
+
+/// Consumer page usage of every key in `row * COLUMNS + col` order for `OUTPUT
+/// MEDIA`, zero for the keys that stay on the keyboard: the volume on the 'A', 'B'
+/// and 'C' column, the tracks around play/pause on the bottom row. The keys of
+/// `second-pad` all stay.
+#[cfg(feature = "media")]
+pub const MEDIA_USAGES: [u16; KEYS] = {
+    let mut usages = [0; KEYS];
+    usages[3] = MediaKey::VolumeIncrement as u16;
+    usages[COLUMNS + 3] = MediaKey::VolumeDecrement as u16;
+    usages[2 * COLUMNS + 3] = MediaKey::Mute as u16;
+    usages[3 * COLUMNS] = MediaKey::PrevTrack as u16;
+    usages[3 * COLUMNS + 1] = MediaKey::PlayPause as u16;
+    usages[3 * COLUMNS + 2] = MediaKey::NextTrack as u16;
+    usages
+};
+
+/// The keys of [`MEDIA_USAGES`], left out of the NKRO keyboard's state.
+#[cfg(all(feature = "media", feature = "nkro"))]
+pub const MEDIA_KEYS: KeyState = {
+    let mut keys = 0;
+    let mut key = 0;
+    while key < KEYS {
+        if MEDIA_USAGES[key] != 0 {
+            keys |= 1 << key;
+        }
+        key += 1;
+    }
+    KeyState(keys)
+};
+
+/// The media key held, one at a time: a press takes over from the one before and
+/// only the release of the latest one sends the zero report.
+#[cfg(feature = "media")]
+pub struct MediaKeys {
+    // key position and usage
+    held: Option<(usize, u16)>,
+    dirty: bool,
+}
+
+#[cfg(feature = "media")]
+impl MediaKeys {
+    pub const fn new() -> Self {
+        Self {
+            held: None,
+            dirty: false,
+        }
+    }
+
+    /// Takes the event of a media key and returns whether it was one.
+    pub fn handle(&mut self, event: &KeyEvent) -> bool {
+        let key = usize::from(event.row) * COLUMNS + usize::from(event.col);
+        let usage = MEDIA_USAGES.get(key).copied().unwrap_or(0);
+        if usage == 0 {
+            return false;
+        }
+        match event.kind {
+            EventKind::Pressed => {
+                self.held = Some((key, usage));
+                self.dirty = true;
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong
+                if self.held.is_some_and(|(held, _)| held == key) =>
+            {
+                self.held = None;
+                self.dirty = true;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    pub fn release_all(&mut self) {
+        self.dirty |= self.held.take().is_some();
+    }
+
+    pub fn pending(&self) -> Option<MediaKeyboardReport> {
+        self.dirty.then(|| MediaKeyboardReport {
+            usage_id: self.held.map_or(0, |(_, usage)| usage),
+        })
+    }
+
+    pub fn sent(&mut self) {
+        self.dirty = false;
+    }
+}
@@ -0,0 +1,83 @@
+//! A cap on how long the host sees a key held, the `hold-cap` feature: past
+//! `keypad_core::hold_cap::HOLD_CAP_MS` the USB keyboard, the media keys and the
+//! MIDI notes let go of a key even if it is still down, a safety for the host
+//! side. The scanner checks the held keys every scan and queues the release of
+//! one past the cap as an `InputEvent::HoldCapped`, which only goes to the
+//! backend. The key stays down for the scanner, the logs and the event streams,
+//! which keep showing what the keys do, and its real release is swallowed on the
+//! way to the backend.
+//!
+//! `HOLDCAP 5000` sets the cap, `HOLDCAP MIDI OFF` takes a backend out of it and
+//! `ON` back in, `SAVE` keeps both.
+
+use crate::event::KeyEvent;
+use crate::output::OutputMode;
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+use cortex_m::interrupt::{self, Mutex};
+use keypad_core::hold_cap::HoldCap;
+
+// caps `HOLDCAP` accepts, in milliseconds
+pub const HOLD_CAPS_MS: RangeInclusive<u32> = 1000..=600_000;
+
+/// The backends capped by default, all of them, a bit for each
+/// `OutputMode::to_byte` of the HID, MIDI and media outputs.
+pub const DEFAULT_OUTPUTS: u8 = 1 << 2 | 1 << 4 | 1 << 5;
+
+struct Capped {
+    cap: HoldCap,
+    outputs: u8,
+}
+
+// all zero until `start`, so it takes no flash
+static CAPPED: Mutex<RefCell<Capped>> = Mutex::new(RefCell::new(Capped {
+    cap: HoldCap::new(0),
+    outputs: 0,
+}));
+
+const fn bit(output: OutputMode) -> u8 {
+    1 << output as u8
+}
+
+/// Starts with the saved cap and backends.
+pub fn start(cap_ms: u32, outputs: u8) {
+    interrupt::free(|cs| {
+        *CAPPED.borrow(cs).borrow_mut() = Capped {
+            cap: HoldCap::new(cap_ms),
+            outputs,
+        }
+    });
+}
+
+/// Whether `event` goes on to the `output` backend, it always does to one out of
+/// the cap.
+pub fn pass(output: OutputMode, event: &KeyEvent, now_ms: u32) -> bool {
+    interrupt::free(|cs| {
+        let capped = &mut *CAPPED.borrow(cs).borrow_mut();
+        capped.outputs & bit(output) == 0 || capped.cap.pass(event, now_ms)
+    })
+}
+
+/// Hands `release` the release of every key past the cap.
+pub fn expire(now_ms: u32, release: impl FnMut(KeyEvent)) {
+    interrupt::free(|cs| CAPPED.borrow(cs).borrow_mut().cap.expire(now_ms, release));
+}
+
+pub fn set_cap(cap_ms: u32) {
+    interrupt::free(|cs| CAPPED.borrow(cs).borrow_mut().cap.set_cap(cap_ms));
+}
+
+pub fn set_output(output: OutputMode, capped: bool) {
+    interrupt::free(|cs| {
+        let outputs = &mut CAPPED.borrow(cs).borrow_mut().outputs;
+        *outputs = (*outputs & !bit(output)) | u8::from(capped) << output as u8;
+    });
+}
+
+/// The cap and the capped backends, for `SAVE`.
+pub fn settings() -> (u32, u8) {
+    interrupt::free(|cs| {
+        let capped = CAPPED.borrow(cs).borrow();
+        (capped.cap.cap_ms(), capped.outputs)
+    })
+}
@@ -0,0 +1,249 @@
+//! The keypad as an I2C1 slave (PB6 SCL, PB7 SDA) for another MCU. The master
+//! writes a register number and reads it back:
+//!
+//! * [`REG_KEYS`]: debounced key bitmask, 2 bytes little endian, the bitmap of
+//!   the NKRO report, see `keypad_core::report`
+//! * [`REG_EVENTS`]: pops one pending event, [`EVENT_SIZE`] bytes, kind 0 if none
+//! * [`REG_STATUS`]: [`STATUS_OVERFLOW`] and [`STATUS_EMERGENCY_STOP`] flags
+//!
+//! The data ready line (PB1) is high while events are pending.
+
+use crate::event::KeyEvent;
+use crate::keypad::KeyState;
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use heapless::Deque;
+use keypad_core::report;
+use stm32f1xx_hal::gpio::{Alternate, ErasedPin, OpenDrain, Output, PB6, PB7};
+use stm32f1xx_hal::pac::{I2C1, RCC};
+use stm32f1xx_hal::rcc::{BusClock, Clocks, Enable, Reset};
+
+// 7-bit address the keypad answers on
+pub const ADDRESS: u8 = 0x42;
+
+pub const FIFO_SIZE: usize = 16;
+
+pub const REG_KEYS: u8 = 0;
+pub const REG_EVENTS: u8 = 1;
+pub const REG_STATUS: u8 = 2;
+
+// events were lost because the FIFO was full, cleared by reading the status
+pub const STATUS_OVERFLOW: u8 = 1 << 0;
+// the emergency stop button was pressed since boot
+pub const STATUS_EMERGENCY_STOP: u8 = 1 << 1;
+
+// kind, row in the high and column in the low nibble, key, modifiers
+pub const EVENT_SIZE: usize = 4;
+
+pub type Pins = (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>);
+
+struct Registers {
+    keys: u16,
+    events: Deque<KeyEvent, FIFO_SIZE>,
+    status: u8,
+    ready: Option<ErasedPin<Output>>,
+}
+
+impl Registers {
+    fn update_ready(&mut self) {
+        let pending = !self.events.is_empty();
+        match self.ready.as_mut() {
+            Some(ready) if pending => ready.set_high(),
+            Some(ready) => ready.set_low(),
+            None => {}
+        }
+    }
+}
+
+// written by the scanner and consumer, read from the I2C interrupt
+static REGISTERS: Mutex<RefCell<Registers>> = Mutex::new(RefCell::new(Registers {
+    keys: 0,
+    events: Deque::new(),
+    status: 0,
+    ready: None,
+}));
+
+// the register has a bit for each of the 16 keys of the pad
+pub fn set_keys(keys: KeyState) {
+    interrupt::free(|cs| REGISTERS.borrow(cs).borrow_mut().keys = report::bitmap(keys));
+}
+
+/// Queues `event` for the master, sets [`STATUS_OVERFLOW`] if the FIFO is full.
+pub fn push_event(event: &KeyEvent) {
+    interrupt::free(|cs| {
+        let mut registers = REGISTERS.borrow(cs).borrow_mut();
+        if registers.events.push_back(*event).is_err() {
+            registers.status |= STATUS_OVERFLOW;
+        }
+        registers.update_ready();
+    });
+}
+
+pub fn latch_emergency_stop() {
+    interrupt::free(|cs| REGISTERS.borrow(cs).borrow_mut().status |= STATUS_EMERGENCY_STOP);
+}
+
+fn encode_event(event: &KeyEvent) -> [u8; EVENT_SIZE] {
+    // the chord id doesn't fit, the master reads the keys register instead
+    let kind = event.kind.code();
+    let key = if event.key.is_ascii() {
+        event.key as u8
+    } else {
+        b'?'
+    };
+    [kind, event.row << 4 | event.col, key, event.modifiers.0]
+}
+
+// the bus side is driven from two interrupts, so it lives here rather than in a task
+static SLAVE: Mutex<RefCell<Option<I2cSlave>>> = Mutex::new(RefCell::new(None));
+
+/// Enables I2C1 as a slave at [`ADDRESS`].
+pub fn init(i2c: I2C1, pins: Pins, ready: ErasedPin<Output>, clocks: &Clocks) {
+    let slave = I2cSlave::new(i2c, pins, ready, clocks);
+    interrupt::free(|cs| *SLAVE.borrow(cs).borrow_mut() = Some(slave));
+}
+
+/// Handles the I2C1 event interrupt.
+pub fn on_event() {
+    interrupt::free(|cs| {
+        if let Some(slave) = SLAVE.borrow(cs).borrow_mut().as_mut() {
+            slave.on_event();
+        }
+    });
+}
+
+/// Handles the I2C1 error interrupt.
+pub fn on_error() {
+    interrupt::free(|cs| {
+        if let Some(slave) = SLAVE.borrow(cs).borrow_mut().as_mut() {
+            slave.on_error();
+        }
+    });
+}
+
+struct I2cSlave {
+    i2c: I2C1,
+    _pins: Pins,
+    register: u8,
+    // the next byte written by the master selects the register
+    expect_register: bool,
+    reply: [u8; EVENT_SIZE],
+    reply_len: usize,
+    sent: usize,
+}
+
+impl I2cSlave {
+    fn new(i2c: I2C1, pins: Pins, ready: ErasedPin<Output>, clocks: &Clocks) -> Self {
+        // NOTE(unsafe) only the I2C1 enable and reset bits are touched, like the HAL does
+        let rcc = unsafe { &*RCC::ptr() };
+        I2C1::enable(rcc);
+        I2C1::reset(rcc);
+
+        let freq = I2C1::clock(clocks).to_MHz() as u8;
+        i2c.cr2.write(|w| {
+            unsafe { w.freq().bits(freq) }
+                .itevten()
+                .set_bit()
+                .itbufen()
+                .set_bit()
+                .iterren()
+                .set_bit()
+        });
+        // bit 14 of OAR1 has to be kept at 1 by software
+        i2c.oar1
+            .write(|w| unsafe { w.bits(1 << 14 | (ADDRESS as u32) << 1) });
+        i2c.cr1.write(|w| w.pe().set_bit());
+        // ACK is cleared while the peripheral is disabled, so it is set afterwards
+        i2c.cr1.modify(|_, w| w.ack().set_bit());
+
+        interrupt::free(|cs| {
+            let mut registers = REGISTERS.borrow(cs).borrow_mut();
+            registers.ready = Some(ready);
+            registers.update_ready();
+        });
+
+        Self {
+            i2c,
+            _pins: pins,
+            register: REG_KEYS,
+            expect_register: false,
+            reply: [0; EVENT_SIZE],
+            reply_len: 0,
+            sent: 0,
+        }
+    }
+
+    fn on_event(&mut self) {
+        let sr1 = self.i2c.sr1.read();
+        if sr1.addr().bit_is_set() {
+            // reading SR2 right after SR1 clears ADDR
+            if self.i2c.sr2.read().tra().bit_is_set() {
+                self.prepare_reply();
+            } else {
+                self.expect_register = true;
+            }
+        }
+        if sr1.rx_ne().bit_is_set() {
+            let byte = self.i2c.dr.read().dr().bits();
+            // anything after the register number is ignored, all registers are read only
+            if self.expect_register {
+                self.register = byte;
+                self.expect_register = false;
+            }
+        }
+        if sr1.tx_e().bit_is_set() {
+            let byte = match self.sent < self.reply_len {
+                true => self.reply[self.sent],
+                false => 0xff,
+            };
+            self.sent += 1;
+            self.i2c.dr.write(|w| w.dr().bits(byte));
+        }
+        if sr1.stopf().bit_is_set() {
+            // STOPF is cleared by reading SR1 and then writing CR1
+            self.i2c.cr1.modify(|_, w| w);
+        }
+    }
+
+    // the master NACKs the last byte of every read, so an acknowledge failure is
+    // the normal end of a transfer
+    fn on_error(&mut self) {
+        self.i2c.sr1.modify(|_, w| {
+            w.af()
+                .clear_bit()
+                .berr()
+                .clear_bit()
+                .arlo()
+                .clear_bit()
+                .ovr()
+                .clear_bit()
+        });
+    }
+
+    fn prepare_reply(&mut self) {
+        self.sent = 0;
+        self.reply_len = interrupt::free(|cs| {
+            let mut registers = REGISTERS.borrow(cs).borrow_mut();
+            match self.register {
+                REG_KEYS => {
+                    self.reply[..2].copy_from_slice(&registers.keys.to_le_bytes());
+                    2
+                }
+                REG_EVENTS => {
+                    self.reply = registers
+                        .events
+                        .pop_front()
+                        .map_or([0; EVENT_SIZE], |event| encode_event(&event));
+                    registers.update_ready();
+                    EVENT_SIZE
+                }
+                REG_STATUS => {
+                    self.reply[0] = registers.status;
+                    registers.status &= !STATUS_OVERFLOW;
+                    1
+                }
+                _ => 0,
+            }
+        });
+    }
+}
@@ -0,0 +1,45 @@
+//! What the firmware tells a host about the board it runs on and about itself.
+
+use core::fmt;
+use core::ptr;
+
+/// Crate version, git hash and build date, from `build.rs`.
+pub const FIRMWARE: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " ",
+    env!("FIRMWARE_GIT_HASH"),
+    " ",
+    env!("FIRMWARE_BUILD_DATE")
+);
+
+// the 96 bit unique device ID, in the system memory
+const UID: *const u32 = 0x1fff_f7e8 as *const u32;
+
+/// The unique device ID, displayed as three hex groups, the lowest address first.
+pub struct Uid;
+
+pub fn uid() -> Uid {
+    Uid
+}
+
+impl Uid {
+    fn words(&self) -> [u32; 3] {
+        // SAFETY: the UID registers are always readable, word aligned and read-only
+        [0, 1, 2].map(|word| unsafe { ptr::read_volatile(UID.add(word)) })
+    }
+}
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c] = self.words();
+        write!(f, "{:08x}-{:08x}-{:08x}", a, b, c)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Uid {
+    fn format(&self, f: defmt::Formatter) {
+        let [a, b, c] = self.words();
+        defmt::write!(f, "{=u32:08x}-{=u32:08x}-{=u32:08x}", a, b, c)
+    }
+}
@@ -0,0 +1,69 @@
+//! Simulated key presses from the `PRESS` command, for testing the consumers
+//! without touching the matrix. An injected key is ORed into the raw scan, so it
+//! goes through the debouncer, the layers, the gestures and the event queue like
+//! a real one. Without the `debug-inject` feature nothing can be injected.
+
+#[cfg(feature = "debug-inject")]
+use crate::keypad::KEYS;
+
+/// Hold time of a `PRESS` without one.
+#[cfg(feature = "debug-inject")]
+pub const DEFAULT_HOLD_MS: u32 = 100;
+#[cfg(feature = "debug-inject")]
+pub const MAX_HOLD_MS: u32 = 10_000;
+
+/// Shortest hold the debouncer still sees as a press at this scan period and
+/// press threshold.
+#[cfg(feature = "debug-inject")]
+pub fn min_hold_ms(scan_period_ms: u32, press_threshold: u8) -> u32 {
+    scan_period_ms * (u32::from(press_threshold) + 1)
+}
+
+pub struct Injected {
+    #[cfg(feature = "debug-inject")]
+    mask: u32,
+    // release time of every injected key, in ms since boot
+    #[cfg(feature = "debug-inject")]
+    releases: [u32; KEYS],
+}
+
+impl Injected {
+    #[cfg(feature = "debug-inject")]
+    pub const fn new() -> Self {
+        Self {
+            mask: 0,
+            releases: [0; KEYS],
+        }
+    }
+
+    #[cfg(not(feature = "debug-inject"))]
+    pub const fn new() -> Self {
+        Self {}
+    }
+
+    /// Holds the key with index `row * COLUMNS + col` until `hold_ms` after `now`,
+    /// pressing a held key again only moves its release.
+    #[cfg(feature = "debug-inject")]
+    pub fn press(&mut self, index: usize, now: u32, hold_ms: u32) {
+        self.mask |= 1 << index;
+        self.releases[index] = now.wrapping_add(hold_ms);
+    }
+
+    /// Releases the keys whose hold ran out and returns the ones still held.
+    pub fn poll(&mut self, now: u32) -> u32 {
+        #[cfg(feature = "debug-inject")]
+        {
+            for (index, &release) in self.releases.iter().enumerate() {
+                if now.wrapping_sub(release) as i32 >= 0 {
+                    self.mask &= !(1 << index);
+                }
+            }
+            self.mask
+        }
+        #[cfg(not(feature = "debug-inject"))]
+        {
+            let _ = now;
+            0
+        }
+    }
+}
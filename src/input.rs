@@ -0,0 +1,96 @@
+//! The discrete buttons next to the matrix as input sources: every one is
+//! registered with its [`ButtonId`], read on every scan and debounced by the
+//! matrix's own [`Debouncer`], and its presses and releases go into the event
+//! queue like the keys. The encoder switch goes out as `EncoderEvent::Pressed`,
+//! the others as [`ButtonEvent`]s. The emergency button keeps its EXTI path for
+//! the latch, the queue only has it for the log.
+//!
+//! The buttons are read while the scanner runs, parked included. A press that
+//! comes and goes while it waits for a key in `crate::sleep` is missed.
+//!
+//! A new panel button is its pin in `Board`, an id here, one more [`BUTTONS`] and
+//! its entry in `init`.
+
+use crate::keypad::{self, Debouncer, Thresholds};
+use stm32f1xx_hal::gpio::PinExt;
+
+pub type ButtonId = u8;
+
+pub const EMERGENCY_BUTTON: ButtonId = 0;
+pub const ENCODER_SWITCH: ButtonId = 1;
+
+/// The buttons `init` registers.
+pub const BUTTONS: usize = 2;
+
+/// Something that reads as pressed or not.
+pub trait InputSource {
+    fn is_pressed(&self) -> bool;
+}
+
+/// A button to ground on a pull-up input, read from the input register so the
+/// pin stays with the task that owns it.
+pub struct PinSource {
+    idr: *const u32,
+    mask: u32,
+}
+
+impl PinSource {
+    pub fn of(pin: &impl PinExt) -> Self {
+        // SAFETY: only the address of the register is taken
+        let idr = unsafe { (*keypad::port(pin.port_id())).idr.as_ptr() };
+        Self {
+            idr,
+            mask: 1 << pin.pin_id(),
+        }
+    }
+}
+
+// the input register is read-only and shared by all the tasks anyway
+unsafe impl Send for PinSource {}
+
+impl InputSource for PinSource {
+    fn is_pressed(&self) -> bool {
+        // SAFETY: a read of the input register, which has no side effects
+        unsafe { self.idr.read_volatile() & self.mask == 0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ButtonEvent {
+    pub id: ButtonId,
+    pub pressed: bool,
+}
+
+/// The registered buttons, a debouncer counter each.
+pub struct Buttons<S: InputSource, const N: usize> {
+    sources: [(ButtonId, S); N],
+    debouncer: Debouncer<N>,
+}
+
+impl<S: InputSource, const N: usize> Buttons<S, N> {
+    pub const fn new(sources: [(ButtonId, S); N]) -> Self {
+        Self {
+            sources,
+            debouncer: Debouncer::new(Thresholds::DEFAULT),
+        }
+    }
+
+    /// Reads every button once and hands `emit` the ones the debouncer let change.
+    pub fn poll(&mut self, mut emit: impl FnMut(ButtonEvent)) {
+        let mut raw = 0;
+        for (index, (_, source)) in self.sources.iter().enumerate() {
+            if source.is_pressed() {
+                raw |= 1 << index;
+            }
+        }
+        let was = self.debouncer.state();
+        let state = self.debouncer.update(raw);
+        for (index, &(id, _)) in self.sources.iter().enumerate() {
+            if (was ^ state) & 1 << index != 0 {
+                let pressed = state & 1 << index != 0;
+                emit(ButtonEvent { id, pressed });
+            }
+        }
+    }
+}
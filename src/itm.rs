@@ -0,0 +1,78 @@
+//! ITM trace over SWO on PB3, the `itm` feature, for a probe that prefers SWO
+//! over RTT. The log lines go out on stimulus port [`LOG_PORT`], the key events
+//! as the records of `rtt::encode` on [`EVENT_PORT`]. The ITM adds its local
+//! timestamps and passes the DWT packets on, so a trace viewer lines both up
+//! with the cycle counter.
+//!
+//! SWO runs asynchronous NRZ at [`SWO_BAUD`] off the core clock, the clock
+//! manager retunes it with every switch of the speed. A write waits for the
+//! stimulus FIFO, which the TPIU drains at the baud rate whether a probe listens
+//! or not.
+
+use crate::event::KeyEvent;
+use crate::rtt;
+use cortex_m::interrupt;
+use cortex_m::peripheral::{itm, ITM, TPIU};
+use stm32f1xx_hal::pac::DBGMCU;
+
+/// The SWO baud rate, a divider of both core clocks.
+pub const SWO_BAUD: u32 = 2_000_000;
+
+pub const LOG_PORT: usize = 0;
+pub const EVENT_PORT: usize = 1;
+
+// the CoreSight lock access key and the bits of the Cortex-M3 TRM
+const UNLOCK: u32 = 0xc5ac_ce55;
+const TCR_ITMENA: u32 = 1 << 0;
+const TCR_TSENA: u32 = 1 << 1;
+const TCR_SYNCENA: u32 = 1 << 2;
+const TCR_DWTENA: u32 = 1 << 3;
+const TCR_TRACE_BUS_ID: u32 = 1 << 16;
+const SPPR_NRZ: u32 = 2;
+// the formatter is for the parallel trace port, SWO takes the packets bare
+const FFCR_TRIGIN: u32 = 1 << 8;
+
+/// Hands PB3 to TRACESWO and sets up the TPIU and the two ports, after the
+/// trace was enabled in the DCB.
+pub fn start(dbgmcu: &DBGMCU, tpiu: &mut TPIU, itm: &mut ITM, sysclk_hz: u32) {
+    // TRACE_MODE 0 is the asynchronous SWO
+    dbgmcu
+        .cr
+        .modify(|_, w| unsafe { w.trace_ioen().set_bit().trace_mode().bits(0) });
+    // SAFETY: the trace units are only set up here, before the first write
+    unsafe {
+        tpiu.sppr.write(SPPR_NRZ);
+        tpiu.ffcr.write(FFCR_TRIGIN);
+        itm.lar.write(UNLOCK);
+        itm.tcr
+            .write(TCR_TRACE_BUS_ID | TCR_DWTENA | TCR_SYNCENA | TCR_TSENA | TCR_ITMENA);
+        itm.ter[0].write(1 << LOG_PORT | 1 << EVENT_PORT);
+    }
+    set_clock(sysclk_hz);
+}
+
+/// Keeps SWO at [`SWO_BAUD`] at a new core clock.
+pub fn set_clock(sysclk_hz: u32) {
+    // SAFETY: the prescaler is the TPIU's alone after `start`
+    unsafe { (*TPIU::PTR).acpr.write(sysclk_hz / SWO_BAUD - 1) };
+}
+
+/// Writes `line` and a newline to the log port.
+pub fn write_line(line: &str) {
+    interrupt::free(|_| {
+        let port = stim(LOG_PORT);
+        cortex_m::itm::write_all(port, line.as_bytes());
+        cortex_m::itm::write_all(port, b"\n");
+    });
+}
+
+pub fn write_event(event: &KeyEvent) {
+    interrupt::free(|_| cortex_m::itm::write_all(stim(EVENT_PORT), &rtt::encode(event)));
+}
+
+// every priority level writes, a line or a record goes out whole within a
+// critical section
+fn stim(port: usize) -> &'static mut itm::Stim {
+    // SAFETY: the ports are only written here, with interrupts masked
+    unsafe { &mut (*ITM::PTR).stim[port] }
+}
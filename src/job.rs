@@ -0,0 +1,181 @@
+//! The body of `feature_job`, the runs of the optional features sharing a task:
+//! the display and LCD updates, the joystick and battery samples, the backlight
+//! frames and the replies to `DUMP`. The periodic ones schedule their next run
+//! themselves.
+
+use crate::app::feature_job;
+#[cfg(any(feature = "display", feature = "backlight"))]
+use crate::app::now_ms;
+#[cfg(feature = "display")]
+use crate::app::raise_error;
+#[cfg(feature = "battery")]
+use crate::app::send_led_message;
+#[cfg(feature = "dump")]
+use crate::app::{send_reply, Reply};
+#[cfg(feature = "battery")]
+use crate::battery::{self, Level};
+#[cfg(feature = "dump")]
+use crate::command::ReplyTo;
+#[cfg(feature = "dump")]
+use crate::dump;
+#[cfg(feature = "dump")]
+use crate::keypad::{self, COLUMNS};
+#[cfg(feature = "display")]
+use crate::led_mode::ErrorCode;
+#[cfg(feature = "battery")]
+use crate::led_mode::{LedMessage, LedMode};
+#[cfg(feature = "battery")]
+use crate::logging;
+#[cfg(feature = "lcd")]
+use crate::monotonic;
+#[cfg(any(
+    feature = "display",
+    feature = "lcd",
+    feature = "joystick",
+    feature = "battery",
+    feature = "backlight"
+))]
+use crate::spawn::Counted;
+#[cfg(any(
+    feature = "display",
+    feature = "joystick",
+    feature = "battery",
+    feature = "backlight"
+))]
+use crate::timing;
+#[cfg(feature = "dump")]
+use core::fmt::Write;
+#[cfg(any(
+    feature = "display",
+    feature = "lcd",
+    feature = "joystick",
+    feature = "battery",
+    feature = "backlight"
+))]
+use rtic::mutex::prelude::*;
+
+/// A run of one of the optional features, see `feature_job`. Without any of
+/// them there is no job at all.
+#[derive(Copy, Clone, Debug)]
+pub enum Job {
+    // only spawned once a display answered
+    #[cfg(feature = "display")]
+    DisplayUpdate,
+    // a step of the power-on sequence of the LCD
+    #[cfg(feature = "lcd")]
+    LcdInit(u8),
+    // redraws the typed code, the slow writes stay out of `event_router`
+    #[cfg(feature = "lcd")]
+    LcdRender,
+    #[cfg(feature = "joystick")]
+    JoystickSample,
+    #[cfg(feature = "battery")]
+    BatteryMonitor,
+    #[cfg(feature = "battery")]
+    BatteryHalt,
+    // the transfer itself runs on DMA, being preempted only delays the next frame
+    #[cfg(feature = "backlight")]
+    LedFrame,
+    // the reply to a `DUMP` with the frame the scanner took for it
+    #[cfg(feature = "dump")]
+    Dump(ReplyTo, dump::Dumped),
+}
+
+/// Runs `job`, the body of `feature_job`.
+pub fn feature_job(ctx: feature_job::Context, job: Job) {
+    match (job, ctx) {
+        #[cfg(feature = "display")]
+        (Job::DisplayUpdate, mut ctx) => {
+            // rendering and the I2C transfer take a while, so only a copy is held
+            let model = ctx.shared.display_model.lock(|model| *model);
+            if ctx.local.status_display.render(&model, now_ms()).is_err() {
+                raise_error(ErrorCode::I2cUnreachable);
+                log!("display updates disabled");
+                return;
+            }
+            feature_job::spawn_after(timing::DISPLAY_PERIOD, Job::DisplayUpdate).or_count();
+        }
+        #[cfg(feature = "lcd")]
+        (Job::LcdInit(step), ctx) => {
+            // each step waits for the controller by rescheduling
+            let mut shared = (ctx.shared.lcd, ctx.shared.delay);
+            if let Some(wait) = shared.lock(|lcd, delay| lcd.init_step(step, delay)) {
+                feature_job::spawn_after(monotonic::millis(wait), Job::LcdInit(step + 1))
+                    .or_count();
+            }
+        }
+        #[cfg(feature = "lcd")]
+        (Job::LcdRender, ctx) => {
+            let mut shared = (ctx.shared.lcd, ctx.shared.delay);
+            shared.lock(|lcd, delay| lcd.render(delay));
+        }
+        #[cfg(feature = "joystick")]
+        (Job::JoystickSample, ctx) => {
+            let mut adc = ctx.shared.adc;
+            let raw = adc.lock(|adc| ctx.local.joystick_pins.sample(adc));
+            let mut shared = (ctx.shared.joystick, ctx.shared.event_producer);
+            shared.lock(|joystick, producer| {
+                joystick.update(raw, |event| {
+                    producer.push(event);
+                })
+            });
+            feature_job::spawn_after(timing::JOYSTICK_PERIOD, Job::JoystickSample).or_count();
+        }
+        #[cfg(feature = "battery")]
+        (Job::BatteryMonitor, mut ctx) => {
+            let vdda_mv = ctx
+                .shared
+                .diagnostics
+                .lock(|diagnostics| diagnostics.vdda_mv);
+            let pin = ctx.local.battery_pin;
+            let mv = ctx.shared.adc.lock(|adc| pin.sample(adc, vdda_mv));
+            let level = ctx.shared.battery.lock(|battery| battery.update(mv));
+            // printed as a `u32`, which saves the flash of a `u16` formatter
+            let mv = u32::from(mv);
+            match level {
+                Some(Level::Low) => {
+                    log!("battery low: {} mV", mv);
+                    send_led_message(LedMessage::Enter(LedMode::LowBattery));
+                }
+                Some(Level::Ok) => {
+                    log!("battery ok again: {} mV", mv);
+                    send_led_message(LedMessage::Leave(LedMode::LowBattery));
+                }
+                Some(Level::Critical) => {
+                    log!("battery critical: {} mV, halting", mv);
+                    feature_job::spawn_after(timing::HALT_DELAY, Job::BatteryHalt).or_count();
+                    return;
+                }
+                None => {}
+            }
+            feature_job::spawn_after(timing::BATTERY_PERIOD, Job::BatteryMonitor).or_count();
+        }
+        #[cfg(feature = "battery")]
+        (Job::BatteryHalt, _) => {
+            logging::flush();
+            battery::standby();
+        }
+        #[cfg(feature = "backlight")]
+        (Job::LedFrame, mut ctx) => {
+            let frame = ctx
+                .shared
+                .backlight
+                .lock(|backlight| backlight.frame(now_ms()));
+            ctx.local.strip.write(&frame);
+            feature_job::spawn_after(timing::FRAME_PERIOD, Job::LedFrame).or_count();
+        }
+        #[cfg(feature = "dump")]
+        (Job::Dump(reply_to, Ok(snapshot)), _) => {
+            let mut line = Reply::new();
+            let _ = write!(line, "DUMP {}x{}", keypad::ROWS, COLUMNS);
+            send_reply(reply_to, &line);
+            for row in 0..keypad::ROWS {
+                line.clear();
+                let _ = write!(line, "{}", snapshot.row(row));
+                send_reply(reply_to, &line);
+            }
+        }
+        #[cfg(feature = "dump")]
+        (Job::Dump(reply_to, Err(error)), _) => send_reply(reply_to, error),
+    }
+}
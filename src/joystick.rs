@@ -0,0 +1,136 @@
+//! Two axis analog joystick on ADC1, X on PA0 and Y on PA1. Both pins are matrix
+//! columns on rev A, so there the `joystick` feature needs the columns moved off
+//! them by `shift-register` or `mcp23017`.
+//!
+//! Each axis has a dead zone around its center. Leaving the dead zone reports a
+//! direction once, the axis has to come back past the threshold minus the
+//! hysteresis before it counts as centered again.
+
+#[cfg(feature = "joystick")]
+use embedded_hal::adc::OneShot;
+use serde::Serialize;
+use stm32f1xx_hal::adc::Adc;
+#[cfg(feature = "joystick")]
+use stm32f1xx_hal::gpio::{Analog, PA0, PA1};
+use stm32f1xx_hal::pac::ADC1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoystickEvent {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Dead zone and the directions of one axis, all values in raw 12-bit counts.
+pub struct AxisConfig {
+    pub center: u16,
+    // distance from `center` a reading has to exceed to leave the dead zone
+    pub threshold: u16,
+    pub hysteresis: u16,
+    pub low: JoystickEvent,
+    pub high: JoystickEvent,
+}
+
+/// X then Y, tune `center` with the raw values `JOYSTICK` reports.
+pub const AXES: [AxisConfig; 2] = [
+    AxisConfig {
+        center: 2048,
+        threshold: 1000,
+        hysteresis: 150,
+        low: JoystickEvent::Left,
+        high: JoystickEvent::Right,
+    },
+    AxisConfig {
+        center: 2048,
+        threshold: 1000,
+        hysteresis: 150,
+        low: JoystickEvent::Down,
+        high: JoystickEvent::Up,
+    },
+];
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Zone {
+    Center,
+    Low,
+    High,
+}
+
+impl AxisConfig {
+    fn zone(&self, current: Zone, raw: u16) -> Zone {
+        let offset = raw as i32 - self.center as i32;
+        let enter = self.threshold as i32;
+        let leave = enter - self.hysteresis as i32;
+        match current {
+            Zone::Low if offset < -leave => Zone::Low,
+            Zone::High if offset > leave => Zone::High,
+            _ if offset < -enter => Zone::Low,
+            _ if offset > enter => Zone::High,
+            _ => Zone::Center,
+        }
+    }
+}
+
+pub struct Joystick {
+    zones: [Zone; 2],
+    raw: [u16; 2],
+}
+
+impl Joystick {
+    pub const fn new() -> Self {
+        Self {
+            zones: [Zone::Center; 2],
+            raw: [0; 2],
+        }
+    }
+
+    /// The last readings of X and Y.
+    pub fn raw(&self) -> [u16; 2] {
+        self.raw
+    }
+
+    /// Feeds one sample of both axes, `emit` gets the directions that were entered.
+    pub fn update(&mut self, raw: [u16; 2], mut emit: impl FnMut(JoystickEvent)) {
+        self.raw = raw;
+        for ((config, zone), raw) in AXES.iter().zip(self.zones.iter_mut()).zip(raw) {
+            let next = config.zone(*zone, raw);
+            if next != *zone {
+                match next {
+                    Zone::Low => emit(config.low),
+                    Zone::High => emit(config.high),
+                    Zone::Center => {}
+                }
+                *zone = next;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "joystick")]
+pub struct JoystickPins {
+    pub x: PA0<Analog>,
+    pub y: PA1<Analog>,
+}
+
+#[cfg(feature = "joystick")]
+impl JoystickPins {
+    pub fn sample(&mut self, adc: &mut Adc<ADC1>) -> [u16; 2] {
+        // the one-shot conversion can't fail
+        let x = adc.read(&mut self.x).unwrap_or(0);
+        let y = adc.read(&mut self.y).unwrap_or(0);
+        [x, y]
+    }
+}
+
+/// Stand-in without the `joystick` feature, sampling is never started.
+#[cfg(not(feature = "joystick"))]
+pub struct JoystickPins;
+
+#[cfg(not(feature = "joystick"))]
+impl JoystickPins {
+    pub fn sample(&mut self, _adc: &mut Adc<ADC1>) -> [u16; 2] {
+        [0; 2]
+    }
+}
@@ -0,0 +1,51 @@
+//! Debounce thresholds of single keys, the `key-debounce` feature, for a slower
+//! switch in the matrix like a big arcade button. `DEBOUNCE 1 2 120` gives the key
+//! on row 1, column 2 a threshold of its own for both edges, 120 ms rounded up to
+//! scans of the scan period of the moment, `DEBOUNCE 1 2 0` puts it back on the
+//! shared thresholds of `DEBOUNCE`. The scanner takes the table on every scan and
+//! `SAVE` keeps it.
+//!
+//! The table holds scans, a later `SCANRATE` keeps them and changes the time they
+//! take. It turns a period down that would take a key past
+//! `command::MAX_DEBOUNCE_LATENCY_MS`.
+
+use crate::command::{self, DEBOUNCE_THRESHOLDS};
+use crate::keypad::{Thresholds, COLUMNS, KEYS};
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+
+// 0 for the shared thresholds, so it takes no flash
+static TABLE: Mutex<RefCell<[u8; KEYS]>> = Mutex::new(RefCell::new([0; KEYS]));
+
+/// Starts with the saved table.
+pub fn start(table: [u8; KEYS]) {
+    interrupt::free(|cs| *TABLE.borrow(cs).borrow_mut() = table);
+}
+
+pub fn table() -> [u8; KEYS] {
+    interrupt::free(|cs| *TABLE.borrow(cs).borrow())
+}
+
+/// Whether every key of the table is within `DEBOUNCE_THRESHOLDS`, for a loaded one.
+pub fn is_valid(table: &[u8; KEYS]) -> bool {
+    table
+        .iter()
+        .all(|&own| own == 0 || DEBOUNCE_THRESHOLDS.contains(&own))
+}
+
+/// Gives the key `ms` of its own at the scan period, false if that is past
+/// `DEBOUNCE_THRESHOLDS` or `command::debounce_fits`.
+pub fn set(row: usize, col: usize, ms: u32, scan_period_ms: u32) -> bool {
+    let scans = match u8::try_from(ms.div_ceil(scan_period_ms)) {
+        Ok(0) => 0,
+        Ok(scans)
+            if DEBOUNCE_THRESHOLDS.contains(&scans)
+                && command::debounce_fits(scan_period_ms, Thresholds::symmetric(scans)) =>
+        {
+            scans
+        }
+        _ => return false,
+    };
+    interrupt::free(|cs| TABLE.borrow(cs).borrow_mut()[row * COLUMNS + col] = scans);
+    true
+}
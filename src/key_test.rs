@@ -0,0 +1,45 @@
+//! The key test of the production line, the `key-test` feature: [`POWER_ON_KEY`]
+//! held at boot starts it instead of the normal operation. Every press shows the
+//! index of the key, row by row from 0 to 15, on the leds as `LedMode::KeyTest`:
+//! red for bit 2, green for bit 1 and blue for bit 0, blinking for bit 3 and
+//! steady otherwise. Keys 0 and 8 light nothing. The keys of `second-pad` show
+//! their index on that pad, the log has the one in the matrix. Once every key was pressed all
+//! leds flash three times and the board restarts into the normal operation.
+//!
+//! While it runs the keys go nowhere else, no host needs to be attached.
+
+use crate::keypad::{key_bit, KeyState, KEYS};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// '1' of the base layer.
+pub const POWER_ON_KEY: KeyState = KeyState(key_bit(0, 0));
+
+/// The `LedMode::KeyTest` before the first press, and after the last one.
+pub const NO_KEY: u8 = 0xff;
+pub const PASSED: u8 = KEYS as u8;
+
+const ALL_KEYS: u32 = u32::MAX >> (32 - KEYS);
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// a bit of every key pressed so far
+static SEEN: AtomicU32 = AtomicU32::new(0);
+
+pub fn start() {
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Counts the press of key `index`, true once it completes the matrix.
+pub fn press(index: usize) -> bool {
+    let seen = SEEN.load(Ordering::Relaxed);
+    SEEN.store(seen | 1 << index, Ordering::Relaxed);
+    seen != ALL_KEYS && seen | 1 << index == ALL_KEYS
+}
+
+/// The distinct keys pressed so far.
+pub fn seen() -> u32 {
+    SEEN.load(Ordering::Relaxed).count_ones()
+}
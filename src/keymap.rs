@@ -0,0 +1,128 @@
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{key_bit, KeyState, COLUMNS, KEYS, ROWS};
+use keypad_core::sequence::Sequence;
+
+pub use keypad_core::event::UNKNOWN_KEY;
+
+/// Characters printed on the keys of a `COLS` x `ROWS` pad, indexed by `[row][col]`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Keymap<const COLS: usize, const ROWS: usize>(pub [[char; COLS]; ROWS]);
+
+/// Whether `key` can go on a key with `MAP`, anything printable in ASCII. That
+/// leaves a byte per key for the flash.
+pub fn is_mappable(key: char) -> bool {
+    key.is_ascii_graphic()
+}
+
+impl<const COLS: usize, const ROWS: usize> Keymap<COLS, ROWS> {
+    pub fn lookup(&self, row: usize, col: usize) -> char {
+        self.0
+            .get(row)
+            .and_then(|keys| keys.get(col))
+            .copied()
+            .unwrap_or(UNKNOWN_KEY)
+    }
+}
+
+// `LAYOUT`, `LAYER_COUNT`, `LAYERS`, `CHORDS`, `LOCKS` and `SEQUENCES`, generated
+// from `keymap.toml`, the first layer is the base one
+include!(concat!(env!("OUT_DIR"), "/keymap_generated.rs"));
+
+// holding the 'D' key switches to `FN_LAYER`, the key itself never produces events
+pub const FN_KEY: (usize, usize) = (3, 3);
+pub const FN_LAYER: usize = 1;
+
+/// Resolves key events against the keymap of the active layer. The keymaps can be
+/// edited at runtime.
+pub struct Layers {
+    keymaps: [Keymap<COLUMNS, ROWS>; LAYER_COUNT],
+    // active while the fn key is up
+    base: usize,
+    active: usize,
+    // layer every key was pressed on, so its release resolves to the same character
+    pressed_on: [usize; KEYS],
+    // the keymaps changed since they were last saved
+    edited: bool,
+}
+
+impl Layers {
+    pub const fn new(keymaps: [Keymap<COLUMNS, ROWS>; LAYER_COUNT]) -> Self {
+        Self {
+            keymaps,
+            base: 0,
+            active: 0,
+            pressed_on: [0; KEYS],
+            edited: false,
+        }
+    }
+
+    pub fn keymaps(&self) -> &[Keymap<COLUMNS, ROWS>; LAYER_COUNT] {
+        &self.keymaps
+    }
+
+    /// Puts `key` on `row`/`col` of the base layer.
+    pub fn map(&mut self, row: usize, col: usize, key: char) {
+        self.keymaps[self.base].0[row][col] = key;
+        self.edited = true;
+    }
+
+    /// Goes back to `keymaps`, like the compiled-in ones.
+    pub fn reset(&mut self, keymaps: [Keymap<COLUMNS, ROWS>; LAYER_COUNT]) {
+        self.edited |= keymaps != self.keymaps;
+        self.keymaps = keymaps;
+    }
+
+    /// Whether the keymaps changed since they were loaded or saved.
+    pub fn is_edited(&self) -> bool {
+        self.edited
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.edited = false;
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// The character at `row`, `col` of the base layer, whatever it stands for.
+    pub fn base_key(&self, row: usize, col: usize) -> char {
+        self.keymaps[self.base].lookup(row, col)
+    }
+
+    /// Switches the layer used while the fn key is up, takes effect right away
+    /// unless the fn key is held.
+    pub fn set_base(&mut self, layer: usize) {
+        if self.active == self.base {
+            self.active = layer;
+        }
+        self.base = layer;
+    }
+
+    /// Fills in the character of `event`, or returns `None` for the fn key which is
+    /// consumed here to switch layers.
+    pub fn resolve(&mut self, mut event: KeyEvent) -> Option<KeyEvent> {
+        let (row, col) = (event.row as usize, event.col as usize);
+        if (row, col) == FN_KEY {
+            self.active = if event.kind == EventKind::Pressed {
+                FN_LAYER
+            } else {
+                self.base
+            };
+            return None;
+        }
+
+        let index = row * COLUMNS + col;
+        let layer = match event.kind {
+            EventKind::Pressed => {
+                if let Some(layer) = self.pressed_on.get_mut(index) {
+                    *layer = self.active;
+                }
+                self.active
+            }
+            _ => self.pressed_on.get(index).copied().unwrap_or(0),
+        };
+        event.key = self.keymaps[layer].lookup(row, col);
+        Some(event)
+    }
+}
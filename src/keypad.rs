@@ -0,0 +1,433 @@
+#[cfg(not(feature = "mcp23017"))]
+use crate::delay::Delay;
+#[cfg(not(feature = "mcp23017"))]
+use core::convert::Infallible;
+#[cfg(not(feature = "mcp23017"))]
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "shift-register")]
+use embedded_hal::blocking::delay::DelayUs;
+#[cfg(feature = "shift-register")]
+use embedded_hal::blocking::spi::Write;
+#[cfg(not(feature = "mcp23017"))]
+use keypad_core::matrix::{self, ColumnDriver, Rows};
+#[cfg(feature = "pull-check")]
+use keypad_core::pull_check::{self, Verdict, SAMPLES};
+#[cfg(not(feature = "mcp23017"))]
+use stm32f1xx_hal::gpio::PinExt;
+#[cfg(not(feature = "mcp23017"))]
+use stm32f1xx_hal::gpio::{Edge, ErasedPin, Input, Output, Pin, PullDown, PullUp, HL};
+use stm32f1xx_hal::pac::{gpioa, GPIOA, GPIOB, GPIOC, GPIOD, GPIOE};
+
+pub use keypad_core::debounce::{
+    Debouncer, Filtered, KeyFilter, PinDebouncer, Thresholds, DEBOUNCE_THRESHOLD, STUCK_KEY_MS,
+};
+pub use keypad_core::keys::{key_bit, KeyState, COLUMNS, KEYS, PAD_COLUMNS, ROWS};
+pub use keypad_core::limit::{PressLimit, MAX_SIMULTANEOUS};
+#[cfg(not(feature = "mcp23017"))]
+pub use keypad_core::matrix::{ActiveHigh, ActiveLow, Polarity};
+pub use keypad_core::matrix::{Matrix, SelfTest, SlicedScan};
+
+// the debounced state of the latest frame, every key in one word
+static KEY_STATE: AtomicU32 = AtomicU32::new(0);
+
+const _: () = assert!(KEYS <= 32);
+
+#[cfg(all(feature = "second-pad", feature = "mcp23017"))]
+compile_error!("the `second-pad` feature needs 12 lines, the expander driver scans one 4x4 pad");
+
+/// Publishes the debounced state of a frame, called by the scanner once the frame
+/// is in. It is a single word store, a reader sees the whole frame or the one
+/// before, never a mix of the two.
+pub fn publish_key_state(state: KeyState) {
+    KEY_STATE.store(state.0, Ordering::Relaxed);
+}
+
+/// The keys held as of the latest frame, from any task and without a lock. It is
+/// at most one scan period stale. While the scanner sleeps nothing is held and it
+/// stays as is.
+pub fn read_key_state() -> KeyState {
+    KeyState(KEY_STATE.load(Ordering::Relaxed))
+}
+
+/// The row inputs and their wake up edge for a [`Polarity`].
+#[cfg(not(feature = "mcp23017"))]
+pub trait RowInput: Polarity {
+    /// Pull of the row inputs.
+    type Pull;
+    /// Edge of a row when a key gets pressed.
+    const PRESS_EDGE: Edge;
+
+    /// Configures `pin` as a row input.
+    fn row<const P: char, const N: u8>(
+        pin: Pin<P, N>,
+        cr: &mut <Pin<P, N> as HL>::Cr,
+    ) -> Pin<P, N, Input<Self::Pull>>
+    where
+        Pin<P, N>: HL;
+}
+
+#[cfg(not(feature = "mcp23017"))]
+impl RowInput for ActiveHigh {
+    type Pull = PullDown;
+    const PRESS_EDGE: Edge = Edge::Rising;
+
+    fn row<const P: char, const N: u8>(
+        pin: Pin<P, N>,
+        cr: &mut <Pin<P, N> as HL>::Cr,
+    ) -> Pin<P, N, Input<PullDown>>
+    where
+        Pin<P, N>: HL,
+    {
+        pin.into_pull_down_input(cr)
+    }
+}
+
+#[cfg(not(feature = "mcp23017"))]
+impl RowInput for ActiveLow {
+    type Pull = PullUp;
+    const PRESS_EDGE: Edge = Edge::Falling;
+
+    fn row<const P: char, const N: u8>(
+        pin: Pin<P, N>,
+        cr: &mut <Pin<P, N> as HL>::Cr,
+    ) -> Pin<P, N, Input<PullUp>>
+    where
+        Pin<P, N>: HL,
+    {
+        pin.into_pull_up_input(cr)
+    }
+}
+
+// CNF/MODE bits of a floating input
+#[cfg(any(
+    not(any(feature = "shift-register", feature = "mcp23017")),
+    feature = "pull-check"
+))]
+const FLOATING_INPUT: u32 = 0b0100;
+
+/// Columns wired straight to GPIO pins, push-pull or open-drain outputs as
+/// `MODE` says.
+///
+/// Without diodes, two keys pressed in one row connect their columns, and
+/// push-pull drives the selected and an idle column against each other through
+/// them. Open-drain with [`ActiveLow`] never drives a column high, so it takes
+/// that for pads without diodes where several keys may be held at once, like
+/// chords. Pads with a diode per key, or used one key at a time, don't need it.
+#[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+pub struct GpioColumns<const COLS: usize, P, MODE> {
+    pins: [ErasedPin<Output<MODE>>; COLS],
+    // CNF/MODE bits of each pin while it is parked as an input
+    parked: Option<[u32; COLS]>,
+    // the port when every pin is on the same one, the columns then change
+    // together in a single BSRR write
+    port: Option<u8>,
+    // bit of every pin in its port, and of all of them
+    pin_bits: [u16; COLS],
+    all_bits: u16,
+    polarity: PhantomData<P>,
+}
+
+#[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+impl<const COLS: usize, P: Polarity, MODE> GpioColumns<COLS, P, MODE> {
+    pub fn new(pins: [ErasedPin<Output<MODE>>; COLS]) -> Self {
+        let port = pins
+            .first()
+            .map(|first| first.port_id())
+            .filter(|&port| pins.iter().all(|pin| pin.port_id() == port));
+        let mut pin_bits = [0; COLS];
+        for (bit, pin) in pin_bits.iter_mut().zip(pins.iter()) {
+            *bit = 1 << pin.pin_id();
+        }
+        let mut columns = Self {
+            pins,
+            parked: None,
+            port,
+            pin_bits,
+            all_bits: pin_bits.iter().fold(0, |all, bit| all | bit),
+            polarity: PhantomData,
+        };
+        columns.output(0, |_| false);
+        columns
+    }
+
+    // drives the columns `active` says active and the others inactive, `active_bits`
+    // has the pin bits of the active ones
+    fn output(&mut self, active_bits: u16, active: impl Fn(usize) -> bool) {
+        match self.port {
+            Some(port_id) => {
+                let high = if P::ACTIVE_HIGH {
+                    active_bits
+                } else {
+                    self.all_bits & !active_bits
+                };
+                let low = self.all_bits & !high;
+                // SAFETY: BSRR only changes the pins it names, the ones this owns
+                unsafe {
+                    (*port(port_id))
+                        .bsrr
+                        .write(|w| w.bits(u32::from(low) << 16 | u32::from(high)))
+                };
+            }
+            None => {
+                for (index, pin) in self.pins.iter_mut().enumerate() {
+                    drive::<P, MODE>(pin, active(index));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+fn drive<P: Polarity, MODE>(pin: &mut ErasedPin<Output<MODE>>, active: bool) {
+    if active == P::ACTIVE_HIGH {
+        pin.set_high();
+    } else {
+        pin.set_low();
+    }
+}
+
+/// The registers of a GPIO port, by its [`PinExt::port_id`].
+pub fn port(port_id: u8) -> *const gpioa::RegisterBlock {
+    match port_id {
+        0 => GPIOA::ptr(),
+        1 => GPIOB::ptr(),
+        2 => GPIOC::ptr(),
+        3 => GPIOD::ptr(),
+        _ => GPIOE::ptr(),
+    }
+}
+
+/// Replaces the CNF/MODE bits of `pin` and returns the old ones. The HAL can't
+/// change the mode of an erased pin, so this writes CRL/CRH directly.
+#[cfg(any(
+    not(any(feature = "shift-register", feature = "mcp23017")),
+    feature = "pull-check"
+))]
+fn swap_config<MODE>(pin: &ErasedPin<MODE>, config: u32) -> u32 {
+    let gpio = port(pin.port_id());
+    let pin_id = u32::from(pin.pin_id());
+    let shift = (pin_id % 8) * 4;
+    let mut old = 0;
+    let mut swap = |bits: u32| {
+        old = (bits >> shift) & 0b1111;
+        bits & !(0b1111 << shift) | config << shift
+    };
+    // the register is shared with other pins, nothing may modify it in between
+    cortex_m::interrupt::free(|_| {
+        // SAFETY: only the bits of `pin` change and this owns the pin
+        let gpio: &gpioa::RegisterBlock = unsafe { &*gpio };
+        if pin_id < 8 {
+            gpio.crl.modify(|r, w| unsafe { w.bits(swap(r.bits())) });
+        } else {
+            gpio.crh.modify(|r, w| unsafe { w.bits(swap(r.bits())) });
+        }
+    });
+    old
+}
+
+#[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+impl<const COLS: usize, P: Polarity, MODE> ColumnDriver for GpioColumns<COLS, P, MODE> {
+    type Error = Infallible;
+
+    fn select(&mut self, col: usize) -> Result<(), Infallible> {
+        self.output(self.pin_bits[col], |index| index == col);
+        Ok(())
+    }
+
+    fn release(&mut self) -> Result<(), Infallible> {
+        self.output(0, |_| false);
+        Ok(())
+    }
+
+    fn select_all(&mut self) -> Result<(), Infallible> {
+        self.output(self.all_bits, |_| true);
+        Ok(())
+    }
+
+    fn park(&mut self) -> Result<(), Infallible> {
+        if self.parked.is_some() {
+            return Ok(());
+        }
+        self.output(0, |_| false);
+        // the modes of locked pins can't change, the columns stay driven inactive
+        if cfg!(feature = "lock-pins") {
+            return Ok(());
+        }
+        let mut configs = [0; COLS];
+        for (config, pin) in configs.iter_mut().zip(self.pins.iter()) {
+            *config = swap_config(pin, FLOATING_INPUT);
+        }
+        self.parked = Some(configs);
+        Ok(())
+    }
+
+    fn unpark(&mut self) -> Result<(), Infallible> {
+        // the output registers still hold the inactive level from `park`
+        if let Some(configs) = self.parked.take() {
+            for (config, pin) in configs.iter().zip(self.pins.iter()) {
+                swap_config(pin, *config);
+            }
+        }
+        Ok(())
+    }
+}
+
+// after latching, for the column lines
+#[cfg(feature = "shift-register")]
+const LATCH_SETTLE_US: u32 = 1;
+
+/// `COLS` columns on the outputs of a 74HC595 fed over SPI, from Q0 on.
+#[cfg(feature = "shift-register")]
+pub struct ShiftRegisterColumns<SPI, const COLS: usize, P> {
+    spi: SPI,
+    latch: ErasedPin<Output>,
+    delay: Delay,
+    polarity: PhantomData<P>,
+}
+
+#[cfg(feature = "shift-register")]
+impl<SPI: Write<u8>, const COLS: usize, P: Polarity> ShiftRegisterColumns<SPI, COLS, P> {
+    // one output per column
+    const FITS: () = assert!(COLS <= 8);
+
+    pub fn new(spi: SPI, latch: ErasedPin<Output>, delay: Delay) -> Self {
+        let () = Self::FITS;
+        let mut columns = Self {
+            spi,
+            latch,
+            delay,
+            polarity: PhantomData,
+        };
+        // the first scan drives them again if this fails
+        let _ = columns.output(0);
+        columns
+    }
+
+    // `active` has a bit set for every active column
+    fn output(&mut self, active: u8) -> Result<(), SPI::Error> {
+        let byte = if P::ACTIVE_HIGH { active } else { !active };
+        // a failed transfer leaves the old outputs latched
+        self.spi.write(&[byte])?;
+        self.latch.set_high();
+        self.latch.set_low();
+        self.delay.delay_us(LATCH_SETTLE_US);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "shift-register")]
+impl<SPI: Write<u8>, const COLS: usize, P: Polarity> ColumnDriver
+    for ShiftRegisterColumns<SPI, COLS, P>
+{
+    type Error = SPI::Error;
+
+    fn select(&mut self, col: usize) -> Result<(), SPI::Error> {
+        self.output(1 << col)
+    }
+
+    fn release(&mut self) -> Result<(), SPI::Error> {
+        self.output(0)
+    }
+
+    fn select_all(&mut self) -> Result<(), SPI::Error> {
+        self.output(((1 << COLS) - 1) as u8)
+    }
+
+    // the 74HC595 outputs can only float through OE, which is tied low
+    fn park(&mut self) -> Result<(), SPI::Error> {
+        self.release()
+    }
+
+    fn unpark(&mut self) -> Result<(), SPI::Error> {
+        self.release()
+    }
+}
+
+/// The rows on GPIO pins, `P` tells their pull.
+#[cfg(not(feature = "mcp23017"))]
+pub struct GpioRows<P: RowInput, const ROWS: usize> {
+    pins: [ErasedPin<Input<P::Pull>>; ROWS],
+    // port and first pin of rows on consecutive pins of one port, like PA4-PA7,
+    // they are read with a single load of the input register
+    port_rows: Option<(u8, u8)>,
+}
+
+#[cfg(not(feature = "mcp23017"))]
+impl<P: RowInput, const ROWS: usize> GpioRows<P, ROWS> {
+    pub fn new(pins: [ErasedPin<Input<P::Pull>>; ROWS]) -> Self {
+        let port_rows = pins
+            .first()
+            .map(|first| (first.port_id(), first.pin_id()))
+            .filter(|&(port, first)| {
+                (first..)
+                    .zip(pins.iter())
+                    .all(|(pin, row)| row.port_id() == port && row.pin_id() == pin)
+            });
+        Self { pins, port_rows }
+    }
+
+    /// The [`pull_check`] of every row, the columns have to be inactive. The rows
+    /// float while it samples them and are back on their pulls after.
+    #[cfg(feature = "pull-check")]
+    pub fn pull_check(&self) -> [Verdict; ROWS] {
+        let mut configs = [0; ROWS];
+        for (config, pin) in configs.iter_mut().zip(self.pins.iter()) {
+            *config = swap_config(pin, FLOATING_INPUT);
+        }
+        let mut samples = [0; SAMPLES];
+        for sample in samples.iter_mut() {
+            cortex_m::asm::delay(PULL_CHECK_SETTLE_CYCLES);
+            *sample = self.active();
+        }
+        for (config, pin) in configs.iter().zip(self.pins.iter()) {
+            swap_config(pin, *config);
+        }
+        cortex_m::asm::delay(PULL_CHECK_SETTLE_CYCLES);
+        let pulled = self.active();
+        let mut verdicts = [Verdict::Pulled; ROWS];
+        for (row, verdict) in verdicts.iter_mut().enumerate() {
+            *verdict = pull_check::verdict(&samples, pulled, row);
+        }
+        verdicts
+    }
+
+    // a bit of every row at its active level
+    #[cfg(feature = "pull-check")]
+    fn active(&self) -> u32 {
+        let Ok(levels) = self.read();
+        let levels = if P::ACTIVE_HIGH { levels } else { !levels };
+        levels & ((1 << ROWS) - 1)
+    }
+}
+
+// between the reads of `GpioRows::pull_check`, about 100 us at 72 MHz for a
+// floating row to drift
+#[cfg(feature = "pull-check")]
+const PULL_CHECK_SETTLE_CYCLES: u32 = 7_200;
+
+#[cfg(not(feature = "mcp23017"))]
+impl<P: RowInput, const ROWS: usize> Rows<ROWS> for GpioRows<P, ROWS> {
+    type Error = Infallible;
+
+    fn read(&self) -> Result<u32, Infallible> {
+        Ok(match self.port_rows {
+            Some((port_id, first)) => {
+                // SAFETY: a read of the input register, which has no side effects
+                let levels = unsafe { (*port(port_id)).idr.read().bits() };
+                levels >> first
+            }
+            None => self
+                .pins
+                .iter()
+                .enumerate()
+                .filter(|(_, pin)| pin.is_high())
+                .fold(0, |levels, (row, _)| levels | 1 << row),
+        })
+    }
+}
+
+/// The matrix on GPIO rows, the columns driven by `C`.
+#[cfg(not(feature = "mcp23017"))]
+pub type Keypad<C, const COLS: usize, const ROWS: usize, P> =
+    matrix::Keypad<C, GpioRows<P, ROWS>, Delay, COLS, ROWS, P>;
@@ -0,0 +1,168 @@
+//! 16x2 HD44780 character LCD in 4-bit mode echoing a typed code: digits are
+//! appended, '*' deletes the last one and '#' clears the line.
+//!
+//! RS on PB15, E on PB9 and D4-D7 on PB8, PB5, PB4, PB3.
+
+#[cfg(feature = "lcd")]
+use crate::event::{EventKind, KeyEvent};
+use embedded_hal::blocking::delay::DelayUs;
+#[cfg(feature = "lcd")]
+use stm32f1xx_hal::gpio::{ErasedPin, Output};
+
+#[cfg(feature = "lcd")]
+pub const WIDTH: usize = 16;
+
+// short command and data writes finish within 37 us
+#[cfg(feature = "lcd")]
+const EXECUTION_US: u32 = 45;
+
+/// The typed code, digits beyond [`WIDTH`] are ignored.
+#[cfg(feature = "lcd")]
+#[derive(Default)]
+pub struct Entry {
+    text: heapless::String<WIDTH>,
+}
+
+#[cfg(feature = "lcd")]
+impl Entry {
+    /// Applies a key event, returns `true` if the text changed.
+    pub fn handle(&mut self, event: &KeyEvent) -> bool {
+        if event.kind != EventKind::Pressed {
+            return false;
+        }
+        match event.key {
+            '0'..='9' => self.text.push(event.key).is_ok(),
+            '*' => self.text.pop().is_some(),
+            '#' => {
+                let changed = !self.text.is_empty();
+                self.text.clear();
+                changed
+            }
+            _ => false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+#[cfg(feature = "lcd")]
+pub struct Lcd {
+    rs: ErasedPin<Output>,
+    e: ErasedPin<Output>,
+    // D4-D7
+    data: [ErasedPin<Output>; 4],
+    ready: bool,
+    entry: Entry,
+}
+
+#[cfg(feature = "lcd")]
+impl Lcd {
+    pub fn new(rs: ErasedPin<Output>, e: ErasedPin<Output>, data: [ErasedPin<Output>; 4]) -> Self {
+        Self {
+            rs,
+            e,
+            data,
+            ready: false,
+            entry: Entry::default(),
+        }
+    }
+
+    // the waits run on the shared `Delay`, the monotonic only has to
+    // schedule the long ones between the init steps
+    fn write_nibble(&mut self, nibble: u8, delay: &mut impl DelayUs<u32>) {
+        for (bit, pin) in self.data.iter_mut().enumerate() {
+            if nibble & (1 << bit) != 0 {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+        }
+        // E has to stay high for at least 450 ns
+        self.e.set_high();
+        delay.delay_us(1);
+        self.e.set_low();
+        delay.delay_us(EXECUTION_US);
+    }
+
+    fn write(&mut self, byte: u8, data: bool, delay: &mut impl DelayUs<u32>) {
+        if data {
+            self.rs.set_high();
+        } else {
+            self.rs.set_low();
+        }
+        self.write_nibble(byte >> 4, delay);
+        self.write_nibble(byte & 0x0f, delay);
+    }
+
+    /// Runs step `step` of the power-on initialization and returns how long to wait
+    /// in milliseconds before the next one, `None` once the display is ready.
+    pub fn init_step(&mut self, step: u8, delay: &mut impl DelayUs<u32>) -> Option<u32> {
+        self.rs.set_low();
+        match step {
+            // the controller needs 40 ms after power-on
+            0 => Some(50),
+            // three times 8-bit mode to get into a known state from either mode
+            1 => {
+                self.write_nibble(0x3, delay);
+                Some(5)
+            }
+            2 | 3 => {
+                self.write_nibble(0x3, delay);
+                Some(1)
+            }
+            4 => {
+                self.write_nibble(0x2, delay);
+                // function set: 4-bit, 2 lines, 5x8 dots
+                self.write(0x28, false, delay);
+                // display off
+                self.write(0x08, false, delay);
+                // clear display, takes 1.52 ms
+                self.write(0x01, false, delay);
+                Some(2)
+            }
+            _ => {
+                // entry mode: increment, no shift
+                self.write(0x06, false, delay);
+                // display on, no cursor
+                self.write(0x0c, false, delay);
+                self.ready = true;
+                self.render(delay);
+                None
+            }
+        }
+    }
+
+    /// Applies a key event to the typed code, returns `true` if it needs a
+    /// [`render`](Self::render). Keys typed before the display is ready still end
+    /// up in the code.
+    pub fn handle(&mut self, event: &KeyEvent) -> bool {
+        self.entry.handle(event) && self.ready
+    }
+
+    // rewrites the first line instead of clearing, so there is no long wait
+    pub fn render(&mut self, delay: &mut impl DelayUs<u32>) {
+        // DDRAM address of the first line
+        self.write(0x80, false, delay);
+        let mut text = [b' '; WIDTH];
+        let typed = self.entry.as_str().as_bytes();
+        text[..typed.len()].copy_from_slice(typed);
+        for byte in text {
+            self.write(byte, true, delay);
+        }
+    }
+}
+
+/// Stand-in without the `lcd` feature, the init chain is never started.
+#[cfg(not(feature = "lcd"))]
+pub struct Lcd;
+
+#[cfg(not(feature = "lcd"))]
+impl Lcd {
+    pub fn init_step(&mut self, _step: u8, _delay: &mut impl DelayUs<u32>) -> Option<u32> {
+        None
+    }
+
+    pub fn render(&mut self, _delay: &mut impl DelayUs<u32>) {}
+}
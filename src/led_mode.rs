@@ -0,0 +1,557 @@
+//! What the red, blue and green leds show. The `led_controller` task is the only
+//! one driving them, the other tasks send it the `LedMode` they enter or leave.
+//!
+//! Every mode entered plays its pattern of `patterns` through
+//! `keypad_core::pattern`, at the rank of its severity, and the highest one is
+//! shown. The one it took over from goes on where it was once it is left, but
+//! `Identify`, which is dropped. `Boot`, `Identify` and a passed key test end
+//! after their last step. The controller schedules a `Step` at the end of the
+//! step shown, and moves it whenever a mode taking over or leaving moves that.
+//!
+//! After [`DIM_AFTER_MS`] without an event the red and blue leds fade down to
+//! [`DIM_LEVEL`] of their brightness, a step every PWM period of `crate::leds`,
+//! and the next event lights them up again at once. `Error` and `Emergency` are
+//! always shown at full brightness. Time spent in STOP doesn't count.
+
+use crate::app::{led_controller, now_ms};
+use crate::boot::{self, BootStages};
+#[cfg(feature = "key-test")]
+use crate::key_test;
+use crate::logging::Text;
+use crate::monotonic;
+#[cfg(feature = "morse")]
+use crate::morse::Morse;
+use crate::spawn::Counted;
+use core::fmt;
+use keypad_core::pattern::{Pattern, Player};
+use rtic::Mutex;
+
+mod patterns;
+
+/// Bits of the lights of a step.
+pub const RED: u8 = 1 << 0;
+pub const BLUE: u8 = 1 << 1;
+pub const GREEN: u8 = 1 << 2;
+
+/// The runtime faults of `LedMode::Error`, the red led blinks the code. Of the
+/// entered ones the lowest code is shown.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    StuckKeys = 1,
+    MatrixFault = 2,
+    KeypadUnreachable = 3,
+    // the key event queue was full, see `crate::event::dropped`
+    QueueOverflow = 4,
+    // a device on an I2C bus stopped answering
+    I2cUnreachable = 5,
+    FlashWrite = 6,
+    // less than `crate::stack::LOW_FREE` of the stack was ever left
+    #[cfg(feature = "stack-watch")]
+    StackLow = 7,
+    // a row without its external pull, see `keypad_core::pull_check`
+    #[cfg(feature = "pull-check")]
+    FloatingRow = 8,
+}
+
+impl ErrorCode {
+    /// The number of pulses.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    fn bit(self) -> u8 {
+        1 << (self.code() - 1)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ErrorCode::StuckKeys => "stuck keys",
+            ErrorCode::MatrixFault => "matrix fault",
+            ErrorCode::KeypadUnreachable => "keypad unreachable",
+            ErrorCode::QueueOverflow => "event queue overflow",
+            ErrorCode::I2cUnreachable => "I2C device unreachable",
+            ErrorCode::FlashWrite => "flash write failed",
+            #[cfg(feature = "stack-watch")]
+            ErrorCode::StackLow => "stack low",
+            #[cfg(feature = "pull-check")]
+            ErrorCode::FloatingRow => "floating row",
+        }
+    }
+}
+
+/// `error 4 (event queue overflow)`, as the led blinks it.
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error {} ({})", self.code(), Text(self.name()))
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ErrorCode {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "error {=u8} ({=str})", self.code(), self.name())
+    }
+}
+
+// the blue heartbeat, two 50 ms pulses 150 ms apart every 2 s
+const HEARTBEAT_STEPS: [(u8, u16); 4] = [(BLUE, 50), (0, 100), (BLUE, 50), (0, 1800)];
+// every step of the boot animation and the red flashes of a failed stage
+const BOOT_STEP_MS: u16 = 300;
+const BOOT_FLASH_MS: u16 = 100;
+const BOOT_FLASHES: u8 = 3;
+// the blue led blinks the setting picked in the config mode, then pauses
+const CONFIG_PULSE_MS: u16 = 200;
+const CONFIG_PAUSE_MS: u16 = 1000;
+// the red led blinks once a second while the PIN entry is locked out
+const LOCKOUT_BLINK_MS: u16 = 500;
+// all leds flash 10 times a second for 5 s
+const IDENTIFY_FLASH_MS: u16 = 50;
+const IDENTIFY_FLASHES: u8 = 50;
+// toggle period of the red led while the battery is low
+const LOW_BATTERY_MS: u16 = 2000;
+// the key test blinks bit 3 of a key, the flashes of a passed test take
+// `KEY_TEST_PASSED_MS`
+#[cfg(feature = "key-test")]
+const KEY_TEST_BLINK_MS: u16 = 250;
+#[cfg(feature = "key-test")]
+const KEY_TEST_FLASH_MS: u16 = 200;
+#[cfg(feature = "key-test")]
+const KEY_TEST_FLASHES: u8 = 3;
+#[cfg(feature = "key-test")]
+pub const KEY_TEST_PASSED_MS: u32 = KEY_TEST_FLASHES as u32 * 2 * KEY_TEST_FLASH_MS as u32;
+const ERROR_PULSE_MS: u16 = 200;
+const ERROR_PAUSE_MS: u16 = 1500;
+
+pub const DIM_AFTER_MS: u32 = 60_000;
+/// Of 255, about 10%.
+pub const DIM_LEVEL: u8 = 26;
+
+/// In ascending severity.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LedMode {
+    // the red led blinks with `crate::blink`, the blue one the heartbeat, the
+    // green one is lit while lock 1 is on. The `Flash`es add to that.
+    Normal,
+    // green once the clocks run, blue added once the keypad passed its self-test,
+    // then red flashes for a failed stage. All off before `Normal`, or red for good
+    // if the startup halted.
+    Boot,
+    // the blue led blinks the number of the setting picked, see
+    // `crate::config_mode`
+    Config(u8),
+    // the red led blinks, see `crate::pin`
+    Lockout,
+    // all leds flash to find the board, see the `ID` command
+    Identify,
+    // the index of the key pressed last in binary, or all leds flashing once the
+    // test passed, see `crate::key_test`
+    #[cfg(feature = "key-test")]
+    KeyTest(u8),
+    // slow blink of the red led
+    LowBattery,
+    // the red led blinks the code
+    Error(ErrorCode),
+    // the green led alone
+    Emergency,
+}
+
+// the priorities of the patterns of the modes, in the order of `LedMode`
+const NORMAL: usize = 0;
+const BOOT: usize = 1;
+const CONFIG: usize = 2;
+const LOCKOUT: usize = 3;
+const IDENTIFY: usize = 4;
+#[cfg(feature = "key-test")]
+const KEY_TEST: usize = 5;
+const LOW_BATTERY: usize = 6;
+const ERROR: usize = 7;
+const EMERGENCY: usize = 8;
+const RANKS: usize = EMERGENCY + 1;
+
+impl LedMode {
+    /// The priority of its pattern.
+    fn rank(self) -> usize {
+        match self {
+            LedMode::Normal => NORMAL,
+            LedMode::Boot => BOOT,
+            LedMode::Config(_) => CONFIG,
+            LedMode::Lockout => LOCKOUT,
+            LedMode::Identify => IDENTIFY,
+            #[cfg(feature = "key-test")]
+            LedMode::KeyTest(_) => KEY_TEST,
+            LedMode::LowBattery => LOW_BATTERY,
+            LedMode::Error(_) => ERROR,
+            LedMode::Emergency => EMERGENCY,
+        }
+    }
+}
+
+/// A led lit for a moment in `Normal`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flash {
+    // blue, for a debounced key press
+    Key,
+    // red, for an entry `crate::entry` didn't take, a wrong PIN or too many keys held
+    Reject,
+    // green, for the right PIN
+    Granted,
+    // the colours of the `crate::output` modes, blue, green, both and red
+    OutputText,
+    OutputBinary,
+    OutputHid,
+    OutputSilent,
+}
+
+impl Flash {
+    const ALL: [Flash; 7] = [
+        Flash::Key,
+        Flash::Reject,
+        Flash::Granted,
+        Flash::OutputText,
+        Flash::OutputBinary,
+        Flash::OutputHid,
+        Flash::OutputSilent,
+    ];
+
+    fn light(self) -> u8 {
+        match self {
+            Flash::Key | Flash::OutputText => BLUE,
+            Flash::Reject | Flash::OutputSilent => RED,
+            Flash::Granted | Flash::OutputBinary => GREEN,
+            Flash::OutputHid => BLUE | GREEN,
+        }
+    }
+
+    pub fn ms(self) -> u32 {
+        match self {
+            Flash::Key => 30,
+            Flash::Reject => 150,
+            Flash::Granted => 500,
+            Flash::OutputText | Flash::OutputBinary | Flash::OutputHid | Flash::OutputSilent => {
+                1000
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LedMessage {
+    Enter(LedMode),
+    Leave(LedMode),
+    // a step of the blink chain, see `crate::blink`
+    Blink,
+    // a flash, and its end which only the controller sends to itself
+    Flash(Flash),
+    FlashOver(Flash),
+    // lock 1 of `crate::modifiers` went on or off
+    Lock(bool),
+    // the startup is through, as far as it got
+    Booted(BootStages),
+    // the end of a step. Only the controller sends it to itself.
+    Step,
+    // no events for `DIM_AFTER_MS`, or the event that ended it
+    Dim(bool),
+    // a key for `crate::morse`, and the end of a dit, dah or gap
+    #[cfg(feature = "morse")]
+    Morse(char),
+    #[cfg(feature = "morse")]
+    MorseStep,
+}
+
+pub struct LedController {
+    // the pattern of every mode entered at its rank
+    player: Player<RANKS>,
+    // the time of the message, the modes entered or left then count from it
+    now_ms: u32,
+    // an `ErrorCode::bit` per entered error
+    errors: u8,
+    // the setting of `Config`
+    config: u8,
+    // the key of `KeyTest`
+    #[cfg(feature = "key-test")]
+    key_test: u8,
+    // the red led of the blink chain, shown in `Normal`
+    blink: bool,
+    // flashes not over yet in `Flash` order, a led stays lit until its last one is
+    flashes: [u8; Flash::ALL.len()],
+    // lock 1, shown in `Normal`
+    locked: bool,
+    // the stages `crate::boot::check` sent, once it was through
+    boot: BootStages,
+    booted: bool,
+    dimmed: bool,
+}
+
+impl LedController {
+    /// Nothing playing, `Normal` starts with the `Enter` sent for it.
+    pub const fn new() -> Self {
+        Self {
+            player: Player::new(),
+            now_ms: 0,
+            errors: 0,
+            config: 0,
+            #[cfg(feature = "key-test")]
+            key_test: 0,
+            blink: false,
+            flashes: [0; Flash::ALL.len()],
+            locked: false,
+            boot: BootStages::new(),
+            booted: false,
+            dimmed: false,
+        }
+    }
+
+    // the rank of the mode shown
+    fn shown(&self) -> usize {
+        self.player.shown().unwrap_or(NORMAL)
+    }
+
+    // the pattern of the mode at `rank`, with what it shows
+    fn pattern(&self, rank: usize) -> Pattern {
+        match rank {
+            EMERGENCY => patterns::EMERGENCY,
+            // the lowest code entered
+            ERROR => patterns::error(self.errors.trailing_zeros() as u8 + 1),
+            LOW_BATTERY => patterns::LOW_BATTERY,
+            #[cfg(feature = "key-test")]
+            KEY_TEST => match self.key_test {
+                key_test::PASSED => patterns::KEY_TEST_PASSED,
+                key if key & 8 != 0 && key != key_test::NO_KEY => patterns::KEY_TEST_BLINK,
+                _ => patterns::KEY_TEST_SHOWN,
+            },
+            IDENTIFY => patterns::IDENTIFY,
+            LOCKOUT => patterns::LOCKOUT,
+            CONFIG => patterns::config(self.config),
+            BOOT if !self.booted => patterns::BOOT,
+            BOOT if self.boot.halted() => patterns::BOOT_HALTED,
+            BOOT if self.boot.any_failed() => patterns::BOOT_FAILED,
+            BOOT => patterns::BOOT_PASSED,
+            _ => patterns::HEARTBEAT,
+        }
+    }
+
+    /// Plays the pattern of `mode`, from its first step unless it plays already.
+    pub fn enter(&mut self, mode: LedMode) {
+        match mode {
+            LedMode::Error(error) => self.errors |= error.bit(),
+            LedMode::Config(number) => self.config = number,
+            #[cfg(feature = "key-test")]
+            LedMode::KeyTest(key) => self.key_test = key,
+            _ => {}
+        }
+        self.replay(mode.rank());
+    }
+
+    /// The mode below goes on where it was if this one was shown.
+    pub fn leave(&mut self, mode: LedMode) {
+        let rank = mode.rank();
+        match mode {
+            LedMode::Normal => {}
+            LedMode::Error(error) => {
+                self.errors &= !error.bit();
+                if self.errors != 0 {
+                    // the next lowest code
+                    return self.replay(rank);
+                }
+                self.player.stop(rank, self.now_ms);
+            }
+            _ => self.player.stop(rank, self.now_ms),
+        }
+    }
+
+    fn replay(&mut self, rank: usize) {
+        self.player.play(rank, self.pattern(rank), self.now_ms);
+    }
+
+    pub fn blink(&mut self) {
+        self.blink = !self.blink;
+    }
+
+    pub fn set_lock(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    pub fn start_flash(&mut self, flash: Flash) {
+        self.flashes[flash as usize] += 1;
+    }
+
+    pub fn end_flash(&mut self, flash: Flash) {
+        let flashes = &mut self.flashes[flash as usize];
+        *flashes = flashes.saturating_sub(1);
+    }
+
+    pub fn set_dimmed(&mut self, dimmed: bool) {
+        self.dimmed = dimmed;
+    }
+
+    /// The share of their brightness the red and blue leds are lit with, of 255.
+    pub fn level(&self) -> u8 {
+        match self.shown() {
+            ERROR | EMERGENCY => 255,
+            _ if self.dimmed => DIM_LEVEL,
+            _ => 255,
+        }
+    }
+
+    /// Whether the blue led may breathe: in `Normal` while it flashes nothing.
+    #[cfg(feature = "breathe")]
+    pub fn calm(&self) -> bool {
+        self.shown() == NORMAL
+            && Flash::ALL
+                .into_iter()
+                .all(|flash| flash.light() & BLUE == 0 || self.flashes[flash as usize] == 0)
+    }
+
+    /// `Boot` plays on with the stages of the startup.
+    pub fn booted(&mut self, stages: BootStages) {
+        self.boot = stages;
+        self.booted = true;
+        if self.player.is_playing(BOOT) {
+            self.replay(BOOT);
+        }
+    }
+
+    /// Moves on to the next step of the pattern shown if its step is over.
+    pub fn advance(&mut self) {
+        self.player.update(self.now_ms);
+    }
+
+    /// When the step shown ends, in the ms of `now_ms`.
+    pub fn until(&self) -> Option<u32> {
+        self.player.until()
+    }
+
+    /// The leds lit in the step shown, as `RED`, `BLUE` and `GREEN` bits.
+    pub fn lights(&self) -> u8 {
+        let lights = self.player.lights();
+        match self.shown() {
+            NORMAL => {
+                let red = if self.blink { RED } else { 0 };
+                let green = if self.locked { GREEN } else { 0 };
+                let flashes = Flash::ALL
+                    .into_iter()
+                    .filter(|&flash| self.flashes[flash as usize] > 0)
+                    .fold(0, |lights, flash| lights | flash.light());
+                lights | red | green | flashes
+            }
+            BOOT => {
+                let mut passed = RED;
+                if self.boot.passed(boot::CLOCKS) {
+                    passed |= GREEN;
+                }
+                if self.boot.passed(boot::KEYPAD) {
+                    passed |= BLUE;
+                }
+                lights & passed
+            }
+            #[cfg(feature = "key-test")]
+            KEY_TEST if self.key_test == key_test::NO_KEY => 0,
+            #[cfg(feature = "key-test")]
+            KEY_TEST if self.key_test != key_test::PASSED => {
+                let shown = [(4, RED), (2, GREEN), (1, BLUE)]
+                    .into_iter()
+                    .filter(|&(bit, _)| self.key_test & bit != 0)
+                    .fold(0, |lights, (_, light)| lights | light);
+                lights & shown
+            }
+            _ => lights,
+        }
+    }
+}
+
+/// Shows `message` on the leds, the body of `led_controller`.
+pub fn led_controller(mut ctx: led_controller::Context, message: LedMessage) {
+    let leds = ctx.local.leds;
+    leds.now_ms = now_ms();
+    match message {
+        // the emergency stop takes the green led
+        #[cfg(feature = "morse")]
+        LedMessage::Enter(LedMode::Emergency) => {
+            ctx.local.morse.abort();
+            leds.enter(LedMode::Emergency);
+        }
+        LedMessage::Enter(mode) => leds.enter(mode),
+        LedMessage::Leave(mode) => leds.leave(mode),
+        LedMessage::Blink => leds.blink(),
+        LedMessage::Lock(locked) => leds.set_lock(locked),
+        // a press during a flash extends it, the one of every press ends on its
+        // own. Without room for its end the flashes pending keep the led lit.
+        LedMessage::Flash(flash) => {
+            let over = monotonic::millis(flash.ms());
+            if led_controller::spawn_after(over, LedMessage::FlashOver(flash)).is_ok() {
+                leds.start_flash(flash);
+            }
+        }
+        LedMessage::FlashOver(flash) => leds.end_flash(flash),
+        LedMessage::Booted(stages) => leds.booted(stages),
+        LedMessage::Dim(dimmed) => leds.set_dimmed(dimmed),
+        LedMessage::Step => {
+            // the earliest one pending comes first, it is over by now
+            if ctx
+                .local
+                .step
+                .is_some_and(|at_ms| leds.now_ms.wrapping_sub(at_ms) as i32 >= 0)
+            {
+                *ctx.local.step = None;
+            }
+            leds.advance();
+        }
+        #[cfg(feature = "morse")]
+        LedMessage::Morse(key) => {
+            if ctx.local.morse.push(key) {
+                morse_step(ctx.local.morse);
+            }
+        }
+        #[cfg(feature = "morse")]
+        LedMessage::MorseStep => morse_step(ctx.local.morse),
+    }
+    schedule_step(ctx.local.step, leds);
+    let lights = leds.lights();
+    #[cfg(feature = "morse")]
+    let lights = match ctx.local.morse.green() {
+        Some(true) => lights | GREEN,
+        Some(false) => lights & !GREEN,
+        None => lights,
+    };
+    let level = leds.level();
+    #[cfg(feature = "breathe")]
+    let calm = leds.calm();
+    ctx.shared.status_leds.lock(|status_leds| {
+        status_leds.set_level(level);
+        #[cfg(feature = "breathe")]
+        status_leds.set_calm(calm);
+        status_leds.show(lights);
+    });
+}
+
+// schedules a `Step` for the end of the step shown unless one comes by then. One
+// that comes early, for a step a mode took over from, shows the same step again.
+fn schedule_step(step: &mut Option<u32>, leds: &LedController) {
+    let Some(until_ms) = leds.until() else {
+        return;
+    };
+    if step.is_some_and(|at_ms| at_ms <= until_ms) {
+        return;
+    }
+    let after = monotonic::millis(until_ms.wrapping_sub(leds.now_ms));
+    if led_controller::spawn_after(after, LedMessage::Step)
+        .or_count()
+        .is_some()
+    {
+        *step = Some(until_ms);
+    }
+}
+
+// schedules the end of the next dit, dah or gap, the leds are locked for the
+// message that changes them and not for a whole character
+#[cfg(feature = "morse")]
+fn morse_step(morse: &mut Morse) {
+    if let Some(ms) = morse.step() {
+        if led_controller::spawn_after(monotonic::millis(ms), LedMessage::MorseStep)
+            .or_count()
+            .is_none()
+        {
+            *morse = Morse::new();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! The modes as patterns of `keypad_core::pattern`, a priority per rank of
+//! `LedMode`. The ones with a number, the setting of `Config` and the code of
+//! `Error`, blink as many pulses as it counts.
+
+use super::{BLUE, GREEN, RED};
+use super::{BOOT_FLASHES, BOOT_FLASH_MS, BOOT_STEP_MS, HEARTBEAT_STEPS};
+use super::{CONFIG_PAUSE_MS, CONFIG_PULSE_MS, ERROR_PAUSE_MS, ERROR_PULSE_MS};
+use super::{IDENTIFY_FLASHES, IDENTIFY_FLASH_MS, LOCKOUT_BLINK_MS, LOW_BATTERY_MS};
+#[cfg(feature = "key-test")]
+use super::{KEY_TEST_BLINK_MS, KEY_TEST_FLASHES, KEY_TEST_FLASH_MS};
+use keypad_core::pattern::{LedMask, Pattern};
+
+// the settings of `crate::config_mode` and the highest `ErrorCode`
+const SETTINGS: usize = 4;
+const CODES: usize = 8;
+
+const ALL: LedMask = RED | BLUE | GREEN;
+
+pub static HEARTBEAT: Pattern = Pattern::new(&HEARTBEAT_STEPS);
+// waits on its second step for `crate::boot::check`, then plays once from there with
+// what it found. The lights of the stages that didn't pass stay off.
+pub static BOOT: Pattern = Pattern::new(&[(GREEN, BOOT_STEP_MS), (GREEN | BLUE, u16::MAX)]);
+pub static BOOT_PASSED: Pattern =
+    Pattern::new(&[(GREEN | BLUE, BOOT_STEP_MS), (0, BOOT_STEP_MS)]).times(1);
+static BOOT_FAILED_STEPS: [(LedMask, u16); 2 * BOOT_FLASHES as usize + 2] = pulses(
+    &[(GREEN | BLUE, BOOT_STEP_MS)],
+    (ALL, GREEN | BLUE),
+    BOOT_FLASH_MS,
+    BOOT_STEP_MS,
+);
+pub static BOOT_FAILED: Pattern = Pattern::new(&BOOT_FAILED_STEPS).times(1);
+pub static BOOT_HALTED: Pattern = Pattern::new(&[(ALL, BOOT_STEP_MS)]);
+static CONFIG_STEPS: [(LedMask, u16); 2 * SETTINGS + 1] =
+    pulses(&[], (BLUE, 0), CONFIG_PULSE_MS, CONFIG_PAUSE_MS);
+pub static LOCKOUT: Pattern = Pattern::new(&[(RED, LOCKOUT_BLINK_MS), (0, LOCKOUT_BLINK_MS)]);
+// dropped by a mode that takes over, it is only there to find the board
+pub static IDENTIFY: Pattern = Pattern::new(&[(ALL, IDENTIFY_FLASH_MS), (0, IDENTIFY_FLASH_MS)])
+    .times(IDENTIFY_FLASHES)
+    .cancelled();
+// the bits of the key are masked in, bit 3 blinks them
+#[cfg(feature = "key-test")]
+pub static KEY_TEST_SHOWN: Pattern = Pattern::new(&[(ALL, KEY_TEST_BLINK_MS)]);
+#[cfg(feature = "key-test")]
+pub static KEY_TEST_BLINK: Pattern =
+    Pattern::new(&[(ALL, KEY_TEST_BLINK_MS), (0, KEY_TEST_BLINK_MS)]);
+#[cfg(feature = "key-test")]
+pub static KEY_TEST_PASSED: Pattern =
+    Pattern::new(&[(ALL, KEY_TEST_FLASH_MS), (0, KEY_TEST_FLASH_MS)]).times(KEY_TEST_FLASHES);
+pub static LOW_BATTERY: Pattern = Pattern::new(&[(RED, LOW_BATTERY_MS), (0, LOW_BATTERY_MS)]);
+static ERROR_STEPS: [(LedMask, u16); 2 * CODES + 1] =
+    pulses(&[], (RED, 0), ERROR_PULSE_MS, ERROR_PAUSE_MS);
+pub static EMERGENCY: Pattern = Pattern::new(&[(GREEN, 1000)]);
+
+// `first`, then pulses of the lights on and off until the pause of the last step
+const fn pulses<const N: usize>(
+    first: &[(LedMask, u16)],
+    (on, off): (LedMask, LedMask),
+    pulse_ms: u16,
+    pause_ms: u16,
+) -> [(LedMask, u16); N] {
+    let mut steps = [(off, pulse_ms); N];
+    let mut step = 0;
+    while step < first.len() {
+        steps[step] = first[step];
+        step += 1;
+    }
+    while step < N - 1 {
+        steps[step].0 = on;
+        step += 2;
+    }
+    steps[N - 1] = (0, pause_ms);
+    steps
+}
+
+/// The blue pulses of the setting `number`.
+pub fn config(number: u8) -> Pattern {
+    counted(&CONFIG_STEPS, number)
+}
+
+/// The red pulses of the error `code`.
+pub fn error(code: u8) -> Pattern {
+    counted(&ERROR_STEPS, code)
+}
+
+// `count` pulses and the pause, the last steps of `steps`. All the counts share
+// the one table.
+fn counted(steps: &'static [(LedMask, u16)], count: u8) -> Pattern {
+    Pattern::new(&steps[steps.len() - 2 * usize::from(count) - 1..])
+}
@@ -0,0 +1,193 @@
+//! The three status leds as one resource: the dimmable red and blue ones of
+//! [`StatusLeds`] and the plain green one. `led_controller` lights them with the
+//! phase of the `crate::led_mode`, the commands and the config mode switch and
+//! dim them directly. The dimming of `crate::led_mode` scales the brightness set
+//! that way, it fades down a step per PWM period and comes back at once. With
+//! `ambient` the auto brightness slews over to a new brightness.
+//!
+//! With `breathe` the blue led breathes instead of the heartbeat while
+//! `LedController::calm`, the TIM2 interrupt steps the breath at
+//! `keypad_core::breath::STEP_HZ` in the PWM periods. Any other mode or a blue
+//! flash holds the breath where it was and the led shows the phase, `BREATHE OFF`
+//! brings the heartbeat back.
+
+use crate::command::Led;
+use crate::led_mode;
+#[cfg(any(feature = "ambient", feature = "breathe"))]
+use crate::status_leds::PWM_FREQUENCY_HZ;
+use crate::status_leds::{StatusLeds, DEFAULT_BRIGHTNESS};
+#[cfg(feature = "ambient")]
+use keypad_core::ambient::Slew;
+#[cfg(feature = "breathe")]
+use keypad_core::breath::{self, Breath};
+use stm32f1xx_hal::gpio::{ErasedPin, Output};
+
+/// How long a slew to a new brightness takes.
+#[cfg(feature = "ambient")]
+pub const SLEW_MS: u32 = 500;
+
+#[cfg(feature = "ambient")]
+const SLEW_PERIODS: u8 = (SLEW_MS * PWM_FREQUENCY_HZ / 1000) as u8;
+
+#[cfg(feature = "breathe")]
+const BREATH_PERIODS: u8 = (PWM_FREQUENCY_HZ / breath::STEP_HZ) as u8;
+
+pub struct Leds {
+    dimmable: StatusLeds,
+    green: ErasedPin<Output>,
+    brightness: u8,
+    // of 255, see `LedController::level`, `level` fades down to `target`
+    level: u8,
+    target: u8,
+    // of `brightness`, a step per PWM period
+    #[cfg(feature = "ambient")]
+    slew: Slew,
+    // the setting, whether the controller lets it breathe now, the blue light of
+    // the phase and the PWM periods into the step
+    #[cfg(feature = "breathe")]
+    breath: Breath,
+    #[cfg(feature = "breathe")]
+    breathing: bool,
+    #[cfg(feature = "breathe")]
+    calm: bool,
+    #[cfg(feature = "breathe")]
+    blue: bool,
+    #[cfg(feature = "breathe")]
+    periods: u8,
+}
+
+impl Leds {
+    pub fn new(dimmable: StatusLeds, green: ErasedPin<Output>) -> Self {
+        Self {
+            dimmable,
+            green,
+            brightness: DEFAULT_BRIGHTNESS,
+            level: 255,
+            target: 255,
+            #[cfg(feature = "ambient")]
+            slew: Slew::new(DEFAULT_BRIGHTNESS),
+            #[cfg(feature = "breathe")]
+            breath: Breath::new(),
+            #[cfg(feature = "breathe")]
+            breathing: false,
+            #[cfg(feature = "breathe")]
+            calm: false,
+            #[cfg(feature = "breathe")]
+            blue: false,
+            #[cfg(feature = "breathe")]
+            periods: 0,
+        }
+    }
+
+    /// Lights the leds of the `lights` bits of a phase, the others go dark.
+    pub fn show(&mut self, lights: u8) {
+        self.dimmable.set(Led::Red, lights & led_mode::RED != 0);
+        #[cfg(not(feature = "breathe"))]
+        self.dimmable.set(Led::Blue, lights & led_mode::BLUE != 0);
+        #[cfg(feature = "breathe")]
+        {
+            self.blue = lights & led_mode::BLUE != 0;
+            self.dimmable.set(Led::Blue, self.blue || self.breathes());
+        }
+        if lights & led_mode::GREEN != 0 {
+            self.green.set_high();
+        } else {
+            self.green.set_low();
+        }
+    }
+
+    pub fn set(&mut self, led: Led, on: bool) {
+        self.dimmable.set(led, on);
+    }
+
+    pub fn toggle(&mut self, led: Led) {
+        self.dimmable.toggle(led);
+    }
+
+    /// The brightness of the red and the blue led, they are dimmed together.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        #[cfg(feature = "ambient")]
+        self.slew.jump(brightness);
+        self.apply_brightness();
+    }
+
+    /// Gets to `brightness` over [`SLEW_MS`].
+    #[cfg(feature = "ambient")]
+    pub fn slew_brightness(&mut self, brightness: u8) {
+        self.slew.slew_to(brightness, SLEW_PERIODS);
+    }
+
+    /// Lights the red and the blue led at `level` of their brightness, of 255. A
+    /// lower one is faded down to.
+    pub fn set_level(&mut self, level: u8) {
+        self.target = level;
+        if level > self.level {
+            self.level = level;
+            self.apply_brightness();
+        }
+    }
+
+    /// Breathes the blue led with `breathing` on, see [`Leds::set_calm`].
+    #[cfg(feature = "breathe")]
+    pub fn set_breathing(&mut self, breathing: bool) {
+        self.breathing = breathing;
+        self.dimmable.set(Led::Blue, self.blue || self.breathes());
+        self.apply_brightness();
+    }
+
+    #[cfg(feature = "breathe")]
+    pub fn breathing(&self) -> bool {
+        self.breathing
+    }
+
+    /// Whether the mode shown lets the blue led breathe.
+    #[cfg(feature = "breathe")]
+    pub fn set_calm(&mut self, calm: bool) {
+        self.calm = calm;
+        self.apply_brightness();
+    }
+
+    #[cfg(feature = "breathe")]
+    fn breathes(&self) -> bool {
+        self.breathing && self.calm
+    }
+
+    fn apply_brightness(&mut self) {
+        let lit = (u16::from(self.brightness) * u16::from(self.level) / 255) as u8;
+        self.dimmable.set_brightness(Led::Red, lit);
+        #[cfg(feature = "breathe")]
+        let lit = match self.breathes() {
+            true => (u16::from(lit) * u16::from(self.breath.level()) / 255) as u8,
+            false => lit,
+        };
+        self.dimmable.set_brightness(Led::Blue, lit);
+    }
+
+    /// Called from the TIM2 interrupt, see [`StatusLeds::on_interrupt`].
+    pub fn on_interrupt(&mut self) {
+        let period = self.dimmable.on_interrupt();
+        #[cfg(feature = "ambient")]
+        if let Some(brightness) = period.then(|| self.slew.advance()).flatten() {
+            self.brightness = brightness;
+            self.apply_brightness();
+        }
+        if period && self.level > self.target {
+            self.level -= 1;
+            self.apply_brightness();
+        }
+        #[cfg(feature = "breathe")]
+        if period && self.breathes() {
+            self.periods += 1;
+            if self.periods == BREATH_PERIODS {
+                self.periods = 0;
+                self.breath.advance();
+                self.apply_brightness();
+            }
+        }
+    }
+}
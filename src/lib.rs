@@ -0,0 +1,10 @@
+//! The bring-up the firmware shares with the hardware tests in `tests/hw.rs`: the
+//! clock tree and the pins of the board, the TIM4 monotonic, the blocking delays
+//! and the matrix drivers.
+
+#![no_std]
+
+pub mod board;
+pub mod delay;
+pub mod keypad;
+pub mod monotonic;
@@ -0,0 +1,73 @@
+//! Counters that outlive a run, in the backup registers next to the panic record
+//! of `crate::crash`. They are written back every [`SAVE_PERIOD_MS`] and when the
+//! emergency stop latches, a reset loses what was counted since. A power loss
+//! without a backup battery leaves garbage, which the magic number catches.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use stm32f1xx_hal::backup_domain::BackupDomain;
+
+// DR3, the counters follow as pairs of registers, low half first
+const MAGIC_REGISTER: usize = 2;
+const BOOTS_REGISTER: usize = 3;
+const BLINKS_REGISTER: usize = 5;
+const KEYPRESSES_REGISTER: usize = 7;
+// in the magic register once the counters are valid
+const VALID: u16 = 0x1c0e;
+
+pub const SAVE_PERIOD_MS: u32 = 60_000;
+
+// of every run so far, counted on from the saved value
+static KEYPRESSES: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Counters {
+    // this one included
+    pub boots: u32,
+    // counted steps of the blink chain, see `crate::blink`
+    pub blinks: u32,
+    pub keypresses: u32,
+}
+
+/// The counters of the previous runs, zeroed if the registers don't hold any.
+/// Counts and saves this boot.
+pub fn restore(backup_domain: &BackupDomain) -> Counters {
+    let valid = backup_domain.read_data_register_low(MAGIC_REGISTER) == VALID;
+    let read = |register| {
+        if !valid {
+            return 0;
+        }
+        let low = backup_domain.read_data_register_low(register);
+        let high = backup_domain.read_data_register_low(register + 1);
+        u32::from(high) << 16 | u32::from(low)
+    };
+    let counters = Counters {
+        boots: read(BOOTS_REGISTER).wrapping_add(1),
+        blinks: read(BLINKS_REGISTER),
+        keypresses: read(KEYPRESSES_REGISTER),
+    };
+    KEYPRESSES.store(counters.keypresses, Ordering::Relaxed);
+    write(backup_domain, BOOTS_REGISTER, counters.boots);
+    save(backup_domain, counters.blinks);
+    // last, a reset in between starts the counters over on the next boot again
+    backup_domain.write_data_register_low(MAGIC_REGISTER, VALID);
+    counters
+}
+
+/// Writes the blink count and the key presses counted so far back.
+pub fn save(backup_domain: &BackupDomain, blinks: u32) {
+    write(backup_domain, BLINKS_REGISTER, blinks);
+    write(backup_domain, KEYPRESSES_REGISTER, keypresses());
+}
+
+pub fn count_keypress() {
+    KEYPRESSES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn keypresses() -> u32 {
+    KEYPRESSES.load(Ordering::Relaxed)
+}
+
+fn write(backup_domain: &BackupDomain, register: usize, value: u32) {
+    backup_domain.write_data_register_low(register, value as u16);
+    backup_domain.write_data_register_low(register + 1, (value >> 16) as u16);
+}
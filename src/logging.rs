@@ -0,0 +1,192 @@
+//! Log lines are formatted where they are logged and queued, the `logger` task
+//! at the lowest priority writes them to the sinks. A blocking RTT channel then
+//! only ever stalls the logger, not the task that logged.
+//!
+//! With the `defmt` feature the lines go out as defmt frames over RTT instead,
+//! formatted on the host. The format strings are kept to what both `core::fmt`
+//! and defmt understand. With `no-log` they are only type checked, nothing of
+//! the log is left in the image. With `itm` the lines also go out on an ITM
+//! stimulus port over SWO, see `crate::itm`.
+
+#[cfg(any(not(any(feature = "defmt", feature = "no-log")), feature = "rtt"))]
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+#[cfg(any(not(any(feature = "defmt", feature = "no-log")), feature = "rtt"))]
+use cortex_m::interrupt::{self, Mutex};
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+use heapless::Deque;
+
+/// Longest log line, anything after it is cut off. Command replies are lines as
+/// well, see `Reply` in `main.rs`.
+pub const LINE_LEN: usize = 80;
+
+pub type Line = heapless::String<LINE_LEN>;
+
+/// A string argument of the logs and the replies, written as it is. `{}` of a
+/// `str` goes through the padding of `core::fmt`, which no format string here
+/// asks for and which takes more flash than all the wrappers.
+pub struct Text<'a>(pub &'a str);
+
+/// A `char` argument, for the same reason.
+pub struct Char(pub char);
+
+impl fmt::Display for Text<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl fmt::Display for Char {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_char(self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Text<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Char {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", self.0)
+    }
+}
+
+// lines waiting for the logger, the oldest one goes when it is full
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+const QUEUE_LEN: usize = 16;
+
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+struct Queue {
+    lines: Deque<Line, QUEUE_LEN>,
+    dropped: u32,
+}
+
+// the RTT terminal, written straight instead of through the `rprintln!`
+// machinery, which doesn't fit into the flash next to everything else
+#[cfg(feature = "rtt")]
+static TERMINAL: Mutex<RefCell<Option<rtt_target::UpChannel>>> = Mutex::new(RefCell::new(None));
+
+// pushed to from every priority level, so it is guarded by a critical section
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+static QUEUE: Mutex<RefCell<Queue>> = Mutex::new(RefCell::new(Queue {
+    lines: Deque::new(),
+    dropped: 0,
+}));
+
+/// Formats the message and queues it for the `logger` task, which sends it to
+/// USART1 as a line in `text` mode or as a log message otherwise. With the `rtt`
+/// feature it is also printed over RTT and with the `cdc` feature copied to the
+/// USB serial port.
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logging::log(format_args!($($arg)*))
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        defmt::info!($($arg)*)
+    };
+}
+
+// never evaluates the arguments, a pop or a lock in them is gone with the line
+#[cfg(feature = "no-log")]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+// microseconds since boot, zero until init has set up the monotonic
+#[cfg(feature = "defmt")]
+defmt::timestamp!("{=u64:us}", crate::app::monotonics::now().ticks());
+
+// out of line, every `log!` formatting in place doesn't fit into the flash
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+#[inline(never)]
+pub fn log(args: core::fmt::Arguments) {
+    let mut line = Line::new();
+    let _ = core::fmt::write(&mut line, args);
+    push(line);
+}
+
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+pub fn push(line: Line) {
+    interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        if queue.lines.is_full() {
+            queue.lines.pop_front();
+            queue.dropped += 1;
+        }
+        let _ = queue.lines.push_back(line);
+    });
+    // already pending means it drains this line too
+    let _ = crate::app::logger::spawn();
+}
+
+/// Writes out every queued line, followed by the number of lines dropped since
+/// the last flush. Runs in the `logger` task, and synchronously in handlers that
+/// may be the last thing that runs.
+pub fn flush() {
+    #[cfg(not(any(feature = "defmt", feature = "no-log")))]
+    {
+        let pop = || interrupt::free(|cs| QUEUE.borrow(cs).borrow_mut().lines.pop_front());
+        while let Some(line) = pop() {
+            write(&line);
+        }
+        let dropped =
+            interrupt::free(|cs| core::mem::take(&mut QUEUE.borrow(cs).borrow_mut().dropped));
+        if dropped > 0 {
+            let mut line = Line::new();
+            let _ = core::fmt::write(&mut line, format_args!("dropped log lines: {}", dropped));
+            write(&line);
+        }
+    }
+}
+
+#[cfg(not(any(feature = "defmt", feature = "no-log")))]
+fn write(line: &str) {
+    // USART1 is the MIDI port in a `midi` build, without RTT and USB the lines go
+    // nowhere
+    #[cfg(all(
+        feature = "midi",
+        not(any(feature = "rtt", feature = "itm", feature = "cdc"))
+    ))]
+    let _ = line;
+    #[cfg(all(feature = "text", not(feature = "midi")))]
+    crate::uart::write_line(line);
+    #[cfg(not(any(feature = "text", feature = "midi")))]
+    crate::wire::send(&crate::wire::Message::Log(line));
+    #[cfg(feature = "rtt")]
+    write_terminal(line);
+    #[cfg(feature = "itm")]
+    crate::itm::write_line(line);
+    #[cfg(feature = "cdc")]
+    crate::serial::write_line(line);
+}
+
+/// Hands the RTT up-channel 0 to [`write_terminal`].
+#[cfg(feature = "rtt")]
+pub fn set_terminal(channel: rtt_target::UpChannel) {
+    interrupt::free(|cs| *TERMINAL.borrow(cs).borrow_mut() = Some(channel));
+}
+
+/// Writes `line` and a newline to the RTT terminal, if it was set up yet.
+#[cfg(feature = "rtt")]
+pub fn write_terminal(line: &str) {
+    interrupt::free(|cs| {
+        if let Some(channel) = TERMINAL.borrow(cs).borrow_mut().as_mut() {
+            channel.write(line.as_bytes());
+            channel.write(b"\n");
+        }
+    });
+}
@@ -0,0 +1,396 @@
+//! Key macros, a key that plays back the presses and releases recorded for it
+//! with their timing. `MACRO RECORD <key>`, or the [`MACRO_CHORD`] followed by a
+//! press of the key, starts a recording; the keys pressed meanwhile go through as
+//! usual. The next press of the macro key ends it.
+//!
+//! A press of the macro key plays the macro, another one while it plays cancels
+//! it and releases whatever it holds. The macro keys produce no events of their
+//! own, and no macro may hold a macro key, so a playback never starts another.
+//!
+//! Only with the `macros` feature, the shared resource is a stand-in otherwise.
+
+#[cfg(feature = "macros")]
+use crate::app::{macro_play, now_u64};
+#[cfg(feature = "macros")]
+use crate::chord::MACRO_CHORD;
+#[cfg(feature = "macros")]
+use crate::event::{EventKind, InputEvent, KeyEvent};
+#[cfg(feature = "macros")]
+use crate::keypad::{KeyState, COLUMNS};
+#[cfg(feature = "macros")]
+use crate::monotonic;
+#[cfg(feature = "macros")]
+use crate::rtc;
+#[cfg(feature = "macros")]
+use crate::spawn::Counted;
+#[cfg(feature = "macros")]
+use rtic::mutex::prelude::*;
+
+#[cfg(feature = "macros")]
+pub const SLOTS: usize = 3;
+// presses and releases of a macro
+#[cfg(feature = "macros")]
+pub const MAX_STEPS: usize = 32;
+// `Some` plays the steps that many milliseconds apart instead of as recorded
+#[cfg(feature = "macros")]
+pub const FIXED_DELAY_MS: Option<u32> = None;
+
+#[cfg(feature = "macros")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    index: u8,
+    key: char,
+    pressed: bool,
+    // since the step before
+    after_ms: u16,
+}
+
+#[cfg(feature = "macros")]
+impl Step {
+    /// The event to play, queued with the time and the locks of the playback.
+    pub fn event(&self) -> KeyEvent {
+        let kind = match self.pressed {
+            true => EventKind::Pressed,
+            false => EventKind::Released { held_ms: 0 },
+        };
+        KeyEvent {
+            key: self.key,
+            ..KeyEvent::new(usize::from(self.index), kind)
+        }
+    }
+
+    fn bit(&self) -> KeyState {
+        KeyState(1 << self.index)
+    }
+
+    fn delay_ms(&self) -> u32 {
+        FIXED_DELAY_MS.unwrap_or(u32::from(self.after_ms))
+    }
+}
+
+#[cfg(feature = "macros")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordError {
+    // a macro is recorded or played
+    Busy,
+    // another macro holds the key
+    Recursive,
+    Full,
+}
+
+#[cfg(feature = "macros")]
+impl RecordError {
+    pub fn reply(self) -> &'static str {
+        match self {
+            RecordError::Busy => "ERR busy",
+            RecordError::Recursive => "ERR recursive",
+            RecordError::Full => "ERR full",
+        }
+    }
+}
+
+#[cfg(feature = "macros")]
+/// What becomes of a key event, see [`Macros::handle`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Pass,
+    Swallow,
+    // swallowed, with the red led flashed
+    Reject,
+    // swallowed, `macro_play` has to run with the generation
+    Play(u32),
+}
+
+#[cfg(feature = "macros")]
+struct Slot {
+    key: char,
+    steps: heapless::Vec<Step, MAX_STEPS>,
+}
+
+#[cfg(feature = "macros")]
+enum State {
+    Idle,
+    // the chord was pressed, the next press picks the macro key
+    Armed,
+    Recording {
+        slot: usize,
+        last_ms: u32,
+        // pressed since the recording started
+        held: KeyState,
+    },
+    Playing {
+        slot: usize,
+        next: usize,
+        held: KeyState,
+        // only the releases of `held` are left to play
+        cancelled: bool,
+    },
+}
+
+#[cfg(feature = "macros")]
+pub struct Macros {
+    slots: [Option<Slot>; SLOTS],
+    state: State,
+    // tells the scheduled `macro_play` of a cancelled playback from the current one
+    generation: u32,
+}
+
+#[cfg(feature = "macros")]
+impl Macros {
+    pub const fn new() -> Self {
+        const EMPTY: Option<Slot> = None;
+        Self {
+            slots: [EMPTY; SLOTS],
+            state: State::Idle,
+            generation: 0,
+        }
+    }
+
+    /// Starts recording the macro of `key`, over the one it had. Refused while
+    /// a macro is recorded or played.
+    pub fn record(&mut self, key: char, now: u32) -> Result<(), RecordError> {
+        if matches!(self.state, State::Recording { .. } | State::Playing { .. }) {
+            return Err(RecordError::Busy);
+        }
+        self.state = State::Idle;
+        let mut slots = self.slots.iter().flatten();
+        if slots.any(|slot| slot.key != key && slot.steps.iter().any(|step| step.key == key)) {
+            return Err(RecordError::Recursive);
+        }
+        let slot = self
+            .slots
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|slot| slot.key == key))
+            .or_else(|| self.slots.iter().position(Option::is_none))
+            .ok_or(RecordError::Full)?;
+        self.slots[slot] = Some(Slot {
+            key,
+            steps: heapless::Vec::new(),
+        });
+        self.state = State::Recording {
+            slot,
+            last_ms: now,
+            held: KeyState(0),
+        };
+        Ok(())
+    }
+
+    /// Records, starts or cancels macros as the resolved key `event` asks for.
+    pub fn handle(&mut self, event: &KeyEvent, now: u32) -> Action {
+        let pressed = event.kind == EventKind::Pressed;
+        let recording = matches!(self.state, State::Recording { .. });
+        if event.kind == EventKind::Chord(MACRO_CHORD) && !recording {
+            self.state = State::Armed;
+            return Action::Pass;
+        }
+        if pressed && matches!(self.state, State::Armed) {
+            return match self.record(event.key, now) {
+                Ok(()) => Action::Swallow,
+                Err(_) => Action::Reject,
+            };
+        }
+        let Some(slot) = self.slot_of(event.key) else {
+            return match &mut self.state {
+                State::Recording {
+                    slot,
+                    last_ms,
+                    held,
+                } => {
+                    let steps = &mut self.slots[*slot].as_mut().unwrap().steps;
+                    capture(steps, last_ms, held, event, now)
+                }
+                _ => Action::Pass,
+            };
+        };
+        if !pressed {
+            return Action::Swallow;
+        }
+        match &mut self.state {
+            State::Recording {
+                slot: recording, ..
+            } if *recording == slot => {
+                self.finish();
+                Action::Swallow
+            }
+            State::Recording { .. } => Action::Reject,
+            State::Playing { .. } | State::Idle | State::Armed => self.start(slot),
+        }
+    }
+
+    /// Plays the macro of `slot` on a press of the key bound to it, see
+    /// `crate::bind`, or cancels the one playing.
+    #[cfg(feature = "bind")]
+    pub fn handle_bound(&mut self, slot: usize, event: &KeyEvent) -> Action {
+        if event.kind != EventKind::Pressed {
+            return Action::Swallow;
+        }
+        // an empty slot plays nothing, and a recording may not hold a macro key
+        if self.slots[slot].is_none() || matches!(self.state, State::Recording { .. }) {
+            return Action::Reject;
+        }
+        self.start(slot)
+    }
+
+    // a press of a macro key while one plays cancels it
+    fn start(&mut self, slot: usize) -> Action {
+        match &mut self.state {
+            State::Playing { cancelled, .. } => *cancelled = true,
+            _ => {
+                self.state = State::Playing {
+                    slot,
+                    next: 0,
+                    held: KeyState(0),
+                    cancelled: false,
+                }
+            }
+        }
+        self.generation = self.generation.wrapping_add(1);
+        Action::Play(self.generation)
+    }
+
+    /// The next step of the playback `generation` and the milliseconds until the
+    /// one after it, `None` once the playback is over or was replaced.
+    pub fn play(&mut self, generation: u32) -> Option<(Step, u32)> {
+        let State::Playing {
+            slot,
+            next,
+            held,
+            cancelled,
+        } = &mut self.state
+        else {
+            return None;
+        };
+        if generation != self.generation {
+            return None;
+        }
+        let steps = &self.slots[*slot].as_ref().unwrap().steps;
+        while let Some(step) = steps.get(*next) {
+            *next += 1;
+            if *cancelled && (step.pressed || (*held & step.bit()).is_empty()) {
+                continue;
+            }
+            if step.pressed {
+                *held |= step.bit();
+            } else {
+                *held &= !step.bit();
+            }
+            let delay_ms = match cancelled {
+                true => 0,
+                false => steps.get(*next).map_or(0, Step::delay_ms),
+            };
+            return Some((*step, delay_ms));
+        }
+        self.state = State::Idle;
+        None
+    }
+
+    fn slot_of(&self, key: char) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|slot| slot.key == key))
+    }
+
+    // the keys still held are released at the end
+    fn finish(&mut self) {
+        if let State::Recording { slot, held, .. } = self.state {
+            let steps = &mut self.slots[slot].as_mut().unwrap().steps;
+            for (row, col) in held.iter_pressed() {
+                let index = row * COLUMNS as u8 + col;
+                let press = steps.iter().rev().find(|step| step.index == index);
+                if let Some(&press) = press {
+                    let release = Step {
+                        pressed: false,
+                        after_ms: 0,
+                        ..press
+                    };
+                    // `capture` left room for it
+                    let _ = steps.push(release);
+                }
+            }
+        }
+        self.state = State::Idle;
+    }
+}
+
+#[cfg(feature = "macros")]
+fn capture(
+    steps: &mut heapless::Vec<Step, MAX_STEPS>,
+    last_ms: &mut u32,
+    held: &mut KeyState,
+    event: &KeyEvent,
+    now: u32,
+) -> Action {
+    let index = event.row * COLUMNS as u8 + event.col;
+    let bit = KeyState(1 << index);
+    let pressed = match event.kind {
+        EventKind::Pressed => true,
+        EventKind::Released { .. } | EventKind::ReleasedAfterLong => false,
+        _ => return Action::Pass,
+    };
+    if pressed {
+        // a full macro drops the press, the releases of the keys held have to fit
+        // in behind it
+        let held_keys = held.iter_pressed().count();
+        if steps.len() + held_keys + 2 > MAX_STEPS {
+            return Action::Reject;
+        }
+        *held |= bit;
+    } else {
+        // pressed before the recording started
+        if (*held & bit).is_empty() {
+            return Action::Pass;
+        }
+        *held &= !bit;
+    }
+    let after_ms = match steps.is_empty() {
+        true => 0,
+        false => now.wrapping_sub(*last_ms).min(u32::from(u16::MAX)) as u16,
+    };
+    *last_ms = now;
+    let _ = steps.push(Step {
+        index,
+        key: event.key,
+        pressed,
+        after_ms,
+    });
+    Action::Pass
+}
+
+#[cfg(feature = "macros")]
+/// Queues the steps of a macro due now and comes back for the next one, the body
+/// of `macro_play`. The generation drops the runs of a playback cancelled
+/// meanwhile.
+pub fn macro_play(ctx: macro_play::Context, generation: u32) {
+    let mut shared = (
+        ctx.shared.macros,
+        ctx.shared.modifiers,
+        ctx.shared.event_producer,
+    );
+    shared.lock(|macros, modifiers, producer| {
+        while let Some((step, delay_ms)) = macros.play(generation) {
+            let event = KeyEvent {
+                at: now_u64(),
+                time: rtc::seconds(),
+                modifiers: *modifiers,
+                ..step.event()
+            };
+            producer.push(InputEvent::Playback(event));
+            if delay_ms > 0 {
+                macro_play::spawn_after(monotonic::millis(delay_ms), generation).or_count();
+                break;
+            }
+        }
+    });
+}
+
+/// Stand-in without the `macros` feature, the shared resource of the tasks that
+/// would record and play them.
+#[cfg(not(feature = "macros"))]
+pub struct Macros;
+
+#[cfg(not(feature = "macros"))]
+impl Macros {
+    pub const fn new() -> Self {
+        Self
+    }
+}
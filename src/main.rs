@@ -1,168 +1,1664 @@
 #![no_std]
 #![no_main]
 
-use panic_halt as _;
+#[cfg(feature = "defmt")]
+use defmt_rtt as _;
+#[cfg(feature = "defmt")]
+use panic_probe as _;
 
-#[rtic::app(device = stm32f1xx_hal::pac, dispatchers = [UART5, UART4])]
+#[cfg(all(feature = "mcp23017", feature = "i2c-slave"))]
+compile_error!("the `mcp23017` and `i2c-slave` features both need I2C1");
+#[cfg(all(feature = "mcp23017", feature = "shift-register"))]
+compile_error!(
+    "the `mcp23017` feature moves the whole matrix, `shift-register` has nothing to drive"
+);
+#[cfg(all(feature = "lcd", feature = "shift-register"))]
+compile_error!("the `lcd` and `shift-register` features both use PB3, PB5 and PB8");
+#[cfg(all(feature = "ps2", any(feature = "lcd", feature = "shift-register")))]
+compile_error!("the `ps2` feature needs PB8 and PB9, which `lcd` and `shift-register` use");
+#[cfg(all(feature = "lcd", feature = "backlight"))]
+compile_error!("the `lcd` and `backlight` features both use PB15");
+#[cfg(all(
+    feature = "velocity",
+    any(feature = "joystick", feature = "battery", feature = "ambient")
+))]
+compile_error!(
+    "the `velocity` feature takes ADC1 for its scan, `joystick`, `battery` and `ambient` read it one-shot"
+);
+#[cfg(all(feature = "battery", any(feature = "buzzer", feature = "i2c-slave")))]
+compile_error!("the `battery` feature needs PB1, which `buzzer` and `i2c-slave` use");
+#[cfg(all(
+    feature = "ambient",
+    any(feature = "battery", feature = "buzzer", feature = "i2c-slave")
+))]
+compile_error!("the `ambient` feature needs PB1, which `battery`, `buzzer` and `i2c-slave` use");
+#[cfg(all(feature = "servo", feature = "buzzer"))]
+compile_error!("the `servo` and `buzzer` features both need TIM3");
+#[cfg(all(feature = "buzzer", feature = "i2c-slave"))]
+compile_error!("the `buzzer` and `i2c-slave` features both use PB1");
+#[cfg(all(feature = "sliced-scan", feature = "mcp23017"))]
+compile_error!("the `sliced-scan` feature needs the GPIO matrix, the expander is scanned at once");
+#[cfg(all(
+    feature = "open-drain",
+    any(feature = "shift-register", feature = "mcp23017")
+))]
+compile_error!("the `open-drain` feature is for columns on GPIO pins");
+#[cfg(all(feature = "defmt", feature = "rtt"))]
+compile_error!("the `defmt` and `rtt` features both set up the RTT control block");
+#[cfg(all(feature = "debug-bounce", feature = "mcp23017"))]
+compile_error!("the `debug-bounce` feature needs the rows on EXTI lines, the expander has none");
+#[cfg(all(feature = "itm", any(feature = "rtt", feature = "defmt")))]
+compile_error!("the `rtt`, `defmt` and `itm` features are three log transports, enable one");
+#[cfg(all(feature = "no-log", any(feature = "defmt", feature = "itm")))]
+compile_error!("the `no-log` feature drops the log, which `defmt` and `itm` are there for");
+#[cfg(all(feature = "itm", any(feature = "lcd", feature = "shift-register")))]
+compile_error!("the `itm` feature needs PB3 for SWO, which `lcd` and `shift-register` use");
+#[cfg(all(feature = "uart-config", feature = "midi"))]
+compile_error!("the `uart-config` feature is for the command port, MIDI keeps to 31250 8N1");
+#[cfg(all(feature = "second-pad", feature = "i2c-slave"))]
+compile_error!("the `second-pad` feature has 32 keys, the I2C key register takes 16");
+#[cfg(all(feature = "bind", feature = "nkro"))]
+compile_error!(
+    "the `bind` feature rebinds the keymap characters, the NKRO keyboard reports key positions"
+);
+#[cfg(all(feature = "pull-check", feature = "mcp23017"))]
+compile_error!("the `pull-check` feature floats the GPIO rows, the expander's pulls are internal");
+
+#[macro_use]
+mod logging;
+
+// shared with the hardware tests in `tests/hw.rs`
+use key_board_4_4_rtic::{board, delay, keypad, monotonic};
+
+#[cfg(feature = "ambient")]
+mod ambient;
+mod backlight;
+mod battery;
+#[cfg(feature = "bind")]
+mod bind;
+mod blink;
+mod boot;
+mod bootloader;
+#[cfg(feature = "debug-bounce")]
+mod bounce;
+#[cfg(feature = "cdc")]
+mod buffer;
+mod buzzer;
+mod chord;
+mod clock_manager;
+mod command;
+mod config;
+mod config_mode;
+mod crash;
+mod crc;
+mod display;
+#[cfg(feature = "dump")]
+mod dump;
+mod emergency;
+mod encoder;
+mod entry;
+mod event;
+mod execute;
+mod gesture;
+mod health;
+mod hid;
+#[cfg(feature = "hold-cap")]
+mod hold_cap;
+#[cfg(feature = "i2c-slave")]
+mod i2c_slave;
+mod identity;
+mod inject;
+mod input;
+#[cfg(feature = "itm")]
+mod itm;
+mod job;
+mod joystick;
+#[cfg(feature = "key-debounce")]
+mod key_debounce;
+#[cfg(feature = "key-test")]
+mod key_test;
+mod keymap;
+mod lcd;
+mod led_mode;
+mod leds;
+mod lifetime;
+mod macros;
+#[cfg(feature = "mcp23017")]
+mod mcp23017;
+mod mem;
+#[cfg(feature = "midi")]
+mod midi;
+mod modifiers;
+#[cfg(feature = "morse")]
+mod morse;
+mod output;
+mod piano;
+mod pin;
+mod profile;
+#[cfg(feature = "ps2")]
+mod ps2;
+#[cfg(feature = "relays")]
+mod relays;
+mod reset;
+mod route;
+mod router;
+mod rtc;
+mod rtt;
+mod scanner;
+mod sensors;
+#[cfg(feature = "cdc")]
+mod serial;
+#[cfg(feature = "servo")]
+mod servo;
+mod sleep;
+mod spawn;
+#[cfg(feature = "stack-watch")]
+mod stack;
+mod stats;
+mod status_leds;
+#[cfg(feature = "time-sync")]
+mod time_sync;
+mod timing;
+mod uart;
+mod usb;
+#[cfg(feature = "velocity")]
+mod velocity;
+mod watchdog;
+#[cfg(not(feature = "text"))]
+mod wire;
+
+#[rtic::app(device = stm32f1xx_hal::pac, dispatchers = [UART5, UART4, SPI3, CAN_SCE])]
 mod app {
 
+    #[cfg(feature = "ambient")]
+    use crate::ambient;
+    #[cfg(feature = "backlight")]
+    use crate::backlight;
+    use crate::backlight::{Backlight, Strip};
+    use crate::battery::{Battery, BatteryPin};
+    #[cfg(feature = "bind")]
+    use crate::bind;
+    use crate::blink::{self, BlinkPhase};
+    #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+    use crate::board::ColumnOutput;
+    #[cfg(not(feature = "mcp23017"))]
+    use crate::board::Wiring;
+    use crate::board::{self, Board};
+    use crate::boot::{self, BootStages};
+    use crate::bootloader;
+    #[cfg(feature = "debug-bounce")]
+    use crate::bounce;
+    use crate::buzzer::Buzzer;
+    use crate::buzzer::{self, Tone};
+    use crate::clock_manager::ClockManager;
+    use crate::command::{Command, CommandError, CommandReader, ReplyTo};
+    use crate::config::{self, Settings};
+    #[cfg(feature = "config-mode")]
+    use crate::config_mode;
+    use crate::config_mode::{ConfigInput, ConfigKeys, ConfigMode};
+    use crate::crash;
+    use crate::crc;
+    use crate::delay::Delay;
+    use crate::display::{DisplayModel, StatusDisplay};
+    use crate::emergency::{self, EmergencyButton};
+    use crate::encoder::{EncoderEvent, Quadrature};
+    use crate::entry::{self, Entry};
+    use crate::event::{EventProducer, History, InputEvent, KeyEvent};
+    use crate::execute;
+    use crate::gesture;
+    use crate::health;
+    #[cfg(feature = "hold-cap")]
+    use crate::hold_cap;
+    #[cfg(feature = "i2c-slave")]
+    use crate::i2c_slave;
+    use crate::identity;
+    use crate::inject::Injected;
+    use crate::input::{self, Buttons, PinSource};
+    #[cfg(feature = "itm")]
+    use crate::itm;
+    use crate::job::{self, Job};
+    use crate::joystick::{Joystick, JoystickPins};
+    #[cfg(feature = "key-debounce")]
+    use crate::key_debounce;
+    #[cfg(feature = "key-test")]
+    use crate::key_test;
+    use crate::keymap::{Layers, CHORDS, LAYOUT, SEQUENCES};
+    #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+    use crate::keypad::GpioColumns;
+    #[cfg(feature = "shift-register")]
+    use crate::keypad::ShiftRegisterColumns;
+    use crate::keypad::{
+        self, KeyState, Matrix, PressLimit, SlicedScan, Thresholds, COLUMNS, MAX_SIMULTANEOUS,
+    };
+    #[cfg(not(feature = "mcp23017"))]
+    use crate::keypad::{GpioRows, Keypad, ROWS};
+    use crate::lcd::Lcd;
+    use crate::led_mode::{self, ErrorCode, LedController, LedMessage, LedMode};
+    use crate::leds::Leds;
+    use crate::lifetime;
+    #[cfg(feature = "lock-pins")]
+    use crate::logging::Char;
+    use crate::logging::{self, Text};
+    #[cfg(feature = "macros")]
+    use crate::macros;
+    use crate::macros::Macros;
+    #[cfg(feature = "mcp23017")]
+    use crate::mcp23017::Mcp23017;
+    use crate::modifiers::{self, Modifiers};
+    use crate::monotonic::{self, Tim4Monotonic};
+    #[cfg(feature = "morse")]
+    use crate::morse::Morse;
+    use crate::output::OutputMode;
+    use crate::piano::{Note, Piano};
+    use crate::pin::PinLock;
+    use crate::profile::{Lateness, ScanTiming};
+    #[cfg(feature = "ps2")]
+    use crate::ps2;
+    #[cfg(feature = "relays")]
+    use crate::relays;
+    use crate::reset::{self, Request};
+    use crate::router;
+    use crate::rtc::{self, Rtc};
+    use crate::rtt::{Console, EventChannel};
+    use crate::scanner::{self, EventEngine, Sequences};
+    use crate::sensors::{Diagnostics, InternalSensors};
+    #[cfg(feature = "servo")]
+    use crate::servo;
+    use crate::sleep::{self, DeepSleep, ScanMode, WakeMailbox};
+    use crate::spawn::Counted;
+    use crate::stats::KeyStats;
+    use crate::status_leds::StatusLeds;
+    use crate::timing;
+    use crate::uart::{self, UartDma};
+    use crate::usb::Usb;
+    #[cfg(feature = "velocity")]
+    use crate::velocity;
+    use crate::watchdog::{self, ResetCause, ResetCauses, WindowWatchdog};
+    #[cfg(not(feature = "text"))]
+    use crate::wire::{self, Message};
+    use cortex_m::peripheral::SCB;
+    use heapless::HistoryBuffer;
     use rtic::Monotonic;
-    use rtt_target::{rprintln, rtt_init_print};
+    #[cfg(feature = "rtt")]
+    use rtt_target::rtt_init;
+    use stm32f1xx_hal::adc::Adc;
+    use stm32f1xx_hal::backup_domain::BackupDomain;
+    use stm32f1xx_hal::flash;
+    #[cfg(any(feature = "mcp23017", feature = "display"))]
+    use stm32f1xx_hal::i2c::{BlockingI2c, DutyCycle, Mode as I2cMode};
+    #[cfg(feature = "mcp23017")]
+    use stm32f1xx_hal::pac::I2C1;
+    #[cfg(feature = "display")]
+    use stm32f1xx_hal::pac::I2C2;
+    use stm32f1xx_hal::pac::{ADC1, USART1};
+    use stm32f1xx_hal::serial::{Config, Rx, Serial};
+    #[cfg(feature = "backlight")]
+    use stm32f1xx_hal::spi::NoSck;
+    #[cfg(any(feature = "shift-register", feature = "backlight"))]
+    use stm32f1xx_hal::spi::{Mode, NoMiso, Phase, Polarity, Spi};
+    #[cfg(feature = "buzzer")]
+    use stm32f1xx_hal::timer::Tim3NoRemap;
+    use stm32f1xx_hal::usb::{Peripheral, UsbBus, UsbBusType};
+    use stm32f1xx_hal::watchdog::IndependentWatchdog;
     use stm32f1xx_hal::{gpio::*, prelude::*};
-    use systick_monotonic::{fugit::ExtU32, *};
+    #[cfg(feature = "shift-register")]
+    use stm32f1xx_hal::{pac::SPI1, spi::Spi1Remap};
+    use usb_device::bus::UsbBusAllocator;
+
+    // A monotonic timer to enable scheduling in RTIC, 1 us granularity. It only
+    // interrupts for a deadline or an overflow of its 16 bit counter, every ~65 ms.
+    #[monotonic(binds = TIM4, default = true)]
+    type MyMono = Tim4Monotonic;
+
+    #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+    type Scanner = Keypad<GpioColumns<COLUMNS, Wiring, ColumnOutput>, COLUMNS, ROWS, Wiring>;
+    #[cfg(feature = "shift-register")]
+    type Scanner = Keypad<
+        ShiftRegisterColumns<
+            Spi<SPI1, Spi1Remap, (PB3<Alternate<PushPull>>, NoMiso, PB5<Alternate<PushPull>>), u8>,
+            COLUMNS,
+            Wiring,
+        >,
+        COLUMNS,
+        ROWS,
+        Wiring,
+    >;
+    #[cfg(feature = "mcp23017")]
+    type Scanner =
+        Mcp23017<BlockingI2c<I2C1, (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>)>>;
 
-    // A monotonic timer to enable scheduling in RTIC
-    #[monotonic(binds = SysTick, default = true)]
-    type MyMono = Systick<100>; // 100 Hz / 10 ms granularity
+    #[cfg(not(feature = "display"))]
+    type Display = StatusDisplay;
+    #[cfg(feature = "display")]
+    type Display =
+        StatusDisplay<BlockingI2c<I2C2, (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>)>>;
 
     #[shared]
     struct Shared {
-        led_red: ErasedPin<Output>,
-        led_blue: ErasedPin<Output>,
+        // red and blue dimmed by `status_pwm`, green plain
+        status_leds: Leds,
+        // counted by every other run of `blinker`, see `crate::lifetime`
+        counter: u32,
+        // the counters of `crate::lifetime` are kept in it
+        backup_domain: BackupDomain,
+        // woken from STOP by its alarm, `TIME SET` sets the wall clock
+        rtc: Rtc,
+        // the pending run of `blinker`
+        blink_handle: Option<blinker::SpawnHandle>,
+        scan_period_ms: u32,
+        scans: u32,
+        event_producer: EventProducer,
+        // keys held long enough to count as stuck, see `keypad_core::debounce::StuckKeys`
+        stuck_keys: KeyState,
+        // scans a key has to be stable for to press and to release, the scanner
+        // picks a change up on its next scan
+        debounce: Thresholds,
+        // written by the `MAP` commands, the scanner resolves the keys with it
+        layers: Layers,
+        // toggled by the lock keys, saved with the settings
+        modifiers: Modifiers,
+        // toggled by a short press of the emergency button, the scanner parks the
+        // matrix as for the emergency stop
+        scan_paused: bool,
+        // the self-test found a short, at boot and then by the scanner every
+        // `timing::PRESENCE_CHECK`, see `Matrix::self_test`
+        matrix_fault: bool,
+        // read once in init, for `STATUS`
+        reset_causes: ResetCauses,
+        // keys held by `PRESS`, they count as read high on every scan
+        injected: Injected,
+        scan_lateness: Lateness,
+
+        usb: Usb,
+        display_model: DisplayModel,
+        lcd: Lcd,
+        backlight: Backlight,
+        buzzer: Buzzer,
+        beep_handle: Option<beeper::SpawnHandle>,
+        piano: Piano,
+        entry: Entry,
+        // the PIN and the wrong ones entered, saved with the settings but for the
+        // latter
+        pin_lock: PinLock,
+        macros: Macros,
+        output: OutputMode,
+        adc: Adc<ADC1>,
+        joystick: Joystick,
+        diagnostics: Diagnostics,
+        battery: Battery,
+        // latched by the emergency button, scanning and blinking are suspended
+        emergency: bool,
+        // `watchdog::BLINK`, set by the supervised tasks
+        alive: u8,
+        // runs of the scanner since boot, see `watchdog_feed`
+        scan_seq: u32,
+        // last time `event_router` took an event
+        drained_at: u32,
+        scan_mode: ScanMode,
+        stop_handle: Option<stop_timer::SpawnHandle>,
+        // the row line that woke the waiting scanner, its first frame takes it
+        wake: WakeMailbox,
+        // held by the polling scanner, by USB unless it is suspended and by the
+        // features that only run at full speed
+        clock: ClockManager,
+        usb_holds_clock: bool,
+        wwdg: WindowWatchdog,
+        // blocking waits in driver setup, see `delay`
+        delay: Delay,
+        rtt_events: EventChannel,
+        // the latest events off the queue, logged before a WWDG reset
+        recent_events: HistoryBuffer<InputEvent, 4>,
+        // logged by `HISTORY` and on an emergency stop
+        key_history: History,
+        key_stats: KeyStats,
+        last_emergency: Option<emergency::Snapshot>,
+        // microseconds `idle` slept since the last load sample
+        idle_us: u32,
+        cpu_load_percent: u8,
+        emergency_button: EmergencyButton,
     }
 
     #[local]
     struct Local {
-        counter: u32,
-        emergency_button: ErasedPin<Input<PullUp>>,
-        led_green: ErasedPin<Output>,
+        // driven by `led_controller` alone, see `crate::led_mode`
+        leds: LedController,
+        iwdg: IndependentWatchdog,
+        sleep: DeepSleep,
+        // last scan with a key held or the emergency stop latched
+        idle_since: u32,
+        // in a row without a key
+        empty_scans: u32,
+        // time of the last alive mask check
+        checked_at: u32,
+        engine: EventEngine<Scanner>,
+        // the matrix lines are released during an emergency stop
+        parked: bool,
+        // time of the first failed scan in a row
+        scan_failure: Option<u32>,
+        scan_timing: ScanTiming,
+        // time the scan timing was last logged
+        timing_reported_at: u32,
+        // the column by column scan of `sliced-scan`
+        sliced: SlicedScan<COLUMNS>,
+        sequences: Sequences,
+        encoder_a: ErasedPin<Input<PullUp>>,
+        encoder_b: ErasedPin<Input<PullUp>>,
+        quadrature: Quadrature,
+        encoder_button: ErasedPin<Input<PullUp>>,
+        buttons: Buttons<PinSource, { input::BUTTONS }>,
+        // the `LedMessage::Dim` due once the events stop, see `event_router`
+        dim_handle: Option<led_controller::SpawnHandle>,
+        reported_drops: u32,
+        reported_uart_drops: u32,
+        reported_rtt_drops: u32,
+        // monotonic ticks of the last CPU load sample
+        load_sampled_at: u64,
+        sensors: InternalSensors,
+        // what `init` found of the startup, until the first run of `event_stats`
+        boot_stages: Option<BootStages>,
 
-        key_columns: [ErasedPin<Output>; 4],
-        key_rows: [ErasedPin<Input<PullDown>>; 4],
+        uart_rx: Rx<USART1>,
+        command_reader: CommandReader,
+        console: Console,
+        uart_dma: UartDma,
+        status_display: Display,
+        strip: Strip,
+        joystick_pins: JoystickPins,
+        battery_pin: BatteryPin,
+        flash: flash::Parts,
     }
 
-    #[init]
+    #[init(local = [
+        usb_bus: Option<UsbBusAllocator<UsbBusType>> = None,
+        uart_buffers: uart::Buffers = [[0; uart::BUFFER_SIZE]; 2],
+    ])]
     fn init(mut ctx: init::Context) -> (Shared, Local, init::Monotonics) {
-        rtt_init_print!();
-
-        let systick = ctx.core.SYST;
-        let mono = Systick::new(systick, 16_000_000);
+        // SAFETY: RTIC only set up the NVIC so far, the HAL nothing at all
+        if unsafe { reset::take_bootloader() } {
+            unsafe { bootloader::jump() };
+        }
+        #[cfg(feature = "rtt")]
+        let (rtt_events, console) = {
+            let channels = rtt_init! {
+                up: {
+                    0: {
+                        size: 1024
+                        name: "Terminal"
+                    }
+                    // room for 15 records the host hasn't read yet
+                    1: {
+                        size: 256
+                        name: "Events"
+                    }
+                }
+                down: {
+                    0: {
+                        size: 64
+                        name: "Terminal"
+                    }
+                }
+            };
+            let [log, events] = [channels.up.0, channels.up.1];
+            // a debug build waits for the host rather than losing lines, so the
+            // logger stalls without a probe attached
+            #[cfg(debug_assertions)]
+            let log = {
+                let mut log = log;
+                log.set_mode(rtt_target::ChannelMode::BlockIfFull);
+                log
+            };
+            logging::set_terminal(log);
+            (EventChannel::new(events), Console::new(channels.down.0))
+        };
+        #[cfg(not(feature = "rtt"))]
+        let (rtt_events, console) = (EventChannel::new(), Console::new());
+        #[cfg(feature = "debug-idle")]
+        ctx.device.DBGMCU.cr.modify(|_, w| {
+            w.dbg_sleep().set_bit();
+            w.dbg_stop().set_bit();
+            w.dbg_standby().set_bit()
+        });
 
+        let reset_causes = watchdog::reset_causes(&ctx.device.RCC);
         let rcc = ctx.device.RCC.constrain();
         let mut flash = ctx.device.FLASH.constrain();
-        let clocks = rcc
-            .cfgr
-            .use_hse(8.MHz())
-            .sysclk(16.MHz())
-            .freeze(&mut flash.acr);
-
-        // leds initialization
-        let mut gpio_b = ctx.device.GPIOB.split();
-        let led_red = gpio_b
-            .pb12
-            .into_push_pull_output_with_state(&mut gpio_b.crh, PinState::Low);
-
-        let led_blue = gpio_b
-            .pb14
-            .into_push_pull_output_with_state(&mut gpio_b.crh, PinState::High);
-
-        let led_green = gpio_b
-            .pb13
-            .into_push_pull_output_with_state(&mut gpio_b.crh, PinState::Low);
-
-        // configuring button interrupt
+        let clocks = board::clocks(rcc.cfgr, &mut flash.acr);
+        let mono = Tim4Monotonic::new(ctx.device.TIM4, &clocks);
+        let mut delay = Delay::new(&clocks);
+        let mut stages = BootStages::new();
+        stages.report(boot::CLOCKS, board::clocks_valid(&clocks));
+        // the scanner starts out polling and USB unsuspended, so this stays at full speed
+        let mut clock = ClockManager::new();
+        clock.request_high();
+        clock.request_high();
+        // the I2C slave, the WS2812 timing, the buzzer tones, the PS/2 clock, the
+        // servo pulses and the conversion rate of the velocity sensors are only set
+        // up for it
+        #[cfg(any(
+            feature = "i2c-slave",
+            feature = "backlight",
+            feature = "buzzer",
+            feature = "ps2",
+            feature = "servo",
+            feature = "velocity"
+        ))]
+        clock.request_high();
+
         let mut afio = ctx.device.AFIO.constrain();
-        let mut emergency_button = gpio_b.pb0.into_pull_up_input(&mut gpio_b.crl);
-        emergency_button.make_interrupt_source(&mut afio);
-        emergency_button.enable_interrupt(&mut ctx.device.EXTI);
-        emergency_button.trigger_on_edge(&mut ctx.device.EXTI, Edge::Falling);
+        let board = Board::new(
+            ctx.device.GPIOA,
+            ctx.device.GPIOB,
+            ctx.device.GPIOC,
+            &mut afio,
+            &mut ctx.device.EXTI,
+            &mut delay,
+        );
+        #[cfg(feature = "lock-pins")]
+        let locked_pins = board.gpio_pins();
+
+        let dimmable = StatusLeds::new(ctx.device.TIM2, board.led_red, board.led_blue, &clocks);
+        let mut status_leds = Leds::new(dimmable, board.led_green);
+        // checks the saved settings and every frame on USART1
+        crc::init(ctx.device.CRC);
+        let saved_settings = config::load();
+        let settings = saved_settings.unwrap_or(Settings::DEFAULT);
+        stages.report(boot::SETTINGS, saved_settings.is_some());
+        status_leds.set_brightness(settings.brightness);
+        #[cfg(feature = "breathe")]
+        status_leds.set_breathing(settings.breathing);
+        #[cfg(feature = "ambient")]
+        ambient::start(board.ambient, settings.auto_brightness, settings.curve);
+        #[cfg(feature = "key-debounce")]
+        key_debounce::start(settings.key_debounce);
+        #[cfg(feature = "bind")]
+        bind::start(settings.bindings);
+        #[cfg(feature = "hold-cap")]
+        hold_cap::start(settings.hold_cap_ms, settings.hold_cap_outputs);
+        #[cfg(feature = "relays")]
+        relays::start(board.relays, settings.relays);
+
+        let mut leds = LedController::new();
+        #[cfg(not(feature = "fast-boot"))]
+        leds.enter(LedMode::Boot);
+        leds.set_lock(settings.modifiers.is_locked(modifiers::LOCK1));
 
         // key board initializations
-        let mut gpio_a = ctx.device.GPIOA.split();
+        #[cfg(not(any(feature = "shift-register", feature = "mcp23017")))]
+        let columns = GpioColumns::new(board.columns);
+        #[cfg(feature = "shift-register")]
+        let columns = {
+            let (sck, mosi) = board.shift_register;
+            let spi = Spi::spi1(
+                ctx.device.SPI1,
+                (sck, NoMiso, mosi),
+                &mut afio.mapr,
+                Mode {
+                    polarity: Polarity::IdleLow,
+                    phase: Phase::CaptureOnFirstTransition,
+                },
+                1.MHz(),
+                clocks,
+            );
+            ShiftRegisterColumns::new(spi, board.latch, delay)
+        };
+        #[cfg(not(feature = "mcp23017"))]
+        let rows = GpioRows::new(board.rows);
+        // the columns are inactive since their `new`, the internal pulls only mask
+        // a missing external one partially, so a failed row keeps working
+        #[cfg(feature = "pull-check")]
+        {
+            let verdicts = rows.pull_check();
+            for (row, verdict) in verdicts.iter().enumerate() {
+                log!("row {} pull {}", row, Text(verdict.name()));
+            }
+            if verdicts.iter().any(|verdict| verdict.failed()) {
+                leds.enter(LedMode::Error(ErrorCode::FloatingRow));
+            }
+        }
+        #[cfg(not(feature = "mcp23017"))]
+        let mut keypad = Keypad::new(columns, rows, delay);
+
+        // for `profile`, the blocking I2C drivers also count their timeouts in DWT cycles
+        ctx.core.DCB.enable_trace();
+        ctx.core.DWT.enable_cycle_counter();
+        #[cfg(feature = "itm")]
+        itm::start(
+            &ctx.device.DBGMCU,
+            &mut ctx.core.TPIU,
+            &mut ctx.core.ITM,
+            clocks.sysclk().raw(),
+        );
+
+        // the whole matrix on an I2C expander
+        #[cfg(feature = "mcp23017")]
+        let mut keypad = {
+            let i2c = BlockingI2c::i2c1(
+                ctx.device.I2C1,
+                board.i2c1,
+                &mut afio.mapr,
+                I2cMode::Fast {
+                    frequency: 400.kHz(),
+                    duty_cycle: DutyCycle::Ratio2to1,
+                },
+                clocks,
+                1000,
+                3,
+                1000,
+                1000,
+            );
+            Mcp23017::new(i2c)
+        };
+
+        // a faulty matrix still gets scanned, so the bad key can be found with `DUMP
+        // KEYS`, but nothing is reported to a host
+        let matrix_fault = match keypad.self_test() {
+            Ok(test) => {
+                log!("self-test: {}", KeyState(test.failed()));
+                stages.report(boot::KEYPAD, !test.hard_fault());
+                if test.hard_fault() {
+                    log!(
+                        "matrix self-test failed, shorted rows {:04b}",
+                        test.shorted_rows
+                    );
+                    leds.enter(LedMode::Error(ErrorCode::MatrixFault));
+                }
+                test.hard_fault()
+            }
+            Err(_) => {
+                log!("keypad unreachable, self-test skipped");
+                stages.report(boot::KEYPAD, false);
+                false
+            }
+        };
+
+        let mut pwr = ctx.device.PWR;
+        let backup_domain = rcc.bkp.constrain(ctx.device.BKP, &mut pwr);
+        let last_panic = crash::last_panic(&backup_domain);
+        let reset_causes = reset::identify(&backup_domain, reset_causes);
+        // the jump comes at the start of the next boot, with nothing set up yet. The
+        // shorts of a faulty matrix would ask for it on every boot. Its marker
+        // takes the register of a panic not reported yet, the keys wait for the
+        // boot after.
+        let held = KeyState(keypad.scan().unwrap_or(0));
+        if !matrix_fault
+            && last_panic.is_none()
+            && held & bootloader::POWER_ON_KEYS == bootloader::POWER_ON_KEYS
+        {
+            reset::mark(&backup_domain, Request::Bootloader);
+            SCB::sys_reset();
+        }
+        #[cfg(feature = "key-test")]
+        if !matrix_fault && held & key_test::POWER_ON_KEY == key_test::POWER_ON_KEY {
+            key_test::start();
+            leds.enter(LedMode::KeyTest(key_test::NO_KEY));
+            log!("key test, press every key");
+        }
+        let lifetime = lifetime::restore(&backup_domain);
+        log!(
+            "boot #{}, lifetime keypresses {}",
+            lifetime.boots,
+            lifetime.keypresses
+        );
+        let rtc = Rtc::new(ctx.device.RTC, &backup_domain);
+        match rtc.source() {
+            Some(source) => log!("rtc on {}", Text(source.name())),
+            None => {
+                rtc_start::spawn(0).or_count();
+            }
+        }
+        let sleep = DeepSleep::new(&mut ctx.device.EXTI);
+
+        // keeps EXTI to re-arm its line, after init only `crate::sleep` masks the rows
+        let buttons = Buttons::new([
+            (
+                input::EMERGENCY_BUTTON,
+                PinSource::of(&board.emergency_button),
+            ),
+            (input::ENCODER_SWITCH, PinSource::of(&board.encoder_button)),
+        ]);
+        let emergency_button = EmergencyButton::new(board.emergency_button, ctx.device.EXTI);
+
+        // ADC1 is calibrated while it powers up
+        let adc = Adc::adc1(ctx.device.ADC1, clocks);
+        #[cfg(not(feature = "joystick"))]
+        let joystick_pins = JoystickPins;
+        #[cfg(feature = "joystick")]
+        let joystick_pins = JoystickPins {
+            x: board.joystick.0,
+            y: board.joystick.1,
+        };
+        #[cfg(feature = "joystick")]
+        feature_job::spawn(Job::JoystickSample).or_count();
+        #[cfg(not(feature = "battery"))]
+        let battery_pin = BatteryPin;
+        #[cfg(feature = "battery")]
+        let battery_pin = BatteryPin(board.battery);
+        #[cfg(feature = "battery")]
+        feature_job::spawn(Job::BatteryMonitor).or_count();
+
+        // USB, `Board::new` made the host notice the reset by now
+        let (pin_dm, pin_dp) = board.usb;
+        let usb_bus = ctx.local.usb_bus.insert(UsbBus::new(Peripheral {
+            usb: ctx.device.USB,
+            pin_dm,
+            pin_dp,
+        }));
+        let usb = Usb::new(usb_bus);
+
+        // key event stream on USART1
+        let serial = Serial::new(
+            ctx.device.USART1,
+            board.usart1,
+            &mut afio.mapr,
+            Config::default().baudrate(uart::BAUD_RATE.bps()),
+            &clocks,
+        );
+        let (uart_tx, mut uart_rx) = serial.split();
+        uart_rx.listen();
+        // a saved framing over the build's, see `uart`
+        #[cfg(feature = "uart-config")]
+        uart::start(
+            settings.uart.unwrap_or(uart::BUILD_FRAMING),
+            clocks.pclk2().raw(),
+        );
+        let dma1 = ctx.device.DMA1.split();
+        let uart_dma = UartDma::new(uart_tx.with_dma(dma1.4), ctx.local.uart_buffers);
+
+        // the force sensors on ADC1, converted all along
+        #[cfg(feature = "velocity")]
+        {
+            let buffer =
+                cortex_m::singleton!(: velocity::Buffer = [0; velocity::BUFFER_LEN]).unwrap();
+            velocity::start(board.velocity, dma1.1, buffer);
+        }
+
+        // WS2812 chain on SPI2 MOSI, the clock and MISO pins stay free for the leds
+        #[cfg(not(feature = "backlight"))]
+        let strip = Strip;
+        #[cfg(feature = "backlight")]
+        let strip = {
+            let spi = Spi::spi2(
+                ctx.device.SPI2,
+                (NoSck, NoMiso, board.backlight),
+                Mode {
+                    polarity: Polarity::IdleLow,
+                    phase: Phase::CaptureOnFirstTransition,
+                },
+                3.MHz(),
+                clocks,
+            );
+            let buffer =
+                cortex_m::singleton!(: backlight::Encoded = [0; backlight::ENCODED_SIZE]).unwrap();
+            Strip::new(spi.with_tx_dma(dma1.5), buffer)
+        };
+        #[cfg(feature = "backlight")]
+        feature_job::spawn(Job::LedFrame).or_count();
+
+        // piezo on TIM3 channel 4, the frequency is changed for every tone
+        #[cfg(not(feature = "buzzer"))]
+        let buzzer = Buzzer;
+        #[cfg(feature = "buzzer")]
+        let buzzer = Buzzer::new(ctx.device.TIM3.pwm_hz::<Tim3NoRemap, _, _>(
+            board.buzzer,
+            &mut afio.mapr,
+            4.kHz(),
+            &clocks,
+        ));
+
+        #[cfg(feature = "servo")]
+        servo::init(ctx.device.TIM3, board.servo, &clocks);
+
+        #[cfg(not(feature = "display"))]
+        let status_display = StatusDisplay;
+        #[cfg(feature = "display")]
+        let mut status_display = StatusDisplay::new(BlockingI2c::i2c2(
+            ctx.device.I2C2,
+            board.i2c2,
+            I2cMode::Fast {
+                frequency: 400.kHz(),
+                duty_cycle: DutyCycle::Ratio2to1,
+            },
+            clocks,
+            1000,
+            3,
+            1000,
+            1000,
+        ));
+        // without a display there is nothing to update, everything else runs as usual
+        #[cfg(feature = "display")]
+        match status_display.init() {
+            Ok(()) => {
+                stages.report(boot::DISPLAY, true);
+                feature_job::spawn(Job::DisplayUpdate).or_count();
+            }
+            Err(_) => {
+                stages.report(boot::DISPLAY, false);
+                log!("no display found");
+            }
+        }
+
+        // keypad controller for another MCU, PB1 signals pending events
+        #[cfg(feature = "i2c-slave")]
+        i2c_slave::init(ctx.device.I2C1, board.i2c1, board.data_ready, &clocks);
+
+        // a keyboard for PS/2 hosts
+        #[cfg(feature = "ps2")]
+        ps2::init(ctx.device.TIM1, board.ps2, &clocks);
 
-        let columns = [
-            gpio_a.pa0.into_push_pull_output(&mut gpio_a.crl).erase(),
-            gpio_a.pa1.into_push_pull_output(&mut gpio_a.crl).erase(),
-            gpio_a.pa2.into_push_pull_output(&mut gpio_a.crl).erase(),
-            gpio_a.pa3.into_push_pull_output(&mut gpio_a.crl).erase(),
-        ];
+        // code entry on a character LCD
+        #[cfg(not(feature = "lcd"))]
+        let lcd = Lcd;
+        #[cfg(feature = "lcd")]
+        let lcd = {
+            let (rs, e, data) = board.lcd;
+            Lcd::new(rs, e, data)
+        };
+        #[cfg(feature = "lcd")]
+        feature_job::spawn(Job::LcdInit(0)).or_count();
 
-        let rows = [
-            gpio_a.pa4.into_pull_down_input(&mut gpio_a.crl).erase(),
-            gpio_a.pa5.into_pull_down_input(&mut gpio_a.crl).erase(),
-            gpio_a.pa6.into_pull_down_input(&mut gpio_a.crl).erase(),
-            gpio_a.pa7.into_pull_down_input(&mut gpio_a.crl).erase(),
-        ];
+        log!("init");
+        log!("reset cause: {}", Text(&reset_causes.text()));
+        log!("firmware {}", Text(identity::FIRMWARE));
+        log!("keymap {}", Text(LAYOUT));
+        if saved_settings.is_none() {
+            log!("no saved settings, using the defaults");
+        }
+        log!("uid {}", identity::uid());
+        if reset_causes.contains(ResetCause::IndependentWatchdog) {
+            log!("reset by the watchdog, a task stalled");
+        }
+        log!("System clock: {}", clocks.sysclk());
+        // line 0 where the previous build didn't read the location, the
+        // addresses are for `addr2line`
+        if let Some(panic) = last_panic {
+            log!(
+                "the previous run panicked on line {}, called from",
+                panic.line
+            );
+            for address in panic.backtrace.iter().take_while(|&&address| address != 0) {
+                log!("  {:08x}", address);
+            }
+            crash::clear_panic(&backup_domain);
+        }
+        if let Some(fault) = crash::take_fault() {
+            log!(
+                "the previous run hard faulted at pc {:08x} lr {:08x}",
+                fault.pc,
+                fault.lr
+            );
+            log!(
+                "xpsr {:08x} cfsr {:08x} hfsr {:08x}",
+                fault.xpsr,
+                fault.cfsr,
+                fault.hfsr
+            );
+            log!("bfar {:08x} mmfar {:08x}", fault.bfar, fault.mmfar);
+        }
 
-        // let delay = &systick.delay(&clocks);
+        // the heartbeat starts under the boot animation, and the controller
+        // schedules the steps from there. The blink chain runs under it already, the
+        // leds light up with the stages the first run of `event_stats` sends.
+        send_led_message(LedMessage::Enter(LedMode::Normal));
+        // the events that come hold it off, see `restart_dim_timer`
+        let dim_handle = led_controller::spawn_after(timing::DIM_AFTER, LedMessage::Dim(true)).ok();
+        event_stats::spawn().or_count();
+        #[cfg(feature = "rtt")]
+        console::spawn().or_count();
 
-        rprintln!("init");
-        rprintln!("System closk: {}", clocks.sysclk());
+        // started last, the setup above doesn't feed it
+        let mut iwdg = IndependentWatchdog::new(ctx.device.IWDG);
+        iwdg.stop_on_debug(&ctx.device.DBGMCU, true);
+        if stages.halted() {
+            log!("a critical stage failed, startup halted");
+        } else {
+            blinker::spawn(BlinkPhase::Count, monotonics::now()).or_count();
+            key_listener::spawn(monotonics::now()).or_count();
+            iwdg.start(watchdog::TIMEOUT_MS.millis());
+            watchdog_feed::spawn().or_count();
+        }
+        let wwdg = WindowWatchdog::start(ctx.device.WWDG, &ctx.device.DBGMCU);
+        wwdg_refresh::spawn_after(timing::WINDOW_POLL).or_count();
 
-        foo::spawn().unwrap();
-        key_listener::spawn().unwrap();
+        // every pin is in its mode for good by now
+        #[cfg(feature = "lock-pins")]
+        for (port, pins) in (0..).zip(locked_pins) {
+            if pins != 0 {
+                let port_name = char::from(b'A' + port);
+                if board::lock_pins(port, pins) {
+                    log!("port {} pins {:016b} locked", Char(port_name), pins);
+                } else {
+                    log!("port {} pins {:016b} failed to lock", Char(port_name), pins);
+                }
+            }
+        }
 
         return (
             Shared {
-                led_red: led_red.erase(),
-                led_blue: led_blue.erase(),
+                status_leds,
+                counter: lifetime.blinks,
+                backup_domain,
+                rtc,
+                blink_handle: None,
+                scan_period_ms: settings.scan_period_ms,
+                scans: 0,
+                event_producer: EventProducer::new(),
+                stuck_keys: KeyState(0),
+                debounce: settings.debounce,
+                layers: {
+                    let mut layers = Layers::new(settings.keymaps);
+                    layers.set_base(usize::from(settings.keymap));
+                    layers
+                },
+                modifiers: settings.modifiers,
+                scan_paused: false,
+                matrix_fault,
+                reset_causes,
+                injected: Injected::new(),
+                scan_lateness: Lateness::new(),
+                usb,
+                display_model: DisplayModel::new(),
+                lcd,
+                backlight: Backlight::new(),
+                buzzer,
+                beep_handle: None,
+                piano: Piano::new(),
+                entry: Entry::new(),
+                pin_lock: PinLock::new(settings.pin),
+                macros: Macros::new(),
+                output: settings.output,
+                adc,
+                joystick: Joystick::new(),
+                diagnostics: Diagnostics::default(),
+                battery: Battery::new(),
+                emergency: false,
+                alive: 0,
+                scan_seq: 0,
+                drained_at: 0,
+                scan_mode: ScanMode::Polling,
+                stop_handle: None,
+                wake: WakeMailbox::new(),
+                clock,
+                usb_holds_clock: true,
+                wwdg,
+                delay,
+                rtt_events,
+                recent_events: HistoryBuffer::new(),
+                key_history: History::new(),
+                key_stats: KeyStats::new(),
+                last_emergency: None,
+                idle_us: 0,
+                cpu_load_percent: 0,
+                emergency_button,
             },
             Local {
-                counter: 0,
-                emergency_button: emergency_button.erase(),
-                led_green: led_green.erase(),
-                key_columns: columns,
-                key_rows: rows,
+                leds,
+                engine: EventEngine::new(keypad, settings.debounce, keypad::STUCK_KEY_MS, &CHORDS)
+                    .unwrap_or_else(|_| unreachable!())
+                    .with_repeat(gesture::REPEAT_DELAY_MS, gesture::REPEAT_INTERVAL_MS),
+                parked: false,
+                scan_failure: None,
+                scan_timing: ScanTiming::new(),
+                timing_reported_at: 0,
+                sliced: SlicedScan::new(),
+                sequences: Sequences::new(&SEQUENCES),
+                encoder_a: board.encoder_a,
+                encoder_b: board.encoder_b,
+                quadrature: Quadrature::new(),
+                encoder_button: board.encoder_button,
+                buttons,
+                dim_handle,
+                reported_drops: 0,
+                reported_uart_drops: 0,
+                reported_rtt_drops: 0,
+                load_sampled_at: 0,
+                sensors: InternalSensors::new(),
+                boot_stages: Some(stages),
+                uart_rx,
+                command_reader: CommandReader::new(),
+                console,
+                uart_dma,
+                status_display,
+                strip,
+                joystick_pins,
+                battery_pin,
+                flash,
+                iwdg,
+                checked_at: 0,
+                sleep,
+                idle_since: 0,
+                empty_scans: 0,
             },
             init::Monotonics(mono),
         );
     }
 
-    #[idle()]
-    fn idle(_ctx: idle::Context) -> ! {
-        loop {
-            rtic::export::nop();
+    // stops feeding for good once the scanner didn't run since the last feed, the
+    // event queue went undrained for `watchdog::DRAIN_MS` or a supervised task
+    // missed a check. The IWDG resets the chip `watchdog::TIMEOUT_MS` later.
+    #[task(
+        priority = 1,
+        local=[iwdg, checked_at, fed_seq: u32 = 0],
+        shared=[alive, scan_seq, drained_at, event_producer, emergency, scan_mode]
+    )]
+    fn watchdog_feed(ctx: watchdog_feed::Context) {
+        watchdog::watchdog_feed(ctx);
+    }
+
+    // above everything but the emergency stop, a poll may be ~9 ms late
+    #[task(priority = 3, shared=[wwdg])]
+    fn wwdg_refresh(mut ctx: wwdg_refresh::Context) {
+        ctx.shared.wwdg.lock(|wwdg| wwdg.refresh());
+        wwdg_refresh::spawn_after(timing::WINDOW_POLL).or_count();
+    }
+
+    // one counter tick, ~0.9 ms, is left until the reset: the log sinks only queue
+    // the lines, RTT is the one likely to get them out in time
+    #[task(binds=WWDG, priority = 7, shared=[wwdg, recent_events])]
+    fn wwdg_early_wakeup(ctx: wwdg_early_wakeup::Context) {
+        watchdog::wwdg_early_wakeup(ctx);
+    }
+
+    #[idle(local=[sleep], shared=[scan_mode, stop_handle, clock, emergency, idle_us, rtc])]
+    fn idle(ctx: idle::Context) -> ! {
+        sleep::idle(ctx);
+    }
+
+    // both phases of the blink, each run scheduled from the deadline of the one
+    // before so the period doesn't drift with their latency
+    #[task(
+        shared=[counter, diagnostics, emergency, alive, blink_handle],
+        priority = 3
+    )]
+    fn blinker(ctx: blinker::Context, phase: BlinkPhase, deadline: monotonic::Instant) {
+        blink::blinker(ctx, phase, deadline);
+    }
+
+    // the only task driving the leds, see `crate::led_mode`. A `Step` is pending for
+    // the end of the step shown, a mode that takes over is shown at once and gets
+    // its own for an earlier end. The later one finds its step not over yet. The
+    // dim timer of `event_router` holds a slot as well.
+    #[task(
+        priority = 3,
+        capacity = 4,
+        local=[
+            leds,
+            // the end of the step the earliest `Step` pending is for
+            step: Option<u32> = None,
+            #[cfg(feature = "morse")]
+            morse: Morse = Morse::new(),
+        ],
+        shared=[status_leds]
+    )]
+    fn led_controller(ctx: led_controller::Context, message: LedMessage) {
+        led_mode::led_controller(ctx, message);
+    }
+
+    pub(crate) fn send_led_message(message: LedMessage) {
+        led_controller::spawn(message).or_count();
+    }
+
+    // the led blinks the error until `clear_error`, the log names it the same way
+    pub(crate) fn raise_error(error: ErrorCode) {
+        log!("{}", error);
+        send_led_message(LedMessage::Enter(LedMode::Error(error)));
+    }
+
+    pub(crate) fn clear_error(error: ErrorCode) {
+        log!("{} cleared", error);
+        send_led_message(LedMessage::Leave(LedMode::Error(error)));
+    }
+
+    pub(crate) fn now_ms() -> u32 {
+        monotonic::to_ms(monotonics::now().ticks()) as u32
+    }
+
+    // monotonic ticks since boot, which don't wrap for 500000 years. `now` reads
+    // the counter and the overflows in one critical section, see
+    // `keypad_core::time::WideCounter` for a read across the wrap of the counter.
+    pub(crate) fn now_u64() -> u64 {
+        monotonics::now().ticks()
+    }
+
+    #[task(
+        priority=2,
+        local=[
+            engine,
+            parked,
+            idle_since,
+            empty_scans,
+            scan_failure,
+            unreachable: bool = false,
+            presence_checked_at: u32 = 0,
+            // the failed keys of a new fault, until the next frame reports it
+            fault_pending: Option<u32> = None,
+            scan_timing,
+            timing_reported_at,
+            sliced,
+            press_limit: PressLimit = PressLimit::new(MAX_SIMULTANEOUS),
+            config_keys: ConfigKeys = ConfigKeys::new(),
+            sequences,
+            encoder_button,
+            buttons
+        ],
+        shared=[
+            scan_period_ms,
+            debounce,
+            layers,
+            modifiers,
+            scans,
+            event_producer,
+            stuck_keys,
+            injected,
+            scan_lateness,
+            emergency,
+            scan_paused,
+            scan_seq,
+            scan_mode,
+            stop_handle,
+            clock,
+            wake,
+            matrix_fault
+        ]
+    )]
+    fn key_listener(ctx: key_listener::Context, deadline: monotonic::Instant) {
+        scanner::key_listener(ctx, deadline);
+    }
+
+    // any wake line or a wake up from STOP, the scan that follows reports the key
+    pub(crate) fn wake_scanner(
+        mode: &mut ScanMode,
+        handle: &mut Option<stop_timer::SpawnHandle>,
+        clock: &mut ClockManager,
+    ) {
+        if *mode == ScanMode::Polling {
+            return;
+        }
+        *mode = ScanMode::Polling;
+        clock.request_high();
+        sleep::unlisten();
+        if let Some(handle) = handle.take() {
+            // fails once it ran, it found the scanner stopped already
+            let _ = handle.cancel();
         }
+        key_listener::spawn(monotonics::now()).or_count();
+    }
+
+    #[task(shared=[scan_mode, emergency], priority = 1)]
+    fn stop_timer(ctx: stop_timer::Context) {
+        sleep::stop_timer(ctx);
+    }
+
+    // the first row line to fire posts its row, `wake_scanner` masks the lines
+    // right after, so the bounces and the other keys of the handover don't
+    // interrupt again
+    fn post_wake(wake: &mut WakeMailbox) {
+        if let Some(row) = sleep::pending_row() {
+            wake.post(row, now_u64());
+        }
+    }
+
+    #[task(binds=EXTI4, shared=[scan_mode, stop_handle, clock, wake], priority = 1)]
+    fn row_wake(mut ctx: row_wake::Context) {
+        // an edge of the key `BOUNCE` captures, the scanner is polling
+        #[cfg(feature = "debug-bounce")]
+        if bounce::stamp() {
+            return;
+        }
+        ctx.shared.wake.lock(post_wake);
+        let mut shared = (
+            ctx.shared.scan_mode,
+            ctx.shared.stop_handle,
+            ctx.shared.clock,
+        );
+        shared.lock(wake_scanner);
     }
 
-    #[task(shared=[led_red, led_blue], local=[counter], priority = 3)]
-    fn foo(mut ctx: foo::Context) {
-        rprintln!("foo");
+    #[task(binds=EXTI9_5, shared=[scan_mode, stop_handle, clock, wake], priority = 1)]
+    fn rows_wake(mut ctx: rows_wake::Context) {
+        // an edge of the key `BOUNCE` captures, the scanner is polling
+        #[cfg(feature = "debug-bounce")]
+        if bounce::stamp() {
+            return;
+        }
+        ctx.shared.wake.lock(post_wake);
+        let mut shared = (
+            ctx.shared.scan_mode,
+            ctx.shared.stop_handle,
+            ctx.shared.clock,
+        );
+        shared.lock(wake_scanner);
+    }
 
-        ctx.shared.led_red.lock(|led| led.toggle());
-        ctx.shared.led_blue.lock(|led| led.toggle());
+    // polls the LSE after a power loss, see `crate::rtc`
+    #[task(shared=[rtc], priority = 1)]
+    fn rtc_start(ctx: rtc_start::Context, waited_ms: u32) {
+        rtc::rtc_start(ctx, waited_ms);
+    }
 
-        *ctx.local.counter += 1;
+    // bound so the alarm can wake the core, `DeepSleep::stop` handles it there.
+    // An alarm that lands right after the wake up ends up here.
+    #[task(binds=RTCALARM, priority = 1)]
+    fn rtc_alarm(_ctx: rtc_alarm::Context) {
+        sleep::clear_alarm_line();
+    }
+    // the host would see the keys held as held for good once the keyboard goes
+    // quiet
+    pub(crate) fn switch_output(output: &mut OutputMode, usb: &mut Usb, mode: OutputMode) {
+        if output.is_usb() && mode != *output {
+            usb.release_all();
+        }
+        *output = mode;
+        log!("output {:?}", mode);
+        send_led_message(LedMessage::Flash(mode.flash()));
+    }
 
-        bar::spawn_after(ExtU32::secs(1).into(), *ctx.local.counter).unwrap();
+    // only fed while the entry mode is on, a confirmed number or the right PIN
+    // goes back into the event queue
+    #[task(priority=1, capacity=4, shared=[entry, pin_lock, event_producer])]
+    fn entry_mode(ctx: entry_mode::Context, event: KeyEvent) {
+        entry::entry_mode(ctx, event);
     }
 
-    #[task(shared=[led_red, led_blue], priority = 3)]
-    fn bar(mut ctx: bar::Context, counter: u32) {
-        rprintln!("bar, number of led_red blink: {}", counter);
+    // the keys of the config mode, `key_listener` diverted them from the other
+    // events. A change takes effect right away, 'D' saves it like `SAVE`.
+    #[task(
+        priority=1,
+        capacity=4,
+        local=[config_mode: ConfigMode = ConfigMode::new()],
+        shared=[scan_period_ms, debounce, status_leds, output, usb]
+    )]
+    fn configure(ctx: configure::Context, input: ConfigInput) {
+        #[cfg(feature = "config-mode")]
+        config_mode::configure(ctx, input);
+        #[cfg(not(feature = "config-mode"))]
+        let _ = (ctx, input);
+    }
 
-        ctx.shared.led_red.lock(|led| led.toggle());
-        ctx.shared.led_blue.lock(|led| led.toggle());
+    // queues the steps of a macro due now and comes back for the next one, the
+    // generation drops the runs of a playback cancelled meanwhile
+    #[task(priority=1, capacity=2, shared=[macros, modifiers, event_producer])]
+    fn macro_play(ctx: macro_play::Context, generation: u32) {
+        #[cfg(feature = "macros")]
+        macros::macro_play(ctx, generation);
+        #[cfg(not(feature = "macros"))]
+        let _ = (ctx, generation);
+    }
 
-        foo::spawn_after(ExtU32::secs(1).into()).unwrap();
+    // the single receiving point of the events, its message queue is the event
+    // queue. The capacity is `event::EVENT_CAPACITY`, the producers keep to it and
+    // their drops to the policy of `EventProducer`.
+    #[task(
+        priority=1,
+        capacity=15,
+        local=[dim_handle],
+        shared=[
+            usb,
+            display_model,
+            backlight,
+            piano,
+            entry,
+            pin_lock,
+            macros,
+            output,
+            buzzer,
+            recent_events,
+            key_history,
+            key_stats,
+            rtt_events,
+            matrix_fault,
+            lcd,
+            drained_at,
+            event_producer
+        ]
+    )]
+    fn event_router(ctx: event_router::Context, event: InputEvent) {
+        router::event_router(ctx, event);
     }
 
-    #[task(priority=1, local=[key_columns, key_rows])]
-    fn key_listener(ctx: key_listener::Context) {
-        loop {
-            for i in 0..ctx.local.key_columns.len() {
-                ctx.local.key_columns[i].set_high();
-                for j in 0..ctx.local.key_rows.len() {
-                    if ctx.local.key_rows[j].is_high() {
-                        rprintln!("column: {}, row: {}", i, j);
-                    }
+    pub(crate) fn play(buzzer: &mut Buzzer, note: Note) {
+        match note {
+            Note::Play(freq_hz) => {
+                buzzer.start(freq_hz);
+            }
+            Note::Silence => buzzer.silence(),
+        }
+    }
+
+    #[cfg(feature = "buzzer")]
+    pub(crate) fn beep((freq_hz, duration_ms): (u32, u32)) {
+        if beeper::spawn(Tone::Start(freq_hz, duration_ms)).is_err() {
+            log!("tone dropped");
+        }
+    }
+
+    // a new tone replaces the current one, see `crate::buzzer`. Its stop is a run
+    // of the same task, one stale stop may still be pending after a tone was
+    // replaced and the next tone be requested.
+    #[task(priority=2, capacity=3, shared=[buzzer, beep_handle])]
+    fn beeper(ctx: beeper::Context, tone: Tone) {
+        buzzer::beeper(ctx, tone);
+    }
+
+    // the jobs of the optional features share a task. RTIC 1 can't leave a
+    // software task out with its feature, each one would take its dispatch and its
+    // share of the timer queue in every build. There is a slot for every job, a
+    // periodic one only ever has the next run pending.
+    #[task(
+        priority = 1,
+        capacity = 8,
+        local = [status_display, strip, joystick_pins, battery_pin],
+        shared = [display_model, lcd, delay, adc, joystick, event_producer, diagnostics, battery, backlight]
+    )]
+    fn feature_job(ctx: feature_job::Context, job: Job) {
+        job::feature_job(ctx, job);
+    }
+
+    #[task(
+        priority=1,
+        local=[
+            reported_drops,
+            reported_uart_drops,
+            reported_rtt_drops,
+            overflowing: bool = false,
+            // the periods since the last save of the lifetime counters
+            since_save: u32 = 0,
+            #[cfg(feature = "stack-watch")]
+            stack_low: bool = false,
+            sensors,
+            load_sampled_at,
+            boot_stages
+        ],
+        shared=[
+            adc,
+            diagnostics,
+            idle_us,
+            cpu_load_percent,
+            display_model,
+            rtt_events,
+            status_leds,
+            backup_domain,
+            counter,
+            rtc,
+            pin_lock,
+            scans
+        ]
+    )]
+    fn event_stats(ctx: event_stats::Context) {
+        health::event_stats(ctx);
+    }
+
+    #[task(binds=USB_LP_CAN_RX0, shared=[usb, clock, usb_holds_clock], priority = 4)]
+    fn usb_low_priority(ctx: usb_low_priority::Context) {
+        let mut shared = (ctx.shared.usb, ctx.shared.clock, ctx.shared.usb_holds_clock);
+        shared.lock(poll_usb);
+    }
+
+    #[task(binds=USB_HP_CAN_TX, shared=[usb, clock, usb_holds_clock], priority = 4)]
+    fn usb_high_priority(ctx: usb_high_priority::Context) {
+        let mut shared = (ctx.shared.usb, ctx.shared.clock, ctx.shared.usb_holds_clock);
+        shared.lock(poll_usb);
+    }
+
+    // the peripheral needs the PLL while it talks to the host, a suspended one
+    // only raises an interrupt again on the resume signalling
+    fn poll_usb(usb: &mut Usb, clock: &mut ClockManager, holding: &mut bool) {
+        if !*holding {
+            clock.request_high();
+            *holding = true;
+        }
+        usb.poll();
+        if usb.is_suspended() {
+            clock.release_high();
+            *holding = false;
+        }
+    }
+
+    #[task(binds=USART1, local=[uart_rx, command_reader], priority = 2)]
+    fn uart_interrupt(ctx: uart_interrupt::Context) {
+        // errors are cleared by the read, the broken byte is simply lost
+        if let Ok(byte) = ctx.local.uart_rx.read() {
+            // whatever a MIDI device sends back is no command
+            if cfg!(feature = "midi") {
+                return;
+            }
+            if let Some(command) = ctx.local.command_reader.feed(byte) {
+                if run_command::spawn(command, ReplyTo::Uart).is_err() {
+                    log!("command dropped, still busy");
                 }
-                ctx.local.key_columns[i].set_low();
             }
         }
     }
 
-    #[task(binds=EXTI0, local=[led_green, emergency_button], shared = [led_red, led_blue], priority = 6)]
-    fn emergency_stop(mut ctx: emergency_stop::Context) {
-        ctx.local.led_green.toggle();
-        rprintln!("Emergency STOP!");
+    #[task(local=[console], priority = 1)]
+    fn console(ctx: console::Context) {
+        // only spawned with the `rtt` feature, the console is empty otherwise
+        ctx.local.console.poll(|command| {
+            #[cfg(feature = "rtt")]
+            if run_command::spawn(command, ReplyTo::Rtt).is_err() {
+                log!("command dropped, still busy");
+            }
+            #[cfg(not(feature = "rtt"))]
+            let _ = command;
+        });
+        console::spawn_after(timing::CONSOLE_PERIOD).or_count();
+    }
+
+    #[task(priority = 1)]
+    fn logger(_ctx: logger::Context) {
+        logging::flush();
+    }
+
+    #[task(binds=DMA1_CHANNEL4, local=[uart_dma], priority = 2)]
+    fn uart_transmit(ctx: uart_transmit::Context) {
+        ctx.local.uart_dma.on_interrupt();
+    }
+
+    #[task(
+        priority=1,
+        capacity=2,
+        shared=[
+            status_leds,
+            counter,
+            scan_period_ms,
+            debounce,
+            layers,
+            scans,
+            piano,
+            entry,
+            pin_lock,
+            macros,
+            output,
+            usb,
+            buzzer,
+            joystick,
+            diagnostics,
+            last_emergency,
+            cpu_load_percent,
+            scan_lateness,
+            stuck_keys,
+            key_history,
+            key_stats,
+            reset_causes,
+            injected,
+            scan_mode,
+            stop_handle,
+            clock,
+            rtc
+        ]
+    )]
+    fn run_command(
+        ctx: run_command::Context,
+        command: Result<Command, CommandError>,
+        reply_to: ReplyTo,
+    ) {
+        execute::run_command(ctx, command, reply_to);
+    }
+
+    // the erase stalls the core for longer than the window watchdog allows between
+    // two refreshes, so it waits for the window and refreshes right before. Locking
+    // the watchdog masks everything else until the page is written.
+    #[task(
+        priority = 1,
+        local = [flash, save_failed: bool = false],
+        shared = [
+            wwdg,
+            scan_period_ms,
+            debounce,
+            layers,
+            modifiers,
+            pin_lock,
+            output,
+            status_leds
+        ]
+    )]
+    // without `reply_to` for the config mode, the leds flash green or red instead
+    fn save_settings(ctx: save_settings::Context, reply_to: Option<ReplyTo>) {
+        config::save_settings(ctx, reply_to);
+    }
+
+    // the first run gets a requested reset going, the second one resets once the
+    // logs had `reset::DRAIN_MS` to get out
+    #[task(priority = 1, shared = [backup_domain, counter])]
+    fn user_reset(ctx: user_reset::Context, request: Request, drained: bool) {
+        reset::user_reset(ctx, request, drained);
+    }
+
+    // one `history` log line per event, oldest first
+    pub(crate) fn log_history(history: &History) {
+        for event in history.iter() {
+            log!("history {}", event);
+        }
+    }
+
+    // every reply but the status, the type of the log lines since every one
+    // formatted into takes its own flash
+    pub(crate) type Reply = logging::Line;
+
+    pub(crate) fn send_reply(reply_to: ReplyTo, reply: &str) {
+        match reply_to {
+            #[cfg(feature = "text")]
+            ReplyTo::Uart => uart::write_line(reply),
+            #[cfg(not(feature = "text"))]
+            ReplyTo::Uart => wire::send(&Message::Reply(reply)),
+            #[cfg(feature = "rtt")]
+            ReplyTo::Rtt => logging::write_terminal(reply),
+        }
+    }
+
+    #[cfg(feature = "i2c-slave")]
+    #[task(binds=I2C1_EV, priority = 5)]
+    fn i2c_event(_ctx: i2c_event::Context) {
+        i2c_slave::on_event();
+    }
+
+    #[cfg(feature = "i2c-slave")]
+    #[task(binds=I2C1_ER, priority = 5)]
+    fn i2c_error(_ctx: i2c_error::Context) {
+        i2c_slave::on_error();
+    }
 
-        ctx.shared.led_blue.lock(|led| led.set_low());
-        ctx.shared.led_red.lock(|led| led.set_low());
+    // the bit phases of the PS/2 bus, see `crate::ps2`
+    #[cfg(feature = "ps2")]
+    #[task(binds=TIM1_UP, priority = 5)]
+    fn ps2_tick(_ctx: ps2_tick::Context) {
+        ps2::on_tick();
+    }
+
+    // a half of the velocity sensors' buffer is in, see `crate::velocity`
+    #[cfg(feature = "velocity")]
+    #[task(binds=DMA1_CHANNEL1, priority = 2)]
+    fn velocity_sample(_ctx: velocity_sample::Context) {
+        velocity::on_transfer();
+    }
 
-        ctx.local.emergency_button.clear_interrupt_pending_bit();
-        loop {
-            rtic::export::nop();
+    // software PWM of the status leds, above the tasks so the jitter stays small
+    #[task(binds=TIM2, shared=[status_leds], priority = 5)]
+    fn status_pwm(mut ctx: status_pwm::Context) {
+        ctx.shared.status_leds.lock(|leds| leds.on_interrupt());
+    }
+
+    // any edge of either encoder phase, the state machine sorts out the bounce.
+    // Rev B has the emergency button on EXTI15_10 as well, so the line is taken at
+    // the priority of the button and a step goes on to `encoder_step`.
+    #[task(
+        binds=EXTI15_10,
+        local=[encoder_a, encoder_b, quadrature],
+        shared=[emergency_button],
+        priority = 6
+    )]
+    fn encoder_turn(ctx: encoder_turn::Context) {
+        #[cfg(feature = "board-rev-b")]
+        {
+            let mut button = ctx.shared.emergency_button;
+            if button.lock(|button| button.pending()) {
+                emergency::edge(&mut button);
+            }
         }
+        let local = ctx.local;
+        local.encoder_a.clear_interrupt_pending_bit();
+        local.encoder_b.clear_interrupt_pending_bit();
+        if let Some(step) = local
+            .quadrature
+            .update(local.encoder_a.is_high(), local.encoder_b.is_high())
+        {
+            encoder_step::spawn(step).or_count();
+        }
+    }
+
+    // a step of `encoder_turn` into the queue, at the priority of the scanner
+    #[task(priority = 2, capacity = 4, shared=[event_producer])]
+    fn encoder_step(mut ctx: encoder_step::Context, step: EncoderEvent) {
+        ctx.shared
+            .event_producer
+            .lock(|producer| producer.push(step));
+    }
+
+    // only starts the debounce, see `crate::emergency`. Never fires on rev B,
+    // see `encoder_turn`.
+    #[task(binds=EXTI0, shared=[emergency_button], priority = 6)]
+    fn emergency_stop(mut ctx: emergency_stop::Context) {
+        emergency::edge(&mut ctx.shared.emergency_button);
+    }
+
+    // a press shorter than `timing::EMERGENCY_LATCH` pauses or resumes the scanning,
+    // `emergency_latch` takes a longer one. While latched, holding the button again
+    // for `timing::EMERGENCY_HOLD` clears the stop.
+    #[task(
+        priority = 6,
+        local=[
+            pressed_at: u32 = 0,
+            latch_handle: Option<emergency_latch::SpawnHandle> = None
+        ],
+        shared=[emergency, emergency_button, scan_paused, scan_mode, stop_handle, clock]
+    )]
+    fn button_check(ctx: button_check::Context) {
+        emergency::button_check(ctx);
+    }
+
+    // the button held for `timing::EMERGENCY_LATCH`, the stop doesn't wait for the release
+    #[task(
+        priority = 6,
+        shared=[
+            emergency,
+            usb,
+            scan_paused,
+            last_emergency,
+            key_history,
+            blink_handle,
+            scan_mode,
+            stop_handle,
+            clock,
+            backup_domain,
+            counter
+        ]
+    )]
+    fn emergency_latch(ctx: emergency_latch::Context) {
+        emergency::emergency_latch(ctx);
+    }
+
+    // clears the latch if the press that scheduled it is still held
+    #[task(priority = 3, capacity = 2, shared=[emergency, emergency_button, blink_handle])]
+    fn emergency_release(ctx: emergency_release::Context, presses: u32) {
+        emergency::emergency_release(ctx, presses);
     }
 }
@@ -0,0 +1,123 @@
+//! Key matrix behind an MCP23017 I2C GPIO expander: GPA0-GPA3 drive the columns,
+//! GPB0-GPB3 read the rows. The expander only has pull-ups, so the matrix is
+//! scanned active low: the selected column is driven low and a pressed key pulls
+//! its row low.
+
+use crate::keypad::{Matrix, COLUMNS, ROWS};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use keypad_core::matrix::{MatrixScanner, SelfTest};
+
+// A2-A0 tied to ground
+pub const ADDRESS: u8 = 0x20;
+
+// register addresses with IOCON.BANK = 0, the reset default
+const IODIRA: u8 = 0x00;
+const IODIRB: u8 = 0x01;
+const GPPUB: u8 = 0x0d;
+const GPIOB: u8 = 0x13;
+const OLATA: u8 = 0x14;
+
+const COLUMN_MASK: u8 = (1 << COLUMNS) - 1;
+const ROW_MASK: u8 = (1 << ROWS) - 1;
+
+pub struct Mcp23017<I2C> {
+    i2c: I2C,
+    // cleared after any bus error, the expander may have lost power in the meantime
+    configured: bool,
+}
+
+impl<I2C, E> Mcp23017<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// The expander is configured on the first scan, so it may be plugged in later.
+    pub fn new(i2c: I2C) -> Self {
+        Self {
+            i2c,
+            configured: false,
+        }
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(ADDRESS, &[register, value])
+    }
+
+    fn configure(&mut self) -> Result<(), E> {
+        self.write_register(OLATA, COLUMN_MASK)?;
+        self.write_register(IODIRA, !COLUMN_MASK)?;
+        self.write_register(IODIRB, 0xff)?;
+        self.write_register(GPPUB, ROW_MASK)
+    }
+
+    fn try_scan(&mut self) -> Result<u32, E> {
+        if !self.configured {
+            self.configure()?;
+            self.configured = true;
+        }
+        let mut state = 0;
+        for col in 0..COLUMNS {
+            self.write_register(OLATA, COLUMN_MASK & !(1 << col))?;
+            let mut rows = [0];
+            self.i2c.write_read(ADDRESS, &[GPIOB], &mut rows)?;
+            let pressed = !rows[0] & ROW_MASK;
+            for row in 0..ROWS {
+                if pressed & (1 << row) != 0 {
+                    state |= 1 << (row * COLUMNS + col);
+                }
+            }
+        }
+        self.write_register(OLATA, COLUMN_MASK)?;
+        Ok(state)
+    }
+}
+
+impl<I2C, E> Matrix<COLUMNS, ROWS> for Mcp23017<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = E;
+
+    fn scan(&mut self) -> Result<u32, E> {
+        let state = self.try_scan();
+        self.configured &= state.is_ok();
+        state
+    }
+
+    // every pin an input, GPA has no pull-ups enabled
+    fn park(&mut self) -> Result<(), E> {
+        self.write_register(OLATA, 0)?;
+        self.write_register(IODIRA, 0xff)
+    }
+
+    // `configure` drives the columns again on the next scan
+    fn unpark(&mut self) -> Result<(), E> {
+        self.configured = false;
+        Ok(())
+    }
+
+    // the scan configures the expander and leaves every column high
+    fn self_test(&mut self) -> Result<SelfTest<COLUMNS, ROWS>, E> {
+        let closed = self.scan()?;
+        let mut rows = [0];
+        self.i2c.write_read(ADDRESS, &[GPIOB], &mut rows)?;
+        Ok(SelfTest {
+            shorted_rows: !rows[0] & ROW_MASK,
+            closed,
+        })
+    }
+}
+
+impl<I2C, E> MatrixScanner for Mcp23017<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = E;
+
+    fn scan_raw(&mut self) -> Result<u32, E> {
+        Matrix::scan(self)
+    }
+
+    fn dims(&self) -> (u8, u8) {
+        (COLUMNS as u8, ROWS as u8)
+    }
+}
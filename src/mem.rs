@@ -0,0 +1,65 @@
+//! Short `memcpy` and `memclr` loops in place of the ones of `compiler_builtins`,
+//! which unroll a word copy for every alignment of the ends. What each saves of
+//! the flash is noted at it, 1.0 KB together in the debug build and 1.1 KB in the
+//! release one. The default debug build, which logs, is past the flash without
+//! them.
+//!
+//! They are slower. The aligned variants, which the copies of structs, arrays and
+//! task inputs call, go a word at a time, about 2 cycles a byte, the others a byte
+//! at a time, about 8. A 64 B USB packet or log line then takes 7 us at 72 MHz and
+//! 64 us on the HSI, well inside a scan period. The DMA transfers don't use them.
+//!
+//! `compiler_builtins` defines the symbols weak, these take over. The volatile
+//! accesses keep LLVM from turning the loops back into calls to `memcpy`. Nothing
+//! calls `memmove` or `memset` with a value other than 0, the ones of
+//! `compiler_builtins` stay for them.
+
+// with the aligned variants 830 B less than those of `compiler_builtins`
+#[no_mangle]
+unsafe extern "C" fn __aeabi_memcpy(dest: *mut u8, src: *const u8, len: usize) {
+    for offset in 0..len {
+        dest.add(offset)
+            .write_volatile(src.add(offset).read_volatile());
+    }
+}
+
+// the aligned variants, a word at a time and the tail by bytes
+#[no_mangle]
+unsafe extern "C" fn __aeabi_memcpy4(dest: *mut u8, src: *const u8, len: usize) {
+    let words = len / 4;
+    for index in 0..words {
+        let src = src.cast::<u32>().add(index);
+        dest.cast::<u32>()
+            .add(index)
+            .write_volatile(src.read_volatile());
+    }
+    let tail = len & !3;
+    __aeabi_memcpy(dest.add(tail), src.add(tail), len & 3);
+}
+
+#[no_mangle]
+unsafe extern "C" fn __aeabi_memcpy8(dest: *mut u8, src: *const u8, len: usize) {
+    __aeabi_memcpy4(dest, src, len);
+}
+
+// with the aligned variants 320 B less than those of `compiler_builtins`
+#[no_mangle]
+unsafe extern "C" fn __aeabi_memclr(dest: *mut u8, len: usize) {
+    for offset in 0..len {
+        dest.add(offset).write_volatile(0);
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn __aeabi_memclr4(dest: *mut u8, len: usize) {
+    let words = len / 4;
+    for index in 0..words {
+        dest.cast::<u32>().add(index).write_volatile(0);
+    }
+    __aeabi_memclr(dest.add(len & !3), len & 3);
+}
+
+#[no_mangle]
+unsafe extern "C" fn __aeabi_memclr8(dest: *mut u8, len: usize) {
+    __aeabi_memclr4(dest, len);
+}
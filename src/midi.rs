@@ -0,0 +1,47 @@
+//! The keys as MIDI notes on USART1, for the `OUTPUT MIDI` mode of the `midi`
+//! feature, the notes of `keypad_core::midi`. The USART runs at the 31250 baud of
+//! MIDI then, rather than carrying the log and the serial stream, which go to the
+//! USB serial port and RTT alone.
+//!
+//! Holding the fn key shifts the notes pressed meanwhile up an octave. The key
+//! itself never makes it past the layers, so the shift follows the debounced state
+//! the scanner publishes. The emergency stop ends every note still sounding.
+
+#[cfg(feature = "bind")]
+use crate::bind;
+use crate::event::KeyEvent;
+use crate::keymap::FN_KEY;
+use crate::keypad::{self, key_bit, KeyState};
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+#[cfg(feature = "bind")]
+use keypad_core::binding::Binding;
+use keypad_core::midi::Midi;
+
+pub use keypad_core::midi::MidiMessage;
+
+pub const BAUD_RATE: u32 = 31_250;
+
+// played by `event_router`, ended all at once by the emergency stop
+static MIDI: Mutex<RefCell<Midi>> = Mutex::new(RefCell::new(Midi::new(KeyState(key_bit(
+    FN_KEY.0, FN_KEY.1,
+)))));
+
+/// The message for a press or release, `None` for the other events.
+pub fn handle(event: &KeyEvent) -> Option<MidiMessage> {
+    interrupt::free(|cs| {
+        let mut midi = MIDI.borrow(cs).borrow_mut();
+        // a key bound to a note of its own, the octave key doesn't shift it, see
+        // `crate::bind`
+        #[cfg(feature = "bind")]
+        if let Binding::Midi(note) = bind::of(event) {
+            return midi.play(event, note);
+        }
+        midi.handle(event, keypad::read_key_state())
+    })
+}
+
+/// Ends every note still sounding, one message each to `send`.
+pub fn release_all(send: impl FnMut(MidiMessage)) {
+    interrupt::free(|cs| MIDI.borrow(cs).borrow_mut().release_all(send));
+}
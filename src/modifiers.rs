@@ -0,0 +1,11 @@
+//! Lock keys, like a Caps Lock. A press of one toggles its lock instead of
+//! producing events, the release is swallowed as well. Every key event carries
+//! the locks as they were when it was queued, see [`crate::event::KeyEvent`].
+//!
+//! Which keys lock is up to the `[[lock]]` entries of `keymap.toml`. The locks
+//! persist with the settings, see `crate::config`.
+
+pub use keypad_core::modifiers::Modifiers;
+
+// the lock of `id` 1 in `keymap.toml`, the one the green led shows
+pub const LOCK1: u8 = 1 << 0;
@@ -0,0 +1,124 @@
+//! The RTIC monotonic on TIM4 at 1 us, the blocking delays spin on the core.
+//! TIM2 already runs the status led PWM and TIM3 the buzzer.
+//!
+//! The 16 bit counter is extended to 64 bits by `keypad_core::time::WideCounter`:
+//! the update interrupt of every overflow adds 2^16 ticks. Compare channel 1
+//! wakes RTIC for the next deadline. The timer stops in STOP mode along with the
+//! bus clocks.
+
+use stm32f1xx_hal::pac::TIM4;
+use stm32f1xx_hal::rcc::Clocks;
+use stm32f1xx_hal::timer::Timer;
+
+pub use keypad_core::time::{div, to_ms, TICK_HZ};
+use keypad_core::time::{Counter16, WideCounter};
+
+pub type Instant = fugit::TimerInstantU64<TICK_HZ>;
+pub type Duration = fugit::TimerDurationU64<TICK_HZ>;
+
+pub const fn millis(ms: u32) -> Duration {
+    Duration::millis(ms as u64)
+}
+
+pub const fn micros(us: u32) -> Duration {
+    Duration::micros(us as u64)
+}
+
+const OVERFLOW_TICKS: u64 = 1 << 16;
+
+// the status flags clear on writing zero, the other flags are written as ones
+const FLAGS: u32 = 0x1e5f;
+const UIF: u32 = 1 << 0;
+const CC1IF: u32 = 1 << 1;
+
+pub struct Tim4Monotonic {
+    tim: TIM4,
+    wide: WideCounter,
+}
+
+impl Tim4Monotonic {
+    pub fn new(tim: TIM4, clocks: &Clocks) -> Self {
+        let tick_hz = clocks.pclk1_tim().raw();
+        // enables and resets the timer
+        let tim = Timer::new(tim, clocks).release();
+        tim.psc.write(|w| w.psc().bits(prescaler(tick_hz)));
+        tim.arr.write(|w| w.arr().bits(u16::MAX));
+        // loads the prescaler, URS keeps this from counting as an overflow
+        tim.cr1.modify(|_, w| w.urs().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.dier.write(|w| w.uie().set_bit().cc1ie().set_bit());
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+        Self {
+            tim,
+            wide: WideCounter::new(),
+        }
+    }
+}
+
+fn prescaler(tick_hz: u32) -> u16 {
+    (tick_hz / TICK_HZ - 1) as u16
+}
+
+/// Retunes the prescaler for a timer clock of `tick_hz` without losing the count.
+/// Needs interrupts disabled.
+pub fn set_clock(tick_hz: u32) {
+    // SAFETY: `Tim4Monotonic` only writes these registers in `new`, and the
+    // count is put back before anything reads it
+    let tim = unsafe { &*TIM4::ptr() };
+    let count = tim.cnt.read().cnt().bits();
+    tim.psc.write(|w| w.psc().bits(prescaler(tick_hz)));
+    // the update restarts the counter with the new prescaler, with URS set it
+    // doesn't raise an overflow
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.cnt.write(|w| w.cnt().bits(count));
+}
+
+impl Counter16 for Tim4Monotonic {
+    fn count(&self) -> u16 {
+        self.tim.cnt.read().cnt().bits()
+    }
+
+    fn overflow_pending(&self) -> bool {
+        self.tim.sr.read().uif().bit_is_set()
+    }
+}
+
+impl rtic::Monotonic for Tim4Monotonic {
+    // the overflows have to be counted with the queue empty too
+    const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+    type Instant = Instant;
+    type Duration = Duration;
+
+    fn now(&mut self) -> Instant {
+        Instant::from_ticks(self.wide.now(self))
+    }
+
+    fn set_compare(&mut self, instant: Instant) {
+        let now = self.now();
+        // anything further away than an overflow gets another look at the next one
+        let compare = match instant.checked_duration_since(now) {
+            Some(ticks) if ticks.ticks() < OVERFLOW_TICKS => instant.ticks() as u16,
+            _ => (now.ticks() as u16).wrapping_sub(1),
+        };
+        self.tim.ccr1().write(|w| w.ccr().bits(compare));
+    }
+
+    fn clear_compare_flag(&mut self) {
+        self.tim.sr.write(|w| unsafe { w.bits(FLAGS & !CC1IF) });
+    }
+
+    fn zero() -> Instant {
+        Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {}
+
+    fn on_interrupt(&mut self) {
+        if self.tim.sr.read().uif().bit_is_set() {
+            self.tim.sr.write(|w| unsafe { w.bits(FLAGS & !UIF) });
+            self.wide.overflowed();
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! The keys pressed, spelled out in Morse on the green led, for a board with
+//! neither probe nor serial attached. A dit lights it for [`DIT_MS`], a dah for
+//! three dits, with a dit of dark between the two and three between characters.
+//! The `*` key goes out as `x`, the `#` key as `h`.
+//!
+//! Up to [`QUEUE_SIZE`] characters wait behind the one played, more are dropped.
+//! `led_controller` plays them on top of the phases of the `crate::led_mode`,
+//! which leave the green led to the code meanwhile.
+
+use heapless::Deque;
+
+pub const DIT_MS: u32 = 100;
+const DAH_MS: u32 = 3 * DIT_MS;
+const LETTER_GAP_MS: u32 = 3 * DIT_MS;
+
+pub const QUEUE_SIZE: usize = 4;
+
+/// The dits and dahs of a keymap character.
+pub fn code(key: char) -> Option<&'static str> {
+    match key {
+        '0' => Some("-----"),
+        '1' => Some(".----"),
+        '2' => Some("..---"),
+        '3' => Some("...--"),
+        '4' => Some("....-"),
+        '5' => Some("....."),
+        '6' => Some("-...."),
+        '7' => Some("--..."),
+        '8' => Some("---.."),
+        '9' => Some("----."),
+        'A' | 'a' => Some(".-"),
+        'B' | 'b' => Some("-..."),
+        'C' | 'c' => Some("-.-."),
+        'D' | 'd' => Some("-.."),
+        '*' => Some("-..-"),
+        '#' => Some("...."),
+        _ => None,
+    }
+}
+
+pub struct Morse {
+    queue: Deque<&'static str, QUEUE_SIZE>,
+    // the rest of the character played
+    symbols: &'static [u8],
+    lit: bool,
+    playing: bool,
+}
+
+impl Morse {
+    pub const fn new() -> Self {
+        Self {
+            queue: Deque::new(),
+            symbols: &[],
+            lit: false,
+            playing: false,
+        }
+    }
+
+    /// Queues `key`, returns whether the playback has to be started.
+    pub fn push(&mut self, key: char) -> bool {
+        let Some(code) = code(key) else {
+            return false;
+        };
+        if self.queue.push_back(code).is_err() {
+            return false;
+        }
+        !core::mem::replace(&mut self.playing, true)
+    }
+
+    /// Moves on to the next dit, dah or gap and returns its length, `None` once
+    /// everything queued was played.
+    pub fn step(&mut self) -> Option<u32> {
+        if core::mem::take(&mut self.lit) {
+            return match self.symbols.is_empty() {
+                true => Some(LETTER_GAP_MS),
+                false => Some(DIT_MS),
+            };
+        }
+        if self.symbols.is_empty() {
+            match self.queue.pop_front() {
+                Some(code) => self.symbols = code.as_bytes(),
+                None => {
+                    self.playing = false;
+                    return None;
+                }
+            }
+        }
+        let (&symbol, rest) = self.symbols.split_first()?;
+        self.symbols = rest;
+        self.lit = true;
+        match symbol {
+            b'-' => Some(DAH_MS),
+            _ => Some(DIT_MS),
+        }
+    }
+
+    /// The green led while a character is played.
+    pub fn green(&self) -> Option<bool> {
+        self.playing.then_some(self.lit)
+    }
+
+    /// Drops the character played and the queued ones, the step pending still
+    /// comes and ends the playback.
+    pub fn abort(&mut self) {
+        self.queue.clear();
+        self.symbols = &[];
+        self.lit = false;
+    }
+}
@@ -0,0 +1,113 @@
+//! Where the key events go, one backend at a time: text lines or binary frames on
+//! USART1, whichever the `text` feature built in, the USB keyboard, or nowhere.
+//! The `midi` feature swaps the USART1 stream for the MIDI notes of `crate::midi`,
+//! the `media` feature adds the media keys of `crate::hid` in front of the USB
+//! keyboard. `OUTPUT TEXT|BINARY|HID|SILENT|MIDI|MEDIA` picks one, the
+//! [`OUTPUT_CHORD`] steps through the ones built in, as does `crate::config_mode`.
+//! The leds flash the colour of the new mode, and `SAVE` keeps it.
+//!
+//! The logs, the RTT event channel, the USB serial port and the I2C slave don't
+//! depend on it.
+
+use crate::led_mode::Flash;
+
+/// Chord stepping to the next output mode, see `keymap.toml`.
+pub const OUTPUT_CHORD: u8 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutputMode {
+    Text,
+    Binary,
+    Hid,
+    Silent,
+    // numbered for the saved settings of builds without the ones before
+    #[cfg(feature = "midi")]
+    Midi = 4,
+    #[cfg(feature = "media")]
+    Media = 5,
+}
+
+impl OutputMode {
+    /// The USART1 stream of the build.
+    #[cfg(not(feature = "midi"))]
+    pub const DEFAULT: OutputMode = match cfg!(feature = "text") {
+        true => OutputMode::Text,
+        false => OutputMode::Binary,
+    };
+    #[cfg(feature = "midi")]
+    pub const DEFAULT: OutputMode = OutputMode::Midi;
+
+    const ALL: &'static [OutputMode] = &[
+        OutputMode::Text,
+        OutputMode::Binary,
+        OutputMode::Hid,
+        OutputMode::Silent,
+        #[cfg(feature = "midi")]
+        OutputMode::Midi,
+        #[cfg(feature = "media")]
+        OutputMode::Media,
+    ];
+
+    pub fn is_built_in(self) -> bool {
+        match self {
+            OutputMode::Text => cfg!(all(feature = "text", not(feature = "midi"))),
+            OutputMode::Binary => !cfg!(any(feature = "text", feature = "midi")),
+            OutputMode::Hid | OutputMode::Silent => true,
+            #[cfg(feature = "midi")]
+            OutputMode::Midi => true,
+            #[cfg(feature = "media")]
+            OutputMode::Media => true,
+        }
+    }
+
+    /// The one after this one that is built in, or the one before it.
+    pub fn step(self, up: bool) -> OutputMode {
+        let len = Self::ALL.len();
+        let index = Self::ALL.iter().position(|&mode| mode == self).unwrap_or(0);
+        (1..len)
+            .map(|step| if up { step } else { len - step })
+            .map(|step| Self::ALL[(index + step) % len])
+            .find(|mode| mode.is_built_in())
+            .unwrap_or(self)
+    }
+
+    pub fn is_stream(self) -> bool {
+        matches!(self, OutputMode::Text | OutputMode::Binary)
+    }
+
+    /// The ones that hold keys down on the host.
+    pub fn is_usb(self) -> bool {
+        match self {
+            OutputMode::Hid => true,
+            #[cfg(feature = "media")]
+            OutputMode::Media => true,
+            _ => false,
+        }
+    }
+
+    pub fn flash(self) -> Flash {
+        match self {
+            OutputMode::Text => Flash::OutputText,
+            OutputMode::Binary => Flash::OutputBinary,
+            OutputMode::Hid => Flash::OutputHid,
+            OutputMode::Silent => Flash::OutputSilent,
+            // the colour of the stream it replaces
+            #[cfg(feature = "midi")]
+            OutputMode::Midi => Flash::OutputText,
+            #[cfg(feature = "media")]
+            OutputMode::Media => Flash::OutputHid,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_byte(byte: u8) -> Option<OutputMode> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.to_byte() == byte)
+    }
+}
@@ -0,0 +1,90 @@
+//! Piano mode: every key plays its own note while it is held. With several keys
+//! held the most recently pressed one sounds, releasing it falls back to the
+//! previous one that is still held.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{COLUMNS, KEYS};
+
+/// Note of every key in `row * COLUMNS + col` order, chromatic from C5. The 'D'
+/// key is the fn key and never plays.
+#[cfg(not(feature = "second-pad"))]
+pub const NOTES_HZ: [u32; KEYS] = [
+    523, 554, 587, 622, 659, 698, 740, 784, 831, 880, 932, 988, 1047, 1109, 1175, 1245,
+];
+/// With `second-pad` its keys go on chromatic from E6 in every row.
+#[cfg(feature = "second-pad")]
+pub const NOTES_HZ: [u32; KEYS] = [
+    523, 554, 587, 622, 1319, 1397, 1480, 1568, // C5-D#5, E6-G6
+    659, 698, 740, 784, 1661, 1760, 1865, 1976, // E5-G5, G#6-B6
+    831, 880, 932, 988, 2093, 2217, 2349, 2489, // G#5-B5, C7-D#7
+    1047, 1109, 1175, 1245, 2637, 2794, 2960, 3136, // C6-D#6, E7-G7
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Note {
+    Play(u32),
+    Silence,
+}
+
+pub struct Piano {
+    enabled: bool,
+    // held keys in the order they were pressed
+    held: heapless::Vec<u8, KEYS>,
+}
+
+impl Piano {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            held: heapless::Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turns piano mode on or off, returns `Silence` if that cut off a note.
+    pub fn set_enabled(&mut self, enabled: bool) -> Option<Note> {
+        self.enabled = enabled;
+        let playing = !self.held.is_empty();
+        self.held.clear();
+        (playing && !enabled).then_some(Note::Silence)
+    }
+
+    /// Returns the note to switch to if `event` changes what is playing.
+    pub fn track(&mut self, event: &KeyEvent) -> Option<Note> {
+        if !self.enabled {
+            return None;
+        }
+        let key = event.row * COLUMNS as u8 + event.col;
+        match event.kind {
+            EventKind::Pressed => {
+                self.release(key);
+                // can't overflow, every key is in there at most once
+                let _ = self.held.push(key);
+                Some(Note::Play(NOTES_HZ[key as usize]))
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => {
+                let was_last = self.held.last() == Some(&key);
+                self.release(key);
+                was_last.then(|| match self.held.last() {
+                    Some(&previous) => Note::Play(NOTES_HZ[previous as usize]),
+                    None => Note::Silence,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    // copied over instead of `retain`, whose shift of the keys after it pulls the
+    // `memmove` of `compiler_builtins` into debug builds
+    fn release(&mut self, key: u8) {
+        self.held = self
+            .held
+            .iter()
+            .copied()
+            .filter(|&held| held != key)
+            .collect();
+    }
+}
@@ -0,0 +1,176 @@
+//! PIN checks of the `ENTRY PIN` mode of `crate::entry`. Three wrong PINs in a
+//! row lock the keypad out for [`LOCKOUT_MS`], a right one unlocks `PIN SET`
+//! until the next wrong one or the entry mode changes.
+//!
+//! The PIN is saved with the settings, see `crate::config`.
+
+use crate::app::{now_ms, send_led_message};
+use crate::entry::MAX_DIGITS;
+use crate::led_mode::{LedMessage, LedMode};
+use core::fmt;
+use core::ops::RangeInclusive;
+use rtic::Mutex;
+
+// lengths `PIN SET` accepts
+pub const PIN_DIGITS: RangeInclusive<usize> = 4..=8;
+// bytes of a PIN in the settings record
+pub const STORED_LEN: usize = 8;
+
+pub const MAX_FAILURES: u8 = 3;
+pub const LOCKOUT_MS: u32 = 30_000;
+
+/// ASCII digits, zero padded to [`MAX_DIGITS`]. Two PINs compare in all bytes
+/// whatever their lengths, so the time taken doesn't tell how many digits were
+/// right or how long the PIN is.
+#[derive(Copy, Clone)]
+pub struct Pin([u8; MAX_DIGITS]);
+
+impl Pin {
+    pub const DEFAULT: Pin = Pin(*b"1234\0\0\0\0\0\0\0\0");
+
+    /// `None` unless `digits` are 1 to [`MAX_DIGITS`] ASCII digits.
+    pub fn new(digits: &str) -> Option<Pin> {
+        let digits = digits.as_bytes();
+        if digits.is_empty() || digits.len() > MAX_DIGITS || !digits.iter().all(u8::is_ascii_digit)
+        {
+            return None;
+        }
+        let mut pin = [0; MAX_DIGITS];
+        pin[..digits.len()].copy_from_slice(digits);
+        Some(Pin(pin))
+    }
+
+    /// A PIN that may be stored, [`PIN_DIGITS`] long.
+    pub fn is_valid(&self) -> bool {
+        let len = self
+            .0
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        PIN_DIGITS.contains(&len) && self.0[len..].iter().all(|&byte| byte == 0)
+    }
+
+    pub fn stored(&self) -> [u8; STORED_LEN] {
+        let mut stored = [0; STORED_LEN];
+        stored.copy_from_slice(&self.0[..STORED_LEN]);
+        stored
+    }
+
+    pub fn from_stored(stored: &[u8]) -> Pin {
+        let mut pin = [0; MAX_DIGITS];
+        pin[..STORED_LEN].copy_from_slice(&stored[..STORED_LEN]);
+        Pin(pin)
+    }
+}
+
+impl PartialEq for Pin {
+    fn eq(&self, other: &Pin) -> bool {
+        let diff = self
+            .0
+            .iter()
+            .zip(other.0)
+            .fold(0, |diff, (&a, b)| diff | (a ^ b));
+        diff == 0
+    }
+}
+
+impl Eq for Pin {}
+
+// the digits stay out of the logs, whatever prints a command or a setting
+impl fmt::Debug for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Pin(****)")
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Granted,
+    // the failures so far, fewer than `MAX_FAILURES`
+    Denied(u8),
+    LockedOut,
+}
+
+pub struct PinLock {
+    pin: Pin,
+    // wrong PINs in a row
+    failures: u8,
+    // monotonic milliseconds the lockout ends at
+    locked_until: Option<u32>,
+    unlocked: bool,
+}
+
+impl PinLock {
+    pub const fn new(pin: Pin) -> Self {
+        Self {
+            pin,
+            failures: 0,
+            locked_until: None,
+            unlocked: false,
+        }
+    }
+
+    pub fn pin(&self) -> Pin {
+        self.pin
+    }
+
+    pub fn check(&mut self, entered: &Pin, now: u32) -> Verdict {
+        if *entered == self.pin {
+            self.failures = 0;
+            self.unlocked = true;
+            return Verdict::Granted;
+        }
+        self.unlocked = false;
+        self.failures += 1;
+        if self.failures < MAX_FAILURES {
+            return Verdict::Denied(self.failures);
+        }
+        self.locked_until = Some(now.wrapping_add(LOCKOUT_MS));
+        Verdict::LockedOut
+    }
+
+    pub fn is_locked_out(&self) -> bool {
+        self.locked_until.is_some()
+    }
+
+    /// Milliseconds left of the lockout, it ends once there are none.
+    pub fn lockout_left(&mut self, now: u32) -> u32 {
+        let Some(until) = self.locked_until else {
+            return 0;
+        };
+        let left = until.wrapping_sub(now);
+        // past the deadline the difference wraps around
+        if left == 0 || left > LOCKOUT_MS {
+            self.locked_until = None;
+            self.failures = 0;
+            return 0;
+        }
+        left
+    }
+
+    pub fn relock(&mut self) {
+        self.unlocked = false;
+    }
+
+    /// Only changes the PIN after a right one was entered, returns whether it did.
+    pub fn set_pin(&mut self, pin: Pin) -> bool {
+        if self.unlocked {
+            self.pin = pin;
+        }
+        self.unlocked
+    }
+}
+
+/// The countdown of a lockout, run by `event_stats` once a
+/// [`crate::timing::STATS_PERIOD`] until it is over.
+pub fn lockout_countdown(pin_lock: &mut impl Mutex<T = PinLock>) {
+    let left_ms = pin_lock.lock(|lock| lock.is_locked_out().then(|| lock.lockout_left(now_ms())));
+    match left_ms {
+        None => {}
+        Some(0) => {
+            log!("lockout over");
+            send_led_message(LedMessage::Leave(LedMode::Lockout));
+        }
+        Some(left_ms) => log!("locked out, {} s left", left_ms.div_ceil(1000)),
+    }
+}
@@ -0,0 +1,125 @@
+//! Instrumentation: the scan duration on the DWT cycle counter, which init
+//! enables, and the CPU load from the time `idle` spends asleep.
+
+use crate::clock_manager::FULL_SYSCLK_HZ;
+use crate::keypad::COLUMNS;
+use crate::monotonic;
+use cortex_m::peripheral::DWT;
+use keypad_core::matrix::SETTLE_US;
+
+// the scanner holds full speed while it polls, so the cycles are at 72 MHz
+const CYCLES_PER_US: u32 = FULL_SYSCLK_HZ / 1_000_000;
+
+/// CPU load in percent from the time spent sleeping in `idle` out of `elapsed_us`.
+pub fn load_percent(idle_us: u32, elapsed_us: u32) -> u8 {
+    if elapsed_us < 100 {
+        return 0;
+    }
+    let busy = elapsed_us.saturating_sub(idle_us);
+    (busy / (elapsed_us / 100)).min(100) as u8
+}
+
+// upper ends of the scan lateness buckets, the last bucket takes everything above
+const LATENESS_BUCKETS_MS: [u32; 3] = [1, 2, 5];
+
+/// How late the scanner ran after its deadline: the maximum and a histogram of
+/// < 1 ms, < 2 ms, < 5 ms and >= 5 ms.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lateness {
+    pub max_us: u32,
+    pub buckets: [u32; LATENESS_BUCKETS_MS.len() + 1],
+}
+
+impl Lateness {
+    pub const fn new() -> Self {
+        Self {
+            max_us: 0,
+            buckets: [0; LATENESS_BUCKETS_MS.len() + 1],
+        }
+    }
+
+    pub fn record(&mut self, late_us: u32) {
+        self.max_us = self.max_us.max(late_us);
+        let bucket = LATENESS_BUCKETS_MS
+            .iter()
+            .position(|&bound| late_us < bound * 1000)
+            .unwrap_or(LATENESS_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// Scans that take longer are counted as over budget.
+pub const SCAN_BUDGET_US: u32 = 200;
+
+// the columns settle one after the other, twice as many of them with
+// `second-pad`, which has to leave most of the budget to the rest of the scan
+const _: () = assert!(COLUMNS as u32 * SETTLE_US <= SCAN_BUDGET_US / 4);
+pub const SCAN_REPORT_PERIOD_MS: u32 = 10_000;
+
+/// Start of a measurement, see [`Stopwatch::cycles`].
+#[derive(Copy, Clone)]
+pub struct Stopwatch(u32);
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self(DWT::cycle_count())
+    }
+
+    /// Cycles since [`Stopwatch::start`]. The counter wraps every ~60 s at
+    /// 72 MHz, the wrapping difference is right for anything shorter.
+    pub fn cycles(self) -> u32 {
+        DWT::cycle_count().wrapping_sub(self.0)
+    }
+}
+
+/// Min/max/average of the scan durations since the last [`ScanTiming::take`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScanSummary {
+    pub scans: u32,
+    pub min_us: u32,
+    pub avg_us: u32,
+    pub max_us: u32,
+    pub over_budget: u32,
+}
+
+pub struct ScanTiming {
+    scans: u32,
+    min: u32,
+    max: u32,
+    total: u64,
+    over_budget: u32,
+}
+
+impl ScanTiming {
+    pub const fn new() -> Self {
+        Self {
+            scans: 0,
+            min: u32::MAX,
+            max: 0,
+            total: 0,
+            over_budget: 0,
+        }
+    }
+
+    pub fn record(&mut self, cycles: u32) {
+        self.scans += 1;
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.total += u64::from(cycles);
+        if cycles > SCAN_BUDGET_US * CYCLES_PER_US {
+            self.over_budget += 1;
+        }
+    }
+
+    /// Returns the statistics and starts over, `None` without any scan.
+    pub fn take(&mut self) -> Option<ScanSummary> {
+        let timing = core::mem::replace(self, Self::new());
+        (timing.scans > 0).then(|| ScanSummary {
+            scans: timing.scans,
+            min_us: timing.min / CYCLES_PER_US,
+            avg_us: monotonic::div(timing.total, CYCLES_PER_US) as u32 / timing.scans,
+            max_us: timing.max / CYCLES_PER_US,
+            over_budget: timing.over_budget,
+        })
+    }
+}
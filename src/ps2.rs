@@ -0,0 +1,325 @@
+//! The keypad as a PS/2 keyboard, for hosts without USB. We are the device side
+//! and generate the clock, both lines are open-drain with the host's pull-ups:
+//! rev A uses PB8 for the clock and PB9 for the data, rev B PB14 and PB11, all of
+//! them 5 V tolerant.
+//!
+//! TIM1 steps the bus, every millisecond while it is idle and every [`PHASE_US`]
+//! during a frame, four phases a bit for a bit rate of ~12.5 kHz. The keys go out
+//! as Set 2 scan codes, make and `F0` break. A host holding the clock low inhibits
+//! the bus: a frame cut short is sent again once the host lets go. The host
+//! commands answered are `FF` reset, `F4`/`F5` enable and disable, `ED` with the
+//! lock leds, ignored, `EE` echo, `F2` read ID and `FE` resend, any other one is
+//! acknowledged and ignored.
+//!
+//! The timer stops in STOP, commands sent meanwhile wait for the next wake up.
+
+use crate::event::{EventKind, KeyEvent};
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use heapless::Deque;
+use stm32f1xx_hal::gpio::{ErasedPin, OpenDrain, Output};
+use stm32f1xx_hal::pac::TIM1;
+use stm32f1xx_hal::rcc::Clocks;
+use stm32f1xx_hal::timer::Timer;
+
+/// Length of a quarter bit.
+pub const PHASE_US: u16 = 20;
+// how often the idle bus is polled for a host request or a byte to send
+const IDLE_US: u16 = 1000;
+
+// scan codes waiting for the bus, a break takes two
+const QUEUE_SIZE: usize = 32;
+
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const SELF_TEST_PASSED: u8 = 0xaa;
+const ECHO: u8 = 0xee;
+const BREAK: u8 = 0xf0;
+// the ID of an MF2 keyboard
+const ID: [u8; 2] = [0xab, 0x83];
+
+/// Clock and data.
+pub type Pins = (ErasedPin<Output<OpenDrain>>, ErasedPin<Output<OpenDrain>>);
+
+/// Set 2 make code of a keymap character, the digits of the main row rather than
+/// the keypad ones, which depend on the host's num lock.
+pub fn scan_code(key: char) -> Option<u8> {
+    match key {
+        '1' => Some(0x16),
+        '2' => Some(0x1e),
+        '3' => Some(0x26),
+        '4' => Some(0x25),
+        '5' => Some(0x2e),
+        '6' => Some(0x36),
+        '7' => Some(0x3d),
+        '8' => Some(0x3e),
+        '9' => Some(0x46),
+        '0' => Some(0x45),
+        'A' | 'a' => Some(0x1c),
+        'B' | 'b' => Some(0x32),
+        'C' | 'c' => Some(0x21),
+        'D' | 'd' => Some(0x23),
+        '*' => Some(0x7c), // keypad *
+        '#' => Some(0x5a), // enter, PS/2 has no # key of its own
+        _ => None,
+    }
+}
+
+#[derive(Copy, Clone)]
+enum State {
+    Idle,
+    // the 11 bits of a frame, start, data, parity and stop
+    Sending { frame: u16, bit: u8, phase: u8 },
+    // 10 bits from the host, data, parity and stop, then the acknowledge
+    Receiving { frame: u16, bit: u8, phase: u8 },
+}
+
+struct Ps2Device {
+    tim: TIM1,
+    clock: ErasedPin<Output<OpenDrain>>,
+    data: ErasedPin<Output<OpenDrain>>,
+    state: State,
+    queue: Deque<u8, QUEUE_SIZE>,
+    // for `FE`
+    last_sent: u8,
+    // keys are sent while enabled, `F5` stops them until `F4`
+    enabled: bool,
+    // the next byte from the host is the argument of `ED`
+    expect_leds: bool,
+}
+
+// fed by the consumer, driven from the TIM1 interrupt, so it lives here rather than
+// in a task
+static DEVICE: Mutex<RefCell<Option<Ps2Device>>> = Mutex::new(RefCell::new(None));
+
+/// Starts TIM1 and queues the self-test result a keyboard sends at power up.
+pub fn init(tim: TIM1, pins: Pins, clocks: &Clocks) {
+    let (mut clock, mut data) = pins;
+    clock.set_high();
+    data.set_high();
+    // enables and resets the timer, which then ticks every us
+    let tim = Timer::new(tim, clocks).release();
+    let tick_hz = clocks.pclk2_tim().raw();
+    tim.psc
+        .write(|w| w.psc().bits((tick_hz / 1_000_000 - 1) as u16));
+    tim.arr.write(|w| w.arr().bits(IDLE_US - 1));
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.sr.write(|w| unsafe { w.bits(0) });
+    tim.dier.write(|w| w.uie().set_bit());
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+    let mut device = Ps2Device {
+        tim,
+        clock,
+        data,
+        state: State::Idle,
+        queue: Deque::new(),
+        last_sent: 0,
+        enabled: true,
+        expect_leds: false,
+    };
+    device.send(&[SELF_TEST_PASSED]);
+    interrupt::free(|cs| *DEVICE.borrow(cs).borrow_mut() = Some(device));
+}
+
+/// Queues the scan codes of a key event, dropped while the host disabled the keys
+/// or when the queue is full.
+pub fn push_event(event: &KeyEvent) {
+    let Some(code) = scan_code(event.key) else {
+        return;
+    };
+    interrupt::free(|cs| {
+        let mut device = DEVICE.borrow(cs).borrow_mut();
+        let Some(device) = device.as_mut().filter(|device| device.enabled) else {
+            return;
+        };
+        match event.kind {
+            // the host relies on the keyboard for the typematic repeat
+            EventKind::Pressed | EventKind::Repeat => device.send(&[code]),
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => {
+                device.send(&[BREAK, code])
+            }
+            _ => {}
+        }
+    });
+}
+
+/// Handles the TIM1 update interrupt.
+pub fn on_tick() {
+    interrupt::free(|cs| {
+        if let Some(device) = DEVICE.borrow(cs).borrow_mut().as_mut() {
+            device.tim.sr.write(|w| unsafe { w.bits(0) });
+            device.step();
+        }
+    });
+}
+
+// odd parity of the data bits
+fn parity(byte: u8) -> u16 {
+    u16::from(byte.count_ones().is_multiple_of(2))
+}
+
+impl Ps2Device {
+    // all or nothing, so a break never loses its code
+    fn send(&mut self, bytes: &[u8]) {
+        if self.queue.capacity() - self.queue.len() >= bytes.len() {
+            for &byte in bytes {
+                let _ = self.queue.push_back(byte);
+            }
+        }
+    }
+
+    fn set_phase_us(&mut self, us: u16) {
+        self.tim.arr.write(|w| w.arr().bits(us - 1));
+    }
+
+    fn step(&mut self) {
+        self.state = match self.state {
+            State::Idle => self.idle(),
+            State::Sending { frame, bit, phase } => self.sending(frame, bit, phase),
+            State::Receiving { frame, bit, phase } => self.receiving(frame, bit, phase),
+        };
+        if let State::Idle = self.state {
+            self.set_phase_us(IDLE_US);
+        } else {
+            self.set_phase_us(PHASE_US);
+        }
+    }
+
+    fn idle(&mut self) -> State {
+        // the clock held low inhibits the bus
+        if self.clock.is_low() {
+            return State::Idle;
+        }
+        // the data pulled low with the clock released is a request to send
+        if self.data.is_low() {
+            return State::Receiving {
+                frame: 0,
+                bit: 0,
+                phase: 0,
+            };
+        }
+        match self.queue.front() {
+            Some(&byte) => State::Sending {
+                frame: 1 << 10 | parity(byte) << 9 | u16::from(byte) << 1,
+                bit: 0,
+                phase: 1,
+            },
+            None => State::Idle,
+        }
+    }
+
+    // the data changes while the clock is high, the host reads it on the falling
+    // edge
+    fn sending(&mut self, frame: u16, bit: u8, phase: u8) -> State {
+        match phase {
+            0 => {
+                self.clock.set_high();
+                if bit == 11 {
+                    if let Some(byte) = self.queue.pop_front() {
+                        self.last_sent = byte;
+                    }
+                    return State::Idle;
+                }
+            }
+            1 => {
+                if frame & 1 << bit != 0 {
+                    self.data.set_high();
+                } else {
+                    self.data.set_low();
+                }
+            }
+            2 => {
+                // inhibited, the byte stays queued for another try
+                if self.clock.is_low() {
+                    self.data.set_high();
+                    return State::Idle;
+                }
+                self.clock.set_low();
+            }
+            _ => {
+                return State::Sending {
+                    frame,
+                    bit: bit + 1,
+                    phase: 0,
+                }
+            }
+        }
+        State::Sending {
+            frame,
+            bit,
+            phase: phase + 1,
+        }
+    }
+
+    // the host changes the data while the clock is low, it is read once the clock
+    // is high again. The data is pulled low for the clock after the stop bit.
+    fn receiving(&mut self, mut frame: u16, bit: u8, phase: u8) -> State {
+        match phase {
+            0 => self.clock.set_low(),
+            2 => self.clock.set_high(),
+            3 if bit < 10 => {
+                if self.data.is_high() {
+                    frame |= 1 << bit;
+                }
+                if bit == 9 {
+                    self.data.set_low();
+                }
+                return State::Receiving {
+                    frame,
+                    bit: bit + 1,
+                    phase: 0,
+                };
+            }
+            3 => {
+                self.data.set_high();
+                self.received(frame);
+                return State::Idle;
+            }
+            _ => {}
+        }
+        State::Receiving {
+            frame,
+            bit,
+            phase: phase + 1,
+        }
+    }
+
+    fn received(&mut self, frame: u16) {
+        let byte = frame as u8;
+        if frame >> 8 & 1 != parity(byte) || frame & 1 << 9 == 0 {
+            self.send(&[RESEND]);
+            return;
+        }
+        if core::mem::take(&mut self.expect_leds) {
+            self.send(&[ACK]);
+            return;
+        }
+        // a command cancels whatever was still queued
+        self.queue.clear();
+        match byte {
+            0xff => {
+                self.enabled = true;
+                self.send(&[ACK, SELF_TEST_PASSED]);
+            }
+            0xfe => {
+                let last = self.last_sent;
+                self.send(&[last]);
+            }
+            0xf5 => {
+                self.enabled = false;
+                self.send(&[ACK]);
+            }
+            0xf4 => {
+                self.enabled = true;
+                self.send(&[ACK]);
+            }
+            0xf2 => self.send(&[ACK, ID[0], ID[1]]),
+            0xee => self.send(&[ECHO]),
+            0xed => {
+                self.expect_leds = true;
+                self.send(&[ACK]);
+            }
+            _ => self.send(&[ACK]),
+        }
+    }
+}
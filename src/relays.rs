@@ -0,0 +1,133 @@
+//! Four relay outputs for switching loads, the `relays` feature. The keys of the
+//! last column, 'A' to 'D' on the shipped keymap, toggle relay 1 to 4 on a press.
+//! They go by position, the scanner takes them ahead of the locks and the layers,
+//! which would keep 'A', 'B' and 'D' otherwise, and gives them to the relays
+//! alone. `OUT 1 ON` and `OUT 1 OFF` switch them from the host,
+//! `STATUS` shows them and `SAVE` keeps them for the next boot. Rev A drives
+//! them on PB8-PB11, rev B on PB0, PB1, PB11 and PB14, high for on.
+//!
+//! The emergency stop switches all of them off and keeps them there until the
+//! latch clears, they stay off after that.
+
+use crate::board::RELAY_PINS;
+use crate::keypad::{key_bit, KeyState, PAD_COLUMNS};
+use crate::logging::Text;
+use crate::rtc;
+use core::cell::Cell;
+use cortex_m::interrupt::{self, CriticalSection, Mutex};
+use stm32f1xx_hal::gpio::{ErasedPin, Output};
+use stm32f1xx_hal::pac::GPIOB;
+
+pub const RELAYS: usize = 4;
+
+/// The outputs of relay 1 to 4, for keeping their pins taken.
+pub type Pins = [ErasedPin<Output>; RELAYS];
+
+// from the emergency stop until its latch clears
+const INHIBITED: u8 = 1 << 7;
+
+// bit `n` for relay `n + 1`, and `INHIBITED`
+static STATE: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+/// Starts with the saved states.
+pub fn start(_pins: Pins, states: u8) {
+    interrupt::free(|cs| drive(cs, states));
+}
+
+// all of them at once
+fn drive(cs: &CriticalSection, state: u8) {
+    STATE.borrow(cs).set(state);
+    let mut bits = 0;
+    for (index, &pin) in RELAY_PINS.iter().enumerate() {
+        // the upper half of BSRR resets
+        bits |= match state & 1 << index != 0 {
+            true => 1 << pin,
+            false => 1 << (pin + 16),
+        };
+    }
+    // SAFETY: BSRR only touches the pins it has bits for, and those are taken by
+    // `start`
+    unsafe { (*GPIOB::ptr()).bsrr.write(|w| w.bits(bits)) };
+}
+
+/// The states of all of them, bit `n` for relay `n + 1`.
+pub fn states() -> u8 {
+    interrupt::free(|cs| STATE.borrow(cs).get()) & !INHIBITED
+}
+
+/// Switches relay `index + 1` at `now_ms`, `false` while the emergency stop holds
+/// them off.
+pub fn set(index: usize, on: bool, now_ms: u32) -> bool {
+    switch(index, |_| on, now_ms)
+}
+
+pub fn toggle(index: usize, now_ms: u32) -> bool {
+    switch(index, |on| !on, now_ms)
+}
+
+// reads and writes in one critical section, a command and a key toggling at once
+// both count
+fn switch(index: usize, to: impl FnOnce(bool) -> bool, now_ms: u32) -> bool {
+    let (state, on) = interrupt::free(|cs| {
+        let state = STATE.borrow(cs).get();
+        let on = to(state & 1 << index != 0);
+        if state & INHIBITED == 0 {
+            drive(cs, state & !(1 << index) | u8::from(on) << index);
+        }
+        (state, on)
+    });
+    let held_off = state & INHIBITED != 0;
+    if !held_off && (state & 1 << index != 0) != on {
+        logged(index, on, now_ms);
+    }
+    !held_off
+}
+
+// the last column of the first pad, a row for every relay
+const COLUMN: usize = PAD_COLUMNS - 1;
+const KEYS: KeyState =
+    KeyState(key_bit(0, COLUMN) | key_bit(1, COLUMN) | key_bit(2, COLUMN) | key_bit(3, COLUMN));
+
+/// Toggles the relays of the keys pressed in `changes`, the pressed and released
+/// keys of a frame, unless the matrix is `faulty`. Returns the changes of the
+/// other keys.
+pub fn take(
+    (pressed, released): (KeyState, KeyState),
+    faulty: bool,
+    now_ms: u32,
+) -> (KeyState, KeyState) {
+    for index in (0..RELAYS).filter(|&row| !faulty && pressed.is_pressed(row, COLUMN)) {
+        toggle(index, now_ms);
+    }
+    (pressed & !KEYS, released & !KEYS)
+}
+
+/// The emergency stop, every relay off until [`release`].
+pub fn stop(now_ms: u32) {
+    let state = interrupt::free(|cs| {
+        let state = STATE.borrow(cs).get();
+        drive(cs, INHIBITED);
+        state
+    });
+    for index in (0..RELAYS).filter(|index| state & 1 << index != 0) {
+        logged(index, false, now_ms);
+    }
+}
+
+/// The latch cleared, they switch again.
+pub fn release() {
+    interrupt::free(|cs| {
+        let state = STATE.borrow(cs);
+        state.set(state.get() & !INHIBITED);
+    });
+}
+
+fn logged(index: usize, on: bool, now_ms: u32) {
+    log!(
+        "relay {} {} at {} ms, time {}",
+        index + 1,
+        Text(if on { "on" } else { "off" }),
+        now_ms,
+        rtc::seconds()
+    );
+}
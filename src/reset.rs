@@ -0,0 +1,102 @@
+//! Resets the firmware asks for itself: the [`RESET_CHORD`] held for
+//! [`CHORD_HOLD_MS`], and the way into `crate::bootloader`. The `user_reset` task
+//! logs it, saves the lifetime counters and gives the logs [`DRAIN_MS`] to get
+//! out before the reset. A marker in a backup register tells the next boot what
+//! the software reset was for, and these from the one after a HardFault.
+//!
+//! [`RESET_CHORD`]: crate::chord::RESET_CHORD
+//! [`CHORD_HOLD_MS`]: keypad_core::chord::CHORD_HOLD_MS
+
+use crate::app::user_reset;
+use crate::lifetime;
+use crate::logging;
+use crate::spawn::Counted;
+use crate::timing;
+use crate::watchdog::{ResetCause, ResetCauses};
+use cortex_m::peripheral::SCB;
+use rtic::mutex::prelude::*;
+use stm32f1xx_hal::backup_domain::BackupDomain;
+use stm32f1xx_hal::pac::{BKP, PWR, RCC};
+
+// backup register DR1, shared with the panic record of `crate::crash`. Either
+// one tells how the previous run ended, and each is cleared at the boot after.
+const MARKER_REGISTER: usize = 0;
+
+// the full USART1 buffers take 28 ms at 115200 baud, the USB serial port and the
+// probe reading RTT are faster
+pub const DRAIN_MS: u32 = 100;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    // the reset chord, the boot after it notes a `User` reset
+    Restart,
+    // the boot after it jumps to the system bootloader
+    Bootloader,
+}
+
+impl Request {
+    // in the marker register from the request until the next boot
+    fn marker(self) -> u16 {
+        match self {
+            Request::Restart => 0x0b0e,
+            Request::Bootloader => 0xb007,
+        }
+    }
+}
+
+pub fn mark(backup_domain: &BackupDomain, request: Request) {
+    backup_domain.write_data_register_low(MARKER_REGISTER, request.marker());
+}
+
+/// Adds `User` to the `causes` of a software reset that was requested. Clears
+/// the marker.
+pub fn identify(backup_domain: &BackupDomain, causes: ResetCauses) -> ResetCauses {
+    if backup_domain.read_data_register_low(MARKER_REGISTER) != Request::Restart.marker() {
+        return causes;
+    }
+    backup_domain.write_data_register_low(MARKER_REGISTER, 0);
+    match causes.contains(ResetCause::Software) {
+        true => causes.with(ResetCause::User),
+        false => causes,
+    }
+}
+
+/// Whether the previous run asked for the bootloader, on the registers ahead of
+/// the HAL. Clears the marker, so the boot after a failed jump is a normal one.
+///
+/// # Safety
+///
+/// Only for the start of `init`, nothing else may use RCC, PWR or BKP yet.
+pub unsafe fn take_bootloader() -> bool {
+    let rcc = &*RCC::ptr();
+    rcc.apb1enr
+        .modify(|_, w| w.pwren().set_bit().bkpen().set_bit());
+    let bkp = &*BKP::ptr();
+    let requested = bkp.dr[MARKER_REGISTER].read().d().bits() == Request::Bootloader.marker();
+    if requested {
+        let pwr = &*PWR::ptr();
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+        bkp.dr[MARKER_REGISTER].write(|w| w.d().bits(0));
+        pwr.cr.modify(|_, w| w.dbp().clear_bit());
+    }
+    requested
+}
+
+/// The first run gets a requested reset going, the second one resets once the
+/// logs had [`DRAIN_MS`] to get out, the body of `user_reset`.
+pub fn user_reset(ctx: user_reset::Context, request: Request, drained: bool) {
+    if drained {
+        SCB::sys_reset();
+    }
+    match request {
+        Request::Restart => log!("user requested reset"),
+        Request::Bootloader => log!("resetting into the bootloader"),
+    }
+    let mut shared = (ctx.shared.backup_domain, ctx.shared.counter);
+    shared.lock(|backup_domain, blinks| {
+        lifetime::save(backup_domain, *blinks);
+        mark(backup_domain, request);
+    });
+    logging::flush();
+    user_reset::spawn_after(timing::RESET_DRAIN, request, true).or_count();
+}
@@ -0,0 +1,83 @@
+//! The consumers of `event_router` that go by the subscriptions of the `subscribe`
+//! feature, see `keypad_core::route`. All of them take every event but for the
+//! entry mode, which grabs the keys it types with while it is on. The other keys
+//! still go to the backends and the stream then. Without the feature every event goes to every
+//! consumer.
+//!
+//! The log, the leds, the display and the counters see every event regardless.
+
+use crate::event::KeyEvent;
+#[cfg(feature = "subscribe")]
+use crate::keymap::Layers;
+#[cfg(feature = "subscribe")]
+use crate::keypad::{key_bit, KeyState, COLUMNS, ROWS};
+#[cfg(feature = "subscribe")]
+use core::cell::RefCell;
+#[cfg(feature = "subscribe")]
+use cortex_m::interrupt::{self, Mutex};
+use keypad_core::route::Receivers;
+#[cfg(feature = "subscribe")]
+use keypad_core::route::{Router, Subscription};
+
+pub const ENTRY: usize = 0;
+// the macro recorder, and the macro keys
+#[cfg(feature = "macros")]
+pub const MACROS: usize = 1;
+// the output mode's, or a bound key's
+pub const BACKEND: usize = 2;
+pub const STREAM: usize = 3;
+#[cfg(feature = "lcd")]
+pub const LCD: usize = 4;
+// the notes, the clicks stay
+#[cfg(feature = "subscribe")]
+pub const PIANO: usize = 5;
+#[cfg(feature = "subscribe")]
+const CONSUMERS: usize = 6;
+
+#[cfg(feature = "subscribe")]
+static ROUTER: Mutex<RefCell<Router<CONSUMERS>>> = Mutex::new(RefCell::new(Router::new()));
+
+pub fn route(event: &KeyEvent) -> Receivers {
+    #[cfg(feature = "subscribe")]
+    return interrupt::free(|cs| ROUTER.borrow(cs).borrow().route(event));
+    #[cfg(not(feature = "subscribe"))]
+    {
+        let _ = event;
+        Receivers::ALL
+    }
+}
+
+#[cfg(feature = "subscribe")]
+pub fn subscribe(consumer: usize, subscription: Subscription) {
+    interrupt::free(|cs| {
+        ROUTER
+            .borrow(cs)
+            .borrow_mut()
+            .subscribe(consumer, subscription)
+    });
+}
+
+/// Grabs the digits, '*' and '#' of the base layer for the entry mode, or lets
+/// go of them.
+#[cfg(feature = "subscribe")]
+pub fn grab_entry(layers: &Layers, on: bool) {
+    let keys = base_keys(layers, |key| matches!(key, '0'..='9' | '*' | '#'));
+    let subscription = match on {
+        true => Subscription::grab(keys),
+        false => Subscription::NONE,
+    };
+    subscribe(ENTRY, subscription);
+}
+
+#[cfg(feature = "subscribe")]
+fn base_keys(layers: &Layers, wanted: impl Fn(char) -> bool) -> KeyState {
+    let mut keys = KeyState(0);
+    for row in 0..ROWS {
+        for col in 0..COLUMNS {
+            if wanted(layers.base_key(row, col)) {
+                keys.0 |= key_bit(row, col);
+            }
+        }
+    }
+    keys
+}
@@ -0,0 +1,438 @@
+//! The body of `event_router`, the single receiving point of the events: the
+//! macros, the backend of the output mode, the USART1 stream, the entry mode and
+//! the other consumers of `crate::route`, the leds, the buzzer and the log.
+
+#[cfg(feature = "buzzer")]
+use crate::app::beep;
+#[cfg(feature = "lcd")]
+use crate::app::feature_job;
+#[cfg(feature = "macros")]
+use crate::app::macro_play;
+use crate::app::{
+    configure, entry_mode, event_router, led_controller, now_ms, play, send_led_message,
+    switch_output, user_reset,
+};
+#[cfg(feature = "bind")]
+use crate::bind;
+#[cfg(feature = "buzzer")]
+use crate::buzzer;
+use crate::chord;
+use crate::encoder::EncoderEvent;
+use crate::event::{EventKind, InputEvent, KeyEvent};
+#[cfg(feature = "hold-cap")]
+use crate::hold_cap;
+#[cfg(feature = "i2c-slave")]
+use crate::i2c_slave;
+#[cfg(feature = "lcd")]
+use crate::job::Job;
+use crate::joystick::JoystickEvent;
+#[cfg(feature = "key-test")]
+use crate::key_test;
+#[cfg(feature = "key-test")]
+use crate::keypad::{self, COLUMNS};
+#[cfg(feature = "key-test")]
+use crate::led_mode::LedMode;
+use crate::led_mode::{Flash, LedMessage};
+use crate::lifetime;
+#[cfg(feature = "cdc")]
+use crate::logging;
+#[cfg(any(feature = "cdc", feature = "hold-cap", feature = "text"))]
+use crate::logging::Char;
+use crate::logging::Text;
+#[cfg(feature = "macros")]
+use crate::macros::Action;
+#[cfg(feature = "midi")]
+use crate::midi;
+#[cfg(feature = "text")]
+use crate::monotonic;
+use crate::output::{OutputMode, OUTPUT_CHORD};
+#[cfg(feature = "ps2")]
+use crate::ps2;
+use crate::reset::Request;
+use crate::route;
+#[cfg(feature = "cdc")]
+use crate::serial;
+#[cfg(feature = "servo")]
+use crate::servo;
+use crate::spawn::Counted;
+#[cfg(feature = "time-sync")]
+use crate::time_sync;
+use crate::timing;
+use crate::uart;
+#[cfg(not(feature = "text"))]
+use crate::wire::{self, Message};
+#[cfg(any(feature = "cdc", feature = "text"))]
+use core::fmt::Write;
+#[cfg(feature = "bind")]
+use keypad_core::binding::Binding;
+use rtic::mutex::prelude::*;
+
+/// Takes `event` off the event queue.
+pub fn event_router(mut ctx: event_router::Context, event: InputEvent) {
+    ctx.shared.event_producer.lock(|producer| producer.taken());
+    ctx.shared.drained_at.lock(|at| *at = now_ms());
+    restart_dim_timer(ctx.local.dim_handle);
+    // the USB keyboard and the serial streams stay quiet on a faulty matrix
+    let reporting = !ctx.shared.matrix_fault.lock(|fault| *fault);
+    let output = ctx.shared.output.lock(|output| *output);
+    let streaming = reporting && output.is_stream();
+    ctx.shared.recent_events.lock(|recent| recent.write(event));
+    let event = match event {
+        // nothing but the test sees the keys
+        #[cfg(feature = "key-test")]
+        InputEvent::Key(event) if key_test::is_active() => {
+            if event.kind == EventKind::Pressed {
+                key_test_press(&event);
+            }
+            return;
+        }
+        // the keys are ignored for good while the PIN entry is locked out
+        InputEvent::Key(_) | InputEvent::Playback(_)
+            if ctx.shared.pin_lock.lock(|lock| lock.is_locked_out()) =>
+        {
+            return;
+        }
+        InputEvent::Key(event) => {
+            #[cfg(feature = "macros")]
+            let action = ctx.shared.macros.lock(|macros| {
+                if !route::route(&event).has(route::MACROS) {
+                    return Action::Pass;
+                }
+                #[cfg(feature = "bind")]
+                if let Binding::Macro(slot) = bind::of(&event) {
+                    return macros.handle_bound(usize::from(slot), &event);
+                }
+                macros.handle(&event, now_ms())
+            });
+            #[cfg(feature = "macros")]
+            match action {
+                Action::Pass => {}
+                Action::Swallow => return,
+                Action::Reject => {
+                    send_led_message(LedMessage::Flash(Flash::Reject));
+                    return;
+                }
+                Action::Play(generation) => {
+                    macro_play::spawn(generation).or_count();
+                    return;
+                }
+            }
+            ctx.shared.key_history.lock(|history| history.write(event));
+            event
+        }
+        // the macros neither record nor start on what they play
+        #[cfg(feature = "bind")]
+        InputEvent::Playback(event) if matches!(bind::of(&event), Binding::Macro(_)) => {
+            return;
+        }
+        InputEvent::Playback(event) => {
+            ctx.shared.key_history.lock(|history| history.write(event));
+            event
+        }
+        InputEvent::Joystick(event) => {
+            if streaming {
+                stream_joystick(&event);
+            }
+            log!("joystick {:?}", event);
+            return;
+        }
+        InputEvent::QueueOverflow => {
+            log!("events lost, the queue was full");
+            return;
+        }
+        // the key is still down for everything but the backend
+        #[cfg(feature = "hold-cap")]
+        InputEvent::HoldCapped(release) => {
+            log!("key {} released past the hold cap", Char(release.key));
+            if reporting && output == OutputMode::Hid {
+                ctx.shared.usb.lock(|usb| usb.handle(&release));
+            }
+            #[cfg(feature = "media")]
+            if reporting && output == OutputMode::Media {
+                ctx.shared.usb.lock(|usb| usb.handle_media(&release));
+            }
+            #[cfg(feature = "midi")]
+            if reporting && output == OutputMode::Midi {
+                if let Some(message) = midi::handle(&release) {
+                    uart::write_bytes(&message);
+                }
+            }
+            return;
+        }
+        InputEvent::Button(event) => {
+            if event.pressed {
+                log!("button {} pressed", event.id);
+            }
+            return;
+        }
+        InputEvent::EntryComplete(value) => {
+            if streaming {
+                stream_entry(value);
+            }
+            log!("entered {}", value);
+            return;
+        }
+        // `key_listener` took the keys from the other events
+        InputEvent::Config(input) => {
+            if configure::spawn(input).is_err() {
+                log!("config busy, key dropped");
+            }
+            return;
+        }
+        InputEvent::AccessGranted => {
+            if streaming {
+                stream_access_granted();
+            }
+            log!("access granted");
+            return;
+        }
+        InputEvent::Encoder(event) => {
+            if streaming {
+                stream_encoder(&event);
+            }
+            log!("encoder {:?}", event);
+            return;
+        }
+    };
+    #[cfg(feature = "time-sync")]
+    let event = time_sync::restamp(event);
+    if event.kind == EventKind::Pressed {
+        lifetime::count_keypress();
+        send_led_message(LedMessage::Flash(Flash::Key));
+        #[cfg(feature = "morse")]
+        send_led_message(LedMessage::Morse(event.key));
+    }
+    ctx.shared.key_stats.lock(|stats| stats.record(&event));
+    let receivers = route::route(&event);
+    // the entry mode takes over the serial stream, or only its keys with
+    // the subscriptions
+    let entering = receivers.has(route::ENTRY) && ctx.shared.entry.lock(|entry| entry.is_enabled());
+    if entering && entry_mode::spawn(event).is_err() {
+        log!("entry busy, key dropped");
+    }
+    // a bound key goes to its own backend rather than the output mode's
+    #[cfg(feature = "bind")]
+    let (output, streaming) = match bind::of(&event) {
+        Binding::Key => (output, streaming),
+        Binding::Hid(_) => (OutputMode::Hid, false),
+        #[cfg(feature = "midi")]
+        Binding::Midi(_) => (OutputMode::Midi, false),
+        _ => (OutputMode::Silent, false),
+    };
+    // the backend goes without the rest of a key the cap released
+    #[cfg(feature = "hold-cap")]
+    let backend = reporting && hold_cap::pass(output, &event, now_ms());
+    #[cfg(not(feature = "hold-cap"))]
+    let backend = reporting;
+    let backend = backend && receivers.has(route::BACKEND);
+    if backend && output == OutputMode::Hid {
+        ctx.shared.usb.lock(|usb| usb.handle(&event));
+    }
+    #[cfg(feature = "media")]
+    if backend && output == OutputMode::Media {
+        ctx.shared.usb.lock(|usb| usb.handle_media(&event));
+    }
+    #[cfg(feature = "midi")]
+    if backend && output == OutputMode::Midi {
+        if let Some(message) = midi::handle(&event) {
+            uart::write_bytes(&message);
+        }
+    }
+    if streaming && !entering && receivers.has(route::STREAM) {
+        stream_event(&event);
+    }
+    if event.kind == EventKind::Chord(OUTPUT_CHORD) {
+        let mut shared = (&mut ctx.shared.output, &mut ctx.shared.usb);
+        shared.lock(|output, usb| switch_output(output, usb, output.step(true)));
+    }
+    if event.kind == EventKind::ChordHeld(chord::RESET_CHORD) {
+        user_reset::spawn(Request::Restart, false).or_count();
+    }
+    ctx.shared.display_model.lock(|model| model.record(&event));
+    ctx.shared
+        .backlight
+        .lock(|backlight| backlight.record(&event, now_ms()));
+    ctx.shared.rtt_events.lock(|channel| channel.write(&event));
+    #[cfg(feature = "i2c-slave")]
+    i2c_slave::push_event(&event);
+    #[cfg(feature = "ps2")]
+    if reporting {
+        ps2::push_event(&event);
+    }
+    #[cfg(feature = "servo")]
+    if reporting {
+        servo::handle(&event);
+    }
+
+    let note = ctx.shared.piano.lock(|piano| match event.kind {
+        EventKind::Chord(chord::PIANO_CHORD) => {
+            let enabled = !piano.is_enabled();
+            log!("piano mode {}", Text(if enabled { "on" } else { "off" }));
+            piano.set_enabled(enabled)
+        }
+        #[cfg(feature = "subscribe")]
+        _ if !receivers.has(route::PIANO) => None,
+        _ => piano.track(&event),
+    });
+    if let Some(note) = note {
+        ctx.shared.buzzer.lock(|buzzer| play(buzzer, note));
+    }
+    // the notes replace the clicks
+    #[cfg(feature = "buzzer")]
+    if !ctx.shared.piano.lock(|piano| piano.is_enabled()) {
+        match event.kind {
+            EventKind::Pressed => beep(buzzer::PRESS_CLICK),
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => beep(buzzer::RELEASE_BLIP),
+            _ => {}
+        }
+    }
+    // a redraw still pending shows this key as well
+    #[cfg(feature = "lcd")]
+    if receivers.has(route::LCD) && ctx.shared.lcd.lock(|lcd| lcd.handle(&event)) {
+        let _ = feature_job::spawn(Job::LcdRender);
+    }
+
+    #[cfg(feature = "cdc")]
+    if reporting {
+        let mut line = logging::Line::new();
+        let edge = match event.kind {
+            EventKind::Pressed => Some('P'),
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => Some('R'),
+            _ => None,
+        };
+        if let Some(edge) = edge {
+            let _ = write!(line, "{} {} {}", Char(edge), event.row, event.col);
+            serial::write_line(&line);
+        }
+    }
+
+    log!("event {}", event);
+    if event.kind == EventKind::TooManyKeys {
+        send_led_message(LedMessage::Flash(Flash::Reject));
+    }
+}
+
+// every run takes events off the queue, a timer that already went off has
+// dimmed the leds
+fn restart_dim_timer(handle: &mut Option<led_controller::SpawnHandle>) {
+    if handle.take().is_none_or(|handle| handle.cancel().is_err()) {
+        send_led_message(LedMessage::Dim(false));
+    }
+    let after = timing::DIM_AFTER;
+    *handle = led_controller::spawn_after(after, LedMessage::Dim(true)).ok();
+}
+
+// shows the key, or restarts once the last one of the matrix was pressed
+#[cfg(feature = "key-test")]
+fn key_test_press(event: &KeyEvent) {
+    if key_test::seen() as usize == keypad::KEYS {
+        return;
+    }
+    let index = usize::from(event.row) * COLUMNS + usize::from(event.col);
+    let passed = key_test::press(index);
+    log!(
+        "key test: key {}, {} of {} seen",
+        index,
+        key_test::seen(),
+        keypad::KEYS
+    );
+    if passed {
+        send_led_message(LedMessage::Enter(LedMode::KeyTest(key_test::PASSED)));
+        user_reset::spawn_after(timing::KEY_TEST_PASSED, Request::Restart, false).or_count();
+    } else {
+        // the index on its pad, the leds have 4 bits
+        let shown = usize::from(event.row) * keypad::PAD_COLUMNS
+            + usize::from(event.col) % keypad::PAD_COLUMNS;
+        send_led_message(LedMessage::Enter(LedMode::KeyTest(shown as u8)));
+    }
+}
+
+// USART1 stream, `KEY <char> DOWN|UP <timestamp_ms> <time> <modifiers>` lines
+#[cfg(feature = "text")]
+fn stream_event(event: &KeyEvent) {
+    let direction = match event.kind {
+        EventKind::Pressed => "DOWN",
+        EventKind::Released { .. } | EventKind::ReleasedAfterLong => "UP",
+        _ => return,
+    };
+    let mut line = heapless::String::<48>::new();
+    let _ = write!(
+        line,
+        "KEY {} {} {} {} {}",
+        Char(event.key),
+        Text(direction),
+        monotonic::to_ms(event.at),
+        event.time,
+        event.modifiers.0
+    );
+    uart::write_line(&line);
+}
+
+// USART1 stream, one frame per event
+#[cfg(not(feature = "text"))]
+fn stream_event(event: &KeyEvent) {
+    let mut frame = [0; wire::MAX_EVENT_FRAME];
+    let len = wire::encode_event(event, &mut frame);
+    uart::write_frame(&frame[..len]);
+}
+
+// USART1 stream, `ENC CW|CCW|PUSH <timestamp_ms>` lines
+#[cfg(feature = "text")]
+fn stream_encoder(event: &EncoderEvent) {
+    let name = match event {
+        EncoderEvent::Clockwise => "CW",
+        EncoderEvent::CounterClockwise => "CCW",
+        EncoderEvent::Pressed => "PUSH",
+    };
+    let mut line = heapless::String::<32>::new();
+    let _ = write!(line, "ENC {} {}", Text(name), now_ms());
+    uart::write_line(&line);
+}
+
+#[cfg(not(feature = "text"))]
+fn stream_encoder(event: &EncoderEvent) {
+    wire::send(&Message::Encoder(*event));
+}
+
+// USART1 stream, `JOY UP|DOWN|LEFT|RIGHT <timestamp_ms>` lines
+#[cfg(feature = "text")]
+fn stream_joystick(event: &JoystickEvent) {
+    let name = match event {
+        JoystickEvent::Up => "UP",
+        JoystickEvent::Down => "DOWN",
+        JoystickEvent::Left => "LEFT",
+        JoystickEvent::Right => "RIGHT",
+    };
+    let mut line = heapless::String::<32>::new();
+    let _ = write!(line, "JOY {} {}", Text(name), now_ms());
+    uart::write_line(&line);
+}
+
+#[cfg(not(feature = "text"))]
+fn stream_joystick(event: &JoystickEvent) {
+    wire::send(&Message::Joystick(*event));
+}
+
+// USART1 stream, `ENTRY <value>` lines
+#[cfg(feature = "text")]
+fn stream_entry(value: u32) {
+    let mut line = heapless::String::<16>::new();
+    let _ = write!(line, "ENTRY {}", value);
+    uart::write_line(&line);
+}
+
+#[cfg(not(feature = "text"))]
+fn stream_entry(value: u32) {
+    wire::send(&Message::Entry(value));
+}
+
+// USART1 stream, an `ACCESS GRANTED` line
+#[cfg(feature = "text")]
+fn stream_access_granted() {
+    uart::write_line("ACCESS GRANTED");
+}
+
+#[cfg(not(feature = "text"))]
+fn stream_access_granted() {
+    wire::send(&Message::AccessGranted);
+}
@@ -0,0 +1,236 @@
+//! The RTC, on the 32.768 kHz LSE crystal or on the LSI where none is fitted. It
+//! counts at [`HZ`] so its alarm can feed the IWDG in STOP, see `crate::sleep`.
+//! The wall clock is that count in seconds on top of a multiple of 2^16 s kept
+//! in the backup register DR10. `carry` moves whole multiples over to keep the
+//! counter far from its wrap, it would wrap after ~48 days.
+//!
+//! The clock source is picked once per backup domain and kept over resets. After
+//! a power loss cleared it, the LSE gets [`LSE_STARTUP_MS`] to start, polled by
+//! a task, and the LSI takes over for good if it doesn't. The counter stands
+//! still until then.
+//!
+//! The counter registers follow the RTC clock: once the APB1 clock stopped, at
+//! a reset or in STOP, they can't be read before RSF is set again, and the two
+//! halves are reread if the high one changed in between.
+
+use crate::app::rtc_start;
+use crate::spawn::Counted;
+use crate::timing;
+use rtic::Mutex;
+use stm32f1xx_hal::backup_domain::BackupDomain;
+use stm32f1xx_hal::pac::rcc::bdcr::RTCSEL_A;
+use stm32f1xx_hal::pac::{BKP, RCC, RTC};
+
+pub const HZ: u32 = 1024;
+// typical start up time of the LSE in the datasheet
+pub const LSE_STARTUP_MS: u32 = 3000;
+pub const LSE_POLL_MS: u32 = 100;
+
+const LSE_HZ: u32 = 32_768;
+// nominal, the LSI is off by up to 50% anyway
+const LSI_HZ: u32 = 40_000;
+
+// backup register DR10, the wall clock in units of 2^16 s
+const EPOCH_REGISTER: usize = 9;
+const EPOCH_SHIFT: u32 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Lse,
+    Lsi,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Lse => "LSE",
+            Source::Lsi => "LSI",
+        }
+    }
+}
+
+pub struct Rtc {
+    regs: RTC,
+    source: Option<Source>,
+}
+
+impl Rtc {
+    /// Takes up the source picked on an earlier boot, or starts the LSE if there
+    /// is none, see [`Rtc::start`]. The backup domain has to be writable.
+    pub fn new(regs: RTC, _backup_domain: &BackupDomain) -> Self {
+        // SAFETY: only this driver touches the LSI and the backup domain control
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.csr.modify(|_, w| w.lsion().set_bit());
+        let mut rtc = Self { regs, source: None };
+        match rcc.bdcr.read().rtcsel().variant() {
+            RTCSEL_A::NoClock => rcc.bdcr.modify(|_, w| w.lseon().set_bit()),
+            RTCSEL_A::Lse => rtc.select(Source::Lse),
+            // HSE / 128 is never picked
+            _ => rtc.select(Source::Lsi),
+        }
+        rtc
+    }
+
+    /// The source the RTC runs on, `None` while the LSE is still starting.
+    pub fn source(&self) -> Option<Source> {
+        self.source
+    }
+
+    /// Picks the LSE once it runs, or the LSI with `give_up`. Returns the source
+    /// picked, now or before.
+    pub fn start(&mut self, give_up: bool) -> Option<Source> {
+        if self.source.is_none() {
+            let rcc = unsafe { &*RCC::ptr() };
+            if rcc.bdcr.read().lserdy().is_ready() {
+                self.select(Source::Lse);
+            } else if give_up {
+                rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+                self.select(Source::Lsi);
+            }
+        }
+        self.source
+    }
+
+    fn select(&mut self, source: Source) {
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.bdcr.modify(|_, w| {
+            match source {
+                Source::Lse => w.rtcsel().lse(),
+                Source::Lsi => w.rtcsel().lsi(),
+            };
+            w.rtcen().set_bit()
+        });
+        // LSI / 39 is off by 0.2%, well inside the spread of the LSI
+        let prescaler = match source {
+            Source::Lse => LSE_HZ / HZ,
+            Source::Lsi => LSI_HZ / HZ,
+        } - 1;
+        self.configure(|regs| {
+            regs.prlh.write(|w| unsafe { w.bits(prescaler >> 16) });
+            regs.prll.write(|w| unsafe { w.bits(prescaler & 0xffff) });
+        });
+        self.source = Some(source);
+        self.sync();
+    }
+
+    /// Waits until the counter registers caught up with the RTC, needed after
+    /// STOP.
+    pub fn sync(&self) {
+        if self.source.is_some() {
+            self.regs.crl.modify(|_, w| w.rsf().clear_bit());
+            while self.regs.crl.read().rsf().bit_is_clear() {}
+        }
+    }
+
+    /// Sets the wall clock to `seconds`, usually since the Unix epoch. `false`
+    /// while the LSE is still starting.
+    pub fn set_time(&mut self, seconds: u32) -> bool {
+        if self.source.is_none() {
+            return false;
+        }
+        cortex_m::interrupt::free(|_| {
+            set_epoch((seconds >> EPOCH_SHIFT) as u16);
+            self.set_counts((seconds & 0xffff) * HZ);
+        });
+        true
+    }
+
+    /// Moves whole multiples of 2^16 s from the counter to DR10, called often
+    /// enough the counter never wraps.
+    pub fn carry(&mut self) {
+        if self.source.is_none() {
+            return;
+        }
+        cortex_m::interrupt::free(|_| {
+            let counts = counts();
+            let carried = (counts / HZ) >> EPOCH_SHIFT;
+            if carried > 0 {
+                set_epoch(epoch().wrapping_add(carried as u16));
+                // the ticks until the write lands are lost, a few milliseconds
+                self.set_counts(counts - (carried << EPOCH_SHIFT) * HZ);
+            }
+        });
+    }
+
+    pub fn counts(&self) -> u32 {
+        counts()
+    }
+
+    /// Fires the alarm once the counter reaches `counts`, clears a fired alarm.
+    pub fn set_alarm(&mut self, counts: u32) {
+        let alarm = counts.wrapping_sub(1);
+        self.configure(|regs| {
+            regs.alrh.write(|w| w.alrh().bits((alarm >> 16) as u16));
+            regs.alrl.write(|w| w.alrl().bits(alarm as u16));
+        });
+        self.clear_alarm_flag();
+    }
+
+    pub fn listen_alarm(&mut self) {
+        self.configure(|regs| regs.crh.modify(|_, w| w.alrie().set_bit()));
+    }
+
+    pub fn unlisten_alarm(&mut self) {
+        self.configure(|regs| regs.crh.modify(|_, w| w.alrie().clear_bit()));
+    }
+
+    pub fn clear_alarm_flag(&mut self) {
+        self.configure(|regs| regs.crl.modify(|_, w| w.alrf().clear_bit()));
+    }
+
+    fn set_counts(&mut self, counts: u32) {
+        self.configure(|regs| {
+            regs.cnth.write(|w| unsafe { w.bits(counts >> 16) });
+            regs.cntl.write(|w| unsafe { w.bits(counts & 0xffff) });
+        });
+    }
+
+    // the writes land once the configuration mode is left and RTOFF is set again
+    fn configure(&mut self, write: impl FnOnce(&RTC)) {
+        while self.regs.crl.read().rtoff().bit_is_clear() {}
+        self.regs.crl.modify(|_, w| w.cnf().set_bit());
+        write(&self.regs);
+        self.regs.crl.modify(|_, w| w.cnf().clear_bit());
+        while self.regs.crl.read().rtoff().bit_is_clear() {}
+    }
+}
+
+/// Wall clock seconds, from any priority. Zero based until `TIME SET`, from the
+/// last loss of the backup domain.
+pub fn seconds() -> u32 {
+    cortex_m::interrupt::free(|_| (u32::from(epoch()) << EPOCH_SHIFT) + counts() / HZ)
+}
+
+// SAFETY for the register accesses below: reads only, but for DR10, which only
+// `Rtc` writes, in a critical section
+fn counts() -> u32 {
+    let regs = unsafe { &*RTC::ptr() };
+    loop {
+        let high = regs.cnth.read().bits();
+        let low = regs.cntl.read().bits();
+        if regs.cnth.read().bits() == high {
+            return high << 16 | low;
+        }
+    }
+}
+
+fn epoch() -> u16 {
+    unsafe { (*BKP::ptr()).dr[EPOCH_REGISTER].read().d().bits() }
+}
+
+fn set_epoch(epoch: u16) {
+    unsafe { (*BKP::ptr()).dr[EPOCH_REGISTER].write(|w| w.d().bits(epoch)) };
+}
+
+/// Polls the LSE after a power loss, the body of `rtc_start`.
+pub fn rtc_start(mut ctx: rtc_start::Context, waited_ms: u32) {
+    let give_up = waited_ms >= LSE_STARTUP_MS;
+    match ctx.shared.rtc.lock(|rtc| rtc.start(give_up)) {
+        Some(Source::Lse) => log!("rtc on LSE after {} ms", waited_ms),
+        Some(Source::Lsi) => log!("rtc on LSI, the LSE didn't start"),
+        None => {
+            let next = waited_ms + LSE_POLL_MS;
+            rtc_start::spawn_after(timing::LSE_POLL, next).or_count();
+        }
+    }
+}
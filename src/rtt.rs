@@ -0,0 +1,126 @@
+//! Binary key events on RTT up-channel 1, next to the text log on channel 0.
+//!
+//! Every event is one [`EVENT_RECORD_LEN`] byte record, little-endian:
+//!
+//! * magic byte [`EVENT_MAGIC`]
+//! * row, column, kind ([`crate::event::EventKind::code`]) and chord or sequence
+//!   id (0 for other kinds)
+//! * the key as a `u32` code point
+//! * [`crate::event::KeyEvent::at`] as a `u64`
+//! * the bits of [`crate::event::KeyEvent::modifiers`]
+//!
+//! The channel never blocks: a record that doesn't fit is dropped whole and
+//! counted. With the `itm` feature the records go out on an ITM stimulus port
+//! instead, see `crate::itm`, without either nothing is sent.
+//!
+//! Down-channel 0 is a console taking the same command lines as the UART, see
+//! [`crate::command::parse`]. The replies go to the text log.
+
+#[cfg(feature = "rtt")]
+use crate::command::LineReader;
+use crate::command::{Command, CommandError};
+#[cfg(any(feature = "rtt", feature = "itm"))]
+use crate::event::EventKind;
+use crate::event::KeyEvent;
+#[cfg(feature = "rtt")]
+use rtt_target::{DownChannel, UpChannel};
+
+#[cfg(any(feature = "rtt", feature = "itm"))]
+pub const EVENT_MAGIC: u8 = 0xa5;
+#[cfg(any(feature = "rtt", feature = "itm"))]
+pub const EVENT_RECORD_LEN: usize = 18;
+
+#[cfg(any(feature = "rtt", feature = "itm"))]
+pub fn encode(event: &KeyEvent) -> [u8; EVENT_RECORD_LEN] {
+    let id = match event.kind {
+        EventKind::Chord(id) | EventKind::SequenceMatched(id) | EventKind::ChordHeld(id) => id,
+        _ => 0,
+    };
+    let mut record = [0; EVENT_RECORD_LEN];
+    record[..5].copy_from_slice(&[EVENT_MAGIC, event.row, event.col, event.kind.code(), id]);
+    record[5..9].copy_from_slice(&u32::from(event.key).to_le_bytes());
+    record[9..17].copy_from_slice(&event.at.to_le_bytes());
+    record[17] = event.modifiers.0;
+    record
+}
+
+pub struct EventChannel {
+    #[cfg(feature = "rtt")]
+    channel: UpChannel,
+    dropped: u32,
+}
+
+impl EventChannel {
+    /// Takes an up-channel set up in `NoBlockSkip` mode.
+    #[cfg(feature = "rtt")]
+    pub fn new(channel: UpChannel) -> Self {
+        Self {
+            channel,
+            dropped: 0,
+        }
+    }
+
+    #[cfg(not(feature = "rtt"))]
+    pub const fn new() -> Self {
+        Self { dropped: 0 }
+    }
+
+    pub fn write(&mut self, event: &KeyEvent) {
+        #[cfg(feature = "rtt")]
+        if self.channel.write(&encode(event)) < EVENT_RECORD_LEN {
+            self.dropped += 1;
+        }
+        #[cfg(feature = "itm")]
+        crate::itm::write_event(event);
+        #[cfg(not(any(feature = "rtt", feature = "itm")))]
+        let _ = event;
+    }
+
+    /// Records dropped because the host didn't keep up.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+/// Command lines typed into the RTT console.
+pub struct Console {
+    #[cfg(feature = "rtt")]
+    channel: DownChannel,
+    #[cfg(feature = "rtt")]
+    reader: LineReader,
+}
+
+impl Console {
+    #[cfg(feature = "rtt")]
+    pub fn new(channel: DownChannel) -> Self {
+        Self {
+            channel,
+            reader: LineReader::new(),
+        }
+    }
+
+    #[cfg(not(feature = "rtt"))]
+    pub const fn new() -> Self {
+        Self {}
+    }
+
+    /// Drains the down-channel and calls `handle` for every complete line, the
+    /// start of an unfinished line waits for the next poll.
+    pub fn poll(&mut self, mut handle: impl FnMut(Result<Command, CommandError>)) {
+        #[cfg(feature = "rtt")]
+        loop {
+            let mut buffer = [0; 16];
+            let len = self.channel.read(&mut buffer);
+            if len == 0 {
+                break;
+            }
+            for &byte in &buffer[..len] {
+                if let Some(command) = self.reader.feed(byte) {
+                    handle(command);
+                }
+            }
+        }
+        #[cfg(not(feature = "rtt"))]
+        let _ = &mut handle;
+    }
+}
@@ -0,0 +1,506 @@
+//! The body of `key_listener`, a run of the scanner: the park and the presence
+//! check of the matrix, the scan itself and the STOP once the pad is idle.
+//!
+//! Every scan goes through the pipeline of `keypad_core::engine`, with what the
+//! firmware adds to it in [`ScanHooks`]: the key limit, the key that woke the
+//! scanner, the keys of the config mode, the locks and the layers, and the
+//! sequences of the events on their way to the event queue.
+
+#[cfg(feature = "dump")]
+use crate::app::feature_job;
+#[cfg(all(not(feature = "mcp23017"), not(feature = "debug-idle")))]
+use crate::app::stop_timer;
+use crate::app::{
+    clear_error, key_listener, monotonics, now_ms, now_u64, raise_error, send_led_message,
+};
+#[cfg(feature = "debug-bounce")]
+use crate::bounce;
+#[cfg(feature = "config-mode")]
+use crate::config_mode::{self, ConfigInput, ConfigKeys};
+#[cfg(feature = "dump")]
+use crate::dump::{self, Snapshot};
+use crate::encoder::EncoderEvent;
+use crate::event::{EventKind, EventProducer, InputEvent, KeyEvent};
+#[cfg(feature = "hold-cap")]
+use crate::hold_cap;
+#[cfg(feature = "i2c-slave")]
+use crate::i2c_slave;
+use crate::input;
+#[cfg(feature = "dump")]
+use crate::job::Job;
+#[cfg(feature = "key-debounce")]
+use crate::key_debounce;
+use crate::keymap::{Layers, LOCKS, SEQUENCES};
+use crate::keypad::{self, Filtered, KeyState, Matrix, PressLimit, SelfTest, COLUMNS, ROWS};
+use crate::led_mode::{ErrorCode, LedMessage};
+use crate::modifiers::{self, Modifiers};
+use crate::monotonic;
+use crate::profile::{self, Stopwatch};
+#[cfg(feature = "relays")]
+use crate::relays;
+use crate::rtc;
+use crate::sleep::WakeMailbox;
+#[cfg(not(feature = "mcp23017"))]
+use crate::sleep::{self, ScanMode};
+use crate::spawn::Counted;
+use crate::timing;
+#[cfg(feature = "velocity")]
+use crate::velocity;
+#[cfg(feature = "debug-inject")]
+use crate::watchdog;
+use keypad_core::engine::Hooks;
+use keypad_core::sequence::SequenceDetector;
+use keypad_core::wake::WokenKey;
+use rtic::mutex::prelude::*;
+
+pub use keypad_core::engine::EventEngine;
+
+/// The detector of the sequences of `keymap.toml`.
+pub type Sequences = SequenceDetector<{ SEQUENCES.len() }>;
+
+/// The hooks of a frame, out of the locals of the scanner and the resources it
+/// locks for the frame.
+pub struct ScanHooks<'a> {
+    pub producer: &'a mut EventProducer,
+    pub layers: &'a mut Layers,
+    pub modifiers: &'a mut Modifiers,
+    pub wake: &'a mut WakeMailbox,
+    // the keys `INJECT` holds
+    pub injected: u32,
+    pub press_limit: &'a mut PressLimit,
+    #[cfg(feature = "config-mode")]
+    pub config_keys: &'a mut ConfigKeys,
+    pub sequences: &'a mut Sequences,
+    // the failed keys of a new fault, reported ahead of the edges
+    pub fault: Option<u32>,
+    pub now: u32,
+    // wall clock seconds of the scan
+    pub time: u32,
+    // started ahead of the scan, read once it is in
+    pub stopwatch: Stopwatch,
+    pub scan_cycles: Option<u32>,
+    // the keys of the scan, the injected ones with them
+    pub raw: u32,
+    // whether the locks changed, or the config mode was entered
+    pub locks_changed: bool,
+    // the relay keys only switch on a sound matrix
+    #[cfg(feature = "relays")]
+    pub matrix_fault: bool,
+    // set by the frame for its edges
+    pub woken: Option<WokenKey>,
+}
+
+impl Hooks for ScanHooks<'_> {
+    fn emit(&mut self, mut event: KeyEvent) {
+        event.time = self.time;
+        event.modifiers = *self.modifiers;
+        let sequence = self.sequences.track(&event, self.now);
+        self.producer.push(event);
+        #[cfg(feature = "config-mode")]
+        if sequence == Some(config_mode::CONFIG_SEQUENCE) && !config_mode::is_active() {
+            config_mode::enter();
+            self.producer.push(InputEvent::Config(ConfigInput::Entered));
+        }
+        if let Some(id) = sequence {
+            self.producer.push(KeyEvent {
+                kind: EventKind::SequenceMatched(id),
+                ..event
+            });
+        }
+    }
+
+    fn frame(
+        &mut self,
+        state: KeyState,
+        changes: (KeyState, KeyState),
+        now: u32,
+    ) -> (KeyState, KeyState) {
+        #[cfg(not(any(feature = "config-mode", feature = "relays")))]
+        let _ = now;
+        // past the limit the presses end here, the event goes out with the others
+        let (changes, limit_event) = self.press_limit.filter(state, changes);
+        // the press the row line woke the scanner for gets the tick of its edge
+        self.woken = self.wake.merge(changes.0);
+        keypad::publish_key_state(state);
+        #[cfg(feature = "i2c-slave")]
+        if !(changes.0 | changes.1).is_empty() {
+            i2c_slave::set_keys(state);
+        }
+        // the config mode takes its keys ahead of the locks and layers
+        #[cfg(feature = "config-mode")]
+        let (changes, config_keys) = self.config_keys.divert(changes);
+        // the relay keys go by position, ahead of the locks and layers too
+        #[cfg(feature = "relays")]
+        let changes = relays::take(changes, self.matrix_fault, now);
+        // the lock keys end here, the other keys carry the locks in their events
+        let before = *self.modifiers;
+        let changes = self.modifiers.apply(changes, &LOCKS);
+        self.locks_changed = *self.modifiers != before;
+        // 'A' toggled a lock on its way to the config mode
+        #[cfg(feature = "config-mode")]
+        {
+            if let Some(locks) = self.config_keys.hold(state, before, now) {
+                *self.modifiers = locks;
+                self.locks_changed = true;
+                self.producer.push(InputEvent::Config(ConfigInput::Entered));
+            }
+            for (row, col) in config_keys.iter_pressed() {
+                let key = self.layers.base_key(usize::from(row), usize::from(col));
+                self.producer
+                    .push(InputEvent::Config(ConfigInput::Key(key)));
+            }
+        }
+        if let Some(failed) = self.fault.take() {
+            self.emit(KeyEvent::new(
+                KeyState(failed).first(),
+                EventKind::KeypadFault,
+            ));
+        }
+        if let Some(event) = limit_event {
+            self.emit(event);
+        }
+        changes
+    }
+
+    fn scanned(&mut self, raw: u32, _now: u32) -> u32 {
+        self.scan_cycles = Some(self.stopwatch.cycles());
+        self.raw = raw | self.injected;
+        self.raw
+    }
+
+    fn edge(&mut self, mut event: KeyEvent) -> Option<KeyEvent> {
+        if let Some(woken) = self.woken {
+            woken.backdate(&mut event);
+        }
+        #[cfg(feature = "velocity")]
+        velocity::attach(&mut event);
+        self.layers.resolve(event)
+    }
+}
+
+// runs of the scanner per scan period
+const SCAN_STEPS: u32 = if cfg!(feature = "sliced-scan") {
+    COLUMNS as u32
+} else {
+    1
+};
+
+/// A run of the scanner, due at `deadline`.
+pub fn key_listener(mut ctx: key_listener::Context, deadline: monotonic::Instant) {
+    if let Some(late) = monotonics::now().checked_duration_since(deadline) {
+        let late_us = late.ticks() as u32;
+        ctx.shared
+            .scan_lateness
+            .lock(|lateness| lateness.record(late_us));
+    }
+    // `WDG TEST` ends the chain of runs here
+    #[cfg(feature = "debug-inject")]
+    if watchdog::scanner_stalled() {
+        return;
+    }
+    let now = now_ms();
+    ctx.shared.scan_seq.lock(|seq| *seq = seq.wrapping_add(1));
+    let scan_period = ctx.shared.scan_period_ms.lock(|period| *period);
+    // read whether the matrix is parked or not
+    ctx.local.buttons.poll(|event| {
+        let event = match event.id {
+            input::ENCODER_SWITCH if event.pressed => EncoderEvent::Pressed.into(),
+            input::ENCODER_SWITCH => return,
+            _ => InputEvent::Button(event),
+        };
+        ctx.shared
+            .event_producer
+            .lock(|producer| producer.push(event));
+    });
+    let halted = ctx.shared.emergency.lock(|emergency| *emergency)
+        || ctx.shared.scan_paused.lock(|paused| *paused);
+    let mut settling = false;
+    if halted != *ctx.local.parked {
+        let switched = if halted {
+            ctx.local.engine.scanner_mut().park()
+        } else {
+            ctx.local.engine.scanner_mut().unpark()
+        };
+        // retried on the next scan if the matrix can't be reached
+        if switched.is_ok() {
+            *ctx.local.parked = halted;
+            settling = !halted;
+        }
+    }
+    // the first scan after unparking waits a period for the lines to settle
+    if halted || *ctx.local.parked || settling {
+        // the settling one is left to the next scan
+        #[cfg(feature = "dump")]
+        if !settling {
+            if let Some(reply_to) = dump::take_request() {
+                feature_job::spawn(Job::Dump(reply_to, Err("ERR parked"))).or_count();
+            }
+        }
+        *ctx.local.idle_since = now;
+        *ctx.local.empty_scans = 0;
+        ctx.local.sliced.restart();
+        schedule_scan(deadline, scan_period);
+        return;
+    }
+    // `BOUNCE` keeps the column of its key driven, see `crate::bounce`
+    #[cfg(feature = "debug-bounce")]
+    if bounce::poll(|col| {
+        let _ = ctx.local.engine.scanner_mut().select_column(col);
+    }) {
+        *ctx.local.idle_since = now;
+        *ctx.local.empty_scans = 0;
+        ctx.local.sliced.restart();
+        schedule_scan(deadline, scan_period);
+        return;
+    }
+    // a row reading active with no column driven can only be a short or a
+    // floating line. A cable pulled out with no key held reads like an idle pad
+    // though, the pulls keep the rows inactive.
+    if now.wrapping_sub(*ctx.local.presence_checked_at)
+        >= const { timing::ms(timing::PRESENCE_CHECK) }
+    {
+        *ctx.local.presence_checked_at = now;
+        // the self-test drives the columns itself, a sliced frame starts over
+        ctx.local.sliced.restart();
+        // an unreachable matrix is left to the scan below
+        if let Some(test) = presence_test(ctx.local.engine.scanner_mut()) {
+            let fault = test.hard_fault();
+            let was = ctx
+                .shared
+                .matrix_fault
+                .lock(|faulty| core::mem::replace(faulty, fault));
+            if fault && !was {
+                raise_error(ErrorCode::MatrixFault);
+                log!("shorted rows {:04b}", test.shorted_rows);
+                *ctx.local.fault_pending = Some(test.failed());
+            } else if was && !fault {
+                clear_error(ErrorCode::MatrixFault);
+            }
+        }
+    }
+    // a column per run, the rest of the scan once the last one is in
+    #[cfg(feature = "sliced-scan")]
+    let stopwatch = Stopwatch::start();
+    #[cfg(feature = "sliced-scan")]
+    let Some(sliced) = ctx
+        .local
+        .sliced
+        .step(ctx.local.engine.scanner_mut())
+        .transpose()
+    else {
+        ctx.local.scan_timing.record(stopwatch.cycles());
+        schedule_scan(deadline, scan_period);
+        return;
+    };
+    ctx.shared
+        .scans
+        .lock(|scans| *scans = scans.wrapping_add(1));
+
+    let thresholds = ctx.shared.debounce.lock(|debounce| *debounce);
+    let local = ctx.local;
+    local.engine.set_thresholds(thresholds);
+    #[cfg(feature = "key-debounce")]
+    local.engine.set_key_thresholds(&key_debounce::table());
+    #[cfg(feature = "relays")]
+    let matrix_fault = ctx.shared.matrix_fault.lock(|fault| *fault);
+    let mut shared = (
+        ctx.shared.event_producer,
+        ctx.shared.layers,
+        ctx.shared.modifiers,
+        &mut ctx.shared.wake,
+    );
+    #[cfg(not(feature = "sliced-scan"))]
+    let stopwatch = Stopwatch::start();
+    let injected = ctx.shared.injected.lock(|injected| injected.poll(now));
+    let at = now_u64();
+    let (scanned, scan_cycles, raw, locks_changed, locks) =
+        shared.lock(|producer, layers, modifiers, wake| {
+            let mut hooks = ScanHooks {
+                producer,
+                layers,
+                modifiers,
+                wake,
+                injected,
+                press_limit: local.press_limit,
+                #[cfg(feature = "config-mode")]
+                config_keys: local.config_keys,
+                sequences: local.sequences,
+                fault: local.fault_pending.take(),
+                now,
+                time: rtc::seconds(),
+                stopwatch,
+                scan_cycles: None,
+                raw: 0,
+                locks_changed: false,
+                #[cfg(feature = "relays")]
+                matrix_fault,
+                woken: None,
+            };
+            #[cfg(not(feature = "sliced-scan"))]
+            let scanned = local.engine.scan(now, at, &mut hooks);
+            #[cfg(feature = "sliced-scan")]
+            let scanned = sliced.map(|raw| {
+                let raw = hooks.scanned(raw, now);
+                local.engine.update(raw, now, at, &mut hooks)
+            });
+            #[cfg(feature = "hold-cap")]
+            if scanned.is_ok() {
+                hold_cap::expire(now, |release| {
+                    hooks.producer.push(InputEvent::HoldCapped(release));
+                });
+            }
+            // a failed scan leaves a new fault to the next one
+            *local.fault_pending = hooks.fault;
+            (
+                scanned,
+                hooks.scan_cycles,
+                hooks.raw,
+                hooks.locks_changed,
+                *hooks.modifiers,
+            )
+        });
+    local
+        .scan_timing
+        .record(scan_cycles.unwrap_or_else(|| stopwatch.cycles()));
+    if now.wrapping_sub(*local.timing_reported_at) >= profile::SCAN_REPORT_PERIOD_MS {
+        *local.timing_reported_at = now;
+        if let Some(timing) = local.scan_timing.take() {
+            log!(
+                "scan us: min {} avg {} max {}, {} of {} over {}",
+                timing.min_us,
+                timing.avg_us,
+                timing.max_us,
+                timing.over_budget,
+                timing.scans,
+                profile::SCAN_BUDGET_US
+            );
+        }
+    }
+    let filtered = match scanned {
+        Ok(filtered) => {
+            if local.scan_failure.take().is_some() {
+                log!("keypad reachable again");
+            }
+            if core::mem::take(local.unreachable) {
+                clear_error(ErrorCode::KeypadUnreachable);
+            }
+            filtered
+        }
+        // keys keep their state and the next scan retries
+        Err(_) => {
+            let since = *local.scan_failure.get_or_insert_with(|| {
+                log!("keypad unreachable, retrying");
+                now
+            });
+            if now.wrapping_sub(since) > const { timing::ms(timing::MATRIX_TIMEOUT) }
+                && !core::mem::replace(local.unreachable, true)
+            {
+                raise_error(ErrorCode::KeypadUnreachable);
+            }
+            schedule_scan(deadline, scan_period);
+            return;
+        }
+    };
+    if locks_changed {
+        log!("locks {:02b}", locks.0);
+        send_led_message(LedMessage::Lock(locks.is_locked(modifiers::LOCK1)));
+    }
+    let Filtered {
+        state,
+        was_stuck,
+        stuck,
+        ..
+    } = filtered;
+    // the raw keys of a scan of their own, without the injected ones
+    #[cfg(feature = "dump")]
+    if let Some(reply_to) = dump::take_request() {
+        let snapshot = local
+            .engine
+            .scanner_mut()
+            .scan()
+            .map(|raw| Snapshot {
+                debounced: state,
+                raw: KeyState(raw),
+                counters: *local.engine.counters(),
+            })
+            .map_err(|_| "ERR unreachable");
+        // it left another column selected
+        local.sliced.restart();
+        feature_job::spawn(Job::Dump(reply_to, snapshot)).or_count();
+    }
+    if stuck != was_stuck {
+        let (new, recovered) = stuck.diff(was_stuck);
+        if !new.is_empty() {
+            log!("{} stuck keys released: {}", new.pressed_count(), new);
+            if was_stuck.is_empty() {
+                raise_error(ErrorCode::StuckKeys);
+            }
+        }
+        if !recovered.is_empty() {
+            log!("stuck keys recovered: {}", recovered);
+        }
+        if stuck.is_empty() {
+            clear_error(ErrorCode::StuckKeys);
+        }
+        ctx.shared.stuck_keys.lock(|keys| *keys = stuck);
+    }
+
+    if !state.is_empty() {
+        *local.idle_since = now;
+    }
+    if raw == 0 && state.is_empty() {
+        *local.empty_scans += 1;
+    } else {
+        *local.empty_scans = 0;
+    }
+    // the wake up needs the rows on GPIO pins
+    #[cfg(not(feature = "mcp23017"))]
+    if *local.empty_scans >= sleep::IDLE_SCANS && !local.encoder_button.is_low() {
+        // unmasked first, a press from here on raises its line
+        sleep::listen();
+        // a matrix that can't be driven keeps being scanned
+        let pressed = local.engine.scanner_mut().select_all().unwrap_or(true);
+        if !pressed && !local.encoder_button.is_low() {
+            *local.empty_scans = 0;
+            local.sliced.restart();
+            // a wake no press came of
+            ctx.shared.wake.lock(|wake| wake.clear());
+            let mut shared = (
+                ctx.shared.scan_mode,
+                ctx.shared.stop_handle,
+                ctx.shared.clock,
+            );
+            shared.lock(|mode, handle, clock| {
+                *mode = ScanMode::Waiting;
+                clock.release_high();
+                // the monotonic stands still in STOP, so this counts from the wake up
+                #[cfg(not(feature = "debug-idle"))]
+                {
+                    let idle = now.wrapping_sub(*local.idle_since);
+                    let delay = monotonic::millis(sleep::INACTIVITY_MS.saturating_sub(idle));
+                    *handle = stop_timer::spawn_after(delay).ok();
+                }
+                #[cfg(feature = "debug-idle")]
+                let _ = handle;
+            });
+            return;
+        }
+        sleep::unlisten();
+    }
+
+    schedule_scan(deadline, scan_period);
+}
+
+// `Matrix::self_test` of the presence check, `None` for an unreachable matrix.
+// The GPIO backends can't fail, so the check takes any of them.
+fn presence_test<M: Matrix<COLUMNS, ROWS>>(matrix: &mut M) -> Option<SelfTest<COLUMNS, ROWS>> {
+    matrix.self_test().ok()
+}
+
+// the next deadline is carried forward from the last one, so the period doesn't
+// drift with the lateness. After a stall the scans don't try to catch up.
+fn schedule_scan(deadline: monotonic::Instant, period_ms: u32) {
+    let step = monotonic::micros(period_ms * 1000 / SCAN_STEPS);
+    let next = (deadline + step).max(monotonics::now());
+    key_listener::spawn_at(next, next).or_count();
+}
@@ -0,0 +1,138 @@
+//! The internal temperature sensor (ADC1 channel 16) and VREFINT (channel 17).
+//!
+//! VREFINT is a fixed 1.2 V, so its reading gives VDDA, which in turn turns the
+//! temperature sensor reading into millivolts. The sensor is only good for
+//! relative readings, its offset varies by up to 45 °C from chip to chip.
+
+use core::fmt;
+use embedded_hal::adc::{Channel, OneShot};
+use serde::Serialize;
+use stm32f1xx_hal::adc::{Adc, SampleTime};
+use stm32f1xx_hal::pac::ADC1;
+
+// samples in the moving averages, one per second
+pub const AVERAGE_SAMPLES: usize = 8;
+
+// datasheet values: VREFINT, the sensor voltage at 25 °C and its slope in uV/°C
+const VREFINT_MV: u32 = 1200;
+const V25_MV: i32 = 1430;
+const AVG_SLOPE_UV: i32 = 4300;
+
+const FULL_SCALE: u32 = 4095;
+
+struct Temperature;
+
+impl Channel<ADC1> for Temperature {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        16
+    }
+}
+
+struct Vrefint;
+
+impl Channel<ADC1> for Vrefint {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        17
+    }
+}
+
+struct MovingAverage {
+    samples: [u16; AVERAGE_SAMPLES],
+    next: usize,
+    filled: usize,
+}
+
+impl MovingAverage {
+    const fn new() -> Self {
+        Self {
+            samples: [0; AVERAGE_SAMPLES],
+            next: 0,
+            filled: 0,
+        }
+    }
+
+    fn push(&mut self, sample: u16) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % AVERAGE_SAMPLES;
+        self.filled = (self.filled + 1).min(AVERAGE_SAMPLES);
+    }
+
+    // `None` until the first sample
+    fn value(&self) -> Option<u32> {
+        let sum: u32 = self.samples[..self.filled].iter().map(|&s| s as u32).sum();
+        (self.filled > 0).then(|| sum / self.filled as u32)
+    }
+}
+
+/// Smoothed readings of the internal channels.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Diagnostics {
+    pub temperature_tenths: i16,
+    pub vdda_mv: u16,
+}
+
+/// The whole degrees of a temperature in tenths, written with the unsigned
+/// formatting the logs have anyway.
+pub struct Celsius(pub i16);
+
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let degrees = self.0 / 10;
+        if degrees < 0 {
+            f.write_str("-")?;
+        }
+        fmt::Display::fmt(&u32::from(degrees.unsigned_abs()), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Celsius {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=i16}", self.0 / 10)
+    }
+}
+
+pub struct InternalSensors {
+    temperature: MovingAverage,
+    vrefint: MovingAverage,
+}
+
+impl InternalSensors {
+    /// Powers up both internal channels, they need about 10 us before the first sample.
+    pub fn new() -> Self {
+        // the hal only has them on for its own readings
+        unsafe { (*ADC1::ptr()).cr2.modify(|_, w| w.tsvrefe().set_bit()) };
+        Self {
+            temperature: MovingAverage::new(),
+            vrefint: MovingAverage::new(),
+        }
+    }
+
+    pub fn sample(&mut self, adc: &mut Adc<ADC1>) {
+        let config = adc.save_cfg();
+        // the sensor wants at least 17.1 us, 239.5 cycles are 26.6 us at 9 MHz
+        adc.set_sample_time(SampleTime::T_239);
+        let temperature: u16 = adc.read(&mut Temperature).unwrap_or(0);
+        let vrefint: u16 = adc.read(&mut Vrefint).unwrap_or(0);
+        adc.restore_cfg(config);
+
+        self.temperature.push(temperature);
+        self.vrefint.push(vrefint);
+    }
+
+    pub fn diagnostics(&self) -> Option<Diagnostics> {
+        let vrefint = self.vrefint.value()?.max(1);
+        let temperature = self.temperature.value()?;
+        let vdda_mv = VREFINT_MV * FULL_SCALE / vrefint;
+        let sense_mv = (temperature * vdda_mv / FULL_SCALE) as i32;
+        let temperature_tenths = (V25_MV - sense_mv) * 10_000 / AVG_SLOPE_UV + 250;
+        Some(Diagnostics {
+            temperature_tenths: temperature_tenths as i16,
+            vdda_mv: vdda_mv as u16,
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use crate::buffer::LineBuffer;
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use stm32f1xx_hal::pac::Interrupt;
+use usb_device::bus::UsbBus;
+use usbd_serial::CdcAcmClass;
+
+// one short of the 64 B of the endpoint, a packet shorter than it ends the
+// transfer and none needs a zero-length packet after it
+const PACKET_LEN: usize = 63;
+
+struct TxBuffer {
+    lines: LineBuffer<512>,
+    // the host opened the port (DTR set), nothing is buffered otherwise
+    connected: bool,
+}
+
+// shared by every priority level, so it is guarded by a critical section
+static TX: Mutex<RefCell<TxBuffer>> = Mutex::new(RefCell::new(TxBuffer {
+    lines: LineBuffer::new(),
+    connected: false,
+}));
+
+/// Queues `line` for the CDC port, silently dropping it if the port is closed or full.
+pub fn write_line(line: &str) {
+    let queued = interrupt::free(|cs| {
+        let mut tx = TX.borrow(cs).borrow_mut();
+        tx.connected && tx.lines.push_line(line)
+    });
+    if queued {
+        rtic::pend(Interrupt::USB_LP_CAN_RX0);
+    }
+}
+
+/// Moves buffered bytes into the CDC endpoint, called from the USB interrupt.
+pub fn flush<B: UsbBus>(port: &mut CdcAcmClass<'_, B>) {
+    interrupt::free(|cs| {
+        let mut tx = TX.borrow(cs).borrow_mut();
+        tx.connected = port.dtr();
+        if !tx.connected {
+            tx.lines.clear();
+            return;
+        }
+        while !tx.lines.is_empty() {
+            let pending = tx.lines.pending();
+            match port.write_packet(&pending[..pending.len().min(PACKET_LEN)]) {
+                Ok(written) => tx.lines.consume(written),
+                Err(_) => break,
+            }
+        }
+    });
+}
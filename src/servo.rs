@@ -0,0 +1,102 @@
+//! Two hobby servos on TIM3 channels 1 and 2, jogged from the keypad: '2' and '8'
+//! turn servo 0 by [`STEP_DEGREES`], '4' and '6' servo 1, '5' centers both. A key
+//! held repeats, so it sweeps the servo. Rev A has them on PB4 and PB5 with the
+//! partial remap of TIM3, rev B on PA6 and PA7.
+//!
+//! The pulses come at 50 Hz, from 1000 us at 0 degrees to 2000 us at 180. TIM2
+//! and TIM4 are taken by the status leds and the monotonic, and TIM3 can't be
+//! shared with the tones of `buzzer`. The timer holds full speed for its 1 MHz
+//! tick, and like the others it stops in STOP: the servos get no pulses meanwhile.
+
+use crate::event::{EventKind, KeyEvent};
+use stm32f1xx_hal::gpio::{Alternate, ErasedPin, PushPull};
+use stm32f1xx_hal::pac::TIM3;
+use stm32f1xx_hal::rcc::Clocks;
+use stm32f1xx_hal::timer::Timer;
+
+pub const CHANNELS: usize = 2;
+
+pub const MIN_DEGREES: u8 = 0;
+pub const MAX_DEGREES: u8 = 180;
+pub const CENTER_DEGREES: u8 = 90;
+pub const STEP_DEGREES: i16 = 5;
+
+const PERIOD_US: u16 = 20_000;
+const MIN_PULSE_US: u32 = 1000;
+const MAX_PULSE_US: u32 = 2000;
+
+/// The PWM outputs of channel 1 and 2, for keeping their pins taken.
+pub type Pins = [ErasedPin<Alternate<PushPull>>; CHANNELS];
+
+/// Starts TIM3 with both servos centered.
+pub fn init(tim: TIM3, _pins: Pins, clocks: &Clocks) {
+    // enables and resets the timer, which then ticks every us
+    let tim = Timer::new(tim, clocks).release();
+    let tick_hz = clocks.pclk1_tim().raw();
+    tim.psc
+        .write(|w| w.psc().bits((tick_hz / 1_000_000 - 1) as u16));
+    tim.arr.write(|w| w.arr().bits(PERIOD_US - 1));
+    tim.ccmr1_output().write(|w| {
+        w.oc1m()
+            .pwm_mode1()
+            .oc1pe()
+            .set_bit()
+            .oc2m()
+            .pwm_mode1()
+            .oc2pe()
+            .set_bit()
+    });
+    for channel in 0..CHANNELS {
+        set_position(channel, CENTER_DEGREES.into());
+    }
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.ccer.write(|w| w.cc1e().set_bit().cc2e().set_bit());
+    tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+}
+
+/// Turns servo `channel` to `degrees`, clamped to the range of the servos. Takes
+/// over with the next pulse.
+pub fn set_position(channel: usize, degrees: i16) {
+    let degrees = degrees.clamp(MIN_DEGREES.into(), MAX_DEGREES.into()) as u32;
+    let pulse_us =
+        MIN_PULSE_US + (degrees * (MAX_PULSE_US - MIN_PULSE_US) + 90) / u32::from(MAX_DEGREES);
+    // SAFETY: the compare registers of the channels are only written here
+    if let Some(ccr) = unsafe { (*TIM3::ptr()).ccr.get(channel) } {
+        ccr.write(|w| w.ccr().bits(pulse_us as u16));
+    }
+}
+
+/// The position of servo `channel`, read back from its pulse length.
+pub fn position(channel: usize) -> u8 {
+    // SAFETY: a read of a compare register, which has no side effects
+    let pulse_us = unsafe { (*TIM3::ptr()).ccr.get(channel) }
+        .map_or(MIN_PULSE_US, |ccr| u32::from(ccr.read().ccr().bits()));
+    let degrees = (pulse_us.saturating_sub(MIN_PULSE_US) * u32::from(MAX_DEGREES) + 500)
+        / (MAX_PULSE_US - MIN_PULSE_US);
+    degrees as u8
+}
+
+pub fn positions() -> [u8; CHANNELS] {
+    [position(0), position(1)]
+}
+
+/// Jogs the servos on the presses and repeats of their keys.
+pub fn handle(event: &KeyEvent) {
+    if !matches!(event.kind, EventKind::Pressed | EventKind::Repeat) {
+        return;
+    }
+    let (channel, step) = match event.key {
+        '2' => (0, STEP_DEGREES),
+        '8' => (0, -STEP_DEGREES),
+        '4' => (1, -STEP_DEGREES),
+        '6' => (1, STEP_DEGREES),
+        '5' if event.kind == EventKind::Pressed => {
+            for channel in 0..CHANNELS {
+                set_position(channel, CENTER_DEGREES.into());
+            }
+            return;
+        }
+        _ => return,
+    };
+    set_position(channel, i16::from(position(channel)) + step);
+}
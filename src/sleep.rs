@@ -0,0 +1,195 @@
+//! Interrupt driven scanning and STOP mode. Not with `mcp23017`, whose rows
+//! can't interrupt.
+//!
+//! After `IDLE_SCANS` empty scans the scanner drives every column active, unmasks
+//! the EXTI lines of the rows and of the encoder switch and stops rescheduling
+//! itself until one of them fires. Once `INACTIVITY_MS` passed without a
+//! key held down, `idle` goes on from waiting to STOP, except with
+//! `debug-idle`, which never sleeps. `idle` enters STOP with interrupts masked
+//! and, if something still holds full speed, brings HSE and the PLL back before
+//! any handler runs at the wrong clock. The monotonic's timer stops with the bus
+//! clocks, it carries on where it left off and the time spent in STOP doesn't
+//! count for any timer.
+//!
+//! The first row line to fire masks the lines again and posts its row and its tick
+//! to the `keypad_core::wake` mailbox, the scanner gives the press of its first
+//! debounced frame in that row the tick of the edge instead of its own.
+//!
+//! The IWDG can't be stopped, so the RTC alarm wakes the core every
+//! [`FEED_TICKS`] to feed it and the core goes straight back to STOP.
+
+use crate::app::{idle, now_u64, stop_timer, wake_scanner};
+use crate::board::WAKE_LINES;
+use crate::clock_manager;
+use crate::keypad::ROWS;
+use crate::rtc::Rtc;
+use crate::watchdog;
+use cortex_m::peripheral::{NVIC, SCB};
+use rtic::mutex::prelude::*;
+use stm32f1xx_hal::pac::{Interrupt, EXTI, PWR};
+
+pub use keypad_core::wake::WakeMailbox;
+
+pub const INACTIVITY_MS: u32 = 30_000;
+// longer than the chord window even at `SCANRATE 1`, a pending chord needs polling
+#[cfg(not(feature = "mcp23017"))]
+pub const IDLE_SCANS: u32 = 100;
+
+const RTC_ALARM_LINE: u32 = 1 << 17;
+// the rows are the first of the wake lines, the encoder switch the last
+const FIRST_ROW_LINE: u32 = WAKE_LINES.trailing_zeros();
+const ROW_LINES: u32 = ((1 << ROWS) - 1) << FIRST_ROW_LINE;
+
+// a quarter of a second at `rtc::HZ`. The IWDG runs from the LSI, so on the LSI
+// however far it is off that stays at half its timeout, and on the LSE the LSI
+// would have to be a third fast to reach it.
+const FEED_TICKS: u32 = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScanMode {
+    // the scanner reschedules itself every scan period
+    Polling,
+    // stopped until a wake line fires
+    Waiting,
+    // waiting and `idle` enters STOP
+    Stopped,
+}
+
+/// Unmasks the wake lines, their triggers are set up in init.
+#[cfg(not(feature = "mcp23017"))]
+pub fn listen() {
+    // SAFETY: only the wake bits change, the critical section keeps the other
+    // EXTI users out of the read-modify-write
+    cortex_m::interrupt::free(|_| unsafe {
+        let exti = &*EXTI::ptr();
+        exti.pr.write(|w| w.bits(WAKE_LINES));
+        exti.imr.modify(|r, w| w.bits(r.bits() | WAKE_LINES));
+    });
+}
+
+/// The first row whose line is pending, `None` for the encoder switch alone.
+/// [`unlisten`] clears them.
+pub fn pending_row() -> Option<u8> {
+    let pending = unsafe { (*EXTI::ptr()).pr.read().bits() } & ROW_LINES;
+    (pending != 0).then(|| (pending.trailing_zeros() - FIRST_ROW_LINE) as u8)
+}
+
+pub fn unlisten() {
+    cortex_m::interrupt::free(|_| unsafe {
+        let exti = &*EXTI::ptr();
+        exti.imr.modify(|r, w| w.bits(r.bits() & !WAKE_LINES));
+        exti.pr.write(|w| w.bits(WAKE_LINES));
+    });
+}
+
+pub struct DeepSleep;
+
+impl DeepSleep {
+    /// Routes the RTC alarm to its EXTI line, the only way it wakes the core from STOP.
+    pub fn new(exti: &mut EXTI) -> Self {
+        exti.rtsr.modify(|_, w| w.tr17().set_bit());
+        exti.imr.modify(|_, w| w.mr17().set_bit());
+        Self
+    }
+
+    /// Stays in STOP until an EXTI line other than the RTC alarm fires and returns
+    /// `true`, or returns `false` right away if another interrupt is already
+    /// pending or the RTC has no clock yet. Needs interrupts disabled, the clocks
+    /// are only restored here.
+    pub fn stop(&mut self, rtc: &mut Rtc) -> bool {
+        // without its alarm nothing would feed the IWDG
+        if rtc.source().is_none() {
+            return false;
+        }
+        // SAFETY: PDDS/LPDS and SLEEPDEEP are only changed here and by the
+        // battery standby, which never returns
+        unsafe {
+            (*PWR::ptr())
+                .cr
+                .modify(|_, w| w.pdds().clear_bit().lpds().set_bit());
+            (*SCB::PTR).scr.modify(|scr| scr | 1 << 2);
+        }
+        rtc.listen_alarm();
+        let woken = loop {
+            rtc.set_alarm(rtc.counts().wrapping_add(FEED_TICKS));
+            clear_alarm_line();
+            cortex_m::asm::wfi();
+            rtc.sync();
+            let exti = unsafe { &*EXTI::ptr() };
+            let pending = exti.pr.read().bits() & exti.imr.read().bits();
+            if pending != RTC_ALARM_LINE {
+                // without any line pending WFI didn't sleep at all
+                break pending & !RTC_ALARM_LINE != 0;
+            }
+            // awake on HSI for a moment, nothing else runs
+            watchdog::feed_unowned();
+        };
+        rtc.unlisten_alarm();
+        rtc.clear_alarm_flag();
+        clear_alarm_line();
+        unsafe { (*SCB::PTR).scr.modify(|scr| scr & !(1 << 2)) };
+        clock_manager::resume_from_stop();
+        woken
+    }
+}
+
+/// Acknowledges the RTC alarm on its EXTI line.
+pub fn clear_alarm_line() {
+    unsafe { (*EXTI::ptr()).pr.write(|w| w.bits(RTC_ALARM_LINE)) };
+    NVIC::unpend(Interrupt::RTCALARM);
+}
+
+/// Sends the core to STOP once the scanner stopped, and sleeps in WFI otherwise,
+/// the body of `idle`. The time slept counts for the CPU load of `event_stats`.
+pub fn idle(mut ctx: idle::Context) -> ! {
+    loop {
+        // with interrupts masked until the clocks are back up
+        cortex_m::interrupt::free(|_| {
+            if ctx.shared.scan_mode.lock(|mode| *mode) != ScanMode::Stopped {
+                return;
+            }
+            // latched after the scanner stopped, it has to park the matrix
+            let sleep = &mut ctx.local.sleep;
+            if ctx.shared.emergency.lock(|emergency| *emergency)
+                || ctx.shared.rtc.lock(|rtc| sleep.stop(rtc))
+            {
+                let mut shared = (
+                    &mut ctx.shared.scan_mode,
+                    &mut ctx.shared.stop_handle,
+                    &mut ctx.shared.clock,
+                );
+                shared.lock(wake_scanner);
+            }
+        });
+        // the wake up stays masked until the time is taken, so the handler
+        // it runs doesn't count as idle. The monotonic interrupts at least once
+        // per counter overflow, so the core never sleeps longer than ~65 ms.
+        cortex_m::interrupt::free(|_| {
+            let start = now_u64();
+            #[cfg(not(feature = "debug-idle"))]
+            cortex_m::asm::wfi();
+            // spins until an interrupt is pending (ISRPENDING), like WFI would
+            #[cfg(feature = "debug-idle")]
+            while unsafe { (*SCB::PTR).icsr.read() } & 1 << 22 == 0 {
+                rtic::export::nop();
+            }
+            let slept = now_u64().wrapping_sub(start) as u32;
+            ctx.shared
+                .idle_us
+                .lock(|idle| *idle = idle.wrapping_add(slept));
+        });
+    }
+}
+
+/// The scanner waiting for `INACTIVITY_MS`, `idle` takes it into STOP, the body
+/// of `stop_timer`.
+pub fn stop_timer(mut ctx: stop_timer::Context) {
+    let emergency = ctx.shared.emergency.lock(|emergency| *emergency);
+    ctx.shared.scan_mode.lock(|mode| {
+        // a stale run after a wake up finds the scanner polling
+        if *mode == ScanMode::Waiting && !emergency {
+            log!("no keys for {} s, stopping", INACTIVITY_MS / 1000);
+            *mode = ScanMode::Stopped;
+        }
+    });
+}
@@ -0,0 +1,25 @@
+//! Spawns that fail because every message slot of the task is taken. Nothing
+//! unwraps a spawn, the failures are counted for `STATUS` instead.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+static FAILURES: AtomicU32 = AtomicU32::new(0);
+
+/// Failed spawns since boot.
+pub fn failures() -> u32 {
+    FAILURES.load(Ordering::Relaxed)
+}
+
+pub trait Counted<T> {
+    /// The spawn handle, or `None` after counting the failure.
+    fn or_count(self) -> Option<T>;
+}
+
+impl<T, E> Counted<T> for Result<T, E> {
+    fn or_count(self) -> Option<T> {
+        if self.is_err() {
+            FAILURES.fetch_add(1, Ordering::Relaxed);
+        }
+        self.ok()
+    }
+}
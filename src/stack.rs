@@ -0,0 +1,53 @@
+//! How deep the stack ever got, the `stack-watch` feature. Before RAM is
+//! initialized the free RAM below the reset stack pointer is painted with
+//! [`PAINT`], [`usage`] later finds the lowest word the stack overwrote by
+//! scanning up from the bottom. Every task and handler runs on the one MSP stack,
+//! its top is that of `memory.x`, its bottom the end of `.bss` and `.uninit`.
+//!
+//! `event_stats` raises `ErrorCode::StackLow` once less than [`LOW_FREE`] is left,
+//! `STACK` tells the numbers.
+
+// the ends of the stack from the linker script of `cortex-m-rt`
+extern "C" {
+    static _stack_start: u32;
+    static __sheap: u32;
+}
+
+/// The word left where the stack never reached.
+pub const PAINT: u32 = 0x5a5a_a5a5;
+
+/// Free stack below which the red led blinks the error, in bytes.
+pub const LOW_FREE: u32 = 1024;
+
+pub struct Usage {
+    pub used: u32,
+    pub free: u32,
+}
+
+// runs before `.bss` and `.data` are set up, so it stays away from every static
+// and only paints below the stack pointer, where nothing lives yet
+#[cortex_m_rt::pre_init]
+unsafe fn paint() {
+    let end = cortex_m::register::msp::read() as *mut u32;
+    let mut word = core::ptr::addr_of!(__sheap) as *mut u32;
+    while word < end {
+        word.write_volatile(PAINT);
+        word = word.add(1);
+    }
+}
+
+/// The high-water mark of the stack so far.
+pub fn usage() -> Usage {
+    let top = core::ptr::addr_of!(_stack_start);
+    let bottom = core::ptr::addr_of!(__sheap);
+    let mut word = bottom;
+    // SAFETY: the words between the ends are RAM, the stack pointer is above the
+    // lowest one written, which stops the scan
+    while word < top && unsafe { word.read_volatile() } == PAINT {
+        word = unsafe { word.add(1) };
+    }
+    Usage {
+        used: top as u32 - word as u32,
+        free: word as u32 - bottom as u32,
+    }
+}
@@ -0,0 +1,73 @@
+//! Usage statistics since boot or the last `STATS RESET`, counted by the key
+//! consumer on the debounced press and release events, so a bouncing contact
+//! counts once. The presses also go into the lifetime count of `crate::lifetime`,
+//! which is what survives a reset.
+
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{COLUMNS, KEYS};
+
+#[derive(Copy, Clone)]
+pub struct KeyStats {
+    presses: [u32; KEYS],
+    releases: u32,
+    // keys down right now and the most seen at once
+    held: u8,
+    max_held: u8,
+}
+
+impl KeyStats {
+    pub const fn new() -> Self {
+        Self {
+            presses: [0; KEYS],
+            releases: 0,
+            held: 0,
+            max_held: 0,
+        }
+    }
+
+    pub fn record(&mut self, event: &KeyEvent) {
+        match event.kind {
+            EventKind::Pressed => {
+                let key =
+                    &mut self.presses[usize::from(event.row) * COLUMNS + usize::from(event.col)];
+                *key = key.wrapping_add(1);
+                self.held += 1;
+                self.max_held = self.max_held.max(self.held);
+            }
+            EventKind::Released { .. } | EventKind::ReleasedAfterLong => {
+                self.releases = self.releases.wrapping_add(1);
+                // a key held since before the reset
+                self.held = self.held.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+
+    /// Starts over, the keys held stay counted as held.
+    pub fn reset(&mut self) {
+        *self = Self {
+            held: self.held,
+            max_held: self.held,
+            ..Self::new()
+        };
+    }
+
+    /// Presses of every key, in the order of the matrix.
+    pub fn presses(&self) -> &[u32; KEYS] {
+        &self.presses
+    }
+
+    pub fn total_presses(&self) -> u32 {
+        self.presses
+            .iter()
+            .fold(0, |total, &presses| total.wrapping_add(presses))
+    }
+
+    pub fn releases(&self) -> u32 {
+        self.releases
+    }
+
+    pub fn max_held(&self) -> u8 {
+        self.max_held
+    }
+}
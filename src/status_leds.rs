@@ -0,0 +1,122 @@
+//! Dimmable red and blue status leds. Neither pin has a timer channel on either
+//! board, so TIM2 runs a software PWM: the update interrupt turns the lit leds
+//! on and the compare interrupts of channels 1 and 2 turn them off again.
+
+use crate::command::Led;
+use stm32f1xx_hal::gpio::{ErasedPin, Output};
+use stm32f1xx_hal::pac::TIM2;
+use stm32f1xx_hal::rcc::Clocks;
+use stm32f1xx_hal::timer::Timer;
+
+/// Brightness at boot, about 20%.
+pub const DEFAULT_BRIGHTNESS: u8 = 51;
+
+// one PWM period is 256 timer ticks
+pub const PWM_FREQUENCY_HZ: u32 = 250;
+
+/// TIM2 prescaler for the PWM frequency at a timer clock of `tick_hz`.
+pub fn prescaler(tick_hz: u32) -> u16 {
+    (tick_hz / (PWM_FREQUENCY_HZ * 256) - 1) as u16
+}
+
+struct Channel {
+    pin: ErasedPin<Output>,
+    on: bool,
+    brightness: u8,
+}
+
+pub struct StatusLeds {
+    tim: TIM2,
+    // red on compare channel 1, blue on channel 2
+    channels: [Channel; 2],
+}
+
+impl StatusLeds {
+    pub fn new(
+        tim: TIM2,
+        red: ErasedPin<Output>,
+        blue: ErasedPin<Output>,
+        clocks: &Clocks,
+    ) -> Self {
+        let tick_hz = clocks.pclk1_tim().raw();
+        // enables and resets the timer
+        let tim = Timer::new(tim, clocks).release();
+        tim.psc.write(|w| w.psc().bits(prescaler(tick_hz)));
+        tim.arr.write(|w| w.arr().bits(255));
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.dier
+            .write(|w| w.uie().set_bit().cc1ie().set_bit().cc2ie().set_bit());
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        let channel = |pin| Channel {
+            pin,
+            on: false,
+            brightness: DEFAULT_BRIGHTNESS,
+        };
+        let mut leds = Self {
+            tim,
+            channels: [channel(red), channel(blue)],
+        };
+        for index in 0..leds.channels.len() {
+            leds.write_compare(index);
+        }
+        leds
+    }
+
+    fn index(led: Led) -> usize {
+        match led {
+            Led::Red => 0,
+            Led::Blue => 1,
+        }
+    }
+
+    fn write_compare(&mut self, index: usize) {
+        let brightness = self.channels[index].brightness;
+        self.tim.ccr[index].write(|w| w.ccr().bits(brightness as u16));
+    }
+
+    /// 0 is off and 255 fully on, applies whenever the led is lit.
+    pub fn set_brightness(&mut self, led: Led, brightness: u8) {
+        let index = Self::index(led);
+        self.channels[index].brightness = brightness;
+        self.write_compare(index);
+    }
+
+    pub fn set(&mut self, led: Led, on: bool) {
+        let channel = &mut self.channels[Self::index(led)];
+        channel.on = on;
+        // lit again by the next period
+        if !on {
+            channel.pin.set_low();
+        }
+    }
+
+    pub fn toggle(&mut self, led: Led) {
+        let on = self.channels[Self::index(led)].on;
+        self.set(led, !on);
+    }
+
+    /// Called from the TIM2 interrupt, returns whether a period started.
+    pub fn on_interrupt(&mut self) -> bool {
+        let status = self.tim.sr.read();
+        // the flags are cleared by writing zero, the rest is written back as one
+        self.tim.sr.write(|w| unsafe { w.bits(!status.bits()) });
+
+        if status.uif().bit_is_set() {
+            for channel in self.channels.iter_mut() {
+                if channel.on && channel.brightness > 0 {
+                    channel.pin.set_high();
+                }
+            }
+        }
+        let compare = [status.cc1if().bit_is_set(), status.cc2if().bit_is_set()];
+        for (channel, matched) in self.channels.iter_mut().zip(compare) {
+            // full brightness never turns off
+            if matched && channel.brightness < 255 {
+                channel.pin.set_low();
+            }
+        }
+        status.uif().bit_is_set()
+    }
+}
@@ -0,0 +1,97 @@
+//! `TIME SYNC <seconds>`, the `time-sync` feature. Sets the RTC like `TIME SET`
+//! and reports how far it drifted since the previous sync, whose point stays in a
+//! backup register with the RTC, see `keypad_core::drift`. `TRIM` puts the
+//! calibration that would have kept it on time into the RTC as well. A `TIME SET`
+//! forgets the point, the clock was set without a measurement.
+//!
+//! The events still in the queue at a step of the clock were stamped before it,
+//! [`restamp`] moves their wall clock by the step as they come off it.
+
+use crate::event::KeyEvent;
+use crate::rtc::{self, Rtc};
+use core::cell::Cell;
+use core::fmt;
+use cortex_m::interrupt::{self, Mutex};
+use keypad_core::drift::{self, Measurement};
+use stm32f1xx_hal::pac::BKP;
+
+// backup register DR2, next to the reset marker and the panic record in DR1
+const POINT_REGISTER: usize = 1;
+
+// ticks of the latest step of the wall clock and the seconds it stepped by
+static STEP: Mutex<Cell<(u64, u32)>> = Mutex::new(Cell::new((0, 0)));
+
+pub struct Synced {
+    pub measurement: Measurement,
+    // the calibration the RTC runs with from now on
+    pub calibration: u8,
+}
+
+/// `SYNC drift=3 elapsed=86400 ppm=34 cal=0` and the like.
+impl fmt::Display for Synced {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.measurement {
+            Measurement::First => f.write_str("SYNC first")?,
+            Measurement::Backwards => f.write_str("SYNC back")?,
+            Measurement::Drift { drift_s } => write!(f, "SYNC drift={}", drift_s)?,
+            Measurement::Measured(drift) => write!(
+                f,
+                "SYNC drift={} elapsed={} ppm={}",
+                drift.drift_s,
+                drift.elapsed_s,
+                drift.ppm()
+            )?,
+        }
+        write!(f, " cal={}", self.calibration)
+    }
+}
+
+/// Sets the wall clock to the `host`'s seconds at the ticks `at` and measures
+/// against the previous sync. `None` while the LSE is still starting.
+pub fn sync(rtc: &mut Rtc, host: u32, trim: bool, at: u64) -> Option<Synced> {
+    let clock = rtc::seconds();
+    if !rtc.set_time(host) {
+        return None;
+    }
+    stepped(at, host.wrapping_sub(clock));
+    // SAFETY: DR2 and RTCCR are this module's alone
+    let bkp = unsafe { &*BKP::ptr() };
+    let measurement = drift::measure(bkp.dr[POINT_REGISTER].read().d().bits(), host, clock);
+    bkp.dr[POINT_REGISTER].write(|w| w.d().bits(drift::point(host)));
+    let mut calibration = bkp.rtccr.read().cal().bits();
+    if let (true, Measurement::Measured(drift)) = (trim, measurement) {
+        calibration = drift.calibration(calibration);
+        // SAFETY: `drift::MAX_CALIBRATION` fits the 7 bit field
+        bkp.rtccr
+            .modify(|_, w| unsafe { w.cal().bits(calibration) });
+    }
+    Some(Synced {
+        measurement,
+        calibration,
+    })
+}
+
+/// `TIME SET`, forgets the point of the previous sync.
+pub fn set(rtc: &mut Rtc, seconds: u32, at: u64) -> bool {
+    let clock = rtc::seconds();
+    if !rtc.set_time(seconds) {
+        return false;
+    }
+    stepped(at, seconds.wrapping_sub(clock));
+    // SAFETY: as in `sync`
+    unsafe { (*BKP::ptr()).dr[POINT_REGISTER].write(|w| w.d().bits(0)) };
+    true
+}
+
+fn stepped(at: u64, step: u32) {
+    interrupt::free(|cs| STEP.borrow(cs).set((at, step)));
+}
+
+/// `event` on the wall clock of the latest step, if it was stamped before.
+pub fn restamp(mut event: KeyEvent) -> KeyEvent {
+    let (at, step) = interrupt::free(|cs| STEP.borrow(cs).get());
+    if event.at < at {
+        event.time = event.time.wrapping_add(step);
+    }
+    event
+}
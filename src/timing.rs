@@ -0,0 +1,111 @@
+//! The periods and timeouts the tasks are scheduled by, as durations of the
+//! monotonic, so a change of a period or of the monotonic's rate is reviewed here.
+//! The modules that count in milliseconds themselves keep their `_MS` constants,
+//! the durations of the ones `main` schedules by are made from them below.
+//!
+//! The asserts at the bottom hold the timings to each other at compile time.
+
+use crate::command::MAX_DEBOUNCE_LATENCY_MS;
+use crate::keypad::DEBOUNCE_THRESHOLD;
+use crate::monotonic::{millis, Duration};
+use crate::{emergency, gesture, led_mode, lifetime, reset, rtc, watchdog};
+
+/// A duration in whole milliseconds, for the places that count in them. Use it in
+/// a const context, the division only folds away there.
+pub const fn ms(duration: Duration) -> u32 {
+    duration.to_millis() as u32
+}
+
+/// The scan period of the default settings, `SCANRATE` changes it.
+pub const SCAN_PERIOD: Duration = millis(10);
+
+/// How long a press of the default settings takes to debounce.
+pub const DEBOUNCE_TIME: Duration = millis(ms(SCAN_PERIOD) * DEBOUNCE_THRESHOLD as u32);
+
+/// A failing matrix backend, like an unplugged expander, blinks its error code
+/// after this long.
+pub const MATRIX_TIMEOUT: Duration = millis(1000);
+
+/// The scanner repeats the boot self-test this often, for a ribbon cable that
+/// came loose or a short that developed since.
+pub const PRESENCE_CHECK: Duration = millis(3000);
+
+/// Each phase of the blink, a blink every two.
+pub const BLINK_PHASE: Duration = millis(1000);
+
+/// The CPU load, the internal sensors and the drop counters.
+pub const STATS_PERIOD: Duration = millis(1000);
+
+/// Refresh of the status display.
+#[cfg(feature = "display")]
+pub const DISPLAY_PERIOD: Duration = millis(100);
+
+/// Joystick sampling at 100 Hz.
+#[cfg(feature = "joystick")]
+pub const JOYSTICK_PERIOD: Duration = millis(10);
+
+#[cfg(feature = "battery")]
+pub const BATTERY_PERIOD: Duration = millis(5000);
+
+/// Time for the last log line to go out before the battery halt.
+#[cfg(feature = "battery")]
+pub const HALT_DELAY: Duration = millis(100);
+
+/// How long the emergency button has to be held to latch the stop, a shorter
+/// press pauses or resumes the scanning.
+pub const EMERGENCY_LATCH: Duration = millis(2000);
+
+/// How long the button has to be held again to clear the emergency stop.
+pub const EMERGENCY_HOLD: Duration = millis(3000);
+
+pub const BUTTON_SETTLE: Duration = millis(emergency::SETTLE_MS);
+
+/// Backlight frames at ~60 Hz.
+#[cfg(feature = "backlight")]
+pub const FRAME_PERIOD: Duration = millis(16);
+
+/// How long a `UART SET` waits for its `UART CONFIRM`, see `crate::uart`. The
+/// stats period checks it, it may take a second longer.
+#[cfg(feature = "uart-config")]
+pub const UART_CONFIRM: Duration = millis(10_000);
+
+/// Polling of the RTT console.
+pub const CONSOLE_PERIOD: Duration = millis(50);
+
+pub const WATCHDOG_FEED_PERIOD: Duration = millis(watchdog::FEED_PERIOD_MS);
+pub const WINDOW_POLL: Duration = millis(watchdog::POLL_MS);
+/// The save of the lifetime counters and the heartbeat log line, in stats
+/// periods.
+pub const LIFETIME_SAVE_STATS: u32 = lifetime::SAVE_PERIOD_MS / ms(STATS_PERIOD);
+pub const RESET_DRAIN: Duration = millis(reset::DRAIN_MS);
+pub const LSE_POLL: Duration = millis(rtc::LSE_POLL_MS);
+pub const DIM_AFTER: Duration = millis(led_mode::DIM_AFTER_MS);
+#[cfg(feature = "key-test")]
+pub const KEY_TEST_PASSED: Duration = millis(led_mode::KEY_TEST_PASSED_MS);
+pub const LONG_PRESS: Duration = millis(gesture::LONG_PRESS_MS);
+pub const DOUBLE_TAP: Duration = millis(gesture::DOUBLE_TAP_MS);
+
+const _: () = assert!(
+    ms(DEBOUNCE_TIME) <= MAX_DEBOUNCE_LATENCY_MS,
+    "the default settings have to pass `command::debounce_fits`"
+);
+const _: () = assert!(
+    LONG_PRESS.ticks() > DOUBLE_TAP.ticks(),
+    "a double tap has to be over before the first press could turn long"
+);
+const _: () = assert!(
+    gesture::REPEAT_INTERVAL_MS <= gesture::REPEAT_DELAY_MS,
+    "the typematic repeat only gets faster"
+);
+const _: () = assert!(
+    WATCHDOG_FEED_PERIOD.ticks() < millis(watchdog::TIMEOUT_MS).ticks(),
+    "the IWDG would reset between two feeds"
+);
+const _: () = assert!(
+    LSE_POLL.ticks() < millis(rtc::LSE_STARTUP_MS).ticks(),
+    "the LSE startup has to be polled more than once"
+);
+const _: () = assert!(
+    BUTTON_SETTLE.ticks() < EMERGENCY_LATCH.ticks(),
+    "the emergency button settles before it can latch"
+);
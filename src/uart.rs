@@ -0,0 +1,320 @@
+//! USART1 on DMA, see [`UartDma`]. With `uart-config` its framing is set three
+//! ways, each over the one before: `UART_BAUD` and `UART_FORMAT` in the build
+//! environment, the framing `SAVE` stored, and `UART SET`. A `UART SET` only
+//! sticks once a `UART CONFIRM` came in over the new framing, within
+//! `timing::UART_CONFIRM`, otherwise the old one comes back.
+
+#[cfg(feature = "uart-config")]
+use core::cell::Cell;
+use core::cell::RefCell;
+#[cfg(feature = "uart-config")]
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use cortex_m::interrupt::{self, Mutex};
+use embedded_dma::ReadBuffer;
+#[cfg(feature = "uart-config")]
+use keypad_core::framing::{Framing, Parity};
+use stm32f1xx_hal::dma::{Event, Transfer, WriteDma, R};
+use stm32f1xx_hal::pac::{Interrupt, USART1};
+use stm32f1xx_hal::serial::TxDma1;
+
+// USART1 on PA9 (TX) / PA10 (RX)
+#[cfg(not(feature = "midi"))]
+pub const BAUD_RATE: u32 = 115_200;
+#[cfg(feature = "midi")]
+pub const BAUD_RATE: u32 = crate::midi::BAUD_RATE;
+
+/// The framing of the build environment, see `build.rs`.
+#[cfg(feature = "uart-config")]
+pub const BUILD_FRAMING: Framing = match Framing::parse(env!("UART_BAUD"), env!("UART_FORMAT")) {
+    Some(framing) => framing,
+    None => panic!("UART_BAUD or UART_FORMAT is no framing, like 57600 and 8E1"),
+};
+
+// a zero baud rate for none, they start out in the zeroed RAM that way
+#[cfg(feature = "uart-config")]
+const NO_FRAMING: Framing = Framing {
+    baud: 0,
+    parity: Parity::None,
+    two_stop_bits: false,
+};
+
+// the framing on the wire from `start` on, and the clock its divisor is for
+#[cfg(feature = "uart-config")]
+static FRAMING: Mutex<Cell<Framing>> = Mutex::new(Cell::new(NO_FRAMING));
+#[cfg(feature = "uart-config")]
+static PCLK2_HZ: AtomicU32 = AtomicU32::new(0);
+// the framing before an unconfirmed `UART SET`, and when it comes back
+#[cfg(feature = "uart-config")]
+static UNCONFIRMED: Mutex<Cell<(Framing, u32)>> = Mutex::new(Cell::new((NO_FRAMING, 0)));
+// while `UartDma` has a transfer running
+#[cfg(feature = "uart-config")]
+static TRANSMITTING: AtomicBool = AtomicBool::new(false);
+
+/// Sets the baud rate divisor for a USART1 clock of `pclk2_hz`. A byte on the
+/// wire meanwhile is garbled, the transfer itself carries on.
+pub fn set_clock(pclk2_hz: u32) {
+    #[cfg(not(feature = "uart-config"))]
+    let baud = BAUD_RATE;
+    #[cfg(feature = "uart-config")]
+    let baud = {
+        PCLK2_HZ.store(pclk2_hz, Ordering::Relaxed);
+        framing().baud
+    };
+    // SAFETY: the divisor is only written by the HAL's constructor and here
+    unsafe { (*USART1::ptr()).brr.write(|w| w.bits(pclk2_hz / baud)) };
+}
+
+/// Switches the HAL's 8N1 at [`BAUD_RATE`] over to `framing`, from `init`.
+#[cfg(feature = "uart-config")]
+pub fn start(framing: Framing, pclk2_hz: u32) {
+    PCLK2_HZ.store(pclk2_hz, Ordering::Relaxed);
+    interrupt::free(|cs| FRAMING.borrow(cs).set(framing));
+    write_framing(framing);
+}
+
+#[cfg(feature = "uart-config")]
+pub fn framing() -> Framing {
+    interrupt::free(|cs| FRAMING.borrow(cs).get())
+}
+
+// the framing before an unconfirmed `UART SET` and its deadline
+#[cfg(feature = "uart-config")]
+fn unconfirmed() -> Option<(Framing, u32)> {
+    let (previous, deadline_ms) = interrupt::free(|cs| UNCONFIRMED.borrow(cs).get());
+    (previous.baud != 0).then_some((previous, deadline_ms))
+}
+
+/// The framing `SAVE` stores, the confirmed one, `None` for the build's.
+#[cfg(feature = "uart-config")]
+pub fn saved() -> Option<Framing> {
+    let confirmed = unconfirmed().map_or(framing(), |(previous, _)| previous);
+    (confirmed != BUILD_FRAMING).then_some(confirmed)
+}
+
+/// A transfer runs or waits, `reconfigure` is refused.
+#[cfg(feature = "uart-config")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Busy;
+
+/// Switches to `framing` for `UART SET`, until a `confirm` before `deadline_ms`.
+#[cfg(feature = "uart-config")]
+pub fn set(framing: Framing, deadline_ms: u32) -> Result<(), Busy> {
+    let previous = unconfirmed().map_or(self::framing(), |(previous, _)| previous);
+    reconfigure(framing)?;
+    interrupt::free(|cs| UNCONFIRMED.borrow(cs).set((previous, deadline_ms)));
+    Ok(())
+}
+
+/// Keeps the framing of the last `set`, false if there was none to confirm.
+#[cfg(feature = "uart-config")]
+pub fn confirm() -> bool {
+    let (previous, _) = interrupt::free(|cs| UNCONFIRMED.borrow(cs).replace((NO_FRAMING, 0)));
+    previous.baud != 0
+}
+
+/// Goes back to the framing before an unconfirmed `set` once its deadline passed
+/// at `now_ms`, true if it did. A busy transmitter puts it off to the next call.
+#[cfg(feature = "uart-config")]
+pub fn expire(now_ms: u32) -> bool {
+    let Some((previous, deadline_ms)) = unconfirmed() else {
+        return false;
+    };
+    let due = now_ms.wrapping_sub(deadline_ms) < u32::MAX / 2;
+    let back = due && reconfigure(previous).is_ok();
+    if back {
+        interrupt::free(|cs| UNCONFIRMED.borrow(cs).set((NO_FRAMING, 0)));
+    }
+    back
+}
+
+// switches once the last byte went out, refused while a transfer runs or lines
+// wait for one, they would go out garbled
+#[cfg(feature = "uart-config")]
+fn reconfigure(framing: Framing) -> Result<(), Busy> {
+    interrupt::free(|cs| {
+        let queued = QUEUED.borrow(cs).borrow();
+        let waiting = queued.chunk.as_ref().is_some_and(|chunk| chunk.len > 0);
+        if TRANSMITTING.load(Ordering::Relaxed) || waiting {
+            return Err(Busy);
+        }
+        // SAFETY: a read of the status register, the flag takes a byte at most
+        while unsafe { (*USART1::ptr()).sr.read().tc().bit_is_clear() } {}
+        FRAMING.borrow(cs).set(framing);
+        write_framing(framing);
+        Ok(())
+    })
+}
+
+// the registers of `framing`, the receiver and the transmitter stay enabled
+#[cfg(feature = "uart-config")]
+fn write_framing(framing: Framing) {
+    let parity = framing.parity != Parity::None;
+    // SAFETY: the framing is only written by the HAL's constructor and here, with
+    // the transmitter idle
+    let usart = unsafe { &*USART1::ptr() };
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    let divisor = PCLK2_HZ.load(Ordering::Relaxed) / framing.baud;
+    usart.brr.write(|w| unsafe { w.bits(divisor) });
+    usart
+        .cr2
+        .modify(|_, w| w.stop().bits(if framing.two_stop_bits { 0b10 } else { 0 }));
+    // the parity takes the ninth bit of the word, 8 data bits stay
+    usart.cr1.modify(|_, w| {
+        w.m()
+            .bit(parity)
+            .pce()
+            .bit(parity)
+            .ps()
+            .bit(framing.parity == Parity::Odd)
+            .ue()
+            .set_bit()
+    });
+}
+
+// size of each of the two swap buffers, it takes the longest `STATUS` line
+pub const BUFFER_SIZE: usize = 160;
+
+pub type Buffers = [[u8; BUFFER_SIZE]; 2];
+
+/// One of the swap buffers and the number of bytes queued in it.
+pub struct Chunk {
+    bytes: &'static mut [u8; BUFFER_SIZE],
+    len: usize,
+}
+
+impl Chunk {
+    fn new(bytes: &'static mut [u8; BUFFER_SIZE]) -> Self {
+        Self { bytes, len: 0 }
+    }
+
+    // appends all `parts` or nothing
+    fn push(&mut self, parts: &[&[u8]]) -> bool {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if self.len + len > BUFFER_SIZE {
+            return false;
+        }
+        for part in parts {
+            self.bytes[self.len..self.len + part.len()].copy_from_slice(part);
+            self.len += part.len();
+        }
+        true
+    }
+}
+
+// the bytes are 'static, moving the chunk around during a transfer doesn't move them
+unsafe impl ReadBuffer for Chunk {
+    type Word = u8;
+
+    unsafe fn read_buffer(&self) -> (*const u8, usize) {
+        (self.bytes.as_ptr(), self.len)
+    }
+}
+
+struct Queued {
+    // the buffer that isn't being transmitted, `None` until `UartDma::new` hands it over
+    chunk: Option<Chunk>,
+    dropped: u32,
+}
+
+// written from every priority level, so it is guarded by a critical section
+static QUEUED: Mutex<RefCell<Queued>> = Mutex::new(RefCell::new(Queued {
+    chunk: None,
+    dropped: 0,
+}));
+
+/// Queues `line` for USART1, it is dropped and counted when the idle buffer is full.
+#[cfg(feature = "text")]
+pub fn write_line(line: &str) {
+    write(&[line.as_bytes(), b"\n"]);
+}
+
+/// Queues an encoded [`crate::wire`] frame, dropped like a line when there is no room.
+#[cfg(not(feature = "text"))]
+pub fn write_frame(frame: &[u8]) {
+    write(&[frame]);
+}
+
+/// Queues raw bytes, the MIDI messages of [`crate::midi`].
+#[cfg(feature = "midi")]
+pub fn write_bytes(bytes: &[u8]) {
+    write(&[bytes]);
+}
+
+fn write(parts: &[&[u8]]) {
+    let queued = interrupt::free(|cs| {
+        let mut queued = QUEUED.borrow(cs).borrow_mut();
+        let pushed = queued.chunk.as_mut().is_some_and(|chunk| chunk.push(parts));
+        if !pushed {
+            queued.dropped += 1;
+        }
+        pushed
+    });
+    if queued {
+        rtic::pend(Interrupt::DMA1_CHANNEL4);
+    }
+}
+
+/// Number of lines and frames dropped because both buffers were full.
+pub fn dropped() -> u32 {
+    interrupt::free(|cs| QUEUED.borrow(cs).borrow().dropped)
+}
+
+enum State {
+    Idle(TxDma1, Chunk),
+    Busy(Transfer<R, Chunk, TxDma1>),
+}
+
+/// USART1 transmitter on DMA1 channel 4 with two swap buffers: one is sent while
+/// writers fill the other.
+pub struct UartDma {
+    state: Option<State>,
+}
+
+impl UartDma {
+    pub fn new(mut tx: TxDma1, buffers: &'static mut Buffers) -> Self {
+        let [first, second] = buffers;
+        tx.channel.listen(Event::TransferComplete);
+        interrupt::free(|cs| QUEUED.borrow(cs).borrow_mut().chunk = Some(Chunk::new(second)));
+        Self {
+            state: Some(State::Idle(tx, Chunk::new(first))),
+        }
+    }
+
+    /// Called from the DMA1 channel 4 interrupt, either because a transfer completed
+    /// or because a writer queued a line. Starts the next transfer if there is data.
+    pub fn on_interrupt(&mut self) {
+        let (tx, mut free) = match self.state.take() {
+            Some(State::Busy(transfer)) if !transfer.is_done() => {
+                self.state = Some(State::Busy(transfer));
+                return;
+            }
+            Some(State::Busy(transfer)) => {
+                let (chunk, tx) = transfer.wait();
+                (tx, chunk)
+            }
+            Some(State::Idle(tx, chunk)) => (tx, chunk),
+            None => return,
+        };
+        free.len = 0;
+
+        let next = interrupt::free(|cs| {
+            let mut queued = QUEUED.borrow(cs).borrow_mut();
+            match queued.chunk.take() {
+                Some(filled) if filled.len > 0 => {
+                    queued.chunk = Some(free);
+                    Ok(filled)
+                }
+                chunk => {
+                    queued.chunk = chunk;
+                    Err(free)
+                }
+            }
+        });
+        #[cfg(feature = "uart-config")]
+        TRANSMITTING.store(next.is_ok(), Ordering::Relaxed);
+        self.state = Some(match next {
+            Ok(filled) => State::Busy(tx.write(filled)),
+            Err(free) => State::Idle(tx, free),
+        });
+    }
+}
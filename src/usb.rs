@@ -0,0 +1,236 @@
+use crate::event::KeyEvent;
+#[cfg(not(feature = "nkro"))]
+use crate::hid::Keyboard;
+#[cfg(feature = "media")]
+use crate::hid::MediaKeys;
+#[cfg(feature = "nkro")]
+use crate::hid::NkroKeyboard;
+#[cfg(feature = "nkro")]
+use crate::keypad;
+#[cfg(feature = "nkro")]
+use keypad_core::report::NKRO_DESCRIPTOR;
+use stm32f1xx_hal::usb::UsbBusType;
+#[cfg(feature = "nkro")]
+use usb_device::class_prelude::*;
+use usb_device::{bus::UsbBusAllocator, prelude::*};
+#[cfg(not(feature = "nkro"))]
+use usbd_hid::descriptor::KeyboardReport;
+#[cfg(feature = "media")]
+use usbd_hid::descriptor::MediaKeyboardReport;
+#[cfg(any(feature = "media", not(feature = "nkro")))]
+use usbd_hid::descriptor::SerializedDescriptor;
+use usbd_hid::hid_class::HIDClass;
+#[cfg(feature = "nkro")]
+use usbd_hid::hid_class::{
+    HidClassSettings, HidCountryCode, HidProtocol, HidSubClass, ProtocolModeConfig,
+};
+#[cfg(feature = "cdc")]
+use usbd_serial::CdcAcmClass;
+
+/// The USB device: a HID keyboard and, with the `cdc` feature, a CDC serial port
+/// for logs on the same composite device. The `media` feature adds a second HID
+/// interface with the consumer control report of the media keys. The `nkro`
+/// feature makes the keyboard a boot keyboard sending the NKRO bitmap, or the
+/// boot report once the host asked for the boot protocol.
+pub struct Usb {
+    device: UsbDevice<'static, UsbBusType>,
+    #[cfg(feature = "nkro")]
+    protocol: BootProtocol,
+    hid: HIDClass<'static, UsbBusType>,
+    #[cfg(feature = "media")]
+    consumer: HIDClass<'static, UsbBusType>,
+    #[cfg(feature = "cdc")]
+    serial: CdcAcmClass<'static, UsbBusType>,
+    #[cfg(not(feature = "nkro"))]
+    keyboard: Keyboard,
+    #[cfg(feature = "nkro")]
+    keyboard: NkroKeyboard,
+    #[cfg(feature = "media")]
+    media_keys: MediaKeys,
+}
+
+impl Usb {
+    pub fn new(bus: &'static UsbBusAllocator<UsbBusType>) -> Self {
+        #[cfg(not(feature = "nkro"))]
+        let hid = HIDClass::new(bus, KeyboardReport::desc(), 10);
+        // forced to boot, `BootProtocol` keeps the protocol the host picked
+        #[cfg(feature = "nkro")]
+        let hid = HIDClass::new_with_settings(
+            bus,
+            &NKRO_DESCRIPTOR,
+            10,
+            HidClassSettings {
+                subclass: HidSubClass::Boot,
+                protocol: HidProtocol::Keyboard,
+                config: ProtocolModeConfig::ForceBoot,
+                locale: HidCountryCode::NotSupported,
+            },
+        );
+        #[cfg(feature = "media")]
+        let consumer = HIDClass::new(bus, MediaKeyboardReport::desc(), 10);
+        #[cfg(feature = "cdc")]
+        // `crate::serial` buffers the lines, the buffers of a `SerialPort` would
+        // only copy them once more
+        let serial = CdcAcmClass::new(bus, 64);
+
+        let builder = UsbDeviceBuilder::new(bus, UsbVidPid(0x16c0, 0x27db))
+            .manufacturer("Kiryl19125")
+            .product("4x4 keypad")
+            .serial_number("0001");
+        // the CDC functions need an interface association descriptor next to HID
+        #[cfg(feature = "cdc")]
+        let builder = builder.composite_with_iads();
+
+        Self {
+            device: builder.build(),
+            #[cfg(feature = "nkro")]
+            protocol: BootProtocol { boot: false },
+            hid,
+            #[cfg(feature = "media")]
+            consumer,
+            #[cfg(feature = "cdc")]
+            serial,
+            #[cfg(not(feature = "nkro"))]
+            keyboard: Keyboard::new(),
+            #[cfg(feature = "nkro")]
+            keyboard: NkroKeyboard::new(),
+            #[cfg(feature = "media")]
+            media_keys: MediaKeys::new(),
+        }
+    }
+
+    /// Services the device from the USB interrupts. Both classes are handled by the
+    /// same poll, and the CDC port only ever drains its buffer, so an unopened port
+    /// can't hold back the keyboard.
+    pub fn poll(&mut self) {
+        #[cfg(feature = "cdc")]
+        if self.device.poll(&mut [
+            #[cfg(feature = "nkro")]
+            &mut self.protocol,
+            &mut self.hid,
+            #[cfg(feature = "media")]
+            &mut self.consumer,
+            &mut self.serial,
+        ]) {
+            // nothing is read from the host yet, drop whatever it sends
+            let mut buffer = [0; 64];
+            while matches!(self.serial.read_packet(&mut buffer), Ok(count) if count > 0) {}
+        }
+        #[cfg(not(feature = "cdc"))]
+        self.device.poll(&mut [
+            #[cfg(feature = "nkro")]
+            &mut self.protocol,
+            &mut self.hid,
+            #[cfg(feature = "media")]
+            &mut self.consumer,
+        ]);
+
+        self.send_report();
+        #[cfg(feature = "cdc")]
+        crate::serial::flush(&mut self.serial);
+    }
+
+    /// `true` once the host suspended the bus or the cable is unplugged.
+    pub fn is_suspended(&self) -> bool {
+        self.device.state() == UsbDeviceState::Suspend
+    }
+
+    pub fn handle(&mut self, event: &KeyEvent) {
+        #[cfg(not(feature = "nkro"))]
+        self.keyboard.handle(event);
+        #[cfg(feature = "nkro")]
+        let _ = event;
+        // the state the scanner published before it queued the event
+        #[cfg(feature = "nkro")]
+        self.keyboard.set_keys(keypad::read_key_state());
+        self.send_report();
+    }
+
+    /// Sends the media keys on the consumer report, the other keys on the keyboard.
+    #[cfg(feature = "media")]
+    pub fn handle_media(&mut self, event: &KeyEvent) {
+        if !self.media_keys.handle(event) {
+            #[cfg(not(feature = "nkro"))]
+            self.keyboard.handle(event);
+            #[cfg(feature = "nkro")]
+            self.keyboard
+                .set_keys(keypad::read_key_state() & !crate::hid::MEDIA_KEYS);
+        }
+        self.send_report();
+    }
+
+    pub fn release_all(&mut self) {
+        self.keyboard.release_all();
+        #[cfg(feature = "media")]
+        self.media_keys.release_all();
+        self.send_report();
+    }
+
+    fn send_report(&mut self) {
+        #[cfg(feature = "nkro")]
+        if let Some((report, len)) = self.keyboard.pending(self.protocol.boot) {
+            if self.hid.push_raw_input(&report[..len]).is_ok() {
+                self.keyboard.sent();
+            }
+        }
+        #[cfg(not(feature = "nkro"))]
+        if let Some(report) = self.keyboard.pending() {
+            // on failure the report stays pending and is retried on the next USB interrupt
+            if self.hid.push_input(&report).is_ok() {
+                self.keyboard.sent();
+            }
+        }
+        #[cfg(feature = "media")]
+        if let Some(report) = self.media_keys.pending() {
+            if self.consumer.push_input(&report).is_ok() {
+                self.media_keys.sent();
+            }
+        }
+    }
+}
+
+// the HID class requests of the protocol, HID 1.11 7.2.5 and 7.2.6
+#[cfg(feature = "nkro")]
+const GET_PROTOCOL: u8 = 0x03;
+#[cfg(feature = "nkro")]
+const SET_PROTOCOL: u8 = 0x0b;
+// `Usb::new` allocates the keyboard first
+#[cfg(feature = "nkro")]
+const KEYBOARD_INTERFACE: u16 = 0;
+
+/// The protocol the host picked for the keyboard, report after a bus reset. It
+/// answers the protocol requests ahead of the `HIDClass`, which only pushes the
+/// reports of one protocol per subclass.
+#[cfg(feature = "nkro")]
+struct BootProtocol {
+    boot: bool,
+}
+
+#[cfg(feature = "nkro")]
+impl<B: UsbBus> UsbClass<B> for BootProtocol {
+    fn reset(&mut self) {
+        self.boot = false;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        if is_protocol_request(xfer.request(), GET_PROTOCOL) {
+            xfer.accept_with(&[u8::from(!self.boot)]).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if is_protocol_request(&req, SET_PROTOCOL) {
+            self.boot = req.value & 0xff == 0;
+            xfer.accept().ok();
+        }
+    }
+}
+
+#[cfg(feature = "nkro")]
+fn is_protocol_request(req: &control::Request, request: u8) -> bool {
+    req.request_type == control::RequestType::Class
+        && req.recipient == control::Recipient::Interface
+        && req.index == KEYBOARD_INTERFACE
+        && req.request == request
+}
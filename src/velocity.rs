@@ -0,0 +1,192 @@
+//! Strike velocity of the keys with a force sensing resistor under them, the
+//! `velocity` feature. The sensors are dividers on PA0-PA3, ADC1 channels 0 to 3,
+//! which the default rev A wiring has as matrix columns, so there it needs the
+//! columns moved by `shift-register` or `mcp23017`.
+//!
+//! ADC1 converts the fitted channels one after the other in scan mode, over and
+//! over, and DMA1 channel 1 moves the readings into a circular buffer of two
+//! halves, [`SETS`] scans of every channel each, about a millisecond. The half
+//! and full transfer interrupts hand over the half the DMA just left, the sets in
+//! it are averaged into a reading per channel and fed to its
+//! `keypad_core::velocity::Strike`. The scanner gives a key's press the velocity
+//! of the strike under way, see `KeyEvent::velocity`.
+//!
+//! The conversion timing holds for full speed alone, so the feature keeps the
+//! core there.
+
+use crate::app::monotonics;
+use crate::event::{EventKind, KeyEvent};
+use crate::keypad::{COLUMNS, KEYS};
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use keypad_core::velocity::{Strike, StrikeConfig};
+use stm32f1xx_hal::dma::{dma1::C1, Event};
+use stm32f1xx_hal::gpio::{Analog, PA0, PA1, PA2, PA3};
+use stm32f1xx_hal::pac::ADC1;
+
+/// The key index `row * COLUMNS + col` over the sensor of each channel, `None`
+/// for a channel without one fitted, which stays out of the scan.
+pub const CHANNELS: [Option<usize>; 4] = [Some(0), Some(1), Some(2), Some(3)];
+
+// tune with the raw readings of the sensors, in 12-bit counts
+const STRIKE: StrikeConfig = StrikeConfig {
+    onset: 400,
+    release: 250,
+    full_slope: 300,
+};
+
+/// The channels in the scan.
+pub const FITTED: usize = fitted();
+
+// a conversion at the longest sample time, 239.5 cycles and 12.5 for the result,
+// at the 12 MHz the HAL runs the ADC at from its 72 MHz PCLK2
+const CONVERSION_NS: u32 = 252 * 1000 / 12;
+
+/// Scans per half of the buffer, as many as fit into a millisecond.
+pub const SETS: usize = {
+    let sets = 1_000_000 / (CONVERSION_NS * FITTED as u32);
+    if sets == 0 {
+        1
+    } else {
+        sets as usize
+    }
+};
+
+const HALF: usize = SETS * FITTED;
+
+const _: () = assert!(
+    FITTED > 0,
+    "no sensor fitted, leave the `velocity` feature out"
+);
+
+pub const BUFFER_LEN: usize = 2 * HALF;
+
+pub type Buffer = [u16; BUFFER_LEN];
+
+const fn fitted() -> usize {
+    let mut fitted = 0;
+    let mut channel = 0;
+    while channel < CHANNELS.len() {
+        if let Some(key) = CHANNELS[channel] {
+            assert!(key < KEYS);
+            fitted += 1;
+        }
+        channel += 1;
+    }
+    fitted
+}
+
+/// The sensor pins, in analog mode for good.
+pub type Pins = (PA0<Analog>, PA1<Analog>, PA2<Analog>, PA3<Analog>);
+
+struct Sensors {
+    // taken by `start`
+    dma: Option<C1>,
+    // a strike per fitted channel, in the order of `CHANNELS`
+    strikes: [Strike; FITTED],
+}
+
+// all zero until `start`, so it takes no flash
+static SENSORS: Mutex<RefCell<Sensors>> = Mutex::new(RefCell::new(Sensors {
+    dma: None,
+    strikes: [const { Strike::new() }; FITTED],
+}));
+
+/// Starts the conversions, with the ADC powered and calibrated by the HAL. The
+/// pins only have to stay analog.
+pub fn start(_pins: Pins, mut dma: C1, buffer: &'static mut Buffer) {
+    // SAFETY: the HAL's `Adc` of the shared `adc` resource is never used with this
+    // feature, the joystick and the battery can't be built along
+    let adc = unsafe { &*ADC1::ptr() };
+    let mut sequence = 0;
+    let mut position = 0;
+    for (channel, key) in CHANNELS.iter().enumerate() {
+        if key.is_none() {
+            continue;
+        }
+        sequence |= (channel as u32) << (5 * position);
+        position += 1;
+        // 0b111 is 239.5 cycles
+        adc.smpr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | 0b111 << (3 * channel)) });
+    }
+    adc.sqr3.write(|w| unsafe { w.bits(sequence) });
+    adc.sqr1.modify(|_, w| w.l().bits(FITTED as u8 - 1));
+    adc.cr1
+        .modify(|_, w| w.scan().set_bit().discen().clear_bit());
+
+    dma.set_peripheral_address(adc.dr.as_ptr() as u32, false);
+    dma.set_memory_address(buffer.as_ptr() as u32, true);
+    dma.set_transfer_length(buffer.len());
+    dma.ch().cr.modify(|_, w| {
+        w.mem2mem()
+            .clear_bit()
+            .pl()
+            .medium()
+            .msize()
+            .bits16()
+            .psize()
+            .bits16()
+            .circ()
+            .set_bit()
+            .dir()
+            .clear_bit()
+    });
+    dma.listen(Event::HalfTransfer);
+    dma.listen(Event::TransferComplete);
+    dma.start();
+
+    // continuous from the software start on
+    adc.cr2
+        .modify(|_, w| w.cont().set_bit().dma().set_bit().swstart().set_bit());
+
+    interrupt::free(|cs| SENSORS.borrow(cs).borrow_mut().dma = Some(dma));
+}
+
+/// Takes the finished half of the buffer, from the DMA interrupt.
+pub fn on_transfer() {
+    let at = monotonics::now().ticks();
+    interrupt::free(|cs| {
+        let sensors = &mut *SENSORS.borrow(cs).borrow_mut();
+        let Some(dma) = sensors.dma.as_mut() else {
+            return;
+        };
+        dma.ifcr().write(|w| w.cgif1().set_bit());
+        // written by the DMA all along, so only read through its address
+        let buffer = dma.ch().mar.read().bits() as *const u16;
+        // the half the DMA isn't in, its count goes down from the whole buffer. A
+        // late interrupt with both flags set takes the newer half.
+        let first = if dma.get_ndtr() as usize > HALF {
+            HALF
+        } else {
+            0
+        };
+        for (position, strike) in sensors.strikes.iter_mut().enumerate() {
+            let sum: u32 = (first + position..first + HALF)
+                .step_by(FITTED)
+                // SAFETY: within the `'static` buffer `start` got
+                .map(|index| u32::from(unsafe { buffer.add(index).read_volatile() }))
+                .sum();
+            strike.update(&STRIKE, (sum / SETS as u32) as u16, at);
+        }
+    });
+}
+
+/// Gives a press the velocity of the strike on its key, if it has a sensor.
+pub fn attach(event: &mut KeyEvent) {
+    if event.kind == EventKind::Pressed {
+        event.velocity = of(usize::from(event.row) * COLUMNS + usize::from(event.col));
+    }
+}
+
+// zero without a sensor under the key or without a strike
+fn of(index: usize) -> u8 {
+    let Some(position) = CHANNELS.iter().flatten().position(|&key| key == index) else {
+        return 0;
+    };
+    interrupt::free(|cs| {
+        SENSORS.borrow(cs).borrow().strikes[position]
+            .velocity()
+            .unwrap_or(0)
+    })
+}
@@ -0,0 +1,285 @@
+//! Independent watchdog supervision of the periodic tasks.
+//!
+//! The feed only comes while the key pipeline moves: the scanner counts its runs
+//! in `scan_seq`, and every feed wants it to have moved on since the one before,
+//! unless the scanner waits for a wake line. `SCANRATE` keeps the scan period
+//! below a [`FEED_PERIOD_MS`]. The event consumer notes when it left the queue
+//! empty, and events waiting for longer than [`DRAIN_MS`] stop the feed as well.
+//! Either way it logs what stalled before the IWDG resets the chip
+//! [`TIMEOUT_MS`] later. `WDG TEST` of the `debug-inject` feature stalls the
+//! scanner on purpose.
+//!
+//! The blink chain sets its bit in the shared alive mask when it runs, the feed
+//! task looks at the mask every [`CHECK_PERIOD_MS`] and clears it. The check
+//! period has to be longer than the blink, which steps once a second.
+//!
+//! The WWDG runs next to it with a much shorter timeout and a window, it catches
+//! the scheduler itself stalling. Its early wakeup interrupt comes one counter
+//! tick before the reset, which is just enough to log what the firmware was doing.
+
+use crate::app::{now_ms, watchdog_feed, wwdg_early_wakeup};
+use crate::board::PCLK1_HZ;
+use crate::event::InputEvent;
+use crate::keypad;
+use crate::logging::{self, Char, Text};
+use crate::sleep::ScanMode;
+use crate::spawn::Counted;
+use crate::timing;
+#[cfg(feature = "debug-inject")]
+use core::sync::atomic::{AtomicBool, Ordering};
+use rtic::Mutex;
+use serde::Serialize;
+use stm32f1xx_hal::pac::{DBGMCU, IWDG, RCC, WWDG};
+
+pub const TIMEOUT_MS: u32 = 500;
+// well within the timeout, the feed task runs at the lowest priority
+pub const FEED_PERIOD_MS: u32 = 100;
+pub const CHECK_PERIOD_MS: u32 = 2000;
+pub const DRAIN_MS: u32 = 1000;
+
+// alive mask bits
+pub const BLINK: u8 = 1 << 0;
+
+#[cfg(feature = "debug-inject")]
+static SCANNER_STALLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the scanner stop at its next run, for `WDG TEST`.
+#[cfg(feature = "debug-inject")]
+pub fn stall_scanner() {
+    SCANNER_STALLED.store(true, Ordering::Relaxed);
+}
+
+#[cfg(feature = "debug-inject")]
+pub fn scanner_stalled() -> bool {
+    SCANNER_STALLED.load(Ordering::Relaxed)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetCause {
+    PowerOn,
+    Pin,
+    IndependentWatchdog,
+    WindowWatchdog,
+    Software,
+    LowPower,
+    // not a flag, a `Software` reset `crate::reset` did for the reset chord
+    User,
+}
+
+impl ResetCause {
+    pub const ALL: [ResetCause; 7] = [
+        ResetCause::PowerOn,
+        ResetCause::Pin,
+        ResetCause::IndependentWatchdog,
+        ResetCause::WindowWatchdog,
+        ResetCause::Software,
+        ResetCause::LowPower,
+        ResetCause::User,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "POR",
+            ResetCause::Pin => "PIN",
+            ResetCause::IndependentWatchdog => "IWDG",
+            ResetCause::WindowWatchdog => "WWDG",
+            ResetCause::Software => "SW",
+            ResetCause::LowPower => "low-power",
+            ResetCause::User => "USER",
+        }
+    }
+}
+
+/// Every reset flag that was set at boot, one bit per [`ResetCause`] in the order
+/// of [`ResetCause::ALL`]. NRST is pulled by the chip on every internal reset, so
+/// `Pin` mostly comes along with another cause.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ResetCauses(u8);
+
+impl ResetCauses {
+    pub fn contains(self, cause: ResetCause) -> bool {
+        self.0 & 1 << cause as u8 != 0
+    }
+
+    pub fn with(self, cause: ResetCause) -> Self {
+        Self(self.0 | 1 << cause as u8)
+    }
+
+    /// The names of the causes, separated by `+`.
+    pub fn text(self) -> logging::Line {
+        let mut text = heapless::String::new();
+        for cause in ResetCause::ALL
+            .into_iter()
+            .filter(|&cause| self.contains(cause))
+        {
+            if !text.is_empty() {
+                let _ = text.push('+');
+            }
+            let _ = text.push_str(cause.name());
+        }
+        text
+    }
+}
+
+/// Reads why the chip was reset and clears the flags for the next boot. Has
+/// to run before the clocks are set up, which takes the RCC.
+pub fn reset_causes(rcc: &RCC) -> ResetCauses {
+    let csr = rcc.csr.read();
+    let flags = [
+        csr.porrstf().bit_is_set(),
+        csr.pinrstf().bit_is_set(),
+        csr.iwdgrstf().bit_is_set(),
+        csr.wwdgrstf().bit_is_set(),
+        csr.sftrstf().bit_is_set(),
+        csr.lpwrrstf().bit_is_set(),
+    ];
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    ResetCauses(
+        flags
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (bit, &set)| bits | u8::from(set) << bit),
+    )
+}
+
+// the WWDG counter ticks at PCLK1 / 4096 / 2^WDGTB, the timings are at full speed
+const WDGTB: u8 = 3;
+const TICK_NS: u64 = (4096 << WDGTB) * 1_000_000_000 / PCLK1_HZ as u64;
+
+// the counter is reloaded with T6 set and resets the chip once T6 clears
+const COUNTER_START: u8 = 0x7f;
+const COUNTER_RESET: u8 = 0x3f;
+// refreshing while the counter is still above the window resets the chip as well
+const WINDOW: u8 = 0x5f;
+
+/// Time after a refresh until the next one is allowed, ~29 ms.
+pub const WINDOW_OPEN_US: u32 = ((COUNTER_START - WINDOW) as u64 * TICK_NS / 1000) as u32;
+/// Time after a refresh until the reset, ~58 ms.
+pub const WINDOW_CLOSE_US: u32 = ((COUNTER_START - COUNTER_RESET) as u64 * TICK_NS / 1000) as u32;
+/// Period of the refresh polling, a third of the window. The counter slows down
+/// with PCLK1 at low speed, so the refresh waits for the window to open instead of
+/// keeping a fixed period.
+pub const POLL_MS: u32 = (WINDOW_CLOSE_US - WINDOW_OPEN_US) / 3 / 1000;
+
+// a monotonic tick of slack and a poll late by as much still lands in the window
+const _: () = assert!(2 * (POLL_MS + 1) * 1000 < WINDOW_CLOSE_US - WINDOW_OPEN_US);
+
+pub struct WindowWatchdog {
+    wwdg: WWDG,
+}
+
+impl WindowWatchdog {
+    /// Starts the watchdog with the early wakeup interrupt enabled, it can't be
+    /// stopped again. The counter holds while the core is halted by a debugger.
+    pub fn start(wwdg: WWDG, dbg: &DBGMCU) -> Self {
+        // only called from init, nothing else modifies APB1ENR meanwhile
+        unsafe { (*RCC::ptr()).apb1enr.modify(|_, w| w.wwdgen().set_bit()) };
+        dbg.cr.modify(|_, w| w.dbg_wwdg_stop().set_bit());
+        wwdg.cfr
+            .write(|w| w.wdgtb().bits(WDGTB).w().bits(WINDOW).ewi().set_bit());
+        wwdg.cr
+            .write(|w| w.t().bits(COUNTER_START).wdga().set_bit());
+        Self { wwdg }
+    }
+
+    /// Refreshes the counter once the window is open, returns `false` and does
+    /// nothing before.
+    pub fn refresh(&mut self) -> bool {
+        let open = self.wwdg.cr.read().t().bits() <= WINDOW;
+        if open {
+            self.wwdg
+                .cr
+                .write(|w| w.t().bits(COUNTER_START).wdga().set_bit());
+        }
+        open
+    }
+
+    /// Acknowledges the early wakeup interrupt, the reset follows one tick later
+    /// unless the counter is refreshed.
+    pub fn clear_early_wakeup(&mut self) {
+        self.wwdg.sr.write(|w| w.ewif().clear_bit());
+    }
+}
+
+/// Feeds the IWDG and refreshes the WWDG once its window is open, for the RTC
+/// wake ups in STOP mode where no task runs. The WWDG only counts while the core
+/// is awake, so it needs a refresh every few hundred of those. The panic handler
+/// keeps both fed with it as well.
+pub fn feed_unowned() {
+    // SAFETY: no task runs meanwhile, `WindowWatchdog` and the HAL's IWDG
+    // driver only write the same registers
+    unsafe {
+        (*IWDG::ptr()).kr.write(|w| w.key().reset());
+        let wwdg = &*WWDG::ptr();
+        if wwdg.cr.read().t().bits() <= WINDOW {
+            wwdg.cr
+                .write(|w| w.t().bits(COUNTER_START).wdga().set_bit());
+        }
+    }
+}
+
+/// Feeds the IWDG while the supervised tasks move, the body of `watchdog_feed`.
+pub fn watchdog_feed(mut ctx: watchdog_feed::Context) {
+    let now = now_ms();
+    let seq = ctx.shared.scan_seq.lock(|seq| *seq);
+    let fed_seq = core::mem::replace(ctx.local.fed_seq, seq);
+    let drained_at = ctx.shared.drained_at.lock(|at| *at);
+    let mut stalled = None;
+    // the scanner doesn't run while it waits for a wake line
+    if seq == fed_seq && ctx.shared.scan_mode.lock(|mode| *mode) == ScanMode::Polling {
+        stalled = Some("the scanner");
+    } else if now.wrapping_sub(drained_at) >= DRAIN_MS
+        && ctx
+            .shared
+            .event_producer
+            .lock(|producer| producer.pending())
+    {
+        stalled = Some("the event router");
+    } else if now.wrapping_sub(*ctx.local.checked_at) >= CHECK_PERIOD_MS {
+        *ctx.local.checked_at = now;
+        let alive = ctx.shared.alive.lock(core::mem::take);
+        // the blink chain is stopped on purpose while the emergency stop is latched
+        if alive & BLINK == 0 && !ctx.shared.emergency.lock(|emergency| *emergency) {
+            stalled = Some("the blink");
+        }
+    }
+    if let Some(task) = stalled {
+        log!("watchdog: {} stalled, resetting", Text(task));
+        return;
+    }
+    ctx.local.iwdg.feed();
+    watchdog_feed::spawn_after(timing::WATCHDOG_FEED_PERIOD).or_count();
+}
+
+/// Logs the uptime, the keys held and the latest events before the WWDG reset,
+/// the body of `wwdg_early_wakeup`.
+pub fn wwdg_early_wakeup(mut ctx: wwdg_early_wakeup::Context) {
+    ctx.shared.wwdg.lock(|wwdg| wwdg.clear_early_wakeup());
+    let keys = keypad::read_key_state();
+    log!(
+        "window watchdog reset, uptime {} ms, keys {:04x}",
+        now_ms(),
+        keys.0
+    );
+    ctx.shared.recent_events.lock(|recent| {
+        for event in recent.oldest_ordered() {
+            match event {
+                InputEvent::Key(event) | InputEvent::Playback(event) => {
+                    log!("recent key {} {}", Char(event.key), Text(event.kind.name()))
+                }
+                InputEvent::Encoder(event) => log!("recent encoder {:?}", event),
+                InputEvent::Joystick(event) => log!("recent joystick {:?}", event),
+                InputEvent::Button(event) => log!("recent button {}", event.id),
+                InputEvent::EntryComplete(value) => log!("recent entry {}", value),
+                InputEvent::AccessGranted => log!("recent access granted"),
+                InputEvent::Config(_) => log!("recent config key"),
+                InputEvent::QueueOverflow => log!("recent queue overflow"),
+                #[cfg(feature = "hold-cap")]
+                InputEvent::HoldCapped(event) => log!("recent hold cap {}", Char(event.key)),
+            }
+        }
+    });
+    // the logger won't get to run before the reset
+    logging::flush();
+}
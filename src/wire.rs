@@ -0,0 +1,103 @@
+//! Binary protocol for the serial link: postcard encoded [`Message`]s in the
+//! frames of `keypad_core::cobs`, each followed by its CRC-32, COBS encoded and
+//! terminated by a zero byte.
+
+use crate::command::{self, Command, CommandError, StatusReport, MAX_LINE};
+use crate::crc;
+use crate::encoder::EncoderEvent;
+use crate::event::KeyEvent;
+use crate::joystick::JoystickEvent;
+use crate::uart;
+use keypad_core::cobs::{encode_frame, max_frame_len, FrameDecoder};
+use serde::Serialize;
+
+/// Everything the firmware sends to the host.
+#[derive(Serialize)]
+pub enum Message<'a> {
+    Event(KeyEvent),
+    Status(StatusReport),
+    // answer to any command but `STATUS`, `OK` or one of the `ERR` replies
+    Reply(&'a str),
+    // not sent with the `defmt`, `no-log` or `midi` feature, kept so the later
+    // variants keep their tags
+    #[cfg_attr(
+        any(feature = "defmt", feature = "no-log", feature = "midi"),
+        allow(dead_code)
+    )]
+    Log(&'a str),
+    Encoder(EncoderEvent),
+    Joystick(JoystickEvent),
+    // a number confirmed in `crate::entry`
+    Entry(u32),
+    AccessGranted,
+}
+
+// postcard encoding of the largest message, a log line of `logging` length
+pub const MAX_MESSAGE: usize = 100;
+
+// an event with a four byte utf-8 key, a five byte varint hold time, a ten byte
+// varint timestamp, a five byte varint wall clock, the modifiers and with
+// `velocity` the velocity
+pub const MAX_EVENT_PAYLOAD: usize = 31 + cfg!(feature = "velocity") as usize;
+
+pub const MAX_EVENT_FRAME: usize = max_frame_len(MAX_EVENT_PAYLOAD);
+
+// largest frame accepted on the inbound command channel
+pub const MAX_INBOUND_FRAME: usize = max_frame_len(MAX_LINE);
+
+/// Frames `message` into `out`, which has to hold [`max_frame_len`] of its encoded
+/// size. Returns the frame length or `None` if the message exceeds [`MAX_MESSAGE`].
+pub fn encode_message(message: &Message, out: &mut [u8]) -> Option<usize> {
+    let mut payload = [0; MAX_MESSAGE];
+    let payload = postcard::to_slice(message, &mut payload).ok()?;
+    Some(encode_frame(payload, crc::checksum, out))
+}
+
+/// Frames `event` into `out`, which has to hold [`MAX_EVENT_FRAME`] bytes.
+/// Returns the frame length including the zero delimiter.
+pub fn encode_event(event: &KeyEvent, out: &mut [u8]) -> usize {
+    // an event is always well below `MAX_MESSAGE`
+    encode_message(&Message::Event(*event), out).unwrap_or(0)
+}
+
+/// Queues `message` on USART1.
+pub fn send(message: &Message) {
+    let mut frame = [0; max_frame_len(MAX_MESSAGE)];
+    if let Some(len) = encode_message(message, &mut frame) {
+        uart::write_frame(&frame[..len]);
+    }
+}
+
+/// Command channel on top of [`FrameDecoder`], each frame carries one command line.
+pub struct CommandFrames {
+    decoder: FrameDecoder<MAX_INBOUND_FRAME>,
+}
+
+impl CommandFrames {
+    pub const fn new() -> Self {
+        Self {
+            decoder: FrameDecoder::new(crc::checksum),
+        }
+    }
+
+    /// Feeds one byte, returns the parsed command once a frame is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Command, CommandError>> {
+        let payload = self.decoder.feed(byte)?;
+        Some(
+            payload
+                .map_err(|_| CommandError::Corrupted)
+                .and_then(|payload| match payload.is_ascii() {
+                    // SAFETY: ASCII is UTF-8, the check takes less flash than
+                    // `str::from_utf8`
+                    true => Ok(unsafe { core::str::from_utf8_unchecked(payload) }),
+                    false => Err(CommandError::Unknown),
+                })
+                .and_then(command::parse),
+        )
+    }
+
+    /// Number of corrupted frames dropped so far.
+    pub fn errors(&self) -> u32 {
+        self.decoder.errors()
+    }
+}
@@ -0,0 +1,118 @@
+//! Checks on the board itself, for a fixture with the probe attached and no key
+//! held: `cargo test-hw` flashes them, probe-rs runs them and reports every test
+//! over defmt and the verdict as its exit code. They bring the board up the way
+//! `init` does, without the RTIC app around it.
+
+#![no_std]
+#![no_main]
+
+#[cfg(any(feature = "shift-register", feature = "mcp23017"))]
+compile_error!("the hardware tests scan the matrix on the GPIO pins");
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+#[defmt_test::tests]
+mod tests {
+    use cortex_m::peripheral::DWT;
+    use key_board_4_4_rtic::board::{self, Board, ColumnOutput, Wiring};
+    use key_board_4_4_rtic::delay::Delay;
+    use key_board_4_4_rtic::keypad::{self, GpioColumns, GpioRows, Keypad, Matrix};
+    use key_board_4_4_rtic::keypad::{COLUMNS, ROWS};
+    use key_board_4_4_rtic::monotonic::{self, Tim4Monotonic};
+    use rtic::Monotonic;
+    use stm32f1xx_hal::gpio::{ErasedPin, Output, PinExt};
+    use stm32f1xx_hal::pac;
+    use stm32f1xx_hal::prelude::*;
+    use stm32f1xx_hal::rcc::Clocks;
+
+    // the monotonic over 100 ms of core cycles, within 1%
+    const MEASURE_MS: u32 = 100;
+    const TOLERANCE_US: u64 = 1000;
+
+    struct State {
+        clocks: Clocks,
+        mono: Tim4Monotonic,
+        leds: [ErasedPin<Output>; 3],
+        keypad: Keypad<GpioColumns<COLUMNS, Wiring, ColumnOutput>, COLUMNS, ROWS, Wiring>,
+    }
+
+    #[init]
+    fn init() -> State {
+        let mut core = defmt::unwrap!(cortex_m::Peripherals::take());
+        let device = defmt::unwrap!(pac::Peripherals::take());
+        let rcc = device.RCC.constrain();
+        let mut flash = device.FLASH.constrain();
+        let clocks = board::clocks(rcc.cfgr, &mut flash.acr);
+        let mono = Tim4Monotonic::new(device.TIM4, &clocks);
+        let mut delay = Delay::new(&clocks);
+        core.DCB.enable_trace();
+        core.DWT.enable_cycle_counter();
+        let mut afio = device.AFIO.constrain();
+        let mut exti = device.EXTI;
+        let board = Board::new(
+            device.GPIOA,
+            device.GPIOB,
+            device.GPIOC,
+            &mut afio,
+            &mut exti,
+            &mut delay,
+        );
+        let keypad = Keypad::new(
+            GpioColumns::new(board.columns),
+            GpioRows::new(board.rows),
+            delay,
+        );
+        State {
+            clocks,
+            mono,
+            leds: [board.led_red, board.led_blue, board.led_green],
+            keypad,
+        }
+    }
+
+    #[test]
+    fn clocks_as_configured(state: &mut State) {
+        defmt::assert!(board::clocks_valid(&state.clocks));
+        defmt::assert_eq!(state.clocks.sysclk().raw(), board::FULL_SYSCLK_HZ);
+        defmt::assert_eq!(state.clocks.pclk1().raw(), board::PCLK1_HZ);
+        defmt::assert_eq!(state.clocks.pclk2().raw(), board::FULL_SYSCLK_HZ);
+        // TIM4 runs at twice PCLK1, the prescaler takes it down to the tick
+        defmt::assert_eq!(state.clocks.pclk1_tim().raw(), 2 * board::PCLK1_HZ);
+    }
+
+    #[test]
+    fn leds_toggle(state: &mut State) {
+        // SAFETY: a read of the input register, which has no side effects
+        let level = |pin: &ErasedPin<Output>| unsafe {
+            (*keypad::port(pin.port_id())).idr.read().bits() & 1 << pin.pin_id() != 0
+        };
+        for led in state.leds.iter_mut() {
+            led.set_high();
+            defmt::assert!(level(led));
+            led.set_low();
+            defmt::assert!(!level(led));
+        }
+    }
+
+    #[test]
+    fn matrix_reads_empty(state: &mut State) {
+        let test = defmt::unwrap!(state.keypad.self_test());
+        defmt::assert_eq!(test.shorted_rows, 0);
+        defmt::assert_eq!(state.keypad.scan(), Ok(0));
+    }
+
+    #[test]
+    fn monotonic_keeps_time(state: &mut State) {
+        let cycles = MEASURE_MS * (board::FULL_SYSCLK_HZ / 1000);
+        let start = state.mono.now();
+        let since = DWT::cycle_count();
+        // no TIM4 interrupt out here, its overflows are counted by polling
+        while DWT::cycle_count().wrapping_sub(since) < cycles {
+            state.mono.on_interrupt();
+        }
+        let elapsed = state.mono.now() - start;
+        let expected = u64::from(MEASURE_MS) * u64::from(monotonic::TICK_HZ / 1000);
+        defmt::assert!(elapsed.ticks().abs_diff(expected) <= TOLERANCE_US);
+    }
+}